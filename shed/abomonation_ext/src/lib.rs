@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Crate storing values encoded with [abomonation] outside of the memory of
+//! the value, e.g. in a cache. Abomonation decodes the bytes in place, so
+//! [decode] copies them into an aligned buffer first. The bytes written by
+//! [encode] start with a header holding the name and size of the type and
+//! the length of the encoding, so that the values of another type, or of
+//! another definition of the type, and the truncated ones are rejected.
+
+use std::mem;
+
+use abomonation::Abomonation;
+use thiserror::Error;
+
+/// Length of the fixed part of the header: the length of the encoding, the
+/// size of the type and the length of its name
+const HEADER_LEN: usize = 8 + 8 + 2;
+
+/// Error returned by [decode]
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    /// The header is missing or doesn't match the length of the bytes
+    #[error("Abomonated value is truncated or has trailing bytes")]
+    InvalidLength,
+    /// The value was encoded from another type
+    #[error("Abomonated value of type {found} is not a {expected}")]
+    WrongType {
+        /// Type decoded to
+        expected: String,
+        /// Type the value was encoded from, as recorded in its header
+        found: String,
+    },
+    /// The bytes don't decode exactly into a value
+    #[error("Invalid abomonated value of type {0}")]
+    Invalid(&'static str),
+}
+
+/// Name and size of `T`, as recorded in the header
+fn fingerprint<T>() -> (&'static str, u64) {
+    (std::any::type_name::<T>(), mem::size_of::<T>() as u64)
+}
+
+/// Encode `value`, prefixed with the header checked by [decode]
+pub fn encode<T: Abomonation>(value: &T) -> Vec<u8> {
+    let (name, size) = fingerprint::<T>();
+    let name = &name.as_bytes()[..name.len().min(u16::MAX.into())];
+    let len = abomonation::measure(value);
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + name.len() + len);
+    buf.extend_from_slice(&(len as u64).to_le_bytes());
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    buf.extend_from_slice(name);
+    // SAFETY: the bytes are only decoded as a T, by decode
+    unsafe { abomonation::encode(value, &mut buf) }.expect("writing to a Vec doesn't fail");
+    buf
+}
+
+/// Decode a value encoded by [encode], after checking its header.
+///
+/// # Safety
+///
+/// Abomonation trusts the lengths and layout found in the encoded value, and
+/// the header only catches mistakes, not malicious writers. The bytes must
+/// come from [encode] of a `T` by a binary with the same definition of `T`.
+pub unsafe fn decode<T: Abomonation + Clone>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let header = bytes.get(..HEADER_LEN).ok_or(DecodeError::InvalidLength)?;
+    let u64_at = |pos: usize| u64::from_le_bytes(header[pos..pos + 8].try_into().unwrap());
+    let len = u64_at(0);
+    let size = u64_at(8);
+    let name_len = u16::from_le_bytes([header[16], header[17]]) as usize;
+    let name = bytes
+        .get(HEADER_LEN..HEADER_LEN + name_len)
+        .ok_or(DecodeError::InvalidLength)?;
+    let data = &bytes[HEADER_LEN + name_len..];
+
+    let (expected, expected_size) = fingerprint::<T>();
+    if name != &expected.as_bytes()[..expected.len().min(u16::MAX.into())] || size != expected_size
+    {
+        return Err(DecodeError::WrongType {
+            expected: format!("{} of {} bytes", expected, expected_size),
+            found: format!("{} of {} bytes", String::from_utf8_lossy(name), size),
+        });
+    }
+    if data.len() as u64 != len {
+        return Err(DecodeError::InvalidLength);
+    }
+
+    // Decoding reinterprets the bytes in place, so they need to be aligned
+    let mut aligned = vec![0u64; data.len().div_ceil(8)];
+    // SAFETY: the u64s are plain old data, any bytes are valid for them
+    let buf = unsafe {
+        std::slice::from_raw_parts_mut(aligned.as_mut_ptr() as *mut u8, aligned.len() * 8)
+    };
+    let buf = &mut buf[..data.len()];
+    buf.copy_from_slice(data);
+
+    // SAFETY: the caller guarantees that the bytes were encoded from a T
+    match unsafe { abomonation::decode::<T>(buf) } {
+        Some((value, [])) => Ok(value.clone()),
+        _ => Err(DecodeError::Invalid(expected)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let value = vec!["a".to_owned(), "b".to_owned()];
+        let encoded = encode(&value);
+        // Decode from an unaligned copy
+        let unaligned = [&[0u8][..], &encoded].concat();
+        assert_eq!(
+            unsafe { decode::<Vec<String>>(&unaligned[1..]) }.unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn test_rejected() {
+        let encoded = encode(&vec![1u64, 2, 3]);
+        assert!(matches!(
+            unsafe { decode::<Vec<u32>>(&encoded) },
+            Err(DecodeError::WrongType { .. })
+        ));
+        assert!(matches!(
+            unsafe { decode::<u64>(&encode(&1u32)) },
+            Err(DecodeError::WrongType { .. })
+        ));
+        for len in [0, HEADER_LEN, encoded.len() - 1] {
+            assert!(matches!(
+                unsafe { decode::<Vec<u64>>(&encoded[..len]) },
+                Err(DecodeError::InvalidLength)
+            ));
+        }
+        let trailing = [&encoded[..], &[0]].concat();
+        assert!(matches!(
+            unsafe { decode::<Vec<u64>>(&trailing) },
+            Err(DecodeError::InvalidLength)
+        ));
+    }
+}
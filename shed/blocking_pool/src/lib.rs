@@ -0,0 +1,302 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Crate providing [BlockingPool], a named pool for CPU-heavy or otherwise
+//! blocking work, e.g. compression or sqlite calls. It runs its tasks with
+//! [tokio::task::spawn_blocking], but at most a fixed number at a time, so
+//! that a kind of work can't take over tokio's blocking threads and its
+//! backlog is visible. Each pool exports
+//! `blocking_pool.<name>.{queued,running,queue_wait_us,run_time_us,timeouts}`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fbinit::FacebookInit;
+use stats::prelude::*;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio::task::JoinError;
+
+define_stats! {
+    prefix = "blocking_pool";
+    queued: dynamic_singleton_counter("{}.queued", (pool: String)),
+    running: dynamic_singleton_counter("{}.running", (pool: String)),
+    queue_wait_us: dynamic_histogram("{}.queue_wait_us", (pool: String); 1000, 0, 1_000_000, Average, Count; P 50; P 99),
+    run_time_us: dynamic_histogram("{}.run_time_us", (pool: String); 1000, 0, 1_000_000, Average, Count; P 50; P 99),
+    timeouts: dynamic_timeseries("{}.timeouts", (pool: String); Rate, Sum),
+}
+
+/// Error returned when a task run by a [BlockingPool] doesn't complete
+#[derive(Debug, Error)]
+pub enum BlockingPoolError {
+    /// The task didn't complete within the timeout of the pool. It might
+    /// still be running, but its result is discarded.
+    #[error("Task in blocking pool {pool} timed out after {timeout:?}")]
+    Timeout {
+        /// Name of the pool
+        pool: String,
+        /// Timeout of the pool
+        timeout: Duration,
+    },
+    /// The task panicked or the runtime shut down before it ran
+    #[error("Task in blocking pool {pool} failed")]
+    Join {
+        /// Name of the pool
+        pool: String,
+        /// Error returned by tokio
+        #[source]
+        source: JoinError,
+    },
+}
+
+struct Inner {
+    fb: FacebookInit,
+    name: String,
+    max_running: usize,
+    permits: Arc<Semaphore>,
+    queued: AtomicUsize,
+    running: AtomicUsize,
+}
+
+/// Pool running at most `max_running` blocking tasks at a time, queueing the
+/// others. Cloning it gives another handle to the same pool.
+#[derive(Clone)]
+pub struct BlockingPool {
+    inner: Arc<Inner>,
+    timeout: Option<Duration>,
+}
+
+impl BlockingPool {
+    /// Create a pool named `name` that runs up to `max_running` tasks at a
+    /// time. Panics if `max_running` is 0.
+    pub fn new(fb: FacebookInit, name: impl Into<String>, max_running: usize) -> Self {
+        assert!(max_running > 0, "blocking pool must run at least one task");
+        Self {
+            inner: Arc::new(Inner {
+                fb,
+                name: name.into(),
+                max_running,
+                permits: Arc::new(Semaphore::new(max_running)),
+                queued: AtomicUsize::new(0),
+                running: AtomicUsize::new(0),
+            }),
+            timeout: None,
+        }
+    }
+
+    /// Fail the tasks run through this handle that take longer than `timeout`
+    /// to complete, including the time they spent queued. The other handles
+    /// to the pool keep their own timeout, but share its slots.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Name of the pool, used in its stats
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Most tasks the pool runs at a time
+    pub fn max_running(&self) -> usize {
+        self.inner.max_running
+    }
+
+    /// Number of tasks waiting for their turn to run
+    pub fn queue_depth(&self) -> usize {
+        self.inner.queued.load(Ordering::Relaxed)
+    }
+
+    /// Run `f` on a blocking thread once fewer than `max_running` tasks of
+    /// the pool are running, returning its result. If the returned future is
+    /// dropped while `f` is queued it is never run.
+    pub async fn run<F, R>(&self, f: F) -> Result<R, BlockingPoolError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run_inner(f))
+                .await
+                .unwrap_or_else(|_| {
+                    STATS::timeouts.add_value(1, (self.inner.name.clone(),));
+                    Err(BlockingPoolError::Timeout {
+                        pool: self.inner.name.clone(),
+                        timeout,
+                    })
+                }),
+            None => self.run_inner(f).await,
+        }
+    }
+
+    async fn run_inner<F, R>(&self, f: F) -> Result<R, BlockingPoolError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        let queued_at = Instant::now();
+        let permit = {
+            let _queued = Queued::new(&self.inner);
+            self.inner
+                .permits
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("blocking pool semaphore is never closed")
+        };
+        STATS::queue_wait_us.add_value(
+            queued_at.elapsed().as_micros() as i64,
+            (self.inner.name.clone(),),
+        );
+
+        tokio::task::spawn_blocking(move || {
+            // The permit is released when the task completes, even if the
+            // caller stopped waiting for it
+            let _permit = permit;
+            let running = inner.running.fetch_add(1, Ordering::Relaxed) + 1;
+            STATS::running.set_value(inner.fb, running as i64, (inner.name.clone(),));
+            let start = Instant::now();
+            let result = f();
+            STATS::run_time_us.add_value(start.elapsed().as_micros() as i64, (inner.name.clone(),));
+            let running = inner.running.fetch_sub(1, Ordering::Relaxed) - 1;
+            STATS::running.set_value(inner.fb, running as i64, (inner.name.clone(),));
+            result
+        })
+        .await
+        .map_err(|source| BlockingPoolError::Join {
+            pool: self.inner.name.clone(),
+            source,
+        })
+    }
+}
+
+/// Counts a task as queued while it is alive
+struct Queued<'a> {
+    inner: &'a Inner,
+}
+
+impl<'a> Queued<'a> {
+    fn new(inner: &'a Inner) -> Self {
+        let queued = inner.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        STATS::queued.set_value(inner.fb, queued as i64, (inner.name.clone(),));
+        Self { inner }
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let queued = self.inner.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        STATS::queued.set_value(self.inner.fb, queued as i64, (self.inner.name.clone(),));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[fbinit::test]
+    async fn test_run(fb: FacebookInit) {
+        let pool = BlockingPool::new(fb, "test_run", 2);
+        assert_eq!(pool.run(|| 1 + 1).await.unwrap(), 2);
+
+        let err = pool.run(|| panic!("oops")).await.unwrap_err();
+        assert!(matches!(err, BlockingPoolError::Join { source, .. } if source.is_panic()));
+    }
+
+    #[fbinit::test]
+    async fn test_max_running(fb: FacebookInit) {
+        let pool = BlockingPool::new(fb, "test_max_running", 1);
+        let (sender, receiver) = mpsc::channel::<()>();
+        let first = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || receiver.recv()).await }
+        });
+        // Wait for the first task to be running
+        while pool.inner.running.load(Ordering::Relaxed) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 2).await }
+        });
+        while pool.queue_depth() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(pool.queue_depth(), 1);
+
+        // Outside of fbcode the counters are recorded in memory
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert_eq!(stats["blocking_pool.test_max_running.queued"], 1);
+            assert_eq!(stats["blocking_pool.test_max_running.running"], 1);
+        }
+
+        sender.send(()).unwrap();
+        first.await.unwrap().unwrap().unwrap();
+        assert_eq!(second.await.unwrap().unwrap(), 2);
+        assert_eq!(pool.queue_depth(), 0);
+
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert_eq!(stats["blocking_pool.test_max_running.queued"], 0);
+            assert_eq!(stats["blocking_pool.test_max_running.running"], 0);
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_timeout(fb: FacebookInit) {
+        let pool = BlockingPool::new(fb, "test_timeout", 1).with_timeout(Duration::from_millis(10));
+        let (sender, receiver) = mpsc::channel::<()>();
+        let err = pool.run(move || receiver.recv()).await.unwrap_err();
+        assert!(matches!(err, BlockingPoolError::Timeout { .. }));
+
+        // The timed out task still holds the only slot, so the next one times
+        // out in the queue
+        let err = pool.run(|| ()).await.unwrap_err();
+        assert!(matches!(err, BlockingPoolError::Timeout { .. }));
+        assert_eq!(pool.queue_depth(), 0);
+
+        sender.send(()).unwrap();
+        while pool.inner.permits.available_permits() == 0 {
+            tokio::task::yield_now().await;
+        }
+        pool.run(|| ()).await.unwrap();
+    }
+
+    #[fbinit::test]
+    async fn test_timeout_of_shared_pool(fb: FacebookInit) {
+        let pool = BlockingPool::new(fb, "test_timeout_of_shared_pool", 1);
+        let timed = pool.clone().with_timeout(Duration::from_millis(10));
+        let (sender, receiver) = mpsc::channel::<()>();
+        let slow = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || receiver.recv()).await }
+        });
+        while pool.inner.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Only the handle with the timeout gives up on the queued task
+        let err = timed.run(|| ()).await.unwrap_err();
+        assert!(matches!(err, BlockingPoolError::Timeout { .. }));
+        sender.send(()).unwrap();
+        slow.await.unwrap().unwrap().unwrap();
+    }
+}
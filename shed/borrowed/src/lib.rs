@@ -60,10 +60,104 @@
 //! assert!(&foo == bar);
 //! # }
 //! ```
+//!
+//! Mutable borrows are taken with `&mut`, through
+//! [BorrowMut](std::borrow::BorrowMut) if a type is specified, and any
+//! expression, e.g. a nested field, can be borrowed into a named local:
+//! ```
+//! # use borrowed::borrowed;
+//! struct Inner {
+//!     x: Vec<u32>,
+//!     y: String,
+//! }
+//! struct A {
+//!     inner: Inner,
+//! }
+//! # fn main () {
+//! let mut a = A {
+//!     inner: Inner {
+//!         x: vec![],
+//!         y: "foo".to_string(),
+//!     },
+//! };
+//! {
+//!     borrowed!(x = &mut a.inner.x, y: &str = a.inner.y);
+//!     x.push(y.len() as u32);
+//! }
+//! let mut foo = vec![1];
+//! borrowed!(&mut foo as bar: &mut [u32]);
+//! bar[0] = 2;
+//! assert_eq!(a.inner.x, vec![3]);
+//! # }
+//! ```
 
 /// See crate's documentation
 #[macro_export]
 macro_rules! borrowed {
+    ($alias:ident : $borrow:ty = &mut $e:expr, $($tt:tt)*) => {
+        borrowed!($alias: $borrow = &mut $e);
+        borrowed!($($tt)*);
+    };
+    ($alias:ident = &mut $e:expr, $($tt:tt)*) => {
+        borrowed!($alias = &mut $e);
+        borrowed!($($tt)*);
+    };
+    ($alias:ident : $borrow:ty = $e:expr, $($tt:tt)*) => {
+        borrowed!($alias: $borrow = $e);
+        borrowed!($($tt)*);
+    };
+    ($alias:ident = $e:expr, $($tt:tt)*) => {
+        borrowed!($alias = $e);
+        borrowed!($($tt)*);
+    };
+    ($alias:ident : $borrow:ty = &mut $e:expr) => {
+        let $alias: $borrow = {
+            use std::borrow::BorrowMut;
+            $e.borrow_mut()
+        };
+    };
+    // Without a type BorrowMut is always ambiguous, so borrow the value itself
+    ($alias:ident = &mut $e:expr) => {
+        let $alias = &mut $e;
+    };
+    ($alias:ident : $borrow:ty = $e:expr) => {
+        let $alias: $borrow = {
+            use std::borrow::Borrow;
+            $e.borrow()
+        };
+    };
+    ($alias:ident = $e:expr) => {
+        let $alias = {
+            use std::borrow::Borrow;
+            $e.borrow()
+        };
+    };
+
+    (&mut $i:ident as $alias:ident : $borrow:ty $(, $($tt:tt)*)?) => {
+        borrowed!($alias: $borrow = &mut $i, $($($tt)*)?);
+    };
+    (&mut $i:ident as $alias:ident $(, $($tt:tt)*)?) => {
+        borrowed!($alias = &mut $i, $($($tt)*)?);
+    };
+    (&mut $this:ident . $i:ident as $alias:ident : $borrow:ty $(, $($tt:tt)*)?) => {
+        borrowed!($alias: $borrow = &mut $this.$i, $($($tt)*)?);
+    };
+    (&mut $this:ident . $i:ident as $alias:ident $(, $($tt:tt)*)?) => {
+        borrowed!($alias = &mut $this.$i, $($($tt)*)?);
+    };
+    (&mut $i:ident : $borrow:ty $(, $($tt:tt)*)?) => {
+        borrowed!($i: $borrow = &mut $i, $($($tt)*)?);
+    };
+    (&mut $i:ident $(, $($tt:tt)*)?) => {
+        borrowed!($i = &mut $i, $($($tt)*)?);
+    };
+    (&mut $this:ident . $i:ident : $borrow:ty $(, $($tt:tt)*)?) => {
+        borrowed!($i: $borrow = &mut $this.$i, $($($tt)*)?);
+    };
+    (&mut $this:ident . $i:ident $(, $($tt:tt)*)?) => {
+        borrowed!($i = &mut $this.$i, $($($tt)*)?);
+    };
+
     // Ambigous, so need to specify type
     ($i:ident as $alias:ident : $borrow:ty) => {
         let $alias: $borrow = {
@@ -344,4 +438,54 @@ mod tests {
             borrowed!(a, mut c.x as x2: &String,);
         }
     }
+
+    #[test]
+    fn mut_borrows() {
+        let mut a = vec![1];
+        let mut c = A {
+            x: "foo".to_string(),
+        };
+
+        {
+            borrowed!(&mut a, &mut c.x);
+            a.push(2);
+            x.push_str("bar");
+        }
+        {
+            borrowed!(&mut a as a2: &mut Vec<u32>, &mut c.x as x2: &mut String,);
+            a2.push(3);
+            x2.push('!');
+        }
+        {
+            borrowed!(&mut a: &mut [u32]);
+            a[0] = 0;
+        }
+        assert_eq!((a, c.x.as_str()), (vec![0, 2, 3], "foobar!"));
+    }
+
+    #[test]
+    fn assignments() {
+        struct B {
+            a: A,
+        }
+        let mut b = B {
+            a: A {
+                x: "foo".to_string(),
+            },
+        };
+
+        {
+            borrowed!(x: &str = b.a.x, y: &String = b.a.x);
+            assert_eq!((x, y.as_str()), ("foo", "foo"));
+        }
+        {
+            borrowed!(x = &mut b.a.x,);
+            x.push_str("bar");
+        }
+        {
+            borrowed!(x: &mut String = &mut b.a.x);
+            x.push('!');
+        }
+        assert_eq!(b.a.x, "foobar!");
+    }
 }
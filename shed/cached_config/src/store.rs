@@ -146,7 +146,10 @@ impl ConfigStore {
         self.updater_thread_iteration();
     }
 
-    fn get_config_handle_with_deserializer<T>(
+    /// Fetch a self-updating config handle for the config at `path`, parsed
+    /// with `deserializer`, e.g. for configs in other formats than JSON.
+    /// See `ConfigHandle` for uses of this handle.
+    pub fn get_config_handle_with_deserializer<T>(
         &self,
         path: String,
         deserializer: fn(Bytes) -> Result<T>,
@@ -13,9 +13,12 @@
 mod _unused {
     // used in oss
     use abomonation as _;
+    use abomonation_ext as _;
     use anyhow as _;
     use bytes as _;
     use futures_ext as _;
+    use once_cell as _;
+    use stats as _;
 }
 
 #[cfg(not(fbcode_build))]
@@ -8,38 +8,97 @@
  */
 
 use anyhow::{Error, Result};
-use futures_ext::BoxFuture;
+use bytes::Bytes;
+use futures_ext::futures_reexport::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
 
 use super::lrucache::VolatileLruCachePool;
 
 pub fn get_cached_or_fill<T, F>(
-    _cache_pool: &VolatileLruCachePool,
-    _cache_key: String,
+    cache_pool: &VolatileLruCachePool,
+    cache_key: String,
     fetch: F,
 ) -> BoxFuture<Option<T>, Error>
 where
     T: abomonation::Abomonation + Clone + Send + 'static,
     F: FnOnce() -> BoxFuture<Option<T>, Error>,
 {
+    match get_cached(cache_pool, &cache_key) {
+        Ok(Some(value)) => return future::ok(Some(value)).boxify(),
+        Ok(None) => {}
+        Err(e) => return future::err(e).boxify(),
+    }
+
+    let cache_pool = cache_pool.clone();
     fetch()
+        .and_then(move |value| {
+            if let Some(value) = &value {
+                set_cached(&cache_pool, &cache_key, value)?;
+            }
+            Ok(value)
+        })
+        .boxify()
 }
 
-pub fn get_cached<T>(_cache_pool: &VolatileLruCachePool, _cache_key: &String) -> Result<Option<T>>
+pub fn get_cached<T>(cache_pool: &VolatileLruCachePool, cache_key: &String) -> Result<Option<T>>
 where
     T: abomonation::Abomonation + Clone + Send + 'static,
 {
-    Ok(None)
+    let bytes = match cache_pool.get(cache_key)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+
+    // SAFETY: the pool is local to the process, the values of the key are
+    // written by set_cached, and decode checks that it was from a T
+    match unsafe { abomonation_ext::decode(&bytes) } {
+        Ok(value) => Ok(Some(value)),
+        // Treat values that don't decode, e.g. written by an older version of
+        // T, as missing
+        Err(_) => Ok(None),
+    }
 }
 
 /// Returns `false` if the entry could not be inserted (e.g. another entry with the same
 /// key was inserted first)
 pub fn set_cached<T>(
-    _cache_pool: &VolatileLruCachePool,
-    _cache_key: &String,
-    _entry: &T,
+    cache_pool: &VolatileLruCachePool,
+    cache_key: &String,
+    entry: &T,
 ) -> Result<bool>
 where
     T: abomonation::Abomonation + Clone + Send + 'static,
 {
-    Ok(false)
+    cache_pool.set(cache_key, Bytes::from(abomonation_ext::encode(entry)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::oss::lrucache::get_or_create_volatile_pool;
+
+    #[test]
+    fn test_get_cached_or_fill() {
+        let pool = get_or_create_volatile_pool("test_abomonation", 1024).unwrap();
+        let key = "key".to_owned();
+        assert_eq!(get_cached::<Vec<u64>>(&pool, &key).unwrap(), None);
+
+        let filled = get_cached_or_fill(&pool, key.clone(), || {
+            future::ok(Some(vec![1u64, 2, 3])).boxify()
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(filled, Some(vec![1, 2, 3]));
+
+        let cached = get_cached_or_fill(&pool, key, || -> BoxFuture<_, _> {
+            panic!("value should be cached")
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(cached, Some(vec![1u64, 2, 3]));
+        assert_eq!(
+            get_cached::<Vec<u32>>(&pool, &"key".to_owned()).unwrap(),
+            None
+        );
+    }
 }
@@ -7,13 +7,39 @@
  * of this source tree.
  */
 
+//! In-process implementation of the cachelib pools: every pool is an LRU
+//! bounded by the total size of its keys and values.
+
 use std::{
+    collections::HashMap,
     io::{self, Cursor, Read, Write},
     marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Result;
 use bytes::{buf::UninitSlice, Buf, BufMut, Bytes};
+use once_cell::sync::Lazy;
+
+use super::store::Store;
+
+struct Cache {
+    pools: HashMap<String, LruCachePool>,
+    /// Space released by shrinking pools, that other pools can grow into
+    available_space: usize,
+}
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(|| {
+    Mutex::new(Cache {
+        pools: HashMap::new(),
+        available_space: 0,
+    })
+});
+
+fn cache() -> std::sync::MutexGuard<'static, Cache> {
+    CACHE.lock().expect("poisoned lock")
+}
 
 pub fn init_cacheadmin() -> Result<()> {
     Ok(())
@@ -21,7 +47,7 @@ pub fn init_cacheadmin() -> Result<()> {
 
 /// Get the remaining unallocated space in the cache
 pub fn get_available_space() -> Result<usize> {
-    Ok(0)
+    Ok(cache().available_space)
 }
 
 /// Obtain a new pool from the cache. Pools are sub-caches that have their own slice of the cache's
@@ -29,37 +55,45 @@ pub fn get_available_space() -> Result<usize> {
 /// cache without a pool. Note that pools are filled in slabs of 4 MiB, so the actual size you
 /// receive is floor(pool_bytes / 4 MiB).
 /// If the pool already exists, you will get the pre-existing pool instead of a new pool
-pub fn get_or_create_pool(pool_name: &str, _pool_bytes: usize) -> Result<LruCachePool> {
-    Ok(LruCachePool {
-        pool_name: pool_name.to_owned(),
-    })
+pub fn get_or_create_pool(pool_name: &str, pool_bytes: usize) -> Result<LruCachePool> {
+    let pool = cache()
+        .pools
+        .entry(pool_name.to_owned())
+        .or_insert_with(|| LruCachePool {
+            pool_name: pool_name.to_owned(),
+            store: Arc::new(Mutex::new(Store::new(pool_name, pool_bytes))),
+        })
+        .clone();
+    Ok(pool)
 }
 
 /// Obtain a new volatile pool from the cache.
 pub fn get_or_create_volatile_pool(
     pool_name: &str,
-    _pool_bytes: usize,
+    pool_bytes: usize,
 ) -> Result<VolatileLruCachePool> {
     Ok(VolatileLruCachePool {
-        inner: LruCachePool {
-            pool_name: pool_name.to_owned(),
-        },
+        inner: get_or_create_pool(pool_name, pool_bytes)?,
     })
 }
 
 /// Returns an existing cache pool by name. Returns Some(pool) if the pool exists, None if the
 /// pool has not yet been created.
-pub fn get_pool(_pool_name: &str) -> Option<LruCachePool> {
-    None
+pub fn get_pool(pool_name: &str) -> Option<LruCachePool> {
+    cache().pools.get(pool_name).cloned()
 }
 
 /// Obtains an existing volatile cache pool by name.
-pub fn get_volatile_pool(_pool_name: &str) -> Result<Option<VolatileLruCachePool>> {
-    Ok(None)
+pub fn get_volatile_pool(pool_name: &str) -> Result<Option<VolatileLruCachePool>> {
+    Ok(get_pool(pool_name).map(|inner| VolatileLruCachePool { inner }))
 }
 
-/// A handle to data stored inside the cache. Can be used to get accessor structs
+/// A handle to data stored inside the cache. Can be used to get accessor structs.
+/// In this implementation of the cache a handle holds a copy of the data, so
+/// writing to a handle returned by `get_handle` doesn't change the cached value.
 pub struct LruCacheHandle<T> {
+    key: Vec<u8>,
+    data: Vec<u8>,
     _marker: PhantomData<T>,
 }
 
@@ -68,10 +102,25 @@ pub enum ReadWrite {}
 pub enum ReadWriteShared {}
 
 impl<T> LruCacheHandle<T> {
+    fn new(key: &[u8], data: Vec<u8>) -> Self {
+        Self {
+            key: key.to_vec(),
+            data,
+            _marker: PhantomData,
+        }
+    }
+
+    fn cast<U>(self) -> LruCacheHandle<U> {
+        LruCacheHandle {
+            key: self.key,
+            data: self.data,
+            _marker: PhantomData,
+        }
+    }
+
     pub fn get_reader<'a>(&'a self) -> Result<LruCacheHandleReader<'a>> {
         Ok(LruCacheHandleReader {
-            buffer: Cursor::new(Vec::new()),
-            _phantom: PhantomData,
+            buffer: Cursor::new(&self.data),
         })
     }
 }
@@ -79,8 +128,7 @@ impl<T> LruCacheHandle<T> {
 impl LruCacheHandle<ReadWrite> {
     pub fn get_writer<'a>(&'a mut self) -> Result<LruCacheHandleWriter<'a>> {
         Ok(LruCacheHandleWriter {
-            buffer: Cursor::new(Vec::new()),
-            _phantom: PhantomData,
+            buffer: Cursor::new(&mut self.data),
         })
     }
 }
@@ -88,23 +136,19 @@ impl LruCacheHandle<ReadWrite> {
 impl LruCacheHandle<ReadWriteShared> {
     pub fn get_writer<'a>(&'a mut self) -> Result<LruCacheHandleWriter<'a>> {
         Ok(LruCacheHandleWriter {
-            buffer: Cursor::new(Vec::new()),
-            _phantom: PhantomData,
+            buffer: Cursor::new(&mut self.data),
         })
     }
 
     pub fn get_remote_handle(&self) -> Result<LruCacheRemoteHandle<'_>> {
-        Ok(LruCacheRemoteHandle {
-            _phantom: PhantomData,
-        })
+        Ok(LruCacheRemoteHandle { data: &self.data })
     }
 }
 
 /// A read-only handle to an element in the cache. Implements io::Read and bytes::Buf
 /// for easy access to the data within the handle
 pub struct LruCacheHandleReader<'a> {
-    buffer: Cursor<Vec<u8>>,
-    _phantom: PhantomData<&'a ()>,
+    buffer: Cursor<&'a [u8]>,
 }
 
 impl<'a> Buf for LruCacheHandleReader<'a> {
@@ -134,8 +178,7 @@ impl<'a> Read for LruCacheHandleReader<'a> {
 /// A writable handle to an element in the cache. Implements io::{Read, Write} and
 /// bytes::{Buf, BufMut} for easy access to the data within the handle
 pub struct LruCacheHandleWriter<'a> {
-    buffer: Cursor<Vec<u8>>,
-    _phantom: PhantomData<&'a ()>,
+    buffer: Cursor<&'a mut [u8]>,
 }
 
 /// SAFETY: Only calls to advance_mut modify the current position.
@@ -180,8 +223,10 @@ impl<'a> Write for LruCacheHandleWriter<'a> {
 
 /// A handle remotely access data stored inside the cache. Tied to the lifetime of the
 /// LruCacheHandle it is created from.
+/// As this implementation of the cache isn't backed by shared memory, the
+/// offset is always 0.
 pub struct LruCacheRemoteHandle<'a> {
-    _phantom: PhantomData<&'a ()>,
+    data: &'a [u8],
 }
 
 impl<'a> LruCacheRemoteHandle<'a> {
@@ -190,17 +235,41 @@ impl<'a> LruCacheRemoteHandle<'a> {
     }
 
     pub fn get_length(&self) -> usize {
-        0
+        self.data.len()
     }
 }
 
+/// Identifies the version of a value read with
+/// [LruCachePool::get_with_cas_token], to only replace it with
+/// [LruCachePool::compare_and_swap] if it wasn't modified in the meantime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CasToken(u64);
+
 #[derive(Clone)]
 pub struct LruCachePool {
-    #[allow(dead_code)]
     pool_name: String,
+    store: Arc<Mutex<Store>>,
 }
 
 impl LruCachePool {
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().expect("poisoned lock")
+    }
+
+    fn insert<K, V>(&self, key: K, mut value: V, ttl: Option<Duration>, replace: bool) -> bool
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        let value = value.copy_to_bytes(value.remaining());
+        self.store().insert(key.as_ref(), value, ttl, replace)
+    }
+
+    /// Name the pool was created with
+    pub fn pool_name(&self) -> &str {
+        &self.pool_name
+    }
+
     /// Allocate memory for a key of known size; this will claim the memory until the handle is
     /// dropped or inserted into the cache.
     /// Note that if you do not insert the handle, it will not be visible to `get`, and the
@@ -208,78 +277,176 @@ impl LruCachePool {
     /// handles for long time periods, as this will reduce cachelib's efficiency.
     pub fn allocate<K>(
         &self,
-        _key: K,
-        _size: usize,
+        key: K,
+        size: usize,
     ) -> Result<Option<LruCacheHandle<ReadWriteShared>>>
     where
         K: AsRef<[u8]>,
     {
-        Ok(None)
+        let key = key.as_ref();
+        if key.len() + size > self.store().capacity() {
+            return Ok(None);
+        }
+        Ok(Some(LruCacheHandle::new(key, vec![0; size])))
     }
 
     /// Insert a previously allocated handle into the cache, making it visible to `get`
     /// Returns `false` if the handle could not be inserted (e.g. another handle with the same
     /// key was inserted first)
-    pub fn insert_handle(&self, _handle: LruCacheHandle<ReadWriteShared>) -> Result<bool> {
-        Ok(false)
+    pub fn insert_handle(&self, handle: LruCacheHandle<ReadWriteShared>) -> Result<bool> {
+        Ok(self.insert(handle.key, Bytes::from(handle.data), None, false))
     }
 
     /// Insert a key->value mapping into the pool. Returns true if the insertion was successful,
     /// false otherwise. This will not overwrite existing data.
-    pub fn set<K, V>(&self, _key: K, _value: V) -> Result<bool>
+    pub fn set<K, V>(&self, key: K, value: V) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        Ok(self.insert(key, value, None, false))
+    }
+
+    /// Like `set`, but the value expires after `ttl`
+    pub fn set_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<bool>
     where
         K: AsRef<[u8]>,
         V: Buf,
     {
-        Ok(false)
+        Ok(self.insert(key, value, Some(ttl), false))
     }
 
     /// Insert a key->value mapping into the pool. Returns true if the insertion was successful,
     /// false otherwise. This will overwrite existing data.
-    pub fn set_or_replace<K, V>(&self, _key: K, _value: V) -> Result<bool>
+    pub fn set_or_replace<K, V>(&self, key: K, value: V) -> Result<bool>
     where
         K: AsRef<[u8]>,
         V: Buf,
     {
-        Ok(false)
+        Ok(self.insert(key, value, None, true))
+    }
+
+    /// Like `set_or_replace`, but the value expires after `ttl`
+    pub fn set_or_replace_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        Ok(self.insert(key, value, Some(ttl), true))
     }
 
     /// Fetch a read handle for a key. Returns None if the key could not be found in the pool,
     /// Some(handle) if the key was found in the pool
     /// Note that the handle will stop the key being evicted from the cache until dropped -
     /// do not hold onto the handle for longer than the minimum necessary time.
-    pub fn get_handle<K>(&self, _key: K) -> Result<Option<LruCacheHandle<ReadWriteShared>>>
+    pub fn get_handle<K>(&self, key: K) -> Result<Option<LruCacheHandle<ReadWriteShared>>>
     where
         K: AsRef<[u8]>,
     {
-        Ok(None)
+        let key = key.as_ref();
+        Ok(self
+            .store()
+            .get(key)
+            .map(|value| LruCacheHandle::new(key, value.to_vec())))
     }
 
     /// Fetch the value for a key. Returns None if the key could not be found in the pool,
     /// Some(value) if the key was found in the pool
-    pub fn get<K>(&self, _key: K) -> Result<Option<Bytes>>
+    pub fn get<K>(&self, key: K) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self.store().get(key.as_ref()))
+    }
+
+    /// Like `get`, also returning a token to pass to `compare_and_swap`
+    pub fn get_with_cas_token<K>(&self, key: K) -> Result<Option<(Bytes, CasToken)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self
+            .store()
+            .get_with_version(key.as_ref())
+            .map(|(value, version)| (value, CasToken(version))))
+    }
+
+    /// Replace the value for a key only if it wasn't modified, removed or evicted since `token`
+    /// was returned by `get_with_cas_token`. Returns true if the value was replaced.
+    pub fn compare_and_swap<K, V>(&self, key: K, mut value: V, token: CasToken) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        let value = value.copy_to_bytes(value.remaining());
+        Ok(self
+            .store()
+            .compare_and_swap(key.as_ref(), value, None, token.0))
+    }
+
+    /// Like `compare_and_swap`, but the new value expires after `ttl`
+    pub fn compare_and_swap_with_ttl<K, V>(
+        &self,
+        key: K,
+        mut value: V,
+        token: CasToken,
+        ttl: Duration,
+    ) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        let value = value.copy_to_bytes(value.remaining());
+        Ok(self
+            .store()
+            .compare_and_swap(key.as_ref(), value, Some(ttl), token.0))
+    }
+
+    /// Remove the value for a key, if present
+    pub fn remove<K>(&self, key: K) -> Result<()>
     where
         K: AsRef<[u8]>,
     {
-        Ok(None)
+        self.store().remove(key.as_ref());
+        Ok(())
     }
 
     /// Return the current size of this pool
     pub fn get_size(&self) -> Result<usize> {
-        Ok(0)
+        Ok(self.store().capacity())
+    }
+
+    /// Return how many bytes of keys and values are stored in this pool
+    pub fn get_used_size(&self) -> Result<usize> {
+        Ok(self.store().size())
     }
 
     /// Increase the size of the pool by size, returning true if it grew, false if there is
     /// insufficent available memory to grow this pool
-    pub fn grow_pool(&self, _size: usize) -> Result<bool> {
-        Ok(false)
+    pub fn grow_pool(&self, size: usize) -> Result<bool> {
+        let mut cache = cache();
+        if cache.available_space < size {
+            return Ok(false);
+        }
+        cache.available_space -= size;
+        let mut store = self.store();
+        let capacity = store.capacity() + size;
+        store.set_capacity(capacity);
+        Ok(true)
     }
 
     /// Decrease the size of the pool by size, returning `true` if the pool will shrink, `false`
     /// if the pool is already smaller than size.
     /// Note that the actual shrinking is done asynchronously, based on the PoolResizeConfig
     /// supplied at the creation of the cachelib setup.
-    pub fn shrink_pool(&self, _size: usize) -> Result<bool> {
+    pub fn shrink_pool(&self, size: usize) -> Result<bool> {
+        let mut cache = cache();
+        let mut store = self.store();
+        let capacity = match store.capacity().checked_sub(size) {
+            Some(capacity) => capacity,
+            None => return Ok(false),
+        };
+        store.set_capacity(capacity);
+        cache.available_space += size;
         Ok(true)
     }
 
@@ -287,72 +454,190 @@ impl LruCachePool {
     /// false if you asked to move more bytes than are available
     /// Note that the actual shrinking of this pool is done asynchronously, based on the
     /// PoolResizeConfig supplied at the creation of the cachelib setup.
-    pub fn transfer_capacity_to(&self, _dest: &Self, _bytes: usize) -> Result<bool> {
-        Ok(false)
+    pub fn transfer_capacity_to(&self, dest: &Self, bytes: usize) -> Result<bool> {
+        if !self.shrink_pool(bytes)? {
+            return Ok(false);
+        }
+        dest.grow_pool(bytes)
     }
 }
 
 #[derive(Clone)]
 pub struct VolatileLruCachePool {
-    #[allow(dead_code)]
     inner: LruCachePool,
 }
 
 impl VolatileLruCachePool {
-    pub fn allocate<K>(&self, _key: K, _size: usize) -> Result<Option<LruCacheHandle<ReadWrite>>>
+    pub fn pool_name(&self) -> &str {
+        self.inner.pool_name()
+    }
+
+    pub fn allocate<K>(&self, key: K, size: usize) -> Result<Option<LruCacheHandle<ReadWrite>>>
     where
         K: AsRef<[u8]>,
     {
-        Ok(None)
+        Ok(self.inner.allocate(key, size)?.map(LruCacheHandle::cast))
     }
 
-    pub fn insert_handle(&self, _handle: LruCacheHandle<ReadWrite>) -> Result<bool> {
-        Ok(false)
+    pub fn insert_handle(&self, handle: LruCacheHandle<ReadWrite>) -> Result<bool> {
+        self.inner.insert_handle(handle.cast())
     }
 
-    pub fn set<K, V>(&self, _key: K, _value: V) -> Result<bool>
+    pub fn set<K, V>(&self, key: K, value: V) -> Result<bool>
     where
         K: AsRef<[u8]>,
         V: Buf,
     {
-        Ok(false)
+        self.inner.set(key, value)
     }
 
-    pub fn set_or_replace<K, V>(&self, _key: K, _value: V) -> Result<bool>
+    pub fn set_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<bool>
     where
         K: AsRef<[u8]>,
         V: Buf,
     {
-        Ok(false)
+        self.inner.set_with_ttl(key, value, ttl)
     }
 
-    pub fn get_handle<K>(&self, _key: K) -> Result<Option<LruCacheHandle<ReadOnly>>>
+    pub fn set_or_replace<K, V>(&self, key: K, value: V) -> Result<bool>
     where
         K: AsRef<[u8]>,
+        V: Buf,
+    {
+        self.inner.set_or_replace(key, value)
+    }
+
+    pub fn set_or_replace_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
     {
-        Ok(None)
+        self.inner.set_or_replace_with_ttl(key, value, ttl)
     }
 
-    pub fn get<K>(&self, _key: K) -> Result<Option<Bytes>>
+    pub fn get_handle<K>(&self, key: K) -> Result<Option<LruCacheHandle<ReadOnly>>>
     where
         K: AsRef<[u8]>,
     {
-        Ok(None)
+        Ok(self.inner.get_handle(key)?.map(LruCacheHandle::cast))
+    }
+
+    pub fn get<K>(&self, key: K) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.get(key)
+    }
+
+    pub fn get_with_cas_token<K>(&self, key: K) -> Result<Option<(Bytes, CasToken)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.get_with_cas_token(key)
+    }
+
+    pub fn compare_and_swap<K, V>(&self, key: K, value: V, token: CasToken) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        self.inner.compare_and_swap(key, value, token)
+    }
+
+    pub fn compare_and_swap_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        token: CasToken,
+        ttl: Duration,
+    ) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: Buf,
+    {
+        self.inner.compare_and_swap_with_ttl(key, value, token, ttl)
+    }
+
+    pub fn remove<K>(&self, key: K) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.remove(key)
     }
 
     pub fn get_size(&self) -> Result<usize> {
-        Ok(0)
+        self.inner.get_size()
     }
 
-    pub fn grow_pool(&self, _size: usize) -> Result<bool> {
-        Ok(false)
+    pub fn get_used_size(&self) -> Result<usize> {
+        self.inner.get_used_size()
     }
 
-    pub fn shrink_pool(&self, _size: usize) -> Result<bool> {
-        Ok(true)
+    pub fn grow_pool(&self, size: usize) -> Result<bool> {
+        self.inner.grow_pool(size)
+    }
+
+    pub fn shrink_pool(&self, size: usize) -> Result<bool> {
+        self.inner.shrink_pool(size)
     }
 
-    pub fn transfer_capacity_to(&self, _dest: &Self, _bytes: usize) -> Result<bool> {
-        Ok(false)
+    pub fn transfer_capacity_to(&self, dest: &Self, bytes: usize) -> Result<bool> {
+        self.inner.transfer_capacity_to(&dest.inner, bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool() {
+        let pool = get_or_create_pool("test_pool", 1024).unwrap();
+        assert!(pool.set("key", Bytes::from("value")).unwrap());
+        assert!(!pool.set("key", Bytes::from("other")).unwrap());
+        assert_eq!(pool.get("key").unwrap(), Some(Bytes::from("value")));
+        assert!(pool.set_or_replace("key", Bytes::from("other")).unwrap());
+        assert_eq!(
+            get_pool("test_pool").unwrap().get("key").unwrap(),
+            Some(Bytes::from("other"))
+        );
+
+        let mut handle = pool.allocate("handle", 5).unwrap().unwrap();
+        handle.get_writer().unwrap().write_all(b"hello").unwrap();
+        assert!(pool.insert_handle(handle).unwrap());
+        let handle = pool.get_handle("handle").unwrap().unwrap();
+        let mut data = Vec::new();
+        handle.get_reader().unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(handle.get_remote_handle().unwrap().get_length(), 5);
+
+        let (value, token) = pool.get_with_cas_token("key").unwrap().unwrap();
+        assert_eq!(value, Bytes::from("other"));
+        assert!(pool
+            .compare_and_swap("key", Bytes::from("third"), token)
+            .unwrap());
+        assert!(!pool
+            .compare_and_swap("key", Bytes::from("fourth"), token)
+            .unwrap());
+        assert_eq!(pool.get("key").unwrap(), Some(Bytes::from("third")));
+
+        assert_eq!(pool.get_used_size().unwrap(), 19);
+        pool.remove("key").unwrap();
+        assert_eq!(pool.get("key").unwrap(), None);
+        assert!(pool.allocate("huge", 2048).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resize() {
+        let first = get_or_create_volatile_pool("test_resize_first", 100).unwrap();
+        let second = get_or_create_volatile_pool("test_resize_second", 100).unwrap();
+        assert!(first.set("key", Bytes::from(vec![0; 50])).unwrap());
+
+        assert!(!first.transfer_capacity_to(&second, 200).unwrap());
+        assert!(first.transfer_capacity_to(&second, 60).unwrap());
+        assert_eq!(first.get_size().unwrap(), 40);
+        assert_eq!(second.get_size().unwrap(), 160);
+        // The value no longer fits in the pool
+        assert_eq!(first.get("key").unwrap(), None);
     }
 }
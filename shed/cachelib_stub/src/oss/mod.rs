@@ -9,3 +9,4 @@
 
 pub mod abomonation_future_cache;
 pub mod lrucache;
+mod store;
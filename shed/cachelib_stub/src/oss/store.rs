@@ -0,0 +1,294 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "cachelib";
+    hits: dynamic_timeseries("{}.hits", (pool: String); Rate, Sum),
+    misses: dynamic_timeseries("{}.misses", (pool: String); Rate, Sum),
+    evictions: dynamic_timeseries("{}.evictions", (pool: String); Rate, Sum),
+    size_bytes: dynamic_counter("{}.size_bytes", (pool: String)),
+}
+
+struct Entry {
+    value: Bytes,
+    expires_at: Option<Instant>,
+    /// Position of the entry in `Store::lru`
+    tick: u64,
+    /// Tick the value was stored at, identifying it for compare and swap
+    version: u64,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/// In-process storage behind a cache pool: an LRU bounded by the size of its
+/// keys and values, whose entries can also expire after a TTL. The hits,
+/// misses, evictions and size of the store are exported as
+/// `cachelib.<pool>.{hits,misses,evictions,size_bytes}`.
+pub(crate) struct Store {
+    pool_name: String,
+    capacity: usize,
+    size: usize,
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Keys ordered from the least to the most recently used
+    lru: BTreeMap<u64, Vec<u8>>,
+    next_tick: u64,
+}
+
+fn entry_size(key: &[u8], value: &Bytes) -> usize {
+    key.len() + value.len()
+}
+
+impl Store {
+    pub(crate) fn new(pool_name: &str, capacity: usize) -> Self {
+        Self {
+            pool_name: pool_name.to_owned(),
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    fn add_size(&mut self, size: usize) {
+        self.size += size;
+        STATS::size_bytes.increment_value(size as i64, (self.pool_name.clone(),));
+    }
+
+    fn sub_size(&mut self, size: usize) {
+        self.size -= size;
+        STATS::size_bytes.increment_value(-(size as i64), (self.pool_name.clone(),));
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    /// The value of `key`, marking it as the most recently used
+    pub(crate) fn get(&mut self, key: &[u8]) -> Option<Bytes> {
+        self.get_with_version(key).map(|(value, _)| value)
+    }
+
+    /// Like [Store::get], also returning the version of the value to pass to
+    /// [Store::compare_and_swap]
+    pub(crate) fn get_with_version(&mut self, key: &[u8]) -> Option<(Bytes, u64)> {
+        let value = self.lookup(key);
+        let stat = if value.is_some() {
+            &STATS::hits
+        } else {
+            &STATS::misses
+        };
+        stat.add_value(1, (self.pool_name.clone(),));
+        value
+    }
+
+    fn lookup(&mut self, key: &[u8]) -> Option<(Bytes, u64)> {
+        let now = Instant::now();
+        if self.entries.get(key)?.is_expired(now) {
+            self.remove(key);
+            return None;
+        }
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        let key = self.lru.remove(&entry.tick)?;
+        entry.tick = tick;
+        let value = (entry.value.clone(), entry.version);
+        self.lru.insert(tick, key);
+        Some(value)
+    }
+
+    /// Store `value` under `key`, evicting the least recently used entries to
+    /// make room for it. With `replace` false an existing value isn't
+    /// overwritten. Returns whether the value was stored, which it isn't
+    /// either if it is larger than the whole store.
+    pub(crate) fn insert(
+        &mut self,
+        key: &[u8],
+        value: Bytes,
+        ttl: Option<Duration>,
+        replace: bool,
+    ) -> bool {
+        let size = entry_size(key, &value);
+        if size > self.capacity {
+            return false;
+        }
+        let now = Instant::now();
+        if let Some(entry) = self.entries.get(key) {
+            if !replace && !entry.is_expired(now) {
+                return false;
+            }
+            self.remove(key);
+        }
+        self.evict_to(self.capacity - size);
+
+        let tick = self.tick();
+        self.lru.insert(tick, key.to_vec());
+        self.entries.insert(
+            key.to_vec(),
+            Entry {
+                value,
+                expires_at: ttl.map(|ttl| now + ttl),
+                tick,
+                version: tick,
+            },
+        );
+        self.add_size(size);
+        true
+    }
+
+    /// Replace the value of `key` with `value` only if it is still the one
+    /// with `version`, i.e. it wasn't modified, removed or evicted since it
+    /// was read. Returns whether the value was stored.
+    pub(crate) fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        value: Bytes,
+        ttl: Option<Duration>,
+        version: u64,
+    ) -> bool {
+        let now = Instant::now();
+        match self.entries.get(key) {
+            Some(entry) if entry.version == version && !entry.is_expired(now) => {
+                self.insert(key, value, ttl, true)
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove `key`, returning whether it was present
+    pub(crate) fn remove(&mut self, key: &[u8]) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.lru.remove(&entry.tick);
+                self.sub_size(entry_size(key, &entry.value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evict the least recently used entries until the size is at most `size`
+    fn evict_to(&mut self, size: usize) {
+        let mut evictions = 0;
+        while self.size > size {
+            let (_, key) = match self.lru.pop_first() {
+                Some(lru) => lru,
+                None => break,
+            };
+            let entry = self
+                .entries
+                .remove(&key)
+                .expect("LRU and entries out of sync");
+            self.sub_size(entry_size(&key, &entry.value));
+            evictions += 1;
+        }
+        if evictions > 0 {
+            STATS::evictions.add_value(evictions, (self.pool_name.clone(),));
+        }
+    }
+
+    /// Total size of the keys and values stored
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Most bytes of keys and values that can be stored
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Resize the store, evicting entries if it shrinks below its size
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_to(capacity);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = Store::new("test_lru_eviction", 10);
+        assert!(store.insert(b"a", Bytes::from("111"), None, false));
+        assert!(store.insert(b"b", Bytes::from("222"), None, false));
+        assert_eq!(store.size(), 8);
+        // Using a makes b the least recently used
+        assert_eq!(store.get(b"a"), Some(Bytes::from("111")));
+        assert!(store.insert(b"c", Bytes::from("333"), None, false));
+        assert_eq!(store.get(b"b"), None);
+        assert_eq!(store.get(b"a"), Some(Bytes::from("111")));
+        assert_eq!(store.size(), 8);
+
+        assert!(!store.insert(b"a", Bytes::from("4"), None, false));
+        assert!(store.insert(b"a", Bytes::from("4"), None, true));
+        assert_eq!(store.get(b"a"), Some(Bytes::from("4")));
+        assert!(!store.insert(b"big", Bytes::from("too large"), None, true));
+
+        store.set_capacity(4);
+        assert_eq!(store.get(b"c"), None);
+        assert_eq!(store.size(), 2);
+        assert!(store.remove(b"a"));
+        assert_eq!(store.size(), 0);
+
+        // Outside of fbcode the stats are recorded in memory
+        if !cfg!(fbcode_build) {
+            let stats = stats::snapshot();
+            let stat = |name| stats[&format!("cachelib.test_lru_eviction.{}", name)];
+            assert_eq!(stat("hits.sum"), 3);
+            assert_eq!(stat("misses.sum"), 2);
+            assert_eq!(stat("evictions.sum"), 2);
+            assert_eq!(stat("size_bytes"), 0);
+        }
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let mut store = Store::new("test_compare_and_swap", 100);
+        assert!(!store.compare_and_swap(b"a", Bytes::from("1"), None, 0));
+        assert!(store.insert(b"a", Bytes::from("1"), None, false));
+        let (value, version) = store.get_with_version(b"a").unwrap();
+        assert_eq!(value, Bytes::from("1"));
+
+        assert!(store.compare_and_swap(b"a", Bytes::from("2"), None, version));
+        // The value changed since it was read
+        assert!(!store.compare_and_swap(b"a", Bytes::from("3"), None, version));
+        assert_eq!(store.get(b"a"), Some(Bytes::from("2")));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut store = Store::new("test_ttl", 100);
+        assert!(store.insert(b"a", Bytes::from("1"), Some(Duration::ZERO), false));
+        assert_eq!(store.get(b"a"), None);
+        assert_eq!(store.size(), 0);
+
+        assert!(store.insert(b"b", Bytes::from("1"), Some(Duration::ZERO), false));
+        // An expired value doesn't prevent adding a new one
+        assert!(store.insert(
+            b"b",
+            Bytes::from("2"),
+            Some(Duration::from_secs(600)),
+            false
+        ));
+        assert_eq!(store.get(b"b"), Some(Bytes::from("2")));
+    }
+}
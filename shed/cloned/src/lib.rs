@@ -40,10 +40,100 @@
 //! assert!(foo == bar);
 //! # }
 //! ```
+//!
+//! Or cloning any expression, e.g. a nested field, into a named local:
+//! ```
+//! # use cloned::cloned;
+//! struct Config {
+//!     name: String,
+//! }
+//! struct Inner {
+//!     config: Config,
+//! }
+//! struct A {
+//!     inner: Inner,
+//! }
+//! impl A {
+//!     fn foo(&self) {
+//!         cloned!(name = self.inner.config.name, mut other = self.inner.config.name);
+//!         other.push('!');
+//!         (move || {
+//!             println!("{} {}", name, other);
+//!         })();
+//!     }
+//! }
+//! # fn main () {}
+//! ```
+//!
+//! An `Arc` can be captured as a `Weak` reference with `weak`, so that the
+//! closure doesn't keep the value alive:
+//! ```
+//! # use cloned::cloned;
+//! # use std::sync::Arc;
+//! # fn main () {
+//! let value = Arc::new(42);
+//! cloned!(weak value as handle);
+//! let callback = move || handle.upgrade().map(|value| *value);
+//! assert_eq!(callback(), Some(42));
+//! drop(value);
+//! assert_eq!(callback(), None);
+//! # }
+//! ```
 
 /// See crate's documentation
 #[macro_export]
 macro_rules! cloned {
+    ($alias:ident = $e:expr, $($tt:tt)*) => {
+        cloned!($alias = $e);
+        cloned!($($tt)*);
+    };
+    (mut $alias:ident = $e:expr, $($tt:tt)*) => {
+        cloned!(mut $alias = $e);
+        cloned!($($tt)*);
+    };
+    ($alias:ident = $e:expr) => {
+        let $alias = $e.clone();
+    };
+    (mut $alias:ident = $e:expr) => {
+        let mut $alias = $e.clone();
+    };
+
+    (weak $alias:ident = $e:expr, $($tt:tt)*) => {
+        cloned!(weak $alias = $e);
+        cloned!($($tt)*);
+    };
+    (weak $alias:ident = $e:expr) => {
+        let $alias = ::std::sync::Arc::downgrade(&$e);
+    };
+    (weak $i:ident as $alias:ident) => {
+        cloned!(weak $alias = $i);
+    };
+    (weak $i:ident as $alias:ident, $($tt:tt)*) => {
+        cloned!(weak $alias = $i);
+        cloned!($($tt)*);
+    };
+    (weak $this:ident . $i:ident as $alias:ident) => {
+        cloned!(weak $alias = $this.$i);
+    };
+    (weak $this:ident . $i:ident as $alias:ident, $($tt:tt)*) => {
+        cloned!(weak $alias = $this.$i);
+        cloned!($($tt)*);
+    };
+    (weak $i:ident) => {
+        cloned!(weak $i = $i);
+    };
+    (weak $i:ident, $($tt:tt)*) => {
+        cloned!(weak $i = $i);
+        cloned!($($tt)*);
+    };
+    (weak $this:ident . $i:ident) => {
+        cloned!(weak $i = $this.$i);
+    };
+    (weak $this:ident . $i:ident, $($tt:tt)*) => {
+        cloned!(weak $i = $this.$i);
+        cloned!($($tt)*);
+    };
+
     ($i:ident as $alias:ident) => {
         let $alias = $i.clone();
     };
@@ -212,4 +302,52 @@ mod tests {
         cloned!(a, mut c.x as x2);
         cloned!(a, mut c.x as x2,);
     }
+
+    #[test]
+    #[allow(unused_variables, unused_mut)]
+    fn assignments() {
+        struct B {
+            a: A,
+        }
+        let a = 1;
+        let b = B {
+            a: A {
+                x: "foo".to_string(),
+            },
+        };
+
+        cloned!(a2 = a);
+        cloned!(x = b.a.x, mut y = b.a.x);
+        y += "bar";
+        assert_eq!((a2, x.as_str(), y.as_str()), (1, "foo", "foobar"));
+
+        cloned!(x = b.a.x,);
+        cloned!(mut x = b.a.x,);
+        cloned!(a, x = b.a.x, mut a as a2, a3 = a);
+    }
+
+    #[test]
+    #[allow(unused_variables)]
+    fn weak() {
+        use std::sync::Arc;
+
+        struct B {
+            handle: Arc<String>,
+        }
+        let handle = Arc::new("foo".to_string());
+        let b = B {
+            handle: handle.clone(),
+        };
+
+        cloned!(weak handle, weak b.handle as other);
+        assert_eq!(handle.upgrade().as_deref(), Some(&"foo".to_string()));
+        assert_eq!(Arc::strong_count(&b.handle), 2);
+
+        cloned!(weak h1 = b.handle, weak b.handle, weak b.handle as h2,);
+        cloned!(weak weak = b.handle, a = 1, weak b.handle as h3);
+
+        drop(b);
+        assert!(other.upgrade().is_some());
+        drop(handle);
+    }
 }
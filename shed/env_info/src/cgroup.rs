@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// cgroup v1 reports no memory limit as a huge page-aligned value
+const V1_UNLIMITED_MEMORY: u64 = 1 << 62;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Limits {
+    /// CPU quota in CPUs
+    pub(crate) cpu: Option<f64>,
+    /// Memory limit in bytes
+    pub(crate) memory: Option<u64>,
+}
+
+/// Limits of the cgroup of the process, from the cgroup v2 unified hierarchy
+/// if it is mounted, else from the cgroup v1 cpu and memory controllers
+pub(crate) fn detect(root: &Path) -> Limits {
+    let mount = root.join("sys/fs/cgroup");
+    let cgroups = fs::read_to_string(root.join("proc/self/cgroup")).unwrap_or_default();
+    if mount.join("cgroup.controllers").exists() {
+        detect_v2(&mount, &cgroups)
+    } else {
+        detect_v1(&mount, &cgroups)
+    }
+}
+
+fn read(path: &Path) -> Option<String> {
+    Some(fs::read_to_string(path).ok()?.trim().to_owned())
+}
+
+/// The directory of the cgroup of the process for `controller` (empty for
+/// v2), given /proc/self/cgroup lines like `<id>:<controllers>:<path>`.
+/// Inside a cgroup namespace the cgroup of the process is mounted at the root
+/// of the hierarchy, in which case the path might not exist under the mount.
+fn cgroup_dir(mount: &Path, cgroups: &str, controller: &str) -> PathBuf {
+    let path = cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let _id = fields.next()?;
+        let controllers = fields.next()?;
+        let path = fields.next()?;
+        let matches = if controller.is_empty() {
+            controllers.is_empty()
+        } else {
+            controllers.split(',').any(|c| c == controller)
+        };
+        matches.then(|| path.trim_start_matches('/'))
+    });
+    match path {
+        Some(path) if mount.join(path).exists() => mount.join(path),
+        _ => mount.to_path_buf(),
+    }
+}
+
+fn detect_v2(mount: &Path, cgroups: &str) -> Limits {
+    let dir = cgroup_dir(mount, cgroups, "");
+    // Formatted as "<quota> <period>" or "max <period>"
+    let cpu = read(&dir.join("cpu.max")).and_then(|cpu_max| {
+        let (quota, period) = cpu_max.split_once(' ')?;
+        cpu_quota(quota.parse::<f64>().ok()?, period.parse::<f64>().ok()?)
+    });
+    let memory = read(&dir.join("memory.max")).and_then(|max| max.parse().ok());
+    Limits { cpu, memory }
+}
+
+fn detect_v1(mount: &Path, cgroups: &str) -> Limits {
+    let cpu_dir = cgroup_dir(&mount.join("cpu"), cgroups, "cpu");
+    let quota = read(&cpu_dir.join("cpu.cfs_quota_us")).and_then(|quota| quota.parse().ok());
+    let period = read(&cpu_dir.join("cpu.cfs_period_us")).and_then(|period| period.parse().ok());
+    let cpu = match (quota, period) {
+        (Some(quota), Some(period)) => cpu_quota(quota, period),
+        _ => None,
+    };
+
+    let memory_dir = cgroup_dir(&mount.join("memory"), cgroups, "memory");
+    let memory = read(&memory_dir.join("memory.limit_in_bytes"))
+        .and_then(|limit| limit.parse().ok())
+        .filter(|limit| *limit < V1_UNLIMITED_MEMORY);
+    Limits { cpu, memory }
+}
+
+/// Quota in CPUs, a non-positive quota meaning there is none
+fn cpu_quota(quota: f64, period: f64) -> Option<f64> {
+    (quota > 0.0 && period > 0.0).then(|| quota / period)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_v2() {
+        let root = TempDir::new("cgroup_v2").unwrap();
+        let root = root.path();
+        write(root, "sys/fs/cgroup/cgroup.controllers", "cpu memory\n");
+        assert_eq!(detect(root), Limits::default());
+
+        // Namespaced: the cgroup of the process is at the root of the mount
+        write(root, "proc/self/cgroup", "0::/not/mounted\n");
+        write(root, "sys/fs/cgroup/cpu.max", "max 100000\n");
+        write(root, "sys/fs/cgroup/memory.max", "max\n");
+        assert_eq!(detect(root), Limits::default());
+
+        write(root, "sys/fs/cgroup/cpu.max", "50000 100000\n");
+        write(root, "sys/fs/cgroup/memory.max", "1048576\n");
+        assert_eq!(
+            detect(root),
+            Limits {
+                cpu: Some(0.5),
+                memory: Some(1048576),
+            }
+        );
+    }
+
+    #[test]
+    fn test_v1() {
+        let root = TempDir::new("cgroup_v1").unwrap();
+        let root = root.path();
+        write(
+            root,
+            "proc/self/cgroup",
+            "5:memory:/job\n4:cpu,cpuacct:/job\n0::/job\n",
+        );
+        write(root, "sys/fs/cgroup/cpu/job/cpu.cfs_quota_us", "-1\n");
+        write(root, "sys/fs/cgroup/cpu/job/cpu.cfs_period_us", "100000\n");
+        write(
+            root,
+            "sys/fs/cgroup/memory/job/memory.limit_in_bytes",
+            "9223372036854771712\n",
+        );
+        assert_eq!(detect(root), Limits::default());
+
+        write(root, "sys/fs/cgroup/cpu/job/cpu.cfs_quota_us", "400000\n");
+        write(
+            root,
+            "sys/fs/cgroup/memory/job/memory.limit_in_bytes",
+            "2147483648\n",
+        );
+        assert_eq!(
+            detect(root),
+            Limits {
+                cpu: Some(4.0),
+                memory: Some(2147483648),
+            }
+        );
+    }
+}
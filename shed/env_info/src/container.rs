@@ -0,0 +1,98 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fs;
+use std::path::Path;
+
+/// Container runtime a process runs in
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ContainerRuntime {
+    /// Docker, or another runtime creating `/.dockerenv`
+    Docker,
+    /// Podman
+    Podman,
+    /// A Kubernetes pod, whatever the runtime of its containers
+    Kubernetes,
+    /// LXC
+    Lxc,
+    /// systemd-nspawn
+    SystemdNspawn,
+    /// Another runtime, named by the `container` environment variable
+    Other(String),
+}
+
+impl ContainerRuntime {
+    /// The runtime named by the `container` environment variable, which is
+    /// set by systemd-nspawn, Podman and LXC among others
+    pub(crate) fn from_env(name: &str) -> Option<Self> {
+        let runtime = match name.trim() {
+            "" => return None,
+            "docker" => Self::Docker,
+            "podman" | "oci" => Self::Podman,
+            "lxc" => Self::Lxc,
+            "systemd-nspawn" => Self::SystemdNspawn,
+            other => Self::Other(other.to_owned()),
+        };
+        Some(runtime)
+    }
+}
+
+/// Detect the runtime from the files it creates and from the cgroups of the
+/// init process of the container
+pub(crate) fn detect(root: &Path) -> Option<ContainerRuntime> {
+    let cgroups = fs::read_to_string(root.join("proc/1/cgroup")).unwrap_or_default();
+    if cgroups.contains("kubepods") {
+        Some(ContainerRuntime::Kubernetes)
+    } else if root.join(".dockerenv").exists() || cgroups.contains("/docker") {
+        Some(ContainerRuntime::Docker)
+    } else if root.join("run/.containerenv").exists() || cgroups.contains("libpod") {
+        Some(ContainerRuntime::Podman)
+    } else if cgroups.contains("/lxc") {
+        Some(ContainerRuntime::Lxc)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_detect() {
+        let root = TempDir::new("container").unwrap();
+        let root = root.path();
+        assert_eq!(detect(root), None);
+
+        write(root, "run/.containerenv", "");
+        assert_eq!(detect(root), Some(ContainerRuntime::Podman));
+
+        write(
+            root,
+            "proc/1/cgroup",
+            "0::/kubepods/besteffort/pod1234/5678\n",
+        );
+        assert_eq!(detect(root), Some(ContainerRuntime::Kubernetes));
+    }
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(ContainerRuntime::from_env(""), None);
+        assert_eq!(
+            ContainerRuntime::from_env("systemd-nspawn"),
+            Some(ContainerRuntime::SystemdNspawn)
+        );
+        assert_eq!(
+            ContainerRuntime::from_env("wsl"),
+            Some(ContainerRuntime::Other("wsl".to_owned()))
+        );
+    }
+}
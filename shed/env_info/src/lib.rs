@@ -0,0 +1,249 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Crate detecting the resources actually available to the process: the CPU
+//! and memory limits of its cgroup, the container runtime it runs in and the
+//! NUMA layout of the host. The number of CPUs or the memory of the host are
+//! misleading inside a container, so [EnvironmentInfo] also recommends the
+//! number of tokio worker threads and cache sizes to use.
+//!
+//! Detection reads `/proc` and `/sys` and never fails: what can't be
+//! detected, e.g. on other platforms than Linux, is reported as unknown.
+//!
+//! ```
+//! let env = env_info::EnvironmentInfo::detect();
+//! let runtime = tokio::runtime::Builder::new_multi_thread()
+//!     .worker_threads(env.recommended_worker_threads())
+//!     .build()
+//!     .unwrap();
+//! # drop(runtime);
+//! ```
+
+use std::path::Path;
+
+mod cgroup;
+mod container;
+mod numa;
+
+pub use crate::container::ContainerRuntime;
+pub use crate::numa::NumaNode;
+
+/// Resources available to the process
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentInfo {
+    /// Number of CPUs the process may run on, from its affinity mask
+    pub available_cpus: usize,
+
+    /// CPU quota of the cgroup of the process, in CPUs
+    pub cpu_limit: Option<f64>,
+
+    /// Memory limit of the cgroup of the process, in bytes
+    pub memory_limit: Option<u64>,
+
+    /// Total memory of the host, in bytes
+    pub host_memory: Option<u64>,
+
+    /// Container runtime the process runs in
+    pub container_runtime: Option<ContainerRuntime>,
+
+    /// NUMA nodes of the host, empty if unknown
+    pub numa_nodes: Vec<NumaNode>,
+}
+
+impl EnvironmentInfo {
+    /// Detect the environment of the current process. Where `/proc` and
+    /// `/sys` don't list the CPUs, their number is given by the standard
+    /// library instead.
+    pub fn detect() -> Self {
+        let root = Path::new("/");
+        let mut info = Self::detect_at(root);
+        if affinity_cpus(root).is_none() && info.numa_nodes.is_empty() {
+            info.available_cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        }
+        if info.container_runtime.is_none() {
+            info.container_runtime = std::env::var("container")
+                .ok()
+                .and_then(|name| ContainerRuntime::from_env(&name));
+        }
+        info
+    }
+
+    /// Detect the environment from the `/proc` and `/sys` trees under `root`.
+    /// The CPUs available are those of the affinity mask of the process, or
+    /// of the NUMA nodes if it is unknown, or 1 if both are.
+    pub fn detect_at(root: &Path) -> Self {
+        let numa_nodes = numa::detect(root);
+        let available_cpus = affinity_cpus(root)
+            .unwrap_or_else(|| numa_nodes.iter().map(|node| node.cpus.len()).sum());
+        let limits = cgroup::detect(root);
+        Self {
+            available_cpus: available_cpus.max(1),
+            cpu_limit: limits.cpu,
+            memory_limit: limits.memory,
+            host_memory: host_memory(root),
+            container_runtime: container::detect(root),
+            numa_nodes,
+        }
+    }
+
+    /// Number of CPUs the process can effectively use: the available CPUs
+    /// further limited by the cgroup quota
+    pub fn effective_cpus(&self) -> f64 {
+        let available = self.available_cpus as f64;
+        self.cpu_limit
+            .map_or(available, |limit| limit.min(available))
+    }
+
+    /// Number of tokio worker threads to use: one per effectively usable CPU,
+    /// rounding a fractional quota up, and at least one
+    pub fn recommended_worker_threads(&self) -> usize {
+        (self.effective_cpus().ceil() as usize).max(1)
+    }
+
+    /// Memory available to the process: the cgroup limit, or the memory of
+    /// the host if there is none or it is larger
+    pub fn available_memory(&self) -> Option<u64> {
+        match (self.memory_limit, self.host_memory) {
+            (Some(limit), Some(host)) => Some(limit.min(host)),
+            (limit, host) => limit.or(host),
+        }
+    }
+
+    /// Size of a cache using `fraction` of the available memory, or
+    /// `default` if the available memory is unknown
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between 0 and 1.
+    pub fn recommended_cache_bytes(&self, fraction: f64, default: u64) -> u64 {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "cache fraction must be between 0 and 1"
+        );
+        self.available_memory()
+            .map_or(default, |memory| (memory as f64 * fraction) as u64)
+    }
+}
+
+/// Number of CPUs in the affinity mask of the process, from the
+/// `Cpus_allowed_list` of `/proc/self/status`
+fn affinity_cpus(root: &Path) -> Option<usize> {
+    let status = std::fs::read_to_string(root.join("proc/self/status")).ok()?;
+    let list = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))?;
+    Some(numa::parse_cpu_list(list)?.len())
+}
+
+/// Total memory of the host from `/proc/meminfo`
+fn host_memory(root: &Path) -> Option<u64> {
+    let meminfo = std::fs::read_to_string(root.join("proc/meminfo")).ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::fs;
+    use std::path::Path;
+
+    /// Write `contents` to `path` under `root`, creating its directories
+    pub(crate) fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_detect_at() {
+        let root = TempDir::new("env_info").unwrap();
+        let root = root.path();
+        let info = EnvironmentInfo::detect_at(root);
+        assert_eq!(info.available_cpus, 1);
+        assert_eq!(info.recommended_worker_threads(), 1);
+        assert_eq!(info.recommended_cache_bytes(0.5, 1234), 1234);
+
+        write(
+            root,
+            "proc/meminfo",
+            "MemTotal:       16384 kB\nMemFree: 1 kB\n",
+        );
+        write(root, "proc/self/cgroup", "0::/service\n");
+        write(root, "sys/fs/cgroup/cgroup.controllers", "cpu memory\n");
+        write(root, "sys/fs/cgroup/service/cpu.max", "250000 100000\n");
+        write(root, "sys/fs/cgroup/service/memory.max", "8192\n");
+        write(root, "sys/devices/system/node/node0/cpulist", "0-3\n");
+        write(root, "sys/devices/system/node/node1/cpulist", "4-7\n");
+        write(root, ".dockerenv", "");
+
+        let info = EnvironmentInfo::detect_at(root);
+        assert_eq!(
+            info,
+            EnvironmentInfo {
+                available_cpus: 8,
+                cpu_limit: Some(2.5),
+                memory_limit: Some(8192),
+                host_memory: Some(16384 * 1024),
+                container_runtime: Some(ContainerRuntime::Docker),
+                numa_nodes: vec![
+                    NumaNode {
+                        id: 0,
+                        cpus: vec![0, 1, 2, 3],
+                    },
+                    NumaNode {
+                        id: 1,
+                        cpus: vec![4, 5, 6, 7],
+                    },
+                ],
+            }
+        );
+        assert_eq!(info.recommended_worker_threads(), 3);
+        assert_eq!(info.recommended_cache_bytes(0.5, 1234), 4096);
+
+        // The affinity mask takes precedence over the NUMA nodes
+        write(
+            root,
+            "proc/self/status",
+            "Name:\ttest\nCpus_allowed_list:\t0-1\n",
+        );
+        let info = EnvironmentInfo::detect_at(root);
+        assert_eq!(info.available_cpus, 2);
+        assert_eq!(info.recommended_worker_threads(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_cache_fraction_out_of_range() {
+        let root = TempDir::new("env_info").unwrap();
+        EnvironmentInfo::detect_at(root.path()).recommended_cache_bytes(1.5, 1234);
+    }
+
+    #[test]
+    fn test_detect() {
+        let info = EnvironmentInfo::detect();
+        assert!(info.available_cpus >= 1);
+        assert!(info.recommended_worker_threads() >= 1);
+    }
+}
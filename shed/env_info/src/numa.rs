@@ -0,0 +1,73 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fs;
+use std::path::Path;
+
+/// A NUMA node of the host
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NumaNode {
+    /// Id of the node
+    pub id: u32,
+    /// CPUs of the node
+    pub cpus: Vec<usize>,
+}
+
+/// The NUMA nodes listed in `/sys/devices/system/node`, ordered by id
+pub(crate) fn detect(root: &Path) -> Vec<NumaNode> {
+    let entries = match fs::read_dir(root.join("sys/devices/system/node")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut nodes: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let id = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()?;
+            let cpulist = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            Some(NumaNode {
+                id,
+                cpus: parse_cpu_list(&cpulist)?,
+            })
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.id);
+    nodes
+}
+
+/// Parse a kernel CPU list like `0-3,8,10-11`
+pub(crate) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("\n"), Some(vec![]));
+        assert_eq!(
+            parse_cpu_list("0-2,8,10-11\n"),
+            Some(vec![0, 1, 2, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list("0-a"), None);
+    }
+}
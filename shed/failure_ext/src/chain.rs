@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::error::Error as StdError;
+use std::fmt::Write;
+
+use crate::slogkv::cause_workaround;
+
+/// Iterator over an error and its causes along with their depth in the chain,
+/// see [causes]
+pub struct Causes<'a> {
+    next: Option<&'a dyn StdError>,
+    depth: usize,
+}
+
+impl<'a> Iterator for Causes<'a> {
+    type Item = (usize, &'a dyn StdError);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let err = self.next?;
+        let depth = self.depth;
+        self.next = cause_workaround(err);
+        self.depth += 1;
+        Some((depth, err))
+    }
+}
+
+/// Iterate over `err` and its causes, `err` itself being at depth 0. Pass an
+/// [anyhow::Error] with `err.as_ref()`.
+pub fn causes(err: &dyn StdError) -> Causes<'_> {
+    Causes {
+        next: Some(err),
+        depth: 0,
+    }
+}
+
+/// The last cause in the chain of `err`, or `err` itself if it has no cause
+pub fn root_cause(err: &dyn StdError) -> &dyn StdError {
+    causes(err).last().map_or(err, |(_, cause)| cause)
+}
+
+/// Render `err` and its causes on separate lines, each cause indented one
+/// level deeper than the error it caused, e.g.
+///
+/// ```text
+/// Failed to load config
+///   caused by: Failed to read /etc/config.json
+///     caused by: No such file or directory (os error 2)
+/// ```
+pub fn format_chain(err: &dyn StdError) -> String {
+    let mut out = String::new();
+    for (depth, err) in causes(err) {
+        if depth == 0 {
+            write!(out, "{}", err)
+        } else {
+            write!(
+                out,
+                "\n{:indent$}caused by: {}",
+                "",
+                err,
+                indent = depth * 2
+            )
+        }
+        .expect("writing to a String doesn't fail");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_chain() {
+        let err = Err::<(), _>(anyhow!("root"))
+            .context("middle")
+            .context("top")
+            .unwrap_err();
+
+        let chain: Vec<_> = causes(err.as_ref())
+            .map(|(depth, err)| (depth, err.to_string()))
+            .collect();
+        assert_eq!(
+            chain,
+            vec![
+                (0, "top".to_owned()),
+                (1, "middle".to_owned()),
+                (2, "root".to_owned()),
+            ]
+        );
+        assert_eq!(root_cause(err.as_ref()).to_string(), "root");
+        assert_eq!(
+            format_chain(err.as_ref()),
+            "top\n  caused by: middle\n    caused by: root"
+        );
+
+        let err = anyhow!("alone");
+        assert_eq!(root_cause(err.as_ref()).to_string(), "alone");
+        assert_eq!(format_chain(err.as_ref()), "alone");
+    }
+}
@@ -19,7 +19,15 @@ use std::fmt::{self, Debug, Display};
 pub use failure;
 
 mod slogkv;
-pub use crate::slogkv::{cause_workaround as cause, SlogKVError, SlogKVErrorKey};
+pub use crate::slogkv::{
+    cause_workaround as cause, error_tags, ErrorTags, ErrorTagsExt, SlogKVError, SlogKVErrorKey,
+};
+
+mod chain;
+pub use crate::chain::{causes, format_chain, root_cause, Causes};
+
+mod retriable;
+pub use crate::retriable::{is_retriable, retriable, retriable_as, Retriable};
 
 mod convert;
 pub use self::convert::convert;
@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use crate::{error_tags, ErrorTagsExt};
+use anyhow::Error;
+use std::error::Error as StdError;
+use std::io;
+
+/// Errors that know whether retrying the operation that failed might
+/// succeed, used to make the same retry decisions for errors coming from
+/// different crates.
+pub trait Retriable: StdError + Send + Sync + 'static {
+    /// Whether retrying might succeed. Defaults to true, so that marking an
+    /// error type retriable is just an empty impl.
+    fn is_retriable(&self) -> bool {
+        true
+    }
+
+    /// Convert into [Error] whose [crate::ErrorTags::retriable] is set, so
+    /// that [is_retriable] can tell without knowing the type of the error.
+    fn into_tagged_error(self) -> Error
+    where
+        Self: Sized,
+    {
+        let retriable = self.is_retriable();
+        Error::new(self).with_retriable(retriable)
+    }
+}
+
+impl Retriable for io::Error {
+    fn is_retriable(&self) -> bool {
+        matches!(
+            self.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+        )
+    }
+}
+
+/// Whether `err` is known to be retriable, or not, from the outermost
+/// [crate::ErrorTags::retriable] in its chain or else from an [io::Error]
+/// in its chain. Returns None if it can't tell.
+pub fn retriable(err: &Error) -> Option<bool> {
+    error_tags(err)
+        .retriable
+        .or_else(|| retriable_as::<io::Error>(err))
+}
+
+/// Whether retrying the operation that failed with `err` might succeed. Errors
+/// of unknown retriability are not retriable.
+pub fn is_retriable(err: &Error) -> bool {
+    retriable(err).unwrap_or(false)
+}
+
+/// Like [retriable] but only considering the first error of type `E` in the
+/// chain of `err`, for error types that weren't tagged with
+/// [Retriable::into_tagged_error].
+pub fn retriable_as<E: Retriable>(err: &Error) -> Option<bool> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<E>())
+        .map(Retriable::is_retriable)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use thiserror::Error;
+
+    #[derive(Debug, Error)]
+    #[error("overloaded")]
+    struct Overloaded;
+
+    impl Retriable for Overloaded {}
+
+    #[derive(Debug, Error)]
+    #[error("invalid: {0}")]
+    struct Invalid(&'static str);
+
+    impl Retriable for Invalid {
+        fn is_retriable(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_is_retriable() {
+        assert_eq!(retriable(&anyhow!("unknown")), None);
+        assert!(!is_retriable(&anyhow!("unknown")));
+
+        let err = Overloaded.into_tagged_error().context("query failed");
+        assert!(is_retriable(&err));
+        assert_eq!(retriable_as::<Overloaded>(&err), Some(true));
+        assert!(err.downcast_ref::<Overloaded>().is_some());
+        let err = Invalid("query").into_tagged_error().context("query failed");
+        assert_eq!(retriable(&err), Some(false));
+
+        // Tags take precedence over the types in the chain
+        let err = Error::from(io::Error::from(io::ErrorKind::TimedOut));
+        assert!(is_retriable(&err));
+        assert!(!is_retriable(&err.with_retriable(false)));
+
+        let err = Error::from(Invalid("query")).context("query failed");
+        assert_eq!(retriable(&err), None);
+        assert_eq!(retriable_as::<Invalid>(&err), Some(false));
+        assert_eq!(retriable_as::<Overloaded>(&err), None);
+    }
+}
@@ -10,6 +10,7 @@
 use super::{Compat, Error};
 use futures::future::SharedError;
 use std::error::Error as StdError;
+use std::fmt::{self, Debug, Display};
 use std::ops::Deref;
 
 /// Wrapper around [Error] that implements [slog::KV] trait, so it might be used in [slog] logging
@@ -26,17 +27,33 @@ impl slog::KV for SlogKVError {
         serializer.emit_str(Error.into_str(), &format!("{}", err))?;
         serializer.emit_str(ErrorDebug.into_str(), &format!("{:#?}", err))?;
 
+        let tags = error_tags(err);
+        if let Some(kind) = tags.kind {
+            serializer.emit_str(ErrorKind.into_str(), kind)?;
+        }
+        if let Some(retriable) = tags.retriable {
+            serializer.emit_bool(Retriable.into_str(), retriable)?;
+        }
+
         #[cfg(fbcode_build)]
         {
             let backtrace = err.backtrace();
-            if let std::backtrace::BacktraceStatus::Captured = backtrace.status() {
+            let captured = backtrace.status() == std::backtrace::BacktraceStatus::Captured;
+            serializer.emit_bool(HasBacktrace.into_str(), captured)?;
+            if captured {
                 serializer.emit_str(Backtrace.into_str(), &backtrace.to_string())?;
             }
         }
 
         let mut err = err.deref() as &dyn StdError;
+        let mut message = err.to_string();
         while let Some(cause) = cause_workaround(err) {
-            serializer.emit_str(Cause.into_str(), &format!("{}", cause))?;
+            // Skip the errors wrapped by the tags, which display the same
+            let cause_message = cause.to_string();
+            if cause_message != message {
+                serializer.emit_str(Cause.into_str(), &cause_message)?;
+            }
+            message = cause_message;
             err = cause;
         }
         serializer.emit_str(RootCause.into_str(), &format!("{}", err))?;
@@ -58,6 +75,12 @@ pub enum SlogKVErrorKey {
     Cause,
     /// The error that is being logged, but in debug format
     ErrorDebug,
+    /// Kind of the error, see [ErrorTags::kind]
+    ErrorKind,
+    /// Whether the failed operation can be retried, see [ErrorTags::retriable]
+    Retriable,
+    /// Whether a backtrace was captured when the error occured
+    HasBacktrace,
 }
 use crate::SlogKVErrorKey::*;
 
@@ -70,6 +93,9 @@ impl SlogKVErrorKey {
             Backtrace => "backtrace",
             Cause => "cause",
             ErrorDebug => "error_debug",
+            ErrorKind => "error_kind",
+            Retriable => "retriable",
+            HasBacktrace => "has_backtrace",
         }
     }
 }
@@ -84,11 +110,83 @@ impl ::std::str::FromStr for SlogKVErrorKey {
             "backtrace" => Ok(Backtrace),
             "cause" => Ok(Cause),
             "error_debug" => Ok(ErrorDebug),
+            "error_kind" => Ok(ErrorKind),
+            "retriable" => Ok(Retriable),
+            "has_backtrace" => Ok(HasBacktrace),
             _ => Err(()),
         }
     }
 }
 
+/// Typed context attached to an error with [ErrorTagsExt], logged by
+/// [SlogKVError] as separate fields
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ErrorTags {
+    /// Category of the error, e.g. "timeout" or "not_found"
+    pub kind: Option<&'static str>,
+    /// Whether retrying the operation that failed might succeed
+    pub retriable: Option<bool>,
+}
+
+/// Context carrying [ErrorTags], attached like any other context so that the
+/// error can still be downcast to the types of its chain. It displays as the
+/// error it was attached to, so that tagging doesn't change the message of
+/// the error, and [SlogKVError] doesn't log it as another cause.
+#[derive(Debug)]
+struct Tagged {
+    tags: ErrorTags,
+    message: String,
+}
+
+impl Display for Tagged {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+/// The tags attached to `err`. If a tag was set more than once the outermost
+/// value is used.
+pub fn error_tags(err: &Error) -> ErrorTags {
+    err.downcast_ref::<Tagged>()
+        .map_or_else(ErrorTags::default, |tagged| tagged.tags.clone())
+}
+
+/// Extension trait attaching [ErrorTags] to errors and results
+pub trait ErrorTagsExt: Sized {
+    /// Modify the tags of the error
+    fn tag(self, f: impl FnOnce(&mut ErrorTags)) -> Self;
+
+    /// Set [ErrorTags::kind] of the error
+    fn with_kind(self, kind: &'static str) -> Self {
+        self.tag(|tags| tags.kind = Some(kind))
+    }
+
+    /// Set [ErrorTags::retriable] of the error
+    fn with_retriable(self, retriable: bool) -> Self {
+        self.tag(|tags| tags.retriable = Some(retriable))
+    }
+}
+
+impl ErrorTagsExt for Error {
+    fn tag(mut self, f: impl FnOnce(&mut ErrorTags)) -> Self {
+        // The tags found through the contexts of the error are updated in place
+        if let Some(tagged) = self.downcast_mut::<Tagged>() {
+            f(&mut tagged.tags);
+            return self;
+        }
+        let mut tags = ErrorTags::default();
+        f(&mut tags);
+        let message = self.to_string();
+        self.context(Tagged { tags, message })
+    }
+}
+
+impl<T> ErrorTagsExt for Result<T, Error> {
+    fn tag(self, f: impl FnOnce(&mut ErrorTags)) -> Self {
+        self.map_err(|err| err.tag(f))
+    }
+}
+
 /// Like Fail::cause, but handles SharedError whose Fail implementation
 /// does not return the right underlying error.
 pub fn cause_workaround(fail: &dyn StdError) -> Option<&dyn StdError> {
@@ -98,3 +196,54 @@ pub fn cause_workaround(fail: &dyn StdError) -> Option<&dyn StdError> {
     }
     Some(cause)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_tags() {
+        let err = anyhow!("root").context("middle");
+        assert_eq!(error_tags(&err), ErrorTags::default());
+
+        let err = err.with_kind("timeout").with_retriable(false);
+        let err = Err::<(), _>(err.context("top"))
+            .with_retriable(true)
+            .unwrap_err();
+        assert_eq!(
+            error_tags(&err),
+            ErrorTags {
+                kind: Some("timeout"),
+                retriable: Some(true),
+            }
+        );
+
+        // Tagging doesn't change the message of the error
+        assert_eq!(err.to_string(), "top");
+        assert_eq!(err.root_cause().to_string(), "root");
+    }
+
+    #[test]
+    fn test_downcast_tagged() {
+        #[derive(Debug, thiserror::Error)]
+        #[error("typed")]
+        struct Typed(u32);
+
+        let err = Error::from(Typed(1)).with_kind("typed");
+        assert_eq!(err.to_string(), "typed");
+        assert_eq!(err.downcast_ref::<Typed>().unwrap().0, 1);
+        assert!(err.chain().any(|cause| cause.is::<Typed>()));
+
+        let err = err.context("top").with_retriable(true);
+        assert_eq!(err.downcast_ref::<Typed>().unwrap().0, 1);
+        assert_eq!(
+            error_tags(&err),
+            ErrorTags {
+                kind: Some("typed"),
+                retriable: Some(true),
+            }
+        );
+        assert_eq!(err.downcast::<Typed>().unwrap().0, 1);
+    }
+}
@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, Future, FutureExt, Shared};
+
+/// What a [LazyTryShared] does when its initialization fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Keep the error, and return it to all the following callers.
+    Cache,
+    /// Forget the error, so that the next caller runs the initialization
+    /// again. The callers that were waiting for the failed attempt still get
+    /// its error.
+    Retry,
+}
+
+type Init<T, E> = Box<dyn FnMut() -> BoxFuture<'static, Result<T, E>> + Send>;
+type Attempt<T, E> = Shared<BoxFuture<'static, Result<T, E>>>;
+
+struct State<T, E> {
+    init: Init<T, E>,
+    /// The running or completed attempt, with its number.
+    current: Option<(u64, Attempt<T, E>)>,
+    attempts: u64,
+}
+
+/// Lazily initialized value shared by the clones of this handle, for an
+/// initialization that may fail. The first call to [LazyTryShared::get] runs
+/// the future returned by the `init` closure, the concurrent calls wait for
+/// it, and the following ones get its value. Unlike [futures::future::Shared]
+/// or [crate::FbTryFutureExt::try_shared], a failed initialization may be
+/// retried, according to [OnError].
+pub struct LazyTryShared<T, E> {
+    on_error: OnError,
+    state: Arc<Mutex<State<T, E>>>,
+}
+
+impl<T, E> LazyTryShared<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    /// Create a value initialized by the future returned by `init`, which
+    /// isn't called until the value is first requested.
+    pub fn new<F, Fut>(on_error: OnError, mut init: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        Self {
+            on_error,
+            state: Arc::new(Mutex::new(State {
+                init: Box::new(move || init().boxed()),
+                current: None,
+                attempts: 0,
+            })),
+        }
+    }
+
+    /// The value, initializing it if it wasn't yet, or if the previous
+    /// initialization failed and is to be retried.
+    pub async fn get(&self) -> Result<T, E> {
+        let (attempt, shared) = {
+            let mut state = self.state.lock().expect("lock poisoned");
+            match state.current.clone() {
+                Some(current) => current,
+                None => {
+                    state.attempts += 1;
+                    let current = (state.attempts, (state.init)().shared());
+                    state.current = Some(current.clone());
+                    current
+                }
+            }
+        };
+
+        let result = shared.await;
+        if result.is_err() && self.on_error == OnError::Retry {
+            let mut state = self.state.lock().expect("lock poisoned");
+            // Another caller may have reset the failed attempt already, and
+            // started the next one
+            if matches!(&state.current, Some((current, _)) if *current == attempt) {
+                state.current = None;
+            }
+        }
+        result
+    }
+
+    /// Whether the value was successfully initialized, without waiting for a
+    /// running initialization.
+    pub fn is_initialized(&self) -> bool {
+        let state = self.state.lock().expect("lock poisoned");
+        matches!(
+            state.current.as_ref().and_then(|(_, shared)| shared.peek()),
+            Some(Ok(_))
+        )
+    }
+}
+
+impl<T, E> Clone for LazyTryShared<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            on_error: self.on_error,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Value whose initializations fail until the `fail_until`th one, and
+    /// the counter of initializations
+    fn counted(
+        on_error: OnError,
+        fail_until: usize,
+    ) -> (LazyTryShared<usize, String>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let shared = LazyTryShared::new(on_error, {
+            let calls = calls.clone();
+            move || {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    tokio::time::delay_for(Duration::from_millis(1)).await;
+                    if call < fail_until {
+                        Err(format!("attempt {} failed", call))
+                    } else {
+                        Ok(call)
+                    }
+                }
+            }
+        });
+        (shared, calls)
+    }
+
+    #[tokio::test]
+    async fn test_memoizes_success() {
+        let (shared, calls) = counted(OnError::Retry, 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert!(!shared.is_initialized());
+
+        // The concurrent callers wait for the same initialization
+        let other = shared.clone();
+        let (a, b) = futures::join!(shared.get(), other.get());
+        assert_eq!((a, b), (Ok(1), Ok(1)));
+        assert_eq!(shared.get().await, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(shared.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_cache_error() {
+        let (shared, calls) = counted(OnError::Cache, 2);
+        assert_eq!(shared.get().await, Err("attempt 1 failed".to_owned()));
+        assert_eq!(shared.get().await, Err("attempt 1 failed".to_owned()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(!shared.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_retry_error() {
+        let (shared, calls) = counted(OnError::Retry, 3);
+
+        // Both callers get the error of the attempt they waited for, which is
+        // only reset once
+        let (a, b) = futures::join!(shared.get(), shared.get());
+        assert_eq!(a, Err("attempt 1 failed".to_owned()));
+        assert_eq!(b, Err("attempt 1 failed".to_owned()));
+        assert_eq!(shared.get().await, Err("attempt 2 failed".to_owned()));
+        assert_eq!(shared.get().await, Ok(3));
+        assert_eq!(shared.get().await, Ok(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
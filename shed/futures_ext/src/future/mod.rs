@@ -11,7 +11,9 @@
 
 mod abort_handle_ref;
 mod conservative_receiver;
+mod lazy_try_shared;
 mod on_cancel;
+mod on_cancel_async;
 mod on_cancel_with_data;
 mod try_shared;
 
@@ -24,7 +26,9 @@ pub use shared_error::anyhow::SharedError;
 
 pub use self::abort_handle_ref::{spawn_controlled, ControlledHandle};
 pub use self::conservative_receiver::ConservativeReceiver;
+pub use self::lazy_try_shared::{LazyTryShared, OnError};
 pub use self::on_cancel::OnCancel;
+pub use self::on_cancel_async::OnCancelAsync;
 pub use self::on_cancel_with_data::{CancelData, OnCancelWithData};
 pub use self::try_shared::TryShared;
 
@@ -48,6 +52,22 @@ pub trait FbFutureExt: Future {
         OnCancel::new(self, on_cancel)
     }
 
+    /// Spawn the cleanup future returned by the `on_cancel` callback if this
+    /// future is cancelled (dropped without completion), giving it up to
+    /// `grace_period` to complete. Must be dropped within a Tokio runtime.
+    fn on_cancel_async<F, C>(
+        self,
+        grace_period: Duration,
+        on_cancel: F,
+    ) -> OnCancelAsync<Self, F, C>
+    where
+        Self: Sized,
+        F: FnOnce() -> C,
+        C: Future<Output = ()> + Send + 'static,
+    {
+        OnCancelAsync::new(self, grace_period, on_cancel)
+    }
+
     /// Call the `on_cancel` callback if this future is cancelled (dropped
     /// without completion).  Pass additional data extracted from the
     /// inner future via the CancelData trait.
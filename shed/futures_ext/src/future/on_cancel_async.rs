@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::future::Future;
+use futures::ready;
+use futures::task::{Context, Poll};
+use pin_project::{pin_project, pinned_drop};
+
+/// Future combinator that spawns the cleanup future returned by the
+/// `on_cancel` closure if the inner future is cancelled (dropped before
+/// completion). The cleanup is given `grace_period` to complete, after which
+/// it is dropped in turn.
+///
+/// The cleanup is spawned on the current Tokio runtime, so the combinator
+/// must be dropped within one.
+#[pin_project(PinnedDrop)]
+pub struct OnCancelAsync<Fut, OnCancelFn, CleanupFut>
+where
+    Fut: Future,
+    OnCancelFn: FnOnce() -> CleanupFut,
+    CleanupFut: Future<Output = ()> + Send + 'static,
+{
+    #[pin]
+    inner: Fut,
+
+    on_cancel: Option<OnCancelFn>,
+
+    grace_period: Duration,
+}
+
+impl<Fut, OnCancelFn, CleanupFut> OnCancelAsync<Fut, OnCancelFn, CleanupFut>
+where
+    Fut: Future,
+    OnCancelFn: FnOnce() -> CleanupFut,
+    CleanupFut: Future<Output = ()> + Send + 'static,
+{
+    /// Construct an `OnCancelAsync` combinator that will spawn the future
+    /// returned by `on_cancel` if `inner` is cancelled, for up to
+    /// `grace_period`.
+    pub fn new(inner: Fut, grace_period: Duration, on_cancel: OnCancelFn) -> Self {
+        Self {
+            inner,
+            on_cancel: Some(on_cancel),
+            grace_period,
+        }
+    }
+}
+
+impl<Fut, OnCancelFn, CleanupFut> Future for OnCancelAsync<Fut, OnCancelFn, CleanupFut>
+where
+    Fut: Future,
+    OnCancelFn: FnOnce() -> CleanupFut,
+    CleanupFut: Future<Output = ()> + Send + 'static,
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let v = ready!(this.inner.poll(cx));
+        *this.on_cancel = None;
+        Poll::Ready(v)
+    }
+}
+
+#[pinned_drop]
+impl<Fut, OnCancelFn, CleanupFut> PinnedDrop for OnCancelAsync<Fut, OnCancelFn, CleanupFut>
+where
+    Fut: Future,
+    OnCancelFn: FnOnce() -> CleanupFut,
+    CleanupFut: Future<Output = ()> + Send + 'static,
+{
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if let Some(on_cancel) = this.on_cancel.take() {
+            let cleanup = on_cancel();
+            let grace_period = *this.grace_period;
+            // Detached, the cleanup outlives this future
+            drop(tokio_shim::task::spawn(async move {
+                let _ = tokio_shim::time::timeout(grace_period, cleanup).await;
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use futures::channel::oneshot;
+    use futures::future;
+
+    #[tokio::test]
+    async fn runs_when_cancelled() {
+        let (tx, rx) = oneshot::channel();
+        let fut = OnCancelAsync::new(async {}, Duration::from_secs(10), || async move {
+            let _ = tx.send(());
+        });
+        drop(fut);
+        assert!(rx.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn doesnt_run_when_complete() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let fut = OnCancelAsync::new(async {}, Duration::from_secs(10), {
+            let cancelled = cancelled.clone();
+            || async move { cancelled.store(true, Ordering::Relaxed) }
+        });
+        fut.await;
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert!(!cancelled.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn drops_cleanup_after_grace_period() {
+        // The sender is dropped along with the cleanup, which never completes
+        let (tx, rx) = oneshot::channel::<()>();
+        let fut = OnCancelAsync::new(async {}, Duration::from_millis(10), || async move {
+            let _tx = tx;
+            future::pending::<()>().await
+        });
+        drop(fut);
+        assert!(rx.await.is_err());
+    }
+}
@@ -12,6 +12,7 @@
 //! Crate extending functionality of [`futures`] crate
 
 pub mod future;
+pub mod retry;
 pub mod stream;
 
 pub use crate::future::{FbFutureExt, FbTryFutureExt};
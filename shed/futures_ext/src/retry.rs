@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module retrying async operations: [retry] runs an operation until it
+//! succeeds, fails with an error that isn't worth retrying, or the attempts
+//! or the time allowed by a [RetryPolicy] run out, waiting between the
+//! attempts according to a [Backoff].
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// Delays between the attempts of a [RetryPolicy]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay before each retry
+    Fixed(Duration),
+    /// A delay doubled for each retry, randomly shortened by up to half to
+    /// spread the retries of concurrent callers
+    Exponential {
+        /// Delay before the first retry
+        base: Duration,
+        /// Longest delay
+        max: Duration,
+    },
+    /// A random delay between `base` and three times the previous one, which
+    /// spreads the retries of concurrent callers more than
+    /// [Backoff::Exponential]
+    DecorrelatedJitter {
+        /// Shortest delay, and the one the first retry is based on
+        base: Duration,
+        /// Longest delay
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Delay to wait after the failed attempt number `attempt`, counting
+    /// from 1, when the delay before that attempt was `previous`, zero for
+    /// the first one
+    pub fn delay(&self, attempt: u32, previous: Duration) -> Duration {
+        let mut rng = rand::thread_rng();
+        match *self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { base, max } => {
+                let delay = base
+                    .checked_mul(1 << attempt.saturating_sub(1).min(31))
+                    .map_or(max, |delay| delay.min(max));
+                rng.gen_range(delay / 2..=delay)
+            }
+            Backoff::DecorrelatedJitter { base, max } => {
+                let upper = previous.saturating_mul(3).max(base);
+                rng.gen_range(base..=upper).min(max)
+            }
+        }
+    }
+}
+
+/// How [retry] retries an operation
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Delays between the attempts. Defaults to an exponential backoff from
+    /// 50 milliseconds up to 2 seconds.
+    pub backoff: Backoff,
+    /// Most attempts, including the first one. Defaults to 3.
+    pub max_attempts: u32,
+    /// Time since the start of the first attempt after which no attempt
+    /// starts, if any. Defaults to none.
+    pub max_elapsed: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::Exponential {
+                base: Duration::from_millis(50),
+                max: Duration::from_secs(2),
+            },
+            max_attempts: 3,
+            max_elapsed: None,
+        }
+    }
+}
+
+/// Run `op` until it succeeds, fails with an error for which `retry_on` is
+/// false, or the attempts or the time allowed by `policy` run out, and
+/// return the result of the last attempt. `op` is given the number of the
+/// attempt, counting from 1. Must be run within a Tokio runtime.
+///
+/// # Examples
+///
+/// ```
+/// use futures_ext::retry::{retry, RetryPolicy};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let result: Result<u32, String> = retry(
+///     &RetryPolicy::default(),
+///     |err: &String| err == "busy",
+///     |attempt| async move {
+///         if attempt < 2 {
+///             Err("busy".to_owned())
+///         } else {
+///             Ok(attempt)
+///         }
+///     },
+/// )
+/// .await;
+/// assert_eq!(result, Ok(2));
+/// # }
+/// ```
+pub async fn retry<T, E, Op, Fut, RetryOn>(
+    policy: &RetryPolicy,
+    mut retry_on: RetryOn,
+    mut op: Op,
+) -> Result<T, E>
+where
+    Op: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    RetryOn: FnMut(&E) -> bool,
+{
+    let start = Instant::now();
+    let mut delay = Duration::from_secs(0);
+    let mut attempt = 1;
+    loop {
+        let err = match op(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if attempt >= policy.max_attempts || !retry_on(&err) {
+            return Err(err);
+        }
+        delay = policy.backoff.delay(attempt, delay);
+        if let Some(max_elapsed) = policy.max_elapsed {
+            if start.elapsed() + delay > max_elapsed {
+                return Err(err);
+            }
+        }
+        tokio_shim::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed(delay: Duration, max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            backoff: Backoff::Fixed(delay),
+            max_attempts,
+            max_elapsed: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = fixed(Duration::from_millis(1), 3);
+        let result: Result<_, &str> = retry(
+            &policy,
+            |_| true,
+            |attempt| async move {
+                if attempt < 3 {
+                    Err("busy")
+                } else {
+                    Ok(attempt)
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Ok(3));
+
+        // Gives up after max_attempts
+        let mut attempts = 0;
+        let result: Result<(), _> = retry(
+            &policy,
+            |_| true,
+            |attempt| {
+                attempts = attempt;
+                async { Err("busy") }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("busy"));
+        assert_eq!(attempts, 3);
+
+        // Doesn't retry the other errors
+        let result: Result<(), _> = retry(
+            &policy,
+            |err| *err == "busy",
+            |attempt| {
+                attempts = attempt;
+                async { Err("invalid") }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("invalid"));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_elapsed() {
+        // The attempts start at 0, 20 and 40ms, and the next one would be past 50ms
+        let policy = RetryPolicy {
+            max_elapsed: Some(Duration::from_millis(50)),
+            ..fixed(Duration::from_millis(20), 10)
+        };
+        let mut attempts = 0;
+        let result: Result<(), _> = retry(
+            &policy,
+            |_| true,
+            |attempt| {
+                attempts = attempt;
+                async { Err("busy") }
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let fixed = Backoff::Fixed(Duration::from_millis(10));
+        assert_eq!(
+            fixed.delay(5, Duration::from_secs(1)),
+            Duration::from_millis(10)
+        );
+
+        let base = Duration::from_millis(50);
+        let max = Duration::from_secs(2);
+        let exponential = Backoff::Exponential { base, max };
+        for attempt in 1..40 {
+            let delay = exponential.delay(attempt, Duration::from_secs(0));
+            assert!(delay <= max);
+            assert!(delay >= base / 2);
+        }
+        assert!(exponential.delay(1, Duration::from_secs(0)) <= base);
+        assert!(exponential.delay(3, Duration::from_secs(0)) >= base * 2);
+
+        let jitter = Backoff::DecorrelatedJitter { base, max };
+        let mut delay = Duration::from_secs(0);
+        for attempt in 1..40 {
+            let next = jitter.delay(attempt, delay);
+            assert!(next >= base);
+            assert!(next <= max);
+            assert!(next <= (delay * 3).max(base));
+            delay = next;
+        }
+    }
+}
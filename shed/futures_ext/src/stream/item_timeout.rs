@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use futures::{
+    future::Future,
+    ready,
+    stream::Stream,
+    task::{Context, Poll},
+};
+use pin_project::pin_project;
+use std::pin::Pin;
+use std::time::Duration;
+use thiserror::Error;
+use tokio_shim::time::Sleep;
+
+/// Error returned by an [ItemTimeout] stream when no item arrived within its duration.
+#[derive(Debug, Error)]
+#[error("Stream item timeout with duration {:?} was exceeded", .0)]
+pub struct ItemTimeoutError(pub Duration);
+
+/// Deadline for the next item of a stream, started the first time the stream is polled for it.
+#[pin_project]
+struct Watchdog {
+    duration: Duration,
+    #[pin]
+    deadline: Option<Sleep>,
+}
+
+impl Watchdog {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            deadline: None,
+        }
+    }
+
+    /// Ready once the duration elapsed while waiting for the next item, after which the
+    /// deadline is reset.
+    fn poll_elapsed(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut this = self.project();
+        if this.deadline.is_none() {
+            this.deadline
+                .set(Some(tokio_shim::time::sleep(*this.duration)));
+        }
+        // NOTE: This unwrap() is safe as we just set the value.
+        ready!(this.deadline.as_mut().as_pin_mut().unwrap().poll(cx));
+        this.deadline.set(None);
+        Poll::Ready(())
+    }
+
+    /// Restart the deadline the next time the stream is polled, as an item arrived.
+    fn reset(self: Pin<&mut Self>) {
+        self.project().deadline.set(None);
+    }
+}
+
+/// A stream that errors whenever the next item of the inner stream does not arrive within a
+/// given duration, but unlike [crate::stream::StreamWithTimeout] keeps returning the items of
+/// the inner stream afterwards. The clock for each item starts the first time the stream is
+/// polled for it.
+#[pin_project]
+pub struct ItemTimeout<S> {
+    #[pin]
+    inner: S,
+    done: bool,
+    #[pin]
+    watchdog: Watchdog,
+}
+
+impl<S> ItemTimeout<S> {
+    /// Create a new [ItemTimeout].
+    pub fn new(inner: S, duration: Duration) -> Self {
+        Self {
+            inner,
+            done: false,
+            watchdog: Watchdog::new(duration),
+        }
+    }
+}
+
+impl<S: Stream> Stream for ItemTimeout<S> {
+    type Item = Result<<S as Stream>::Item, ItemTimeoutError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(res) = this.inner.poll_next(cx) {
+            *this.done = res.is_none();
+            this.watchdog.reset();
+            return Poll::Ready(res.map(Ok));
+        }
+
+        ready!(this.watchdog.as_mut().poll_elapsed(cx));
+        Poll::Ready(Some(Err(ItemTimeoutError(this.watchdog.duration))))
+    }
+}
+
+/// Like [ItemTimeout], but returns the item produced by a fallback closure instead of an
+/// error when the next item does not arrive in time.
+#[pin_project]
+pub struct ItemTimeoutOrElse<S, F> {
+    #[pin]
+    inner: S,
+    done: bool,
+    #[pin]
+    watchdog: Watchdog,
+    fallback: F,
+}
+
+impl<S, F> ItemTimeoutOrElse<S, F> {
+    /// Create a new [ItemTimeoutOrElse].
+    pub fn new(inner: S, duration: Duration, fallback: F) -> Self {
+        Self {
+            inner,
+            done: false,
+            watchdog: Watchdog::new(duration),
+            fallback,
+        }
+    }
+}
+
+impl<S, F> Stream for ItemTimeoutOrElse<S, F>
+where
+    S: Stream,
+    F: FnMut() -> S::Item,
+{
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(res) = this.inner.poll_next(cx) {
+            *this.done = res.is_none();
+            this.watchdog.reset();
+            return Poll::Ready(res);
+        }
+
+        ready!(this.watchdog.as_mut().poll_elapsed(cx));
+        Poll::Ready(Some((this.fallback)()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_item_timeout() {
+        tokio::time::pause();
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut s = ItemTimeout::new(rx, Duration::from_secs(1)).boxed();
+
+        tx.unbounded_send(1).unwrap();
+        assert_eq!(s.next().await.unwrap().unwrap(), 1);
+
+        // Each gap longer than the duration is an error, and the stream goes on
+        for _ in 0..2 {
+            let next = s.next();
+            futures::pin_mut!(next);
+            assert!(futures::poll!(next.as_mut()).is_pending());
+            tokio::time::advance(Duration::from_secs(2)).await;
+            assert!(next.await.unwrap().is_err());
+        }
+
+        tx.unbounded_send(2).unwrap();
+        assert_eq!(s.next().await.unwrap().unwrap(), 2);
+        drop(tx);
+        assert!(s.next().await.is_none());
+        assert!(s.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_item_in_time() {
+        tokio::time::pause();
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut s = ItemTimeout::new(rx, Duration::from_secs(1)).boxed();
+
+        // The time between the items is not limited, only the time waiting for them
+        tokio::time::advance(Duration::from_secs(2)).await;
+        tx.unbounded_send(1).unwrap();
+        assert_eq!(s.next().await.unwrap().unwrap(), 1);
+
+        let next = s.next();
+        futures::pin_mut!(next);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        tokio::time::advance(Duration::from_millis(500)).await;
+        tx.unbounded_send(2).unwrap();
+        assert_eq!(next.await.unwrap().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_item_timeout_or_else() {
+        tokio::time::pause();
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut s = ItemTimeoutOrElse::new(rx, Duration::from_secs(1), || 0).boxed();
+
+        tx.unbounded_send(1).unwrap();
+        assert_eq!(s.next().await, Some(1));
+
+        let next = s.next();
+        futures::pin_mut!(next);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(next.await, Some(0));
+
+        drop(tx);
+        assert_eq!(s.next().await, None);
+    }
+}
@@ -9,6 +9,7 @@
 
 //! Module extending functionality of [`futures::stream`] module
 
+mod item_timeout;
 mod return_remainder;
 mod stream_with_timeout;
 mod weight_limited_buffered_stream;
@@ -19,12 +20,13 @@ use std::time::Duration;
 
 use crate::future::ConservativeReceiver;
 
+pub use self::item_timeout::{ItemTimeout, ItemTimeoutError, ItemTimeoutOrElse};
 pub use self::return_remainder::ReturnRemainder;
 pub use self::stream_with_timeout::{StreamTimeoutError, StreamWithTimeout};
 pub use self::weight_limited_buffered_stream::{
     BufferedParams, WeightLimitedBufferedStream, WeightLimitedBufferedTryStream,
 };
-pub use self::yield_periodically::YieldPeriodically;
+pub use self::yield_periodically::{thread_yield_count, YieldPeriodically};
 
 /// A trait implemented by default for all Streams which extends the standard
 /// functionality.
@@ -41,6 +43,11 @@ pub trait FbStreamExt: Stream {
 
     /// Like [futures::stream::StreamExt::buffered] call,
     /// but can also limit number of futures in a buffer by "weight".
+    /// The items are the futures along with their weight, e.g. their
+    /// estimated memory use, and the sum of the weights of the futures in
+    /// the buffer stays within `params.weight_limit`, except for a future
+    /// heavier than it, which runs on its own. See
+    /// [WeightLimitedBufferedStream].
     fn buffered_weight_limited<'a, I, Fut>(
         self,
         params: BufferedParams,
@@ -61,7 +68,27 @@ pub trait FbStreamExt: Stream {
         StreamWithTimeout::new(self, timeout)
     }
 
+    /// Construct a new [self::item_timeout::ItemTimeout], returning an error each time the
+    /// next item doesn't arrive within `timeout`, without ending the stream.
+    fn timeout_item(self, timeout: Duration) -> ItemTimeout<Self>
+    where
+        Self: Sized,
+    {
+        ItemTimeout::new(self, timeout)
+    }
+
+    /// Construct a new [self::item_timeout::ItemTimeoutOrElse], returning the item produced by
+    /// `fallback` each time the next item doesn't arrive within `timeout`.
+    fn timeout_item_or_else<F>(self, timeout: Duration, fallback: F) -> ItemTimeoutOrElse<Self, F>
+    where
+        Self: Sized,
+        F: FnMut() -> Self::Item,
+    {
+        ItemTimeoutOrElse::new(self, timeout, fallback)
+    }
+
     /// Construct a new [self::yield_periodically::YieldPeriodically], with a sensible default.
+    /// Its yields are counted by [self::yield_periodically::thread_yield_count].
     fn yield_periodically(self) -> YieldPeriodically<Self>
     where
         Self: Sized,
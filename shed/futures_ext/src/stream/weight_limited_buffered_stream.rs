@@ -26,11 +26,19 @@ pub struct BufferedParams {
     pub buffer_size: usize,
 }
 
+/// Future of the buffer returning its weight with its output, and its weight
+type Weighted<'a, I> = (BoxFuture<'a, (I, u64)>, u64);
+
 /// Like [stream::Buffered], but can also limit number of futures in a buffer by "weight".
+/// A future only starts if its weight fits in what is left of the weight
+/// limit, unless the buffer is empty, so that a future heavier than the
+/// limit still runs, on its own.
 #[pin_project]
 pub struct WeightLimitedBufferedStream<'a, S, I> {
     #[pin]
     queue: stream::FuturesOrdered<BoxFuture<'a, (I, u64)>>,
+    /// Future taken from the stream that didn't fit in the buffer yet
+    next: Option<Weighted<'a, I>>,
     current_weight: u64,
     weight_limit: u64,
     max_buffer_size: usize,
@@ -46,6 +54,7 @@ where
     pub fn new(params: BufferedParams, stream: S) -> Self {
         Self {
             queue: stream::FuturesOrdered::new(),
+            next: None,
             current_weight: 0,
             weight_limit: params.weight_limit,
             max_buffer_size: params.buffer_size,
@@ -66,15 +75,29 @@ where
 
         // First up, try to spawn off as many futures as possible by filling up
         // our slab of futures.
-        while this.queue.len() < *this.max_buffer_size && this.current_weight < this.weight_limit {
-            let future = match this.stream.as_mut().poll_next(cx) {
-                Poll::Ready(Some((f, weight))) => {
-                    *this.current_weight += weight;
-                    f.map(move |val| (val, weight)).boxed()
+        while this.queue.len() < *this.max_buffer_size {
+            if this.next.is_none() {
+                if this.current_weight >= this.weight_limit {
+                    break;
                 }
-                Poll::Ready(None) | Poll::Pending => break,
-            };
-
+                *this.next = match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some((f, weight))) => {
+                        Some((f.map(move |val| (val, weight)).boxed(), weight))
+                    }
+                    Poll::Ready(None) | Poll::Pending => break,
+                };
+            }
+            if !fits(
+                this.next,
+                *this.current_weight,
+                *this.weight_limit,
+                this.queue.is_empty(),
+            ) {
+                break;
+            }
+
+            let (future, weight) = this.next.take().expect("checked above");
+            *this.current_weight += weight;
             this.queue.push(future);
         }
 
@@ -87,7 +110,7 @@ where
         // If we've gotten this far, then there are no events for us to process
         // and nothing was ready, so figure out if we're not done yet or if
         // we've reached the end.
-        if this.stream.is_done() {
+        if this.stream.is_done() && this.next.is_none() {
             Poll::Ready(None)
         } else {
             Poll::Pending
@@ -95,12 +118,24 @@ where
     }
 }
 
+/// Whether the future taken from the stream can start: if its weight fits in
+/// what is left of the limit, or if nothing else runs
+fn fits<F>(next: &Option<(F, u64)>, current_weight: u64, weight_limit: u64, empty: bool) -> bool {
+    match next {
+        Some((_, weight)) => empty || current_weight.saturating_add(*weight) <= weight_limit,
+        None => false,
+    }
+}
+
 /// Like [stream::Buffered], but is for TryStream and can also
-/// limit number of futures in a buffer by "weight"
+/// limit number of futures in a buffer by "weight", as
+/// [WeightLimitedBufferedStream] does
 #[pin_project]
 pub struct WeightLimitedBufferedTryStream<'a, S, I, E> {
     #[pin]
     queue: stream::FuturesOrdered<BoxFuture<'a, (Result<I, E>, u64)>>,
+    /// Future taken from the stream that didn't fit in the buffer yet
+    next: Option<Weighted<'a, Result<I, E>>>,
     current_weight: u64,
     weight_limit: u64,
     max_buffer_size: usize,
@@ -116,6 +151,7 @@ where
     pub fn new(params: BufferedParams, stream: S) -> Self {
         Self {
             queue: stream::FuturesOrdered::new(),
+            next: None,
             current_weight: 0,
             weight_limit: params.weight_limit,
             max_buffer_size: params.buffer_size,
@@ -138,27 +174,41 @@ where
 
         // First up, try to spawn off as many futures as possible by filling up
         // our slab of futures.
-        while this.queue.len() < *this.max_buffer_size && this.current_weight < this.weight_limit {
-            let future = match this.stream.as_mut().poll_next(cx) {
-                Poll::Ready(Some(Ok((f, weight)))) => {
-                    *this.current_weight += weight;
-                    f.map(move |val| (val, weight)).boxed()
+        while this.queue.len() < *this.max_buffer_size {
+            if this.next.is_none() {
+                if this.current_weight >= this.weight_limit {
+                    break;
                 }
-                Poll::Ready(Some(Err(e))) => {
-                    // We failed to even get the weight of the future
-                    // Let's record the failure in the queue instead
-                    // of returning error from the stream now. Otherwise
-                    // the error returned now may actually correspond
-                    // to a future for which we succeeded querying weight.
-                    // Note: this behavior is different from what we had
-                    //       in `WeightLimitedBufferedStream` for Stream 0.1
-                    //       but IMO it's more correct, as the stream can
-                    //       keep returning successes after an error
-                    future::ready((Err(e), 0u64)).boxed()
-                }
-                Poll::Ready(None) | Poll::Pending => break,
-            };
-
+                *this.next = match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok((f, weight)))) => {
+                        Some((f.map(move |val| (val, weight)).boxed(), weight))
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        // We failed to even get the weight of the future
+                        // Let's record the failure in the queue instead
+                        // of returning error from the stream now. Otherwise
+                        // the error returned now may actually correspond
+                        // to a future for which we succeeded querying weight.
+                        // Note: this behavior is different from what we had
+                        //       in `WeightLimitedBufferedStream` for Stream 0.1
+                        //       but IMO it's more correct, as the stream can
+                        //       keep returning successes after an error
+                        Some((future::ready((Err(e), 0u64)).boxed(), 0))
+                    }
+                    Poll::Ready(None) | Poll::Pending => break,
+                };
+            }
+            if !fits(
+                this.next,
+                *this.current_weight,
+                *this.weight_limit,
+                this.queue.is_empty(),
+            ) {
+                break;
+            }
+
+            let (future, weight) = this.next.take().expect("checked above");
+            *this.current_weight += weight;
             this.queue.push(future);
         }
 
@@ -171,7 +221,7 @@ where
         // If we've gotten this far, then there are no events for us to process
         // and nothing was ready, so figure out if we're not done yet or if
         // we've reached the end.
-        if this.stream.is_done() {
+        if this.stream.is_done() && this.next.is_none() {
             Poll::Ready(None)
         } else {
             Poll::Pending
@@ -187,8 +237,9 @@ mod test {
     use futures::stream;
     use futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
 
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
     use std::sync::Arc;
+    use std::time::Duration;
 
     type TestStream = BoxStream<'static, (BoxFuture<'static, ()>, u64)>;
 
@@ -267,6 +318,53 @@ mod test {
         }
     }
 
+    /// Run futures sleeping, in paused time, for as many milliseconds as
+    /// their weight, up to `weight_limit`, returning their outputs and the
+    /// largest sum of the weights of those running at once
+    async fn run_weighted(weights: Vec<u64>, weight_limit: u64) -> (Vec<u64>, u64) {
+        let running = Arc::new(AtomicU64::new(0));
+        let max_running = Arc::new(AtomicU64::new(0));
+        let s = stream::iter(weights).map(|weight| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            let future = async move {
+                let now = running.fetch_add(weight, Ordering::SeqCst) + weight;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::delay_for(Duration::from_millis(weight)).await;
+                running.fetch_sub(weight, Ordering::SeqCst);
+                weight
+            };
+            (future, weight)
+        });
+        let params = BufferedParams {
+            weight_limit,
+            buffer_size: 10,
+        };
+        let outputs = WeightLimitedBufferedStream::new(params, s).collect().await;
+        (outputs, max_running.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_weight_stays_within_limit() {
+        tokio::time::pause();
+        let (outputs, max_running) = run_weighted(vec![5, 3, 4, 1, 1, 6], 8).await;
+        assert_eq!(outputs, vec![5, 3, 4, 1, 1, 6]);
+        assert_eq!(max_running, 8);
+
+        let (outputs, max_running) = run_weighted(vec![5, 3, 4, 1, 1, 6], 100).await;
+        assert_eq!(outputs, vec![5, 3, 4, 1, 1, 6]);
+        assert_eq!(max_running, 20);
+    }
+
+    #[tokio::test]
+    async fn test_heavier_than_limit() {
+        tokio::time::pause();
+        // The heavy future waits for the others to complete, then runs alone
+        let (outputs, max_running) = run_weighted(vec![2, 10, 1], 4).await;
+        assert_eq!(outputs, vec![2, 10, 1]);
+        assert_eq!(max_running, 10);
+    }
+
     type Error = String;
     type TestTryStream =
         BoxStream<'static, Result<(BoxFuture<'static, Result<(), Error>>, u64), Error>>;
@@ -12,14 +12,30 @@ use futures::{
     task::{Context, Poll},
 };
 use pin_project::pin_project;
+use std::cell::Cell;
 use std::pin::Pin;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use time_ext::{Clock, SystemClock};
+
+thread_local! {
+    static YIELD_COUNT: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Number of times a [YieldPeriodically] stream yielded on this thread. The difference of the
+/// counts before and after polling a future or stream is the number of yields it made, which
+/// futures_stats reports as their `yield_count`.
+pub fn thread_yield_count() -> u64 {
+    YIELD_COUNT.with(|count| count.get())
+}
 
 /// A stream that will yield control back to the caller if it runs for more than a given duration
 /// without yielding (i.e. returning Poll::Pending).  The clock starts counting the first time the
-/// stream is polled, and is reset every time the stream yields.
+/// stream is polled, and is reset every time the stream yields. The time is read from a
+/// [Clock], which is the system clock unless the stream is created with
+/// [YieldPeriodically::with_clock]. With [YieldPeriodically::with_item_budget], the stream also
+/// yields after returning a given number of items in a row.
 #[pin_project]
-pub struct YieldPeriodically<S> {
+pub struct YieldPeriodically<S, C = SystemClock> {
     #[pin]
     inner: S,
     /// Default budget.
@@ -28,21 +44,46 @@ pub struct YieldPeriodically<S> {
     current_budget: Duration,
     /// Whether the next iteration must yield because the budget was exceeded.
     must_yield: bool,
+    /// Number of items after which to yield, if any.
+    item_budget: Option<usize>,
+    /// Number of items returned since the last yield.
+    items: usize,
+    clock: C,
 }
 
 impl<S> YieldPeriodically<S> {
     /// Create a new [YieldPeriodically].
     pub fn new(inner: S, budget: Duration) -> Self {
+        Self::with_clock(inner, budget, SystemClock)
+    }
+}
+
+impl<S, C: Clock> YieldPeriodically<S, C> {
+    /// Create a new [YieldPeriodically] measuring the time spent polling with
+    /// the provided clock.
+    pub fn with_clock(inner: S, budget: Duration, clock: C) -> Self {
         Self {
             inner,
             budget,
             current_budget: budget,
             must_yield: false,
+            item_budget: None,
+            items: 0,
+            clock,
+        }
+    }
+
+    /// Also yield once `items` items were returned without yielding, even if the time budget
+    /// was not exceeded, e.g. when each item is cheap to produce but expensive to process.
+    pub fn with_item_budget(self, items: usize) -> Self {
+        Self {
+            item_budget: Some(items),
+            ..self
         }
     }
 }
 
-impl<S: Stream> Stream for YieldPeriodically<S> {
+impl<S: Stream, C: Clock> Stream for YieldPeriodically<S, C> {
     type Item = <S as Stream>::Item;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -50,19 +91,27 @@ impl<S: Stream> Stream for YieldPeriodically<S> {
 
         if *this.must_yield {
             *this.must_yield = false;
+            *this.items = 0;
+            YIELD_COUNT.with(|count| count.set(count.get() + 1));
             cx.waker().wake_by_ref();
             return Poll::Pending;
         }
 
-        let now = Instant::now();
+        let now = this.clock.now();
         let res = this.inner.poll_next(cx);
 
         if res.is_pending() {
             *this.current_budget = *this.budget;
+            *this.items = 0;
             return res;
         }
 
-        let elapsed = now.elapsed();
+        *this.items += 1;
+        if matches!(*this.item_budget, Some(budget) if *this.items >= budget) {
+            *this.must_yield = true;
+        }
+
+        let elapsed = this.clock.elapsed(now);
 
         match this.current_budget.checked_sub(elapsed) {
             Some(new_budget) => *this.current_budget = new_budget,
@@ -81,6 +130,8 @@ mod test {
     use super::*;
 
     use futures::stream::StreamExt;
+    use std::time::Instant;
+    use time_ext::TestClock;
 
     #[test]
     fn test_yield_happens() {
@@ -120,6 +171,48 @@ mod test {
         assert!(did_unpause, "Stream did not unpause");
     }
 
+    #[test]
+    fn test_yield_with_clock() {
+        let clock = TestClock::new();
+        let stream = futures::stream::repeat(()).inspect({
+            let clock = clock.clone();
+            move |_| clock.advance(Duration::from_millis(40))
+        });
+
+        let stream = YieldPeriodically::with_clock(stream, Duration::from_millis(100), clock);
+
+        futures::pin_mut!(stream);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = futures::task::Context::from_waker(&waker);
+
+        // The budget is exceeded by the third item, so the stream yields after it
+        for _ in 0..3 {
+            assert!(stream.as_mut().poll_next(&mut cx).is_ready());
+        }
+        assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+        assert!(stream.as_mut().poll_next(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_yield_after_items() {
+        let stream = YieldPeriodically::new(futures::stream::repeat(()), Duration::from_secs(60))
+            .with_item_budget(2);
+
+        futures::pin_mut!(stream);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = futures::task::Context::from_waker(&waker);
+
+        let yields = thread_yield_count();
+        for _ in 0..2 {
+            assert!(stream.as_mut().poll_next(&mut cx).is_ready());
+            assert!(stream.as_mut().poll_next(&mut cx).is_ready());
+            assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+        }
+        assert_eq!(thread_yield_count() - yields, 2);
+    }
+
     #[tokio::test]
     async fn test_yield_registers_for_wakeup() {
         // This will hang if the stream doesn't register
@@ -14,10 +14,11 @@ use futures::future::{Future, TryFuture};
 use futures::stream::Stream;
 use futures::task::{Context, Poll};
 use futures_ext::future::CancelData;
+use futures_ext::stream::thread_yield_count;
 use std::pin::Pin;
 use std::time::{Duration, Instant};
 
-use super::{FutureStats, StreamStats};
+use super::{FutureStats, PollHistogram, StreamStats};
 
 /// A Future that gathers some basic statistics for inner Future.
 /// This structure's main usage is by calling [TimedFutureExt::timed].
@@ -26,6 +27,9 @@ pub struct TimedFuture<F> {
     start: Option<Instant>,
     poll_count: u64,
     poll_time: Duration,
+    max_poll_time: Duration,
+    poll_histogram: PollHistogram,
+    yield_count: u64,
 }
 
 impl<F> TimedFuture<F> {
@@ -35,6 +39,9 @@ impl<F> TimedFuture<F> {
             start: None,
             poll_count: 0,
             poll_time: Duration::from_secs(0),
+            max_poll_time: Duration::from_secs(0),
+            poll_histogram: PollHistogram::new(),
+            yield_count: 0,
         }
     }
 }
@@ -48,9 +55,14 @@ impl<F: Future> Future for TimedFuture<F> {
         this.poll_count += 1;
 
         let poll_start = Instant::now();
+        let yields = thread_yield_count();
 
         let poll = unsafe { Pin::new_unchecked(&mut this.inner).poll(cx) };
-        this.poll_time += poll_start.elapsed();
+        let poll_time = poll_start.elapsed();
+        this.poll_time += poll_time;
+        this.max_poll_time = this.max_poll_time.max(poll_time);
+        this.poll_histogram.add(poll_time);
+        this.yield_count += thread_yield_count().wrapping_sub(yields);
 
         let out = match poll {
             Poll::Pending => return Poll::Pending,
@@ -61,7 +73,12 @@ impl<F: Future> Future for TimedFuture<F> {
             completion_time: this.start.expect("start time not set").elapsed(),
             poll_time: this.poll_time,
             poll_count: this.poll_count,
+            max_poll_time: this.max_poll_time,
+            poll_histogram: this.poll_histogram.clone(),
+            yield_count: this.yield_count,
         };
+        #[cfg(feature = "tracing")]
+        stats.record_on_span(&tracing::Span::current());
 
         Poll::Ready((stats, out))
     }
@@ -77,6 +94,9 @@ impl<F> CancelData for TimedFuture<F> {
                 .map_or_else(|| Duration::from_secs(0), |start| start.elapsed()),
             poll_time: self.poll_time,
             poll_count: self.poll_count,
+            max_poll_time: self.max_poll_time,
+            poll_histogram: self.poll_histogram.clone(),
+            yield_count: self.yield_count,
         }
     }
 }
@@ -133,6 +153,9 @@ where
     count: usize,
     poll_count: u64,
     poll_time: Duration,
+    max_poll_time: Duration,
+    poll_histogram: PollHistogram,
+    yield_count: u64,
     first_item_time: Option<Duration>,
 }
 
@@ -151,6 +174,9 @@ where
             count: 0,
             poll_count: 0,
             poll_time: Duration::from_secs(0),
+            max_poll_time: Duration::from_secs(0),
+            poll_histogram: PollHistogram::new(),
+            yield_count: 0,
             first_item_time: None,
         }
     }
@@ -160,9 +186,14 @@ where
             completion_time: self.start.expect("start time not set").elapsed(),
             poll_time: self.poll_time,
             poll_count: self.poll_count,
+            max_poll_time: self.max_poll_time,
+            poll_histogram: self.poll_histogram.clone(),
+            yield_count: self.yield_count,
             count: self.count,
             first_item_time: self.first_item_time,
         };
+        #[cfg(feature = "tracing")]
+        stats.record_on_span(&tracing::Span::current());
         let callback = self.callback.take().expect("callback was already called");
         callback(stats)
     }
@@ -200,8 +231,13 @@ where
         this.poll_count += 1;
 
         let poll_start = Instant::now();
+        let yields = thread_yield_count();
         let poll = unsafe { Pin::new_unchecked(&mut this.inner).poll_next(cx) };
-        this.poll_time += poll_start.elapsed();
+        let poll_time = poll_start.elapsed();
+        this.poll_time += poll_time;
+        this.max_poll_time = this.max_poll_time.max(poll_time);
+        this.poll_histogram.add(poll_time);
+        this.yield_count += thread_yield_count().wrapping_sub(yields);
         match poll {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Some(item)) => {
@@ -308,13 +344,33 @@ mod tests {
     use std::sync::{Arc, Mutex};
 
     use futures::stream::{self, StreamExt};
-    use futures_ext::FbFutureExt;
+    use futures_ext::{FbFutureExt, FbStreamExt};
 
     #[tokio::test]
     async fn test_timed_future() {
         let (stats, result) = async { 123u32 }.timed().await;
         assert_eq!(result, 123u32);
         assert!(stats.poll_count > 0);
+        assert_eq!(stats.poll_histogram.count(), stats.poll_count);
+    }
+
+    #[tokio::test]
+    async fn test_long_poll() {
+        let (stats, ()) = async {
+            tokio::task::yield_now().await;
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        .timed()
+        .await;
+        assert_eq!(stats.poll_count, 2);
+        assert!(stats.max_poll_time >= Duration::from_millis(20));
+        assert!(stats.poll_time >= stats.max_poll_time);
+        assert_eq!(
+            stats
+                .poll_histogram
+                .count_at_least(Duration::from_millis(10)),
+            1
+        );
     }
 
     #[tokio::test]
@@ -347,6 +403,7 @@ mod tests {
                 let callback_called = callback_called.clone();
                 move |stats| async move {
                     assert_eq!(stats.count, TEST_COUNT);
+                    assert_eq!(stats.poll_histogram.count(), stats.poll_count);
                     callback_called.store(true, Ordering::SeqCst);
                 }
             })
@@ -355,4 +412,128 @@ mod tests {
         assert_eq!(out, vec![0; TEST_COUNT]);
         assert!(callback_called.load(Ordering::SeqCst));
     }
+
+    #[tokio::test]
+    async fn test_yield_count() {
+        let yield_count = Arc::new(Mutex::new(None));
+        let (stats, out) = stream::iter(0u32..5)
+            .yield_periodically()
+            .with_item_budget(2)
+            .timed({
+                let yield_count = yield_count.clone();
+                move |stats| async move {
+                    *yield_count.lock().unwrap() = Some(stats.yield_count);
+                }
+            })
+            .collect::<Vec<u32>>()
+            .timed()
+            .await;
+        assert_eq!(out, vec![0, 1, 2, 3, 4]);
+        assert_eq!(*yield_count.lock().unwrap(), Some(2));
+        assert_eq!(stats.yield_count, 2);
+    }
+
+    #[cfg(feature = "tracing")]
+    mod span {
+        use super::*;
+
+        use std::fmt::Debug;
+
+        use tracing::field::{Empty, Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Instrument, Metadata, Subscriber};
+        use tracing_core::span::Current;
+
+        type Recorded = Arc<Mutex<Vec<(String, u64)>>>;
+
+        /// Subscriber keeping the `u64` values recorded on its spans, of
+        /// which the current one is the last entered
+        #[derive(Default)]
+        struct Spans {
+            metadata: Mutex<Vec<&'static Metadata<'static>>>,
+            entered: Mutex<Vec<Id>>,
+            recorded: Recorded,
+        }
+
+        struct FieldVisitor<'a>(&'a mut Vec<(String, u64)>);
+
+        impl Visit for FieldVisitor<'_> {
+            fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                self.0.push((field.name().to_owned(), value));
+            }
+        }
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut metadata = self.metadata.lock().unwrap();
+                metadata.push(span.metadata());
+                Id::from_u64(metadata.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, values: &Record<'_>) {
+                values.record(&mut FieldVisitor(&mut self.recorded.lock().unwrap()));
+            }
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, span: &Id) {
+                self.entered.lock().unwrap().push(span.clone());
+            }
+
+            fn exit(&self, _span: &Id) {
+                self.entered.lock().unwrap().pop();
+            }
+
+            fn current_span(&self) -> Current {
+                match self.entered.lock().unwrap().last() {
+                    Some(id) => {
+                        let metadata = self.metadata.lock().unwrap()[id.into_u64() as usize - 1];
+                        Current::new(id.clone(), metadata)
+                    }
+                    None => Current::none(),
+                }
+            }
+        }
+
+        fn take(recorded: &Recorded) -> Vec<(String, u64)> {
+            std::mem::take(&mut *recorded.lock().unwrap())
+        }
+
+        #[tokio::test]
+        async fn test_record_on_span() {
+            let spans = Spans::default();
+            let recorded = spans.recorded.clone();
+            let _guard = tracing::subscriber::set_default(spans);
+
+            // Only the fields declared by the span are recorded
+            let span = tracing::info_span!("future", poll_count = Empty, yield_count = Empty);
+            let (stats, ()) = tokio::task::yield_now().timed().instrument(span).await;
+            assert_eq!(stats.poll_count, 2);
+            assert_eq!(
+                take(&recorded),
+                vec![("poll_count".to_owned(), 2), ("yield_count".to_owned(), 0)]
+            );
+
+            let span = tracing::info_span!("stream", count = Empty);
+            let out = stream::iter(0u32..3)
+                .timed(|_| async {})
+                .collect::<Vec<u32>>()
+                .instrument(span)
+                .await;
+            assert_eq!(out, vec![0, 1, 2]);
+            assert_eq!(take(&recorded), vec![("count".to_owned(), 3)]);
+
+            // Outside of a span nothing is recorded
+            async {}.timed().await;
+            assert_eq!(take(&recorded), vec![]);
+        }
+    }
 }
@@ -11,9 +11,13 @@
 //! This method returns a Future that times the execution of the wrapped future, and
 //! passes this value to a callback upon completion of the Future. This is useful for
 //! recording performance information about Futures.
+//!
+//! With the `tracing` feature, the stats are also recorded on the current `tracing` span when
+//! the Future or Stream completes, in the fields of `FutureStats::record_on_span` and
+//! `StreamStats::record_on_span` that the span declares, e.g.
+//! `tracing::info_span!("fetch", poll_count = tracing::field::Empty)`.
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
-#![feature(never_type)]
 
 use std::time::Duration;
 
@@ -35,6 +39,31 @@ pub struct FutureStats {
 
     /// Number of times that the Future was polled.
     pub poll_count: u64,
+
+    /// Longest time the wrapped Future spent in a single call to `poll()`. A long poll
+    /// blocks the executor thread, delaying the other futures running on it.
+    pub max_poll_time: Duration,
+
+    /// Durations of the individual calls to `poll()`.
+    pub poll_histogram: PollHistogram,
+
+    /// Number of times the Future yielded to the executor in a
+    /// [futures_ext::stream::YieldPeriodically] stream it polled.
+    pub yield_count: u64,
+}
+
+impl FutureStats {
+    /// Record the stats on `span`, in its fields `completion_time_us`, `poll_time_us`,
+    /// `poll_count`, `max_poll_time_us` and `yield_count`. The fields the span doesn't declare
+    /// are not recorded.
+    #[cfg(feature = "tracing")]
+    pub fn record_on_span(&self, span: &tracing::Span) {
+        span.record("completion_time_us", as_micros(self.completion_time));
+        span.record("poll_time_us", as_micros(self.poll_time));
+        span.record("poll_count", self.poll_count);
+        span.record("max_poll_time_us", as_micros(self.max_poll_time));
+        span.record("yield_count", self.yield_count);
+    }
 }
 
 /// A structure that holds some basic statistics for Stream.
@@ -54,6 +83,142 @@ pub struct StreamStats {
     /// Number of times that the Stream was polled.
     pub poll_count: u64,
 
+    /// Longest time the wrapped Stream spent in a single call to `poll()`.
+    pub max_poll_time: Duration,
+
+    /// Durations of the individual calls to `poll()`.
+    pub poll_histogram: PollHistogram,
+
+    /// Number of times the Stream yielded to the executor in a
+    /// [futures_ext::stream::YieldPeriodically] stream, e.g. itself.
+    pub yield_count: u64,
+
     /// Number of items in the stream
     pub count: usize,
 }
+
+impl StreamStats {
+    /// Record the stats on `span`, in its fields `completion_time_us`, `first_item_time_us`,
+    /// `poll_time_us`, `poll_count`, `max_poll_time_us`, `yield_count` and `count`. The fields
+    /// the span doesn't declare are not recorded.
+    #[cfg(feature = "tracing")]
+    pub fn record_on_span(&self, span: &tracing::Span) {
+        span.record("completion_time_us", as_micros(self.completion_time));
+        if let Some(first_item_time) = self.first_item_time {
+            span.record("first_item_time_us", as_micros(first_item_time));
+        }
+        span.record("poll_time_us", as_micros(self.poll_time));
+        span.record("poll_count", self.poll_count);
+        span.record("max_poll_time_us", as_micros(self.max_poll_time));
+        span.record("yield_count", self.yield_count);
+        span.record("count", self.count as u64);
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn as_micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Number of buckets of a [PollHistogram].
+const POLL_HISTOGRAM_BUCKETS: usize = 32;
+
+/// Histogram of the durations of polls, with buckets of powers of two microseconds:
+/// the first bucket counts the polls shorter than a microsecond, and the bucket `i`
+/// those taking from `2^(i-1)` up to `2^i` microseconds. The polls longer than the
+/// last bucket are counted in it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollHistogram {
+    buckets: [u64; POLL_HISTOGRAM_BUCKETS],
+}
+
+impl PollHistogram {
+    /// Create an empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bucket(poll_time: Duration) -> usize {
+        let micros = u64::try_from(poll_time.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        bucket.min(POLL_HISTOGRAM_BUCKETS - 1)
+    }
+
+    fn lower_bound(bucket: usize) -> Duration {
+        match bucket {
+            0 => Duration::from_secs(0),
+            _ => Duration::from_micros(1 << (bucket - 1)),
+        }
+    }
+
+    /// Record a poll that took `poll_time`.
+    pub fn add(&mut self, poll_time: Duration) {
+        self.buckets[Self::bucket(poll_time)] += 1;
+    }
+
+    /// Add the polls recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &PollHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+
+    /// Total number of polls recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Number of polls that took at least `threshold`, rounded down to a power of two
+    /// microseconds, e.g. the polls that blocked the executor for too long.
+    pub fn count_at_least(&self, threshold: Duration) -> u64 {
+        self.buckets[Self::bucket(threshold)..].iter().sum()
+    }
+
+    /// The buckets with polls in them, as the shortest duration of a poll in the
+    /// bucket and the number of polls in it, from the shortest polls to the longest.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (Self::lower_bound(bucket), *count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_histogram() {
+        let mut histogram = PollHistogram::new();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.buckets().count(), 0);
+
+        histogram.add(Duration::from_nanos(300));
+        histogram.add(Duration::from_micros(1));
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_millis(20));
+        histogram.add(Duration::from_secs(1 << 20));
+        assert_eq!(
+            histogram.buckets().collect::<Vec<_>>(),
+            vec![
+                (Duration::from_secs(0), 1),
+                (Duration::from_micros(1), 1),
+                (Duration::from_micros(2), 2),
+                (Duration::from_micros(16384), 1),
+                (Duration::from_micros(1 << 30), 1),
+            ]
+        );
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.count_at_least(Duration::from_millis(10)), 2);
+        assert_eq!(histogram.count_at_least(Duration::from_secs(0)), 6);
+
+        let mut other = PollHistogram::new();
+        other.add(Duration::from_micros(2));
+        other.merge(&histogram);
+        assert_eq!(other.count(), 7);
+        assert_eq!(other.count_at_least(Duration::from_micros(2)), 5);
+    }
+}
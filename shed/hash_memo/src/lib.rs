@@ -11,6 +11,10 @@
 
 //! Memoize `Hasher::finish()` values to save recomputing them
 
+mod fnv;
+
+pub use fnv::fnv1a;
+
 use once_cell::sync::OnceCell;
 use std::borrow::Borrow;
 use std::fmt;
@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+
+use crate::{get_hostname, overrides};
+
+/// How long [get_hostname_cached] reuses the hostname before asking the
+/// system again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+static DEFAULT: Lazy<CachedHostname> = Lazy::new(|| CachedHostname::new(DEFAULT_TTL));
+
+/// Returns the hostname as reported by the system, asking the system at most
+/// once per [DEFAULT_TTL]. Prefer this over [get_hostname] on hot paths, e.g.
+/// when logging. An overridden hostname is returned as soon as it is set.
+pub fn get_hostname_cached() -> Result<String> {
+    DEFAULT.get()
+}
+
+type Source = Box<dyn Fn() -> Result<String> + Send + Sync>;
+type Override = Box<dyn Fn() -> Option<String> + Send + Sync>;
+type OnChange = Box<dyn Fn(&str, &str) + Send + Sync>;
+
+struct Cached {
+    hostname: String,
+    fetched_at: Instant,
+}
+
+/// Memoizes the hostname for a TTL, so that it is looked up again once in a
+/// while, as it can change during the lifetime of the process (e.g.
+/// containers being renamed or DHCP assigning a new name). The overrides of
+/// the hostname are checked before the cache, so they apply immediately.
+pub struct CachedHostname {
+    ttl: Duration,
+    overrides: Override,
+    source: Source,
+    on_change: Option<OnChange>,
+    cached: RwLock<Option<Cached>>,
+}
+
+impl CachedHostname {
+    /// Create a cache that reuses the hostname for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_source(ttl, overrides::hostname_override, get_hostname)
+    }
+
+    fn with_source(
+        ttl: Duration,
+        overrides: impl Fn() -> Option<String> + Send + Sync + 'static,
+        source: impl Fn() -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            ttl,
+            overrides: Box::new(overrides),
+            source: Box::new(source),
+            on_change: None,
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Call `on_change` with the old and new hostname whenever a refresh finds
+    /// that the hostname changed
+    pub fn on_change(mut self, on_change: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        self.on_change = Some(Box::new(on_change));
+        self
+    }
+
+    /// Returns the cached hostname, looking it up again if it is older than
+    /// the TTL. If the lookup fails the error is returned and the cache is
+    /// left as is, so that it is retried on the next call.
+    pub fn get(&self) -> Result<String> {
+        if let Some(hostname) = (self.overrides)() {
+            return Ok(hostname);
+        }
+        if let Some(cached) = &*self.cached.read().expect("poisoned lock") {
+            if cached.fetched_at.elapsed() < self.ttl {
+                return Ok(cached.hostname.clone());
+            }
+        }
+        self.refresh()
+    }
+
+    /// Look up the hostname now, regardless of the TTL
+    pub fn refresh(&self) -> Result<String> {
+        let hostname = (self.source)()?;
+        let previous = self.cached.write().expect("poisoned lock").replace(Cached {
+            hostname: hostname.clone(),
+            fetched_at: Instant::now(),
+        });
+        if let (Some(on_change), Some(previous)) = (&self.on_change, previous) {
+            if previous.hostname != hostname {
+                on_change(&previous.hostname, &hostname);
+            }
+        }
+        Ok(hostname)
+    }
+}
+
+impl fmt::Debug for CachedHostname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedHostname")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_cached_hostname() {
+        let hostname = Arc::new(Mutex::new("first".to_owned()));
+        let lookups = Arc::new(Mutex::new(0));
+        let changes = Arc::new(Mutex::new(Vec::new()));
+
+        let cache = CachedHostname::with_source(Duration::from_secs(3600), || None, {
+            let hostname = hostname.clone();
+            let lookups = lookups.clone();
+            move || {
+                *lookups.lock().unwrap() += 1;
+                Ok(hostname.lock().unwrap().clone())
+            }
+        })
+        .on_change({
+            let changes = changes.clone();
+            move |old: &str, new: &str| {
+                changes
+                    .lock()
+                    .unwrap()
+                    .push((old.to_owned(), new.to_owned()))
+            }
+        });
+
+        assert_eq!(cache.get().unwrap(), "first");
+        *hostname.lock().unwrap() = "second".to_owned();
+        assert_eq!(cache.get().unwrap(), "first");
+        assert_eq!(*lookups.lock().unwrap(), 1);
+
+        assert_eq!(cache.refresh().unwrap(), "second");
+        assert_eq!(cache.get().unwrap(), "second");
+        assert_eq!(*lookups.lock().unwrap(), 2);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![("first".to_owned(), "second".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_expired() {
+        let lookups = Arc::new(Mutex::new(0));
+        let cache = CachedHostname::with_source(Duration::ZERO, || None, {
+            let lookups = lookups.clone();
+            move || {
+                *lookups.lock().unwrap() += 1;
+                Ok("host".to_owned())
+            }
+        });
+        cache.get().unwrap();
+        cache.get().unwrap();
+        assert_eq!(*lookups.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_override() {
+        let forced = Arc::new(Mutex::new(None));
+        let cache = CachedHostname::with_source(
+            Duration::from_secs(3600),
+            {
+                let forced = forced.clone();
+                move || forced.lock().unwrap().clone()
+            },
+            || Ok("host".to_owned()),
+        );
+        assert_eq!(cache.get().unwrap(), "host");
+        *forced.lock().unwrap() = Some("forced".to_owned());
+        assert_eq!(cache.get().unwrap(), "forced");
+        *forced.lock().unwrap() = None;
+        assert_eq!(cache.get().unwrap(), "host");
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// The fully-qualified name of `hostname`, or `hostname` itself if it doesn't
+/// resolve
+pub(crate) fn fqdn(hostname: String) -> String {
+    canonical_name(&hostname).unwrap_or(hostname)
+}
+
+/// Resolve the canonical name of `host` through the system resolver, which
+/// applies the search domains of resolv.conf and the entries of /etc/hosts
+/// like `hostname --fqdn` does. Returns None if the name doesn't resolve.
+pub(crate) fn canonical_name(host: &str) -> Option<String> {
+    let host = CString::new(host).ok()?;
+    // SAFETY: an all-zero addrinfo is a valid value for the hints
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_family = libc::AF_UNSPEC;
+    hints.ai_socktype = libc::SOCK_STREAM;
+    hints.ai_flags = libc::AI_CANONNAME;
+
+    let mut result = ptr::null_mut();
+    // SAFETY: the pointers are valid for the duration of the call and result
+    // is freed below on success
+    let rc = unsafe { libc::getaddrinfo(host.as_ptr(), ptr::null(), &hints, &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+
+    // SAFETY: on success result points to a valid list, whose first entry
+    // holds the canonical name if it was requested
+    let name = unsafe {
+        let canonname = (*result).ai_canonname;
+        let name = if canonname.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(canonname).to_string_lossy().into_owned())
+        };
+        libc::freeaddrinfo(result);
+        name
+    };
+    name.filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_canonical_name() {
+        // Resolved through /etc/hosts
+        assert_eq!(canonical_name("localhost").as_deref(), Some("localhost"));
+        // The .invalid top level domain never resolves
+        assert_eq!(canonical_name("no-such-host.invalid"), None);
+        assert_eq!(canonical_name("nul\0byte"), None);
+    }
+
+    #[test]
+    fn test_fqdn() {
+        assert_eq!(fqdn("localhost".to_owned()), "localhost");
+        assert_eq!(
+            fqdn("no-such-host.invalid".to_owned()),
+            "no-such-host.invalid"
+        );
+    }
+}
@@ -12,10 +12,23 @@
 //! Crate that wraps the OSS hostname and FB internal libraries to provide
 //! hostname resolution
 
+mod cached;
+#[cfg(not(fbcode_build))]
+mod fqdn;
+mod overrides;
+
+pub use crate::cached::{get_hostname_cached, CachedHostname, DEFAULT_TTL};
+pub use crate::overrides::{set_hostname_override, HOSTNAME_OVERRIDE_ENV};
+
 use anyhow::Result;
 
-/// Returns hostname as reported by the system
+/// Returns hostname as reported by the system, unless overridden with
+/// [set_hostname_override] or [HOSTNAME_OVERRIDE_ENV]
 pub fn get_hostname() -> Result<String> {
+    if let Some(hostname) = overrides::hostname_override() {
+        return Ok(hostname);
+    }
+
     #[cfg(not(fbcode_build))]
     {
         Ok(::hostname_orig::get()?.to_string_lossy().into_owned())
@@ -31,3 +44,27 @@ pub fn get_hostname() -> Result<String> {
             .ok_or_else(|| ::anyhow::Error::msg("No hostname in fbwhoami"))
     }
 }
+
+/// Returns the fully-qualified domain name of the host, e.g. for TLS or
+/// service discovery. The hostname is resolved through the system resolver,
+/// search domains included, falling back to the hostname as reported by the
+/// system if it doesn't resolve. An overridden hostname is returned as is.
+pub fn get_fqdn() -> Result<String> {
+    if let Some(hostname) = overrides::hostname_override() {
+        return Ok(hostname);
+    }
+    let hostname = get_hostname()?;
+
+    #[cfg(not(fbcode_build))]
+    {
+        Ok(fqdn::fqdn(hostname))
+    }
+
+    #[cfg(fbcode_build)]
+    {
+        use libc as _; // used in oss
+
+        // fbwhoami already reports the fully-qualified name
+        Ok(hostname)
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::env;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+
+/// Environment variable that, when set to a non-empty value, is returned by
+/// [crate::get_hostname] and [crate::get_fqdn] instead of the name of the
+/// system
+pub const HOSTNAME_OVERRIDE_ENV: &str = "RUST_SHED_HOSTNAME_OVERRIDE";
+
+static OVERRIDE: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+
+/// Force [crate::get_hostname] and [crate::get_fqdn] to return `hostname`,
+/// e.g. to get deterministic names in tests, or pass None to go back to the
+/// name of the system. Takes precedence over [HOSTNAME_OVERRIDE_ENV].
+pub fn set_hostname_override(hostname: Option<String>) {
+    *OVERRIDE.write().expect("poisoned lock") = hostname;
+}
+
+/// The hostname forced by [set_hostname_override] or [HOSTNAME_OVERRIDE_ENV],
+/// if any
+pub(crate) fn hostname_override() -> Option<String> {
+    hostname_override_from(|name| env::var(name).ok())
+}
+
+/// Same as [hostname_override], reading the environment with `var`
+fn hostname_override_from(var: impl FnOnce(&str) -> Option<String>) -> Option<String> {
+    if let Some(hostname) = &*OVERRIDE.read().expect("poisoned lock") {
+        return Some(hostname.clone());
+    }
+    var(HOSTNAME_OVERRIDE_ENV).filter(|hostname| !hostname.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{get_fqdn, get_hostname, get_hostname_cached};
+
+    #[test]
+    fn test_hostname_override() {
+        let env = |hostname: &'static str| {
+            move |name: &str| {
+                assert_eq!(name, HOSTNAME_OVERRIDE_ENV);
+                Some(hostname.to_owned())
+            }
+        };
+        assert_eq!(
+            hostname_override_from(env("from-env.example.com")),
+            Some("from-env.example.com".to_owned())
+        );
+        assert_eq!(hostname_override_from(env("")), None);
+        assert_eq!(hostname_override_from(|_| None), None);
+
+        // Checked before the cache filled with the name of the system
+        let system = get_hostname_cached().unwrap();
+        set_hostname_override(Some("forced".to_owned()));
+        assert_eq!(
+            hostname_override_from(env("from-env")).as_deref(),
+            Some("forced")
+        );
+        assert_eq!(get_hostname().unwrap(), "forced");
+        assert_eq!(get_hostname_cached().unwrap(), "forced");
+        assert_eq!(get_fqdn().unwrap(), "forced");
+
+        set_hostname_override(None);
+        assert_eq!(get_hostname_cached().unwrap(), system);
+    }
+}
@@ -0,0 +1,35 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Crate for evaluating JustKnobs, i.e. dynamically updated feature gates.
+//! The version on GitHub reads the knobs from a JSON or TOML config kept up to
+//! date by cached_config, see [CachedConfigJustKnobs].
+
+#[cfg(fbcode_build)]
+mod _unused {
+    // used in oss
+    use anyhow as _;
+    use bytes as _;
+    use cached_config as _;
+    use once_cell as _;
+    use serde as _;
+    use slog as _;
+    use toml as _;
+}
+
+#[cfg(not(fbcode_build))]
+mod oss;
+
+#[cfg(not(fbcode_build))]
+pub use crate::oss::*;
+
+#[cfg(fbcode_build)]
+pub use justknobs::*;
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use cached_config::{ConfigHandle, ConfigStore};
+use serde::Deserialize;
+use slog::Logger;
+
+use super::JustKnobs;
+
+/// Content of the config the knobs are read from, e.g.
+/// ```json
+/// {
+///   "bools": { "my_project/feature": true },
+///   "ints": { "my_project/batch_size": 100 }
+/// }
+/// ```
+/// or the same in TOML, for configs whose path ends with `.toml`
+/// ```toml
+/// [bools]
+/// "my_project/feature" = true
+///
+/// [ints]
+/// "my_project/batch_size" = 100
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct JustKnobsConfig {
+    /// Values of the boolean knobs, by name
+    #[serde(default)]
+    pub bools: HashMap<String, bool>,
+    /// Values of the integer knobs, by name
+    #[serde(default)]
+    pub ints: HashMap<String, i64>,
+}
+
+/// Knobs read from a JSON or TOML config, see [JustKnobsConfig], that is reloaded by
+/// cached_config whenever it changes. As the values are plain, `hash_val`
+/// and `switch_val` have no effect. Evaluating a knob missing from the config
+/// fails.
+#[derive(Clone)]
+pub struct CachedConfigJustKnobs {
+    config: ConfigHandle<JustKnobsConfig>,
+}
+
+impl CachedConfigJustKnobs {
+    /// Read the knobs from the config at `path` of `store`, parsed as TOML if
+    /// the path ends with `.toml` and as JSON otherwise
+    pub fn from_store(store: &ConfigStore, path: impl Into<String>) -> Result<Self> {
+        let path = path.into();
+        let config = if path.ends_with(".toml") {
+            store.get_config_handle_with_deserializer(path.clone(), deserialize_toml)
+        } else {
+            // The knobs are plain JSON rather than thrift simple JSON
+            store.get_config_handle_DEPRECATED(path.clone())
+        }
+        .with_context(|| format!("While loading JustKnobs from {}", path))?;
+        Ok(Self::from_handle(config))
+    }
+
+    /// Read the knobs from the JSON or TOML file at `path`, see
+    /// [CachedConfigJustKnobs::from_store], checking for changes every
+    /// `poll_interval`
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        poll_interval: Duration,
+        logger: impl Into<Option<Logger>>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let (directory, file_name) = match (path.parent(), path.file_name()) {
+            (Some(directory), Some(file_name)) => (directory, file_name),
+            _ => return Err(anyhow!("Invalid JustKnobs path {}", path.display())),
+        };
+        let store = ConfigStore::file(logger, directory.to_owned(), None, poll_interval);
+        Self::from_store(&store, file_name.to_string_lossy())
+    }
+
+    /// Read the knobs from an existing handle, e.g. a fixed one made with
+    /// [ConfigHandle::from_json]
+    pub fn from_handle(config: ConfigHandle<JustKnobsConfig>) -> Self {
+        Self { config }
+    }
+}
+
+fn deserialize_toml(config: Bytes) -> Result<JustKnobsConfig> {
+    Ok(toml::from_slice(&config)?)
+}
+
+impl JustKnobs for CachedConfigJustKnobs {
+    fn eval(&self, name: &str, _hash_val: Option<&str>, _switch_val: Option<&str>) -> Result<bool> {
+        self.config
+            .get()
+            .bools
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("Missing just knob: {}", name))
+    }
+
+    fn get(&self, name: &str, _switch_val: Option<&str>) -> Result<i64> {
+        self.config
+            .get()
+            .ints
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("Missing int just knob: {}", name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cached_config::{ModificationTime, TestSource};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reload() {
+        let source = Arc::new(TestSource::new());
+        source.insert_config(
+            "knobs",
+            r#"{ "bools": { "feature": false }, "ints": { "size": 1 } }"#,
+            ModificationTime::UnixTimestamp(1),
+        );
+        let store = ConfigStore::new(source.clone(), None, None);
+        let knobs = CachedConfigJustKnobs::from_store(&store, "knobs").unwrap();
+        assert!(!knobs.eval("feature", None, None).unwrap());
+        assert_eq!(knobs.get("size", None).unwrap(), 1);
+        assert!(knobs.eval("missing", None, None).is_err());
+
+        source.insert_config(
+            "knobs",
+            r#"{ "bools": { "feature": true } }"#,
+            ModificationTime::UnixTimestamp(2),
+        );
+        source.insert_to_refresh("knobs".to_owned());
+        store.force_update_configs();
+        assert!(knobs.eval("feature", None, None).unwrap());
+        assert!(knobs.get("size", None).is_err());
+    }
+
+    #[test]
+    fn test_toml() {
+        let source = Arc::new(TestSource::new());
+        source.insert_config(
+            "knobs.toml",
+            "[bools]\n\"my/feature\" = true\n\n[ints]\n\"my/size\" = 3\n",
+            ModificationTime::UnixTimestamp(1),
+        );
+        source.insert_config(
+            "broken.toml",
+            r#"{ "bools": {} }"#,
+            ModificationTime::UnixTimestamp(1),
+        );
+        let store = ConfigStore::new(source, None, None);
+        let knobs = CachedConfigJustKnobs::from_store(&store, "knobs.toml").unwrap();
+        assert!(knobs.eval("my/feature", None, None).unwrap());
+        assert_eq!(knobs.get("my/size", None).unwrap(), 3);
+        assert!(CachedConfigJustKnobs::from_store(&store, "broken.toml").is_err());
+    }
+
+    #[test]
+    fn test_global() {
+        let knobs =
+            ConfigHandle::from_json(r#"{ "bools": { "test_global/feature": true } }"#).unwrap();
+        crate::init_just_knobs(Arc::new(CachedConfigJustKnobs::from_handle(knobs)));
+        assert!(crate::eval("test_global/feature", None, None).unwrap());
+    }
+}
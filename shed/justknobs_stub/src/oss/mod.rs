@@ -0,0 +1,87 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+mod config;
+mod overrides;
+
+pub use self::config::{CachedConfigJustKnobs, JustKnobsConfig};
+pub use self::overrides::{
+    current_overrides, test_override, JustKnobsOverride, JustKnobsOverrides, KnobValue,
+};
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+
+/// Backend providing the values of the knobs
+pub trait JustKnobs {
+    /// Evaluate the boolean knob `name`. `hash_val` identifies the entity the
+    /// knob is evaluated for, e.g. for gradual rollouts, and `switch_val`
+    /// selects a per-switch override of the knob.
+    fn eval(&self, name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> Result<bool>;
+
+    /// Get the value of the integer knob `name`, see [JustKnobs::eval] for the
+    /// meaning of `switch_val`
+    fn get(&self, name: &str, switch_val: Option<&str>) -> Result<i64>;
+}
+
+/// Backend used until [init_just_knobs] is called, with every boolean knob
+/// disabled and every integer knob 0
+#[derive(Clone, Copy, Debug, Default)]
+struct DefaultJustKnobs;
+
+impl JustKnobs for DefaultJustKnobs {
+    fn eval(
+        &self,
+        _name: &str,
+        _hash_val: Option<&str>,
+        _switch_val: Option<&str>,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn get(&self, _name: &str, _switch_val: Option<&str>) -> Result<i64> {
+        Ok(0)
+    }
+}
+
+static JUST_KNOBS: Lazy<RwLock<Arc<dyn JustKnobs + Send + Sync>>> =
+    Lazy::new(|| RwLock::new(Arc::new(DefaultJustKnobs)));
+
+fn just_knobs() -> Arc<dyn JustKnobs + Send + Sync> {
+    JUST_KNOBS.read().expect("poisoned lock").clone()
+}
+
+/// Set the backend the knobs of this process are read from, e.g. a
+/// [CachedConfigJustKnobs]. Until then every boolean knob is disabled and
+/// every integer knob is 0.
+pub fn init_just_knobs(just_knobs: Arc<dyn JustKnobs + Send + Sync>) {
+    *JUST_KNOBS.write().expect("poisoned lock") = just_knobs;
+}
+
+/// Evaluate the boolean knob `name`, see [JustKnobs::eval]. Overrides made
+/// with [test_override] take precedence over the backend.
+pub fn eval(name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> Result<bool> {
+    match overrides::get_override(name) {
+        Some(KnobValue::Bool(value)) => Ok(value),
+        Some(KnobValue::Int(_)) => bail!("Just knob {} is overridden with an int", name),
+        None => just_knobs().eval(name, hash_val, switch_val),
+    }
+}
+
+/// Get the value of the integer knob `name`, see [JustKnobs::get]. Overrides
+/// made with [test_override] take precedence over the backend.
+pub fn get(name: &str, switch_val: Option<&str>) -> Result<i64> {
+    match overrides::get_override(name) {
+        Some(KnobValue::Int(value)) => Ok(value),
+        Some(KnobValue::Bool(_)) => bail!("Int just knob {} is overridden with a bool", name),
+        None => just_knobs().get(name, switch_val),
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::marker::PhantomData;
+use std::pin::pin;
+
+/// Value a knob is overridden with by [test_override]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KnobValue {
+    /// Value of a boolean knob, see [crate::eval]
+    Bool(bool),
+    /// Value of an integer knob, see [crate::get]
+    Int(i64),
+}
+
+impl From<bool> for KnobValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for KnobValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+thread_local! {
+    static OVERRIDES: RefCell<HashMap<String, KnobValue>> = RefCell::new(HashMap::new());
+}
+
+/// Override the knob `name` with `value` on the current thread until the
+/// returned guard is dropped, which restores the previous override if any.
+/// As the overrides are per thread they don't leak into the tests running
+/// concurrently, but they also don't apply to the other threads a test
+/// spawns, e.g. the workers of a multi-threaded tokio runtime, unless they are
+/// passed along with [current_overrides].
+///
+/// # Example
+/// ```
+/// let _knob = justknobs::test_override("my_project/feature", true);
+/// assert!(justknobs::eval("my_project/feature", None, None).unwrap());
+/// ```
+pub fn test_override(name: &str, value: impl Into<KnobValue>) -> JustKnobsOverride {
+    let previous =
+        OVERRIDES.with(|overrides| overrides.borrow_mut().insert(name.to_owned(), value.into()));
+    JustKnobsOverride {
+        name: name.to_owned(),
+        previous,
+        _not_send: PhantomData,
+    }
+}
+
+/// The value the knob `name` is overridden with on the current thread
+pub(crate) fn get_override(name: &str) -> Option<KnobValue> {
+    OVERRIDES.with(|overrides| overrides.borrow().get(name).copied())
+}
+
+/// The overrides made with [test_override] on the current thread, to apply
+/// them to the work the test runs elsewhere.
+///
+/// # Example
+/// ```
+/// # #[tokio::main]
+/// # async fn main() {
+/// let _knob = justknobs::test_override("my_project/feature", true);
+/// let task = justknobs::current_overrides()
+///     .wrap(async { justknobs::eval("my_project/feature", None, None).unwrap() });
+/// assert!(tokio::spawn(task).await.unwrap());
+/// # }
+/// ```
+pub fn current_overrides() -> JustKnobsOverrides {
+    JustKnobsOverrides(OVERRIDES.with(|overrides| overrides.borrow().clone()))
+}
+
+/// Overrides captured by [current_overrides]
+#[derive(Clone, Debug, Default)]
+pub struct JustKnobsOverrides(HashMap<String, KnobValue>);
+
+impl JustKnobsOverrides {
+    /// Run `f` with these overrides applied on the current thread, on top of
+    /// its own ones, which are restored once `f` returns
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guards: Vec<_> = self
+            .0
+            .iter()
+            .map(|(name, value)| test_override(name, *value))
+            .collect();
+        f()
+    }
+
+    /// Wrap `fut` so that it runs with these overrides applied, on whichever
+    /// thread it is polled, e.g. to pass them to a spawned task
+    pub async fn wrap<F: Future>(self, fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        future::poll_fn(|cx| self.scope(|| fut.as_mut().poll(cx))).await
+    }
+}
+
+/// Guard of an override made with [test_override]
+#[must_use = "the override is removed when the guard is dropped"]
+#[derive(Debug)]
+pub struct JustKnobsOverride {
+    name: String,
+    previous: Option<KnobValue>,
+    // The override lives in a thread local, so it has to be removed from the
+    // thread it was made on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for JustKnobsOverride {
+    fn drop(&mut self) {
+        OVERRIDES.with(|overrides| {
+            let mut overrides = overrides.borrow_mut();
+            match self.previous {
+                Some(previous) => overrides.insert(self.name.clone(), previous),
+                None => overrides.remove(&self.name),
+            };
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{eval, get};
+    use std::thread;
+
+    // The backend is global and other tests replace it, so only the presence
+    // of the overrides is checked when none should apply
+    #[test]
+    fn test_scoped_override() {
+        assert_eq!(get_override("test_scoped/feature"), None);
+        {
+            let _outer = test_override("test_scoped/feature", true);
+            assert!(eval("test_scoped/feature", None, None).unwrap());
+            {
+                let _inner = test_override("test_scoped/feature", false);
+                assert!(!eval("test_scoped/feature", None, None).unwrap());
+            }
+            assert!(eval("test_scoped/feature", None, None).unwrap());
+
+            // Other threads don't see the override
+            thread::spawn(|| assert_eq!(get_override("test_scoped/feature"), None))
+                .join()
+                .unwrap();
+        }
+        assert_eq!(get_override("test_scoped/feature"), None);
+
+        let _int = test_override("test_scoped/size", 3);
+        assert_eq!(get("test_scoped/size", None).unwrap(), 3);
+        assert!(eval("test_scoped/size", None, None).is_err());
+    }
+
+    #[test]
+    fn test_propagate_to_thread() {
+        let _feature = test_override("test_propagate/feature", true);
+        let overrides = current_overrides();
+        thread::spawn(move || {
+            let _size = test_override("test_propagate/size", 1);
+            overrides.scope(|| {
+                assert_eq!(
+                    get_override("test_propagate/feature"),
+                    Some(KnobValue::Bool(true))
+                );
+                assert_eq!(get("test_propagate/size", None).unwrap(), 1);
+            });
+            // The overrides of the thread itself are left as they were
+            assert_eq!(get_override("test_propagate/feature"), None);
+            assert_eq!(get("test_propagate/size", None).unwrap(), 1);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_propagate_to_task() {
+        let _feature = test_override("test_propagate/task", true);
+        let task = current_overrides().wrap(async {
+            tokio::task::yield_now().await;
+            get_override("test_propagate/task")
+        });
+        assert_eq!(
+            tokio::spawn(task).await.unwrap(),
+            Some(KnobValue::Bool(true))
+        );
+        // Not applied to the worker threads outside of the wrapped future
+        let unwrapped = tokio::spawn(async { get_override("test_propagate/task") });
+        assert_eq!(unwrapped.await.unwrap(), None);
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Counterparts of [LockExt](crate::LockExt) and
+//! [RwLockExt](crate::RwLockExt) for the locks of [tokio::sync], enabled with
+//! the `async` feature.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::time::{timeout, Instant};
+
+use crate::LockTimeoutError;
+
+/// Extend functionality of [tokio::sync::Mutex]
+///
+/// # Example
+/// ```
+/// # use tokio::sync::Mutex;
+/// # use lock_ext::AsyncLockExt;
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let lock = Mutex::new(Vec::new());
+/// lock.with(|value| value.push("hello")).await;
+/// let hello = lock.with(|value| value.get(0).unwrap().to_owned()).await;
+/// # assert_eq!(&hello, &"hello");
+/// # }
+/// ```
+#[async_trait]
+pub trait AsyncLockExt {
+    /// Value that is being held inside the lock
+    type Value;
+
+    /// The passed `scope` function will be called with the lock being held
+    /// and the locked value will be accessible inside the `scope` as `&mut`
+    async fn with<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out + Send,
+        Out: Send;
+
+    /// Acquire the lock, giving up after waiting for `timeout`
+    async fn lock_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<MutexGuard<'_, Self::Value>, LockTimeoutError>;
+}
+
+#[async_trait]
+impl<V: Send> AsyncLockExt for Mutex<V> {
+    type Value = V;
+
+    async fn with<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out + Send,
+        Out: Send,
+    {
+        let mut value = self.lock().await;
+        scope(&mut *value)
+    }
+
+    async fn lock_timeout(
+        &self,
+        duration: Duration,
+    ) -> Result<MutexGuard<'_, Self::Value>, LockTimeoutError> {
+        let start = Instant::now();
+        timeout(duration, self.lock())
+            .await
+            .map_err(|_| LockTimeoutError {
+                waited: start.elapsed(),
+            })
+    }
+}
+
+/// Extend functionality of [tokio::sync::RwLock]
+///
+/// # Example
+/// ```
+/// # use tokio::sync::RwLock;
+/// # use lock_ext::AsyncRwLockExt;
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let lock = RwLock::new(Vec::new());
+/// lock.with_write(|value| value.push("hello")).await;
+/// let hello = lock.with_read(|value| value.get(0).unwrap().to_owned()).await;
+/// # assert_eq!(&hello, &"hello");
+/// # }
+/// ```
+#[async_trait]
+pub trait AsyncRwLockExt {
+    /// Value that is being held inside the lock
+    type Value;
+
+    /// The passed `scope` function will be called with the read lock being held
+    /// and the locked value will be accessible inside the `scope` as `&`
+    async fn with_read<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&Self::Value) -> Out + Send,
+        Out: Send;
+
+    /// The passed `scope` function will be called with the write lock being held
+    /// and the locked value will be accessible inside the `scope` as `&mut`
+    async fn with_write<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out + Send,
+        Out: Send;
+
+    /// Acquire the read lock, giving up after waiting for `timeout`
+    async fn read_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RwLockReadGuard<'_, Self::Value>, LockTimeoutError>;
+
+    /// Acquire the write lock, giving up after waiting for `timeout`
+    async fn write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RwLockWriteGuard<'_, Self::Value>, LockTimeoutError>;
+}
+
+#[async_trait]
+impl<V: Send + Sync> AsyncRwLockExt for RwLock<V> {
+    type Value = V;
+
+    async fn with_read<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&Self::Value) -> Out + Send,
+        Out: Send,
+    {
+        let value = self.read().await;
+        scope(&*value)
+    }
+
+    async fn with_write<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out + Send,
+        Out: Send,
+    {
+        let mut value = self.write().await;
+        scope(&mut *value)
+    }
+
+    async fn read_timeout(
+        &self,
+        duration: Duration,
+    ) -> Result<RwLockReadGuard<'_, Self::Value>, LockTimeoutError> {
+        let start = Instant::now();
+        timeout(duration, self.read())
+            .await
+            .map_err(|_| LockTimeoutError {
+                waited: start.elapsed(),
+            })
+    }
+
+    async fn write_timeout(
+        &self,
+        duration: Duration,
+    ) -> Result<RwLockWriteGuard<'_, Self::Value>, LockTimeoutError> {
+        let start = Instant::now();
+        timeout(duration, self.write())
+            .await
+            .map_err(|_| LockTimeoutError {
+                waited: start.elapsed(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn mutex() {
+        let lock = Mutex::new(Vec::new());
+        lock.with(|vs| vs.push("test")).await;
+        assert_eq!(lock.with(|vs| vs.len()).await, 1);
+
+        let guard = lock.lock_timeout(Duration::from_secs(1)).await.unwrap();
+        let err = lock.lock_timeout(Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(err.waited, Duration::from_secs(1));
+        drop(guard);
+        assert!(lock.lock_timeout(Duration::from_secs(1)).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rwlock() {
+        let lock = RwLock::new(Vec::new());
+        lock.with_write(|vs| vs.push("test")).await;
+        assert_eq!(lock.with_read(|vs| vs.len()).await, 1);
+
+        let read = lock.read_timeout(Duration::from_secs(1)).await.unwrap();
+        assert!(lock.read_timeout(Duration::from_secs(1)).await.is_ok());
+        let err = lock
+            .write_timeout(Duration::from_secs(2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.waited, Duration::from_secs(2));
+        drop(read);
+        assert!(lock.write_timeout(Duration::from_secs(1)).await.is_ok());
+    }
+}
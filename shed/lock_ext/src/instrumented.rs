@@ -0,0 +1,304 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use stats::prelude::*;
+
+use crate::LockExt;
+
+define_stats! {
+    prefix = "lock_ext";
+    wait_us: dynamic_histogram("{}.wait_us", (name: &'static str); 10, 0, 10_000, Average, Count; P 50; P 99),
+    hold_us: dynamic_histogram("{}.hold_us", (name: &'static str); 10, 0, 10_000, Average, Count; P 50; P 99),
+}
+
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros() as i64
+}
+
+/// A [parking_lot::Mutex] that records how long each acquisition waited for
+/// the lock and how long the lock was then held, in microseconds, into the
+/// `lock_ext.<name>.wait_us` and `lock_ext.<name>.hold_us` histograms. Use it
+/// in place of a plain mutex to find out whether a lock is contended.
+pub struct InstrumentedMutex<T: ?Sized> {
+    name: &'static str,
+    inner: Mutex<T>,
+}
+
+impl<T> InstrumentedMutex<T> {
+    /// Create a new mutex whose stats are exported under `name`
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(value),
+        }
+    }
+
+    /// Consume the mutex, returning the value
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> InstrumentedMutex<T> {
+    /// Name the stats of this mutex are exported under
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Acquire the lock, blocking until it is available
+    pub fn lock(&self) -> InstrumentedMutexGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.lock();
+        let acquired = Instant::now();
+        STATS::wait_us.add_value(micros(acquired - start), (self.name,));
+        InstrumentedMutexGuard {
+            name: self.name,
+            acquired,
+            guard,
+        }
+    }
+
+    /// Mutable access to the value, without locking as `&mut self` guarantees
+    /// exclusive access
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T> LockExt for InstrumentedMutex<T> {
+    type Value = T;
+
+    fn with<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out,
+    {
+        let mut value = self.lock();
+        scope(&mut *value)
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for InstrumentedMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentedMutex")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Guard of an [InstrumentedMutex], recording the hold time when dropped
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct InstrumentedMutexGuard<'a, T: ?Sized> {
+    name: &'static str,
+    acquired: Instant,
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for InstrumentedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for InstrumentedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for InstrumentedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        STATS::hold_us.add_value(micros(self.acquired.elapsed()), (self.name,));
+    }
+}
+
+/// A [parking_lot::RwLock] recording its wait and hold times like an
+/// [InstrumentedMutex] does, for both the read and the write accesses
+pub struct InstrumentedRwLock<T: ?Sized> {
+    name: &'static str,
+    inner: RwLock<T>,
+}
+
+impl<T> InstrumentedRwLock<T> {
+    /// Create a new lock whose stats are exported under `name`
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: RwLock::new(value),
+        }
+    }
+
+    /// Consume the lock, returning the value
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> InstrumentedRwLock<T> {
+    /// Name the stats of this lock are exported under
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Acquire the read lock, blocking until it is available
+    pub fn read(&self) -> InstrumentedRwLockReadGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.read();
+        let acquired = Instant::now();
+        STATS::wait_us.add_value(micros(acquired - start), (self.name,));
+        InstrumentedRwLockReadGuard {
+            name: self.name,
+            acquired,
+            guard,
+        }
+    }
+
+    /// Acquire the write lock, blocking until it is available
+    pub fn write(&self) -> InstrumentedRwLockWriteGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.write();
+        let acquired = Instant::now();
+        STATS::wait_us.add_value(micros(acquired - start), (self.name,));
+        InstrumentedRwLockWriteGuard {
+            name: self.name,
+            acquired,
+            guard,
+        }
+    }
+
+    /// Mutable access to the value, without locking as `&mut self` guarantees
+    /// exclusive access
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for InstrumentedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentedRwLock")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Read guard of an [InstrumentedRwLock], recording the hold time when dropped
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct InstrumentedRwLockReadGuard<'a, T: ?Sized> {
+    name: &'static str,
+    acquired: Instant,
+    guard: RwLockReadGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for InstrumentedRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for InstrumentedRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        STATS::hold_us.add_value(micros(self.acquired.elapsed()), (self.name,));
+    }
+}
+
+/// Write guard of an [InstrumentedRwLock], recording the hold time when
+/// dropped
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct InstrumentedRwLockWriteGuard<'a, T: ?Sized> {
+    name: &'static str,
+    acquired: Instant,
+    guard: RwLockWriteGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for InstrumentedRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for InstrumentedRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for InstrumentedRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        STATS::hold_us.add_value(micros(self.acquired.elapsed()), (self.name,));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Check that every acquisition of the lock recorded its wait and hold
+    /// times. Outside of fbcode the stats are recorded in memory.
+    fn assert_recorded(name: &str, acquisitions: i64) {
+        if cfg!(fbcode_build) {
+            return;
+        }
+        let stats = stats::snapshot();
+        for stat in ["wait_us", "hold_us"] {
+            assert_eq!(
+                stats[&format!("lock_ext.{}.{}.count", name, stat)],
+                acquisitions,
+                "{}",
+                stat
+            );
+        }
+    }
+
+    #[test]
+    fn instrumented_mutex() {
+        let lock = Arc::new(InstrumentedMutex::new("test.mutex", 0));
+        assert_eq!(lock.name(), "test.mutex");
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(lock.with(|value| *value), 400);
+        assert_recorded("test.mutex", 401);
+    }
+
+    #[test]
+    fn instrumented_rwlock() {
+        let mut lock = InstrumentedRwLock::new("test.rwlock", vec![1]);
+        lock.write().push(2);
+        {
+            let first = lock.read();
+            let second = lock.read();
+            assert_eq!(*first, *second);
+        }
+        lock.get_mut().push(3);
+        assert_eq!(lock.into_inner(), vec![1, 2, 3]);
+        assert_recorded("test.rwlock", 3);
+    }
+}
@@ -11,8 +11,38 @@
 
 //! Crate extending functionalities of [std::sync]
 
-use parking_lot::Mutex as ParkingLotMutex;
+#[cfg(feature = "async")]
+mod async_lock;
+#[cfg(feature = "stats")]
+mod instrumented;
+mod mapped;
+mod sharded;
+mod timed;
+
+#[cfg(feature = "async")]
+pub use crate::async_lock::{AsyncLockExt, AsyncRwLockExt};
+#[cfg(feature = "stats")]
+pub use crate::instrumented::{
+    InstrumentedMutex, InstrumentedMutexGuard, InstrumentedRwLock, InstrumentedRwLockReadGuard,
+    InstrumentedRwLockWriteGuard,
+};
+pub use crate::mapped::{MappedReadGuard, MappedWriteGuard};
+pub use crate::sharded::ShardedLockMap;
+pub use crate::timed::{TryLockFor, TryRwLockFor};
+
+use crate::mapped::{ReadGuard, WriteGuard};
+use parking_lot::{Mutex as ParkingLotMutex, RwLock as ParkingLotRwLock};
 use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Error returned when a lock couldn't be acquired before the deadline
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("timed out after waiting {waited:?} for the lock")]
+pub struct LockTimeoutError {
+    /// How long the acquisition was attempted for
+    pub waited: Duration,
+}
 
 /// Extend functionality of [std::sync::Mutex]
 ///
@@ -70,6 +100,8 @@ impl<V> LockExt for ParkingLotMutex<V> {
 /// lock.with_write(|value| value.push("hello"));
 /// let hello = lock.with_read(|value| value.get(0).unwrap().to_owned());
 /// # assert_eq!(&hello, &"hello");
+/// let first = lock.read_map(|value| &value[0]);
+/// # assert_eq!(*first, "hello");
 /// ```
 pub trait RwLockExt {
     /// Value that is being held inside the lock
@@ -86,6 +118,20 @@ pub trait RwLockExt {
     fn with_write<Scope, Out>(&self, scope: Scope) -> Out
     where
         Scope: FnOnce(&mut Self::Value) -> Out;
+
+    /// Take the read lock and return a guard giving access only to the part
+    /// of the locked value returned by `project`
+    fn read_map<Project, T>(&self, project: Project) -> MappedReadGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&Self::Value) -> &T,
+        T: ?Sized;
+
+    /// Take the write lock and return a guard giving access only to the part
+    /// of the locked value returned by `project`
+    fn write_map<Project, T>(&self, project: Project) -> MappedWriteGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&mut Self::Value) -> &mut T,
+        T: ?Sized;
 }
 
 impl<V> RwLockExt for RwLock<V> {
@@ -106,6 +152,60 @@ impl<V> RwLockExt for RwLock<V> {
         let mut value = self.write().expect("lock poisoned");
         scope(&mut *value)
     }
+
+    fn read_map<Project, T>(&self, project: Project) -> MappedReadGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&Self::Value) -> &T,
+        T: ?Sized,
+    {
+        let guard = ReadGuard::Std(self.read().expect("lock poisoned"));
+        MappedReadGuard::new(guard, project)
+    }
+
+    fn write_map<Project, T>(&self, project: Project) -> MappedWriteGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&mut Self::Value) -> &mut T,
+        T: ?Sized,
+    {
+        let guard = WriteGuard::Std(self.write().expect("lock poisoned"));
+        MappedWriteGuard::new(guard, project)
+    }
+}
+
+impl<V> RwLockExt for ParkingLotRwLock<V> {
+    type Value = V;
+
+    fn with_read<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&Self::Value) -> Out,
+    {
+        let value = self.read();
+        scope(&*value)
+    }
+
+    fn with_write<Scope, Out>(&self, scope: Scope) -> Out
+    where
+        Scope: FnOnce(&mut Self::Value) -> Out,
+    {
+        let mut value = self.write();
+        scope(&mut *value)
+    }
+
+    fn read_map<Project, T>(&self, project: Project) -> MappedReadGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&Self::Value) -> &T,
+        T: ?Sized,
+    {
+        MappedReadGuard::new(ReadGuard::ParkingLot(self.read()), project)
+    }
+
+    fn write_map<Project, T>(&self, project: Project) -> MappedWriteGuard<'_, Self::Value, T>
+    where
+        Project: FnOnce(&mut Self::Value) -> &mut T,
+        T: ?Sized,
+    {
+        MappedWriteGuard::new(WriteGuard::ParkingLot(self.write()), project)
+    }
 }
 
 #[cfg(test)]
@@ -130,4 +230,24 @@ mod test {
         assert_eq!(vs.with_write(|vs| vs.pop()), Some("test"));
         assert_eq!(vs.with_read(|vs| vs.len()), 0);
     }
+
+    #[test]
+    fn rwlock_map() {
+        let lock = RwLock::new((vec!["a"], String::from("b")));
+        lock.write_map(|(_, s)| s).push('c');
+        {
+            let first = lock.read_map(|(vs, _)| vs.as_slice());
+            let second = lock.read_map(|(_, s)| s.as_str());
+            assert_eq!(&*first, &["a"]);
+            assert_eq!(&*second, "bc");
+            assert!(lock.try_write().is_err());
+        }
+        assert!(lock.try_write().is_ok());
+
+        let lock = parking_lot::RwLock::new(vec![1, 2, 3]);
+        lock.with_write(|vs| vs.push(4));
+        *lock.write_map(|vs| &mut vs[0]) = 0;
+        assert_eq!(*lock.read_map(|vs| &vs[..2]), [0, 2]);
+        assert_eq!(lock.with_read(|vs| vs.len()), 4);
+    }
 }
@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// Read guards of the supported locks
+pub(crate) enum ReadGuard<'a, V> {
+    Std(std::sync::RwLockReadGuard<'a, V>),
+    ParkingLot(parking_lot::RwLockReadGuard<'a, V>),
+}
+
+impl<'a, V> Deref for ReadGuard<'a, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            Self::Std(guard) => guard,
+            Self::ParkingLot(guard) => guard,
+        }
+    }
+}
+
+/// Write guards of the supported locks
+pub(crate) enum WriteGuard<'a, V> {
+    Std(std::sync::RwLockWriteGuard<'a, V>),
+    ParkingLot(parking_lot::RwLockWriteGuard<'a, V>),
+}
+
+impl<'a, V> Deref for WriteGuard<'a, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            Self::Std(guard) => guard,
+            Self::ParkingLot(guard) => guard,
+        }
+    }
+}
+
+impl<'a, V> DerefMut for WriteGuard<'a, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        match self {
+            Self::Std(guard) => guard,
+            Self::ParkingLot(guard) => guard,
+        }
+    }
+}
+
+/// A read guard giving access to a part of the locked value, returned by
+/// [RwLockExt::read_map](crate::RwLockExt::read_map). The read lock is held
+/// until the guard is dropped.
+pub struct MappedReadGuard<'a, V, T: ?Sized> {
+    // The value is owned by the lock, so it doesn't move with the guard and
+    // stays valid for as long as the guard is held
+    value: NonNull<T>,
+    _guard: ReadGuard<'a, V>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, V, T: ?Sized> MappedReadGuard<'a, V, T> {
+    pub(crate) fn new(guard: ReadGuard<'a, V>, project: impl FnOnce(&V) -> &T) -> Self {
+        let value = NonNull::from(project(&*guard));
+        Self {
+            value,
+            _guard: guard,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, V, T: ?Sized> Deref for MappedReadGuard<'a, V, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe as the read lock is held for the lifetime of self
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<'a, V, T: ?Sized + fmt::Debug> fmt::Debug for MappedReadGuard<'a, V, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A write guard giving access to a part of the locked value, returned by
+/// [RwLockExt::write_map](crate::RwLockExt::write_map). The write lock is
+/// held until the guard is dropped.
+pub struct MappedWriteGuard<'a, V, T: ?Sized> {
+    // See MappedReadGuard
+    value: NonNull<T>,
+    _guard: WriteGuard<'a, V>,
+    // Invariant in T, like &mut T
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, V, T: ?Sized> MappedWriteGuard<'a, V, T> {
+    pub(crate) fn new(
+        mut guard: WriteGuard<'a, V>,
+        project: impl FnOnce(&mut V) -> &mut T,
+    ) -> Self {
+        let value = NonNull::from(project(&mut *guard));
+        Self {
+            value,
+            _guard: guard,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, V, T: ?Sized> Deref for MappedWriteGuard<'a, V, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe as the write lock is held for the lifetime of self
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<'a, V, T: ?Sized> DerefMut for MappedWriteGuard<'a, V, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safe as the write lock is held for the lifetime of self, and the
+        // value can only be borrowed through &mut self
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<'a, V, T: ?Sized + fmt::Debug> fmt::Debug for MappedWriteGuard<'a, V, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
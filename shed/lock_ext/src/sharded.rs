@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Borrow;
+use std::collections::hash_map::{Entry, HashMap, RandomState};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+use parking_lot::Mutex;
+
+/// Number of shards of [ShardedLockMap::new]
+const DEFAULT_SHARDS: usize = 16;
+
+/// A concurrent hash map split into a fixed number of shards, each guarded by
+/// its own lock, so that threads accessing different keys rarely contend. An
+/// alternative to `Mutex<HashMap<K, V>>` for maps shared by many threads.
+///
+/// The values are only accessible inside closures, with the lock of the shard
+/// of the key being held. Don't access the map from inside these closures,
+/// as that might deadlock if the other key lives in the same shard.
+///
+/// # Example
+/// ```
+/// # use lock_ext::ShardedLockMap;
+/// let counts = ShardedLockMap::new();
+/// counts.with_entry("hello", |entry| *entry.or_insert(0) += 1);
+/// counts.with_entry("hello", |entry| *entry.or_insert(0) += 1);
+/// assert_eq!(counts.get("hello"), Some(2));
+/// ```
+pub struct ShardedLockMap<K, V> {
+    shards: Box<[Mutex<HashMap<K, V>>]>,
+    // Distinct from the hashers of the shards, so that the keys of a shard
+    // don't all share the same hash bits
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedLockMap<K, V> {
+    /// Create an empty map with the default number of shards
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Create an empty map with `shards` shards. More shards reduce the
+    /// contention at the cost of memory. Panics if `shards` is zero.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "ShardedLockMap needs at least one shard");
+        Self {
+            shards: (0..shards).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Mutex<HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    /// Call `scope` with the entry of `key`, to inspect, insert or modify its
+    /// value in place while the lock of its shard is held
+    pub fn with_entry<Scope, Out>(&self, key: K, scope: Scope) -> Out
+    where
+        Scope: FnOnce(Entry<'_, K, V>) -> Out,
+    {
+        let mut shard = self.shard(&key).lock();
+        scope(shard.entry(key))
+    }
+
+    /// Call `scope` with the value of `key` if present, without taking
+    /// ownership of the key
+    pub fn with_value<Q, Scope, Out>(&self, key: &Q, scope: Scope) -> Out
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Scope: FnOnce(Option<&mut V>) -> Out,
+    {
+        let mut shard = self.shard(key).lock();
+        scope(shard.get_mut(key))
+    }
+
+    /// A clone of the value of `key`
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.with_value(key, |value| value.cloned())
+    }
+
+    /// Whether the map contains `key`
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lock().contains_key(key)
+    }
+
+    /// Insert a value, returning the previous value of the key
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).lock().insert(key, value)
+    }
+
+    /// Remove a key, returning its value
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lock().remove(key)
+    }
+
+    /// Keep only the entries for which `keep` returns true. The shards are
+    /// locked one at a time, so this is not atomic with respect to
+    /// concurrent modifications of other shards.
+    pub fn retain(&self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            shard.lock().retain(&mut keep);
+        }
+    }
+
+    /// Number of entries in the map. As the shards are counted one at a time,
+    /// this is only a snapshot when the map is concurrently modified.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    /// Whether the map has no entries, see [ShardedLockMap::len]
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    /// Remove all the entries
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().clear();
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedLockMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> fmt::Debug for ShardedLockMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedLockMap")
+            .field("shards", &self.shards.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn sharded_lock_map() {
+        let map = Arc::new(ShardedLockMap::with_shards(4));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        map.with_entry(i % 100, |entry| *entry.or_insert(0) += 1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.len(), 100);
+        assert!((0..100).all(|i| map.get(&i) == Some(80)));
+
+        assert_eq!(map.remove(&0), Some(80));
+        assert!(!map.contains_key(&0));
+        map.with_value(&1, |value| *value.unwrap() = 0);
+        assert_eq!(map.get(&1), Some(0));
+        map.retain(|key, _| key % 2 == 0);
+        assert_eq!(map.len(), 49);
+        map.clear();
+        assert!(map.is_empty());
+    }
+}
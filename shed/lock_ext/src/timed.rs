@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::cmp::min;
+use std::sync::{
+    Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, TryLockResult,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::LockTimeoutError;
+
+/// Longest sleep between two acquisition attempts
+const MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Timed acquisition of [std::sync::Mutex], which only supports either
+/// blocking or failing right away. `parking_lot` locks have their own
+/// `try_lock_for` methods.
+///
+/// # Example
+/// ```
+/// # use std::sync::Mutex;
+/// # use std::time::Duration;
+/// # use lock_ext::TryLockFor;
+/// let lock = Mutex::new(0);
+/// let guard = lock.try_lock_for(Duration::from_millis(10)).unwrap();
+/// let err = lock.try_lock_for(Duration::from_millis(10)).unwrap_err();
+/// assert!(err.waited >= Duration::from_millis(10));
+/// ```
+pub trait TryLockFor<'a> {
+    /// Guard returned when the lock is acquired
+    type Guard;
+
+    /// Attempt to acquire the lock until `timeout` elapses
+    fn try_lock_for(&'a self, timeout: Duration) -> Result<Self::Guard, LockTimeoutError>;
+}
+
+impl<'a, V: 'a> TryLockFor<'a> for Mutex<V> {
+    type Guard = MutexGuard<'a, V>;
+
+    fn try_lock_for(&'a self, timeout: Duration) -> Result<Self::Guard, LockTimeoutError> {
+        retry_for(timeout, || self.try_lock())
+    }
+}
+
+/// Timed acquisition of [std::sync::RwLock], see [TryLockFor]
+pub trait TryRwLockFor<'a> {
+    /// Guard returned when the read lock is acquired
+    type ReadGuard;
+
+    /// Guard returned when the write lock is acquired
+    type WriteGuard;
+
+    /// Attempt to acquire the read lock until `timeout` elapses
+    fn try_read_for(&'a self, timeout: Duration) -> Result<Self::ReadGuard, LockTimeoutError>;
+
+    /// Attempt to acquire the write lock until `timeout` elapses
+    fn try_write_for(&'a self, timeout: Duration) -> Result<Self::WriteGuard, LockTimeoutError>;
+}
+
+impl<'a, V: 'a> TryRwLockFor<'a> for RwLock<V> {
+    type ReadGuard = RwLockReadGuard<'a, V>;
+    type WriteGuard = RwLockWriteGuard<'a, V>;
+
+    fn try_read_for(&'a self, timeout: Duration) -> Result<Self::ReadGuard, LockTimeoutError> {
+        retry_for(timeout, || self.try_read())
+    }
+
+    fn try_write_for(&'a self, timeout: Duration) -> Result<Self::WriteGuard, LockTimeoutError> {
+        retry_for(timeout, || self.try_write())
+    }
+}
+
+/// Call `attempt` until it succeeds or `timeout` elapses, sleeping with an
+/// exponential backoff in between
+fn retry_for<Guard>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> TryLockResult<Guard>,
+) -> Result<Guard, LockTimeoutError> {
+    let start = Instant::now();
+    let mut backoff = Duration::from_micros(1);
+    loop {
+        match attempt() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Poisoned(_)) => panic!("lock poisoned"),
+        }
+
+        let waited = start.elapsed();
+        if waited >= timeout {
+            return Err(LockTimeoutError { waited });
+        }
+        thread::sleep(min(backoff, timeout - waited));
+        backoff = min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn try_lock_for() {
+        let lock = Arc::new(Mutex::new(0));
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let holder = thread::spawn({
+            let lock = lock.clone();
+            move || {
+                let _guard = lock.lock().unwrap();
+                locked_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            }
+        });
+        locked_rx.recv().unwrap();
+
+        let err = lock.try_lock_for(Duration::from_millis(20)).unwrap_err();
+        assert!(err.waited >= Duration::from_millis(20));
+
+        release_tx.send(()).unwrap();
+        *lock.try_lock_for(Duration::from_secs(10)).unwrap() += 1;
+        holder.join().unwrap();
+        assert_eq!(*lock.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn try_rwlock_for() {
+        let lock = RwLock::new(0);
+        let read = lock.try_read_for(Duration::from_millis(1)).unwrap();
+        assert!(lock.try_read_for(Duration::from_millis(1)).is_ok());
+        assert!(lock.try_write_for(Duration::from_millis(5)).is_err());
+        drop(read);
+        assert!(lock.try_write_for(Duration::from_millis(1)).is_ok());
+    }
+}
@@ -7,77 +7,944 @@
  * of this source tree.
  */
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use fbinit::FacebookInit;
+use futures::future;
+use shared_error::anyhow::{IntoSharedError, SharedError};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::BufStream;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::codec::{self, Compression, MemcacheCodec, RAW_CODEC_ID};
+use crate::protocol::{self, CasResult, Item, StoreCommand, StoreItem};
 
 /// Type of value returned from memcache
 pub type MemcacheGetType = Vec<u8>;
 /// Type of value that can be written to memcache
 pub type MemcacheSetType = Bytes;
 
-/// Client for Memcache
-#[derive(Clone, Debug)]
-pub struct MemcacheClient;
+/// Environment variable listing the memcached servers [MemcacheClient::new]
+/// connects to, as comma separated `host:port` addresses
+pub const MEMCACHE_SERVERS_ENV: &str = "MEMCACHE_SERVERS";
+
+/// How long a single memcache operation can take, connecting included, by
+/// default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most keys sent in a single get command by [MemcacheClient::get_multi]
+const MAX_KEYS_PER_GET: usize = 100;
+
+/// Identifies the version of a value read with [MemcacheClient::gets], to
+/// only replace it with [MemcacheClient::cas] if it wasn't modified since
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CasToken(u64);
+
+/// Result of a batch operation, with an entry for each of the keys. Failing
+/// to talk to a server fails all the keys stored on it with the same error.
+pub type MemcacheMultiResult<T> = HashMap<String, Result<T, SharedError>>;
+
+type Connection = BufStream<TcpStream>;
+type OpFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// A memcached server, with a lazily established connection that is
+/// re-established after any failure
+struct Server {
+    addr: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Server {
+    async fn run<T>(
+        &self,
+        timeout: Duration,
+        op: impl for<'a> FnOnce(&'a mut Connection) -> OpFuture<'a, T>,
+    ) -> Result<T> {
+        // Waiting behind the other operations on this server counts towards
+        // the timeout too
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut connection = match tokio::time::timeout_at(deadline, self.connection.lock()).await {
+            Ok(connection) => connection,
+            Err(_) => bail!(
+                "Timed out after {:?} waiting for the connection to memcache server {}",
+                timeout,
+                self.addr
+            ),
+        };
+        let result = tokio::time::timeout_at(deadline, async {
+            let connection = match &mut *connection {
+                Some(connection) => connection,
+                connection @ None => {
+                    let stream = TcpStream::connect(&self.addr).await?;
+                    stream.set_nodelay(true)?;
+                    connection.insert(BufStream::new(stream))
+                }
+            };
+            op(connection).await
+        })
+        .await;
+
+        let result = match result {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Timed out after {:?}", timeout)),
+        };
+        if result.is_err() {
+            // The state of the connection is unknown, e.g. a response might
+            // still be in flight, so start over with a new one
+            *connection = None;
+        }
+        result.with_context(|| format!("While talking to memcache server {}", self.addr))
+    }
+}
+
+/// Client for Memcache, talking the memcached text protocol to a set of
+/// servers that the keys are spread over. A client without servers is a no-op
+/// that never finds any value.
+///
+/// Values can be stored as raw bytes or serialized with a [MemcacheCodec],
+/// and compressed if the client is configured with
+/// [MemcacheClient::with_compression]. Compressed values are decompressed
+/// transparently by any client.
+#[derive(Clone)]
+pub struct MemcacheClient {
+    servers: Arc<[Server]>,
+    timeout: Duration,
+    compression: Option<Compression>,
+}
 
 impl MemcacheClient {
-    /// Return a new instance of MemcacheClient.
-    pub fn new(_fb: FacebookInit) -> Result<Self> {
-        Ok(MemcacheClient)
+    /// Return a new instance of MemcacheClient, connecting to the servers
+    /// listed in [MEMCACHE_SERVERS_ENV] or a no-op client if it isn't set.
+    pub fn new(fb: FacebookInit) -> Result<Self> {
+        Self::from_servers_var(fb, env::var(MEMCACHE_SERVERS_ENV))
+    }
+
+    /// Same as [MemcacheClient::new], given the value of [MEMCACHE_SERVERS_ENV]
+    fn from_servers_var(fb: FacebookInit, var: Result<String, env::VarError>) -> Result<Self> {
+        match var {
+            Ok(servers) => Self::with_servers(
+                fb,
+                servers.split(',').map(str::trim).filter(|s| !s.is_empty()),
+            ),
+            Err(env::VarError::NotPresent) => Ok(Self::with_no_servers()),
+            Err(e) => Err(e).with_context(|| format!("While reading {}", MEMCACHE_SERVERS_ENV)),
+        }
+    }
+
+    /// Return a new instance of MemcacheClient spreading the keys over the
+    /// given `host:port` servers. Connections are established on first use.
+    pub fn with_servers(
+        _fb: FacebookInit,
+        servers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self> {
+        let servers: Arc<[Server]> = servers
+            .into_iter()
+            .map(|addr| Server {
+                addr: addr.into(),
+                connection: Mutex::new(None),
+            })
+            .collect();
+        if servers.is_empty() {
+            bail!("No memcache servers given");
+        }
+        Ok(Self {
+            servers,
+            timeout: DEFAULT_TIMEOUT,
+            compression: None,
+        })
+    }
+
+    fn with_no_servers() -> Self {
+        Self {
+            servers: Arc::new([]),
+            timeout: DEFAULT_TIMEOUT,
+            compression: None,
+        }
+    }
+
+    /// Set how long a single operation can take, see [DEFAULT_TIMEOUT]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Compress the values larger than `threshold` bytes with zstd at the
+    /// given `level` before storing them, unless that doesn't make them
+    /// smaller. The ratios achieved are exported as
+    /// `memcache.compression_ratio_pct`.
+    pub fn with_compression(mut self, threshold: usize, level: i32) -> Self {
+        self.compression = Some(Compression { threshold, level });
+        self
+    }
+
+    /// Index of the server `key` is stored on, None for a no-op client
+    fn server_index(&self, key: &str) -> Option<usize> {
+        if self.servers.is_empty() {
+            return None;
+        }
+        // A stable hash, so that all the clients agree on where a key is stored
+        let hash = hash_memo::fnv1a(key.as_bytes());
+        Some((hash % self.servers.len() as u64) as usize)
+    }
+
+    /// The server `key` is stored on, None for a no-op client
+    fn server(&self, key: &str) -> Option<&Server> {
+        self.server_index(key).map(|index| &self.servers[index])
+    }
+
+    /// Split `entries` by the server their key is stored on, reporting the
+    /// invalid keys and, for a no-op client, the `noop` result in `results`
+    fn group_by_server<T, Out>(
+        &self,
+        entries: impl IntoIterator<Item = (String, T)>,
+        results: &mut MemcacheMultiResult<Out>,
+        noop: impl Fn() -> Out,
+    ) -> Vec<(&Server, Vec<(String, T)>)> {
+        let mut groups: Vec<Vec<_>> = self.servers.iter().map(|_| Vec::new()).collect();
+        for (key, value) in entries {
+            if let Err(e) = protocol::validate_key(&key) {
+                results.insert(key, Err(e.shared_error()));
+                continue;
+            }
+            match self.server_index(&key) {
+                Some(index) => groups[index].push((key, value)),
+                None => {
+                    results.insert(key, Ok(noop()));
+                }
+            }
+        }
+        self.servers
+            .iter()
+            .zip(groups)
+            .filter(|(_, group)| !group.is_empty())
+            .collect()
     }
 
     /// Gets the Memcache value under `key`
-    pub async fn get<K>(&self, _key: K) -> Result<Option<MemcacheGetType>>
+    pub async fn get<K>(&self, key: K) -> Result<Option<MemcacheGetType>>
     where
         K: AsRef<str>,
     {
-        Ok(None)
+        Ok(self
+            .get_item(key.as_ref())
+            .await?
+            .map(|(_codec, data)| data))
+    }
+
+    /// Gets the Memcache value under `key`, decoded with `codec`. Fails if the
+    /// value was written with another codec.
+    pub async fn get_decoded<T, C, K>(&self, codec: &C, key: K) -> Result<Option<T>>
+    where
+        C: MemcacheCodec<T>,
+        K: AsRef<str>,
+    {
+        self.get_item(key.as_ref())
+            .await?
+            .map(|(id, data)| codec::decode(codec, id, &data))
+            .transpose()
+    }
+
+    /// The data of the value under `key` and the id of its codec
+    async fn get_item(&self, key: &str) -> Result<Option<(u8, MemcacheGetType)>> {
+        protocol::validate_key(key)?;
+        let server = match self.server(key) {
+            Some(server) => server,
+            None => return Ok(None),
+        };
+        let key = key.to_owned();
+        let items = server
+            .run(self.timeout, move |conn| {
+                Box::pin(async move { protocol::get(conn, &[&key]).await })
+            })
+            .await?;
+        items.into_iter().next().map(codec::unpack).transpose()
+    }
+
+    async fn store(
+        &self,
+        command: StoreCommand,
+        key: &str,
+        codec: u8,
+        val: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        protocol::validate_key(key)?;
+        let server = match self.server(key) {
+            Some(server) => server,
+            None => return Ok(true),
+        };
+        let key = key.to_owned();
+        let exptime = protocol::exptime(ttl);
+        let (flags, val) = codec::pack(self.compression, codec, val)?;
+        server
+            .run(self.timeout, move |conn| {
+                Box::pin(async move {
+                    let item = StoreItem {
+                        key: &key,
+                        flags,
+                        exptime,
+                        data: &val,
+                    };
+                    protocol::store(conn, command, &item).await
+                })
+            })
+            .await
     }
 
     /// Sets the Memcache value under `key` to `val`
-    pub async fn set<K, V>(&self, _key: K, _val: V) -> Result<()>
+    pub async fn set<K, V>(&self, key: K, val: V) -> Result<()>
     where
         K: AsRef<str>,
         MemcacheSetType: From<V>,
     {
+        self.store(
+            StoreCommand::Set,
+            key.as_ref(),
+            RAW_CODEC_ID,
+            MemcacheSetType::from(val).to_vec(),
+            None,
+        )
+        .await?;
         Ok(())
     }
 
     /// Sets the Memcache value under `key` to `val` with the given expiration
-    pub async fn set_with_ttl<K, V>(&self, _key: K, _val: V, _exp: Duration) -> Result<()>
+    pub async fn set_with_ttl<K, V>(&self, key: K, val: V, exp: Duration) -> Result<()>
     where
         K: AsRef<str>,
         MemcacheSetType: From<V>,
     {
+        self.store(
+            StoreCommand::Set,
+            key.as_ref(),
+            RAW_CODEC_ID,
+            MemcacheSetType::from(val).to_vec(),
+            Some(exp),
+        )
+        .await?;
         Ok(())
     }
 
     /// Similar to `set`, but if the value is already present in Memcache it won't overwrite it.
     /// A boolean value is returned to say if the write was successful (true) or if a value was
     /// already present (false)
-    pub async fn add<K, V>(&self, _key: K, _val: V) -> Result<bool>
+    pub async fn add<K, V>(&self, key: K, val: V) -> Result<bool>
     where
         K: AsRef<str>,
         MemcacheSetType: From<V>,
     {
-        Ok(true)
+        self.store(
+            StoreCommand::Add,
+            key.as_ref(),
+            RAW_CODEC_ID,
+            MemcacheSetType::from(val).to_vec(),
+            None,
+        )
+        .await
     }
 
     /// `add` equivalent of the `set_with_ttl` method
-    pub async fn add_with_ttl<K, V>(&self, _key: K, _val: V, _exp: Duration) -> Result<bool>
+    pub async fn add_with_ttl<K, V>(&self, key: K, val: V, exp: Duration) -> Result<bool>
+    where
+        K: AsRef<str>,
+        MemcacheSetType: From<V>,
+    {
+        self.store(
+            StoreCommand::Add,
+            key.as_ref(),
+            RAW_CODEC_ID,
+            MemcacheSetType::from(val).to_vec(),
+            Some(exp),
+        )
+        .await
+    }
+
+    /// Sets the Memcache value under `key` to `val` encoded with `codec`
+    pub async fn set_encoded<T, C, K>(&self, codec: &C, key: K, val: &T) -> Result<()>
+    where
+        C: MemcacheCodec<T>,
+        K: AsRef<str>,
+    {
+        let data = codec.encode(val)?;
+        self.store(StoreCommand::Set, key.as_ref(), codec.id(), data, None)
+            .await?;
+        Ok(())
+    }
+
+    /// `set_encoded` equivalent of the `set_with_ttl` method
+    pub async fn set_encoded_with_ttl<T, C, K>(
+        &self,
+        codec: &C,
+        key: K,
+        val: &T,
+        exp: Duration,
+    ) -> Result<()>
+    where
+        C: MemcacheCodec<T>,
+        K: AsRef<str>,
+    {
+        let data = codec.encode(val)?;
+        self.store(StoreCommand::Set, key.as_ref(), codec.id(), data, Some(exp))
+            .await?;
+        Ok(())
+    }
+
+    /// `add` equivalent of the `set_encoded` method
+    pub async fn add_encoded<T, C, K>(&self, codec: &C, key: K, val: &T) -> Result<bool>
+    where
+        C: MemcacheCodec<T>,
+        K: AsRef<str>,
+    {
+        let data = codec.encode(val)?;
+        self.store(StoreCommand::Add, key.as_ref(), codec.id(), data, None)
+            .await
+    }
+
+    /// Like `get`, also returning a token to pass to `cas` to replace the
+    /// value only if it wasn't modified in the meantime
+    pub async fn gets<K>(&self, key: K) -> Result<Option<(MemcacheGetType, CasToken)>>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        protocol::validate_key(key)?;
+        let server = match self.server(key) {
+            Some(server) => server,
+            None => return Ok(None),
+        };
+        let key = key.to_owned();
+        let items = server
+            .run(self.timeout, move |conn| {
+                Box::pin(async move { protocol::gets(conn, &[&key]).await })
+            })
+            .await?;
+        items
+            .into_iter()
+            .next()
+            .map(|item| match item.cas {
+                Some(cas) => Ok((codec::unpack(item)?.1, CasToken(cas))),
+                None => bail!("memcache didn't return the version of {}", item.key),
+            })
+            .transpose()
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        val: MemcacheSetType,
+        token: CasToken,
+        ttl: Option<Duration>,
+    ) -> Result<CasResult> {
+        protocol::validate_key(key)?;
+        let server = match self.server(key) {
+            Some(server) => server,
+            None => return Ok(CasResult::NotFound),
+        };
+        let key = key.to_owned();
+        let exptime = protocol::exptime(ttl);
+        let (flags, val) = codec::pack(self.compression, RAW_CODEC_ID, val.to_vec())?;
+        server
+            .run(self.timeout, move |conn| {
+                Box::pin(async move {
+                    let item = StoreItem {
+                        key: &key,
+                        flags,
+                        exptime,
+                        data: &val,
+                    };
+                    protocol::cas(conn, &item, token.0).await
+                })
+            })
+            .await
+    }
+
+    /// Sets the Memcache value under `key` to `val` only if it wasn't modified since `token` was
+    /// returned by `gets`, allowing read-modify-write cycles without losing concurrent updates
+    pub async fn cas<K, V>(&self, key: K, val: V, token: CasToken) -> Result<CasResult>
+    where
+        K: AsRef<str>,
+        MemcacheSetType: From<V>,
+    {
+        self.compare_and_swap(key.as_ref(), val.into(), token, None)
+            .await
+    }
+
+    /// `cas` equivalent of the `set_with_ttl` method
+    pub async fn cas_with_ttl<K, V>(
+        &self,
+        key: K,
+        val: V,
+        token: CasToken,
+        exp: Duration,
+    ) -> Result<CasResult>
     where
         K: AsRef<str>,
         MemcacheSetType: From<V>,
     {
-        Ok(true)
+        self.compare_and_swap(key.as_ref(), val.into(), token, Some(exp))
+            .await
     }
 
     /// Removes the value under `key`.
-    pub async fn del<K>(&self, _key: K) -> Result<()>
+    pub async fn del<K>(&self, key: K) -> Result<()>
     where
         K: AsRef<str>,
     {
+        let key = key.as_ref();
+        protocol::validate_key(key)?;
+        let server = match self.server(key) {
+            Some(server) => server,
+            None => return Ok(()),
+        };
+        let key = key.to_owned();
+        server
+            .run(self.timeout, move |conn| {
+                Box::pin(async move { protocol::delete(conn, &key).await })
+            })
+            .await?;
         Ok(())
     }
+
+    /// Gets the Memcache values under `keys`, with a single round trip to
+    /// each of the servers the keys are stored on. The result has an entry
+    /// for each of the keys, None for the missing ones.
+    pub async fn get_multi<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> MemcacheMultiResult<Option<MemcacheGetType>>
+    where
+        K: AsRef<str>,
+    {
+        let mut results = HashMap::new();
+        let keys = keys.into_iter().map(|key| (key.as_ref().to_owned(), ()));
+        let groups = self.group_by_server(keys, &mut results, || None);
+
+        let replies = future::join_all(groups.into_iter().map(|(server, keys)| async move {
+            let keys: Vec<_> = keys.into_iter().map(|(key, ())| key).collect();
+            let reply = server
+                .run(self.timeout, {
+                    let keys = keys.clone();
+                    move |conn| {
+                        Box::pin(async move {
+                            let mut items = Vec::new();
+                            for chunk in keys.chunks(MAX_KEYS_PER_GET) {
+                                let chunk: Vec<_> = chunk.iter().map(String::as_str).collect();
+                                items.extend(protocol::get(conn, &chunk).await?);
+                            }
+                            Ok(items)
+                        })
+                    }
+                })
+                .await;
+            (keys, reply)
+        }))
+        .await;
+
+        for (keys, reply) in replies {
+            match reply {
+                Ok(items) => {
+                    let mut items: HashMap<String, Item> = items
+                        .into_iter()
+                        .map(|item| (item.key.clone(), item))
+                        .collect();
+                    for key in keys {
+                        let value = items
+                            .remove(&key)
+                            .map(|item| codec::unpack(item).map(|(_codec, data)| data))
+                            .transpose();
+                        results.insert(key, value.shared_error());
+                    }
+                }
+                Err(e) => {
+                    let e = e.shared_error();
+                    results.extend(keys.into_iter().map(|key| (key, Err(e.clone()))));
+                }
+            }
+        }
+        results
+    }
+
+    /// Sets the Memcache values under the given keys, pipelining the writes
+    /// to each of the servers. The result has an entry for each of the keys.
+    pub async fn set_multi<K, V>(
+        &self,
+        items: impl IntoIterator<Item = (K, V)>,
+    ) -> MemcacheMultiResult<()>
+    where
+        K: AsRef<str>,
+        MemcacheSetType: From<V>,
+    {
+        let mut results = HashMap::new();
+        let mut items_to_store = Vec::new();
+        for (key, val) in items {
+            let key = key.as_ref().to_owned();
+            let val = MemcacheSetType::from(val).to_vec();
+            match codec::pack(self.compression, RAW_CODEC_ID, val) {
+                Ok(packed) => items_to_store.push((key, packed)),
+                Err(e) => {
+                    results.insert(key, Err(e.shared_error()));
+                }
+            }
+        }
+        let groups = self.group_by_server(items_to_store, &mut results, || ());
+
+        let replies = future::join_all(groups.into_iter().map(|(server, items)| async move {
+            let keys: Vec<_> = items.iter().map(|(key, _)| key.clone()).collect();
+            let reply = server
+                .run(self.timeout, move |conn| {
+                    Box::pin(async move {
+                        let items: Vec<_> = items
+                            .iter()
+                            .map(|(key, (flags, val))| StoreItem {
+                                key,
+                                flags: *flags,
+                                exptime: 0,
+                                data: val,
+                            })
+                            .collect();
+                        protocol::store_multi(conn, StoreCommand::Set, &items).await
+                    })
+                })
+                .await;
+            (keys, reply)
+        }))
+        .await;
+
+        for (keys, reply) in replies {
+            collect_replies(&mut results, keys, reply, |_stored| ());
+        }
+        results
+    }
+
+    /// Removes the values under `keys`, pipelining the deletes to each of the
+    /// servers. The result has an entry for each of the keys.
+    pub async fn delete_multi<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> MemcacheMultiResult<()>
+    where
+        K: AsRef<str>,
+    {
+        let mut results = HashMap::new();
+        let keys = keys.into_iter().map(|key| (key.as_ref().to_owned(), ()));
+        let groups = self.group_by_server(keys, &mut results, || ());
+
+        let replies = future::join_all(groups.into_iter().map(|(server, keys)| async move {
+            let keys: Vec<_> = keys.into_iter().map(|(key, ())| key).collect();
+            let reply = server
+                .run(self.timeout, {
+                    let keys = keys.clone();
+                    move |conn| {
+                        Box::pin(async move {
+                            let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+                            protocol::delete_multi(conn, &keys).await
+                        })
+                    }
+                })
+                .await;
+            (keys, reply)
+        }))
+        .await;
+
+        for (keys, reply) in replies {
+            collect_replies(&mut results, keys, reply, |_deleted| ());
+        }
+        results
+    }
+}
+
+/// Report the per-key replies of a pipelined operation, or the failure of the
+/// whole operation for all its keys
+fn collect_replies<R, Out>(
+    results: &mut MemcacheMultiResult<Out>,
+    keys: Vec<String>,
+    reply: Result<Vec<Result<R>>>,
+    convert: impl Fn(R) -> Out,
+) {
+    match reply {
+        Ok(replies) => {
+            for (key, reply) in keys.into_iter().zip(replies) {
+                results.insert(key, reply.map(&convert).shared_error());
+            }
+        }
+        Err(e) => {
+            let e = e.shared_error();
+            results.extend(keys.into_iter().map(|key| (key, Err(e.clone()))));
+        }
+    }
+}
+
+impl fmt::Debug for MemcacheClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let servers: Vec<_> = self.servers.iter().map(|s| &s.addr).collect();
+        f.debug_struct("MemcacheClient")
+            .field("servers", &servers)
+            .field("timeout", &self.timeout)
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+/// A minimal in-memory memcached server for the tests of this crate
+#[cfg(test)]
+pub(crate) mod test_server {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+    use tokio::net::TcpListener;
+
+    /// Values stored by the server, with their flags and unique version
+    pub type Store = Arc<Mutex<HashMap<String, (u32, u64, Vec<u8>)>>>;
+
+    static NEXT_CAS: AtomicU64 = AtomicU64::new(1);
+
+    /// Start a server, returning its address and its content
+    pub async fn start() -> (String, Store) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let store = Store::default();
+        tokio::spawn({
+            let store = store.clone();
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve(BufStream::new(stream), store.clone()));
+                }
+            }
+        });
+        (addr, store)
+    }
+
+    async fn serve(mut stream: BufStream<tokio::net::TcpStream>, store: Store) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                return;
+            }
+            let parts: Vec<_> = line.trim_end().split(' ').collect();
+            let mut response = Vec::new();
+            match parts[0] {
+                "get" | "gets" => {
+                    for key in &parts[1..] {
+                        if let Some((flags, cas, data)) = store.lock().unwrap().get(*key) {
+                            let mut header = format!("VALUE {} {} {}", key, flags, data.len());
+                            if parts[0] == "gets" {
+                                header.push_str(&format!(" {}", cas));
+                            }
+                            response.extend(header.bytes());
+                            response.extend(b"\r\n");
+                            response.extend(data);
+                            response.extend(b"\r\n");
+                        }
+                    }
+                    response.extend(b"END\r\n");
+                }
+                "set" | "add" | "cas" => {
+                    let len: usize = parts[4].parse().unwrap();
+                    let mut data = vec![0; len + 2];
+                    stream.read_exact(&mut data).await.unwrap();
+                    data.truncate(len);
+                    let mut store = store.lock().unwrap();
+                    let current = store.get(parts[1]).map(|(_, cas, _)| *cas);
+                    let reply: &[u8] = match (parts[0], current) {
+                        ("add", Some(_)) => b"NOT_STORED\r\n",
+                        ("cas", None) => b"NOT_FOUND\r\n",
+                        ("cas", Some(cas)) if cas.to_string() != parts[5] => b"EXISTS\r\n",
+                        _ => {
+                            let cas = NEXT_CAS.fetch_add(1, Ordering::Relaxed);
+                            store.insert(
+                                parts[1].to_owned(),
+                                (parts[2].parse().unwrap(), cas, data),
+                            );
+                            b"STORED\r\n"
+                        }
+                    };
+                    response.extend(reply);
+                }
+                "delete" => match store.lock().unwrap().remove(parts[1]) {
+                    Some(_) => response.extend(b"DELETED\r\n"),
+                    None => response.extend(b"NOT_FOUND\r\n"),
+                },
+                _ => response.extend(b"ERROR\r\n"),
+            }
+            stream.write_all(&response).await.unwrap();
+            stream.flush().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_client(fb: FacebookInit) {
+        let (addr, store) = test_server::start().await;
+        let client = MemcacheClient::with_servers(fb, [addr]).unwrap();
+
+        assert_eq!(client.get("key").await.unwrap(), None);
+        client.set("key", b"value".to_vec()).await.unwrap();
+        assert_eq!(client.get("key").await.unwrap(), Some(b"value".to_vec()));
+        assert!(!client.add("key", b"other".to_vec()).await.unwrap());
+        assert!(client
+            .add_with_ttl("new", b"other".to_vec(), Duration::from_secs(10))
+            .await
+            .unwrap());
+        assert_eq!(store.lock().unwrap().len(), 2);
+
+        client.del("key").await.unwrap();
+        assert_eq!(client.get("key").await.unwrap(), None);
+        assert!(client.get("bad key").await.is_err());
+    }
+
+    #[fbinit::test]
+    async fn test_codecs_and_compression(fb: FacebookInit) {
+        use crate::{AbomonationCodec, JsonCodec};
+
+        let (addr, store) = test_server::start().await;
+        let client = MemcacheClient::with_servers(fb, [addr])
+            .unwrap()
+            .with_compression(100, 3);
+        let abomonation = unsafe { AbomonationCodec::new() };
+
+        let value: Vec<u64> = (0..100).collect();
+        client
+            .set_encoded(&JsonCodec, "json", &value)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_decoded(&JsonCodec, "json").await.unwrap(),
+            Some(value.clone())
+        );
+        assert!(client
+            .get_decoded::<Vec<u64>, _, _>(&abomonation, "json")
+            .await
+            .is_err());
+        assert!(!client
+            .add_encoded(&abomonation, "json", &value)
+            .await
+            .unwrap());
+
+        let large = vec![b'a'; 1000];
+        client.set("raw", large.clone()).await.unwrap();
+        assert!(store.lock().unwrap()["raw"].2.len() < 100);
+        assert_eq!(client.get("raw").await.unwrap(), Some(large.clone()));
+        assert_eq!(
+            *client.get_multi(["raw"]).await["raw"].as_ref().unwrap(),
+            Some(large)
+        );
+    }
+
+    #[fbinit::test]
+    async fn test_cas(fb: FacebookInit) {
+        let (addr, _) = test_server::start().await;
+        let client = MemcacheClient::with_servers(fb, [addr]).unwrap();
+
+        assert_eq!(client.gets("key").await.unwrap(), None);
+        client.set("key", b"1".to_vec()).await.unwrap();
+        let (value, token) = client.gets("key").await.unwrap().unwrap();
+        assert_eq!(value, b"1");
+
+        assert_eq!(
+            client.cas("key", b"2".to_vec(), token).await.unwrap(),
+            CasResult::Stored
+        );
+        // The value changed since it was read
+        assert_eq!(
+            client.cas("key", b"3".to_vec(), token).await.unwrap(),
+            CasResult::Exists
+        );
+        assert_eq!(client.get("key").await.unwrap(), Some(b"2".to_vec()));
+
+        client.del("key").await.unwrap();
+        assert_eq!(
+            client
+                .cas_with_ttl("key", b"4".to_vec(), token, Duration::from_secs(10))
+                .await
+                .unwrap(),
+            CasResult::NotFound
+        );
+    }
+
+    #[fbinit::test]
+    async fn test_multi(fb: FacebookInit) {
+        let (first, _) = test_server::start().await;
+        let (second, _) = test_server::start().await;
+        let client = MemcacheClient::with_servers(fb, [first, second]).unwrap();
+
+        let keys: Vec<_> = (0..250).map(|i| format!("key{}", i)).collect();
+        let results = client
+            .set_multi(keys.iter().map(|key| (key, key.clone().into_bytes())))
+            .await;
+        assert_eq!(results.len(), 250);
+        assert!(results.values().all(Result::is_ok));
+
+        let results = client.delete_multi(["key0", "bad key"]).await;
+        assert!(results["key0"].is_ok());
+        assert!(results["bad key"].is_err());
+
+        let results = client.get_multi(&keys).await;
+        assert_eq!(results.len(), 250);
+        assert_eq!(*results["key0"].as_ref().unwrap(), None);
+        for key in &keys[1..] {
+            assert_eq!(
+                results[key].as_ref().unwrap().as_deref(),
+                Some(key.as_bytes())
+            );
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_unreachable(fb: FacebookInit) {
+        // Nothing listens on the discard port
+        let client = MemcacheClient::with_servers(fb, ["127.0.0.1:9"]).unwrap();
+        assert!(client.get("key").await.is_err());
+        let results = client.get_multi(["a", "b"]).await;
+        assert!(results["a"].is_err() && results["b"].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_waiting_for_connection() {
+        let server = Server {
+            addr: "127.0.0.1:9".to_owned(),
+            connection: Mutex::new(None),
+        };
+        let _busy = server.connection.lock().await;
+        let result = server
+            .run(Duration::from_millis(10), |_| Box::pin(async { Ok(()) }))
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[fbinit::test]
+    async fn test_noop(fb: FacebookInit) {
+        let client = MemcacheClient::from_servers_var(fb, Err(env::VarError::NotPresent)).unwrap();
+        client.set("key", b"value".to_vec()).await.unwrap();
+        assert_eq!(client.get("key").await.unwrap(), None);
+        assert_eq!(
+            *client.get_multi(["key"]).await["key"].as_ref().unwrap(),
+            None
+        );
+    }
+
+    #[fbinit::test]
+    fn test_servers_var(fb: FacebookInit) {
+        let client = MemcacheClient::from_servers_var(fb, Ok("a:1, b:2,".to_owned())).unwrap();
+        let servers: Vec<_> = client.servers.iter().map(|s| s.addr.as_str()).collect();
+        assert_eq!(servers, vec!["a:1", "b:2"]);
+
+        assert!(MemcacheClient::from_servers_var(fb, Ok(" , ".to_owned())).is_err());
+        let not_unicode = env::VarError::NotUnicode(Default::default());
+        assert!(MemcacheClient::from_servers_var(fb, Err(not_unicode)).is_err());
+    }
 }
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Serialization of typed values and compression of the stored bytes. Both
+//! are recorded in the flags memcached keeps along with each value, so that
+//! readers know how to decode whatever they find.
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use stats::prelude::*;
+
+use crate::protocol::Item;
+
+define_stats! {
+    prefix = "memcache";
+    compressed: timeseries(Rate, Sum),
+    compression_ratio_pct: histogram(1, 0, 100, Average, Count; P 50; P 99),
+    compression_saved_bytes: timeseries(Sum),
+}
+
+/// Flag of the values compressed with zstd
+const FLAG_ZSTD: u32 = 1;
+/// The id of the codec of a value is stored in the second byte of its flags
+const CODEC_SHIFT: u32 = 8;
+
+/// Id of the values stored as raw bytes, i.e. not through a [MemcacheCodec]
+pub(crate) const RAW_CODEC_ID: u8 = 0;
+
+/// Serialization format of the values of type `T` stored with
+/// [crate::MemcacheClient::set_encoded]
+pub trait MemcacheCodec<T> {
+    /// Identifier of the format, stored with the values so that values that
+    /// were written in another format are rejected instead of misread. 0 is
+    /// reserved for raw bytes.
+    fn id(&self) -> u8;
+
+    /// Serialize `value`
+    fn encode(&self, value: &T) -> Result<Vec<u8>>;
+
+    /// Deserialize a value serialized by [MemcacheCodec::encode]
+    fn decode(&self, data: &[u8]) -> Result<T>;
+}
+
+/// Stores values as JSON, readable by clients in any language
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl<T: Serialize + DeserializeOwned> MemcacheCodec<T> for JsonCodec {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).context("While decoding JSON value from memcache")
+    }
+}
+
+/// Stores the in-memory representation of values with [abomonation], which is
+/// the fastest to decode but is only readable by binaries that have the same
+/// definition of `T`. Values written from another type or that don't decode
+/// exactly are rejected, see [abomonation_ext].
+#[derive(Clone, Copy, Debug)]
+pub struct AbomonationCodec {
+    _trusted: (),
+}
+
+impl AbomonationCodec {
+    /// Create the codec.
+    ///
+    /// # Safety
+    ///
+    /// Decoding trusts the bytes found in memcache, see
+    /// [abomonation_ext::decode]: the keys read with this codec must only be
+    /// written by binaries with the same definition of `T`, with this codec.
+    pub unsafe fn new() -> Self {
+        Self { _trusted: () }
+    }
+}
+
+impl<T: abomonation::Abomonation + Clone> MemcacheCodec<T> for AbomonationCodec {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(abomonation_ext::encode(value))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T> {
+        // SAFETY: the codec was created by a caller vouching for the values
+        unsafe { abomonation_ext::decode(data) }.context("While decoding value from memcache")
+    }
+}
+
+/// Compression of the values larger than `threshold` bytes, see
+/// [crate::MemcacheClient::with_compression]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Compression {
+    pub threshold: usize,
+    pub level: i32,
+}
+
+/// Compress `data` if configured to and worth it, returning the flags to store
+/// it with
+pub(crate) fn pack(
+    compression: Option<Compression>,
+    codec: u8,
+    data: Vec<u8>,
+) -> Result<(u32, Vec<u8>)> {
+    let flags = u32::from(codec) << CODEC_SHIFT;
+    let compression = match compression {
+        Some(compression) if data.len() > compression.threshold => compression,
+        _ => return Ok((flags, data)),
+    };
+
+    let compressed = zstd::stream::encode_all(data.as_slice(), compression.level)
+        .context("While compressing value for memcache")?;
+    STATS::compression_ratio_pct.add_value((compressed.len() * 100 / data.len()) as i64);
+    if compressed.len() >= data.len() {
+        return Ok((flags, data));
+    }
+    STATS::compressed.add_value(1);
+    STATS::compression_saved_bytes.add_value((data.len() - compressed.len()) as i64);
+    Ok((flags | FLAG_ZSTD, compressed))
+}
+
+/// Decompress the data of `item` if needed, returning it with the id of the
+/// codec it was written with
+pub(crate) fn unpack(item: Item) -> Result<(u8, Vec<u8>)> {
+    let codec = (item.flags >> CODEC_SHIFT) as u8;
+    if item.flags & FLAG_ZSTD == 0 {
+        return Ok((codec, item.data));
+    }
+    let data = zstd::stream::decode_all(item.data.as_slice())
+        .with_context(|| format!("While decompressing value of {} from memcache", item.key))?;
+    Ok((codec, data))
+}
+
+/// Decode data unpacked by [unpack] with `codec`
+pub(crate) fn decode<T>(codec: &impl MemcacheCodec<T>, id: u8, data: &[u8]) -> Result<T> {
+    if id != codec.id() {
+        bail!(
+            "Value from memcache was written with codec {}, expected {}",
+            id,
+            codec.id()
+        );
+    }
+    codec.decode(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(flags: u32, data: Vec<u8>) -> Item {
+        Item {
+            key: "key".to_owned(),
+            flags,
+            data,
+            cas: None,
+        }
+    }
+
+    #[test]
+    fn test_codecs() {
+        let value = vec!["a".to_owned(), "b".to_owned()];
+        let json = JsonCodec.encode(&value).unwrap();
+        assert_eq!(json, br#"["a","b"]"#);
+        assert_eq!(decode::<Vec<String>>(&JsonCodec, 1, &json).unwrap(), value);
+        let abomonation = unsafe { AbomonationCodec::new() };
+        assert!(decode::<Vec<String>>(&abomonation, 1, &json).is_err());
+
+        let abomonated = abomonation.encode(&value).unwrap();
+        assert_eq!(
+            decode::<Vec<String>>(&abomonation, 2, &abomonated).unwrap(),
+            value
+        );
+        assert!(
+            decode::<Vec<String>>(&abomonation, 2, &abomonated[..abomonated.len() - 1]).is_err()
+        );
+        assert!(decode::<Vec<u64>>(&abomonation, 2, &abomonated).is_err());
+    }
+
+    #[test]
+    fn test_compression() {
+        let compression = Some(Compression {
+            threshold: 100,
+            level: 3,
+        });
+        let small = b"small".to_vec();
+        assert_eq!(
+            pack(compression, 1, small.clone()).unwrap(),
+            (1 << CODEC_SHIFT, small)
+        );
+
+        let large = vec![b'a'; 1000];
+        let (flags, packed) = pack(compression, 1, large.clone()).unwrap();
+        assert_eq!(flags, 1 << CODEC_SHIFT | FLAG_ZSTD);
+        assert!(packed.len() < 100);
+        assert_eq!(unpack(item(flags, packed)).unwrap(), (1, large.clone()));
+
+        let (flags, packed) = pack(None, RAW_CODEC_ID, large.clone()).unwrap();
+        assert_eq!(unpack(item(flags, packed)).unwrap(), (RAW_CODEC_ID, large));
+    }
+}
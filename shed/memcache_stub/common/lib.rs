@@ -7,16 +7,24 @@
  * of this source tree.
  */
 
-//! This crate provides a client for accessing Memcache. The version on GitHub
-//! is no-op for now.
+//! This crate provides a client for accessing Memcache, talking the memcached
+//! text protocol. Values can be serialized with pluggable codecs and
+//! compressed with zstd.
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
 mod client;
+mod codec;
 mod keygen;
+mod protocol;
 
-pub use crate::client::{MemcacheClient, MemcacheGetType, MemcacheSetType};
+pub use crate::client::{
+    CasToken, MemcacheClient, MemcacheGetType, MemcacheMultiResult, MemcacheSetType,
+    DEFAULT_TIMEOUT, MEMCACHE_SERVERS_ENV,
+};
+pub use crate::codec::{AbomonationCodec, JsonCodec, MemcacheCodec};
 pub use crate::keygen::KeyGen;
+pub use crate::protocol::CasResult;
 
 /// Memcache max size for key + value + overhead is around 1MB, so we are leaving 1KB for key +
 /// overhead
@@ -0,0 +1,484 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Implementation of the memcached text protocol, see
+//! https://github.com/memcached/memcached/blob/master/doc/protocol.txt
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest key accepted by memcached
+const MAX_KEY_LENGTH: usize = 250;
+
+/// Expiration times longer than this are interpreted by memcached as an
+/// absolute unix timestamp rather than as a number of seconds from now
+const MAX_RELATIVE_EXPTIME: u64 = 30 * 24 * 60 * 60;
+
+/// A value returned by a retrieval command
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Item {
+    pub key: String,
+    pub flags: u32,
+    pub data: Vec<u8>,
+    /// Unique version of the value, only returned by [gets]
+    pub cas: Option<u64>,
+}
+
+/// Storage commands of the protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StoreCommand {
+    /// Store the value unconditionally
+    Set,
+    /// Store the value only if the key isn't present
+    Add,
+}
+
+impl StoreCommand {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Add => "add",
+        }
+    }
+}
+
+/// Check that the key can be sent to memcached: at most 250 bytes, without
+/// whitespace or control characters
+pub(crate) fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        bail!(
+            "Invalid memcache key of length {}, must be between 1 and {}",
+            key.len(),
+            MAX_KEY_LENGTH
+        );
+    }
+    if key
+        .bytes()
+        .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+    {
+        bail!(
+            "Invalid memcache key {:?}, contains whitespace or control characters",
+            key
+        );
+    }
+    Ok(())
+}
+
+/// Expiration time to send for a TTL, 0 meaning that the value never expires
+pub(crate) fn exptime(ttl: Option<Duration>) -> u64 {
+    let ttl = match ttl {
+        Some(ttl) => ttl,
+        None => return 0,
+    };
+    // Round up so that short TTLs don't turn into "never expires"
+    let secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+    if secs <= MAX_RELATIVE_EXPTIME {
+        secs
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        now + secs
+    }
+}
+
+/// Read a line of response. The outer error means that the connection is
+/// broken, the inner one that the server reported an error for the command.
+async fn read_line<S>(stream: &mut S) -> Result<Result<String>>
+where
+    S: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    stream.read_until(b'\n', &mut line).await?;
+    if !line.ends_with(b"\r\n") {
+        bail!("Connection to memcache closed mid-response");
+    }
+    line.truncate(line.len() - 2);
+    let line = String::from_utf8(line).context("Invalid response from memcache")?;
+
+    if line == "ERROR" {
+        return Ok(Err(anyhow!("memcache rejected the command")));
+    }
+    if let Some(msg) = line
+        .strip_prefix("CLIENT_ERROR ")
+        .or_else(|| line.strip_prefix("SERVER_ERROR "))
+    {
+        return Ok(Err(anyhow!("memcache error: {}", msg)));
+    }
+    Ok(Ok(line))
+}
+
+/// Retrieve the values of `keys`, the keys that are missing are absent from
+/// the result
+pub(crate) async fn get<S>(stream: &mut S, keys: &[&str]) -> Result<Vec<Item>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    retrieve(stream, "get", keys).await
+}
+
+/// Like [get], also returning the unique version of each value to pass to
+/// [cas]
+pub(crate) async fn gets<S>(stream: &mut S, keys: &[&str]) -> Result<Vec<Item>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    retrieve(stream, "gets", keys).await
+}
+
+async fn retrieve<S>(stream: &mut S, command: &str, keys: &[&str]) -> Result<Vec<Item>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let mut command = String::from(command);
+    for key in keys {
+        command.push(' ');
+        command.push_str(key);
+    }
+    command.push_str("\r\n");
+    stream.write_all(command.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut items = Vec::new();
+    loop {
+        let line = read_line(stream).await??;
+        if line == "END" {
+            return Ok(items);
+        }
+        let parse = || -> Option<(String, u32, usize, Option<u64>)> {
+            let mut parts = line.strip_prefix("VALUE ")?.split(' ');
+            let key = parts.next()?.to_owned();
+            let flags = parts.next()?.parse().ok()?;
+            let len = parts.next()?.parse().ok()?;
+            let cas = match parts.next() {
+                Some(cas) => Some(cas.parse().ok()?),
+                None => None,
+            };
+            Some((key, flags, len, cas))
+        };
+        let (key, flags, len, cas) =
+            parse().ok_or_else(|| anyhow!("Unexpected memcache response {:?}", line))?;
+
+        let mut data = vec![0; len + 2];
+        stream.read_exact(&mut data).await?;
+        if !data.ends_with(b"\r\n") {
+            bail!("Value of {} from memcache isn't terminated", key);
+        }
+        data.truncate(len);
+        items.push(Item {
+            key,
+            flags,
+            data,
+            cas,
+        });
+    }
+}
+
+/// A value to store with a storage command
+#[derive(Clone, Debug)]
+pub(crate) struct StoreItem<'a> {
+    pub key: &'a str,
+    pub flags: u32,
+    pub exptime: u64,
+    pub data: &'a [u8],
+}
+
+async fn write_store<S>(stream: &mut S, command: StoreCommand, item: &StoreItem<'_>) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let header = format!(
+        "{} {} {} {} {}\r\n",
+        command.name(),
+        item.key,
+        item.flags,
+        item.exptime,
+        item.data.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(item.data).await?;
+    stream.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+async fn read_store_reply<S>(stream: &mut S) -> Result<Result<bool>>
+where
+    S: AsyncBufRead + Unpin,
+{
+    Ok(match read_line(stream).await? {
+        Ok(line) => match line.as_str() {
+            "STORED" => Ok(true),
+            "NOT_STORED" => Ok(false),
+            _ => bail!("Unexpected memcache response {:?}", line),
+        },
+        Err(e) => Err(e),
+    })
+}
+
+/// Store the value of `item`, returning whether it was stored, i.e. false
+/// when the condition of the command isn't met
+pub(crate) async fn store<S>(
+    stream: &mut S,
+    command: StoreCommand,
+    item: &StoreItem<'_>,
+) -> Result<bool>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    write_store(stream, command, item).await?;
+    stream.flush().await?;
+    read_store_reply(stream).await?
+}
+
+/// Outcome of [crate::MemcacheClient::cas]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CasResult {
+    /// The value was stored
+    Stored,
+    /// The value was modified since it was read, so it wasn't replaced
+    Exists,
+    /// The value was deleted or expired since it was read
+    NotFound,
+}
+
+/// Store the value of `item` only if the current value still has the unique
+/// version `cas`, as returned by [gets]
+pub(crate) async fn cas<S>(stream: &mut S, item: &StoreItem<'_>, cas: u64) -> Result<CasResult>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let header = format!(
+        "cas {} {} {} {} {}\r\n",
+        item.key,
+        item.flags,
+        item.exptime,
+        item.data.len(),
+        cas
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(item.data).await?;
+    stream.write_all(b"\r\n").await?;
+    stream.flush().await?;
+
+    let line = read_line(stream).await??;
+    match line.as_str() {
+        "STORED" => Ok(CasResult::Stored),
+        "EXISTS" => Ok(CasResult::Exists),
+        "NOT_FOUND" => Ok(CasResult::NotFound),
+        _ => bail!("Unexpected memcache response {:?}", line),
+    }
+}
+
+/// Pipelined version of [store], sending all the commands before reading the
+/// replies. Errors reported by the server are returned per item.
+pub(crate) async fn store_multi<S>(
+    stream: &mut S,
+    command: StoreCommand,
+    items: &[StoreItem<'_>],
+) -> Result<Vec<Result<bool>>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    for item in items {
+        write_store(stream, command, item).await?;
+    }
+    stream.flush().await?;
+
+    let mut replies = Vec::with_capacity(items.len());
+    for _ in items {
+        replies.push(read_store_reply(stream).await?);
+    }
+    Ok(replies)
+}
+
+async fn read_delete_reply<S>(stream: &mut S) -> Result<Result<bool>>
+where
+    S: AsyncBufRead + Unpin,
+{
+    Ok(match read_line(stream).await? {
+        Ok(line) => match line.as_str() {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            _ => bail!("Unexpected memcache response {:?}", line),
+        },
+        Err(e) => Err(e),
+    })
+}
+
+/// Delete `key`, returning whether it was present
+pub(crate) async fn delete<S>(stream: &mut S, key: &str) -> Result<bool>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(format!("delete {}\r\n", key).as_bytes())
+        .await?;
+    stream.flush().await?;
+    read_delete_reply(stream).await?
+}
+
+/// Pipelined version of [delete], errors reported by the server are returned
+/// per key
+pub(crate) async fn delete_multi<S>(stream: &mut S, keys: &[&str]) -> Result<Vec<Result<bool>>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    for key in keys {
+        stream
+            .write_all(format!("delete {}\r\n", key).as_bytes())
+            .await?;
+    }
+    stream.flush().await?;
+
+    let mut replies = Vec::with_capacity(keys.len());
+    for _ in keys {
+        replies.push(read_delete_reply(stream).await?);
+    }
+    Ok(replies)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::BufStream;
+
+    /// Run `op` against a server replying `response`, returning the result of
+    /// `op` and what was sent to the server
+    async fn exchange<T>(
+        response: &'static [u8],
+        op: impl for<'a> FnOnce(
+            &'a mut BufStream<tokio::io::DuplexStream>,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = T> + 'a>>,
+    ) -> (T, Vec<u8>) {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut client = BufStream::new(client);
+        server.write_all(response).await.unwrap();
+        let result = op(&mut client).await;
+        drop(client);
+        let mut sent = Vec::new();
+        server.read_to_end(&mut sent).await.unwrap();
+        (result, sent)
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let (items, sent) = exchange(
+            b"VALUE a 3 5\r\nhe\r\no\r\nVALUE c 0 0\r\n\r\nEND\r\n",
+            |s| Box::pin(async move { get(s, &["a", "b", "c"]).await.unwrap() }),
+        )
+        .await;
+        assert_eq!(sent, b"get a b c\r\n");
+        assert_eq!(
+            items,
+            vec![
+                Item {
+                    key: "a".to_owned(),
+                    flags: 3,
+                    data: b"he\r\no".to_vec(),
+                    cas: None,
+                },
+                Item {
+                    key: "c".to_owned(),
+                    flags: 0,
+                    data: Vec::new(),
+                    cas: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gets_and_cas() {
+        let (items, sent) = exchange(b"VALUE a 0 1 42\r\nx\r\nEND\r\n", |s| {
+            Box::pin(async move { gets(s, &["a"]).await.unwrap() })
+        })
+        .await;
+        assert_eq!(sent, b"gets a\r\n");
+        assert_eq!(items[0].cas, Some(42));
+
+        let (result, sent) = exchange(b"EXISTS\r\n", |s| {
+            let item = StoreItem {
+                key: "a",
+                flags: 0,
+                exptime: 0,
+                data: b"y",
+            };
+            Box::pin(async move { cas(s, &item, 42).await.unwrap() })
+        })
+        .await;
+        assert_eq!(result, CasResult::Exists);
+        assert_eq!(sent, b"cas a 0 0 1 42\r\ny\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_store_and_delete() {
+        let (stored, sent) = exchange(b"NOT_STORED\r\n", |s| {
+            let item = StoreItem {
+                key: "k",
+                flags: 1,
+                exptime: 10,
+                data: b"v",
+            };
+            Box::pin(async move { store(s, StoreCommand::Add, &item).await.unwrap() })
+        })
+        .await;
+        assert!(!stored);
+        assert_eq!(sent, b"add k 1 10 1\r\nv\r\n");
+
+        let (deleted, sent) = exchange(b"DELETED\r\n", |s| {
+            Box::pin(async move { delete(s, "k").await.unwrap() })
+        })
+        .await;
+        assert!(deleted);
+        assert_eq!(sent, b"delete k\r\n");
+
+        let (replies, sent) = exchange(b"STORED\r\nSERVER_ERROR out of memory\r\n", |s| {
+            let items = [("a", b"1"), ("b", b"2")].map(|(key, data)| StoreItem {
+                key,
+                flags: 0,
+                exptime: 0,
+                data,
+            });
+            Box::pin(async move { store_multi(s, StoreCommand::Set, &items).await.unwrap() })
+        })
+        .await;
+        assert_eq!(sent, b"set a 0 0 1\r\n1\r\nset b 0 0 1\r\n2\r\n");
+        assert!(replies[0].as_ref().unwrap());
+        assert_eq!(
+            replies[1].as_ref().unwrap_err().to_string(),
+            "memcache error: out of memory"
+        );
+
+        let (replies, sent) = exchange(b"DELETED\r\nNOT_FOUND\r\n", |s| {
+            Box::pin(async move { delete_multi(s, &["a", "b"]).await.unwrap() })
+        })
+        .await;
+        assert_eq!(sent, b"delete a\r\ndelete b\r\n");
+        assert_eq!(
+            replies.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+            vec![true, false]
+        );
+    }
+
+    #[test]
+    fn test_validate_key_and_exptime() {
+        assert!(validate_key("foo:bar").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("foo bar").is_err());
+        assert!(validate_key(&"a".repeat(251)).is_err());
+
+        assert_eq!(exptime(None), 0);
+        assert_eq!(exptime(Some(Duration::from_millis(1))), 1);
+        assert_eq!(exptime(Some(Duration::from_secs(60))), 60);
+        assert!(exptime(Some(Duration::from_secs(MAX_RELATIVE_EXPTIME + 1))) > 1_000_000_000);
+    }
+}
@@ -8,7 +8,7 @@
  */
 
 //! This crate provides a client for accessing Memcache. The version on GitHub
-//! is no-op for now.
+//! talks the memcached text protocol to the servers it is configured with.
 
 #[cfg(fbcode_build)]
 use memcache_common as _; // used in oss
@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![no_main]
+
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use netstring::NetstringDecoder;
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte selects the mode and where the input is split, so that
+    // both the complete and the partial input paths are exercised
+    let (mode, data) = match data.split_first() {
+        Some((mode, data)) => (*mode, data),
+        None => return,
+    };
+    let mut decoder = NetstringDecoder::with_max_length(1 << 20);
+    if mode & 1 != 0 {
+        decoder = decoder.strict();
+    }
+    let split = (mode as usize >> 1) % (data.len() + 1);
+
+    let mut bytes_decoder = decoder;
+    let mut bytes = Bytes::copy_from_slice(data);
+    while let Ok(Some(_)) = bytes_decoder.decode_bytes(&mut bytes) {}
+
+    let mut buf = BytesMut::from(&data[..split]);
+    while let Ok(Some(_)) = decoder.decode(&mut buf) {}
+    buf.extend_from_slice(&data[split..]);
+    while let Ok(Some(_)) = decoder.decode(&mut buf) {}
+});
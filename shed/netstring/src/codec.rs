@@ -0,0 +1,88 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::{Error, Result};
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{NetstringDecoder, NetstringEncoder};
+
+/// A Netstring codec, combining [NetstringDecoder] and [NetstringEncoder] so
+/// that it can be used with transports like `tokio_util::codec::Framed` that
+/// need both directions handled by a single type.
+///
+/// Decoded items are `BytesMut`, encoded items can be anything that can be
+/// referenced as a `[u8]`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NetstringCodec {
+    decoder: NetstringDecoder,
+}
+
+impl NetstringCodec {
+    /// Create a codec whose decoder rejects payloads longer than
+    /// `max_length`, see [NetstringDecoder::with_max_length].
+    pub fn with_max_length(max_length: usize) -> Self {
+        Self {
+            decoder: NetstringDecoder::with_max_length(max_length),
+        }
+    }
+
+    /// Make the decoder strict, see [NetstringDecoder::strict].
+    pub fn strict(self) -> Self {
+        Self {
+            decoder: self.decoder.strict(),
+        }
+    }
+}
+
+impl Decoder for NetstringCodec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        self.decoder.decode(buf)
+    }
+}
+
+impl<Out> Encoder<Out> for NetstringCodec
+where
+    Out: AsRef<[u8]>,
+{
+    type Error = Error;
+
+    fn encode(&mut self, msg: Out, buf: &mut BytesMut) -> Result<()> {
+        NetstringEncoder::default().encode(msg, buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::Framed;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn framed_roundtrip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = Framed::new(client, NetstringCodec::default());
+        let mut server = Framed::new(server, NetstringCodec::with_max_length(16));
+
+        client.send(b"hello").await.unwrap();
+        client.send(b"world").await.unwrap();
+        assert_eq!(server.next().await.unwrap().unwrap().as_ref(), b"hello");
+        assert_eq!(server.next().await.unwrap().unwrap().as_ref(), b"world");
+
+        server.send(&b"reply"[..]).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap().as_ref(), b"reply");
+
+        client.send(vec![0; 17]).await.unwrap();
+        assert!(server.next().await.unwrap().is_err());
+    }
+}
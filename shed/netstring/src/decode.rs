@@ -9,13 +9,13 @@
 
 use crate::ErrorKind;
 use anyhow::{bail, ensure, Error, Result};
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
 use tokio_util::codec::Decoder;
 
 #[derive(Debug, Copy, Clone)]
 enum State {
-    Num(usize),  // waiting for a complete number
-    Body(usize), // waiting for remaining body and comma
+    Num(usize, usize), // waiting for a complete number, with the digits seen so far
+    Body(usize),       // waiting for remaining body and comma
 }
 
 /// A Netstring decoder.
@@ -24,6 +24,10 @@ enum State {
 #[derive(Debug, Copy, Clone)]
 pub struct NetstringDecoder {
     state: Option<State>,
+    max_length: Option<usize>,
+    strict: bool,
+    // offset in the stream of the start of the netstring being decoded
+    position: u64,
 }
 
 #[derive(Debug)]
@@ -46,12 +50,58 @@ impl Slice {
 impl Default for NetstringDecoder {
     fn default() -> Self {
         Self {
-            state: Some(State::Num(0)),
+            state: Some(State::Num(0, 0)),
+            max_length: None,
+            strict: false,
+            position: 0,
         }
     }
 }
 
 impl NetstringDecoder {
+    /// Create a decoder rejecting payloads longer than `max_length` bytes with
+    /// [ErrorKind::FrameTooLong]. The error is returned as soon as the length
+    /// prefix is known to exceed the limit, before any payload is buffered.
+    pub fn with_max_length(max_length: usize) -> Self {
+        Self {
+            max_length: Some(max_length),
+            ..Self::default()
+        }
+    }
+
+    /// Make the decoder strict: lengths with leading zeros or no digits at all
+    /// are rejected too, and all errors are reported as
+    /// [ErrorKind::NetstringMalformed] with the offset in the stream of the
+    /// offending byte.
+    pub fn strict(self) -> Self {
+        Self {
+            strict: true,
+            ..self
+        }
+    }
+
+    /// Error for a malformed netstring, `offset` is relative to the start of
+    /// the netstring being decoded
+    fn malformed(&self, offset: usize, reason: &'static str) -> ErrorKind {
+        if self.strict {
+            ErrorKind::NetstringMalformed {
+                position: self.position + offset as u64,
+                reason,
+            }
+        } else {
+            ErrorKind::NetstringDecode(reason)
+        }
+    }
+
+    fn check_length(&self, length: usize) -> Result<()> {
+        match self.max_length {
+            Some(max_length) if length > max_length => {
+                bail!(ErrorKind::FrameTooLong { length, max_length })
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Decode parser. This maintains the internal state machine which tracks what we've seen
     /// before. It will return as much output as it can on each call, or None if nothing can be
     /// returned. The second part of the tuple is the amount of the input buffer we have consumed;
@@ -67,17 +117,37 @@ impl NetstringDecoder {
             let buf = &buf[consumed..];
 
             let (next, ret): (State, Option<Option<(bool, Slice)>>) = match state {
-                State::Num(mut cur) => {
+                State::Num(mut cur, mut digits) => {
                     let mut next = None;
 
                     for (idx, inp) in buf.iter().enumerate() {
                         match *inp {
-                            digit @ b'0'..=b'9' => cur = cur * 10 + ((digit - b'0') as usize),
+                            digit @ b'0'..=b'9' => {
+                                if self.strict && digits == 1 && cur == 0 {
+                                    bail!(self.malformed(
+                                        consumed + idx - 1,
+                                        "Leading zero in payload size"
+                                    ));
+                                }
+                                cur = cur
+                                    .checked_mul(10)
+                                    .and_then(|cur| cur.checked_add((digit - b'0') as usize))
+                                    .ok_or_else(|| {
+                                        self.malformed(consumed + idx, "Payload size overflow")
+                                    })?;
+                                digits += 1;
+                                self.check_length(cur)?;
+                            }
+                            b':' if self.strict && digits == 0 => {
+                                bail!(self.malformed(consumed + idx, "Missing payload size"))
+                            }
                             b':' => {
                                 next = Some((idx + 1, State::Body(cur)));
                                 break;
                             }
-                            _ => bail!(ErrorKind::NetstringDecode("Bad character in payload size")),
+                            _ => bail!(
+                                self.malformed(consumed + idx, "Bad character in payload size")
+                            ),
                         }
                     }
 
@@ -91,7 +161,7 @@ impl NetstringDecoder {
                         // we need more.
                         consumed += buf.len();
 
-                        (State::Num(cur), Some(None))
+                        (State::Num(cur, digits), Some(None))
                     }
                 }
 
@@ -102,10 +172,13 @@ impl NetstringDecoder {
                         // start expecting the next buffer.
                         let v = Slice::new(consumed, len);
 
-                        ensure!(buf[len] == b',', ErrorKind::NetstringDecode("missing ','"));
+                        ensure!(
+                            buf[len] == b',',
+                            self.malformed(consumed + len, "missing ','")
+                        );
                         consumed += len + 1;
 
-                        (State::Num(0), Some(Some((true, v))))
+                        (State::Num(0, 0), Some(Some((true, v))))
                     } else {
                         // Consume as much of the input as we can, and leave the state set up
                         // to handle the rest as it arrives.
@@ -125,19 +198,17 @@ impl NetstringDecoder {
     }
 }
 
-impl Decoder for NetstringDecoder {
-    type Item = BytesMut;
-    type Error = Error;
-
-    /// Decode a netstring. Is left in a broken state if it ever returns an error,
-    /// as it implies the framing is broken on the stream and the whole thing needs
-    /// to be reset.
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+impl NetstringDecoder {
+    /// Find the next complete netstring in the buffer, returning the slice of
+    /// the payload and how much of the buffer it consumes. The internal state
+    /// is rolled back if the netstring is incomplete, as the caller will
+    /// present the same input again with more data appended.
+    fn decode_frame(&mut self, buf: &[u8]) -> Result<Option<(usize, Slice)>> {
         // The Decoder API can't deal with partial results, so if we don't get a complete
         // result we roll back the internal state to this.
         let saved = *self;
 
-        let (consumed, ret) = self.decode_buf(buf.as_ref())?;
+        let (consumed, ret) = self.decode_buf(buf)?;
 
         match ret {
             Some((true, slice)) => {
@@ -156,6 +227,44 @@ impl Decoder for NetstringDecoder {
                     consumed
                 );
 
+                self.position += consumed as u64;
+                Ok(Some((consumed, slice)))
+            }
+            Some((false, _)) | None => {
+                // Either partial result or incomplete input - roll back state and ask for more.
+                *self = saved;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Decode a netstring from an immutable buffer. The payload is returned as
+    /// a slice of `buf` sharing its memory, so no data is copied. Like
+    /// [Decoder::decode], the consumed input is removed from `buf` and the
+    /// decoder is left in a broken state if it returns an error.
+    pub fn decode_bytes(&mut self, buf: &mut Bytes) -> Result<Option<Bytes>> {
+        match self.decode_frame(buf.as_ref())? {
+            Some((consumed, slice)) => {
+                let ret = buf.slice(slice.start()..slice.end());
+                buf.advance(consumed);
+                Ok(Some(ret))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Decoder for NetstringDecoder {
+    type Item = BytesMut;
+    type Error = Error;
+
+    /// Decode a netstring. Is left in a broken state if it ever returns an error,
+    /// as it implies the framing is broken on the stream and the whole thing needs
+    /// to be reset. The payload is split off `buf` without copying it, it can be
+    /// turned into [Bytes] with [BytesMut::freeze].
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        match self.decode_frame(buf.as_ref())? {
+            Some((consumed, slice)) => {
                 let mut ret = buf.split_to(slice.end());
 
                 if consumed > slice.end() {
@@ -166,11 +275,7 @@ impl Decoder for NetstringDecoder {
 
                 Ok(Some(ret))
             }
-            Some((false, _)) | None => {
-                // Either partial result or incomplete input - roll back state and ask for more.
-                *self = saved;
-                Ok(None)
-            }
+            None => Ok(None),
         }
     }
 }
@@ -178,6 +283,7 @@ impl Decoder for NetstringDecoder {
 #[cfg(test)]
 mod test {
     use bytes::{BufMut, BytesMut};
+    use quickcheck::quickcheck;
     use tokio_util::codec::Decoder;
 
     use super::*;
@@ -368,6 +474,99 @@ mod test {
         }
     }
 
+    #[test]
+    fn decode_bytes_shares_input() {
+        let input = Bytes::from_static(b"5:hello,5:world,3:ab");
+        let mut buf = input.clone();
+
+        let mut codec = NetstringDecoder::default();
+
+        let hello = codec.decode_bytes(&mut buf).unwrap().unwrap();
+        assert_eq!(hello.as_ref(), b"hello");
+        assert_eq!(hello.as_ptr(), input[2..].as_ptr());
+
+        let world = codec.decode_bytes(&mut buf).unwrap().unwrap();
+        assert_eq!(world.as_ref(), b"world");
+        assert!(codec.decode_bytes(&mut buf).unwrap().is_none());
+        assert_eq!(buf.as_ref(), b"3:ab");
+    }
+
+    #[test]
+    fn decode_max_length() {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_slice(b"5:hello,");
+
+        let mut codec = NetstringDecoder::with_max_length(5);
+
+        match codec.decode(&mut buf) {
+            Ok(Some(ref res)) if res.as_ref() == b"hello" => {}
+            bad => panic!(
+                "decode failed: {:?}",
+                bad.as_ref().map(|x| x.as_ref().map(BytesMut::as_ref))
+            ),
+        }
+
+        // Rejected before the colon arrives
+        buf.put_slice(b"10");
+
+        match codec.decode(&mut buf) {
+            Err(e) => match e.downcast_ref::<ErrorKind>() {
+                Some(ErrorKind::FrameTooLong {
+                    length: 10,
+                    max_length: 5,
+                }) => {}
+                _ => panic!("unexpected error {:?}", e),
+            },
+            bad => panic!(
+                "decode succeeded: {:?}",
+                bad.as_ref().map(|x| x.as_ref().map(BytesMut::as_ref))
+            ),
+        }
+    }
+
+    #[test]
+    fn decode_strict() {
+        fn strict_error(input: &[u8]) -> (u64, &'static str) {
+            let mut buf = BytesMut::from(input);
+            let mut codec = NetstringDecoder::default().strict();
+            loop {
+                match codec.decode(&mut buf) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => panic!("decode of {:?} succeeded", input),
+                    Err(e) => match e.downcast_ref::<ErrorKind>() {
+                        Some(ErrorKind::NetstringMalformed { position, reason }) => {
+                            return (*position, reason);
+                        }
+                        _ => panic!("unexpected error {:?}", e),
+                    },
+                }
+            }
+        }
+
+        assert_eq!(
+            strict_error(b"05:hello,"),
+            (0, "Leading zero in payload size")
+        );
+        assert_eq!(strict_error(b"0:,:,"), (3, "Missing payload size"));
+        assert_eq!(
+            strict_error(b"5:hello,1x:a,"),
+            (9, "Bad character in payload size")
+        );
+        assert_eq!(strict_error(b"5:hellox"), (7, "missing ','"));
+        assert_eq!(
+            strict_error(b"99999999999999999999999:"),
+            (19, "Payload size overflow")
+        );
+
+        let mut buf = BytesMut::from(&b"0:,10:0123456789,"[..]);
+        let mut codec = NetstringDecoder::default().strict();
+        assert_eq!(codec.decode(&mut buf).unwrap().as_deref(), Some(&b""[..]));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap().as_deref(),
+            Some(&b"0123456789"[..])
+        );
+    }
+
     #[test]
     fn decode_bad_comma() {
         let mut buf = BytesMut::with_capacity(1);
@@ -383,4 +582,20 @@ mod test {
             ),
         }
     }
+
+    quickcheck! {
+        fn decode_never_panics(input: Vec<u8>, split: usize, strict: bool) -> bool {
+            // Feed the input in two chunks to also exercise the partial input paths
+            let split = split % (input.len() + 1);
+            let mut codec = NetstringDecoder::with_max_length(1 << 20);
+            if strict {
+                codec = codec.strict();
+            }
+            let mut buf = BytesMut::from(&input[..split]);
+            while let Ok(Some(_)) = codec.decode(&mut buf) {}
+            buf.put_slice(&input[split..]);
+            while let Ok(Some(_)) = codec.decode(&mut buf) {}
+            true
+        }
+    }
 }
@@ -28,10 +28,31 @@ pub enum ErrorKind {
     /// Error while decoding netstring
     #[error("{0}")]
     NetstringDecode(&'static str),
+    /// Error while decoding netstring in strict mode
+    #[error("{reason} at byte {position}")]
+    NetstringMalformed {
+        /// Offset in the stream of the offending byte
+        position: u64,
+        /// What is wrong with the netstring
+        reason: &'static str,
+    },
+    /// The length of the netstring payload exceeds the limit of the decoder
+    #[error("Netstring payload size {length} exceeds the maximum of {max_length}")]
+    FrameTooLong {
+        /// Length of the payload, or as much of it as was read when the limit
+        /// was exceeded
+        length: usize,
+        /// Maximum payload length allowed by the decoder
+        max_length: usize,
+    },
 }
 
+mod codec;
 mod decode;
 mod encode;
+mod stream;
 
+pub use crate::codec::NetstringCodec;
 pub use crate::decode::NetstringDecoder;
 pub use crate::encode::NetstringEncoder;
+pub use crate::stream::write_netstring_stream;
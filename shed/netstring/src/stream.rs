@@ -0,0 +1,73 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Write a netstring with a payload of `len` bytes read from `payload`,
+/// streaming it through a small buffer instead of holding the whole frame in
+/// memory as [NetstringEncoder](crate::NetstringEncoder) does. Suitable for
+/// multi-megabyte payloads, e.g. files, whose length is known upfront.
+///
+/// Fails with [io::ErrorKind::UnexpectedEof] if `payload` ends before `len`
+/// bytes were read, in which case a truncated netstring was written and the
+/// framing of `writer` is broken. Any data in `payload` after `len` bytes is
+/// left unread.
+pub async fn write_netstring_stream<W, R>(writer: &mut W, payload: R, len: u64) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    writer.write_all(format!("{}:", len).as_bytes()).await?;
+
+    let copied = tokio::io::copy(&mut payload.take(len), writer).await?;
+    if copied != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("netstring payload ended after {} of {} bytes", copied, len),
+        ));
+    }
+
+    writer.write_all(b",").await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    use super::*;
+    use crate::NetstringDecoder;
+
+    #[tokio::test]
+    async fn stream_roundtrip() {
+        let payload: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut out = Vec::new();
+        write_netstring_stream(&mut out, &payload[..], payload.len() as u64)
+            .await
+            .unwrap();
+        assert!(out.starts_with(b"100000:"));
+
+        let mut buf = BytesMut::from(&out[..]);
+        let decoded = NetstringDecoder::default().decode(&mut buf).unwrap();
+        assert_eq!(decoded.as_deref(), Some(&payload[..]));
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_short_payload() {
+        let mut out = Vec::new();
+        let err = write_netstring_stream(&mut out, &b"hello"[..], 10)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
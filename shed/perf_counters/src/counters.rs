@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::PerfCounters;
+
+thread_local! {
+    // Const initialized and without destructors, so that the allocator can
+    // use them without allocating or running into destroyed thread locals.
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<u64> = const { Cell::new(0) };
+    static BLOCKING_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Count an allocation of `size` bytes on the calling thread. This is the
+/// hook for custom global allocators, [CountingAllocator] calls it for the
+/// allocator it wraps.
+pub fn record_allocation(size: usize) {
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    let _ = ALLOCATED_BYTES.try_with(|bytes| bytes.set(bytes.get() + size as u64));
+}
+
+/// Global allocator counting the allocations made through the allocator it
+/// wraps
+pub struct CountingAllocator<A> {
+    inner: A,
+}
+
+impl<A> CountingAllocator<A> {
+    /// Count the allocations made through `inner`
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+/// Run `f`, which blocks the thread e.g. on synchronous IO or a lock, counting
+/// the time it took as [PerfCounters::blocking_time]
+pub fn blocking<R>(f: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let _ = BLOCKING_TIME.try_with(|time| time.set(time.get() + elapsed));
+    result
+}
+
+#[cfg(unix)]
+fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec to write the result to
+    let ret = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    if ret != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Duration {
+    Duration::ZERO
+}
+
+pub(crate) fn snapshot() -> PerfCounters {
+    PerfCounters {
+        cpu_time: thread_cpu_time(),
+        allocations: ALLOCATIONS.with(Cell::get),
+        allocated_bytes: ALLOCATED_BYTES.with(Cell::get),
+        blocking_time: BLOCKING_TIME.with(Cell::get),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let start = snapshot();
+        record_allocation(10);
+        record_allocation(20);
+        blocking(|| std::thread::sleep(Duration::from_millis(10)));
+        // Spin to use some CPU time, which is only measured on unix
+        #[cfg(unix)]
+        {
+            let spin_start = thread_cpu_time();
+            while thread_cpu_time() == spin_start {}
+        }
+
+        let used = snapshot() - start;
+        // The test's global allocator may count more allocations
+        assert!(used.allocations >= 2);
+        assert!(used.allocated_bytes >= 30);
+        assert!(used.blocking_time >= Duration::from_millis(10));
+        #[cfg(unix)]
+        assert!(used.cpu_time > Duration::ZERO);
+    }
+}
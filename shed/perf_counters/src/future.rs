@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+
+use futures::future::Future;
+use futures::ready;
+use futures::task::{Context, Poll};
+use pin_project::pin_project;
+
+use crate::{counters, PerfCounters};
+
+/// A Future that counts the resources used by polling the inner Future.
+/// This structure's main usage is by calling
+/// [PerfCountersFutureExt::perf_counted].
+#[pin_project]
+pub struct PerfCountedFuture<F> {
+    #[pin]
+    inner: F,
+    counters: PerfCounters,
+}
+
+impl<F: Future> Future for PerfCountedFuture<F> {
+    type Output = (PerfCounters, F::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        // The future may be polled on a different thread each time, but each
+        // poll runs on a single thread, so the thread's counters are
+        // accumulated around each poll.
+        let start = counters::snapshot();
+        let poll = this.inner.poll(cx);
+        *this.counters += counters::snapshot() - start;
+        let out = ready!(poll);
+        Poll::Ready((*this.counters, out))
+    }
+}
+
+/// A trait that provides the `perf_counted` method to [futures::Future]
+pub trait PerfCountersFutureExt: Future + Sized {
+    /// Combinator that returns a future that will count the resources used
+    /// by polling this future and return them together with its result. Work
+    /// the future waits on, e.g. spawned tasks, isn't counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use perf_counters::PerfCountersFutureExt;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (counters, value) = async { vec![1u32; 100] }.perf_counted().await;
+    /// assert_eq!(value.len(), 100);
+    /// println!("used {:?} of CPU time", counters.cpu_time);
+    /// # });
+    /// ```
+    fn perf_counted(self) -> PerfCountedFuture<Self> {
+        PerfCountedFuture {
+            inner: self,
+            counters: PerfCounters::default(),
+        }
+    }
+}
+
+impl<T: Future> PerfCountersFutureExt for T {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::alloc::System;
+    use std::time::Duration;
+
+    use crate::{blocking, CountingAllocator};
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator<System> = CountingAllocator::new(System);
+
+    #[tokio::test]
+    async fn test_perf_counted() {
+        let (counters, value) = async {
+            tokio::task::yield_now().await;
+            let value = vec![0u8; 1000];
+            blocking(|| std::thread::sleep(Duration::from_millis(10)));
+            value
+        }
+        .perf_counted()
+        .await;
+        assert_eq!(value.len(), 1000);
+        assert!(counters.allocations >= 1);
+        assert!(counters.allocated_bytes >= 1000);
+        assert!(counters.blocking_time >= Duration::from_millis(10));
+
+        let (counters, ()) = async {}.perf_counted().await;
+        assert_eq!(counters.allocations, 0);
+        assert_eq!(counters.blocking_time, Duration::ZERO);
+    }
+}
@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Counters of the resources used by an operation beyond its wall-clock
+//! latency, in the spirit of `futures_stats`: the CPU time spent polling it,
+//! the allocations it made and the time it spent blocking its thread.
+//!
+//! Allocations are only counted if the binary installs [CountingAllocator] as
+//! its global allocator, or if its own allocator calls [record_allocation]:
+//!
+//! ```
+//! use perf_counters::CountingAllocator;
+//! use std::alloc::System;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator<System> = CountingAllocator::new(System);
+//! # fn main() {}
+//! ```
+
+use std::ops::{Add, AddAssign, Sub};
+use std::time::Duration;
+
+mod counters;
+mod future;
+
+pub use crate::counters::{blocking, record_allocation, CountingAllocator};
+pub use crate::future::{PerfCountedFuture, PerfCountersFutureExt};
+
+/// Resources used by an operation
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PerfCounters {
+    /// CPU time of the thread running the operation
+    pub cpu_time: Duration,
+
+    /// Number of allocations made
+    pub allocations: u64,
+
+    /// Total size of the allocations made
+    pub allocated_bytes: u64,
+
+    /// Wall-clock time spent in [blocking] sections, during which the thread
+    /// couldn't make progress on other work
+    pub blocking_time: Duration,
+}
+
+impl PerfCounters {
+    /// Current values of the counters of the calling thread, which are only
+    /// meaningful as the difference between two snapshots
+    pub fn thread_snapshot() -> Self {
+        counters::snapshot()
+    }
+}
+
+impl Add for PerfCounters {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            cpu_time: self.cpu_time + other.cpu_time,
+            allocations: self.allocations + other.allocations,
+            allocated_bytes: self.allocated_bytes + other.allocated_bytes,
+            blocking_time: self.blocking_time + other.blocking_time,
+        }
+    }
+}
+
+impl AddAssign for PerfCounters {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for PerfCounters {
+    type Output = Self;
+
+    /// Difference between two snapshots, saturating at zero
+    fn sub(self, other: Self) -> Self {
+        Self {
+            cpu_time: self.cpu_time.saturating_sub(other.cpu_time),
+            allocations: self.allocations.saturating_sub(other.allocations),
+            allocated_bytes: self.allocated_bytes.saturating_sub(other.allocated_bytes),
+            blocking_time: self.blocking_time.saturating_sub(other.blocking_time),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use stats::prelude::*;
+use time_ext::{Clock, SystemClock};
+
+use crate::ExceedsBurstError;
+
+define_stats! {
+    prefix = "rate_limiter";
+    acquired: dynamic_timeseries("{}.acquired", (name: String); Rate, Sum),
+    throttled: dynamic_timeseries("{}.throttled", (name: String); Rate, Sum),
+    wait_us: dynamic_histogram("{}.wait_us", (name: String); 1000, 0, 1_000_000, Average, Count; P 50; P 99),
+}
+
+/// Parameters shared by all the buckets of a limiter
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Config {
+    burst: u32,
+    /// Tokens added per second
+    refill_rate: f64,
+}
+
+impl Config {
+    pub(crate) fn new(burst: u32, refill_rate: f64) -> Self {
+        assert!(burst > 0, "burst must be positive");
+        assert!(
+            refill_rate.is_finite() && refill_rate > 0.0,
+            "refill rate must be positive"
+        );
+        Self { burst, refill_rate }
+    }
+
+    pub(crate) fn check(&self, tokens: u32) -> Result<(), ExceedsBurstError> {
+        if tokens > self.burst {
+            Err(ExceedsBurstError {
+                requested: tokens,
+                burst: self.burst,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// State of a single bucket. The tokens go negative when they are reserved
+/// by [Bucket::reserve] before being refilled.
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A full bucket
+    pub(crate) fn new(config: &Config, now: Instant) -> Self {
+        Self {
+            tokens: config.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, config: &Config, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * config.refill_rate).min(config.burst as f64);
+        self.updated = self.updated.max(now);
+    }
+
+    /// Whether the bucket is full as of `now`, i.e. it is indistinguishable
+    /// from a new one
+    pub(crate) fn is_full(&mut self, config: &Config, now: Instant) -> bool {
+        self.refill(config, now);
+        self.tokens >= config.burst as f64
+    }
+
+    /// Take `tokens` if they are available
+    pub(crate) fn try_take(&mut self, config: &Config, now: Instant, tokens: u32) -> bool {
+        self.refill(config, now);
+        if self.tokens >= tokens as f64 {
+            self.tokens -= tokens as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take `tokens`, going into debt if they are not available yet, and
+    /// return how long to wait until the debt is repaid. Reserving instead of
+    /// polling serves the waiters in order.
+    pub(crate) fn reserve(&mut self, config: &Config, now: Instant, tokens: u32) -> Duration {
+        self.refill(config, now);
+        self.tokens -= tokens as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / config.refill_rate)
+        }
+    }
+
+    /// Hand back `tokens` taken by [Bucket::reserve] whose wait was dropped.
+    /// The waiters queued behind them still wait as long as they planned to.
+    pub(crate) fn refund(&mut self, config: &Config, tokens: u32) {
+        self.tokens = (self.tokens + tokens as f64).min(config.burst as f64);
+    }
+}
+
+/// Record the outcome of an acquisition into the stats of limiter `name`
+pub(crate) fn record(name: &str, tokens: u64, wait: Option<Duration>) {
+    match wait {
+        Some(wait) if wait.is_zero() => {}
+        Some(wait) => {
+            STATS::throttled.add_value(1, (name.to_owned(),));
+            STATS::wait_us.add_value(wait.as_micros() as i64, (name.to_owned(),));
+        }
+        None => {
+            STATS::throttled.add_value(1, (name.to_owned(),));
+            return;
+        }
+    }
+    STATS::acquired.add_value(tokens as i64, (name.to_owned(),));
+}
+
+/// Calls the refund of a reservation unless it was disarmed, i.e. if the
+/// wait for the reserved tokens is dropped before completing
+struct Refund<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Drop for Refund<F> {
+    fn drop(&mut self) {
+        if let Some(refund) = self.0.take() {
+            refund();
+        }
+    }
+}
+
+/// Wait on `clock` for the tokens reserved with [Bucket::reserve], calling
+/// `refund` to hand them back if this is dropped before completing
+pub(crate) async fn wait(
+    name: &str,
+    tokens: u32,
+    wait: Duration,
+    clock: &impl Clock,
+    refund: impl FnOnce(),
+) {
+    if !wait.is_zero() {
+        let mut refund = Refund(Some(refund));
+        clock.sleep(wait).await;
+        refund.0 = None;
+    }
+    record(name, tokens.into(), Some(wait));
+}
+
+/// A token bucket shared by all its callers, holding up to `burst` tokens and
+/// refilled with `refill_rate` tokens per second. It starts full, so up to
+/// `burst` tokens can be taken at once before the rate applies.
+pub struct TokenBucket<C = SystemClock> {
+    name: String,
+    config: Config,
+    bucket: Mutex<Bucket>,
+    clock: C,
+}
+
+impl TokenBucket {
+    /// Create a bucket whose stats are exported under `name`. Panics if
+    /// `burst` is zero or `refill_rate` is not positive.
+    pub fn new(name: impl Into<String>, burst: u32, refill_rate: f64) -> Self {
+        Self::with_clock(name, burst, refill_rate, SystemClock)
+    }
+}
+
+impl<C: Clock> TokenBucket<C> {
+    /// Create a bucket reading the time from the provided clock, see
+    /// [TokenBucket::new]
+    pub fn with_clock(name: impl Into<String>, burst: u32, refill_rate: f64, clock: C) -> Self {
+        let config = Config::new(burst, refill_rate);
+        Self {
+            name: name.into(),
+            bucket: Mutex::new(Bucket::new(&config, clock.now())),
+            config,
+            clock,
+        }
+    }
+
+    /// Name the stats of this bucket are exported under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Take `tokens` if they are available right now, without waiting
+    pub fn try_acquire(&self, tokens: u32) -> bool {
+        let acquired = self
+            .bucket
+            .lock()
+            .try_take(&self.config, self.clock.now(), tokens);
+        record(
+            &self.name,
+            tokens.into(),
+            acquired.then_some(Duration::ZERO),
+        );
+        acquired
+    }
+
+    /// Take `tokens`, waiting on the clock until they are available. The
+    /// tokens are reserved when this is called, so that the waiters are served
+    /// in order, and handed back if the returned future is dropped before
+    /// completing. Fails without waiting if `tokens` exceeds the burst of the
+    /// bucket, as they would never be available.
+    pub async fn acquire(&self, tokens: u32) -> Result<(), ExceedsBurstError> {
+        self.config.check(tokens)?;
+        let delay = self
+            .bucket
+            .lock()
+            .reserve(&self.config, self.clock.now(), tokens);
+        wait(&self.name, tokens, delay, &self.clock, || {
+            self.bucket.lock().refund(&self.config, tokens)
+        })
+        .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+    use time_ext::TestClock;
+
+    #[test]
+    fn test_try_acquire() {
+        let clock = TestClock::new();
+        let bucket = TokenBucket::with_clock("test_try_acquire", 10, 5.0, clock.clone());
+
+        assert!(bucket.try_acquire(6));
+        assert!(bucket.try_acquire(4));
+        assert!(!bucket.try_acquire(1));
+
+        clock.advance(Duration::from_millis(200));
+        assert!(bucket.try_acquire(1));
+        assert!(!bucket.try_acquire(1));
+
+        // The bucket doesn't fill above its burst
+        clock.advance(Duration::from_secs(60));
+        assert!(bucket.try_acquire(10));
+        assert!(!bucket.try_acquire(1));
+        assert!(!bucket.try_acquire(11));
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let clock = TestClock::new();
+        let bucket = TokenBucket::with_clock("test_acquire", 2, 10.0, clock.clone());
+        assert!(bucket.acquire(3).await.is_err());
+        assert!(matches!(bucket.acquire(2).now_or_never(), Some(Ok(()))));
+
+        // The tokens are reserved, so the next waiters queue behind, waiting
+        // for the clock rather than for the real time
+        let mut first = Box::pin(bucket.acquire(1));
+        let mut second = Box::pin(bucket.acquire(2));
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        clock.advance(Duration::from_millis(100));
+        assert!(first.await.is_ok());
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        clock.advance(Duration::from_millis(200));
+        assert!(second.await.is_ok());
+        assert!(!bucket.try_acquire(1));
+
+        clock.advance(Duration::from_millis(100));
+        assert!(bucket.try_acquire(1));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_dropped() {
+        let clock = TestClock::new();
+        let bucket = TokenBucket::with_clock("test_acquire_dropped", 2, 10.0, clock.clone());
+        assert!(bucket.try_acquire(2));
+
+        // The tokens reserved by a dropped waiter are handed back
+        let mut dropped = Box::pin(bucket.acquire(2));
+        assert!(futures::poll!(dropped.as_mut()).is_pending());
+        drop(dropped);
+        assert!(!bucket.try_acquire(1));
+        clock.advance(Duration::from_millis(100));
+        assert!(bucket.try_acquire(1));
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use time_ext::{Clock, SystemClock};
+
+use crate::bucket::{record, wait, Bucket, Config};
+use crate::ExceedsBurstError;
+
+/// Minimum number of buckets kept before full ones are purged
+const MIN_PURGE_THRESHOLD: usize = 1024;
+
+struct Buckets<K> {
+    buckets: HashMap<K, Bucket>,
+    /// Number of buckets at which the full ones are purged next
+    purge_threshold: usize,
+}
+
+/// A token bucket per key, all with the same `burst` and `refill_rate`, see
+/// [crate::TokenBucket]. A bucket is created full the first time its key is
+/// seen, so the buckets that are full again are indistinguishable from new
+/// ones and are dropped from time to time to bound the memory used.
+pub struct KeyedTokenBucket<K, C = SystemClock> {
+    name: String,
+    config: Config,
+    buckets: Mutex<Buckets<K>>,
+    clock: C,
+}
+
+impl<K: Hash + Eq> KeyedTokenBucket<K> {
+    /// Create a limiter whose stats are exported under `name`. Panics if
+    /// `burst` is zero or `refill_rate` is not positive.
+    pub fn new(name: impl Into<String>, burst: u32, refill_rate: f64) -> Self {
+        Self::with_clock(name, burst, refill_rate, SystemClock)
+    }
+}
+
+impl<K: Hash + Eq, C: Clock> KeyedTokenBucket<K, C> {
+    /// Create a limiter reading the time from the provided clock, see
+    /// [KeyedTokenBucket::new]
+    pub fn with_clock(name: impl Into<String>, burst: u32, refill_rate: f64, clock: C) -> Self {
+        Self {
+            name: name.into(),
+            config: Config::new(burst, refill_rate),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                purge_threshold: MIN_PURGE_THRESHOLD,
+            }),
+            clock,
+        }
+    }
+
+    /// Name the stats of this limiter are exported under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of buckets currently tracked
+    pub fn len(&self) -> usize {
+        self.buckets.lock().buckets.len()
+    }
+
+    /// Whether no bucket is currently tracked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn with_bucket<Q, Out>(&self, key: &Q, f: impl FnOnce(&mut Bucket, Instant) -> Out) -> Out
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock();
+        if let Some(bucket) = buckets.buckets.get_mut(key) {
+            return f(bucket, now);
+        }
+
+        if buckets.buckets.len() >= buckets.purge_threshold {
+            let config = self.config;
+            buckets
+                .buckets
+                .retain(|_, bucket| !bucket.is_full(&config, now));
+            buckets.purge_threshold = (buckets.buckets.len() * 2).max(MIN_PURGE_THRESHOLD);
+        }
+        let bucket = buckets
+            .buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| Bucket::new(&self.config, now));
+        f(bucket, now)
+    }
+
+    /// Take `tokens` from the bucket of `key` if they are available right
+    /// now, without waiting
+    pub fn try_acquire<Q>(&self, key: &Q, tokens: u32) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let acquired = self.with_bucket(key, |bucket, now| {
+            bucket.try_take(&self.config, now, tokens)
+        });
+        record(
+            &self.name,
+            tokens.into(),
+            acquired.then_some(Duration::ZERO),
+        );
+        acquired
+    }
+
+    /// Take `tokens` from the bucket of `key`, waiting until they are
+    /// available, see [crate::TokenBucket::acquire]
+    pub async fn acquire<Q>(&self, key: &Q, tokens: u32) -> Result<(), ExceedsBurstError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.config.check(tokens)?;
+        let delay = self.with_bucket(key, |bucket, now| bucket.reserve(&self.config, now, tokens));
+        wait(&self.name, tokens, delay, &self.clock, || {
+            // A bucket in debt is never purged, but play safe
+            if let Some(bucket) = self.buckets.lock().buckets.get_mut(key) {
+                bucket.refund(&self.config, tokens);
+            }
+        })
+        .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+    use time_ext::TestClock;
+
+    #[test]
+    fn test_keyed() {
+        let clock = TestClock::new();
+        let limiter: KeyedTokenBucket<String, _> =
+            KeyedTokenBucket::with_clock("test_keyed", 2, 1.0, clock.clone());
+
+        assert!(limiter.try_acquire("a", 2));
+        assert!(!limiter.try_acquire("a", 1));
+        assert!(limiter.try_acquire("b", 1));
+        assert_eq!(limiter.len(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.try_acquire("a", 1));
+        assert!(!limiter.try_acquire("a", 1));
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let clock = TestClock::new();
+        let limiter: KeyedTokenBucket<String, _> =
+            KeyedTokenBucket::with_clock("test_keyed_acquire", 1, 1.0, clock.clone());
+        assert!(limiter.acquire("a", 2).await.is_err());
+        assert!(matches!(
+            limiter.acquire("a", 1).now_or_never(),
+            Some(Ok(()))
+        ));
+
+        assert!(limiter.try_acquire("b", 1));
+
+        let mut waiting = Box::pin(limiter.acquire("a", 1));
+        let mut dropped = Box::pin(limiter.acquire("b", 1));
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        assert!(futures::poll!(dropped.as_mut()).is_pending());
+        drop(dropped);
+
+        // Only the dropped waiter handed its token back
+        clock.advance(Duration::from_secs(1));
+        assert!(waiting.await.is_ok());
+        assert!(!limiter.try_acquire("a", 1));
+        assert!(limiter.try_acquire("b", 1));
+    }
+
+    #[test]
+    fn test_purge() {
+        let clock = TestClock::new();
+        let limiter = KeyedTokenBucket::with_clock("test_purge", 1, 1.0, clock.clone());
+        for key in 0..MIN_PURGE_THRESHOLD {
+            assert!(limiter.try_acquire(&key, 1));
+        }
+        assert_eq!(limiter.len(), MIN_PURGE_THRESHOLD);
+
+        // Once refilled, the buckets are dropped when a new key comes in
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.try_acquire(&0, 1));
+        assert!(limiter.try_acquire(&MIN_PURGE_THRESHOLD, 1));
+        assert_eq!(limiter.len(), 2);
+        assert!(!limiter.try_acquire(&0, 1));
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Crate providing async rate limiters based on token buckets. A bucket holds
+//! up to `burst` tokens and is refilled continuously at a fixed rate, each
+//! operation takes one or more tokens out of it. [TokenBucket] limits all its
+//! callers together, while [KeyedTokenBucket] keeps a separate bucket for each
+//! key, e.g. for each client. [SlidingWindowLimiter] instead limits each key
+//! to a number of units over a sliding window, tracking a bounded number of
+//! keys.
+//!
+//! The time is read and waited for through a [time_ext::Clock], so that
+//! tests can control it.
+//! Each limiter exports `rate_limiter.<name>.{acquired,throttled,wait_us}`.
+
+mod bucket;
+mod keyed;
+mod sliding_window;
+
+pub use crate::bucket::TokenBucket;
+pub use crate::keyed::KeyedTokenBucket;
+pub use crate::sliding_window::SlidingWindowLimiter;
+
+use thiserror::Error;
+
+/// Error returned when asking for more tokens than a bucket can ever hold
+#[derive(Debug, Error)]
+#[error("Requested {requested} tokens from a bucket with a burst of {burst}")]
+pub struct ExceedsBurstError {
+    /// Number of tokens requested
+    pub requested: u32,
+    /// Size of the bucket
+    pub burst: u32,
+}
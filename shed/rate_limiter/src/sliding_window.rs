@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use stats::prelude::*;
+use time_ext::{Clock, SystemClock};
+
+use crate::bucket::record;
+
+define_stats! {
+    prefix = "rate_limiter";
+    evicted_keys: dynamic_timeseries("{}.evicted_keys", (name: String); Rate, Sum),
+}
+
+/// Counts of a key in its current and previous windows
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    current: u64,
+    previous: u64,
+    /// Position of the key in `Windows::lru`
+    tick: u64,
+}
+
+impl Window {
+    /// Move the window forward to the one containing `now`
+    fn advance(&mut self, length: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= length * 2 {
+            self.start = now;
+            self.previous = 0;
+            self.current = 0;
+        } else if elapsed >= length {
+            self.start += length;
+            self.previous = self.current;
+            self.current = 0;
+        }
+    }
+
+    /// Estimate of the count over the last `length`, weighting the previous
+    /// window by how much of it still overlaps with the sliding window
+    fn estimate(&self, length: Duration, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f64();
+        let overlap = 1.0 - elapsed / length.as_secs_f64();
+        self.previous as f64 * overlap.max(0.0) + self.current as f64
+    }
+}
+
+struct Windows<K> {
+    windows: HashMap<K, Window>,
+    /// Keys by the tick they were last used at, the least recently used first
+    lru: BTreeMap<u64, K>,
+    next_tick: u64,
+}
+
+/// A sliding window limiter per key, allowing up to `limit` units for each key
+/// in any `window`, e.g. requests per client identity. The count over the
+/// sliding window is approximated from the counts of the current and previous
+/// fixed windows, which keeps the state of a key constant in size.
+///
+/// At most `max_keys` keys are tracked: when a new key comes in past that, the
+/// least recently used one is forgotten, which resets its limit. Make it
+/// larger than the number of callers expected within a window, the evictions
+/// are exported as `rate_limiter.<name>.evicted_keys`.
+pub struct SlidingWindowLimiter<K, C = SystemClock> {
+    name: String,
+    limit: u64,
+    window: Duration,
+    max_keys: usize,
+    windows: Mutex<Windows<K>>,
+    clock: C,
+}
+
+impl<K: Hash + Eq + Clone> SlidingWindowLimiter<K> {
+    /// Create a limiter whose stats are exported under `name`. Panics if
+    /// `window` or `max_keys` is zero.
+    pub fn new(name: impl Into<String>, limit: u64, window: Duration, max_keys: usize) -> Self {
+        Self::with_clock(name, limit, window, max_keys, SystemClock)
+    }
+}
+
+impl<K: Hash + Eq + Clone, C: Clock> SlidingWindowLimiter<K, C> {
+    /// Create a limiter reading the time from the provided clock, see
+    /// [SlidingWindowLimiter::new]
+    pub fn with_clock(
+        name: impl Into<String>,
+        limit: u64,
+        window: Duration,
+        max_keys: usize,
+        clock: C,
+    ) -> Self {
+        assert!(!window.is_zero(), "window must be positive");
+        assert!(max_keys > 0, "max_keys must be positive");
+        Self {
+            name: name.into(),
+            limit,
+            window,
+            max_keys,
+            windows: Mutex::new(Windows {
+                windows: HashMap::new(),
+                lru: BTreeMap::new(),
+                next_tick: 0,
+            }),
+            clock,
+        }
+    }
+
+    /// Name the stats of this limiter are exported under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of keys currently tracked
+    pub fn len(&self) -> usize {
+        self.windows.lock().windows.len()
+    }
+
+    /// Whether no key is currently tracked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Count `units` against the limit of `key` if they fit in it. Units that
+    /// are refused are not counted.
+    pub fn try_acquire<Q>(&self, key: &Q, units: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.clock.now();
+        let mut windows = self.windows.lock();
+        let windows = &mut *windows;
+        let tick = windows.next_tick;
+        windows.next_tick += 1;
+
+        let window = match windows.windows.get_mut(key) {
+            Some(window) => {
+                let key = windows
+                    .lru
+                    .remove(&window.tick)
+                    .expect("key missing from the LRU");
+                windows.lru.insert(tick, key);
+                window.tick = tick;
+                window
+            }
+            None => {
+                if windows.windows.len() >= self.max_keys {
+                    if let Some((_, evicted)) = windows.lru.pop_first() {
+                        windows.windows.remove::<K>(&evicted);
+                        STATS::evicted_keys.add_value(1, (self.name.clone(),));
+                    }
+                }
+                let key = key.to_owned();
+                windows.lru.insert(tick, key.clone());
+                windows.windows.entry(key).or_insert(Window {
+                    start: now,
+                    current: 0,
+                    previous: 0,
+                    tick,
+                })
+            }
+        };
+
+        window.advance(self.window, now);
+        let acquired = window.estimate(self.window, now) + units as f64 <= self.limit as f64;
+        if acquired {
+            window.current += units;
+        }
+        record(&self.name, units, acquired.then_some(Duration::ZERO));
+        acquired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time_ext::TestClock;
+
+    #[test]
+    fn test_sliding_window() {
+        let clock = TestClock::new();
+        let limiter: SlidingWindowLimiter<String, _> = SlidingWindowLimiter::with_clock(
+            "test_sliding_window",
+            10,
+            Duration::from_secs(10),
+            100,
+            clock.clone(),
+        );
+
+        assert!(limiter.try_acquire("a", 10));
+        assert!(!limiter.try_acquire("a", 1));
+        assert!(limiter.try_acquire("b", 1));
+
+        // Half of the previous window still counts
+        clock.advance(Duration::from_secs(15));
+        assert!(limiter.try_acquire("a", 5));
+        assert!(!limiter.try_acquire("a", 1));
+
+        // A new window starts, all of the previous one counts
+        clock.advance(Duration::from_secs(5));
+        assert!(limiter.try_acquire("a", 5));
+        assert!(!limiter.try_acquire("a", 1));
+
+        clock.advance(Duration::from_secs(20));
+        assert!(limiter.try_acquire("a", 10));
+    }
+
+    #[test]
+    fn test_lru() {
+        let clock = TestClock::new();
+        let limiter =
+            SlidingWindowLimiter::with_clock("test_lru", 1, Duration::from_secs(10), 2, clock);
+
+        assert!(limiter.try_acquire(&1, 1));
+        assert!(limiter.try_acquire(&2, 1));
+        assert!(!limiter.try_acquire(&1, 1));
+
+        // 2 is the least recently used key, so it is forgotten
+        assert!(limiter.try_acquire(&3, 1));
+        assert_eq!(limiter.len(), 2);
+        assert!(!limiter.try_acquire(&1, 1));
+        assert!(!limiter.try_acquire(&3, 1));
+        assert!(limiter.try_acquire(&2, 1));
+    }
+}
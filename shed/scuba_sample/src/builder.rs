@@ -8,19 +8,30 @@
  */
 
 //! See the [ScubaSampleBuilder] documentation
+//!
+//! In non-fbcode builds samples are written as JSON lines to a log file, a
+//! socket or any writer. Builders created with [ScubaSampleBuilder::new] log
+//! to `<dataset>.jsonl` in the directory named by the
+//! `SCUBA_SAMPLE_LOG_DIR` environment variable if it is set, so that code
+//! logging to Scuba datasets produces usable telemetry outside of fbcode.
+//! Logging to a socket never blocks: the samples are written by a background
+//! thread that reconnects when the socket fails, and those that can't be
+//! written are counted by [ScubaSampleBuilder::dropped_samples].
 
 use fbinit::FacebookInit;
 use serde_json::{Error, Value};
 use std::collections::hash_map::Entry;
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::{Error as IoError, Write};
+use std::fs::OpenOptions;
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::num::NonZeroU64;
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::log_writer::LogWriter;
 use crate::sample::ScubaSample;
 use crate::value::ScubaValue;
 use crate::Sampling;
@@ -30,17 +41,26 @@ use crate::Sampling;
 #[derive(Clone)]
 pub struct ScubaSampleBuilder {
     sample: ScubaSample,
-    log_file: Option<Arc<Mutex<File>>>,
+    log_file: Option<Arc<LogWriter>>,
     sampling: Sampling,
     seq: Option<Arc<(String, AtomicU64)>>,
 }
 
 impl ScubaSampleBuilder {
     /// Create a new instance of the Builder with initially an empty sample
-    /// that will preserve the sample in the provided dataset. The arguments
-    /// are used only in fbcode builds.
-    pub fn new<T: Into<String>>(_fb: FacebookInit, _dataset: T) -> Self {
-        Self::with_discard()
+    /// that will preserve the sample in the provided dataset. In non-fbcode
+    /// builds the sample is discarded, unless `SCUBA_SAMPLE_LOG_DIR` names a
+    /// directory in which case it is logged to `<dataset>.jsonl` in it.
+    pub fn new<T: Into<String>>(_fb: FacebookInit, dataset: T) -> Self {
+        let builder = Self::with_discard();
+        match std::env::var_os(LOG_DIR_VAR) {
+            Some(dir) => {
+                let log_file = Path::new(&dir).join(format!("{}.jsonl", dataset.into()));
+                // Telemetry must not prevent the service from running
+                builder.clone().with_log_file(log_file).unwrap_or(builder)
+            }
+            None => builder,
+        }
     }
 
     /// Create a new instance of the Builder with initially an empty sample
@@ -56,15 +76,51 @@ impl ScubaSampleBuilder {
 
     /// Create a new instance of the Builder with initially an empty sample
     /// that will preserve the sample in the provided log file.
-    pub fn with_log_file<L: AsRef<Path>>(mut self, log_file: L) -> Result<Self, IoError> {
+    pub fn with_log_file<L: AsRef<Path>>(self, log_file: L) -> Result<Self, IoError> {
         let log_file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(log_file)?;
-        self.log_file = Some(Arc::new(Mutex::new(log_file)));
+        Ok(self.with_log_writer(log_file))
+    }
+
+    /// Preserve the samples by writing them as JSON lines to the TCP socket
+    /// at `addr`, e.g. of a local log collector. Fails if it can't connect to
+    /// it, but reconnects in the background once connected.
+    pub fn with_log_socket<A: ToSocketAddrs>(mut self, addr: A) -> Result<Self, IoError> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let log = LogWriter::socket(move || connect_tcp(&addrs))?;
+        self.log_file = Some(Arc::new(log));
+        Ok(self)
+    }
+
+    /// Preserve the samples by writing them as JSON lines to the Unix socket
+    /// at `path`, see [ScubaSampleBuilder::with_log_socket].
+    #[cfg(unix)]
+    pub fn with_log_unix_socket<P: AsRef<Path>>(mut self, path: P) -> Result<Self, IoError> {
+        let path = path.as_ref().to_owned();
+        let log = LogWriter::socket(move || {
+            let socket = std::os::unix::net::UnixStream::connect(&path)?;
+            socket.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+            Ok(socket)
+        })?;
+        self.log_file = Some(Arc::new(log));
         Ok(self)
     }
 
+    /// Preserve the samples by writing them as JSON lines to `writer`.
+    pub fn with_log_writer<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.log_file = Some(Arc::new(LogWriter::writer(writer)));
+        self
+    }
+
+    /// Number of the samples logged by this builder, or the builders sharing
+    /// its log writer, that couldn't be written, e.g. because the socket they
+    /// are logged to is disconnected or can't keep up.
+    pub fn dropped_samples(&self) -> u64 {
+        self.log_file.as_ref().map_or(0, |log| log.dropped())
+    }
+
     /// Enable log sequencing.  Each sample from this builder (or its clones)
     /// will get a monotonically incrementing sequence number logged in the
     /// named field with each log.
@@ -175,14 +231,7 @@ impl ScubaSampleBuilder {
             return false;
         }
 
-        if let Some(ref log_file) = self.log_file {
-            if let Ok(sample) = self.to_json() {
-                let mut log_file = log_file.lock().expect("Poisoned lock");
-                let _ = log_file.write_all(sample.to_string().as_bytes());
-                let _ = log_file.write_all(b"\n");
-            }
-        }
-
+        self.write_sample();
         true
     }
 
@@ -196,24 +245,30 @@ impl ScubaSampleBuilder {
             return true;
         }
 
+        self.write_sample();
+        true
+    }
+
+    /// Write the sample as a line of JSON to the configured log writer. The
+    /// line is written at once so that concurrent writers to the same file or
+    /// collector don't interleave their samples.
+    fn write_sample(&self) {
         if let Some(ref log_file) = self.log_file {
-            if let Ok(sample) = self.sample.to_json() {
-                let mut log_file = log_file.lock().expect("Poisoned lock");
-                let _ = log_file.write_all(sample.to_string().as_bytes());
-                let _ = log_file.write_all(b"\n");
+            if let Ok(sample) = self.to_json() {
+                let mut line = sample.to_string();
+                line.push('\n');
+                log_file.write_line(line.into_bytes());
             }
         }
-
-        true
     }
 
     /// Either flush the configured client with the provided timeout or flush
     /// the configured log file making sure all the logged samples have been
-    /// written to it. The timeout is used only in fbcode builds.
-    pub fn flush(&self, _timeout: Duration) {
+    /// written to it. In non-fbcode builds the timeout bounds the wait for the
+    /// samples queued for a socket.
+    pub fn flush(&self, timeout: Duration) {
         if let Some(ref log_file) = self.log_file {
-            let mut log_file = log_file.lock().expect("Poisoned lock");
-            let _ = log_file.flush();
+            log_file.flush(timeout);
         }
     }
 
@@ -250,6 +305,30 @@ impl fmt::Debug for ScubaSampleBuilder {
     }
 }
 
+/// Environment variable naming the directory to log the samples of
+/// [ScubaSampleBuilder::new] to
+const LOG_DIR_VAR: &str = "SCUBA_SAMPLE_LOG_DIR";
+
+/// Timeouts of the sockets the samples are logged to, so that an unresponsive
+/// collector doesn't hold the samples queued behind
+const SOCKET_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connect to the first of `addrs` that accepts the connection
+fn connect_tcp(addrs: &[SocketAddr]) -> Result<TcpStream, IoError> {
+    let mut last_err = IoError::new(ErrorKind::InvalidInput, "no address to connect to");
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, SOCKET_CONNECT_TIMEOUT) {
+            Ok(socket) => {
+                socket.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+                return Ok(socket);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
 /// Enum representing commonly used server data written to the Scuba sample.
 pub enum ServerData {
     /// Hostname of the server
@@ -293,3 +372,118 @@ impl ServerData {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Cursor};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    /// Writer that can be inspected after being moved into a builder
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+            self.0.lock().expect("Poisoned lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+
+    fn lines(buffer: &SharedBuffer) -> Vec<Value> {
+        let buffer = buffer.0.lock().expect("Poisoned lock");
+        Cursor::new(&*buffer)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_log_writer() {
+        let buffer = SharedBuffer::default();
+        let mut builder = ScubaSampleBuilder::with_discard().with_log_writer(buffer.clone());
+        builder.add("int", 1).add("str", "foo");
+        assert!(builder.log_with_time(10));
+        builder.add("int", 2);
+        assert!(builder.log_with_time(20));
+
+        let lines = lines(&buffer);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["int"]["time"], 10);
+        assert_eq!(lines[0]["int"]["int"], 1);
+        assert_eq!(lines[0]["normal"]["str"], "foo");
+        assert_eq!(lines[1]["int"]["int"], 2);
+    }
+
+    #[test]
+    fn test_log_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut builder = ScubaSampleBuilder::with_discard()
+            .with_log_socket(listener.local_addr().unwrap())
+            .unwrap();
+        let (socket, _) = listener.accept().unwrap();
+
+        builder.add("int", 1);
+        builder.log();
+        builder.flush(Duration::from_secs(1));
+        let mut line = String::new();
+        BufReader::new(socket).read_line(&mut line).unwrap();
+        let sample: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(sample["int"]["int"], 1);
+        assert_eq!(builder.dropped_samples(), 0);
+    }
+
+    /// Log samples until `done` returns true, failing after a few seconds
+    fn log_until(
+        builder: &mut ScubaSampleBuilder,
+        mut done: impl FnMut(&ScubaSampleBuilder) -> bool,
+    ) {
+        for _ in 0..500 {
+            builder.log();
+            builder.flush(Duration::from_secs(1));
+            if done(builder) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("logging didn't get there");
+    }
+
+    #[test]
+    fn test_log_socket_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut builder = ScubaSampleBuilder::with_discard()
+            .with_log_socket(listener.local_addr().unwrap())
+            .unwrap();
+        builder.add("int", 1);
+
+        // The samples go to a new connection once the collector closed the
+        // first one
+        drop(listener.accept().unwrap());
+        listener.set_nonblocking(true).unwrap();
+        let mut socket = None;
+        log_until(&mut builder, |_| {
+            socket = listener.accept().ok();
+            socket.is_some()
+        });
+        let (socket, _) = socket.unwrap();
+        socket.set_nonblocking(false).unwrap();
+        builder.log();
+        builder.flush(Duration::from_secs(1));
+        let mut line = String::new();
+        BufReader::new(socket).read_line(&mut line).unwrap();
+        let sample: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(sample["int"]["int"], 1);
+
+        // Without a collector the samples are dropped, but don't block
+        drop(listener);
+        let dropped = builder.dropped_samples();
+        log_until(&mut builder, |builder| builder.dropped_samples() > dropped);
+    }
+}
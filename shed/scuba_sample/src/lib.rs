@@ -22,6 +22,7 @@ pub mod builder;
 pub mod sample;
 pub mod value;
 
+mod log_writer;
 mod sampling;
 
 pub use crate::builder::ScubaSampleBuilder;
@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Destinations of the JSON lines logged by [crate::ScubaSampleBuilder]
+
+use std::io::{Error as IoError, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of lines queued for a socket before the next ones are dropped
+const SOCKET_QUEUE_LEN: usize = 1024;
+/// Minimum time between two attempts to reconnect to a socket
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Time between two attempts to queue a flush while the queue is full
+const FLUSH_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+type Connect = Box<dyn FnMut() -> Result<Box<dyn Write + Send>, IoError> + Send>;
+
+enum Message {
+    Line(Vec<u8>),
+    Flush(SyncSender<()>),
+}
+
+enum Sink {
+    Writer(Mutex<Box<dyn Write + Send>>),
+    /// Lines written by a background thread, so that logging doesn't block on
+    /// the socket
+    Socket(SyncSender<Message>),
+}
+
+/// Writer of the logged lines, counting the lines that couldn't be written
+pub(crate) struct LogWriter {
+    sink: Sink,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogWriter {
+    /// Write the lines to `writer` as they are logged
+    pub(crate) fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Sink::Writer(Mutex::new(Box::new(writer))),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Write the lines in the background to the socket returned by `connect`,
+    /// which is called again to reconnect after the socket failed. The lines
+    /// logged while the queue is full or the socket is disconnected are
+    /// dropped. Fails if the first connection fails.
+    pub(crate) fn socket<F, W>(mut connect: F) -> Result<Self, IoError>
+    where
+        F: FnMut() -> Result<W, IoError> + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let socket: Box<dyn Write + Send> = Box::new(connect()?);
+        let connect: Connect = Box::new(move || Ok(Box::new(connect()?)));
+        let (sender, receiver) = mpsc::sync_channel(SOCKET_QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        std::thread::Builder::new()
+            .name("scuba-log".to_owned())
+            .spawn({
+                let dropped = dropped.clone();
+                move || write_to_socket(socket, connect, receiver, &dropped)
+            })?;
+        Ok(Self {
+            sink: Sink::Socket(sender),
+            dropped,
+        })
+    }
+
+    /// Write `line`, or count it as dropped
+    pub(crate) fn write_line(&self, line: Vec<u8>) {
+        match &self.sink {
+            Sink::Writer(writer) => {
+                let mut writer = writer.lock().expect("Poisoned lock");
+                if writer.write_all(&line).is_err() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Sink::Socket(sender) => match sender.try_send(Message::Line(line)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+        }
+    }
+
+    /// Flush the lines written so far, waiting up to `timeout` for those
+    /// queued for a socket, including the wait for room in the queue
+    pub(crate) fn flush(&self, timeout: Duration) {
+        match &self.sink {
+            Sink::Writer(writer) => {
+                let _ = writer.lock().expect("Poisoned lock").flush();
+            }
+            Sink::Socket(sender) => {
+                let deadline = Instant::now() + timeout;
+                let (done, flushed) = mpsc::sync_channel(1);
+                let mut message = Message::Flush(done);
+                loop {
+                    match sender.try_send(message) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(full)) if Instant::now() < deadline => {
+                            message = full;
+                            std::thread::sleep(FLUSH_RETRY_DELAY);
+                        }
+                        Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => return,
+                    }
+                }
+                let _ = flushed.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+            }
+        }
+    }
+
+    /// Number of lines that couldn't be written
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Write the queued lines to the socket until all the senders are gone
+fn write_to_socket(
+    socket: Box<dyn Write + Send>,
+    connect: Connect,
+    receiver: Receiver<Message>,
+    dropped: &AtomicU64,
+) {
+    let mut socket = Socket {
+        connected: Some(socket),
+        connect,
+        last_attempt: Instant::now(),
+    };
+    for message in receiver {
+        match message {
+            Message::Line(line) => {
+                if !socket.write_line(&line) {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Message::Flush(done) => {
+                socket.flush();
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+struct Socket {
+    connected: Option<Box<dyn Write + Send>>,
+    connect: Connect,
+    last_attempt: Instant,
+}
+
+impl Socket {
+    fn reconnect(&mut self) {
+        self.last_attempt = Instant::now();
+        self.connected = (self.connect)().ok();
+    }
+
+    /// Write `line`, reconnecting if the socket failed, but not more often
+    /// than every [RECONNECT_DELAY] while the reconnections fail
+    fn write_line(&mut self, line: &[u8]) -> bool {
+        if self.connected.is_none() {
+            if self.last_attempt.elapsed() < RECONNECT_DELAY {
+                return false;
+            }
+            self.reconnect();
+        }
+        for attempt in 0..2 {
+            let connected = match &mut self.connected {
+                Some(connected) => connected,
+                None => break,
+            };
+            if connected.write_all(line).is_ok() {
+                return true;
+            }
+            // Retry the line once on a new socket
+            if attempt == 0 {
+                self.reconnect();
+            }
+        }
+        self.connected = None;
+        false
+    }
+
+    fn flush(&mut self) {
+        if let Some(connected) = &mut self.connected {
+            if connected.flush().is_err() {
+                self.connected = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Socket whose writes block until it is released
+    struct StuckSocket(Arc<Mutex<()>>);
+
+    impl Write for StuckSocket {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+            let _released = self.0.lock().expect("Poisoned lock");
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_full_queue() {
+        let stuck = Arc::new(Mutex::new(()));
+        let release = stuck.lock().expect("Poisoned lock");
+        let writer = LogWriter::socket({
+            let stuck = stuck.clone();
+            move || Ok(StuckSocket(stuck.clone()))
+        })
+        .unwrap();
+        // One line blocks the thread, the others fill the queue
+        for _ in 0..=SOCKET_QUEUE_LEN * 2 {
+            writer.write_line(b"line\n".to_vec());
+        }
+        assert!(writer.dropped() > 0);
+
+        let start = Instant::now();
+        writer.flush(Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        drop(release);
+        writer.flush(Duration::from_secs(5));
+    }
+}
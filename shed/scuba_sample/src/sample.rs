@@ -13,7 +13,8 @@ use crate::value::{NullScubaValue, ScubaValue};
 
 use serde_json::{Error, Map, Number, Value};
 use std::collections::hash_map::{Entry, HashMap};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
+use time_ext::{Clock, SystemClock};
 
 const TIME_COLUMN: &str = "time";
 const INT_KEY: &str = "int";
@@ -36,14 +37,13 @@ impl ScubaSample {
     /// Create a new empty sample with the current timestamp as the timestamp of
     /// this sample
     pub fn new() -> Self {
-        ScubaSample {
-            time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Current timestamp is earlier than UNIX epoch")
-                .as_secs(),
-            subset: None,
-            values: HashMap::new(),
-        }
+        Self::with_clock(&SystemClock)
+    }
+
+    /// Create a new empty sample with the current timestamp of the provided
+    /// clock as the timestamp of this sample
+    pub fn with_clock(clock: &dyn Clock) -> Self {
+        Self::with_timestamp(seconds_since_epoch(clock))
     }
 
     /// Joins the values from another scuba sample to the current one.
@@ -127,10 +127,13 @@ impl ScubaSample {
 
     /// Reset the time of this sample with the current timestamp.
     pub fn set_time_now(&mut self) -> &mut Self {
-        self.time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Current timestamp is earlier than UNIX epoch")
-            .as_secs();
+        self.set_time_from(&SystemClock)
+    }
+
+    /// Reset the time of this sample with the current timestamp of the
+    /// provided clock.
+    pub fn set_time_from(&mut self, clock: &dyn Clock) -> &mut Self {
+        self.time = seconds_since_epoch(clock);
         self
     }
 
@@ -191,6 +194,14 @@ impl ScubaSample {
     }
 }
 
+fn seconds_since_epoch(clock: &dyn Clock) -> u64 {
+    clock
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .expect("Current timestamp is earlier than UNIX epoch")
+        .as_secs()
+}
+
 impl Default for ScubaSample {
     fn default() -> Self {
         Self::new()
@@ -230,6 +241,18 @@ mod tests {
     use serde_json::json;
     use std::collections::HashSet;
 
+    #[test]
+    fn with_clock() {
+        let clock = time_ext::TestClock::new();
+        clock.set_system_time(UNIX_EPOCH + std::time::Duration::from_secs(100));
+        let mut sample = ScubaSample::with_clock(&clock);
+        assert_eq!(sample.time, 100);
+
+        clock.set_system_time(UNIX_EPOCH + std::time::Duration::from_secs(200));
+        sample.set_time_from(&clock);
+        assert_eq!(sample.time, 200);
+    }
+
     /// Test that JSON serialization of a ScubaSample matches the expected format.
     #[test]
     fn to_json() {
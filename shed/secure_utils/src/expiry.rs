@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::{Context, Result};
+use fbinit::FacebookInit;
+use openssl::asn1::Asn1Time;
+use openssl::x509::X509Ref;
+use slog::{warn, Logger};
+use stats::prelude::*;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::SslConfig;
+
+define_stats! {
+    prefix = "secure_utils.cert_expiry";
+    days_until_expiry: dynamic_singleton_counter("{}.{}.days_until_expiry", (name: String, kind: String)),
+}
+
+/// Expiry of one of the certificates of a [SslConfig]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertExpiry {
+    /// File the certificate was read from
+    pub path: String,
+    /// Subject of the certificate, e.g. `CN=localhost,O=Example`
+    pub subject: String,
+    /// Whole days left until the certificate expires, negative if it already
+    /// expired
+    pub days_until_expiry: i32,
+}
+
+struct Inner {
+    fb: FacebookInit,
+    name: String,
+    config: SslConfig,
+    warn_below_days: i32,
+    on_warning: Box<dyn Fn(&CertExpiry) + Send + Sync>,
+}
+
+/// Periodically inspects the certificates of a [SslConfig], i.e. both the CAs
+/// and the identity, exporting the days left until the first of the CAs and
+/// the first of the identity and its chain expire as the
+/// `secure_utils.cert_expiry.<name>.ca.days_until_expiry` and
+/// `secure_utils.cert_expiry.<name>.identity.days_until_expiry` counters, and
+/// calling a warning callback for the certificates that expire soon.
+#[derive(Clone)]
+pub struct CertExpiryMonitor {
+    inner: Arc<Inner>,
+}
+
+impl CertExpiryMonitor {
+    /// Create a monitor that calls `on_warning` for every certificate that
+    /// has fewer than `warn_below_days` days left on every check. The counters
+    /// are exported under `name`, which identifies the config among the
+    /// monitored ones.
+    pub fn new(
+        fb: FacebookInit,
+        name: impl Into<String>,
+        config: SslConfig,
+        warn_below_days: i32,
+        on_warning: impl Fn(&CertExpiry) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                fb,
+                name: name.into(),
+                config,
+                warn_below_days,
+                on_warning: Box::new(on_warning),
+            }),
+        }
+    }
+
+    /// Inspect the certificates once, export their expiry and call the
+    /// warning callback where needed. Returns the expiry of every certificate.
+    pub fn check(&self) -> Result<Vec<CertExpiry>> {
+        let now = Asn1Time::days_from_now(0)?;
+        let mut expiries = Vec::new();
+        for (path, cert) in self.inner.config.certificates()? {
+            let days_until_expiry = now
+                .diff(cert.not_after())
+                .with_context(|| format!("While computing expiry of certificate in {}", path))?
+                .days;
+            expiries.push(CertExpiry {
+                path: path.to_owned(),
+                subject: subject(&cert),
+                days_until_expiry,
+            });
+        }
+
+        let (mut ca_days, mut identity_days) = (None, None);
+        for expiry in &expiries {
+            let soonest = if expiry.path == self.inner.config.ca_pem {
+                &mut ca_days
+            } else {
+                &mut identity_days
+            };
+            *soonest = Some(soonest.map_or(expiry.days_until_expiry, |days: i32| {
+                days.min(expiry.days_until_expiry)
+            }));
+            if expiry.days_until_expiry < self.inner.warn_below_days {
+                (self.inner.on_warning)(expiry);
+            }
+        }
+        for (kind, days) in [("ca", ca_days), ("identity", identity_days)] {
+            if let Some(days) = days {
+                STATS::days_until_expiry.set_value(
+                    self.inner.fb,
+                    days as i64,
+                    (self.inner.name.clone(), kind.to_owned()),
+                );
+            }
+        }
+        Ok(expiries)
+    }
+
+    /// Spawn a thread running [CertExpiryMonitor::check] every `interval`,
+    /// logging failures to read the certificates. The thread stops once all
+    /// the clones of this object are dropped.
+    pub fn spawn(&self, logger: Logger, interval: Duration) -> Result<()> {
+        let inner = Arc::downgrade(&self.inner);
+        thread::Builder::new()
+            .name("tls-cert-expiry".into())
+            .spawn(move || monitor_thread(inner, logger, interval))?;
+        Ok(())
+    }
+}
+
+fn monitor_thread(inner: Weak<Inner>, logger: Logger, interval: Duration) {
+    loop {
+        let this = match inner.upgrade() {
+            Some(inner) => CertExpiryMonitor { inner },
+            None => return,
+        };
+        if let Err(e) = this.check() {
+            warn!(
+                logger,
+                "Failed to check expiry of TLS certificates due to {:#?}", e
+            );
+        }
+        drop(this);
+        thread::sleep(interval);
+    }
+}
+
+fn subject(cert: &X509Ref) -> String {
+    cert.subject_name()
+        .entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            match entry.data().to_string() {
+                Ok(value) => format!("{}={}", key, value),
+                Err(_) => format!("{}=?", key),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::CertSpec;
+    use std::sync::Mutex;
+    use tempdir::TempDir;
+
+    fn write_cert(dir: &TempDir, name: &str, days: i64) -> String {
+        let cn = [("CN", name)];
+        CertSpec {
+            subject: &cn,
+            // Leave an hour of margin so that the whole days left don't
+            // change while the test runs
+            valid_for_secs: days * 24 * 60 * 60 + 60 * 60,
+            ..Default::default()
+        }
+        .build()
+        .write_cert(dir.path(), &format!("{}.pem", name))
+    }
+
+    #[fbinit::test]
+    fn test_cert_expiry_monitor(fb: FacebookInit) {
+        let dir = TempDir::new("secure_utils").unwrap();
+        let ca = write_cert(&dir, "ca", 365);
+        let cert = write_cert(&dir, "leaf", 10);
+        let config = SslConfig::new(&ca, &cert, "unused", None::<&str>);
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let monitor = CertExpiryMonitor::new(fb, "frontend", config, 30, {
+            let warnings = warnings.clone();
+            move |expiry: &CertExpiry| warnings.lock().unwrap().push(expiry.clone())
+        });
+
+        let expiries = monitor.check().unwrap();
+        assert_eq!(
+            expiries,
+            vec![
+                CertExpiry {
+                    path: ca,
+                    subject: "CN=ca".to_owned(),
+                    days_until_expiry: 365,
+                },
+                CertExpiry {
+                    path: cert,
+                    subject: "CN=leaf".to_owned(),
+                    days_until_expiry: 10,
+                },
+            ]
+        );
+        assert_eq!(*warnings.lock().unwrap(), expiries[1..]);
+
+        // Outside of fbcode the counters are recorded in memory
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert_eq!(
+                stats["secure_utils.cert_expiry.frontend.ca.days_until_expiry"],
+                365
+            );
+            assert_eq!(
+                stats["secure_utils.cert_expiry.frontend.identity.days_until_expiry"],
+                10
+            );
+        }
+    }
+}
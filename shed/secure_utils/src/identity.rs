@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::{anyhow, Context, Result};
+use openssl::nid::Nid;
+use openssl::ssl::SslRef;
+use openssl::x509::X509Ref;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Identity of a TLS peer as found in its leaf certificate. Names are
+/// normalized: DNS names are lowercased and stripped of the trailing dot, so
+/// that they can be compared directly with configured values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// Common name of the subject, if any
+    pub common_name: Option<String>,
+    /// DNS subject alternative names
+    pub dns_names: Vec<String>,
+    /// URI subject alternative names, e.g. SPIFFE ids
+    pub uris: Vec<String>,
+    /// Email subject alternative names
+    pub emails: Vec<String>,
+    /// IP address subject alternative names
+    pub ip_addresses: Vec<IpAddr>,
+    /// Other attributes of the subject, keyed by their long name (e.g.
+    /// `organizationalUnitName`) or, for attributes unknown to OpenSSL, by
+    /// their dotted OID. An attribute can appear multiple times.
+    pub attributes: BTreeMap<String, Vec<String>>,
+}
+
+impl PeerIdentity {
+    /// Extract the identity of the peer of an established connection. Fails
+    /// if the peer didn't present a certificate.
+    pub fn from_ssl(ssl: &SslRef) -> Result<Self> {
+        let cert = ssl
+            .peer_certificate()
+            .ok_or_else(|| anyhow!("Peer didn't present a certificate"))?;
+        Self::from_cert(&cert)
+    }
+
+    /// Extract the identity from a certificate
+    pub fn from_cert(cert: &X509Ref) -> Result<Self> {
+        let mut identity = Self::default();
+
+        for entry in cert.subject_name().entries() {
+            let value = entry
+                .data()
+                .to_string()
+                .context("While decoding subject of the certificate")?;
+            if entry.object().nid() == Nid::COMMONNAME {
+                identity.common_name.get_or_insert(value);
+            } else {
+                identity
+                    .attributes
+                    .entry(entry.object().to_string())
+                    .or_default()
+                    .push(value);
+            }
+        }
+
+        for name in cert.subject_alt_names().into_iter().flatten() {
+            if let Some(dns) = name.dnsname() {
+                identity.dns_names.push(normalize_dns_name(dns));
+            } else if let Some(uri) = name.uri() {
+                identity.uris.push(uri.to_string());
+            } else if let Some(email) = name.email() {
+                identity.emails.push(email.to_string());
+            } else if let Some(ip) = name.ipaddress() {
+                identity.ip_addresses.push(parse_ip(ip)?);
+            }
+        }
+
+        Ok(identity)
+    }
+
+    /// First value of the subject attribute with the given key, see
+    /// [PeerIdentity::attributes]
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .get(key)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+
+    /// Whether the peer has the given DNS name, either as a subject
+    /// alternative name or, if it has none, as the common name
+    pub fn has_dns_name(&self, name: &str) -> bool {
+        let name = normalize_dns_name(name);
+        if self.dns_names.is_empty() {
+            matches!(&self.common_name, Some(cn) if normalize_dns_name(cn) == name)
+        } else {
+            self.dns_names.contains(&name)
+        }
+    }
+}
+
+fn normalize_dns_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn parse_ip(bytes: &[u8]) -> Result<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(bytes);
+            Ok(IpAddr::from(octets))
+        }
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(bytes);
+            Ok(IpAddr::from(octets))
+        }
+        len => Err(anyhow!(
+            "Invalid IP address of length {} in certificate",
+            len
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::CertSpec;
+
+    #[test]
+    fn test_peer_identity() {
+        let cert = CertSpec {
+            subject: &[
+                ("CN", "client"),
+                ("OU", "storage"),
+                ("1.3.6.1.4.1.99999.1", "tier-1"),
+            ],
+            dns_names: &["Client.Example.com."],
+            uris: &["spiffe://example.com/client"],
+            ips: &["10.0.0.1"],
+            ..Default::default()
+        }
+        .build()
+        .cert;
+
+        let identity = PeerIdentity::from_cert(&cert).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("client"));
+        assert_eq!(identity.dns_names, vec!["client.example.com"]);
+        assert_eq!(identity.uris, vec!["spiffe://example.com/client"]);
+        assert_eq!(identity.ip_addresses, vec![IpAddr::from([10, 0, 0, 1])]);
+        assert!(identity.emails.is_empty());
+        assert_eq!(
+            identity.attribute("organizationalUnitName"),
+            Some("storage")
+        );
+        assert_eq!(identity.attribute("1.3.6.1.4.1.99999.1"), Some("tier-1"));
+        assert!(identity.has_dns_name("CLIENT.example.com"));
+        assert!(!identity.has_dns_name("client"));
+    }
+}
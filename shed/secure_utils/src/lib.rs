@@ -8,29 +8,82 @@
  */
 
 //! Crate with useful security utilities
+//!
+//! The TLS building blocks come with two backends: the openssl based one,
+//! enabled by the default `openssl_backend` feature, and the rustls based one
+//! in the `rustls_backend` module, enabled by the `rustls_backend` feature.
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
-
+// Without any backend there is nothing to build from the config
+#![cfg_attr(
+    not(any(feature = "openssl_backend", feature = "rustls_backend")),
+    allow(dead_code)
+)]
+
+#[cfg(feature = "openssl_backend")]
+mod expiry;
 #[cfg(fbcode_build)]
 pub mod facebook;
-#[cfg(not(fbcode_build))]
+#[cfg(feature = "openssl_backend")]
+mod identity;
+#[cfg(feature = "openssl_backend")]
+mod openssl_backend;
+#[cfg(all(not(fbcode_build), feature = "openssl_backend"))]
 mod oss;
+#[cfg(feature = "openssl_backend")]
+mod pinning;
+#[cfg(feature = "openssl_backend")]
+mod reloadable;
+#[cfg(feature = "rustls_backend")]
+pub mod rustls_backend;
+#[cfg(test)]
+mod test_utils;
+
+#[cfg(feature = "openssl_backend")]
+pub use crate::expiry::{CertExpiry, CertExpiryMonitor};
+#[cfg(feature = "openssl_backend")]
+pub use crate::identity::PeerIdentity;
+#[cfg(feature = "openssl_backend")]
+pub use crate::openssl_backend::{build_identity, read_pkcs12, read_x509, read_x509_stack};
+#[cfg(feature = "openssl_backend")]
+pub use crate::pinning::{PinMismatchError, SpkiPin, SpkiPinSet};
+#[cfg(feature = "openssl_backend")]
+pub use crate::reloadable::ReloadableSslConfig;
 
 use anyhow::{Context, Result};
-use openssl::pkcs12::ParsedPkcs12;
-use openssl::pkey::PKey;
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslMethod, SslVerifyMode};
-use openssl::x509::X509;
-use slog::Logger;
+use std::fmt;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+/// Files the certificate and private key of the TLS acceptor are read from
+#[derive(Clone)]
+enum IdentityFiles {
+    Pem { cert: String, private_key: String },
+    Pkcs12 { bundle: String, password: String },
+}
+
+impl fmt::Debug for IdentityFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pem { cert, private_key } => f
+                .debug_struct("Pem")
+                .field("cert", cert)
+                .field("private_key", private_key)
+                .finish(),
+            // Don't leak the password into logs
+            Self::Pkcs12 { bundle, .. } => f
+                .debug_struct("Pkcs12")
+                .field("bundle", bundle)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
 /// Certificates for the TLS acceptor
 #[derive(Clone, Debug)]
 pub struct SslConfig {
     ca_pem: String,
-    cert: String,
-    private_key: String,
+    identity: IdentityFiles,
     #[allow(unused)] // TODO unused warning after rustc upgrade
     tls_seed_path: Option<PathBuf>,
 }
@@ -45,72 +98,34 @@ impl SslConfig {
     ) -> Self {
         Self {
             ca_pem: ca_pem.into(),
-            cert: cert.into(),
-            private_key: private_key.into(),
+            identity: IdentityFiles::Pem {
+                cert: cert.into(),
+                private_key: private_key.into(),
+            },
             tls_seed_path: tls_seed_path.map(|x| x.into()),
         }
     }
 
-    /// Builds the tls acceptor
-    pub fn build_tls_acceptor(self, logger: Logger) -> Result<SslAcceptor> {
-        Ok(self.tls_acceptor_builder(logger)?.build())
-    }
-
-    /// Creates a acceptor builder with Ssl security configs pre set.
-    fn inner_tls_acceptor_builder(self) -> Result<SslAcceptorBuilder> {
-        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
-
-        let pkcs12 =
-            build_identity(self.cert, self.private_key).context("failed to build pkcs12")?;
-        acceptor.set_certificate(&pkcs12.cert)?;
-        acceptor.set_private_key(&pkcs12.pkey)?;
-
-        // Set up client authentication via root certificate
-        for cert in read_x509_stack(self.ca_pem)? {
-            acceptor.cert_store_mut().add_cert(cert)?;
+    /// Create a new instance of SslConfig with the certificate, its chain and
+    /// the private key read from a password protected PKCS#12 (`.p12`/`.pfx`)
+    /// bundle instead of separate pem files
+    pub fn from_pkcs12(
+        ca_pem: impl Into<String>,
+        bundle: impl Into<String>,
+        password: impl Into<String>,
+        tls_seed_path: Option<impl Into<PathBuf>>,
+    ) -> Self {
+        Self {
+            ca_pem: ca_pem.into(),
+            identity: IdentityFiles::Pkcs12 {
+                bundle: bundle.into(),
+                password: password.into(),
+            },
+            tls_seed_path: tls_seed_path.map(|x| x.into()),
         }
-        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
-
-        Ok(acceptor)
     }
 }
 
-/// Read certificate and private key data from pem files and convert it into native_tls::Identity
-/// archive
-pub fn build_identity(
-    cert_pem_file: impl AsRef<Path>,
-    private_key_pem_file: impl AsRef<Path>,
-) -> Result<ParsedPkcs12> {
-    let cert = read_x509(cert_pem_file)?;
-
-    // Read PEM-formatted input file as bytes.
-    let key_pem = read_bytes(private_key_pem_file)?;
-
-    // Parse PEM-encoded data into appropriate formats for each item.
-    let pkey = PKey::private_key_from_pem(&key_pem)?;
-
-    Ok(ParsedPkcs12 {
-        pkey,
-        cert,
-        chain: None,
-    })
-}
-
-/// Read certificate pem file and decode it as X509
-pub fn read_x509<P: AsRef<Path>>(cert_pem_file: P) -> Result<X509> {
-    // Read PEM-formatted input file as bytes.
-    let cert_pem = read_bytes(cert_pem_file)?;
-    let cert = X509::from_pem(&cert_pem)?;
-    Ok(cert)
-}
-
-/// Read certificate pem file and decode it as stack of X509
-pub fn read_x509_stack<P: AsRef<Path>>(cert_pem_file: P) -> Result<Vec<X509>> {
-    let cert_pem = read_bytes(cert_pem_file)?;
-    let certs = X509::stack_from_pem(&cert_pem)?;
-    Ok(certs)
-}
-
 fn read_bytes<T: AsRef<Path>>(path: T) -> Result<Vec<u8>> {
     let path = path.as_ref();
     (|| -> Result<_> {
@@ -0,0 +1,228 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! [openssl] based implementation of the TLS building blocks of this crate.
+//! Enabled with the `openssl_backend` feature, which is on by default.
+
+use anyhow::{Context, Result};
+use openssl::pkcs12::{ParsedPkcs12, Pkcs12};
+use openssl::pkey::PKey;
+use openssl::ssl::{
+    SslAcceptor, SslAcceptorBuilder, SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode,
+};
+use openssl::x509::X509;
+use slog::Logger;
+use std::path::Path;
+
+use crate::{read_bytes, IdentityFiles, SslConfig};
+
+impl IdentityFiles {
+    fn build(self) -> Result<ParsedPkcs12> {
+        match self {
+            Self::Pem { cert, private_key } => build_identity(cert, private_key),
+            Self::Pkcs12 { bundle, password } => read_pkcs12(bundle, &password),
+        }
+    }
+}
+
+impl SslConfig {
+    /// Builds the tls acceptor
+    pub fn build_tls_acceptor(self, logger: Logger) -> Result<SslAcceptor> {
+        Ok(self.tls_acceptor_builder(logger)?.build())
+    }
+
+    /// Builds a tls connector that presents the same identity as the acceptor
+    /// and trusts the same certificate authorities
+    pub fn build_tls_connector(self) -> Result<SslConnector> {
+        Ok(self.tls_connector_builder()?.build())
+    }
+
+    /// Creates a connector builder with Ssl security configs pre set.
+    pub fn tls_connector_builder(self) -> Result<SslConnectorBuilder> {
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+
+        let pkcs12 = self.identity.build().context("failed to build pkcs12")?;
+        connector.set_certificate(&pkcs12.cert)?;
+        connector.set_private_key(&pkcs12.pkey)?;
+        for cert in pkcs12.chain.into_iter().flatten() {
+            connector.add_extra_chain_cert(cert)?;
+        }
+
+        for cert in read_x509_stack(self.ca_pem)? {
+            connector.cert_store_mut().add_cert(cert)?;
+        }
+
+        Ok(connector)
+    }
+
+    /// All the certificates of this config, i.e. the CAs, the identity and
+    /// its chain, along with the file they were read from
+    pub(crate) fn certificates(&self) -> Result<Vec<(&str, X509)>> {
+        let mut certs: Vec<_> = read_x509_stack(&self.ca_pem)?
+            .into_iter()
+            .map(|cert| (self.ca_pem.as_str(), cert))
+            .collect();
+        match &self.identity {
+            IdentityFiles::Pem { cert, .. } => certs.extend(
+                read_x509_stack(cert)?
+                    .into_iter()
+                    .map(|c| (cert.as_str(), c)),
+            ),
+            IdentityFiles::Pkcs12 { bundle, password } => {
+                let pkcs12 = read_pkcs12(bundle, password)?;
+                certs.push((bundle.as_str(), pkcs12.cert));
+                certs.extend(
+                    pkcs12
+                        .chain
+                        .into_iter()
+                        .flatten()
+                        .map(|c| (bundle.as_str(), c)),
+                );
+            }
+        }
+        Ok(certs)
+    }
+
+    /// Creates a acceptor builder with Ssl security configs pre set.
+    pub(crate) fn inner_tls_acceptor_builder(self) -> Result<SslAcceptorBuilder> {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+
+        let pkcs12 = self.identity.build().context("failed to build pkcs12")?;
+        acceptor.set_certificate(&pkcs12.cert)?;
+        acceptor.set_private_key(&pkcs12.pkey)?;
+        for cert in pkcs12.chain.into_iter().flatten() {
+            acceptor.add_extra_chain_cert(cert)?;
+        }
+
+        // Set up client authentication via root certificate
+        for cert in read_x509_stack(self.ca_pem)? {
+            acceptor.cert_store_mut().add_cert(cert)?;
+        }
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+
+        Ok(acceptor)
+    }
+}
+
+/// Read certificate and private key data from pem files and convert it into native_tls::Identity
+/// archive
+pub fn build_identity(
+    cert_pem_file: impl AsRef<Path>,
+    private_key_pem_file: impl AsRef<Path>,
+) -> Result<ParsedPkcs12> {
+    let cert = read_x509(cert_pem_file)?;
+
+    // Read PEM-formatted input file as bytes.
+    let key_pem = read_bytes(private_key_pem_file)?;
+
+    // Parse PEM-encoded data into appropriate formats for each item.
+    let pkey = PKey::private_key_from_pem(&key_pem)?;
+
+    Ok(ParsedPkcs12 {
+        pkey,
+        cert,
+        chain: None,
+    })
+}
+
+/// Read a password protected PKCS#12 (`.p12`/`.pfx`) bundle and decode the
+/// certificate, its chain and the private key stored in it
+pub fn read_pkcs12(bundle_file: impl AsRef<Path>, password: &str) -> Result<ParsedPkcs12> {
+    let bundle_file = bundle_file.as_ref();
+    let der = read_bytes(bundle_file)?;
+    let pkcs12 = Pkcs12::from_der(&der)
+        .with_context(|| format!("While decoding PKCS#12 bundle {}", bundle_file.display()))?;
+    let parsed = pkcs12.parse(password).with_context(|| {
+        format!(
+            "While parsing PKCS#12 bundle {}, is the password correct?",
+            bundle_file.display()
+        )
+    })?;
+    Ok(parsed)
+}
+
+/// Read certificate pem file and decode it as X509
+pub fn read_x509<P: AsRef<Path>>(cert_pem_file: P) -> Result<X509> {
+    // Read PEM-formatted input file as bytes.
+    let cert_pem = read_bytes(cert_pem_file)?;
+    let cert = X509::from_pem(&cert_pem)?;
+    Ok(cert)
+}
+
+/// Read certificate pem file and decode it as stack of X509
+pub fn read_x509_stack<P: AsRef<Path>>(cert_pem_file: P) -> Result<Vec<X509>> {
+    let cert_pem = read_bytes(cert_pem_file)?;
+    let certs = X509::stack_from_pem(&cert_pem)?;
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{write, CertSpec};
+    use openssl::stack::Stack;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_pkcs12() {
+        let dir = TempDir::new("secure_utils").unwrap();
+        let ca = CertSpec {
+            subject: &[("CN", "ca")],
+            is_ca: true,
+            ..Default::default()
+        }
+        .build();
+        let leaf = CertSpec {
+            issuer: Some(&ca),
+            ..Default::default()
+        }
+        .build();
+        let ca_pem = ca.write_cert(dir.path(), "ca.pem");
+
+        let mut chain = Stack::new().unwrap();
+        chain.push(ca.cert.clone()).unwrap();
+        let bundle = Pkcs12::builder()
+            .name("leaf")
+            .pkey(&leaf.key)
+            .cert(&leaf.cert)
+            .ca(chain)
+            .build2("secret")
+            .unwrap();
+        let bundle = write(dir.path(), "leaf.p12", &bundle.to_der().unwrap());
+
+        let config = SslConfig::from_pkcs12(&ca_pem, &bundle, "secret", None::<&str>);
+        assert!(!format!("{:?}", config).contains("secret"));
+        let certs: Vec<_> = config
+            .certificates()
+            .unwrap()
+            .into_iter()
+            .map(|(path, cert)| (path.to_owned(), cert.to_der().unwrap()))
+            .collect();
+        assert_eq!(
+            certs,
+            vec![
+                (ca_pem.clone(), ca.cert.to_der().unwrap()),
+                (bundle.clone(), leaf.cert.to_der().unwrap()),
+                (bundle.clone(), ca.cert.to_der().unwrap()),
+            ]
+        );
+
+        let logger = Logger::root(slog::Discard, slog::o!());
+        config.clone().build_tls_acceptor(logger).unwrap();
+        config.build_tls_connector().unwrap();
+
+        let config = SslConfig::from_pkcs12(&ca_pem, &bundle, "wrong", None::<&str>);
+        let err = config.build_tls_connector().unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("is the password correct?"),
+            "{:#}",
+            err
+        );
+    }
+}
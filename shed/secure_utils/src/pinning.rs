@@ -0,0 +1,174 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::{Context, Error, Result};
+use openssl::sha::sha256;
+use openssl::ssl::{SslConnectorBuilder, SslRef, SslVerifyMode};
+use openssl::x509::X509Ref;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// SHA-256 hash of the DER encoded SubjectPublicKeyInfo of a certificate. It
+/// stays the same when a certificate is reissued for the same key, so it is
+/// suitable for pinning. It is displayed and parsed as hex.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Compute the pin of the certificate's public key
+    pub fn from_cert(cert: &X509Ref) -> Result<Self> {
+        let spki = cert
+            .public_key()
+            .and_then(|key| key.public_key_to_der())
+            .context("While encoding the public key of the certificate")?;
+        Ok(Self(sha256(&spki)))
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpkiPin({})", self)
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pin = [0; 32];
+        hex::decode_to_slice(s, &mut pin)
+            .with_context(|| format!("Invalid SPKI pin {:?}, expected 64 hex digits", s))?;
+        Ok(Self(pin))
+    }
+}
+
+/// Error returned when none of the certificates presented by the peer match
+/// the pinned public keys
+#[derive(Debug, Error)]
+#[error(
+    "None of the presented SPKI pins [{}] match the expected pins [{}]",
+    join(presented),
+    join(expected)
+)]
+pub struct PinMismatchError {
+    /// Pins of the certificates presented by the peer, leaf first
+    pub presented: Vec<SpkiPin>,
+    /// Pins that were expected
+    pub expected: Vec<SpkiPin>,
+}
+
+fn join(pins: &[SpkiPin]) -> String {
+    pins.iter()
+        .map(|pin| pin.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A set of allowed public keys. A certificate chain is accepted if any of its
+/// certificates has one of the pinned keys, which allows pinning either the
+/// key of the peer or the key of an issuing CA.
+#[derive(Clone, Debug, Default)]
+pub struct SpkiPinSet {
+    pins: BTreeSet<SpkiPin>,
+}
+
+impl SpkiPinSet {
+    /// Create a set from the allowed pins
+    pub fn new(pins: impl IntoIterator<Item = SpkiPin>) -> Self {
+        Self {
+            pins: pins.into_iter().collect(),
+        }
+    }
+
+    /// Check that at least one of the certificates has a pinned key. If any
+    /// of the certificates can't be hashed its pin is left out of the error.
+    pub fn check_chain<'a>(
+        &self,
+        chain: impl IntoIterator<Item = &'a X509Ref>,
+    ) -> Result<(), PinMismatchError> {
+        let presented: Vec<_> = chain
+            .into_iter()
+            .filter_map(|cert| SpkiPin::from_cert(cert).ok())
+            .collect();
+        if presented.iter().any(|pin| self.pins.contains(pin)) {
+            Ok(())
+        } else {
+            Err(PinMismatchError {
+                presented,
+                expected: self.pins.iter().copied().collect(),
+            })
+        }
+    }
+
+    /// Check the certificates presented by the peer of an established
+    /// connection
+    pub fn verify_peer(&self, ssl: &SslRef) -> Result<(), PinMismatchError> {
+        match ssl.peer_cert_chain() {
+            Some(chain) => self.check_chain(chain),
+            None => self.check_chain(ssl.peer_certificate().as_deref()),
+        }
+    }
+
+    /// Make the connector fail the handshake with peers whose certificate
+    /// chain doesn't contain a pinned key, in addition to the regular
+    /// verification. OpenSSL reports such failures only as a generic
+    /// verification error, call [SpkiPinSet::verify_peer] on the failed
+    /// connection to get the [PinMismatchError] naming the pins.
+    pub fn pin_connector(self, builder: &mut SslConnectorBuilder) {
+        builder.set_verify_callback(SslVerifyMode::PEER, move |preverify_ok, ctx| {
+            // Only look at the chain once, when verifying the leaf
+            if !preverify_ok || ctx.error_depth() != 0 {
+                return preverify_ok;
+            }
+            match ctx.chain() {
+                Some(chain) => self.check_chain(chain).is_ok(),
+                None => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::CertSpec;
+
+    #[test]
+    fn test_pinning() {
+        let cert = CertSpec::default().build().cert;
+        let other = CertSpec::default().build().cert;
+        let pin = SpkiPin::from_cert(&cert).unwrap();
+        let other_pin = SpkiPin::from_cert(&other).unwrap();
+
+        assert_eq!(pin, pin.to_string().parse().unwrap());
+        assert!("not hex".parse::<SpkiPin>().is_err());
+
+        let pins = SpkiPinSet::new(vec![pin]);
+        assert!(pins.check_chain(vec![&*other, &*cert]).is_ok());
+
+        let err = pins.check_chain(vec![&*other]).unwrap_err();
+        assert_eq!(err.presented, vec![other_pin]);
+        assert_eq!(err.expected, vec![pin]);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "None of the presented SPKI pins [{}] match the expected pins [{}]",
+                other_pin, pin
+            )
+        );
+    }
+}
@@ -0,0 +1,226 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::Result;
+use openssl::ssl::{SslAcceptor, SslConnector};
+use slog::{info, warn, Logger};
+use std::fs;
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::{IdentityFiles, SslConfig};
+
+/// Acceptor and connector built from the files as of the given modification
+/// times
+struct Loaded {
+    acceptor: Arc<SslAcceptor>,
+    connector: Arc<SslConnector>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+struct Inner {
+    config: SslConfig,
+    logger: Logger,
+    loaded: RwLock<Loaded>,
+}
+
+/// A [SslConfig] that rebuilds its acceptor and connector when the certificate
+/// files change, so that certificates can be rotated without restarting the
+/// service. Connections that are already established keep using the acceptor
+/// or connector they were created with.
+#[derive(Clone)]
+pub struct ReloadableSslConfig {
+    inner: Arc<Inner>,
+}
+
+impl ReloadableSslConfig {
+    /// Build the acceptor and connector from the config and spawn a thread
+    /// checking for changes of the certificate files every `poll_interval`.
+    /// The thread stops once all the clones of this object are dropped. If
+    /// `poll_interval` is None then no thread is spawned and
+    /// [ReloadableSslConfig::reload_if_changed] has to be called manually.
+    /// A failure to reload is logged and the previous certificates are kept.
+    pub fn new(
+        config: SslConfig,
+        logger: Logger,
+        poll_interval: impl Into<Option<Duration>>,
+    ) -> Result<Self> {
+        let loaded = load(&config, &logger)?;
+        let this = Self {
+            inner: Arc::new(Inner {
+                config,
+                logger,
+                loaded: RwLock::new(loaded),
+            }),
+        };
+
+        if let Some(poll_interval) = poll_interval.into() {
+            let inner = Arc::downgrade(&this.inner);
+            thread::Builder::new()
+                .name("tls-cert-reload".into())
+                .spawn(move || reloader_thread(inner, poll_interval))?;
+        }
+
+        Ok(this)
+    }
+
+    /// The current acceptor, to be used for accepting new connections
+    pub fn acceptor(&self) -> Arc<SslAcceptor> {
+        self.inner
+            .loaded
+            .read()
+            .expect("lock poisoned")
+            .acceptor
+            .clone()
+    }
+
+    /// The current connector, to be used for establishing new connections
+    pub fn connector(&self) -> Arc<SslConnector> {
+        self.inner
+            .loaded
+            .read()
+            .expect("lock poisoned")
+            .connector
+            .clone()
+    }
+
+    /// Rebuild the acceptor and connector if any of the certificate files
+    /// changed since they were last built. Returns whether they were rebuilt.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = modification_times(&self.inner.config);
+        if modified == self.inner.loaded.read().expect("lock poisoned").modified {
+            return Ok(false);
+        }
+
+        let loaded = load(&self.inner.config, &self.inner.logger)?;
+        *self.inner.loaded.write().expect("lock poisoned") = loaded;
+        Ok(true)
+    }
+}
+
+fn load(config: &SslConfig, logger: &Logger) -> Result<Loaded> {
+    // Read the modification times first, so that a change racing with the
+    // build will be picked up by the next reload.
+    let modified = modification_times(config);
+    Ok(Loaded {
+        acceptor: Arc::new(config.clone().build_tls_acceptor(logger.clone())?),
+        connector: Arc::new(config.clone().build_tls_connector()?),
+        modified,
+    })
+}
+
+impl SslConfig {
+    /// Paths of all the files this config reads the certificates from
+    fn paths(&self) -> Vec<&str> {
+        let mut paths = vec![self.ca_pem.as_str()];
+        match &self.identity {
+            IdentityFiles::Pem { cert, private_key } => paths.extend([cert.as_str(), private_key]),
+            IdentityFiles::Pkcs12 { bundle, .. } => paths.push(bundle),
+        }
+        paths
+    }
+}
+
+fn modification_times(config: &SslConfig) -> Vec<Option<SystemTime>> {
+    config
+        .paths()
+        .into_iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn reloader_thread(inner: Weak<Inner>, poll_interval: Duration) {
+    loop {
+        thread::sleep(poll_interval);
+        let this = match inner.upgrade() {
+            Some(inner) => ReloadableSslConfig { inner },
+            None => return,
+        };
+        match this.reload_if_changed() {
+            Ok(false) => {}
+            Ok(true) => info!(this.inner.logger, "Reloaded TLS certificates"),
+            Err(e) => warn!(
+                this.inner.logger,
+                "Failed to reload TLS certificates due to {:#?}", e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{write, CertSpec, TestCert};
+    use std::fs::File;
+    use tempdir::TempDir;
+
+    /// Move the modification time of the file forward, so that the change is
+    /// seen however coarse the file system timestamps are
+    fn touch(path: &str, generation: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(generation * 60))
+            .unwrap();
+    }
+
+    /// Write the three files of the config
+    fn rotate(dir: &TempDir, ca: &TestCert, leaf: &TestCert, generation: u64) {
+        touch(&ca.write_cert(dir.path(), "ca.pem"), generation);
+        touch(&leaf.write_cert(dir.path(), "cert.pem"), generation);
+        touch(&leaf.write_key(dir.path(), "key.pem"), generation);
+    }
+
+    #[test]
+    fn test_reload_if_changed() {
+        let dir = TempDir::new("secure_utils").unwrap();
+        let ca = CertSpec {
+            subject: &[("CN", "ca")],
+            is_ca: true,
+            ..Default::default()
+        }
+        .build();
+        let leaf = || {
+            CertSpec {
+                issuer: Some(&ca),
+                ..Default::default()
+            }
+            .build()
+        };
+        rotate(&dir, &ca, &leaf(), 0);
+
+        let path = |name| dir.path().join(name).to_str().unwrap().to_owned();
+        let config = SslConfig::new(
+            path("ca.pem"),
+            path("cert.pem"),
+            path("key.pem"),
+            None::<&str>,
+        );
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let reloadable = ReloadableSslConfig::new(config, logger, None).unwrap();
+        let acceptor = reloadable.acceptor();
+        let connector = reloadable.connector();
+        assert!(!reloadable.reload_if_changed().unwrap());
+        assert!(Arc::ptr_eq(&acceptor, &reloadable.acceptor()));
+
+        rotate(&dir, &ca, &leaf(), 1);
+        assert!(reloadable.reload_if_changed().unwrap());
+        assert!(!Arc::ptr_eq(&acceptor, &reloadable.acceptor()));
+        assert!(!Arc::ptr_eq(&connector, &reloadable.connector()));
+        assert!(!reloadable.reload_if_changed().unwrap());
+
+        // A broken rotation keeps the previous certificates
+        let acceptor = reloadable.acceptor();
+        touch(&write(dir.path(), "cert.pem", b"not a certificate"), 2);
+        assert!(reloadable.reload_if_changed().is_err());
+        assert!(Arc::ptr_eq(&acceptor, &reloadable.acceptor()));
+    }
+}
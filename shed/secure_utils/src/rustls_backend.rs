@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! [rustls] based implementation of the TLS building blocks of this crate, for
+//! users that need pure Rust TLS. Enabled with the `rustls_backend` feature.
+
+use anyhow::{anyhow, bail, Context, Result};
+use p12_keystore::KeyStore;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{read_bytes, IdentityFiles, SslConfig};
+
+impl SslConfig {
+    /// Builds the rustls equivalent of the tls acceptor: a server config
+    /// requiring clients to present a certificate signed by one of the CAs
+    pub fn build_rustls_server_config(self) -> Result<ServerConfig> {
+        let roots = read_rustls_root_store(&self.ca_pem)?;
+        let (certs, key) = self.identity.build_rustls()?;
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            .with_single_cert(certs, key)
+            .context("failed to build rustls server config")
+    }
+
+    /// Builds the rustls equivalent of the tls connector: a client config
+    /// presenting the same identity as the server config and trusting the
+    /// same CAs
+    pub fn build_rustls_client_config(self) -> Result<ClientConfig> {
+        let roots = read_rustls_root_store(&self.ca_pem)?;
+        let (certs, key) = self.identity.build_rustls()?;
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_single_cert(certs, key)
+            .context("failed to build rustls client config")
+    }
+
+    /// Same as [SslConfig::build_rustls_server_config], wrapped in an [Arc] as
+    /// expected by acceptors like `tokio_rustls::TlsAcceptor`
+    pub fn build_rustls_acceptor_config(self) -> Result<Arc<ServerConfig>> {
+        Ok(Arc::new(self.build_rustls_server_config()?))
+    }
+}
+
+impl IdentityFiles {
+    fn build_rustls(self) -> Result<(Vec<Certificate>, PrivateKey)> {
+        match self {
+            Self::Pem { cert, private_key } => Ok((
+                read_rustls_certs(cert)?,
+                read_rustls_private_key(private_key)?,
+            )),
+            Self::Pkcs12 { bundle, password } => read_rustls_pkcs12(bundle, &password),
+        }
+    }
+}
+
+/// Read all the certificates from a pem file, in the order they appear in it
+pub fn read_rustls_certs(cert_pem_file: impl AsRef<Path>) -> Result<Vec<Certificate>> {
+    let cert_pem_file = cert_pem_file.as_ref();
+    let pem = read_bytes(cert_pem_file)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+        .with_context(|| format!("While parsing certificates {}", cert_pem_file.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", cert_pem_file.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Read the first RSA, PKCS#8 or EC private key from a pem file
+pub fn read_rustls_private_key(private_key_pem_file: impl AsRef<Path>) -> Result<PrivateKey> {
+    let private_key_pem_file = private_key_pem_file.as_ref();
+    let pem = read_bytes(private_key_pem_file)?;
+    let mut reader = BufReader::new(pem.as_slice());
+    loop {
+        let item = rustls_pemfile::read_one(&mut reader).with_context(|| {
+            format!(
+                "While parsing private key {}",
+                private_key_pem_file.display()
+            )
+        })?;
+        match item {
+            Some(Item::RSAKey(key)) | Some(Item::PKCS8Key(key)) | Some(Item::ECKey(key)) => {
+                return Ok(PrivateKey(key));
+            }
+            Some(_) => continue,
+            None => {
+                return Err(anyhow!(
+                    "No private key found in {}",
+                    private_key_pem_file.display()
+                ));
+            }
+        }
+    }
+}
+
+/// Read a password protected PKCS#12 (`.p12`/`.pfx`) bundle and decode the
+/// certificate followed by its chain, and the private key stored in it
+pub fn read_rustls_pkcs12(
+    bundle_file: impl AsRef<Path>,
+    password: &str,
+) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let bundle_file = bundle_file.as_ref();
+    let der = read_bytes(bundle_file)?;
+    let keystore = KeyStore::from_pkcs12(&der, password).with_context(|| {
+        format!(
+            "While parsing PKCS#12 bundle {}, is the password correct?",
+            bundle_file.display()
+        )
+    })?;
+    let (_, key_chain) = keystore.private_key_chain().ok_or_else(|| {
+        anyhow!(
+            "No private key found in PKCS#12 bundle {}",
+            bundle_file.display()
+        )
+    })?;
+    let certs = key_chain
+        .chain()
+        .iter()
+        .map(|cert| Certificate(cert.as_der().to_vec()))
+        .collect();
+    Ok((certs, PrivateKey(key_chain.key().to_vec())))
+}
+
+fn read_rustls_root_store(ca_pem_file: impl AsRef<Path>) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_rustls_certs(ca_pem_file)? {
+        roots
+            .add(&cert)
+            .context("failed to add CA certificate to the root store")?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{write, CertSpec, TestCert};
+    use openssl::pkcs12::Pkcs12;
+    use openssl::stack::Stack;
+    use rustls::{ClientConnection, ServerConnection};
+    use tempdir::TempDir;
+
+    fn ca_cert(name: &str) -> TestCert {
+        let cn = [("CN", name)];
+        CertSpec {
+            subject: &cn,
+            is_ca: true,
+            ..Default::default()
+        }
+        .build()
+    }
+
+    fn leaf_cert(ca: &TestCert) -> TestCert {
+        CertSpec {
+            dns_names: &["localhost"],
+            issuer: Some(ca),
+            ..Default::default()
+        }
+        .build()
+    }
+
+    /// Run the TLS handshake of the client and server in memory
+    fn handshake(client: ClientConfig, server: ServerConfig) -> Result<()> {
+        let mut client = ClientConnection::new(Arc::new(client), "localhost".try_into()?)?;
+        let mut server = ServerConnection::new(Arc::new(server))?;
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buf = Vec::new();
+            client.write_tls(&mut buf)?;
+            server.read_tls(&mut buf.as_slice())?;
+            server.process_new_packets()?;
+
+            buf.clear();
+            server.write_tls(&mut buf)?;
+            client.read_tls(&mut buf.as_slice())?;
+            client.process_new_packets()?;
+        }
+        assert!(server.peer_certificates().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pem() {
+        let dir = TempDir::new("secure_utils").unwrap();
+        let ca = ca_cert("ca");
+        let leaf = leaf_cert(&ca);
+        let config = SslConfig::new(
+            ca.write_cert(dir.path(), "ca.pem"),
+            leaf.write_cert(dir.path(), "cert.pem"),
+            leaf.write_key(dir.path(), "key.pem"),
+            None::<&str>,
+        );
+        handshake(
+            config.clone().build_rustls_client_config().unwrap(),
+            config.clone().build_rustls_server_config().unwrap(),
+        )
+        .unwrap();
+
+        // A server with a certificate from another CA isn't trusted
+        let other_ca = ca_cert("other");
+        let other_leaf = leaf_cert(&other_ca);
+        let other = SslConfig::new(
+            other_ca.write_cert(dir.path(), "other_ca.pem"),
+            other_leaf.write_cert(dir.path(), "other_cert.pem"),
+            other_leaf.write_key(dir.path(), "other_key.pem"),
+            None::<&str>,
+        );
+        assert!(handshake(
+            config.build_rustls_client_config().unwrap(),
+            other.build_rustls_server_config().unwrap(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_pkcs12() {
+        let dir = TempDir::new("secure_utils").unwrap();
+        let ca = ca_cert("ca");
+        let leaf = leaf_cert(&ca);
+        let ca_pem = ca.write_cert(dir.path(), "ca.pem");
+
+        let mut chain = Stack::new().unwrap();
+        chain.push(ca.cert.clone()).unwrap();
+        let bundle = Pkcs12::builder()
+            .name("leaf")
+            .pkey(&leaf.key)
+            .cert(&leaf.cert)
+            .ca(chain)
+            .build2("secret")
+            .unwrap();
+        let bundle = write(dir.path(), "leaf.p12", &bundle.to_der().unwrap());
+
+        let (certs, _) = read_rustls_pkcs12(&bundle, "secret").unwrap();
+        assert_eq!(
+            certs,
+            vec![
+                Certificate(leaf.cert.to_der().unwrap()),
+                Certificate(ca.cert.to_der().unwrap()),
+            ]
+        );
+
+        let config = SslConfig::from_pkcs12(&ca_pem, &bundle, "secret", None::<&str>);
+        handshake(
+            config.clone().build_rustls_client_config().unwrap(),
+            config.build_rustls_server_config().unwrap(),
+        )
+        .unwrap();
+
+        let config = SslConfig::from_pkcs12(&ca_pem, &bundle, "wrong", None::<&str>);
+        let err = config.build_rustls_client_config().unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("is the password correct?"),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Certificates generated for the tests of this crate
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+
+/// A generated certificate along with its private key
+pub(crate) struct TestCert {
+    pub(crate) cert: X509,
+    pub(crate) key: PKey<Private>,
+}
+
+impl TestCert {
+    /// Write the certificate to `name` under `dir`, returning its path
+    pub(crate) fn write_cert(&self, dir: &Path, name: &str) -> String {
+        write(dir, name, &self.cert.to_pem().unwrap())
+    }
+
+    /// Write the private key to `name` under `dir`, returning its path
+    pub(crate) fn write_key(&self, dir: &Path, name: &str) -> String {
+        write(dir, name, &self.key.private_key_to_pem_pkcs8().unwrap())
+    }
+}
+
+/// Write `contents` to `name` under `dir`, returning its path
+pub(crate) fn write(dir: &Path, name: &str, contents: &[u8]) -> String {
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Properties of a generated certificate
+pub(crate) struct CertSpec<'a> {
+    /// Entries of the subject name, by their short name or OID
+    pub(crate) subject: &'a [(&'a str, &'a str)],
+    /// Time from now after which the certificate expires
+    pub(crate) valid_for_secs: i64,
+    /// Subject alternative names
+    pub(crate) dns_names: &'a [&'a str],
+    pub(crate) uris: &'a [&'a str],
+    pub(crate) ips: &'a [&'a str],
+    /// Whether the certificate can sign other certificates
+    pub(crate) is_ca: bool,
+    /// Certificate signing this one, self-signed if None
+    pub(crate) issuer: Option<&'a TestCert>,
+}
+
+impl Default for CertSpec<'_> {
+    fn default() -> Self {
+        Self {
+            subject: &[("CN", "localhost")],
+            valid_for_secs: 24 * 60 * 60,
+            dns_names: &[],
+            uris: &[],
+            ips: &[],
+            is_ca: false,
+            issuer: None,
+        }
+    }
+}
+
+impl CertSpec<'_> {
+    /// Generate a new key and a certificate for it
+    pub(crate) fn build(&self) -> TestCert {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        for (field, value) in self.subject {
+            name.append_entry_by_text(field, value).unwrap();
+        }
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+        builder
+            .set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        match self.issuer {
+            Some(issuer) => builder.set_issuer_name(issuer.cert.subject_name()),
+            None => builder.set_issuer_name(&name),
+        }
+        .unwrap();
+        builder.set_pubkey(&key).unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        builder
+            .set_not_before(&Asn1Time::from_unix((now - 60) as libc::time_t).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(
+                &Asn1Time::from_unix((now + self.valid_for_secs) as libc::time_t).unwrap(),
+            )
+            .unwrap();
+
+        if self.is_ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        }
+        if !(self.dns_names.is_empty() && self.uris.is_empty() && self.ips.is_empty()) {
+            let mut san = SubjectAlternativeName::new();
+            for dns in self.dns_names {
+                san.dns(dns);
+            }
+            for uri in self.uris {
+                san.uri(uri);
+            }
+            for ip in self.ips {
+                san.ip(ip);
+            }
+            let context = builder.x509v3_context(self.issuer.map(|issuer| &*issuer.cert), None);
+            let san = san.build(&context).unwrap();
+            builder.append_extension(san).unwrap();
+        }
+
+        let signing_key = self.issuer.map_or(&key, |issuer| &issuer.key);
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        TestCert {
+            cert: builder.build(),
+            key,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // Binaries are built with the same compiler as this crate, so capture its
+    // version here for `services::build_info!`.
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(version) = version {
+        println!("cargo:rustc-env=SERVICES_RUSTC_VERSION={}", version.trim());
+    }
+    println!("cargo:rerun-if-env-changed=RUSTC");
+
+    // Fallback for the git hash of binaries whose build system doesn't set
+    // `BUILD_GIT_HASH`, correct when they are in the same checkout as this
+    // crate, e.g. in a workspace.
+    if let Some(hash) = git(&["rev-parse", "HEAD"]) {
+        println!("cargo:rustc-env=SERVICES_GIT_HASH={}", hash);
+    }
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        let mut refs = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+        if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            refs.push(git_dir.join(head));
+        }
+        // Missing files would rerun this script on every build
+        for path in refs.iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .current_dir(env::var_os("CARGO_MANIFEST_DIR")?)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_owned())
+}
@@ -21,10 +21,14 @@ mod errors {
     error_chain! {
         foreign_links {
             CString(::std::ffi::NulError) #[doc = "Error that can be returned when dealing with thrift services"];
+            Io(::std::io::Error) #[doc = "Error that can be returned when serving the status of a service"];
         }
     }
 }
 pub use crate::errors::*;
+
+use std::collections::BTreeMap;
+
 #[cfg(fbcode_build)]
 mod facebook;
 
@@ -52,6 +56,26 @@ pub trait Fb303Service: Send + Sync {
     /// threads at the same time. It might be possible to relax these constraints.
     #[allow(non_snake_case)]
     fn getStatus(&self) -> FbStatus;
+
+    /// Whether the service is alive, i.e. it is running and should not be
+    /// restarted by an orchestrator, even if it can't take traffic right now.
+    /// By default it is derived from [Fb303Service::getStatus].
+    fn is_alive(&self) -> bool {
+        !matches!(self.getStatus(), FbStatus::Dead | FbStatus::Stopped)
+    }
+
+    /// Whether the service is ready, i.e. it can take traffic. By default it
+    /// is derived from [Fb303Service::getStatus].
+    fn is_ready(&self) -> bool {
+        matches!(self.getStatus(), FbStatus::Alive | FbStatus::Warning)
+    }
+
+    /// Counters exported by this service, mirroring `fb303::getCounters`. By
+    /// default nothing is exported.
+    #[allow(non_snake_case)]
+    fn getCounters(&self) -> BTreeMap<String, i64> {
+        BTreeMap::new()
+    }
 }
 
 /// A default Fb303Service that just returns Alive.
@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Build metadata and uptime of the running service.
+//!
+//! The metadata is captured at compile time of the binary with the
+//! [build_info!](crate::build_info!) macro and registered once at startup with
+//! [register_build_info]. The git hash and build time are taken from the
+//! `BUILD_GIT_HASH` and `BUILD_TIMESTAMP` environment variables, which are
+//! expected to be set by the build system when compiling the binary. Without
+//! `BUILD_GIT_HASH`, the git hash falls back to the one of the checkout this
+//! crate was built from, which is the one of the binary if they are in the
+//! same repository.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use once_cell::sync::{Lazy, OnceCell};
+
+#[doc(hidden)]
+/// Version of the compiler used to build this crate, used by the
+/// [build_info!](crate::build_info!) macro if `BUILD_RUSTC_VERSION` is not set.
+pub const RUSTC_VERSION: &str = match option_env!("SERVICES_RUSTC_VERSION") {
+    Some(version) => version,
+    None => "unknown",
+};
+
+#[doc(hidden)]
+/// Hash of the commit of the checkout this crate was built from, used by the
+/// [build_info!](crate::build_info!) macro if `BUILD_GIT_HASH` is not set.
+pub const GIT_HASH: Option<&str> = option_env!("SERVICES_GIT_HASH");
+
+static BUILD_INFO: OnceCell<BuildInfo> = OnceCell::new();
+static START_TIME: Lazy<(Instant, SystemTime)> = Lazy::new(|| (Instant::now(), SystemTime::now()));
+
+/// Metadata about the build of the running binary.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BuildInfo {
+    /// Name of the package the binary belongs to
+    pub package: &'static str,
+    /// Version of the package the binary belongs to
+    pub version: &'static str,
+    /// Hash of the commit the binary was built from, if known
+    pub git_hash: Option<&'static str>,
+    /// Time the binary was built at, if known
+    pub build_time: Option<&'static str>,
+    /// Version of the compiler the binary was built with
+    pub rustc_version: &'static str,
+}
+
+/// Capture the [BuildInfo] of the crate this macro is invoked in.
+///
+/// # Example
+/// ```
+/// let info = services::build_info!();
+/// services::build_info::register_build_info(info);
+/// # assert_eq!(services::build_info::build_info().unwrap().package, "services");
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build_info::BuildInfo {
+            package: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: match option_env!("BUILD_GIT_HASH") {
+                Some(hash) => Some(hash),
+                None => $crate::build_info::GIT_HASH,
+            },
+            build_time: option_env!("BUILD_TIMESTAMP"),
+            rustc_version: match option_env!("BUILD_RUSTC_VERSION") {
+                Some(version) => version,
+                None => $crate::build_info::RUSTC_VERSION,
+            },
+        }
+    };
+}
+
+/// Register the build info of the running binary, this should be called once
+/// at startup, subsequent calls are ignored. This also marks the start of the
+/// service for the purpose of [uptime].
+pub fn register_build_info(info: BuildInfo) {
+    mark_started();
+    let _ = BUILD_INFO.set(info);
+}
+
+/// Build info of the running binary, if it was registered.
+pub fn build_info() -> Option<&'static BuildInfo> {
+    BUILD_INFO.get()
+}
+
+/// Time at which the service started, i.e. when the build info was registered
+/// or the status of the service was first served.
+pub fn start_time() -> SystemTime {
+    START_TIME.1
+}
+
+/// How long the service has been running for, see [start_time].
+pub fn uptime() -> Duration {
+    START_TIME.0.elapsed()
+}
+
+pub(crate) fn mark_started() {
+    Lazy::force(&START_TIME);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = crate::build_info!();
+        assert_eq!(info.package, "services");
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        match option_env!("BUILD_RUSTC_VERSION") {
+            Some(version) => assert_eq!(info.rustc_version, version),
+            None => assert!(
+                info.rustc_version.starts_with("rustc "),
+                "{}",
+                info.rustc_version
+            ),
+        }
+        match option_env!("BUILD_GIT_HASH") {
+            Some(hash) => assert_eq!(info.git_hash, Some(hash)),
+            None => assert_eq!(info.git_hash, GIT_HASH),
+        }
+        if let Some(hash) = info.git_hash {
+            assert!(
+                hash.len() >= 7 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+                "{}",
+                hash
+            );
+        }
+        assert_eq!(info.build_time, option_env!("BUILD_TIMESTAMP"));
+    }
+}
@@ -19,6 +19,13 @@ use services_common as _; // used in oss
 #[cfg(not(fbcode_build))]
 mod oss;
 
+pub mod build_info;
+pub mod shutdown;
+pub mod status;
+
+pub use crate::shutdown::ShutdownCoordinator;
+pub use crate::status::ServiceState;
+
 #[cfg(fbcode_build)]
 pub use services::*;
 
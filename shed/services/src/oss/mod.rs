@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::net::Ipv4Addr;
+use std::thread::sleep;
+use std::time::Duration;
+
+use fbinit::FacebookInit;
+
+pub use services_common::*;
+
+pub mod status_server;
+
+pub use self::status_server::StatusServer;
+
+pub fn export_proc_stats_to_fb303(_: bool) {}
+
+pub fn run_service_framework<T: Into<Vec<u8>>>(
+    _: FacebookInit,
+    _: T,
+    _: i32,
+    _: i32,
+    _: Box<dyn Fb303Service>,
+) -> Result<!> {
+    loop {
+        sleep(Duration::from_secs(3600))
+    }
+}
+
+/// Like [run_service_framework], but serve the status of the provided service
+/// over HTTP on the given port, see [status_server] for the available
+/// endpoints. Unlike [run_service_framework], this fails if the port can't be
+/// bound.
+pub fn run_service_framework_with_status_server<T: Into<Vec<u8>>>(
+    _: FacebookInit,
+    _: T,
+    port: i32,
+    _: i32,
+    service: Box<dyn Fb303Service>,
+) -> Result<!> {
+    let port: u16 = port
+        .try_into()
+        .map_err(|_| Error::from(format!("invalid port {}", port)))?;
+    StatusServer::bind((Ipv4Addr::UNSPECIFIED, port), service)?.serve()
+}
@@ -0,0 +1,316 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A minimal HTTP server exposing the status of a [Fb303Service] so that load
+//! balancers and orchestrators, which can't speak fb303, can probe the service.
+//!
+//! The following endpoints are served:
+//! - `/health` - 200 if the service [is alive](Fb303Service::is_alive),
+//! - `/ready` - 200 if the service [is ready](Fb303Service::is_ready) to take traffic,
+//! - `/stats` - JSON object with the [stats recorded](stats::snapshot) by the
+//!   process, along with the counters of [Fb303Service::getCounters],
+//! - `/build_info` - JSON object with the [BuildInfo](crate::build_info::BuildInfo)
+//!   of the binary and its uptime.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+use services_common::{Fb303Service, FbStatus, Result};
+
+use crate::build_info;
+
+/// Timeout for reading the request and writing the response of a single
+/// connection, so that a slow client can't keep a worker busy forever.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of threads serving the connections, each one at a time. Probes are
+/// cheap, so a few are enough, and bounding them protects the service from a
+/// flood of connections.
+const WORKERS: usize = 4;
+
+/// Bounds of the delay before accepting again after failing to accept a
+/// connection, doubled on each consecutive failure.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Server answering HTTP status probes on behalf of a [Fb303Service].
+pub struct StatusServer {
+    listener: TcpListener,
+    service: Arc<dyn Fb303Service>,
+}
+
+impl StatusServer {
+    /// Bind the server to the provided address. Use [StatusServer::serve] to
+    /// start answering requests.
+    pub fn bind(addr: impl ToSocketAddrs, service: Box<dyn Fb303Service>) -> Result<Self> {
+        build_info::mark_started();
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            service: Arc::from(service),
+        })
+    }
+
+    /// Address the server is listening on, useful when binding to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests forever on a fixed number of worker threads, including
+    /// the current one, each accepting and handling a connection at a time.
+    pub fn serve(self) -> Result<!> {
+        for _ in 1..WORKERS {
+            let listener = self.listener.try_clone()?;
+            let service = self.service.clone();
+            thread::spawn(move || accept_loop(&listener, &*service));
+        }
+        accept_loop(&self.listener, &*self.service)
+    }
+}
+
+fn accept_loop(listener: &TcpListener, service: &dyn Fb303Service) -> ! {
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                backoff = MIN_ACCEPT_BACKOFF;
+                // There is no one to report the error to, the client will see
+                // the connection being closed.
+                let _ = handle_connection(stream, service);
+            }
+            Err(_) => {
+                // Failing to accept a connection (e.g. because of running out
+                // of file descriptors) is usually transient, but may last, so
+                // don't spin on it.
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, service: &dyn Fb303Service) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Consume the headers, none of them are relevant for the response.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let response = match method {
+        "GET" | "HEAD" => route(path, service),
+        _ => Response::text(405, "Method Not Allowed", "method not allowed\n".to_owned()),
+    };
+    response.write(&stream, method == "HEAD")
+}
+
+fn route(path: &str, service: &dyn Fb303Service) -> Response {
+    match path {
+        "/health" => probe_response(service.getStatus(), service.is_alive()),
+        "/ready" => probe_response(service.getStatus(), service.is_ready()),
+        "/stats" => {
+            let mut counters = stats::snapshot();
+            counters.extend(service.getCounters());
+            json_response(serde_json::to_string(&counters))
+        }
+        "/build_info" => {
+            let info = build_info::build_info();
+            json_response(serde_json::to_string(&json!({
+                "package": info.map(|info| info.package),
+                "version": info.map(|info| info.version),
+                "git_hash": info.and_then(|info| info.git_hash),
+                "build_time": info.and_then(|info| info.build_time),
+                "rustc_version": info.map(|info| info.rustc_version),
+                "uptime_secs": build_info::uptime().as_secs(),
+            })))
+        }
+        _ => Response::text(404, "Not Found", "not found\n".to_owned()),
+    }
+}
+
+fn json_response(body: serde_json::Result<String>) -> Response {
+    match body {
+        Ok(body) => Response {
+            code: 200,
+            reason: "OK",
+            content_type: "application/json",
+            body,
+        },
+        Err(e) => Response::text(500, "Internal Server Error", format!("{}\n", e)),
+    }
+}
+
+fn probe_response(status: FbStatus, ok: bool) -> Response {
+    let body = format!("{}\n", status_name(status));
+    if ok {
+        Response::text(200, "OK", body)
+    } else {
+        Response::text(503, "Service Unavailable", body)
+    }
+}
+
+/// Name of the status as defined in `fb303::fb_status`.
+fn status_name(status: FbStatus) -> &'static str {
+    match status {
+        FbStatus::Dead => "DEAD",
+        FbStatus::Starting => "STARTING",
+        FbStatus::Alive => "ALIVE",
+        FbStatus::Stopping => "STOPPING",
+        FbStatus::Stopped => "STOPPED",
+        FbStatus::Warning => "WARNING",
+    }
+}
+
+struct Response {
+    code: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(code: u16, reason: &'static str, body: String) -> Self {
+        Self {
+            code,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    fn write(&self, mut stream: &TcpStream, head_only: bool) -> Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.code,
+            self.reason,
+            self.content_type,
+            self.body.len(),
+        )?;
+        if !head_only {
+            stream.write_all(self.body.as_bytes())?;
+        }
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use stats::prelude::*;
+
+    define_stats! {
+        prefix = "status_server_test";
+        probes: counter(),
+    }
+
+    struct TestService {
+        alive: Arc<AtomicBool>,
+    }
+
+    impl Fb303Service for TestService {
+        fn getStatus(&self) -> FbStatus {
+            if self.alive.load(Ordering::Relaxed) {
+                FbStatus::Alive
+            } else {
+                FbStatus::Starting
+            }
+        }
+
+        fn getCounters(&self) -> BTreeMap<String, i64> {
+            let mut counters = BTreeMap::new();
+            counters.insert("requests".to_owned(), 42);
+            counters
+        }
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_status_server() {
+        let alive = Arc::new(AtomicBool::new(false));
+        let server = StatusServer::bind(
+            "127.0.0.1:0",
+            Box::new(TestService {
+                alive: alive.clone(),
+            }),
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let health = get(addr, "/health");
+        assert!(health.starts_with("HTTP/1.1 200 OK\r\n"), "{}", health);
+        assert!(health.ends_with("\r\n\r\nSTARTING\n"), "{}", health);
+
+        let ready = get(addr, "/ready?verbose");
+        assert!(ready.starts_with("HTTP/1.1 503 "), "{}", ready);
+
+        alive.store(true, Ordering::Relaxed);
+        let ready = get(addr, "/ready");
+        assert!(ready.starts_with("HTTP/1.1 200 OK\r\n"), "{}", ready);
+        assert!(ready.ends_with("\r\n\r\nALIVE\n"), "{}", ready);
+
+        let stats = get(addr, "/stats");
+        assert!(
+            stats.contains("Content-Type: application/json\r\n"),
+            "{}",
+            stats
+        );
+        STATS::probes.increment_value(3);
+        let stats = get(addr, "/stats");
+        assert!(
+            stats.contains("\"status_server_test.probes\":3"),
+            "{}",
+            stats
+        );
+        assert!(stats.contains("\"requests\":42"), "{}", stats);
+
+        let build_info = crate::build_info!();
+        build_info::register_build_info(build_info.clone());
+        let info = get(addr, "/build_info");
+        let body = &info[info.find("\r\n\r\n").unwrap() + 4..];
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["package"], "services");
+        assert_eq!(body["version"], build_info.version);
+        assert_eq!(body["rustc_version"], build_info.rustc_version);
+        assert_eq!(body["git_hash"], json!(build_info.git_hash));
+        assert!(body["uptime_secs"].is_u64(), "{}", info);
+
+        let missing = get(addr, "/missing");
+        assert!(missing.starts_with("HTTP/1.1 404 "), "{}", missing);
+    }
+}
@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Coordinator of a graceful shutdown of a service.
+//!
+//! Components of the service register drain callbacks with the
+//! [ShutdownCoordinator]. Once the shutdown starts the service stops being
+//! ready, so that load balancers stop sending new traffic to it, and after a
+//! grace period all the drain callbacks are run concurrently, each bounded by
+//! its own deadline, to wait for the work that is still in flight.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, FutureExt, Shared};
+use stats::prelude::*;
+use tokio::time::Instant;
+
+use crate::{Fb303Service, FbStatus};
+
+define_stats! {
+    prefix = "services.shutdown";
+    drain_duration_ms: dynamic_histogram("{}.drain_duration_ms", (component: String); 100, 0, 60_000, Average; P 50; P 99),
+    drain_timeouts: dynamic_timeseries("{}.drain_timeouts", (component: String); Sum),
+    shutdown_duration_ms: timeseries(Average),
+}
+
+const PHASE_RUNNING: u8 = 0;
+const PHASE_STOPPING: u8 = 1;
+const PHASE_STOPPED: u8 = 2;
+
+struct Drain {
+    name: String,
+    deadline: Duration,
+    drain: Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>,
+}
+
+struct Inner {
+    phase: Arc<AtomicU8>,
+    grace_period: Duration,
+    drains: Arc<Mutex<Vec<Drain>>>,
+    /// The shutdown started by the first call to [ShutdownCoordinator::shutdown],
+    /// kept here so that it goes on even if that caller stops waiting for it.
+    shutdown: Mutex<Option<Shared<BoxFuture<'static, ShutdownReport>>>>,
+}
+
+/// Outcome of a single drain callback.
+#[derive(Clone, Debug)]
+pub struct DrainOutcome {
+    /// Name of the component the callback was registered for
+    pub name: String,
+    /// How long the callback took, capped by its deadline
+    pub duration: Duration,
+    /// Whether the callback failed to finish before its deadline
+    pub timed_out: bool,
+}
+
+/// Summary of a completed shutdown.
+#[derive(Clone, Debug)]
+pub struct ShutdownReport {
+    /// Outcomes of all the drain callbacks, in the order of their registration
+    pub drains: Vec<DrainOutcome>,
+    /// Time elapsed from starting the shutdown until all drains completed,
+    /// including the grace period
+    pub duration: Duration,
+}
+
+/// Coordinates a graceful shutdown, see the [module documentation](self) for
+/// details. Cloning the coordinator gives another handle to the same state.
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    inner: Arc<Inner>,
+}
+
+impl ShutdownCoordinator {
+    /// Create a coordinator that will wait `grace_period` between the service
+    /// becoming not ready and starting to drain its components.
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                phase: Arc::new(AtomicU8::new(PHASE_RUNNING)),
+                grace_period,
+                drains: Arc::new(Mutex::new(Vec::new())),
+                shutdown: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Register a callback that will be called during shutdown to wait for
+    /// the in-flight work of the named component. The callback is abandoned
+    /// if it doesn't complete within `deadline`. Callbacks registered after
+    /// the shutdown started are never called.
+    pub fn register_drain<F, Fut>(&self, name: impl Into<String>, deadline: Duration, drain: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.inner
+            .drains
+            .lock()
+            .expect("poisoned lock")
+            .push(Drain {
+                name: name.into(),
+                deadline,
+                drain: Box::new(move || drain().boxed()),
+            });
+    }
+
+    /// Returns `true` once the shutdown has started.
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.phase.load(Ordering::Acquire) != PHASE_RUNNING
+    }
+
+    /// Wrap the service so that it reports [FbStatus::Stopping] once the
+    /// shutdown started and [FbStatus::Stopped] once it completed. Before
+    /// that the status of the wrapped service is reported.
+    pub fn wrap_service(&self, service: Box<dyn Fb303Service>) -> Box<dyn Fb303Service> {
+        Box::new(ShutdownAwareService {
+            service,
+            coordinator: self.clone(),
+        })
+    }
+
+    /// Perform the shutdown: flip the service to not ready, wait for the
+    /// grace period and then wait for all the drain callbacks to complete or
+    /// hit their deadline. Only the first call performs the shutdown, the
+    /// later ones wait for it to complete and get the same report.
+    pub async fn shutdown(&self) -> ShutdownReport {
+        let shutdown = self
+            .inner
+            .shutdown
+            .lock()
+            .expect("poisoned lock")
+            .get_or_insert_with(|| {
+                run_shutdown(
+                    self.inner.phase.clone(),
+                    self.inner.grace_period,
+                    self.inner.drains.clone(),
+                )
+                .boxed()
+                .shared()
+            })
+            .clone();
+        shutdown.await
+    }
+
+    /// Wait for SIGTERM or SIGINT and then perform the [shutdown](Self::shutdown).
+    pub async fn shutdown_on_signal(&self) -> io::Result<ShutdownReport> {
+        wait_for_signal().await?;
+        Ok(self.shutdown().await)
+    }
+}
+
+async fn run_shutdown(
+    phase: Arc<AtomicU8>,
+    grace_period: Duration,
+    drains: Arc<Mutex<Vec<Drain>>>,
+) -> ShutdownReport {
+    let start = Instant::now();
+    // The phase only ever moves forward from running
+    let _ = phase.compare_exchange(
+        PHASE_RUNNING,
+        PHASE_STOPPING,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    tokio::time::sleep(grace_period).await;
+
+    let drains = std::mem::take(&mut *drains.lock().expect("poisoned lock"));
+    let drains = future::join_all(drains.into_iter().map(run_drain)).await;
+
+    phase.store(PHASE_STOPPED, Ordering::Release);
+    let duration = start.elapsed();
+    STATS::shutdown_duration_ms.add_value(duration.as_millis() as i64);
+    ShutdownReport { drains, duration }
+}
+
+async fn run_drain(drain: Drain) -> DrainOutcome {
+    let Drain {
+        name,
+        deadline,
+        drain,
+    } = drain;
+
+    let start = Instant::now();
+    let timed_out = tokio::time::timeout(deadline, drain()).await.is_err();
+    let duration = start.elapsed();
+
+    STATS::drain_duration_ms.add_value(duration.as_millis() as i64, (name.clone(),));
+    if timed_out {
+        STATS::drain_timeouts.add_value(1, (name.clone(),));
+    }
+
+    DrainOutcome {
+        name,
+        duration,
+        timed_out,
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+struct ShutdownAwareService {
+    service: Box<dyn Fb303Service>,
+    coordinator: ShutdownCoordinator,
+}
+
+impl Fb303Service for ShutdownAwareService {
+    fn getStatus(&self) -> FbStatus {
+        match self.coordinator.inner.phase.load(Ordering::Acquire) {
+            PHASE_RUNNING => self.service.getStatus(),
+            PHASE_STOPPING => FbStatus::Stopping,
+            _ => FbStatus::Stopped,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self.coordinator.inner.phase.load(Ordering::Acquire) {
+            PHASE_RUNNING => self.service.is_alive(),
+            PHASE_STOPPING => true,
+            _ => false,
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.coordinator.is_shutting_down() && self.service.is_ready()
+    }
+
+    fn getCounters(&self) -> BTreeMap<String, i64> {
+        self.service.getCounters()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+
+    use crate::AliveService;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown() {
+        let coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+        let service = coordinator.wrap_service(AliveService::new());
+
+        let drained = Arc::new(AtomicBool::new(false));
+        coordinator.register_drain("fast", Duration::from_secs(10), {
+            let drained = drained.clone();
+            move || async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                drained.store(true, Ordering::Relaxed);
+            }
+        });
+        coordinator.register_drain("stuck", Duration::from_secs(2), future::pending::<()>);
+
+        assert_eq!(service.getStatus(), FbStatus::Alive);
+        assert!(!coordinator.is_shutting_down());
+
+        let shutdown = tokio::spawn({
+            let coordinator = coordinator.clone();
+            async move { coordinator.shutdown().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(coordinator.is_shutting_down());
+        assert_eq!(service.getStatus(), FbStatus::Stopping);
+        assert!(service.is_alive());
+        assert!(!service.is_ready());
+
+        let report = shutdown.await.unwrap();
+        assert_eq!(service.getStatus(), FbStatus::Stopped);
+        assert!(!service.is_alive());
+        assert!(drained.load(Ordering::Relaxed));
+        assert_eq!(report.duration, Duration::from_secs(7));
+
+        let outcomes: Vec<_> = report
+            .drains
+            .iter()
+            .map(|d| (d.name.as_str(), d.timed_out))
+            .collect();
+        assert_eq!(outcomes, vec![("fast", false), ("stuck", true)]);
+
+        // Later calls don't shut down again, and get the same report at once.
+        let start = Instant::now();
+        let again = coordinator.shutdown().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(0));
+        assert_eq!(again.drains.len(), 2);
+        assert_eq!(again.duration, report.duration);
+        assert_eq!(service.getStatus(), FbStatus::Stopped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_shutdown() {
+        let coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+        let service = coordinator.wrap_service(AliveService::new());
+        coordinator.register_drain("slow", Duration::from_secs(10), || {
+            tokio::time::sleep(Duration::from_secs(1))
+        });
+
+        // A second caller waits for the first shutdown, even once the first
+        // caller stopped waiting for it.
+        let mut first = Box::pin(coordinator.shutdown());
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        drop(first);
+        assert_eq!(service.getStatus(), FbStatus::Stopping);
+
+        let start = Instant::now();
+        let report = coordinator.shutdown().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(6));
+        assert_eq!(report.drains.len(), 1);
+        assert_eq!(service.getStatus(), FbStatus::Stopped);
+    }
+}
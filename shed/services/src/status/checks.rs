@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use futures::future::{self, BoxFuture, FutureExt};
+use stats::prelude::*;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use super::ServiceState;
+
+define_stats! {
+    prefix = "services.health_check";
+    duration_ms: dynamic_histogram("{}.duration_ms", (check: String); 10, 0, 10_000, Average; P 50; P 99),
+    failures: dynamic_timeseries("{}.failures", (check: String); Sum),
+}
+
+type CheckFn = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+struct Check {
+    name: String,
+    timeout: Duration,
+    check: CheckFn,
+}
+
+/// Result of the last run of a health check.
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    /// Error message if the check failed or timed out
+    pub error: Option<String>,
+    /// When the check was started
+    pub checked_at: SystemTime,
+    /// How long the check took
+    pub duration: Duration,
+}
+
+impl CheckResult {
+    /// Whether the check passed.
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+struct Inner {
+    checks: Mutex<Vec<Check>>,
+    results: RwLock<BTreeMap<String, CheckResult>>,
+}
+
+/// A set of named asynchronous health checks, e.g. pinging the database or
+/// the cache the service depends on, that are run periodically and aggregated
+/// into the readiness of the service. Cloning gives another handle to the same
+/// set of checks.
+#[derive(Clone)]
+pub struct HealthChecks {
+    inner: Arc<Inner>,
+}
+
+impl HealthChecks {
+    /// Create an empty set of checks.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                checks: Mutex::new(Vec::new()),
+                results: RwLock::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Register a named check, it fails if it returns an error or doesn't
+    /// complete within `timeout`. Registering a check with the name of an
+    /// existing one replaces it.
+    pub fn register<F, Fut>(&self, name: impl Into<String>, timeout: Duration, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        let mut checks = self.inner.checks.lock().expect("poisoned lock");
+        checks.retain(|c| c.name != name);
+        checks.push(Check {
+            name,
+            timeout,
+            check: Arc::new(move || check().boxed()),
+        });
+    }
+
+    /// Results of the last run of every check, keyed by the check name. Checks
+    /// that were not run yet are missing.
+    pub fn results(&self) -> BTreeMap<String, CheckResult> {
+        self.inner.results.read().expect("poisoned lock").clone()
+    }
+
+    /// Whether every registered check passed in its last run. A check that
+    /// was not run yet is considered unhealthy.
+    pub fn is_healthy(&self) -> bool {
+        let checks = self.inner.checks.lock().expect("poisoned lock");
+        let results = self.inner.results.read().expect("poisoned lock");
+        checks
+            .iter()
+            .all(|c| matches!(results.get(&c.name), Some(result) if result.is_healthy()))
+    }
+
+    /// Run all the checks concurrently once and record their results.
+    pub async fn run_once(&self) {
+        let checks: Vec<_> = self
+            .inner
+            .checks
+            .lock()
+            .expect("poisoned lock")
+            .iter()
+            .map(|c| (c.name.clone(), c.timeout, c.check.clone()))
+            .collect();
+
+        let results =
+            future::join_all(checks.into_iter().map(|(name, timeout, check)| async move {
+                let checked_at = SystemTime::now();
+                let start = Instant::now();
+                let error = match tokio::time::timeout(timeout, check()).await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(format!("{:#}", e)),
+                    Err(_) => Some(format!("timed out after {:?}", timeout)),
+                };
+                let duration = start.elapsed();
+
+                STATS::duration_ms.add_value(duration.as_millis() as i64, (name.clone(),));
+                if error.is_some() {
+                    STATS::failures.add_value(1, (name.clone(),));
+                }
+                (
+                    name,
+                    CheckResult {
+                        error,
+                        checked_at,
+                        duration,
+                    },
+                )
+            }))
+            .await;
+
+        self.inner
+            .results
+            .write()
+            .expect("poisoned lock")
+            .extend(results);
+    }
+
+    /// Run the checks every `interval` and set the readiness of the service
+    /// to whether they are all [healthy](Self::is_healthy). The readiness of
+    /// `state` should not be set elsewhere while this is running. The
+    /// returned future never completes.
+    pub async fn run(self, interval: Duration, state: ServiceState) {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.run_once().await;
+            state.set_ready(self.is_healthy());
+        }
+    }
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use anyhow::bail;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_health_checks() {
+        let checks = HealthChecks::new();
+        let db_up = Arc::new(AtomicBool::new(true));
+        checks.register("db", Duration::from_secs(1), {
+            let db_up = db_up.clone();
+            move || {
+                let db_up = db_up.load(Ordering::Relaxed);
+                async move {
+                    if !db_up {
+                        bail!("db is down");
+                    }
+                    Ok(())
+                }
+            }
+        });
+        assert!(!checks.is_healthy());
+
+        checks.run_once().await;
+        assert!(checks.is_healthy());
+
+        checks.register("cache", Duration::from_secs(1), future::pending);
+        checks.run_once().await;
+        let results = checks.results();
+        assert!(results["db"].is_healthy());
+        assert_eq!(
+            results["cache"].error.as_deref(),
+            Some("timed out after 1s")
+        );
+        assert!(!checks.is_healthy());
+
+        checks.register("cache", Duration::from_secs(1), || async { Ok(()) });
+        let state = ServiceState::new();
+        let run = tokio::spawn(checks.clone().run(Duration::from_secs(10), state.clone()));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(state.is_ready());
+
+        db_up.store(false, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(!state.is_ready());
+        assert_eq!(checks.results()["db"].error.as_deref(), Some("db is down"));
+        run.abort();
+    }
+}
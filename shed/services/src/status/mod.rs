@@ -0,0 +1,212 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Liveness and readiness state of a service.
+//!
+//! Following the semantics of orchestrators, the two states are independent:
+//! a service that is not alive should be restarted, while a service that is
+//! alive but not ready should just not receive traffic, e.g. because it is
+//! still warming up its caches or because it is overloaded.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{Fb303Service, FbStatus};
+
+mod checks;
+mod reporters;
+
+pub use self::checks::{CheckResult, HealthChecks};
+pub use self::reporters::{LogReporter, StatsReporter, StatusReporter};
+
+#[cfg(unix)]
+pub use self::reporters::SystemdNotifyReporter;
+
+/// The state a [Transition] happened in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Probe {
+    /// Whether the service should be kept running
+    Liveness,
+    /// Whether the service can take traffic
+    Readiness,
+}
+
+/// A change of one of the states of a [ServiceState].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Transition {
+    /// The state that changed
+    pub probe: Probe,
+    /// The new value of the state
+    pub value: bool,
+}
+
+struct State {
+    alive: bool,
+    ready: bool,
+    was_ready: bool,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    reporters: RwLock<Vec<Box<dyn StatusReporter>>>,
+}
+
+/// Liveness and readiness state of a service, see the
+/// [module documentation](self) for details. A new state is alive, but not
+/// ready. Cloning gives another handle to the same state and it can be passed
+/// to [run_service_framework](crate::run_service_framework) as the service.
+#[derive(Clone)]
+pub struct ServiceState {
+    inner: Arc<Inner>,
+}
+
+impl ServiceState {
+    /// Create a new state that is alive, but not ready.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    alive: true,
+                    ready: false,
+                    was_ready: false,
+                }),
+                reporters: RwLock::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Whether the service should be kept running.
+    pub fn is_alive(&self) -> bool {
+        self.inner.state.lock().expect("poisoned lock").alive
+    }
+
+    /// Whether the service can take traffic.
+    pub fn is_ready(&self) -> bool {
+        self.inner.state.lock().expect("poisoned lock").ready
+    }
+
+    /// Set the liveness of the service, notifying the reporters if the value
+    /// changed.
+    pub fn set_alive(&self, alive: bool) {
+        let changed = {
+            let mut state = self.inner.state.lock().expect("poisoned lock");
+            std::mem::replace(&mut state.alive, alive) != alive
+        };
+        if changed {
+            self.notify(Transition {
+                probe: Probe::Liveness,
+                value: alive,
+            });
+        }
+    }
+
+    /// Set the readiness of the service, notifying the reporters if the value
+    /// changed.
+    pub fn set_ready(&self, ready: bool) {
+        let changed = {
+            let mut state = self.inner.state.lock().expect("poisoned lock");
+            state.was_ready |= ready;
+            std::mem::replace(&mut state.ready, ready) != ready
+        };
+        if changed {
+            self.notify(Transition {
+                probe: Probe::Readiness,
+                value: ready,
+            });
+        }
+    }
+
+    /// Register a reporter that is notified every time the liveness or
+    /// readiness changes. Reporters are notified after the state is updated,
+    /// so they observe the new state, but the order of the notifications is
+    /// not guaranteed if the state is updated concurrently from multiple
+    /// threads. Any `Fn(Transition)` closure can be used as a reporter.
+    pub fn add_reporter(&self, reporter: impl StatusReporter + 'static) {
+        self.inner
+            .reporters
+            .write()
+            .expect("poisoned lock")
+            .push(Box::new(reporter));
+    }
+
+    fn notify(&self, transition: Transition) {
+        for reporter in self.inner.reporters.read().expect("poisoned lock").iter() {
+            reporter.report(transition);
+        }
+    }
+}
+
+impl Default for ServiceState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fb303Service for ServiceState {
+    /// The status is [FbStatus::Dead] if the service is not alive. Otherwise
+    /// it's [FbStatus::Alive] if it's ready, [FbStatus::Starting] if it has
+    /// never been ready yet and [FbStatus::Warning] if it stopped being ready.
+    fn getStatus(&self) -> FbStatus {
+        let state = self.inner.state.lock().expect("poisoned lock");
+        match (state.alive, state.ready, state.was_ready) {
+            (false, _, _) => FbStatus::Dead,
+            (true, true, _) => FbStatus::Alive,
+            (true, false, false) => FbStatus::Starting,
+            (true, false, true) => FbStatus::Warning,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        ServiceState::is_alive(self)
+    }
+
+    fn is_ready(&self) -> bool {
+        ServiceState::is_ready(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_service_state() {
+        let state = ServiceState::new();
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        state.add_reporter({
+            let transitions = transitions.clone();
+            move |t: Transition| transitions.lock().unwrap().push((t.probe, t.value))
+        });
+
+        assert!(state.is_alive());
+        assert!(!state.is_ready());
+        assert_eq!(state.getStatus(), FbStatus::Starting);
+
+        state.set_ready(true);
+        state.set_ready(true);
+        assert_eq!(state.getStatus(), FbStatus::Alive);
+        assert!(Fb303Service::is_ready(&state));
+
+        state.set_ready(false);
+        assert_eq!(state.getStatus(), FbStatus::Warning);
+        assert!(!Fb303Service::is_ready(&state));
+
+        state.set_alive(false);
+        assert_eq!(state.getStatus(), FbStatus::Dead);
+        assert!(!Fb303Service::is_alive(&state));
+
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            vec![
+                (Probe::Readiness, true),
+                (Probe::Readiness, false),
+                (Probe::Liveness, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use fbinit::FacebookInit;
+use slog::{info, warn, Logger};
+use stats::prelude::*;
+
+use super::{Probe, Transition};
+
+define_stats! {
+    prefix = "services.status";
+    alive: singleton_counter(),
+    ready: singleton_counter(),
+    transitions: dynamic_timeseries("transitions.{}", (probe: &'static str); Sum),
+}
+
+/// Sink that is notified about the transitions of a
+/// [ServiceState](super::ServiceState).
+pub trait StatusReporter: Send + Sync {
+    /// Called after the liveness or readiness of the service changed.
+    fn report(&self, transition: Transition);
+}
+
+impl<F> StatusReporter for F
+where
+    F: Fn(Transition) + Send + Sync,
+{
+    fn report(&self, transition: Transition) {
+        self(transition)
+    }
+}
+
+/// Reporter that logs every transition.
+pub struct LogReporter {
+    logger: Logger,
+}
+
+impl LogReporter {
+    /// Create a reporter logging to the provided logger.
+    pub fn new(logger: Logger) -> Self {
+        Self { logger }
+    }
+}
+
+impl StatusReporter for LogReporter {
+    fn report(&self, transition: Transition) {
+        match (transition.probe, transition.value) {
+            (Probe::Liveness, true) => info!(self.logger, "Service is alive"),
+            (Probe::Liveness, false) => warn!(self.logger, "Service is no longer alive"),
+            (Probe::Readiness, true) => info!(self.logger, "Service is ready"),
+            (Probe::Readiness, false) => warn!(self.logger, "Service is no longer ready"),
+        }
+    }
+}
+
+/// Reporter that exports the current liveness and readiness as the
+/// `services.status.alive` and `services.status.ready` counters and counts the
+/// transitions of each of them.
+pub struct StatsReporter {
+    fb: FacebookInit,
+}
+
+impl StatsReporter {
+    /// Create a reporter exporting the status through the `stats` crate.
+    pub fn new(fb: FacebookInit) -> Self {
+        Self { fb }
+    }
+}
+
+impl StatusReporter for StatsReporter {
+    fn report(&self, transition: Transition) {
+        let (counter, probe) = match transition.probe {
+            Probe::Liveness => (&*STATS::alive, "liveness"),
+            Probe::Readiness => (&*STATS::ready, "readiness"),
+        };
+        counter.set_value(self.fb, transition.value as i64);
+        STATS::transitions.add_value(1, (probe,));
+    }
+}
+
+#[cfg(unix)]
+pub use self::systemd::SystemdNotifyReporter;
+
+#[cfg(unix)]
+mod systemd {
+    use std::env;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+
+    use super::{Probe, StatusReporter, Transition};
+
+    /// Reporter notifying systemd about the status of the service using the
+    /// `sd_notify` protocol: readiness is reported with `READY=1` and the
+    /// service losing its liveness with `STOPPING=1`. As systemd has no notion
+    /// of a service becoming not ready again, that is only reported via
+    /// `STATUS`.
+    pub struct SystemdNotifyReporter {
+        socket: UnixDatagram,
+        path: PathBuf,
+    }
+
+    impl SystemdNotifyReporter {
+        /// Create a reporter sending notifications to the socket from the
+        /// `NOTIFY_SOCKET` environment variable, or `None` if it's not set,
+        /// i.e. the service is not run by systemd with `Type=notify`.
+        pub fn from_env() -> io::Result<Option<Self>> {
+            match env::var_os("NOTIFY_SOCKET") {
+                Some(path) => Self::new(path).map(Some),
+                None => Ok(None),
+            }
+        }
+
+        /// Create a reporter sending notifications to the provided socket.
+        /// Sockets in the abstract namespace are not supported.
+        pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+            let path = path.into();
+            if path.as_os_str().as_bytes().starts_with(b"@") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("abstract notify socket {} is not supported", path.display()),
+                ));
+            }
+            Ok(Self {
+                socket: UnixDatagram::unbound()?,
+                path,
+            })
+        }
+    }
+
+    impl StatusReporter for SystemdNotifyReporter {
+        fn report(&self, transition: Transition) {
+            let message = match (transition.probe, transition.value) {
+                (Probe::Liveness, true) => "STATUS=alive",
+                (Probe::Liveness, false) => "STOPPING=1\nSTATUS=not alive",
+                (Probe::Readiness, true) => "READY=1\nSTATUS=ready",
+                (Probe::Readiness, false) => "STATUS=not ready",
+            };
+            // Notifications are best effort, there is nothing to be done if
+            // systemd is not listening.
+            let _ = self.socket.send_to(message.as_bytes(), &self.path);
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_systemd_notify_reporter() {
+            let path = env::temp_dir().join(format!("services-notify-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixDatagram::bind(&path).unwrap();
+
+            let reporter = SystemdNotifyReporter::new(&path).unwrap();
+            reporter.report(Transition {
+                probe: Probe::Readiness,
+                value: true,
+            });
+
+            let mut buf = [0; 64];
+            let len = listener.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"READY=1\nSTATUS=ready");
+            std::fs::remove_file(&path).unwrap();
+
+            assert!(SystemdNotifyReporter::new("@notify").is_err());
+        }
+    }
+}
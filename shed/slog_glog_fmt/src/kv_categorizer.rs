@@ -61,7 +61,11 @@ impl KVCategorizer for ErrorCategorizer {
             Ok(SlogKVErrorKey::Error) => KVCategory::LevelLog(Level::Error),
             Ok(SlogKVErrorKey::Cause) => KVCategory::LevelLog(Level::Debug),
             Ok(SlogKVErrorKey::Backtrace) => KVCategory::LevelLog(Level::Trace),
-            Ok(SlogKVErrorKey::RootCause) | Err(()) => InlineCategorizer.categorize(key),
+            Ok(SlogKVErrorKey::RootCause)
+            | Ok(SlogKVErrorKey::ErrorKind)
+            | Ok(SlogKVErrorKey::Retriable)
+            | Ok(SlogKVErrorKey::HasBacktrace)
+            | Err(()) => InlineCategorizer.categorize(key),
             Ok(SlogKVErrorKey::ErrorDebug) => KVCategory::LevelLog(Level::Debug),
         }
     }
@@ -73,6 +77,9 @@ impl KVCategorizer for ErrorCategorizer {
             Ok(SlogKVErrorKey::Backtrace) => "Originated in",
             Ok(SlogKVErrorKey::RootCause) => "Root cause",
             Ok(SlogKVErrorKey::ErrorDebug) => "Debug context",
+            Ok(SlogKVErrorKey::ErrorKind) => "Error kind",
+            Ok(SlogKVErrorKey::Retriable) => "Retriable",
+            Ok(SlogKVErrorKey::HasBacktrace) => "Has backtrace",
             Err(()) => InlineCategorizer.name(key),
         }
     }
@@ -100,7 +107,7 @@ mod tests {
     use super::*;
 
     use anyhow::Error;
-    use failure_ext::SlogKVError;
+    use failure_ext::{ErrorTagsExt, SlogKVError};
     use itertools::assert_equal;
     use slog::{b, record, KV};
     use thiserror::Error;
@@ -149,4 +156,34 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn test_error_tags() {
+        let err = Error::from(TestError::MyError(0))
+            .with_kind("my_error")
+            .with_retriable(true);
+        let debug = format!("{:#?}", err);
+
+        let categorizer = ErrorCategorizer;
+        assert_eq!(categorizer.categorize("error_kind"), KVCategory::Inline);
+        assert_eq!(categorizer.name("retriable"), "Retriable");
+
+        let mut serializer = CollectorSerializer::new(&categorizer);
+        SlogKVError(err)
+            .serialize(
+                &record!(Level::Info, "test", &format_args!(""), b!()),
+                &mut serializer,
+            )
+            .expect("failed to serialize");
+        assert_equal(
+            serializer.into_inner(),
+            vec![
+                ("error", "my error #0 displayed".to_owned()),
+                ("error_debug", debug),
+                ("error_kind", "my_error".to_owned()),
+                ("retriable", "true".to_owned()),
+                ("root_cause", "my error #0 displayed".to_owned()),
+            ],
+        );
+    }
 }
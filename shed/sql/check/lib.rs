@@ -0,0 +1,535 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module introduces a proc macro validating the queries of sql::queries! at
+//! compile time. It only validates them with the `validate` feature, which
+//! parses their SQL with sqlparser, and with the `postgres` feature also
+//! parses the Mysql queries as Postgres, which runs the same SQL, except for
+//! the Mysql-only statements such as `REPLACE INTO`. It also
+//! introduces the proc macro turning
+//! the `:name` placeholders of the queries into the `{name}` of `format!`.
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+extern crate proc_macro;
+
+use std::collections::BTreeSet;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parenthesized, parse_macro_input, Error, Expr, Ident, Lit, LitStr, Token};
+
+/// Check the query of a sql::queries! entry. This should never be used
+/// directly, it is made public so that the queries! macro can make use of it.
+///
+/// The input is `params(a, b) lists(ids) <kind> mysql("...") sqlite("...")`,
+/// where the lists are substituted by a parenthesized list of values, e.g.
+/// the `values` of a write query, and the kind is `read`, `none` or
+/// `insert_or_ignore`. With the `validate` feature, it fails to compile if
+/// the queries don't use exactly the parameters and lists, or if they are not
+/// valid SQL of their dialect once substituted. With the `postgres` feature,
+/// the Mysql queries must also be valid Postgres, e.g. they can't quote
+/// identifiers with backticks. The Mysql-only statements, `REPLACE INTO` and
+/// `ON DUPLICATE KEY UPDATE`, are not checked as Postgres: they are only
+/// ever sent to Mysql, so that the feature can be enabled for crates that
+/// also have such queries.
+#[proc_macro]
+pub fn check_query(input: TokenStream) -> TokenStream {
+    if !cfg!(feature = "validate") {
+        return TokenStream::new();
+    }
+    let query = parse_macro_input!(input as CheckedQuery);
+    match query.check() {
+        Ok(()) => TokenStream::new(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The string literal of a query with its `:name` placeholders replaced by
+/// `{name}`, for `format!`. This should never be used directly, it is made
+/// public so that the queries! macro can make use of it.
+///
+/// A query that is not a literal, e.g. `concat!(...)`, is returned as is.
+#[proc_macro]
+pub fn named_query(input: TokenStream) -> TokenStream {
+    let query = parse_macro_input!(input as Expr);
+    match literal(&query) {
+        Some(lit) => {
+            let lit = LitStr::new(&named_to_format(&lit.value()), lit.span());
+            quote!(#lit).into()
+        }
+        None => quote!(#query).into(),
+    }
+}
+
+/// Replace the `:name` placeholders of `sql` by `{name}`. Those in quotes,
+/// in `{...}` and the casts `::type` are left alone, and so is a `:` after a
+/// word, e.g. in `label:`.
+fn named_to_format(sql: &str) -> String {
+    let mut result = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut chars = sql.chars().peekable();
+    let mut previous = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '{') => {
+                result.push(c);
+                // Copy the placeholder or the escaped `{{`
+                for c in chars.by_ref() {
+                    result.push(c);
+                    if c == '}' || c == '{' {
+                        break;
+                    }
+                }
+                previous = Some('}');
+                continue;
+            }
+            (None, ':') if chars.peek() == Some(&':') => {
+                chars.next();
+                result.push_str("::");
+                previous = Some(':');
+                continue;
+            }
+            (None, ':')
+                if !previous.is_some_and(is_word)
+                    && chars
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+            {
+                result.push('{');
+                while let Some(c) = chars.next_if(|c| is_word(*c)) {
+                    result.push(c);
+                }
+                result.push('}');
+                previous = Some('}');
+                continue;
+            }
+            (None, _) => {}
+        }
+        result.push(c);
+        previous = Some(c);
+    }
+    result
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+struct CheckedQuery {
+    params: Vec<Ident>,
+    lists: Vec<Ident>,
+    kind: Ident,
+    mysql: Expr,
+    sqlite: Expr,
+}
+
+fn parse_group<T>(
+    input: ParseStream,
+    name: &str,
+    parse: impl FnOnce(ParseStream) -> syn::Result<T>,
+) -> syn::Result<T> {
+    let ident: Ident = input.parse()?;
+    if ident != name {
+        return Err(Error::new(ident.span(), format!("expected `{}`", name)));
+    }
+    let content;
+    parenthesized!(content in input);
+    parse(&content)
+}
+
+fn parse_idents(input: ParseStream) -> syn::Result<Vec<Ident>> {
+    Ok(Punctuated::<Ident, Token![,]>::parse_terminated(input)?
+        .into_iter()
+        .collect())
+}
+
+impl Parse for CheckedQuery {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            params: parse_group(input, "params", parse_idents)?,
+            lists: parse_group(input, "lists", parse_idents)?,
+            kind: input.parse()?,
+            mysql: parse_group(input, "mysql", Expr::parse)?,
+            sqlite: parse_group(input, "sqlite", Expr::parse)?,
+        })
+    }
+}
+
+/// The string literal of `expr`, if it is one. Queries built by other macros,
+/// e.g. `concat!`, are not checked.
+fn literal(expr: &Expr) -> Option<&LitStr> {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Str(lit) => Some(lit),
+            _ => None,
+        },
+        Expr::Group(group) => literal(&group.expr),
+        Expr::Paren(paren) => literal(&paren.expr),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Dialect {
+    Mysql,
+    Postgres,
+    Sqlite,
+}
+
+impl Dialect {
+    fn insert_or_ignore(self) -> &'static str {
+        match self {
+            Dialect::Mysql => "INSERT IGNORE",
+            Dialect::Postgres => "INSERT",
+            Dialect::Sqlite => "INSERT OR IGNORE",
+        }
+    }
+
+    /// Text standing for the `index`th value of a query, Postgres getting
+    /// the `$n` placeholders it is sent
+    fn value(self, index: usize) -> String {
+        match self {
+            Dialect::Postgres => format!("${}", index),
+            Dialect::Mysql | Dialect::Sqlite => "1".to_owned(),
+        }
+    }
+}
+
+impl CheckedQuery {
+    fn check(&self) -> syn::Result<()> {
+        if let Some(mysql) = literal(&self.mysql) {
+            self.check_literal(mysql, Dialect::Mysql)?;
+            if cfg!(feature = "postgres") && !is_mysql_only(&mysql.value()) {
+                self.check_literal(mysql, Dialect::Postgres)?;
+            }
+        }
+        if let Some(sqlite) = literal(&self.sqlite) {
+            self.check_literal(sqlite, Dialect::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    fn check_literal(&self, lit: &LitStr, dialect: Dialect) -> syn::Result<()> {
+        let lit = LitStr::new(&named_to_format(&lit.value()), lit.span());
+        let mut sql = self.substitute(&lit, dialect)?;
+        if let (Dialect::Postgres, "insert_or_ignore") = (dialect, self.kind.to_string().as_str()) {
+            sql = format!(
+                "{} ON CONFLICT DO NOTHING",
+                sql.trim_end().trim_end_matches(';')
+            );
+        }
+        if let Dialect::Sqlite = dialect {
+            if has_parenthesized_compound(&sql) {
+                return Err(Error::new(
+                    lit.span(),
+                    "Sqlite doesn't support parentheses around the SELECTs of a UNION, \
+                     INTERSECT or EXCEPT, give the query without them in sqlite(...)",
+                ));
+            }
+        }
+        parse(&sql, dialect).map_err(|err| Error::new(lit.span(), err))
+    }
+
+    /// Replace the placeholders of `lit` by values of the right shape,
+    /// checking that they are exactly the parameters of the query
+    fn substitute(&self, lit: &LitStr, dialect: Dialect) -> syn::Result<String> {
+        let mut unused: BTreeSet<String> = self
+            .params
+            .iter()
+            .chain(self.lists.iter())
+            .map(|name| name.to_string())
+            .collect();
+        let mut values = 0;
+        let sql = substitute(&lit.value(), |name| {
+            unused.remove(name);
+            if self.params.iter().any(|param| param == name) {
+                values += 1;
+                Ok(dialect.value(values))
+            } else if self.lists.iter().any(|list| list == name) {
+                values += 1;
+                Ok(format!("({})", dialect.value(values)))
+            } else if name == "insert_or_ignore" && self.kind == "insert_or_ignore" {
+                Ok(dialect.insert_or_ignore().to_owned())
+            } else if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+                Err(
+                    "positional placeholders are not supported, use the name of a parameter"
+                        .to_owned(),
+                )
+            } else {
+                Err(format!(
+                    "query uses `{}`, which is not one of its parameters",
+                    name
+                ))
+            }
+        })
+        .map_err(|err| Error::new(lit.span(), err))?;
+        match unused.into_iter().next() {
+            Some(name) => Err(Error::new(
+                lit.span(),
+                format!("parameter `{}` is not used in the query", name),
+            )),
+            None => Ok(sql),
+        }
+    }
+}
+
+/// Replace each `{name}` placeholder of `sql` by `replace(name)`, with the
+/// escapes of `format!`
+fn substitute(
+    sql: &str,
+    mut replace: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut result = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => {
+                            return Err("unclosed `{` in query, use `{{` to escape it".to_owned())
+                        }
+                    }
+                }
+                let name = placeholder.split(':').next().unwrap_or_default().trim();
+                result.push_str(&replace(name)?);
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '}' => return Err("unmatched `}` in query, use `}}` to escape it".to_owned()),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Open,
+    Close,
+}
+
+/// The words and parentheses of `sql`, outside of quotes
+fn tokens(sql: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut quote = None;
+    let mut start = None;
+    for (idx, c) in sql.char_indices() {
+        let is_word = quote.is_none() && (c.is_ascii_alphanumeric() || c == '_');
+        match start {
+            Some(word_start) if !is_word => {
+                tokens.push(Token::Word(&sql[word_start..idx]));
+                start = None;
+            }
+            None if is_word => start = Some(idx),
+            _ => {}
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => tokens.push(Token::Open),
+            (None, ')') => tokens.push(Token::Close),
+            (None, _) => {}
+        }
+    }
+    if let Some(word_start) = start {
+        tokens.push(Token::Word(&sql[word_start..]));
+    }
+    tokens
+}
+
+/// Whether `sql` is a statement that only Mysql runs, `REPLACE INTO` or an
+/// `INSERT ... ON DUPLICATE KEY UPDATE`
+fn is_mysql_only(sql: &str) -> bool {
+    let words: Vec<_> = tokens(sql)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word),
+            Token::Open | Token::Close => None,
+        })
+        .collect();
+    let is = |word: &str, expected: &str| word.eq_ignore_ascii_case(expected);
+    words.first().is_some_and(|word| is(word, "REPLACE"))
+        || words
+            .windows(3)
+            .any(|w| is(w[0], "ON") && is(w[1], "DUPLICATE") && is(w[2], "KEY"))
+}
+
+/// Whether an operand of a UNION, INTERSECT or EXCEPT of `sql` is a
+/// parenthesized SELECT, e.g. `(SELECT 1) UNION (SELECT 2)`, which Mysql and
+/// Postgres run but Sqlite rejects
+fn has_parenthesized_compound(sql: &str) -> bool {
+    const COMPOUND: &[&str] = &["UNION", "INTERSECT", "EXCEPT"];
+    let tokens = tokens(sql);
+    let is_word = |idx: usize, words: &[&str]| match tokens.get(idx) {
+        Some(Token::Word(word)) => words.iter().any(|w| w.eq_ignore_ascii_case(word)),
+        _ => false,
+    };
+    let is_select =
+        |idx: usize| tokens[idx] == Token::Open && is_word(idx + 1, &["SELECT", "WITH"]);
+    // A parenthesized SELECT is an operand if it doesn't follow a word, as
+    // the subqueries of `FROM (...)` or `IN (...)` do, or follows a compound
+    let is_operand = |idx: usize| {
+        is_select(idx)
+            && (idx == 0
+                || tokens[idx - 1] == Token::Open
+                || is_word(idx - 1, COMPOUND)
+                || is_word(idx - 1, &["ALL", "DISTINCT"]))
+    };
+    let mut opened = Vec::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Open => opened.push(idx),
+            Token::Close => {
+                let open = opened.pop();
+                if is_word(idx + 1, COMPOUND) && matches!(open, Some(open) if is_operand(open)) {
+                    return true;
+                }
+            }
+            Token::Word(_) => {
+                if is_word(idx, COMPOUND) {
+                    let next = if is_word(idx + 1, &["ALL", "DISTINCT"]) {
+                        idx + 2
+                    } else {
+                        idx + 1
+                    };
+                    if next < tokens.len() && is_select(next) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(feature = "validate")]
+fn parse(sql: &str, dialect: Dialect) -> Result<(), String> {
+    use sqlparser::dialect::{MySqlDialect, PostgreSqlDialect, SQLiteDialect};
+    use sqlparser::parser::Parser;
+
+    let (name, result) = match dialect {
+        Dialect::Mysql => ("mysql", Parser::parse_sql(&MySqlDialect {}, sql)),
+        Dialect::Postgres => ("postgres", Parser::parse_sql(&PostgreSqlDialect {}, sql)),
+        Dialect::Sqlite => ("sqlite", Parser::parse_sql(&SQLiteDialect {}, sql)),
+    };
+    result
+        .map(|_| ())
+        .map_err(|err| format!("invalid {} query: {}", name, err))
+}
+
+#[cfg(not(feature = "validate"))]
+fn parse(_sql: &str, _dialect: Dialect) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn placeholders(sql: &str) -> Result<(String, Vec<String>), String> {
+        let mut names = Vec::new();
+        let sql = substitute(sql, |name| {
+            names.push(name.to_owned());
+            Ok("?".to_owned())
+        })?;
+        Ok((sql, names))
+    }
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(
+            placeholders("SELECT x FROM foo WHERE id IN {ids} AND x = {x}").unwrap(),
+            (
+                "SELECT x FROM foo WHERE id IN ? AND x = ?".to_owned(),
+                vec!["ids".to_owned(), "x".to_owned()]
+            )
+        );
+        assert_eq!(
+            placeholders("SELECT '{{}}', {x:?}").unwrap(),
+            ("SELECT '{}', ?".to_owned(), vec!["x".to_owned()])
+        );
+        assert!(placeholders("SELECT {x").is_err());
+        assert!(placeholders("SELECT x}").is_err());
+    }
+
+    #[test]
+    fn test_named_to_format() {
+        assert_eq!(
+            named_to_format("SELECT x FROM foo WHERE id = :id AND y IN {ys} OR z=:z_1"),
+            "SELECT x FROM foo WHERE id = {id} AND y IN {ys} OR z={z_1}"
+        );
+        assert_eq!(
+            named_to_format("SELECT ':a', x::text, '{{:b}}', {c:?}, d:e, :f, @g := 1"),
+            "SELECT ':a', x::text, '{{:b}}', {c:?}, d:e, {f}, @g := 1"
+        );
+        assert_eq!(named_to_format("SELECT {{:a}}"), "SELECT {{{a}}}");
+    }
+
+    #[cfg(feature = "validate")]
+    #[test]
+    fn test_parse() {
+        let replace = "REPLACE INTO foo (id, x) VALUES (1, 2)";
+        assert!(parse(replace, Dialect::Mysql).is_ok());
+        assert!(parse(replace, Dialect::Postgres).is_err());
+        assert!(parse(
+            "INSERT INTO foo (x) VALUES ($1) ON CONFLICT DO NOTHING",
+            Dialect::Postgres
+        )
+        .is_ok());
+        assert!(parse("INSERT OR IGNORE INTO foo (x) VALUES (1)", Dialect::Sqlite).is_ok());
+        assert!(parse("SELECT x FROM", Dialect::Mysql).is_err());
+    }
+
+    #[test]
+    fn test_is_mysql_only() {
+        assert!(is_mysql_only("REPLACE INTO foo (id, x) VALUES (1, 2)"));
+        assert!(is_mysql_only(
+            "INSERT INTO foo (id, x) VALUES (1, 2) ON DUPLICATE KEY UPDATE x = 2"
+        ));
+        assert!(!is_mysql_only("INSERT INTO foo (id, x) VALUES (1, 2)"));
+        assert!(!is_mysql_only(
+            "SELECT x FROM foo WHERE y = 'ON DUPLICATE KEY' OR z = REPLACE(x, 'a', 'b')"
+        ));
+    }
+
+    #[test]
+    fn test_parenthesized_compound() {
+        assert!(has_parenthesized_compound(
+            "(SELECT x FROM foo WHERE id = 1) UNION ALL (SELECT x FROM foo WHERE id = 2)"
+        ));
+        assert!(has_parenthesized_compound(
+            "SELECT x FROM foo UNION (SELECT x FROM bar)"
+        ));
+        assert!(!has_parenthesized_compound(
+            "WITH t AS (SELECT id FROM foo WHERE x IN (1)) \
+             SELECT id FROM t UNION SELECT id FROM foo WHERE y = '(SELECT 1) UNION'"
+        ));
+        assert!(!has_parenthesized_compound(
+            "SELECT x FROM (SELECT x FROM foo) UNION SELECT x FROM (SELECT x FROM bar)"
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Queries that sql_check must reject, with the errors they get
+
+#[cfg(feature = "validate")]
+#[test]
+fn test_compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+    if cfg!(feature = "postgres") {
+        cases.compile_fail("tests/ui_postgres/*.rs");
+        cases.pass("tests/ui_postgres_pass/*.rs");
+    }
+}
@@ -0,0 +1,16 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+sql_check::check_query!(
+    params(x) lists() read
+    mysql("SELECT x FROM foo WHERE x = {x} AND y = {y}")
+    sqlite("SELECT x FROM foo WHERE x = {x}")
+);
+
+fn main() {}
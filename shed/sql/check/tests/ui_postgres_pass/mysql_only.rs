@@ -0,0 +1,24 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+// The Mysql-only statements are not checked as Postgres
+
+sql_check::check_query!(
+    params(x) lists() none
+    mysql("REPLACE INTO foo (id, x) VALUES (1, {x})")
+    sqlite("REPLACE INTO foo (id, x) VALUES (1, {x})")
+);
+
+sql_check::check_query!(
+    params(x) lists() none
+    mysql("INSERT INTO foo (id, x) VALUES (1, {x}) ON DUPLICATE KEY UPDATE x = {x}")
+    sqlite("INSERT INTO foo (id, x) VALUES (1, {x}) ON CONFLICT (id) DO UPDATE SET x = {x}")
+);
+
+fn main() {}
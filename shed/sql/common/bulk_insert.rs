@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [BulkInsert], which inserts many rows with a `write`
+//! query taking `values`, a bounded number of rows per statement.
+//!
+//! # Example
+//! ```
+//! use anyhow::Error;
+//! use sql::{queries, Connection};
+//! use sql_common::bulk_insert::{BulkInsert, BulkInsertResult};
+//!
+//! queries! {
+//!     write InsertFoo(values: (x: i64, name: String)) {
+//!         none,
+//!         "INSERT INTO foo (x, name) VALUES {values}"
+//!     }
+//! }
+//!
+//! async fn insert_all(
+//!     conn: &Connection,
+//!     rows: Vec<(i64, String)>,
+//! ) -> Result<BulkInsertResult, Error> {
+//!     BulkInsert::new(1000)
+//!         .run(rows, |chunk| async move {
+//!             let values: Vec<_> = chunk.iter().map(|(x, name)| (x, name)).collect();
+//!             InsertFoo::query(conn, &values).await
+//!         })
+//!         .await
+//! }
+//! #
+//! # fn main() {}
+//! ```
+
+use std::future::Future;
+
+use anyhow::Error;
+
+use crate::transaction::Transaction;
+use crate::WriteResult;
+
+/// Inserts rows in chunks of at most `chunk_size` rows, each chunk with its
+/// own statement. Without a transaction, the chunks inserted before a
+/// failing one stay inserted.
+#[derive(Clone, Debug)]
+pub struct BulkInsert {
+    chunk_size: usize,
+}
+
+impl BulkInsert {
+    /// Insert at most `chunk_size` rows per statement. Panics if
+    /// `chunk_size` is 0.
+    pub fn new(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunks must have at least one row");
+        Self { chunk_size }
+    }
+
+    /// Most rows inserted per statement
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Insert `rows` by calling `insert` with each chunk of them in order
+    pub async fn run<T, F, Fut>(
+        &self,
+        rows: impl IntoIterator<Item = T>,
+        mut insert: F,
+    ) -> Result<BulkInsertResult, Error>
+    where
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<WriteResult, Error>>,
+    {
+        let mut rows = rows.into_iter();
+        let mut result = BulkInsertResult::default();
+        loop {
+            let chunk: Vec<T> = rows.by_ref().take(self.chunk_size).collect();
+            if chunk.is_empty() {
+                return Ok(result);
+            }
+            result.add(insert(chunk).await?);
+        }
+    }
+
+    /// Insert `rows` in `transaction` by calling `insert` with each chunk of
+    /// them in order, e.g. with the `query_with_transaction` of a query
+    pub async fn run_with_transaction<T, F, Fut>(
+        &self,
+        mut transaction: Transaction,
+        rows: impl IntoIterator<Item = T>,
+        mut insert: F,
+    ) -> Result<(Transaction, BulkInsertResult), Error>
+    where
+        F: FnMut(Transaction, Vec<T>) -> Fut,
+        Fut: Future<Output = Result<(Transaction, WriteResult), Error>>,
+    {
+        let mut rows = rows.into_iter();
+        let mut result = BulkInsertResult::default();
+        loop {
+            let chunk: Vec<T> = rows.by_ref().take(self.chunk_size).collect();
+            if chunk.is_empty() {
+                return Ok((transaction, result));
+            }
+            let (next, write_result) = insert(transaction, chunk).await?;
+            transaction = next;
+            result.add(write_result);
+        }
+    }
+}
+
+/// Results of the statements of a [BulkInsert]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BulkInsertResult {
+    affected_rows: u64,
+    last_insert_ids: Vec<Option<u64>>,
+    insert_ids: Vec<u64>,
+}
+
+impl BulkInsertResult {
+    fn add(&mut self, result: WriteResult) {
+        self.affected_rows += result.affected_rows();
+        self.last_insert_ids.push(result.last_insert_id());
+        self.insert_ids.extend_from_slice(result.insert_ids());
+    }
+
+    /// Return the number of rows affected by all the statements
+    pub fn affected_rows(&self) -> u64 {
+        self.affected_rows
+    }
+
+    /// Return the id of the last inserted row of each statement, if any
+    pub fn last_insert_ids(&self) -> &[Option<u64>] {
+        &self.last_insert_ids
+    }
+
+    /// Return the ids of the inserted rows of all the statements, in order, see
+    /// [WriteResult::insert_ids]
+    pub fn insert_ids(&self) -> &[u64] {
+        &self.insert_ids
+    }
+
+    /// Return the number of statements run
+    pub fn chunks(&self) -> usize {
+        self.last_insert_ids.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[tokio::test]
+    async fn test_run() -> Result<(), Error> {
+        let mut chunks = Vec::new();
+        let mut inserted = 0;
+        let result = BulkInsert::new(2)
+            .run(0..5u64, |chunk| {
+                chunks.push(chunk.clone());
+                inserted += chunk.len() as u64;
+                let last_insert_id = inserted;
+                let insert_ids =
+                    (last_insert_id + 1 - chunk.len() as u64..=last_insert_id).collect();
+                async move {
+                    Ok(WriteResult::new(Some(last_insert_id), chunk.len() as u64)
+                        .with_insert_ids(insert_ids))
+                }
+            })
+            .await?;
+        assert_eq!(chunks, vec![vec![0, 1], vec![2, 3], vec![4]]);
+        assert_eq!(result.affected_rows(), 5);
+        assert_eq!(result.last_insert_ids(), &[Some(2), Some(4), Some(5)]);
+        assert_eq!(result.insert_ids(), &[1, 2, 3, 4, 5]);
+        assert_eq!(result.chunks(), 3);
+
+        let result = BulkInsert::new(2)
+            .run(Vec::<u64>::new(), |_| async { Err(anyhow!("no rows")) })
+            .await?;
+        assert_eq!(result, BulkInsertResult::default());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_on_error() {
+        let mut calls = 0;
+        let result = BulkInsert::new(1)
+            .run(0..3, |row| {
+                calls += 1;
+                async move {
+                    match row[0] {
+                        1 => Err(anyhow!("failed")),
+                        _ => Ok(WriteResult::new(None, 1)),
+                    }
+                }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+    }
+}
@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing the read-through caching of the results of the `read`
+//! queries in memcache. Each read query has a `cache_key` function returning
+//! the [CacheKey] of its result for some parameters, made of its name and a
+//! hash of the parameters. With the `memcache` feature, `QueryCache` looks the
+//! results up in memcache before running the queries, and removes them when a
+//! write changes them:
+//!
+//! ```ignore
+//! let cache = QueryCache::new(client, KeyGen::new("myservice.sql", 1, 0), JsonCodec)
+//!     .with_ttl(Duration::from_secs(600));
+//!
+//! let rows = cache
+//!     .get_or_fetch(&SelectName::cache_key(&id), || SelectName::query(&conn, &id))
+//!     .await?;
+//!
+//! cache
+//!     .invalidate_after([SelectName::cache_key(&id)], UpdateName::query(&conn, &id, &name))
+//!     .await?;
+//! ```
+//!
+//! Memcache failing is not an error: the queries run on the database, and
+//! the errors are only counted in `sql.query_cache.<query>.errors`.
+
+use hash_memo::fnv1a;
+
+/// Key of the result of a read query for some parameters, see the
+/// `cache_key` function of the queries
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CacheKey {
+    query: &'static str,
+    key: String,
+}
+
+impl CacheKey {
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Key of the query `query` run with
+    /// the parameters `params`, formatted as SQL literals, the lists as
+    /// parenthesized lists of literals.
+    #[doc(hidden)]
+    pub fn new(query: &'static str, params: &[String]) -> Self {
+        let params = params.join("\0");
+        Self {
+            query,
+            key: format!("{}.{:016x}", query, fnv1a(params.as_bytes())),
+        }
+    }
+
+    /// Name of the query
+    pub fn query(&self) -> &str {
+        self.query
+    }
+
+    /// The key, which a `QueryCache` prefixes with its `KeyGen`
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+}
+
+#[cfg(feature = "memcache")]
+pub use self::memcache_cache::QueryCache;
+
+#[cfg(feature = "memcache")]
+mod memcache_cache {
+    use std::future::Future;
+    use std::time::Duration;
+
+    use anyhow::{format_err, Error};
+    use memcache::{KeyGen, MemcacheClient, MemcacheCodec};
+    use stats::prelude::*;
+
+    use super::CacheKey;
+
+    define_stats! {
+        prefix = "sql.query_cache";
+        hits: dynamic_timeseries("{}.hits", (query: String); Rate, Sum),
+        misses: dynamic_timeseries("{}.misses", (query: String); Rate, Sum),
+        errors: dynamic_timeseries("{}.errors", (query: String); Rate, Sum),
+    }
+
+    /// Read-through cache of query results in memcache, serialized with a
+    /// `MemcacheCodec`
+    #[derive(Clone)]
+    pub struct QueryCache<C> {
+        client: MemcacheClient,
+        keygen: KeyGen,
+        codec: C,
+        ttl: Option<Duration>,
+    }
+
+    impl<C> QueryCache<C> {
+        /// Cache the results in `client` under the keys of `keygen`,
+        /// serialized with `codec`. The results don't expire.
+        pub fn new(client: MemcacheClient, keygen: KeyGen, codec: C) -> Self {
+            Self {
+                client,
+                keygen,
+                codec,
+                ttl: None,
+            }
+        }
+
+        /// Let the results expire after `ttl`
+        pub fn with_ttl(self, ttl: Duration) -> Self {
+            Self {
+                ttl: Some(ttl),
+                ..self
+            }
+        }
+
+        /// The result under `key`, or else the result of `fetch`, which runs
+        /// the query, stored under `key`. The errors of `fetch` are returned
+        /// and not stored.
+        pub async fn get_or_fetch<T, F, Fut>(&self, key: &CacheKey, fetch: F) -> Result<T, Error>
+        where
+            C: MemcacheCodec<T>,
+            F: FnOnce() -> Fut,
+            Fut: Future<Output = Result<T, Error>>,
+        {
+            let query = key.query().to_owned();
+            let key = self.keygen.key(key.as_str());
+            match self.client.get_decoded(&self.codec, &key).await {
+                Ok(Some(value)) => {
+                    STATS::hits.add_value(1, (query,));
+                    return Ok(value);
+                }
+                Ok(None) => STATS::misses.add_value(1, (query.clone(),)),
+                // Memcache is unavailable or the value was written by another
+                // version, so the value is read from the database
+                Err(_) => STATS::errors.add_value(1, (query.clone(),)),
+            }
+            let value = fetch().await?;
+            let stored = match self.ttl {
+                Some(ttl) => {
+                    self.client
+                        .set_encoded_with_ttl(&self.codec, &key, &value, ttl)
+                        .await
+                }
+                None => self.client.set_encoded(&self.codec, &key, &value).await,
+            };
+            if stored.is_err() {
+                STATS::errors.add_value(1, (query,));
+            }
+            Ok(value)
+        }
+
+        /// Remove the results under `keys`, failing if any of them may not
+        /// be removed
+        pub async fn invalidate<'a>(
+            &self,
+            keys: impl IntoIterator<Item = &'a CacheKey>,
+        ) -> Result<(), Error> {
+            let keys: Vec<_> = keys
+                .into_iter()
+                .map(|key| self.keygen.key(key.as_str()))
+                .collect();
+            for (key, result) in self.client.delete_multi(&keys).await {
+                result.map_err(|err| format_err!("failed to invalidate {}: {}", key, err))?;
+            }
+            Ok(())
+        }
+
+        /// Run `write`, then remove the results under `keys` if it succeeded,
+        /// e.g. for the reads whose result it changes
+        pub async fn invalidate_after<T, Fut>(
+            &self,
+            keys: impl IntoIterator<Item = CacheKey>,
+            write: Fut,
+        ) -> Result<T, Error>
+        where
+            Fut: Future<Output = Result<T, Error>>,
+        {
+            let result = write.await?;
+            let keys: Vec<_> = keys.into_iter().collect();
+            self.invalidate(&keys).await?;
+            Ok(result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_key() {
+        let key = CacheKey::new("SelectFoo", &["1".to_owned(), "(2, 3)".to_owned()]);
+        assert_eq!(key.query(), "SelectFoo");
+        assert!(key.as_str().starts_with("SelectFoo."));
+        assert_eq!(
+            key,
+            CacheKey::new("SelectFoo", &["1".to_owned(), "(2, 3)".to_owned()])
+        );
+        assert_ne!(
+            key,
+            CacheKey::new("SelectFoo", &["1, (2".to_owned(), "3)".to_owned()])
+        );
+        assert_ne!(
+            key.as_str(),
+            CacheKey::new("SelectBar", &["1".to_owned(), "(2, 3)".to_owned()]).as_str()
+        );
+    }
+}
+
+#[cfg(all(test, feature = "memcache"))]
+mod memcache_test {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::{anyhow, Error};
+    use fbinit::FacebookInit;
+    use memcache::{JsonCodec, KeyGen, MemcacheClient};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Store = Arc<Mutex<HashMap<String, (String, Vec<u8>)>>>;
+
+    /// Start a memcached server handling get, set and delete
+    async fn start_server() -> (String, Store) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let store = Store::default();
+        tokio::spawn({
+            let store = store.clone();
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve(BufStream::new(stream), store.clone()));
+                }
+            }
+        });
+        (addr, store)
+    }
+
+    async fn serve(mut stream: BufStream<tokio::net::TcpStream>, store: Store) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                return;
+            }
+            let parts: Vec<_> = line.trim_end().split(' ').collect();
+            let mut response = Vec::new();
+            match parts[0] {
+                "get" => {
+                    for key in &parts[1..] {
+                        if let Some((flags, data)) = store.lock().unwrap().get(*key) {
+                            response.extend(
+                                format!("VALUE {} {} {}\r\n", key, flags, data.len()).bytes(),
+                            );
+                            response.extend(data);
+                            response.extend(b"\r\n");
+                        }
+                    }
+                    response.extend(b"END\r\n");
+                }
+                "set" => {
+                    let len: usize = parts[4].parse().unwrap();
+                    let mut data = vec![0; len + 2];
+                    stream.read_exact(&mut data).await.unwrap();
+                    data.truncate(len);
+                    let value = (parts[2].to_owned(), data);
+                    store.lock().unwrap().insert(parts[1].to_owned(), value);
+                    response.extend(b"STORED\r\n");
+                }
+                "delete" => match store.lock().unwrap().remove(parts[1]) {
+                    Some(_) => response.extend(b"DELETED\r\n"),
+                    None => response.extend(b"NOT_FOUND\r\n"),
+                },
+                _ => response.extend(b"ERROR\r\n"),
+            }
+            stream.write_all(&response).await.unwrap();
+            stream.flush().await.unwrap();
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_query_cache(fb: FacebookInit) -> Result<(), Error> {
+        let (addr, store) = start_server().await;
+        let client = MemcacheClient::with_servers(fb, [addr])?;
+        let cache = QueryCache::new(client, KeyGen::new("test.sql", 1, 0), JsonCodec);
+        let key = CacheKey::new("SelectFoo", &["1".to_owned()]);
+
+        let mut fetches = 0;
+        for _ in 0..2 {
+            let rows: Vec<(u64,)> = cache
+                .get_or_fetch(&key, || {
+                    fetches += 1;
+                    async { Ok(vec![(1,), (2,)]) }
+                })
+                .await?;
+            assert_eq!(rows, vec![(1,), (2,)]);
+        }
+        assert_eq!(fetches, 1);
+        assert_eq!(store.lock().unwrap().len(), 1);
+
+        let res: Result<Vec<(u64,)>, _> = cache
+            .get_or_fetch(&CacheKey::new("SelectFoo", &[]), || async {
+                Err(anyhow!("failed"))
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(store.lock().unwrap().len(), 1);
+
+        let affected = cache
+            .invalidate_after([key.clone()], async { Ok(1) })
+            .await?;
+        assert_eq!(affected, 1);
+        assert!(store.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
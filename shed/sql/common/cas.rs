@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing the compare-and-swap writes of optimistic concurrency:
+//! the `cas_write` queries update a row only if its version is still the one
+//! the caller read, and return [CasOutcome::Conflict] when another writer got
+//! there first. [CasRetry] reads the row again and retries on conflicts.
+//!
+//! # Example
+//! ```
+//! use anyhow::Error;
+//! use sql::{queries, Connection};
+//! use sql_common::cas::{CasOutcome, CasRetry};
+//!
+//! queries! {
+//!     read SelectCounter(id: u64) -> (u64, u64) {
+//!         "SELECT value, version FROM counters WHERE id = {id}"
+//!     }
+//!
+//!     cas_write UpdateCounter(id: u64, version: u64, value: u64) {
+//!         "UPDATE counters SET value = {value}, version = version + 1
+//!          WHERE id = {id} AND version = {version}"
+//!     }
+//! }
+//!
+//! async fn increment(conn: &Connection, id: u64) -> Result<CasOutcome, Error> {
+//!     CasRetry::new(5)
+//!         .run(|| async move {
+//!             let rows = SelectCounter::query(conn, &id).await?;
+//!             let (value, version) = rows[0];
+//!             UpdateCounter::query(conn, &id, &version, &(value + 1)).await
+//!         })
+//!         .await
+//! }
+//! #
+//! # fn main() {}
+//! ```
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::WriteResult;
+
+/// Outcome of a `cas_write` query
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CasOutcome {
+    /// The row had the expected version and was updated
+    Updated,
+    /// No row had the expected version, another writer updated it or it was
+    /// deleted
+    Conflict,
+}
+
+impl CasOutcome {
+    /// Whether the row was updated
+    pub fn is_updated(self) -> bool {
+        self == CasOutcome::Updated
+    }
+}
+
+impl From<WriteResult> for CasOutcome {
+    /// The row was updated if the query affected any. As the queries change
+    /// the version of the rows they match, the rows they affect are those they
+    /// match on Mysql as on Sqlite and Postgres.
+    fn from(result: WriteResult) -> Self {
+        if result.affected_rows() > 0 {
+            CasOutcome::Updated
+        } else {
+            CasOutcome::Conflict
+        }
+    }
+}
+
+/// Runs a read followed by a `cas_write` again until the write doesn't
+/// conflict, at most a number of times
+#[derive(Clone, Debug)]
+pub struct CasRetry {
+    attempts: usize,
+    delay: Duration,
+}
+
+impl CasRetry {
+    /// Run the write at most `attempts` times, without delay. Panics if
+    /// `attempts` is 0.
+    pub fn new(attempts: usize) -> Self {
+        assert!(attempts > 0, "the write must be attempted at least once");
+        Self {
+            attempts,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Wait for `delay` after each conflict, doubled after each of them, so
+    /// that the writers contending for a row spread out
+    pub fn with_delay(self, delay: Duration) -> Self {
+        Self { delay, ..self }
+    }
+
+    /// Most times the write is run
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// Call `write` until it returns [CasOutcome::Updated], returning
+    /// [CasOutcome::Conflict] if it still conflicts after all the attempts.
+    /// Errors are returned at once. `write` should read the row, e.g. its
+    /// version, each time it is called.
+    pub async fn run<F, Fut>(&self, mut write: F) -> Result<CasOutcome, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<CasOutcome, Error>>,
+    {
+        let mut delay = self.delay;
+        for attempt in 1..=self.attempts {
+            if write().await? == CasOutcome::Updated {
+                return Ok(CasOutcome::Updated);
+            }
+            if attempt < self.attempts && !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        Ok(CasOutcome::Conflict)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+    use futures::future;
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(
+            CasOutcome::from(WriteResult::new(None, 1)),
+            CasOutcome::Updated
+        );
+        assert_eq!(
+            CasOutcome::from(WriteResult::new(None, 0)),
+            CasOutcome::Conflict
+        );
+        assert!(CasOutcome::Updated.is_updated());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run() -> Result<(), Error> {
+        let mut calls = 0;
+        let start = tokio::time::Instant::now();
+        let outcome = CasRetry::new(5)
+            .with_delay(Duration::from_secs(1))
+            .run(|| {
+                calls += 1;
+                future::ok(if calls < 3 {
+                    CasOutcome::Conflict
+                } else {
+                    CasOutcome::Updated
+                })
+            })
+            .await?;
+        assert_eq!(outcome, CasOutcome::Updated);
+        assert_eq!(calls, 3);
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+
+        let mut calls = 0;
+        let outcome = CasRetry::new(2)
+            .run(|| {
+                calls += 1;
+                future::ok(CasOutcome::Conflict)
+            })
+            .await?;
+        assert_eq!(outcome, CasOutcome::Conflict);
+        assert_eq!(calls, 2);
+
+        let result = CasRetry::new(2)
+            .run(|| future::err(anyhow!("failed")))
+            .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
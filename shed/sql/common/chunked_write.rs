@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [ChunkedWrite], which deletes or updates many rows with
+//! a `write` query affecting a bounded number of rows per statement, e.g.
+//! with `LIMIT`, until it affects none. Each statement only locks the rows
+//! of its chunk, so that a large purge doesn't lock a table for minutes.
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//!
+//! use anyhow::Error;
+//! use sql::{queries, Connection};
+//! use sql_common::chunked_write::{ChunkedWrite, ChunkedWriteResult};
+//!
+//! queries! {
+//!     write DeleteOld(ts: u64, limit: u64) {
+//!         none,
+//!         "DELETE FROM foo WHERE ts < {ts} LIMIT {limit}"
+//!     }
+//! }
+//!
+//! async fn purge(conn: &Connection, ts: u64) -> Result<ChunkedWriteResult, Error> {
+//!     ChunkedWrite::new(1000)
+//!         .with_pause(Duration::from_millis(100))
+//!         .run(|limit| async move { DeleteOld::query(conn, &ts, &limit).await })
+//!         .await
+//! }
+//! #
+//! # fn main() {}
+//! ```
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Error;
+use futures::future::{self, Either, FutureExt};
+
+use crate::WriteResult;
+
+/// Runs a write affecting at most `chunk_size` rows until it affects none,
+/// pausing between the chunks. The chunks written before a failing one stay
+/// written.
+#[derive(Clone, Debug)]
+pub struct ChunkedWrite {
+    chunk_size: u64,
+    pause: Duration,
+}
+
+impl ChunkedWrite {
+    /// Write at most `chunk_size` rows per statement, without pause. Panics
+    /// if `chunk_size` is 0.
+    pub fn new(chunk_size: u64) -> Self {
+        assert!(chunk_size > 0, "chunks must have at least one row");
+        Self {
+            chunk_size,
+            pause: Duration::ZERO,
+        }
+    }
+
+    /// Wait for `pause` between two chunks, letting the other queries and
+    /// the replication catch up
+    pub fn with_pause(self, pause: Duration) -> Self {
+        Self { pause, ..self }
+    }
+
+    /// Most rows written per statement
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Call `write` with the chunk size until the rows it affects are 0
+    pub async fn run<F, Fut>(&self, write: F) -> Result<ChunkedWriteResult, Error>
+    where
+        F: FnMut(u64) -> Fut,
+        Fut: Future<Output = Result<WriteResult, Error>>,
+    {
+        self.run_until(future::pending(), write).await
+    }
+
+    /// Like [ChunkedWrite::run], stopping once `cancel` completes. The
+    /// chunk being written when it does is completed, so that no statement
+    /// is left running on the server.
+    pub async fn run_until<C, F, Fut>(
+        &self,
+        cancel: C,
+        mut write: F,
+    ) -> Result<ChunkedWriteResult, Error>
+    where
+        C: Future<Output = ()>,
+        F: FnMut(u64) -> Fut,
+        Fut: Future<Output = Result<WriteResult, Error>>,
+    {
+        let mut cancel = Box::pin(cancel.fuse());
+        let mut result = ChunkedWriteResult::default();
+        loop {
+            if cancel.as_mut().now_or_never().is_some() {
+                result.cancelled = true;
+                return Ok(result);
+            }
+            let affected_rows = write(self.chunk_size).await?.affected_rows();
+            if affected_rows == 0 {
+                return Ok(result);
+            }
+            result.affected_rows += affected_rows;
+            result.chunks += 1;
+            if !self.pause.is_zero() {
+                let sleep = Box::pin(tokio::time::sleep(self.pause));
+                if let Either::Right(_) = future::select(sleep, cancel.as_mut()).await {
+                    result.cancelled = true;
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
+
+/// Result of a [ChunkedWrite]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChunkedWriteResult {
+    affected_rows: u64,
+    chunks: usize,
+    cancelled: bool,
+}
+
+impl ChunkedWriteResult {
+    /// Return the number of rows affected by all the statements
+    pub fn affected_rows(&self) -> u64 {
+        self.affected_rows
+    }
+
+    /// Return the number of statements that affected rows
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    /// Return whether the write was cancelled before affecting all the rows
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    /// Write deleting at most `limit` of the `rows` left
+    fn delete(rows: &mut u64, calls: &mut usize, limit: u64) -> Result<WriteResult, Error> {
+        *calls += 1;
+        let deleted = limit.min(*rows);
+        *rows -= deleted;
+        Ok(WriteResult::new(None, deleted))
+    }
+
+    #[tokio::test]
+    async fn test_run() -> Result<(), Error> {
+        let (mut rows, mut calls) = (5, 0);
+        let result = ChunkedWrite::new(2)
+            .run(|limit| future::ready(delete(&mut rows, &mut calls, limit)))
+            .await?;
+        assert_eq!(result.affected_rows(), 5);
+        assert_eq!(result.chunks(), 3);
+        assert!(!result.cancelled());
+        assert_eq!((rows, calls), (0, 4));
+
+        let result = ChunkedWrite::new(2)
+            .run(|_| async { Err(anyhow!("failed")) })
+            .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_until() -> Result<(), Error> {
+        let (mut rows, mut calls) = (10, 0);
+        let write = ChunkedWrite::new(2).with_pause(Duration::from_secs(10));
+        let cancel = tokio::time::sleep(Duration::from_secs(15));
+        let result = write
+            .run_until(cancel, |limit| {
+                future::ready(delete(&mut rows, &mut calls, limit))
+            })
+            .await?;
+        // Cancelled during the second pause
+        assert_eq!(result.affected_rows(), 4);
+        assert_eq!(result.chunks(), 2);
+        assert!(result.cancelled());
+        assert_eq!((rows, calls), (6, 2));
+
+        let result = write
+            .run_until(future::ready(()), |_| async { Err(anyhow!("unreachable")) })
+            .await?;
+        assert!(result.cancelled());
+        Ok(())
+    }
+}
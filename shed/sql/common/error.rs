@@ -9,8 +9,82 @@
 
 //! Module that helps with dealing of the internal errors of this crate
 
+use anyhow::Error;
 use thiserror::Error;
 
+use crate::pool::PoolError;
+
+/// Kind of the error of a query, which tells whether running the query again
+/// may succeed, see [error_kind]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The query couldn't reach the database, and may succeed on another
+    /// connection or later
+    Connection,
+    /// The query conflicted with a concurrent one, e.g. on a deadlock, and
+    /// may succeed if its transaction runs again
+    Conflict,
+    /// The database failed the query, e.g. on a syntax error, and would fail
+    /// it again
+    Query,
+}
+
+/// Mysql error codes of the errors that aren't [ErrorKind::Query]: too many
+/// connections, can't connect, unknown host, server gone away, lost
+/// connection, lock wait timeout and deadlock
+const CODES: &[(u16, ErrorKind)] = &[
+    (1040, ErrorKind::Connection),
+    (2002, ErrorKind::Connection),
+    (2003, ErrorKind::Connection),
+    (2005, ErrorKind::Connection),
+    (2006, ErrorKind::Connection),
+    (2013, ErrorKind::Connection),
+    (1205, ErrorKind::Conflict),
+    (1213, ErrorKind::Conflict),
+];
+
+/// Messages of the same errors, for the clients whose errors have no code,
+/// and of the serialization failures and deadlocks of Postgres and the busy
+/// databases of Sqlite
+const MESSAGES: &[(&str, ErrorKind)] = &[
+    ("Too many connections", ErrorKind::Connection),
+    ("Can't connect to MySQL server", ErrorKind::Connection),
+    ("Unknown MySQL server host", ErrorKind::Connection),
+    ("MySQL server has gone away", ErrorKind::Connection),
+    ("Lost connection to MySQL server", ErrorKind::Connection),
+    ("Lock wait timeout exceeded", ErrorKind::Conflict),
+    ("Deadlock found", ErrorKind::Conflict),
+    ("could not serialize access", ErrorKind::Conflict),
+    ("deadlock detected", ErrorKind::Conflict),
+    ("database is locked", ErrorKind::Conflict),
+];
+
+/// Kind of `err`, given by the first error of its chain that isn't a
+/// [ErrorKind::Query]: [PoolError] and io errors are connection errors, and
+/// the others are classified from their Mysql error code or message.
+pub fn error_kind(err: &Error) -> ErrorKind {
+    err.chain()
+        .find_map(|cause| {
+            if cause.is::<PoolError>() || cause.is::<std::io::Error>() {
+                return Some(ErrorKind::Connection);
+            }
+            match cause.downcast_ref::<ServerError>() {
+                Some(server_error) => CODES
+                    .iter()
+                    .find(|(code, _)| *code == server_error.code)
+                    .map(|(_, kind)| *kind),
+                None => {
+                    let message = cause.to_string();
+                    MESSAGES
+                        .iter()
+                        .find(|(pattern, _)| message.contains(pattern))
+                        .map(|(_, kind)| *kind)
+                }
+            }
+        })
+        .unwrap_or(ErrorKind::Query)
+}
+
 /// Required for code in Mononoke that needs to downcast to ServerError to check
 /// the code. To be removed after upgrading to mysql_async 0.21+ which drops
 /// failure and provides correct std::error::Error impls for its error types.
@@ -23,6 +97,47 @@ pub struct ServerError {
     state: String,
 }
 
+/// Error converting a column of a query result to its type in `queries!`
+#[derive(Error, Debug)]
+pub enum ColumnError {
+    /// The column is NULL, but its type is not an `Option`
+    #[error("{column} is NULL, but `{rtype}` is not optional, read it as `Option<{rtype}>`")]
+    Null {
+        /// Name or index of the column
+        column: String,
+        /// Type of the column in the query result
+        rtype: &'static str,
+    },
+    /// The value of the column can't be converted to its type
+    #[error("failed to parse {column} as `{rtype}`: {value:?}")]
+    InvalidValue {
+        /// Name or index of the column
+        column: String,
+        /// Type of the column in the query result
+        rtype: &'static str,
+        /// The value of the column
+        value: mysql_async::Value,
+    },
+}
+
+/// Error of a read query returning more rows than its connection allows, see
+/// [crate::Connection::with_max_rows]
+#[derive(Error, Debug)]
+#[error("query returned more than {max_rows} rows")]
+pub struct TooManyRowsError {
+    /// Most rows the query could return
+    pub max_rows: usize,
+}
+
+/// Error of [crate::Connection::wait_for_replication] when the replica
+/// didn't reach the position in time
+#[derive(Error, Debug)]
+#[error("replica didn't reach the replication position within {timeout:?}")]
+pub struct ReplicationTimeoutError {
+    /// How long the replica was waited for
+    pub timeout: std::time::Duration,
+}
+
 /// Used to convert a mysql_async error type into [anyhow::Error]
 pub fn from_failure(failure: mysql_async::Error) -> anyhow::Error {
     match failure {
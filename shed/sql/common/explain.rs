@@ -0,0 +1,256 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [QueryPlan], the plan the database chose for a query,
+//! returned by the `explain` function of the `read` queries. It runs
+//! `EXPLAIN QUERY PLAN` on Sqlite, `EXPLAIN FORMAT=JSON` on Mysql and
+//! `EXPLAIN (FORMAT JSON)` on Postgres, so that tests can check that a query
+//! uses its index:
+//!
+//! ```ignore
+//! let plan = SelectByName::explain(&conn, &"foo").await?;
+//! assert!(plan.uses_index("name_idx"), "{:?}", plan);
+//! assert_eq!(plan.full_scans().count(), 0);
+//! ```
+
+use anyhow::{format_err, Context, Error};
+use mysql_async::Value;
+use serde_json::Value as JsonValue;
+
+/// Plan of a query, listing how it reads each table
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueryPlan {
+    steps: Vec<PlanStep>,
+}
+
+/// Step of a [QueryPlan]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanStep {
+    /// Table read by the step, if any
+    pub table: Option<String>,
+    /// Index used to read the table, if any. The primary key is `PRIMARY`
+    /// on Sqlite as on Mysql.
+    pub index: Option<String>,
+    /// Whether the step reads every row of the table or of the index
+    pub full_scan: bool,
+    /// Description of the step by the database
+    pub detail: String,
+}
+
+impl QueryPlan {
+    /// The steps of the plan, in the order the database listed them
+    pub fn steps(&self) -> &[PlanStep] {
+        &self.steps
+    }
+
+    /// Whether a step reads a table with the index `index`
+    pub fn uses_index(&self, index: &str) -> bool {
+        self.steps
+            .iter()
+            .any(|step| step.index.as_deref() == Some(index))
+    }
+
+    /// The tables read by steps scanning all of their rows or of an index
+    pub fn full_scans(&self) -> impl Iterator<Item = &str> {
+        self.steps
+            .iter()
+            .filter(|step| step.full_scan)
+            .filter_map(|step| step.table.as_deref())
+    }
+
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Plan of the `detail` column of the
+    /// rows of `EXPLAIN QUERY PLAN`.
+    #[doc(hidden)]
+    pub fn from_sqlite(details: Vec<String>) -> Self {
+        Self {
+            steps: details.into_iter().map(sqlite_step).collect(),
+        }
+    }
+
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Plan of the row of
+    /// `EXPLAIN FORMAT=JSON`.
+    #[doc(hidden)]
+    pub fn from_mysql(rows: Vec<(String,)>) -> Result<Self, Error> {
+        let (json,) = rows
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("EXPLAIN returned no rows"))?;
+        let json: JsonValue = serde_json::from_str(&json).context("invalid EXPLAIN output")?;
+        let mut steps = Vec::new();
+        mysql_steps(&json, &mut steps);
+        Ok(Self { steps })
+    }
+
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Plan of the row of
+    /// `EXPLAIN (FORMAT JSON)`.
+    #[doc(hidden)]
+    pub fn from_postgres(rows: Vec<crate::postgres::Row>) -> Result<Self, Error> {
+        let json = match rows
+            .into_iter()
+            .next()
+            .and_then(|row| row.into_iter().next())
+        {
+            Some(Value::Bytes(json)) => json,
+            _ => return Err(format_err!("EXPLAIN returned no plan")),
+        };
+        let json: JsonValue = serde_json::from_slice(&json).context("invalid EXPLAIN output")?;
+        let mut steps = Vec::new();
+        postgres_steps(&json, &mut steps);
+        Ok(Self { steps })
+    }
+}
+
+/// Step of a Sqlite detail, e.g. `SEARCH TABLE foo USING INDEX foo_x (x=?)`
+fn sqlite_step(detail: String) -> PlanStep {
+    let mut words = detail.split_whitespace();
+    let operation = words.next();
+    let table = match operation {
+        Some("SCAN") | Some("SEARCH") => match words.next() {
+            Some("TABLE") => words.next(),
+            Some("CONSTANT") | Some("SUBQUERY") => None,
+            table => table,
+        },
+        _ => None,
+    };
+    let index = detail.split_once(" USING ").and_then(|(_, using)| {
+        if using.starts_with("INTEGER PRIMARY KEY") {
+            Some("PRIMARY")
+        } else {
+            using
+                .strip_prefix("COVERING ")
+                .unwrap_or(using)
+                .strip_prefix("INDEX ")
+                .and_then(|index| index.split_whitespace().next())
+        }
+    });
+    PlanStep {
+        table: table.map(str::to_owned),
+        index: index.map(str::to_owned),
+        full_scan: table.is_some() && operation == Some("SCAN"),
+        detail,
+    }
+}
+
+/// Steps of the `table` objects of a Mysql plan, e.g.
+/// `{"table_name": "foo", "access_type": "ref", "key": "foo_x"}`
+fn mysql_steps(json: &JsonValue, steps: &mut Vec<PlanStep>) {
+    match json {
+        JsonValue::Object(object) => {
+            for (key, value) in object {
+                if key == "table" {
+                    let field = |name| value.get(name).and_then(JsonValue::as_str);
+                    let access_type = field("access_type").unwrap_or_default();
+                    steps.push(PlanStep {
+                        table: field("table_name").map(str::to_owned),
+                        index: field("key").map(str::to_owned),
+                        full_scan: access_type == "ALL" || access_type == "index",
+                        detail: value.to_string(),
+                    });
+                }
+                mysql_steps(value, steps);
+            }
+        }
+        JsonValue::Array(values) => {
+            for value in values {
+                mysql_steps(value, steps);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Steps of the nodes of a Postgres plan, e.g.
+/// `{"Node Type": "Index Scan", "Relation Name": "foo", "Index Name": "foo_x"}`
+fn postgres_steps(json: &JsonValue, steps: &mut Vec<PlanStep>) {
+    match json {
+        JsonValue::Object(object) => {
+            if let Some(node_type) = object.get("Node Type").and_then(JsonValue::as_str) {
+                let field = |name| object.get(name).and_then(JsonValue::as_str);
+                let table = field("Relation Name");
+                steps.push(PlanStep {
+                    table: table.map(str::to_owned),
+                    index: field("Index Name").map(str::to_owned),
+                    full_scan: node_type == "Seq Scan",
+                    detail: match table {
+                        Some(table) => format!("{} on {}", node_type, table),
+                        None => node_type.to_owned(),
+                    },
+                });
+            }
+            for value in object.values() {
+                postgres_steps(value, steps);
+            }
+        }
+        JsonValue::Array(values) => {
+            for value in values {
+                postgres_steps(value, steps);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_sqlite() {
+        let plan = QueryPlan::from_sqlite(vec![
+            "SEARCH TABLE foo USING COVERING INDEX foo_x (x=?)".to_owned(),
+            "SEARCH bar USING INTEGER PRIMARY KEY (rowid=?)".to_owned(),
+            "SCAN baz".to_owned(),
+            "USE TEMP B-TREE FOR ORDER BY".to_owned(),
+        ]);
+        assert_eq!(
+            plan.steps()[0],
+            PlanStep {
+                table: Some("foo".to_owned()),
+                index: Some("foo_x".to_owned()),
+                full_scan: false,
+                detail: "SEARCH TABLE foo USING COVERING INDEX foo_x (x=?)".to_owned(),
+            }
+        );
+        assert!(plan.uses_index("foo_x"));
+        assert!(plan.uses_index("PRIMARY"));
+        assert_eq!(plan.full_scans().collect::<Vec<_>>(), vec!["baz"]);
+        assert_eq!(plan.steps()[3].table, None);
+    }
+
+    #[test]
+    fn test_from_mysql() -> Result<(), Error> {
+        let json = r#"{"query_block": {"select_id": 1, "nested_loop": [
+            {"table": {"table_name": "foo", "access_type": "ref", "key": "foo_x"}},
+            {"table": {"table_name": "bar", "access_type": "ALL"}}
+        ]}}"#;
+        let plan = QueryPlan::from_mysql(vec![(json.to_owned(),)])?;
+        assert_eq!(plan.steps().len(), 2);
+        assert!(plan.uses_index("foo_x"));
+        assert_eq!(plan.full_scans().collect::<Vec<_>>(), vec!["bar"]);
+        assert!(QueryPlan::from_mysql(vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_postgres() -> Result<(), Error> {
+        let json = r#"[{"Plan": {"Node Type": "Nested Loop", "Plans": [
+            {"Node Type": "Index Scan", "Relation Name": "foo", "Index Name": "foo_x"},
+            {"Node Type": "Seq Scan", "Relation Name": "bar"}
+        ]}}]"#;
+        let plan = QueryPlan::from_postgres(vec![vec![Value::Bytes(json.into())]])?;
+        assert_eq!(plan.steps().len(), 3);
+        assert_eq!(plan.steps()[0].detail, "Nested Loop");
+        assert!(plan.uses_index("foo_x"));
+        assert_eq!(plan.full_scans().collect::<Vec<_>>(), vec!["bar"]);
+        Ok(())
+    }
+}
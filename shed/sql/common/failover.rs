@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [FailoverConnection], which runs the read queries that
+//! failed to reach a replica again on the master, see
+//! [SqlConnections::with_replica_failover]. The fallbacks are rate limited,
+//! so that a replica outage doesn't move all of its load to the master. Each
+//! failover connection exports `sql.failover.<name>.{fallbacks,throttled}`.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Error;
+use rate_limiter::TokenBucket;
+use stats::prelude::*;
+
+use crate::error::{error_kind, ErrorKind};
+use crate::{Connection, ConnectionLayer, SqlConnections, Unwrap};
+
+define_stats! {
+    prefix = "sql.failover";
+    fallbacks: dynamic_timeseries("{}.fallbacks", (name: String); Rate, Sum),
+    throttled: dynamic_timeseries("{}.throttled", (name: String); Rate, Sum),
+}
+
+/// Whether `err` means that the query couldn't reach the database, rather
+/// than that the database failed it, see [ErrorKind::Connection]. Query
+/// errors, e.g. syntax errors, would fail the same way on another connection.
+pub fn is_connection_error(err: &Error) -> bool {
+    error_kind(err) == ErrorKind::Connection
+}
+
+/// How often a [FailoverConnection] may fall back to the master
+#[derive(Clone, Debug)]
+pub struct FailoverPolicy {
+    /// Most fallbacks at once, before `per_second` applies. Defaults to 100.
+    pub burst: u32,
+    /// Sustained fallbacks per second. Defaults to 10.
+    pub per_second: f64,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self {
+            burst: 100,
+            per_second: 10.0,
+        }
+    }
+}
+
+/// Connection reading from a replica, and from the master when a read
+/// failed with a [connection error](is_connection_error). Use it as a
+/// [Connection] by converting it with [From].
+pub struct FailoverConnection {
+    name: String,
+    connection: Connection,
+    fallback: Connection,
+    limiter: TokenBucket,
+}
+
+impl FailoverConnection {
+    /// Read from `connection`, falling back to `fallback` according to
+    /// `policy`, exporting stats under `name`. Panics if the burst of
+    /// `policy` is 0 or its rate isn't positive.
+    pub fn new(
+        name: impl Into<String>,
+        connection: Connection,
+        fallback: Connection,
+        policy: FailoverPolicy,
+    ) -> Self {
+        let name = name.into();
+        Self {
+            limiter: TokenBucket::new(
+                format!("sql.failover.{}", name),
+                policy.burst,
+                policy.per_second,
+            ),
+            name,
+            connection,
+            fallback,
+        }
+    }
+
+    /// Name of the connection, used in its stats
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The connection running the queries first
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The connection running the read queries that failed to reach
+    /// [FailoverConnection::connection]
+    pub fn fallback(&self) -> &Connection {
+        &self.fallback
+    }
+
+    /// Whether a read failing with `err` should run again on the fallback,
+    /// counting the fallback if so
+    pub fn should_fall_back(&self, err: &Error) -> bool {
+        if !is_connection_error(err) {
+            return false;
+        }
+        if !self.limiter.try_acquire(1) {
+            STATS::throttled.add_value(1, (self.name.clone(),));
+            return false;
+        }
+        STATS::fallbacks.add_value(1, (self.name.clone(),));
+        true
+    }
+}
+
+impl ConnectionLayer for FailoverConnection {
+    fn kind(&self) -> &'static str {
+        "Failover"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<FailoverConnection> for Connection {
+    fn from(conn: FailoverConnection) -> Self {
+        Connection::Layered(Arc::new(conn))
+    }
+}
+
+impl Connection {
+    /// The first failover connection on the way to the
+    /// [Connection::read_backend], which read queries fall back from. This
+    /// should never be used directly, it is made public so that the queries!
+    /// macro can make use of it
+    #[doc(hidden)]
+    pub fn read_failover(&self) -> Option<&FailoverConnection> {
+        self.read_layer()
+    }
+}
+
+impl SqlConnections {
+    /// Run the read queries of `read_connection` that fail to reach the
+    /// replica again on `read_master_connection`, at most as often as
+    /// `policy` allows, exporting stats under `<name>.read`. Writes and
+    /// transactions never fall back.
+    pub fn with_replica_failover(self, name: &str, policy: FailoverPolicy) -> Self {
+        Self {
+            read_connection: FailoverConnection::new(
+                format!("{}.read", name),
+                self.read_connection,
+                self.read_master_connection.clone(),
+                policy,
+            )
+            .into(),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    use crate::pool::PoolError;
+
+    fn sqlite() -> Connection {
+        Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap())
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        assert!(is_connection_error(
+            &anyhow!("Lost connection to MySQL server during query")
+                .context("While executing MySelect query")
+        ));
+        assert!(is_connection_error(&Error::new(
+            PoolError::AcquireTimeout {
+                pool: "test".to_owned(),
+                timeout: std::time::Duration::from_secs(1),
+            }
+        )));
+        assert!(is_connection_error(&Error::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused
+        ))));
+        assert!(!is_connection_error(&anyhow!("Unknown column 'x'")));
+        assert!(!is_connection_error(&anyhow!("Deadlock found")));
+    }
+
+    #[test]
+    fn test_should_fall_back() {
+        let policy = FailoverPolicy {
+            burst: 2,
+            per_second: 0.001,
+        };
+        let failover = FailoverConnection::new("test_should_fall_back", sqlite(), sqlite(), policy);
+        let lost = anyhow!("Lost connection to MySQL server");
+        assert!(!failover.should_fall_back(&anyhow!("Syntax error")));
+        assert!(failover.should_fall_back(&lost));
+        assert!(failover.should_fall_back(&lost));
+        // Rate limited
+        assert!(!failover.should_fall_back(&lost));
+    }
+
+    #[test]
+    fn test_with_replica_failover() {
+        let connections =
+            SqlConnections::new_single(sqlite()).with_replica_failover("test", Default::default());
+        let failover = connections.read_connection.read_failover().unwrap();
+        assert_eq!(failover.name(), "test.read");
+        assert!(matches!(
+            connections.read_connection.read_backend(),
+            Connection::Sqlite(_)
+        ));
+        assert!(connections.write_connection.read_failover().is_none());
+    }
+}
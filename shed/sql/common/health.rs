@@ -0,0 +1,320 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing health checks of connections: [Connection::ping] sends a
+//! trivial query, and [Connection::spawn_health_check] pings in the
+//! background and keeps the result in a [Health], so that callers can skip
+//! a dead database instead of waiting for each of their queries to time out.
+//!
+//! A failed ping of a [Connection::MysqlPool] closes the connection it used,
+//! so that the next ping and queries reconnect. The other connections
+//! reconnect by themselves, if their client does.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use stats::prelude::*;
+
+use crate::{Connection, SqlConnections, SqlShardedConnections};
+
+define_stats! {
+    prefix = "sql.health";
+    pings: dynamic_timeseries("{}.pings", (name: String); Rate, Sum),
+    failures: dynamic_timeseries("{}.failures", (name: String); Rate, Sum),
+}
+
+/// How [Connection::spawn_health_check] pings a connection
+#[derive(Clone, Debug)]
+pub struct HealthCheckOptions {
+    /// Name of the connection in the stats, `sql.health.<name>.{pings,
+    /// failures}`
+    pub name: String,
+    /// Time between two pings. Defaults to 10 seconds.
+    pub interval: Duration,
+    /// Longest time to wait for a ping before counting it as failed.
+    /// Defaults to 5 seconds.
+    pub timeout: Duration,
+    /// Number of failed pings in a row after which the connection is
+    /// unhealthy. Defaults to 2.
+    pub failures: u32,
+}
+
+impl HealthCheckOptions {
+    /// Options of the health check of the connection `name`, with the
+    /// default interval, timeout and failures
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+            failures: 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    last_check: Option<Instant>,
+}
+
+struct Inner {
+    options: HealthCheckOptions,
+    state: Mutex<State>,
+}
+
+/// Health of a connection checked by [Connection::spawn_health_check].
+/// Cloning it gives another handle to the same health, and the checks stop
+/// once all of them are dropped. A connection is healthy until enough pings
+/// in a row failed, and again after a ping succeeded.
+#[derive(Clone)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+impl Health {
+    /// Health of a connection that has not been checked yet
+    pub fn new(options: HealthCheckOptions) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                options,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Whether the connection is considered healthy
+    pub fn is_healthy(&self) -> bool {
+        let state = self.inner.state.lock().expect("lock poisoned");
+        state.consecutive_failures < self.inner.options.failures
+    }
+
+    /// Error of the last ping, if it failed
+    pub fn last_error(&self) -> Option<String> {
+        let state = self.inner.state.lock().expect("lock poisoned");
+        state.last_error.clone()
+    }
+
+    /// Time since the last ping, if any
+    pub fn since_last_check(&self) -> Option<Duration> {
+        let state = self.inner.state.lock().expect("lock poisoned");
+        state.last_check.map(|last_check| last_check.elapsed())
+    }
+
+    /// Ping `connection` once and record the result, returning whether it
+    /// is healthy
+    pub async fn check(&self, connection: &Connection) -> bool {
+        let options = &self.inner.options;
+        let result = match tokio::time::timeout(options.timeout, connection.ping()).await {
+            Ok(result) => result,
+            Err(_) => Err(format_err!("ping timed out after {:?}", options.timeout)),
+        };
+        STATS::pings.add_value(1, (options.name.clone(),));
+        let mut state = self.inner.state.lock().expect("lock poisoned");
+        state.last_check = Some(Instant::now());
+        match result {
+            Ok(()) => {
+                state.consecutive_failures = 0;
+                state.last_error = None;
+            }
+            Err(err) => {
+                STATS::failures.add_value(1, (options.name.clone(),));
+                state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+                state.last_error = Some(format!("{:#}", err));
+            }
+        }
+        state.consecutive_failures < options.failures
+    }
+}
+
+impl Connection {
+    /// Send a trivial query to the [Connection::backend] to check that it
+    /// is reachable
+    pub async fn ping(&self) -> Result<(), Error> {
+        match self.backend() {
+            Connection::Sqlite(con) => con.run(|con| Ok(con.execute_batch("SELECT 1")?)).await,
+            Connection::Mysql(conn) => {
+                let _: Vec<(u64,)> = conn.read_query("SELECT 1".to_owned()).await?;
+                Ok(())
+            }
+            Connection::MysqlPool(pool) => {
+                let conn = pool.acquire().await?;
+                let result: Result<Vec<(u64,)>, _> =
+                    conn.run(conn.read_query("SELECT 1".to_owned())).await;
+                if let Err(err) = result {
+                    // The connection is likely broken, don't reuse it
+                    conn.discard();
+                    return Err(err);
+                }
+                Ok(())
+            }
+            Connection::Postgres(conn) => {
+                conn.read_query("SELECT 1".to_owned(), Vec::new()).await?;
+                Ok(())
+            }
+            Connection::Layered(_) => {
+                unreachable!("backend is never a wrapping connection")
+            }
+        }
+    }
+
+    /// Ping this connection every `options.interval` in the background and
+    /// return its health. The pings stop once the health and its clones are
+    /// dropped. Must be called from a tokio runtime.
+    pub fn spawn_health_check(&self, options: HealthCheckOptions) -> Health {
+        let interval = options.interval;
+        let health = Health::new(options);
+        let weak = Arc::downgrade(&health.inner);
+        let connection = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let inner = match weak.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                Health { inner }.check(&connection).await;
+            }
+        });
+        health
+    }
+}
+
+impl SqlShardedConnections {
+    /// Check the health of the write connection of each shard in the
+    /// background, named `<name>.<index>` in the stats. Must be called from a
+    /// tokio runtime.
+    pub fn with_health_checks(self, options: HealthCheckOptions) -> Self {
+        let health = self
+            .write_connections
+            .iter()
+            .enumerate()
+            .map(|(index, connection)| {
+                connection.spawn_health_check(HealthCheckOptions {
+                    name: format!("{}.{}", options.name, index),
+                    ..options.clone()
+                })
+            })
+            .collect();
+        Self { health, ..self }
+    }
+
+    /// Whether the shard `index` is healthy, which it is if its health is
+    /// not checked
+    pub fn is_shard_healthy(&self, index: usize) -> bool {
+        self.health.get(index).is_none_or(Health::is_healthy)
+    }
+
+    /// Connections of the shards that are healthy, with their index
+    pub fn iter_healthy_shards(&self) -> impl Iterator<Item = (usize, SqlConnections)> + '_ {
+        self.iter_shards()
+            .enumerate()
+            .filter(move |(index, _)| self.is_shard_healthy(*index))
+    }
+
+    /// Connections of the shard of `key`, or an error without waiting for a
+    /// query to time out if that shard is unhealthy. Panics if there are no
+    /// shards.
+    pub fn healthy_shard_for_key(&self, key: &[u8]) -> Result<SqlConnections, Error> {
+        let index = self.shard_index_for_key(key);
+        if let Some(health) = self.health.get(index) {
+            if !health.is_healthy() {
+                bail!(
+                    "shard {} is unhealthy: {}",
+                    index,
+                    health.last_error().unwrap_or_default()
+                );
+            }
+        }
+        Ok(self.shard_at(index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use fbinit::FacebookInit;
+
+    use crate::mysql::Pool;
+    use crate::pool::PoolOptions;
+
+    fn sqlite() -> Connection {
+        Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap())
+    }
+
+    fn unreachable_pool(fb: FacebookInit, name: &str) -> (Connection, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::new(fb, name, PoolOptions::default(), {
+            let attempts = attempts.clone();
+            move || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                async { Err(format_err!("server is down")) }
+            }
+        });
+        (pool.into(), attempts)
+    }
+
+    #[fbinit::test]
+    async fn test_check(fb: FacebookInit) {
+        assert!(sqlite().ping().await.is_ok());
+
+        let (pool, attempts) = unreachable_pool(fb, "test_check");
+        assert!(pool.ping().await.is_err());
+        let health = Health::new(HealthCheckOptions::new("test_check"));
+        assert!(health.is_healthy());
+        assert!(health.since_last_check().is_none());
+        assert!(health.check(&pool).await);
+        assert!(!health.check(&pool).await);
+        assert!(!health.is_healthy());
+        assert!(health.last_error().unwrap().contains("server is down"));
+        // Each ping tries to reconnect
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        assert!(health.check(&sqlite()).await);
+        assert!(health.is_healthy());
+        assert!(health.last_error().is_none());
+    }
+
+    #[fbinit::test]
+    async fn test_sharded_health_checks(fb: FacebookInit) {
+        let (pool, _) = unreachable_pool(fb, "test_sharded_health_checks");
+        let sharded: SqlShardedConnections = vec![
+            SqlConnections::new_single(sqlite()),
+            SqlConnections::new_single(pool),
+        ]
+        .into();
+        assert!(sharded.is_shard_healthy(1));
+
+        let options = HealthCheckOptions {
+            interval: Duration::from_millis(1),
+            failures: 1,
+            ..HealthCheckOptions::new("test_sharded_health_checks")
+        };
+        let sharded = sharded
+            .with_health_checks(options)
+            .with_sharding_strategy(|key: &[u8], _shards| key.len());
+        while sharded.is_shard_healthy(1) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(sharded.is_shard_healthy(0));
+        let healthy: Vec<_> = sharded
+            .iter_healthy_shards()
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(healthy, vec![0]);
+        assert!(sharded.healthy_shard_for_key(b"").is_ok());
+        assert!(sharded.healthy_shard_for_key(b"a").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [Json], the marker type of the query parameters and
+//! results stored as JSON, in Mysql `JSON` columns or Sqlite `TEXT` columns.
+//! `serde_json::Value` can be used directly, and any type implementing
+//! `Serialize` and `Deserialize` can be used wrapped in [Json]:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Settings {
+//!     colors: Vec<String>,
+//! }
+//!
+//! queries! {
+//!     read SelectSettings(id: u64) -> (Json<Settings>) {
+//!         "SELECT settings FROM users WHERE id = {id}"
+//!     }
+//! }
+//! ```
+
+use std::str::from_utf8;
+
+use mysql_async::prelude::{ConvIr, FromValue};
+use mysql_async::{FromValueError, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::mysql;
+
+/// Value stored as its JSON serialization. Converting a value that can't be
+/// serialized, e.g. a map whose keys are not strings, panics. Reading a
+/// column that is NULL or not valid JSON for `T` fails, use `Option<Json<T>>`
+/// for the nullable columns.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    /// The deserialized value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Serialize> From<Json<T>> for Value {
+    fn from(json: Json<T>) -> Value {
+        Value::Bytes(serde_json::to_vec(&json.0).expect("failed to serialize to JSON"))
+    }
+}
+
+/// Intermediate result of the conversion of a [Value] to [Json]
+pub struct JsonIr<T> {
+    bytes: Vec<u8>,
+    output: T,
+}
+
+impl<T: DeserializeOwned> ConvIr<Json<T>> for JsonIr<T> {
+    fn new(v: Value) -> Result<Self, FromValueError> {
+        match v {
+            Value::Bytes(bytes) => {
+                let output = from_utf8(&bytes)
+                    .ok()
+                    .and_then(|json| serde_json::from_str(json).ok());
+                match output {
+                    Some(output) => Ok(Self { bytes, output }),
+                    None => Err(FromValueError(Value::Bytes(bytes))),
+                }
+            }
+            v => Err(FromValueError(v)),
+        }
+    }
+
+    fn commit(self) -> Json<T> {
+        Json(self.output)
+    }
+
+    fn rollback(self) -> Value {
+        Value::Bytes(self.bytes)
+    }
+}
+
+impl<T: DeserializeOwned> FromValue for Json<T> {
+    type Intermediate = JsonIr<T>;
+}
+
+impl<T: DeserializeOwned> mysql::TryFromRowField for Json<T> {
+    fn try_from(field: mysql::RowField) -> Result<Self, mysql::MysqlError> {
+        mysql::opt_try_from_rowfield(field)
+    }
+}
+
+impl<T: DeserializeOwned> mysql::OptionalTryFromRowField for Json<T> {
+    fn try_from_opt(field: mysql::RowField) -> Result<Option<Self>, mysql::MysqlError> {
+        mysql::opt_try_from_rowfield(field)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mysql_async::prelude::ToValue;
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Settings {
+        colors: Vec<String>,
+        size: Option<u64>,
+    }
+
+    #[test]
+    fn test_json() {
+        let settings = Json(Settings {
+            colors: vec!["red".to_owned()],
+            size: None,
+        });
+        let value = settings.to_value();
+        assert_eq!(
+            value,
+            Value::Bytes(br#"{"colors":["red"],"size":null}"#.to_vec())
+        );
+        assert_eq!(Json::<Settings>::from_value_opt(value).unwrap(), settings);
+
+        let value = Value::Bytes(br#"{"colors":"red"}"#.to_vec());
+        assert_eq!(
+            Json::<Settings>::from_value_opt(value.clone())
+                .unwrap_err()
+                .0,
+            value
+        );
+        assert!(Json::<Settings>::from_value_opt(Value::NULL).is_err());
+        assert_eq!(
+            Option::<Json<Settings>>::from_value_opt(Value::NULL).unwrap(),
+            None
+        );
+    }
+}
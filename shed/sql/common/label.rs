@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [ConnectionLabel], which names the tier, shard and role
+//! of a connection in the errors of its queries, e.g.
+//! `While executing SelectFoo query (tier:foo shard:12 role:read)`. A label
+//! is attached with [Connection::with_label], or [SqlConnections::with_label]
+//! which adds the role of each connection. [SqlShardedConnections] label each
+//! shard with its index:
+//!
+//! ```ignore
+//! let sharded = SqlShardedConnections::from(shards)
+//!     .with_label(ConnectionLabel::new().with_tier("foo"));
+//! ```
+//!
+//! The queries of a transaction aren't labelled, only the failures to start
+//! it.
+
+use std::any::Any;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+use crate::{Connection, ConnectionLayer, SqlConnections, SqlShardedConnections, Unwrap};
+
+/// Which of the [SqlConnections] a connection is
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionRole {
+    /// [SqlConnections::write_connection]
+    Write,
+    /// [SqlConnections::read_connection]
+    Read,
+    /// [SqlConnections::read_master_connection]
+    ReadMaster,
+}
+
+impl Display for ConnectionRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionRole::Write => write!(f, "write"),
+            ConnectionRole::Read => write!(f, "read"),
+            ConnectionRole::ReadMaster => write!(f, "read_master"),
+        }
+    }
+}
+
+/// Tier, shard and role of a connection, each of them optional
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConnectionLabel {
+    tier: Option<String>,
+    shard: Option<String>,
+    role: Option<ConnectionRole>,
+}
+
+impl ConnectionLabel {
+    /// Label without tier, shard or role
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Label with the tier `tier`
+    pub fn with_tier(self, tier: impl Into<String>) -> Self {
+        Self {
+            tier: Some(tier.into()),
+            ..self
+        }
+    }
+
+    /// Label with the shard `shard`, e.g. its index
+    pub fn with_shard(self, shard: impl ToString) -> Self {
+        Self {
+            shard: Some(shard.to_string()),
+            ..self
+        }
+    }
+
+    /// Label with the role `role`
+    pub fn with_role(self, role: ConnectionRole) -> Self {
+        Self {
+            role: Some(role),
+            ..self
+        }
+    }
+
+    /// The tier, if any
+    pub fn tier(&self) -> Option<&str> {
+        self.tier.as_deref()
+    }
+
+    /// The shard, if any
+    pub fn shard(&self) -> Option<&str> {
+        self.shard.as_deref()
+    }
+
+    /// The role, if any
+    pub fn role(&self) -> Option<ConnectionRole> {
+        self.role
+    }
+
+    /// Whether the label has no tier, shard or role
+    pub fn is_empty(&self) -> bool {
+        self.tier.is_none() && self.shard.is_none() && self.role.is_none()
+    }
+
+    /// This label with the tier, shard and role it lacks taken from `inner`
+    fn or(self, inner: &ConnectionLabel) -> Self {
+        Self {
+            tier: self.tier.or_else(|| inner.tier.clone()),
+            shard: self.shard.or_else(|| inner.shard.clone()),
+            role: self.role.or(inner.role),
+        }
+    }
+}
+
+impl Display for ConnectionLabel {
+    /// The label as pairs, e.g. `tier:foo shard:12 role:read`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs: Vec<_> = [
+            self.tier.as_ref().map(|tier| format!("tier:{}", tier)),
+            self.shard.as_ref().map(|shard| format!("shard:{}", shard)),
+            self.role.map(|role| format!("role:{}", role)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{}", pairs.join(" "))
+    }
+}
+
+/// Connection of [Connection::with_label]
+pub struct LabeledConnection {
+    connection: Connection,
+    label: ConnectionLabel,
+}
+
+impl LabeledConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The label of the connection
+    pub fn label(&self) -> &ConnectionLabel {
+        &self.label
+    }
+}
+
+impl ConnectionLayer for LabeledConnection {
+    fn kind(&self) -> &'static str {
+        "Labeled"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection naming `label` in the errors of the queries of this
+    /// connection. The parts `label` lacks are taken from the labels of this
+    /// connection, see [Connection::label].
+    pub fn with_label(self, label: ConnectionLabel) -> Connection {
+        Connection::Layered(Arc::new(LabeledConnection {
+            connection: self,
+            label,
+        }))
+    }
+
+    /// The label of the connection, merging the labels on the way to the
+    /// [Connection::read_backend], the outer ones first
+    pub fn label(&self) -> ConnectionLabel {
+        self.read_layers()
+            .filter_map(|layer| layer.as_any().downcast_ref::<LabeledConnection>())
+            .fold(ConnectionLabel::new(), |label, labeled| {
+                label.or(labeled.label())
+            })
+    }
+}
+
+/// `context` followed by the label of `connection` if it has one, e.g.
+/// `While executing SelectFoo query (shard:12 role:read)`. This should never
+/// be used directly, it is made public so that the queries! macro can make
+/// use of it
+#[doc(hidden)]
+pub fn context(connection: &Connection, context: &str) -> String {
+    let label = connection.label();
+    if label.is_empty() {
+        context.to_owned()
+    } else {
+        format!("{} ({})", context, label)
+    }
+}
+
+impl SqlConnections {
+    /// Label each of the connections with `label` and its role
+    pub fn with_label(self, label: ConnectionLabel) -> Self {
+        Self {
+            write_connection: self
+                .write_connection
+                .with_label(label.clone().with_role(ConnectionRole::Write)),
+            read_connection: self
+                .read_connection
+                .with_label(label.clone().with_role(ConnectionRole::Read)),
+            read_master_connection: self
+                .read_master_connection
+                .with_label(label.with_role(ConnectionRole::ReadMaster)),
+        }
+    }
+}
+
+impl SqlShardedConnections {
+    /// Label the connections of each shard with `label`, e.g. their tier,
+    /// besides their shard and role
+    pub fn with_label(self, label: ConnectionLabel) -> Self {
+        let shards: Vec<_> = self
+            .iter_shards()
+            .map(|shard| shard.with_label(label.clone()))
+            .collect();
+        self.with_shards(shards)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_label() {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(conn.label().is_empty());
+        assert_eq!(
+            context(&conn, "While executing Foo query"),
+            "While executing Foo query"
+        );
+
+        let connections = SqlConnections::new_single(conn);
+        let sharded = SqlShardedConnections::from(vec![connections.clone(), connections])
+            .with_label(ConnectionLabel::new().with_tier("foo"));
+        let shard = sharded.shard(1).unwrap();
+        assert_eq!(
+            shard.write_connection.label(),
+            ConnectionLabel::new()
+                .with_tier("foo")
+                .with_shard(1)
+                .with_role(ConnectionRole::Write)
+        );
+        let conn = shard.read_master_connection.clone().readonly();
+        assert!(matches!(conn.backend(), Connection::Sqlite(_)));
+        assert_eq!(conn.label().role(), Some(ConnectionRole::ReadMaster));
+        assert_eq!(
+            context(&conn, "While executing Foo query"),
+            "While executing Foo query (tier:foo shard:1 role:read_master)"
+        );
+
+        let conn = conn.with_label(ConnectionLabel::new().with_shard("a"));
+        assert_eq!(
+            conn.label().to_string(),
+            "tier:foo shard:a role:read_master"
+        );
+    }
+}
@@ -12,12 +12,40 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod bulk_insert;
+pub mod cache;
+pub mod cas;
+pub mod chunked_write;
 pub mod error;
+pub mod explain;
+pub mod failover;
+pub mod health;
+pub mod json;
+pub mod label;
+pub mod migration;
 pub mod mysql;
+pub mod observer;
+pub mod pool;
+pub mod postgres;
+pub mod query_stats;
+pub mod read_only;
+pub mod read_your_writes;
+pub mod replication;
+pub mod retry;
+pub mod row_limit;
+pub mod sharded_transaction;
+pub mod sharding;
+pub mod spans;
 pub mod sqlite;
+pub mod tag;
+pub mod test_database;
 pub mod transaction;
+pub mod url;
+pub mod value;
+pub mod write_limit;
 
 use anyhow::{bail, format_err, Context, Error};
+use std::any::Any;
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 
@@ -85,14 +113,18 @@ impl SqlConnectionsWithSchema {
     }
 
     /// Execute sql on the schema connection to create schema if not present
-    /// For mysql the schema connection should be None as schema is setup in advance2
+    /// For mysql the schema connection should be None as schema is setup in advance2.
+    /// See [SqlConnectionsWithSchema::migrate] for versioned schemas.
     pub fn create_schema(&self, schema_sql: &str) -> Result<(), Error> {
+        if let Some(conn) = &self.schema_connection {
+            conn.check_write("schema creation")?;
+        }
         match &self.schema_connection {
             Some(Connection::Sqlite(conn)) => conn
                 .get_sqlite_guard()
                 .execute_batch(schema_sql)
                 .with_context(|| format_err!("failed sql: {}", schema_sql)),
-            Some(_) => bail!("not expecting schema connection for mysql"),
+            Some(_) => bail!("not expecting schema connection for mysql or postgres"),
             None => Ok(()),
         }
     }
@@ -105,6 +137,8 @@ impl From<SqlConnectionsWithSchema> for SqlConnections {
 }
 
 /// Struct to store a set of write, read and read-only connections for multiple shards.
+/// See [sharding] for routing keys to their shard. The three vectors hold the
+/// connections of the same shards, in the same order.
 #[derive(Clone)]
 pub struct SqlShardedConnections {
     /// Write connections to the master for each shard
@@ -113,6 +147,10 @@ pub struct SqlShardedConnections {
     pub read_connections: Vec<Connection>,
     /// Read master connections for each shard
     pub read_master_connections: Vec<Connection>,
+    /// Routes the keys to their shard
+    strategy: Arc<dyn sharding::ShardingStrategy>,
+    /// Health of each shard, empty if it is not checked
+    health: Vec<health::Health>,
 }
 
 impl SqlShardedConnections {
@@ -120,28 +158,45 @@ impl SqlShardedConnections {
     pub fn is_empty(&self) -> bool {
         self.write_connections.is_empty()
     }
-}
 
-impl From<Vec<SqlConnections>> for SqlShardedConnections {
-    fn from(shard_connections: Vec<SqlConnections>) -> Self {
-        let mut write_connections = Vec::with_capacity(shard_connections.len());
-        let mut read_connections = Vec::with_capacity(shard_connections.len());
-        let mut read_master_connections = Vec::with_capacity(shard_connections.len());
-        for connections in shard_connections.into_iter() {
+    /// Replace the connections of the shards, keeping how they are routed
+    /// and checked
+    fn with_shards(self, shards: impl IntoIterator<Item = SqlConnections>) -> Self {
+        let mut write_connections = Vec::new();
+        let mut read_connections = Vec::new();
+        let mut read_master_connections = Vec::new();
+        for connections in shards {
             write_connections.push(connections.write_connection);
             read_connections.push(connections.read_connection);
             read_master_connections.push(connections.read_master_connection);
         }
-
         Self {
+            write_connections,
             read_connections,
             read_master_connections,
-            write_connections,
+            ..self
         }
     }
 }
 
-/// Enum that generalizes over connections to Sqlite and MyRouter.
+impl From<Vec<SqlConnections>> for SqlShardedConnections {
+    /// The connections of each shard are labelled with its index, see
+    /// [label]
+    fn from(shards: Vec<SqlConnections>) -> Self {
+        let sharded = Self {
+            write_connections: Vec::new(),
+            read_connections: Vec::new(),
+            read_master_connections: Vec::new(),
+            strategy: Arc::new(sharding::Fnv1aModulo),
+            health: Vec::new(),
+        };
+        sharded.with_shards(shards.into_iter().enumerate().map(|(index, shard)| {
+            shard.with_label(label::ConnectionLabel::new().with_shard(index))
+        }))
+    }
+}
+
+/// Enum that generalizes over connections to Sqlite, MyRouter and Postgres.
 #[derive(Clone)]
 pub enum Connection {
     /// Sqlite lets you use this crate with rusqlite connections such as in memory or on disk Sqlite
@@ -149,6 +204,129 @@ pub enum Connection {
     Sqlite(Arc<sqlite::SqliteMultithreaded>),
     /// A variant used for the new Mysql client connection factory.
     Mysql(mysql::Connection),
+    /// Mysql connections taken from a [mysql::Pool] for each query or
+    /// transaction.
+    MysqlPool(mysql::Pool),
+    /// A Postgres connection, see [postgres] for how queries are sent and
+    /// which features it requires.
+    Postgres(postgres::Connection),
+    /// A connection wrapping another one to change how its queries run, e.g.
+    /// [retry::RetryingConnection] or [Connection::readonly]. The wrapped
+    /// connection is private to this crate, so that e.g. a read-only
+    /// connection can't be unwrapped.
+    Layered(Arc<dyn ConnectionLayer>),
+}
+
+/// Connection wrapping another [Connection], whose queries go through it on
+/// their way to the [Connection::backend]. Only this crate can implement it
+/// or unwrap a layer, as the methods returning the wrapped connections take
+/// an argument of a type other crates can't name.
+pub trait ConnectionLayer: Any + Send + Sync {
+    /// Kind of the layer, shown in the debug output of its connection
+    fn kind(&self) -> &'static str;
+
+    /// The wrapped connection, running the write queries and transactions
+    fn inner(&self, _: Unwrap) -> &Connection;
+
+    /// The wrapped connection running the read queries, which is
+    /// [ConnectionLayer::inner] unless the layer routes the reads
+    fn read_inner(&self, _: Unwrap) -> &Connection {
+        self.inner(Unwrap)
+    }
+
+    /// Called after a write completed on the connection
+    fn record_write(&self) {}
+
+    /// The layer as [Any], to downcast it to its type
+    fn as_any(&self) -> &dyn Any;
+}
+
+mod sealed {
+    /// Argument of the methods of [super::ConnectionLayer] returning the
+    /// wrapped connections, public in a private module so that only this
+    /// crate can pass it
+    #[derive(Clone, Copy, Debug)]
+    pub struct Unwrap;
+}
+
+use sealed::Unwrap;
+
+impl Connection {
+    fn layered(&self) -> Option<&dyn ConnectionLayer> {
+        match self {
+            Connection::Layered(layer) => Some(layer.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// This connection if it is a layer of type `L`
+    pub(crate) fn as_layer<L: ConnectionLayer>(&self) -> Option<&L> {
+        self.layered()?.as_any().downcast_ref()
+    }
+
+    /// The layers on the way to the [Connection::backend], the outer ones
+    /// first
+    pub(crate) fn layers(&self) -> impl Iterator<Item = &dyn ConnectionLayer> {
+        std::iter::successors(self.layered(), |layer| layer.inner(Unwrap).layered())
+    }
+
+    /// The layers on the way to the [Connection::read_backend], the outer
+    /// ones first
+    pub(crate) fn read_layers(&self) -> impl Iterator<Item = &dyn ConnectionLayer> {
+        std::iter::successors(self.layered(), |layer| layer.read_inner(Unwrap).layered())
+    }
+
+    /// The outermost layer of type `L` on the way to the
+    /// [Connection::backend]
+    pub(crate) fn layer<L: ConnectionLayer>(&self) -> Option<&L> {
+        self.layers()
+            .find_map(|layer| layer.as_any().downcast_ref::<L>())
+    }
+
+    /// The outermost layer of type `L` on the way to the
+    /// [Connection::read_backend]
+    pub(crate) fn read_layer<L: ConnectionLayer>(&self) -> Option<&L> {
+        self.read_layers()
+            .find_map(|layer| layer.as_any().downcast_ref::<L>())
+    }
+
+    /// The connection running write queries and transactions, under all the
+    /// layers of this connection. This should never be used directly, it is
+    /// made public so that the queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn backend(&self) -> &Connection {
+        self.layers()
+            .last()
+            .map_or(self, |layer| layer.inner(Unwrap))
+    }
+
+    /// The connection running read queries, which after a recent write is
+    /// the master for a read-your-writes connection. This should never be
+    /// used directly, it is made public so that the queries! macro can make
+    /// use of it
+    #[doc(hidden)]
+    pub fn read_backend(&self) -> &Connection {
+        self.read_layers()
+            .last()
+            .map_or(self, |layer| layer.read_inner(Unwrap))
+    }
+
+    /// The first retrying connection on the way to the [Connection::read_backend],
+    /// whose policy applies to read queries. This should never be used
+    /// directly, it is made public so that the queries! macro can make use of
+    /// it
+    #[doc(hidden)]
+    pub fn read_retrying(&self) -> Option<&retry::RetryingConnection> {
+        self.read_layer()
+    }
+
+    /// Record that a write completed, for the layers on the way to the
+    /// [Connection::backend], e.g. read-your-writes connections.
+    pub fn record_write(&self) {
+        for layer in self.layers() {
+            layer.record_write();
+        }
+    }
 }
 
 impl From<sqlite::SqliteMultithreaded> for Connection {
@@ -163,11 +341,20 @@ impl From<mysql::Connection> for Connection {
     }
 }
 
+impl From<postgres::Connection> for Connection {
+    fn from(conn: postgres::Connection) -> Self {
+        Connection::Postgres(conn)
+    }
+}
+
 impl Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Connection::Sqlite(..) => write!(f, "Sqlite"),
             Connection::Mysql(..) => write!(f, "Mysql client"),
+            Connection::MysqlPool(pool) => write!(f, "Mysql pool {}", pool.name()),
+            Connection::Postgres(..) => write!(f, "Postgres"),
+            Connection::Layered(layer) => write!(f, "{} {:?}", layer.kind(), layer.inner(Unwrap)),
         }
     }
 }
@@ -176,6 +363,10 @@ impl Debug for Connection {
 pub struct WriteResult {
     last_insert_id: Option<u64>,
     affected_rows: u64,
+    insert_ids: Vec<u64>,
+    rows_matched: Option<u64>,
+    rows_changed: Option<u64>,
+    replication_position: Option<replication::ReplicationPosition>,
 }
 
 impl WriteResult {
@@ -184,6 +375,57 @@ impl WriteResult {
         WriteResult {
             last_insert_id,
             affected_rows,
+            insert_ids: Vec::new(),
+            rows_matched: None,
+            rows_changed: None,
+            replication_position: None,
+        }
+    }
+
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    pub fn with_insert_ids(self, insert_ids: Vec<u64>) -> Self {
+        WriteResult { insert_ids, ..self }
+    }
+
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    /// Mysql assigns consecutive ids to the rows of a plain insert, and returns the first one.
+    /// The rows of the other `query`s, e.g. `{insert_or_ignore}`, `REPLACE` or upserts, get no
+    /// ids, as some of their rows are not inserted.
+    pub fn with_consecutive_insert_ids(self, query: &str) -> Self {
+        let insert_ids = match self.last_insert_id {
+            Some(first) if first > 0 && is_plain_insert(query) => {
+                (first..first + self.affected_rows).collect()
+            }
+            _ => Vec::new(),
+        };
+        self.with_insert_ids(insert_ids)
+    }
+
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    /// Sqlite and Postgres count the rows matched by the query as affected, whether or not their
+    /// values changed.
+    pub fn with_rows_matched(self) -> Self {
+        WriteResult {
+            rows_matched: Some(self.affected_rows),
+            ..self
+        }
+    }
+
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    /// Mysql reports the rows matched and changed by an update in its info string.
+    pub fn with_rows_matched_changed(self, rows_matched: u64, rows_changed: u64) -> Self {
+        WriteResult {
+            rows_matched: Some(rows_matched),
+            rows_changed: Some(rows_changed),
+            ..self
+        }
+    }
+
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    pub fn with_replication_position(self, position: replication::ReplicationPosition) -> Self {
+        WriteResult {
+            replication_position: Some(position),
+            ..self
         }
     }
 
@@ -192,8 +434,102 @@ impl WriteResult {
         self.last_insert_id
     }
 
-    /// Return number of rows affected by the `write` query
+    /// Return number of rows affected by the `write` query. Sqlite and Postgres count the rows
+    /// the query matched, see [WriteResult::rows_matched]. Mysql counts by default the rows whose
+    /// values changed, counting twice those updated by `ON DUPLICATE KEY UPDATE`, and the rows it
+    /// matched if the client connected with `CLIENT_FOUND_ROWS`.
     pub fn affected_rows(&self) -> u64 {
         self.affected_rows
     }
+
+    /// Return the number of rows inserted, updated or deleted by the `write` query, including
+    /// those it updated to the values they already had, if the database reports it. It is
+    /// reported by Sqlite and Postgres, and by Mysql for updates.
+    pub fn rows_matched(&self) -> Option<u64> {
+        self.rows_matched
+    }
+
+    /// Return the number of rows updated by the `write` query to values they didn't already
+    /// have, if the database reports it. It is only reported by Mysql for updates, Sqlite and
+    /// Postgres count the rows matched as changed.
+    pub fn rows_changed(&self) -> Option<u64> {
+        self.rows_changed
+    }
+
+    /// Return the ids of the rows inserted by a `write` query with `values`, in order, or none
+    /// for the other queries. Sqlite returns the id of each inserted row. Mysql only returns the
+    /// id of the first row, the others are the following ids, which holds for plain inserts with
+    /// an `auto_increment_increment` of 1, so `insert_or_ignore`, `REPLACE` and upserts return
+    /// no ids on Mysql. Postgres returns no ids.
+    pub fn insert_ids(&self) -> &[u64] {
+        &self.insert_ids
+    }
+
+    /// Return the replication position of the master after the `write` query, for the
+    /// connections of [Connection::with_replication_tracking] to Mysql, see [replication].
+    pub fn replication_position(&self) -> Option<&replication::ReplicationPosition> {
+        self.replication_position.as_ref()
+    }
+}
+
+/// Whether `query` only inserts rows, which are all inserted unless it fails, as opposed to
+/// `INSERT IGNORE`, `REPLACE` or `INSERT ... ON DUPLICATE KEY UPDATE`
+fn is_plain_insert(query: &str) -> bool {
+    let words = match read_only::words(query) {
+        Some(words) => words,
+        None => return false,
+    };
+    let is = |idx: usize, keyword: &str| {
+        words
+            .get(idx)
+            .is_some_and(|(_, word)| word.eq_ignore_ascii_case(keyword))
+    };
+    let into = (0..words.len()).find(|idx| is(*idx, "INTO"));
+    is(0, "INSERT")
+        && into.is_some_and(|into| !(1..into).any(|idx| is(idx, "IGNORE")))
+        && !(0..words.len()).any(|idx| is(idx, "ON") && is(idx + 1, "DUPLICATE"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_plain_insert() {
+        assert!(is_plain_insert("INSERT INTO foo (x) VALUES {values}"));
+        assert!(is_plain_insert(
+            "insert into foo (x, y) VALUES {values} -- ON DUPLICATE KEY"
+        ));
+        assert!(is_plain_insert(
+            "INSERT INTO foo (x) SELECT x FROM bar WHERE y = 'ON DUPLICATE'"
+        ));
+        assert!(!is_plain_insert(
+            "{insert_or_ignore} INTO foo (x) VALUES {values}"
+        ));
+        assert!(!is_plain_insert(
+            "INSERT IGNORE INTO foo (x) VALUES {values}"
+        ));
+        assert!(!is_plain_insert(
+            "INSERT LOW_PRIORITY IGNORE INTO foo (x) VALUES (1)"
+        ));
+        assert!(!is_plain_insert("REPLACE INTO foo (x) VALUES {values}"));
+        assert!(!is_plain_insert(
+            "INSERT INTO foo (x) VALUES {values} ON DUPLICATE KEY UPDATE x = x + 1"
+        ));
+        assert!(!is_plain_insert("UPDATE foo SET x = 1"));
+    }
+
+    #[test]
+    fn test_consecutive_insert_ids() {
+        assert_eq!(
+            WriteResult::new(Some(3), 2)
+                .with_consecutive_insert_ids("INSERT INTO foo (x) VALUES {values}")
+                .insert_ids(),
+            &[3, 4]
+        );
+        assert!(WriteResult::new(Some(3), 2)
+            .with_consecutive_insert_ids("REPLACE INTO foo (x) VALUES {values}")
+            .insert_ids()
+            .is_empty());
+    }
 }
@@ -0,0 +1,593 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [Migrations], a runner of versioned schema migrations.
+//! The versions applied to a database are tracked in its `schema_version`
+//! table, which [Migrations::migrate_up] creates if missing. Each migration
+//! runs in its own transaction, although Mysql commits its schema changes
+//! immediately, so a migration failing there can leave part of its changes.
+//!
+//! A migrator holds the row of the `schema_version_lock` table while it runs
+//! the migrations, so that concurrent migrators of a database run one after
+//! the other. The row of a migrator that died while holding it must be
+//! deleted by hand.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Error};
+
+use crate::transaction::Transaction;
+use crate::{Connection, SqlConnectionsWithSchema};
+
+/// Name of the table tracking the applied migrations
+pub const SCHEMA_VERSION_TABLE: &str = "schema_version";
+
+/// Name of the table holding the lock of the migrators
+pub const MIGRATION_LOCK_TABLE: &str = "schema_version_lock";
+
+/// Pause between two attempts to take the lock held by another migrator
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A versioned change of the schema. Its scripts are made of SQL statements
+/// separated by `;`.
+#[derive(Clone, Debug)]
+pub struct Migration {
+    /// Version of the schema after this migration, starting from 1
+    pub version: u64,
+    /// Short description of the migration
+    pub name: String,
+    /// Script applying the migration
+    pub up: String,
+    /// Script reverting the migration, if it can be
+    pub down: Option<String>,
+}
+
+impl Migration {
+    /// Create a migration to `version` that can't be reverted
+    pub fn new(version: u64, name: impl Into<String>, up: impl Into<String>) -> Self {
+        Self {
+            version,
+            name: name.into(),
+            up: up.into(),
+            down: None,
+        }
+    }
+
+    /// Make the migration revertable with the script `down`
+    pub fn with_down(self, down: impl Into<String>) -> Self {
+        Self {
+            down: Some(down.into()),
+            ..self
+        }
+    }
+}
+
+/// Whether a migration is applied or reverted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// The migration is applied
+    Up,
+    /// The migration is reverted
+    Down,
+}
+
+/// A migration applied or reverted by [Migrations], or that would have been
+/// in dry-run mode
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MigrationStep {
+    /// Version of the migration
+    pub version: u64,
+    /// Name of the migration
+    pub name: String,
+    /// Whether the migration is applied or reverted
+    pub direction: Direction,
+    /// Statements run, including the update of the schema version
+    pub statements: Vec<String>,
+}
+
+/// Set of migrations of a schema
+#[derive(Clone, Debug)]
+pub struct Migrations {
+    /// Sorted by version
+    migrations: Vec<Migration>,
+    dry_run: bool,
+    lock_timeout: Duration,
+}
+
+impl Migrations {
+    /// Create the set of `migrations`, which must have distinct versions
+    /// above 0. They can be given in any order.
+    pub fn new(migrations: impl IntoIterator<Item = Migration>) -> Result<Self, Error> {
+        let mut migrations: Vec<_> = migrations.into_iter().collect();
+        migrations.sort_by_key(|migration| migration.version);
+        if let Some(first) = migrations.first() {
+            if first.version == 0 {
+                bail!("migration {:?} has version 0", first.name);
+            }
+        }
+        for pair in migrations.windows(2) {
+            if pair[0].version == pair[1].version {
+                bail!(
+                    "migrations {:?} and {:?} have the same version {}",
+                    pair[0].name,
+                    pair[1].name,
+                    pair[0].version
+                );
+            }
+        }
+        Ok(Self {
+            migrations,
+            dry_run: false,
+            lock_timeout: Duration::from_secs(60),
+        })
+    }
+
+    /// In dry-run mode, the migrations return the steps they would run
+    /// without changing the database
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    /// Longest time to wait for another migrator of the database to release
+    /// its lock before failing. Defaults to 60 seconds.
+    pub fn lock_timeout(self, lock_timeout: Duration) -> Self {
+        Self {
+            lock_timeout,
+            ..self
+        }
+    }
+
+    /// Version of the last migration, or 0 if there are none
+    pub fn latest_version(&self) -> u64 {
+        self.migrations
+            .last()
+            .map_or(0, |migration| migration.version)
+    }
+
+    /// Version of the schema of the database, 0 if no migration was applied
+    pub async fn current_version(&self, connection: &Connection) -> Result<u64, Error> {
+        let mut transaction = connection.start_transaction().await?;
+        let version = read_version(connection, &mut transaction).await?;
+        transaction.rollback().await?;
+        Ok(version)
+    }
+
+    /// Apply the migrations following the current version of the database,
+    /// up to `target` or to the latest version if None
+    pub async fn migrate_up(
+        &self,
+        connection: &Connection,
+        target: Option<u64>,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        let target = self.check_version(target.unwrap_or_else(|| self.latest_version()))?;
+        if self.dry_run {
+            return self.migrate_up_locked(connection, target).await;
+        }
+        let mut transaction = connection.start_transaction().await?;
+        transaction
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (version BIGINT NOT NULL PRIMARY KEY, name VARCHAR(255) NOT NULL)",
+                SCHEMA_VERSION_TABLE
+            ))
+            .await?;
+        transaction.commit().await?;
+        self.locked(connection, self.migrate_up_locked(connection, target))
+            .await
+    }
+
+    async fn migrate_up_locked(
+        &self,
+        connection: &Connection,
+        target: u64,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        let current = self.current_version(connection).await?;
+        self.check_version(current)?;
+        if target < current {
+            bail!(
+                "database is at version {}, above {}, use migrate_down to revert it",
+                current,
+                target
+            );
+        }
+
+        let steps = self
+            .migrations
+            .iter()
+            .filter(|migration| current < migration.version && migration.version <= target)
+            .map(|migration| {
+                let mut statements = split_statements(&migration.up);
+                statements.push(format!(
+                    "INSERT INTO {} (version, name) VALUES ({}, '{}')",
+                    SCHEMA_VERSION_TABLE,
+                    migration.version,
+                    migration.name.replace('\'', "''")
+                ));
+                MigrationStep {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    direction: Direction::Up,
+                    statements,
+                }
+            })
+            .collect();
+        self.run(connection, steps).await
+    }
+
+    /// Revert the migrations applied to the database down to `target`,
+    /// which is the version of the schema once they are reverted
+    pub async fn migrate_down(
+        &self,
+        connection: &Connection,
+        target: u64,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        let target = self.check_version(target)?;
+        if self.dry_run {
+            return self.migrate_down_locked(connection, target).await;
+        }
+        self.locked(connection, self.migrate_down_locked(connection, target))
+            .await
+    }
+
+    async fn migrate_down_locked(
+        &self,
+        connection: &Connection,
+        target: u64,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        let current = self.current_version(connection).await?;
+        self.check_version(current)?;
+        if target > current {
+            bail!(
+                "database is at version {}, below {}, use migrate_up to apply it",
+                current,
+                target
+            );
+        }
+
+        let mut steps = Vec::new();
+        for migration in self.migrations.iter().rev() {
+            if migration.version <= target || migration.version > current {
+                continue;
+            }
+            let down = match &migration.down {
+                Some(down) => down,
+                None => bail!(
+                    "migration {} {:?} can't be reverted",
+                    migration.version,
+                    migration.name
+                ),
+            };
+            let mut statements = split_statements(down);
+            statements.push(format!(
+                "DELETE FROM {} WHERE version = {}",
+                SCHEMA_VERSION_TABLE, migration.version
+            ));
+            steps.push(MigrationStep {
+                version: migration.version,
+                name: migration.name.clone(),
+                direction: Direction::Down,
+                statements,
+            });
+        }
+        self.run(connection, steps).await
+    }
+
+    /// Check that `version` is 0 or the version of a migration
+    fn check_version(&self, version: u64) -> Result<u64, Error> {
+        if version != 0
+            && self
+                .migrations
+                .binary_search_by_key(&version, |migration| migration.version)
+                .is_err()
+        {
+            bail!("unknown schema version {}", version);
+        }
+        Ok(version)
+    }
+
+    /// Run `migrate` holding the lock of the migrators of the database
+    async fn locked(
+        &self,
+        connection: &Connection,
+        migrate: impl std::future::Future<Output = Result<Vec<MigrationStep>, Error>>,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        self.lock(connection).await?;
+        let result = migrate.await;
+        let unlocked = unlock(connection).await;
+        let steps = result?;
+        unlocked?;
+        Ok(steps)
+    }
+
+    /// Take the lock by inserting its row, waiting for up to the lock
+    /// timeout while another migrator holds it
+    async fn lock(&self, connection: &Connection) -> Result<(), Error> {
+        let mut transaction = connection.start_transaction().await?;
+        transaction
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (id BIGINT NOT NULL PRIMARY KEY, locked_at BIGINT NOT NULL)",
+                MIGRATION_LOCK_TABLE
+            ))
+            .await?;
+        transaction.commit().await?;
+
+        let start = Instant::now();
+        loop {
+            let locked_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs());
+            let mut transaction = connection.start_transaction().await?;
+            let insert = transaction
+                .execute(format!(
+                    "INSERT INTO {} (id, locked_at) VALUES (1, {})",
+                    MIGRATION_LOCK_TABLE, locked_at
+                ))
+                .await;
+            match insert {
+                Ok(()) => return transaction.commit().await,
+                Err(err) => {
+                    transaction.rollback().await?;
+                    if start.elapsed() >= self.lock_timeout {
+                        return Err(err).with_context(|| {
+                            format!(
+                                "another migrator held the lock for {:?}, delete the row of {} if none is running",
+                                self.lock_timeout, MIGRATION_LOCK_TABLE
+                            )
+                        });
+                    }
+                }
+            }
+            tokio::time::sleep(LOCK_RETRY_DELAY).await;
+        }
+    }
+
+    async fn run(
+        &self,
+        connection: &Connection,
+        steps: Vec<MigrationStep>,
+    ) -> Result<Vec<MigrationStep>, Error> {
+        if self.dry_run {
+            return Ok(steps);
+        }
+        for step in &steps {
+            let mut transaction = connection.start_transaction().await?;
+            for statement in &step.statements {
+                transaction.execute(statement.clone()).await?;
+            }
+            transaction.commit().await?;
+        }
+        Ok(steps)
+    }
+}
+
+/// Release the lock taken by [Migrations::lock]
+async fn unlock(connection: &Connection) -> Result<(), Error> {
+    let mut transaction = connection.start_transaction().await?;
+    transaction
+        .execute(format!("DELETE FROM {} WHERE id = 1", MIGRATION_LOCK_TABLE))
+        .await?;
+    transaction.commit().await
+}
+
+/// Read the schema version of the database, 0 if it has no tracking table
+async fn read_version(
+    connection: &Connection,
+    transaction: &mut Transaction,
+) -> Result<u64, Error> {
+    let table_exists = match connection.backend() {
+        Connection::Sqlite(_) => format!(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '{}'",
+            SCHEMA_VERSION_TABLE
+        ),
+        Connection::Postgres(_) => format!(
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = '{}'",
+            SCHEMA_VERSION_TABLE
+        ),
+        _ => format!(
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = '{}'",
+            SCHEMA_VERSION_TABLE
+        ),
+    };
+    if transaction.read_u64(&table_exists).await? == 0 {
+        return Ok(0);
+    }
+    transaction
+        .read_u64(&format!(
+            "SELECT COALESCE(MAX(version), 0) FROM {}",
+            SCHEMA_VERSION_TABLE
+        ))
+        .await
+}
+
+/// Split a script on the `;` that aren't quoted or in a comment, dropping
+/// the comments and the empty statements. The comments are `-- ...` up to
+/// the end of the line and `/* ... */`, except the `/*! ... */` that Mysql
+/// runs, which are kept.
+fn split_statements(script: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut statement = String::new();
+    let mut quote = None;
+    let mut chars = script.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == ';' => {
+                statements.push(statement.trim().to_owned());
+                statement.clear();
+                continue;
+            }
+            None if c == '-' && chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                statement.push('\n');
+                continue;
+            }
+            None if c == '/' && chars.peek() == Some(&'*') => {
+                chars.next();
+                let kept = chars.peek() == Some(&'!');
+                if kept {
+                    statement.push_str("/*");
+                }
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if kept {
+                        statement.push(c);
+                    }
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+                if !kept {
+                    statement.push(' ');
+                }
+                continue;
+            }
+            None => {}
+        }
+        statement.push(c);
+    }
+    statements.push(statement.trim().to_owned());
+    statements.retain(|statement| !statement.is_empty());
+    statements
+}
+
+impl SqlConnectionsWithSchema {
+    /// Apply the migrations following the current version of the schema, on
+    /// the schema connection for sqlite and on the write connection otherwise
+    pub async fn migrate(&self, migrations: &Migrations) -> Result<Vec<MigrationStep>, Error> {
+        let connection = self
+            .schema_connection
+            .as_ref()
+            .unwrap_or(&self.connections.write_connection);
+        migrations.migrate_up(connection, None).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn migrations() -> Migrations {
+        Migrations::new(vec![
+            Migration::new(2, "create baz", "CREATE TABLE baz (y INT)").with_down("DROP TABLE baz"),
+            Migration::new(
+                1,
+                "create foo",
+                "CREATE TABLE foo (x INT); CREATE INDEX x ON foo (x);",
+            )
+            .with_down("DROP TABLE foo"),
+            Migration::new(3, "add bar", "CREATE TABLE bar (x INT)"),
+        ])
+        .unwrap()
+    }
+
+    fn versions(steps: &[MigrationStep]) -> Vec<u64> {
+        steps.iter().map(|step| step.version).collect()
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(migrations().latest_version(), 3);
+        assert!(Migrations::new(vec![Migration::new(0, "zero", "")]).is_err());
+        assert!(
+            Migrations::new(vec![Migration::new(1, "a", ""), Migration::new(1, "b", "")]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements(
+                "CREATE TABLE a (x TEXT DEFAULT ';');\n\nINSERT INTO a VALUES (\"b;\");;"
+            ),
+            vec![
+                "CREATE TABLE a (x TEXT DEFAULT ';')".to_owned(),
+                "INSERT INTO a VALUES (\"b;\")".to_owned(),
+            ]
+        );
+        assert!(split_statements(" ; ").is_empty());
+        assert_eq!(
+            split_statements(
+                "-- Create a; and b\nCREATE TABLE a (x INT); /* b; */ CREATE TABLE b (y TEXT DEFAULT '--;');\n\
+                 /*!40101 SET x = 1; */ -- done;"
+            ),
+            vec![
+                "CREATE TABLE a (x INT)".to_owned(),
+                "CREATE TABLE b (y TEXT DEFAULT '--;')".to_owned(),
+                "/*!40101 SET x = 1; */".to_owned(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lock() -> Result<(), Error> {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory()?);
+        let migrations = migrations().lock_timeout(Duration::ZERO);
+        let (first, second) = futures::future::try_join(
+            migrations.migrate_up(&conn, None),
+            migrations
+                .clone()
+                .lock_timeout(Duration::from_secs(60))
+                .migrate_up(&conn, None),
+        )
+        .await?;
+        // The migrations ran once, by either migrator
+        assert_eq!(first.len() + second.len(), 3);
+
+        let mut transaction = conn.start_transaction().await?;
+        transaction
+            .execute("INSERT INTO schema_version_lock (id, locked_at) VALUES (1, 0)".to_owned())
+            .await?;
+        transaction.commit().await?;
+        assert!(migrations.migrate_down(&conn, 2).await.is_err());
+        assert_eq!(migrations.current_version(&conn).await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate() -> Result<(), Error> {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory()?);
+        let migrations = migrations();
+        assert_eq!(migrations.current_version(&conn).await?, 0);
+
+        let dry_run = migrations.clone().dry_run(true);
+        let steps = dry_run.migrate_up(&conn, Some(2)).await?;
+        assert_eq!(versions(&steps), vec![1, 2]);
+        assert_eq!(steps[0].statements.len(), 3);
+        assert_eq!(migrations.current_version(&conn).await?, 0);
+
+        let steps = migrations.migrate_up(&conn, Some(2)).await?;
+        assert_eq!(versions(&steps), vec![1, 2]);
+        assert_eq!(migrations.current_version(&conn).await?, 2);
+        let steps = migrations.migrate_up(&conn, None).await?;
+        assert_eq!(versions(&steps), vec![3]);
+        assert!(migrations.migrate_up(&conn, None).await?.is_empty());
+
+        // Migration 3 has no down script
+        assert!(migrations.migrate_down(&conn, 1).await.is_err());
+        assert!(migrations.migrate_down(&conn, 4).await.is_err());
+        assert_eq!(migrations.current_version(&conn).await?, 3);
+
+        let mut transaction = conn.start_transaction().await?;
+        transaction
+            .execute("DELETE FROM schema_version WHERE version = 3".to_owned())
+            .await?;
+        transaction.commit().await?;
+        let steps = migrations.migrate_down(&conn, 0).await?;
+        assert_eq!(versions(&steps), vec![2, 1]);
+        assert_eq!(steps[0].direction, Direction::Down);
+        assert_eq!(migrations.current_version(&conn).await?, 0);
+        Ok(())
+    }
+}
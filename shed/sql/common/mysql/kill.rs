@@ -0,0 +1,372 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [QueryKiller], which runs `KILL QUERY` on the Mysql
+//! queries whose future is dropped before they complete, e.g. because they
+//! timed out. Neither dropping the future nor closing its connection stops a
+//! query: the server only notices that the client went away when it sends
+//! the results, so a long or locking query keeps loading it.
+//!
+//! Killing is off by default, as each cancelled query costs a lookup and a
+//! kill on another connection of the server. It is enabled by
+//! [crate::Connection::with_kill_on_drop], for the queries of the macros and
+//! the transactions of the connection. Each of its Mysql queries is then sent
+//! with a leading `/* kill:<id> */` comment. When its future is dropped, a
+//! task spawned on the current tokio runtime looks the id up in
+//! `information_schema.processlist` from another connection and kills the
+//! threads running it. This is best effort: a query that hasn't reached the
+//! server yet isn't found, and the failures are only counted in
+//! `sql.query_kill.failures`.
+
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Error;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use lazy_static::lazy_static;
+use stats::prelude::*;
+
+use super::{Connection, Pool};
+use crate::{ConnectionLayer, Unwrap};
+
+define_stats! {
+    prefix = "sql.query_kill";
+    kills: timeseries(Rate, Sum),
+    killed: timeseries(Rate, Sum),
+    failures: timeseries(Rate, Sum),
+}
+
+lazy_static! {
+    /// Random part of the ids, so that the queries of other processes are
+    /// never killed
+    static ref PROCESS_ID: u64 = RandomState::new().build_hasher().finish();
+}
+
+static NEXT_QUERY_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Where a [QueryKiller] runs `KILL QUERY`: on the client of a
+/// `Connection::Mysql`, or on a connection taken from the pool of the query
+#[derive(Clone)]
+pub enum QueryKiller {
+    /// Kill on the Mysql client running the query
+    Connection(Connection),
+    /// Kill on a connection of the pool whose connection runs the query,
+    /// which is closed rather than given back to the pool
+    Pool(Pool),
+}
+
+impl QueryKiller {
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Run `query` with `run`, killing
+    /// it with `killer`, if any, if the returned future is dropped before it
+    /// completes.
+    #[doc(hidden)]
+    pub async fn run<T, E, F, Fut>(
+        killer: Option<QueryKiller>,
+        query: String,
+        run: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<Error>,
+    {
+        let guard = KillOnDrop::new(killer);
+        let result = run(guard.tag(query)).await.map_err(E::into);
+        guard.disarm();
+        result
+    }
+
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it. Stream the rows of `query` with
+    /// `run`, killing it with `killer`, if any, if the stream is dropped
+    /// before it ends.
+    #[doc(hidden)]
+    pub fn run_stream<T, E, S>(
+        killer: Option<QueryKiller>,
+        query: String,
+        run: impl FnOnce(String) -> S,
+    ) -> BoxStream<'static, Result<T, Error>>
+    where
+        T: Send + 'static,
+        E: Into<Error> + 'static,
+        S: Stream<Item = Result<T, E>> + Send + 'static,
+    {
+        let guard = KillOnDrop::new(killer);
+        let rows = run(guard.tag(query)).boxed();
+        stream::unfold(Some((guard, rows)), |state| async move {
+            let (guard, mut rows) = state?;
+            match rows.next().await {
+                Some(row) => Some((row.map_err(E::into), Some((guard, rows)))),
+                None => {
+                    guard.disarm();
+                    None
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Killer of the queries of `backend`, if it is a Mysql connection
+    fn of(backend: &crate::Connection) -> Option<QueryKiller> {
+        match backend {
+            crate::Connection::Mysql(conn) => Some(QueryKiller::Connection(conn.clone())),
+            crate::Connection::MysqlPool(pool) => Some(QueryKiller::Pool(pool.clone())),
+            _ => None,
+        }
+    }
+
+    /// Kill the queries whose comment starts with `comment`, returning how
+    /// many were found
+    async fn kill(&self, comment: &str) -> Result<usize, Error> {
+        match self {
+            QueryKiller::Connection(conn) => kill_on(conn, comment).await,
+            QueryKiller::Pool(pool) => {
+                let conn = pool.acquire().await?;
+                conn.run(kill_on(&conn, comment)).await
+            }
+        }
+    }
+}
+
+async fn kill_on(conn: &Connection, comment: &str) -> Result<usize, Error> {
+    // The lookup itself starts with SELECT, so it doesn't match
+    let ids: Vec<(u64,)> = conn
+        .read_query(format!(
+            "SELECT id FROM information_schema.processlist WHERE info LIKE '{}%'",
+            comment
+        ))
+        .await?;
+    for (id,) in &ids {
+        conn.write_query(format!("KILL QUERY {}", id)).await?;
+    }
+    Ok(ids.len())
+}
+
+/// Connection of [crate::Connection::with_kill_on_drop]
+pub struct KillingConnection {
+    connection: crate::Connection,
+}
+
+impl ConnectionLayer for KillingConnection {
+    fn kind(&self) -> &'static str {
+        "Kill-on-drop"
+    }
+
+    fn inner(&self, _: Unwrap) -> &crate::Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl crate::Connection {
+    /// Connection running `KILL QUERY` on the Mysql queries of the macros
+    /// and of the transactions of this connection whose future is dropped
+    /// before they complete, see [QueryKiller]. The queries are not killed
+    /// by default.
+    pub fn with_kill_on_drop(self) -> crate::Connection {
+        if self.kills_on_drop() {
+            return self;
+        }
+        crate::Connection::Layered(Arc::new(KillingConnection { connection: self }))
+    }
+
+    /// Whether this connection kills its dropped queries, see
+    /// [crate::Connection::with_kill_on_drop]
+    pub fn kills_on_drop(&self) -> bool {
+        self.layer::<KillingConnection>().is_some()
+    }
+
+    /// The killer of the write queries of this connection, if it kills them.
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn query_killer(&self) -> Option<QueryKiller> {
+        self.layer::<KillingConnection>()?;
+        QueryKiller::of(self.backend())
+    }
+
+    /// The killer of the read queries of this connection, if it kills them.
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn read_query_killer(&self) -> Option<QueryKiller> {
+        self.read_layer::<KillingConnection>()?;
+        QueryKiller::of(self.read_backend())
+    }
+}
+
+/// Kills its query when dropped, unless disarmed once the query completed
+/// or it has no killer
+struct KillOnDrop {
+    comment: String,
+    killer: Option<QueryKiller>,
+}
+
+impl KillOnDrop {
+    fn new(killer: Option<QueryKiller>) -> Self {
+        let comment = match killer {
+            Some(_) => {
+                let id = NEXT_QUERY_ID.fetch_add(1, Ordering::Relaxed);
+                format!("/* kill:{:016x}{:08x} */", *PROCESS_ID, id)
+            }
+            None => String::new(),
+        };
+        Self { comment, killer }
+    }
+
+    fn tag(&self, query: String) -> String {
+        if self.killer.is_none() {
+            return query;
+        }
+        format!("{} {}", self.comment, query)
+    }
+
+    fn disarm(mut self) {
+        self.killer = None;
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let killer = match self.killer.take() {
+            Some(killer) => killer,
+            None => return,
+        };
+        STATS::kills.add_value(1);
+        let comment = std::mem::take(&mut self.comment);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    match killer.kill(&comment).await {
+                        Ok(killed) => STATS::killed.add_value(killed as i64),
+                        Err(_) => STATS::failures.add_value(1),
+                    }
+                });
+            }
+            Err(_) => STATS::failures.add_value(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use anyhow::format_err;
+    use fbinit::FacebookInit;
+    use futures::future::{self, FutureExt};
+
+    use crate::pool::PoolOptions;
+    use crate::Connection as SqlConnection;
+
+    /// Killer whose pool fails to connect, counting the kill attempts
+    fn failing_killer(fb: FacebookInit) -> (QueryKiller, Arc<AtomicU64>) {
+        let attempts = Arc::new(AtomicU64::new(0));
+        let pool = Pool::new(fb, "test_kill", PoolOptions::default(), {
+            let attempts = attempts.clone();
+            move || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                future::err(format_err!("no Mysql server"))
+            }
+        });
+        (QueryKiller::Pool(pool), attempts)
+    }
+
+    #[fbinit::test]
+    async fn test_run(fb: FacebookInit) -> Result<(), Error> {
+        let (killer, attempts) = failing_killer(fb);
+        let query = QueryKiller::run(
+            Some(killer.clone()),
+            "SELECT 1".to_owned(),
+            |query| async move { Ok::<_, Error>(query) },
+        )
+        .await?;
+        assert!(query.starts_with("/* kill:"), "{}", query);
+        assert!(query.ends_with(" */ SELECT 1"), "{}", query);
+
+        let other = QueryKiller::run(Some(killer), "SELECT 1".to_owned(), |query| async move {
+            Ok::<_, Error>(query)
+        })
+        .await?;
+        assert_ne!(query, other);
+
+        // Without a killer the query is neither tagged nor killed
+        let query = QueryKiller::run(None, "SELECT 1".to_owned(), |query| async move {
+            Ok::<_, Error>(query)
+        })
+        .await?;
+        assert_eq!(query, "SELECT 1");
+
+        tokio::task::yield_now().await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_kill_on_drop(fb: FacebookInit) {
+        let (killer, attempts) = failing_killer(fb);
+        let query = QueryKiller::run(Some(killer.clone()), "SELECT SLEEP(10)".to_owned(), |_| {
+            future::pending::<Result<(), Error>>()
+        });
+        assert!(query.now_or_never().is_none());
+        while attempts.load(Ordering::Relaxed) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let rows = QueryKiller::run_stream(Some(killer.clone()), "SELECT 1".to_owned(), |_| {
+            stream::iter(vec![Ok::<_, Error>(1), Ok(2)])
+        });
+        assert_eq!(rows.collect::<Vec<_>>().await.len(), 2);
+        let mut rows = QueryKiller::run_stream(Some(killer), "SELECT 1".to_owned(), |_| {
+            stream::iter(vec![Ok::<_, Error>(1), Ok(2)])
+        });
+        assert!(rows.next().await.is_some());
+        drop(rows);
+        while attempts.load(Ordering::Relaxed) == 1 {
+            tokio::task::yield_now().await;
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+    }
+
+    #[fbinit::test]
+    async fn test_with_kill_on_drop(fb: FacebookInit) {
+        let (killer, _) = failing_killer(fb);
+        let pool = match killer {
+            QueryKiller::Pool(pool) => pool,
+            QueryKiller::Connection(_) => unreachable!(),
+        };
+        let conn = SqlConnection::from(pool);
+        assert!(!conn.kills_on_drop());
+        assert!(conn.query_killer().is_none());
+
+        let conn = conn.with_kill_on_drop().readonly();
+        assert!(conn.kills_on_drop());
+        assert!(conn.clone().with_kill_on_drop().kills_on_drop());
+        assert!(matches!(conn.query_killer(), Some(QueryKiller::Pool(_))));
+        assert!(matches!(
+            conn.read_query_killer(),
+            Some(QueryKiller::Pool(_))
+        ));
+
+        // Only the Mysql queries are killed
+        let sqlite = SqlConnection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(sqlite.with_kill_on_drop().query_killer().is_none());
+    }
+}
@@ -11,25 +11,200 @@
 
 #[cfg(fbcode_build)]
 mod facebook;
+mod kill;
 #[cfg(not(fbcode_build))]
 mod mysql_stub;
+mod prepared;
 
 #[cfg(fbcode_build)]
 pub use facebook::{
     opt_try_from_rowfield, Connection, ConnectionStats, MysqlError, OptionalTryFromRowField,
-    RowField, Transaction, TryFromRowField, WriteResult,
+    RowField, Statement, Transaction, TryFromRowField, WriteResult,
 };
+pub use kill::QueryKiller;
 pub use mysql_derive::{OptTryFromRowField, TryFromRowField};
 #[cfg(not(fbcode_build))]
 pub use mysql_stub::{
     opt_try_from_rowfield, Connection, ConnectionStats, MysqlError, OptionalTryFromRowField,
-    RowField, Transaction, TryFromRowField, WriteResult,
+    RowField, Statement, Transaction, TryFromRowField, WriteResult,
 };
+pub use prepared::{PreparedCache, PreparedConnection, DEFAULT_PREPARED_CACHE_SIZE};
+
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Error;
+use fbinit::FacebookInit;
+use futures::future::TryFutureExt;
 
 use super::WriteResult as SqlWriteResult;
+use crate::pool::{self, Connector, PoolOptions, PooledConnection};
+use crate::SqlConnections;
 
 impl Into<SqlWriteResult> for WriteResult {
     fn into(self) -> SqlWriteResult {
-        SqlWriteResult::new(Some(self.last_insert_id()), self.rows_affected())
+        let result = SqlWriteResult::new(Some(self.last_insert_id()), self.rows_affected());
+        match self.info().as_deref().and_then(rows_matched_changed) {
+            Some((matched, changed)) => result.with_rows_matched_changed(matched, changed),
+            None => result,
+        }
+    }
+}
+
+/// The rows matched and changed by an update, from the info string of the
+/// server, `Rows matched: <matched>  Changed: <changed>  Warnings: <warnings>`
+fn rows_matched_changed(info: &str) -> Option<(u64, u64)> {
+    let count = |label: &str| {
+        let (_, rest) = info.split_once(label)?;
+        rest.split_whitespace().next()?.parse().ok()
+    };
+    Some((count("Rows matched:")?, count("Changed:")?))
+}
+
+/// Pool of Mysql connections, limiting how many connections a process opens
+/// to the server. See [crate::pool] for its options and metrics.
+pub type Pool = pool::Pool<Connection>;
+
+impl From<Pool> for crate::Connection {
+    fn from(pool: Pool) -> Self {
+        crate::Connection::MysqlPool(pool)
+    }
+}
+
+/// Transaction on a connection of a [Pool], which it holds until it is
+/// committed, rolled back or dropped. The connection is only given back to
+/// the pool once the transaction is committed or rolled back, a transaction
+/// dropped before, e.g. because one of its queries was cancelled, closes it.
+pub struct PooledTransaction {
+    // Dropped before the connection is given back to the pool
+    transaction: Transaction,
+    connection: PooledConnection<Connection>,
+    pool: Pool,
+    kill_on_drop: bool,
+}
+
+impl PooledTransaction {
+    /// Begin a transaction on a connection acquired from `pool`
+    pub async fn new(pool: &Pool) -> Result<Self, Error> {
+        Self::begin(pool, None).await
+    }
+
+    /// Begin an XA transaction identified by `xid` on a connection acquired
+    /// from `pool`, see [Transaction::xa_prepare]
+    pub async fn new_xa(pool: &Pool, xid: String) -> Result<Self, Error> {
+        Self::begin(pool, Some(xid)).await
+    }
+
+    async fn begin(pool: &Pool, xid: Option<String>) -> Result<Self, Error> {
+        let connection = pool.acquire().await?;
+        connection.start();
+        let transaction = match xid {
+            Some(xid) => connection.begin_xa_transaction(xid).await,
+            None => connection.begin_transaction().await,
+        }
+        .map_err(Error::from)?;
+        Ok(Self {
+            transaction,
+            connection,
+            pool: pool.clone(),
+            kill_on_drop: false,
+        })
+    }
+
+    /// Kill the queries of the transaction that are dropped before they
+    /// complete, see [crate::Connection::with_kill_on_drop]
+    pub fn with_kill_on_drop(mut self) -> Self {
+        self.kill_on_drop = true;
+        self
+    }
+
+    /// Killer of the queries of the transaction, running `KILL QUERY` on
+    /// another connection of its pool, if it kills them
+    pub fn killer(&self) -> Option<QueryKiller> {
+        self.kill_on_drop
+            .then(|| QueryKiller::Pool(self.pool.clone()))
+    }
+
+    /// Commit transaction.
+    pub async fn commit(self) -> Result<(), Error> {
+        let Self {
+            transaction,
+            connection,
+            ..
+        } = self;
+        let result = transaction.commit().map_err(Error::from).await;
+        connection.finish(&result);
+        result
+    }
+
+    /// Rollback transaction.
+    pub async fn rollback(self) -> Result<(), Error> {
+        let Self {
+            transaction,
+            connection,
+            ..
+        } = self;
+        let result = transaction.rollback().map_err(Error::from).await;
+        connection.finish(&result);
+        result
+    }
+}
+
+impl Deref for PooledTransaction {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        &self.transaction
+    }
+}
+
+impl DerefMut for PooledTransaction {
+    fn deref_mut(&mut self) -> &mut Transaction {
+        &mut self.transaction
+    }
+}
+
+impl SqlConnections {
+    /// Create SqlConnections whose connections come from three pools with
+    /// the same `options`, named `<name>.write`, `<name>.read` and
+    /// `<name>.read_master`, each opening Mysql connections with its
+    /// connector.
+    pub fn with_mysql_pools(
+        fb: FacebookInit,
+        name: &str,
+        options: PoolOptions,
+        write: impl Connector<Connection>,
+        read: impl Connector<Connection>,
+        read_master: impl Connector<Connection>,
+    ) -> Self {
+        Self {
+            write_connection: Pool::new(fb, format!("{}.write", name), options.clone(), write)
+                .into(),
+            read_connection: Pool::new(fb, format!("{}.read", name), options.clone(), read).into(),
+            read_master_connection: Pool::new(
+                fb,
+                format!("{}.read_master", name),
+                options,
+                read_master,
+            )
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rows_matched_changed() {
+        assert_eq!(
+            rows_matched_changed("Rows matched: 2  Changed: 1  Warnings: 0"),
+            Some((2, 1))
+        );
+        assert_eq!(
+            rows_matched_changed("Records: 3  Duplicates: 0  Warnings: 0"),
+            None
+        );
+        assert_eq!(rows_matched_changed(""), None);
     }
 }
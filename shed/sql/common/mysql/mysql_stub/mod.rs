@@ -9,6 +9,8 @@
 
 //! Facebook Mysql client stub.
 
+use futures::stream::BoxStream;
+use mysql_async::Value;
 use std::fmt::{self, Display};
 use thiserror::Error;
 
@@ -34,6 +36,11 @@ impl WriteResult {
     pub fn rows_affected(&self) -> u64 {
         unimplemented!("This is a stub");
     }
+    /// Get the info string of the server, e.g. `Rows matched: 2  Changed: 1  Warnings: 0`
+    /// for an update
+    pub fn info(&self) -> Option<String> {
+        unimplemented!("This is a stub");
+    }
 }
 
 /// ODS counters
@@ -51,22 +58,58 @@ impl Connection {
         unimplemented!("This is a stub");
     }
 
+    /// Performs a given query and returns a stream of its rows, read from
+    /// the server as the stream is polled.
+    pub fn read_query_stream<T>(
+        &self,
+        _query: String,
+    ) -> BoxStream<'static, Result<T, MysqlError>> {
+        unimplemented!("This is a stub");
+    }
+
     /// Performs a given query and returns the write result.
     pub async fn write_query(&self, _query: String) -> Result<WriteResult, MysqlError> {
         unimplemented!("This is a stub");
     }
 
+    /// Prepares a given query on the server, with `?` placeholders for its values.
+    pub async fn prepare(&self, _query: String) -> Result<Statement, MysqlError> {
+        unimplemented!("This is a stub");
+    }
+
     /// Begins trasaction and returns Transaction object.
     pub async fn begin_transaction(&self) -> Result<Transaction, MysqlError> {
         unimplemented!("This is a stub");
     }
 
+    /// Begins an XA transaction identified by `xid` with `XA START`, see
+    /// [Transaction::xa_prepare].
+    pub async fn begin_xa_transaction(&self, _xid: String) -> Result<Transaction, MysqlError> {
+        unimplemented!("This is a stub");
+    }
+
     /// Returns the replication lag for a connection.
     pub async fn get_replica_lag_secs(&self) -> Result<Option<u64>, MysqlError> {
         unimplemented!("This is a stub");
     }
 }
 
+/// Statement prepared on the server, whose values are sent with the binary
+/// protocol. It is closed on the server when dropped.
+pub struct Statement;
+
+impl Statement {
+    /// Executes the statement with the given values and returns the result as a vector of rows.
+    pub async fn read_query<T>(&self, _values: Vec<Value>) -> Result<T, MysqlError> {
+        unimplemented!("This is a stub");
+    }
+
+    /// Executes the statement with the given values and returns the write result.
+    pub async fn write_query(&self, _values: Vec<Value>) -> Result<WriteResult, MysqlError> {
+        unimplemented!("This is a stub");
+    }
+}
+
 /// Transaction object.
 pub struct Transaction;
 
@@ -81,6 +124,13 @@ impl Transaction {
         unimplemented!("This is a stub");
     }
 
+    /// Ends and prepares an XA transaction with `XA END` and `XA PREPARE`.
+    /// It can then only be committed or rolled back, with `XA COMMIT` and
+    /// `XA ROLLBACK`, and survives the loss of the connection until it is.
+    pub async fn xa_prepare(&mut self) -> Result<(), MysqlError> {
+        unimplemented!("This is a stub");
+    }
+
     /// Commit transaction.
     pub async fn commit(self) -> Result<(), MysqlError> {
         unimplemented!("This is a stub");
@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [PreparedConnection], a Mysql connection running its
+//! queries as server-side prepared statements, so that the server parses a
+//! hot query once instead of on every call, and the values are sent with the
+//! binary protocol instead of being escaped in the text of the query. The
+//! statements are kept in a [PreparedCache], an LRU keyed by their text,
+//! whose hits, misses and evictions are exported as
+//! `sql.mysql.prepared.{hits,misses,evictions}`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use mysql_async::Value;
+use stats::prelude::*;
+
+use super::{Connection, Statement};
+use crate::WriteResult;
+
+define_stats! {
+    prefix = "sql.mysql.prepared";
+    hits: timeseries(Rate, Sum),
+    misses: timeseries(Rate, Sum),
+    evictions: timeseries(Rate, Sum),
+}
+
+/// Number of statements a [PreparedConnection] keeps prepared by default
+pub const DEFAULT_PREPARED_CACHE_SIZE: usize = 64;
+
+struct Entry<S> {
+    statement: Arc<S>,
+    /// Position of the entry in `Statements::lru`
+    tick: u64,
+}
+
+struct Statements<S> {
+    entries: HashMap<String, Entry<S>>,
+    /// Texts ordered from the least to the most recently used
+    lru: BTreeMap<u64, String>,
+    next_tick: u64,
+}
+
+/// LRU of the statements prepared on a connection, keyed by their text. The
+/// statements it evicts are closed on the server once the queries running
+/// them complete.
+pub struct PreparedCache<S> {
+    capacity: usize,
+    statements: Mutex<Statements<S>>,
+}
+
+impl<S> PreparedCache<S> {
+    /// Create a cache keeping up to `capacity` statements. With a capacity of
+    /// 0 nothing is kept, each query preparing its statement again.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            statements: Mutex::new(Statements {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                next_tick: 0,
+            }),
+        }
+    }
+
+    /// Most statements the cache keeps
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of statements in the cache
+    pub fn len(&self) -> usize {
+        self.statements.lock().expect("lock poisoned").entries.len()
+    }
+
+    /// Whether the cache has no statement
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The statement of `query`, prepared with `prepare` if it isn't cached.
+    /// A statement that failed to prepare is not cached.
+    pub async fn get_or_prepare<F, Fut, E>(&self, query: &str, prepare: F) -> Result<Arc<S>, Error>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<S, E>>,
+        E: Into<Error>,
+    {
+        if let Some(statement) = self.get(query) {
+            STATS::hits.add_value(1);
+            return Ok(statement);
+        }
+        STATS::misses.add_value(1);
+        let statement = Arc::new(prepare(query.to_owned()).await.map_err(E::into)?);
+        self.insert(query, statement.clone());
+        Ok(statement)
+    }
+
+    fn get(&self, query: &str) -> Option<Arc<S>> {
+        let mut statements = self.statements.lock().expect("lock poisoned");
+        let statements = &mut *statements;
+        let entry = statements.entries.get_mut(query)?;
+        statements.next_tick += 1;
+        let text = statements
+            .lru
+            .remove(&entry.tick)
+            .expect("cached statements are in the lru");
+        entry.tick = statements.next_tick;
+        statements.lru.insert(entry.tick, text);
+        Some(entry.statement.clone())
+    }
+
+    fn insert(&self, query: &str, statement: Arc<S>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut statements = self.statements.lock().expect("lock poisoned");
+        statements.next_tick += 1;
+        let tick = statements.next_tick;
+        // Another query may have prepared the same statement meanwhile
+        if let Some(previous) = statements
+            .entries
+            .insert(query.to_owned(), Entry { statement, tick })
+        {
+            statements.lru.remove(&previous.tick);
+        }
+        statements.lru.insert(tick, query.to_owned());
+        while statements.entries.len() > self.capacity {
+            let (_, oldest) = statements
+                .lru
+                .pop_first()
+                .expect("cached statements are in the lru");
+            statements.entries.remove(&oldest);
+            STATS::evictions.add_value(1);
+        }
+    }
+}
+
+/// Mysql connection running its prepared queries with the statements of its
+/// [PreparedCache]. It dereferences to the [Connection], for the queries
+/// that are not prepared.
+pub struct PreparedConnection {
+    connection: Connection,
+    cache: PreparedCache<Statement>,
+}
+
+impl PreparedConnection {
+    /// Run the prepared queries of `connection`, keeping up to `cache_size`
+    /// of their statements prepared, see [DEFAULT_PREPARED_CACHE_SIZE]
+    pub fn new(connection: Connection, cache_size: usize) -> Self {
+        Self {
+            connection,
+            cache: PreparedCache::new(cache_size),
+        }
+    }
+
+    /// The statements prepared on the connection
+    pub fn cache(&self) -> &PreparedCache<Statement> {
+        &self.cache
+    }
+
+    /// Run `query`, with `?` placeholders for its `values`, as a prepared
+    /// statement and return its rows
+    pub async fn read_prepared<T>(&self, query: &str, values: Vec<Value>) -> Result<T, Error> {
+        let statement = self
+            .cache
+            .get_or_prepare(query, |query| self.connection.prepare(query))
+            .await?;
+        Ok(statement.read_query(values).await?)
+    }
+
+    /// Run `query`, with `?` placeholders for its `values`, as a prepared
+    /// statement and return the result of the write
+    pub async fn write_prepared(
+        &self,
+        query: &str,
+        values: Vec<Value>,
+    ) -> Result<WriteResult, Error> {
+        let statement = self
+            .cache
+            .get_or_prepare(query, |query| self.connection.prepare(query))
+            .await?;
+        Ok(statement.write_query(values).await?.into())
+    }
+}
+
+impl Deref for PreparedConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::format_err;
+
+    /// Prepare the statements as their text, counting the calls
+    async fn get(cache: &PreparedCache<String>, prepared: &AtomicUsize, query: &str) -> String {
+        let statement = cache
+            .get_or_prepare(query, |query| async move {
+                prepared.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, Error>(query)
+            })
+            .await
+            .unwrap();
+        (*statement).clone()
+    }
+
+    #[tokio::test]
+    async fn test_prepared_cache() {
+        let cache = PreparedCache::new(2);
+        let prepared = AtomicUsize::new(0);
+        assert_eq!(get(&cache, &prepared, "SELECT 1").await, "SELECT 1");
+        assert_eq!(get(&cache, &prepared, "SELECT 1").await, "SELECT 1");
+        assert_eq!(prepared.load(Ordering::Relaxed), 1);
+
+        get(&cache, &prepared, "SELECT 2").await;
+        // SELECT 2 is the least recently used once SELECT 1 is hit again
+        get(&cache, &prepared, "SELECT 1").await;
+        get(&cache, &prepared, "SELECT 3").await;
+        assert_eq!((cache.len(), prepared.load(Ordering::Relaxed)), (2, 3));
+        get(&cache, &prepared, "SELECT 1").await;
+        assert_eq!(prepared.load(Ordering::Relaxed), 3);
+        get(&cache, &prepared, "SELECT 2").await;
+        assert_eq!(prepared.load(Ordering::Relaxed), 4);
+
+        assert!(cache
+            .get_or_prepare("SELECT x FROM", |_| async {
+                Err::<String, _>(format_err!("syntax error"))
+            })
+            .await
+            .is_err());
+        assert_eq!(cache.len(), 2);
+
+        // Outside of fbcode the stats are recorded in memory
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert!(stats["sql.mysql.prepared.hits.sum"] >= 2);
+            assert!(stats["sql.mysql.prepared.misses.sum"] >= 5);
+            assert!(stats["sql.mysql.prepared.evictions.sum"] >= 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prepared_cache_disabled() {
+        let cache = PreparedCache::new(0);
+        let prepared = AtomicUsize::new(0);
+        get(&cache, &prepared, "SELECT 1").await;
+        get(&cache, &prepared, "SELECT 1").await;
+        assert!(cache.is_empty());
+        assert_eq!(prepared.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [QueryObserver], a hook called after every query run on a
+//! connection it is registered on, e.g. for logging, slow query detection or
+//! sampling. Transactions started on the connection are observed as well:
+//! their queries, and their `BEGIN`, `COMMIT` and `ROLLBACK`.
+
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::{Connection, ConnectionLayer, Unwrap};
+
+/// What a [QueryObserver] is told about a query once it completed
+pub struct QueryInfo<'a> {
+    /// Name of the query in `queries!`, or the statement for the start and
+    /// end of transactions
+    pub name: &'a str,
+    /// Sql of the query, with placeholders for its parameters
+    pub sql: &'a str,
+    /// Names of the parameters of the query and the number of items of its
+    /// lists and values
+    pub params: &'a str,
+    /// Time the query took
+    pub duration: Duration,
+    /// Number of rows read or affected by the query, or its error
+    pub result: Result<u64, &'a Error>,
+    /// Whether the query ran in a transaction
+    pub in_transaction: bool,
+}
+
+/// Hook called after every query run on the connections it is registered on
+/// with [Connection::with_observer]
+pub trait QueryObserver: Send + Sync + 'static {
+    /// Called once the query described by `info` completed
+    fn observe(&self, info: &QueryInfo<'_>);
+}
+
+/// Connection of [Connection::with_observer]
+pub struct ObservedConnection {
+    connection: Connection,
+    observer: Arc<dyn QueryObserver>,
+}
+
+impl ObservedConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The observer of the queries
+    pub fn observer(&self) -> &Arc<dyn QueryObserver> {
+        &self.observer
+    }
+}
+
+impl ConnectionLayer for ObservedConnection {
+    fn kind(&self) -> &'static str {
+        "Observed"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Call `observer` after every query run on this connection or on the
+    /// transactions started from it. It replaces the observer of a
+    /// connection that already had one.
+    pub fn with_observer(self, observer: Arc<dyn QueryObserver>) -> Connection {
+        let connection = match self.as_layer::<ObservedConnection>() {
+            Some(conn) => conn.connection().clone(),
+            None => self,
+        };
+        Connection::Layered(Arc::new(ObservedConnection {
+            connection,
+            observer,
+        }))
+    }
+
+    /// The observer of the queries run on this connection, if any
+    pub fn observer(&self) -> Option<&Arc<dyn QueryObserver>> {
+        self.layer::<ObservedConnection>()
+            .map(ObservedConnection::observer)
+    }
+}
+
+/// Build the summary of the parameters of a query for [QueryInfo::params].
+/// This should never be used directly, it is made public so that the
+/// queries! macro can make use of it
+#[doc(hidden)]
+pub fn params_summary(params: &[&str], lists: &[(&str, usize)]) -> String {
+    params
+        .iter()
+        .map(|param| param.to_string())
+        .chain(
+            lists
+                .iter()
+                .map(|(list, len)| format!("{}: [{} items]", list, len)),
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    struct Names(Mutex<Vec<String>>);
+
+    impl QueryObserver for Names {
+        fn observe(&self, info: &QueryInfo<'_>) {
+            self.0.lock().unwrap().push(info.name.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_with_observer() {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(conn.observer().is_none());
+
+        let names = Arc::new(Names(Mutex::new(Vec::new())));
+        let observed = conn.with_observer(names.clone());
+        observed.observer().unwrap().observe(&QueryInfo {
+            name: "Test",
+            sql: "SELECT 1",
+            params: "",
+            duration: Duration::ZERO,
+            result: Ok(1),
+            in_transaction: false,
+        });
+        assert_eq!(*names.0.lock().unwrap(), vec!["Test".to_owned()]);
+        assert!(matches!(observed.backend(), Connection::Sqlite(_)));
+    }
+
+    #[test]
+    fn test_params_summary() {
+        assert_eq!(params_summary(&[], &[]), "");
+        assert_eq!(
+            params_summary(&["a", "b"], &[("ids", 3)]),
+            "a, b, ids: [3 items]"
+        );
+    }
+}
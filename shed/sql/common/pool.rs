@@ -0,0 +1,588 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [Pool], a bounded pool of database connections. A pool
+//! opens at most `max_connections` connections, reuses the idle ones and
+//! closes those that stayed idle for longer than `idle_timeout`. Each pool
+//! exports `sql.pool.<name>.{in_use,idle,acquired,opened,closed_idle,
+//! acquire_timeouts,acquire_wait_us,closed_cancelled,closed_broken}`.
+//!
+//! A connection whose query was dropped before completing, e.g. because it
+//! timed out, may still have the query running or its results unread, and
+//! one whose query failed on a connection error may be broken. The pool
+//! closes both rather than giving them back to the next caller. Closing the
+//! connection doesn't stop its query on the server, the Mysql queries of the
+//! macros can also be killed, see [crate::Connection::with_kill_on_drop].
+
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicI64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use fbinit::FacebookInit;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use stats::prelude::*;
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::failover::is_connection_error;
+
+define_stats! {
+    prefix = "sql.pool";
+    in_use: dynamic_singleton_counter("{}.in_use", (pool: String)),
+    idle: dynamic_singleton_counter("{}.idle", (pool: String)),
+    acquired: dynamic_timeseries("{}.acquired", (pool: String); Rate, Sum),
+    opened: dynamic_timeseries("{}.opened", (pool: String); Rate, Sum),
+    closed_idle: dynamic_timeseries("{}.closed_idle", (pool: String); Rate, Sum),
+    acquire_timeouts: dynamic_timeseries("{}.acquire_timeouts", (pool: String); Rate, Sum),
+    closed_cancelled: dynamic_timeseries("{}.closed_cancelled", (pool: String); Rate, Sum),
+    closed_broken: dynamic_timeseries("{}.closed_broken", (pool: String); Rate, Sum),
+    acquire_wait_us: dynamic_histogram("{}.acquire_wait_us", (pool: String); 1000, 0, 1_000_000, Average, Count; P 50; P 99),
+}
+
+/// Limits of a [Pool]
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// Most connections open at a time, in use or idle. Defaults to 10.
+    pub max_connections: usize,
+    /// Idle connections unused for longer than this are closed the next time
+    /// the pool is used, or never if None. Defaults to 5 minutes.
+    pub idle_timeout: Option<Duration>,
+    /// Longest time to wait for a connection to be released when all of them
+    /// are in use. Defaults to 10 seconds.
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            idle_timeout: Some(Duration::from_secs(300)),
+            acquire_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Error returned when a [Pool] can't provide a connection
+#[derive(Debug, Error)]
+pub enum PoolError {
+    /// All the connections stayed in use for the whole acquire timeout
+    #[error("No connection of pool {pool} was released within {timeout:?}")]
+    AcquireTimeout {
+        /// Name of the pool
+        pool: String,
+        /// Acquire timeout of the pool
+        timeout: Duration,
+    },
+    /// Opening a new connection failed
+    #[error("Failed to open a connection for pool {pool}")]
+    Connect {
+        /// Name of the pool
+        pool: String,
+        /// Error returned by the connector
+        #[source]
+        source: Error,
+    },
+}
+
+/// Opens the connections of a [Pool]. It is implemented for the closures
+/// returning a future of a connection.
+pub trait Connector<C>: Send + Sync + 'static {
+    /// Open a new connection
+    fn connect(&self) -> BoxFuture<'static, Result<C, Error>>;
+}
+
+impl<C, F, Fut> Connector<C> for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<C, Error>> + Send + 'static,
+{
+    fn connect(&self) -> BoxFuture<'static, Result<C, Error>> {
+        self().boxed()
+    }
+}
+
+/// States of a [PooledConnection]: reusable, running a query, or failed on
+/// a connection error
+const IDLE: u8 = 0;
+const RUNNING: u8 = 1;
+const BROKEN: u8 = 2;
+
+struct Idle<C> {
+    connection: C,
+    since: Instant,
+}
+
+struct Inner<C> {
+    fb: FacebookInit,
+    name: String,
+    options: PoolOptions,
+    connector: Box<dyn Connector<C>>,
+    permits: Arc<Semaphore>,
+    /// Idle connections, the most recently released last
+    idle: Mutex<Vec<Idle<C>>>,
+    /// Connections taken from the pool and not given back yet
+    in_use: AtomicI64,
+}
+
+impl<C> Inner<C> {
+    /// Close the idle connections that timed out and take the most recently
+    /// used of the others
+    fn take_idle(&self) -> Option<C> {
+        let mut idle = self.idle.lock().expect("lock poisoned");
+        if let Some(idle_timeout) = self.options.idle_timeout {
+            let before = idle.len();
+            idle.retain(|conn| conn.since.elapsed() < idle_timeout);
+            let closed = before - idle.len();
+            if closed > 0 {
+                STATS::closed_idle.add_value(closed as i64, (self.name.clone(),));
+            }
+        }
+        let conn = idle.pop();
+        STATS::idle.set_value(self.fb, idle.len() as i64, (self.name.clone(),));
+        Some(conn?.connection)
+    }
+
+    fn release(&self, connection: C) {
+        let mut idle = self.idle.lock().expect("lock poisoned");
+        idle.push(Idle {
+            connection,
+            since: Instant::now(),
+        });
+        STATS::idle.set_value(self.fb, idle.len() as i64, (self.name.clone(),));
+        self.add_in_use(-1);
+    }
+
+    /// Count `delta` more connections in use, exporting the new number
+    fn add_in_use(&self, delta: i64) {
+        let in_use = self.in_use.fetch_add(delta, Ordering::Relaxed) + delta;
+        STATS::in_use.set_value(self.fb, in_use, (self.name.clone(),));
+    }
+}
+
+/// Pool of connections of type `C`. Cloning it gives another handle to the
+/// same pool.
+pub struct Pool<C> {
+    inner: Arc<Inner<C>>,
+}
+
+impl<C> Clone for Pool<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C: Send + 'static> Pool<C> {
+    /// Create a pool named `name` opening its connections with `connector`.
+    /// Panics if `options.max_connections` is 0.
+    pub fn new(
+        fb: FacebookInit,
+        name: impl Into<String>,
+        options: PoolOptions,
+        connector: impl Connector<C>,
+    ) -> Self {
+        assert!(
+            options.max_connections > 0,
+            "pool must allow at least one connection"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                fb,
+                name: name.into(),
+                permits: Arc::new(Semaphore::new(options.max_connections)),
+                options,
+                connector: Box::new(connector),
+                idle: Mutex::new(Vec::new()),
+                in_use: AtomicI64::new(0),
+            }),
+        }
+    }
+
+    /// Name of the pool, used in its stats
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Limits of the pool
+    pub fn options(&self) -> &PoolOptions {
+        &self.inner.options
+    }
+
+    /// Number of connections currently in use
+    pub fn in_use(&self) -> usize {
+        self.inner.options.max_connections - self.inner.permits.available_permits()
+    }
+
+    /// Number of connections open but not in use
+    pub fn idle(&self) -> usize {
+        self.inner.idle.lock().expect("lock poisoned").len()
+    }
+
+    /// Get a connection, reusing an idle one or opening a new one. If
+    /// `max_connections` are already in use this waits for one of them to be
+    /// released, for at most the acquire timeout of the pool.
+    pub async fn acquire(&self) -> Result<PooledConnection<C>, PoolError> {
+        let name = &self.inner.name;
+        let timeout = self.inner.options.acquire_timeout;
+        let start = Instant::now();
+        let permit = tokio::time::timeout(timeout, self.inner.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| {
+                STATS::acquire_timeouts.add_value(1, (name.clone(),));
+                PoolError::AcquireTimeout {
+                    pool: name.clone(),
+                    timeout,
+                }
+            })?
+            .expect("pool semaphore is never closed");
+        STATS::acquire_wait_us.add_value(start.elapsed().as_micros() as i64, (name.clone(),));
+
+        let connection =
+            match self.inner.take_idle() {
+                Some(connection) => connection,
+                None => {
+                    let connection = self.inner.connector.connect().await.map_err(|source| {
+                        PoolError::Connect {
+                            pool: name.clone(),
+                            source,
+                        }
+                    })?;
+                    STATS::opened.add_value(1, (name.clone(),));
+                    connection
+                }
+            };
+        STATS::acquired.add_value(1, (name.clone(),));
+        self.inner.add_in_use(1);
+        Ok(PooledConnection {
+            connection: Some(connection),
+            pool: self.inner.clone(),
+            state: AtomicU8::new(IDLE),
+            _permit: permit,
+        })
+    }
+}
+
+/// Connection taken from a [Pool], given back to it when dropped
+pub struct PooledConnection<C> {
+    connection: Option<C>,
+    pool: Arc<Inner<C>>,
+    /// Whether a query of [PooledConnection::run] is in progress or failed
+    /// on a connection error
+    state: AtomicU8,
+    // Released after the connection is given back, so that the next caller
+    // of acquire reuses it
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<C> PooledConnection<C> {
+    /// Close the connection instead of giving it back to the pool, e.g.
+    /// because it is broken. The pool opens a new one when needed.
+    pub fn discard(mut self) {
+        if self.connection.take().is_some() {
+            self.pool.add_in_use(-1);
+        }
+    }
+
+    /// Run `query` on the connection. If the returned future is dropped
+    /// before `query` completes, or `query` fails with an error that
+    /// [crate::failover::is_connection_error], the connection is closed when
+    /// dropped instead of being given back.
+    pub async fn run<T, E>(&self, query: impl Future<Output = Result<T, E>>) -> Result<T, Error>
+    where
+        E: Into<Error>,
+    {
+        self.start();
+        let result = query.await.map_err(E::into);
+        self.finish(&result);
+        result
+    }
+
+    /// Mark the connection as running queries until [PooledConnection::finish]
+    /// is called, as [PooledConnection::run] does for a single query, e.g.
+    /// for the span of a transaction
+    pub fn start(&self) {
+        self.state.store(RUNNING, Ordering::Relaxed);
+    }
+
+    /// Mark the queries since [PooledConnection::start] as completed with
+    /// `result`, after which the connection is given back when dropped
+    /// unless `result` is a connection error
+    pub fn finish<T>(&self, result: &Result<T, Error>) {
+        let state = match result {
+            Err(err) if is_connection_error(err) => BROKEN,
+            _ => IDLE,
+        };
+        self.state.store(state, Ordering::Relaxed);
+    }
+}
+
+impl<C: Send + 'static> PooledConnection<C> {
+    /// Stream the rows of `rows`, a query on the connection, which is held
+    /// until the stream ends or is dropped. As with [PooledConnection::run],
+    /// the connection is closed instead of being given back if the stream is
+    /// dropped before it ends or a row fails with a connection error.
+    pub fn run_stream<T, E>(
+        self,
+        rows: impl Stream<Item = Result<T, E>> + Send + 'static,
+    ) -> BoxStream<'static, Result<T, Error>>
+    where
+        T: Send + 'static,
+        E: Into<Error> + 'static,
+    {
+        self.state.store(RUNNING, Ordering::Relaxed);
+        stream::unfold(Some((self, rows.boxed())), |state| async move {
+            let (conn, mut rows) = state?;
+            match rows.next().await {
+                Some(row) => {
+                    let row = row.map_err(E::into);
+                    if matches!(&row, Err(err) if is_connection_error(err)) {
+                        conn.state.store(BROKEN, Ordering::Relaxed);
+                    }
+                    Some((row, Some((conn, rows))))
+                }
+                None => {
+                    let _ = conn.state.compare_exchange(
+                        RUNNING,
+                        IDLE,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    );
+                    None
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+impl<C> Deref for PooledConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.connection
+            .as_ref()
+            .expect("connection is only taken on drop")
+    }
+}
+
+impl<C> DerefMut for PooledConnection<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.connection
+            .as_mut()
+            .expect("connection is only taken on drop")
+    }
+}
+
+impl<C> Drop for PooledConnection<C> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let name = &self.pool.name;
+            match *self.state.get_mut() {
+                IDLE => self.pool.release(connection),
+                state => {
+                    if state == RUNNING {
+                        STATS::closed_cancelled.add_value(1, (name.clone(),));
+                    } else {
+                        STATS::closed_broken.add_value(1, (name.clone(),));
+                    }
+                    self.pool.add_in_use(-1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counting_pool(
+        fb: FacebookInit,
+        name: &str,
+        options: PoolOptions,
+    ) -> (Pool<usize>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::new(fb, name, options, {
+            let opened = opened.clone();
+            move || {
+                let id = opened.fetch_add(1, Ordering::Relaxed);
+                async move { Ok(id) }
+            }
+        });
+        (pool, opened)
+    }
+
+    #[fbinit::test]
+    async fn test_reuse(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_reuse", PoolOptions::default());
+        let first = pool.acquire().await?;
+        let second = pool.acquire().await?;
+        assert_eq!((*first, *second), (0, 1));
+        assert_eq!(pool.in_use(), 2);
+        drop(second);
+        assert_eq!((pool.in_use(), pool.idle()), (1, 1));
+        // Outside of fbcode the counters are recorded in memory
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert_eq!(stats["sql.pool.test_reuse.in_use"], 1);
+            assert_eq!(stats["sql.pool.test_reuse.idle"], 1);
+        }
+
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        #[cfg(not(fbcode_build))]
+        {
+            let stats = stats::snapshot();
+            assert_eq!(stats["sql.pool.test_reuse.in_use"], 1);
+            assert_eq!(stats["sql.pool.test_reuse.idle"], 1);
+        }
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_discard(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_discard", PoolOptions::default());
+        pool.acquire().await?.discard();
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_acquire_timeout(fb: FacebookInit) -> Result<(), Error> {
+        let options = PoolOptions {
+            max_connections: 1,
+            acquire_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let (pool, _) = counting_pool(fb, "test_acquire_timeout", options);
+        let conn = pool.acquire().await?;
+        match pool.acquire().await {
+            Err(PoolError::AcquireTimeout { .. }) => {}
+            _ => panic!("expected an acquire timeout"),
+        }
+
+        // A released connection is handed to the waiting caller
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.map(|conn| *conn) }
+        });
+        drop(conn);
+        assert_eq!(waiting.await??, 0);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_idle_timeout(fb: FacebookInit) -> Result<(), Error> {
+        let options = PoolOptions {
+            idle_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let (pool, opened) = counting_pool(fb, "test_idle_timeout", options);
+        drop(pool.acquire().await?);
+        assert_eq!(pool.idle(), 1);
+        // The idle connection timed out, so a new one is opened
+        let conn = pool.acquire().await?;
+        assert_eq!(*conn, 1);
+        assert_eq!((pool.idle(), opened.load(Ordering::Relaxed)), (0, 2));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_close_cancelled(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_close_cancelled", PoolOptions::default());
+        let conn = pool.acquire().await?;
+        assert_eq!(conn.run(async { Ok::<_, Error>(*conn) }).await?, 0);
+        drop(conn);
+        assert_eq!(pool.idle(), 1);
+
+        let conn = pool.acquire().await?;
+        let query = conn.run(futures::future::pending::<Result<(), Error>>());
+        assert!(tokio::time::timeout(Duration::from_millis(1), query)
+            .await
+            .is_err());
+        drop(conn);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_start_finish(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_start_finish", PoolOptions::default());
+        let conn = pool.acquire().await?;
+        conn.start();
+        conn.finish(&Ok(()));
+        drop(conn);
+        assert_eq!(pool.idle(), 1);
+
+        // Dropped before finishing, e.g. a transaction that wasn't committed
+        let conn = pool.acquire().await?;
+        conn.start();
+        drop(conn);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_close_broken(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_close_broken", PoolOptions::default());
+        // A query error leaves the connection usable
+        let conn = pool.acquire().await?;
+        let result = conn.run(async { Err::<(), _>(anyhow::anyhow!("syntax error")) });
+        assert!(result.await.is_err());
+        drop(conn);
+        assert_eq!(pool.idle(), 1);
+
+        let conn = pool.acquire().await?;
+        let result = conn.run(async {
+            Err::<(), _>(anyhow::anyhow!(
+                "Lost connection to MySQL server during query"
+            ))
+        });
+        assert!(result.await.is_err());
+        drop(conn);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_run_stream(fb: FacebookInit) -> Result<(), Error> {
+        let (pool, opened) = counting_pool(fb, "test_run_stream", PoolOptions::default());
+        let rows = stream::iter(vec![Ok::<_, Error>(1), Ok(2)]);
+        let rows: Vec<_> = pool.acquire().await?.run_stream(rows).collect().await;
+        assert_eq!(rows.len(), 2);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 1));
+
+        // A stream dropped before its end closes the connection
+        let rows = stream::iter(vec![Ok::<_, Error>(1), Ok(2)]);
+        let mut rows = pool.acquire().await?.run_stream(rows);
+        assert_eq!(rows.next().await.transpose()?, Some(1));
+        assert_eq!(pool.in_use(), 1);
+        drop(rows);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+        assert_eq!(*pool.acquire().await?, 1);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+}
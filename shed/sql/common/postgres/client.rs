@@ -0,0 +1,550 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Postgres client backed by `tokio-postgres`.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Error};
+use mysql_async::Value;
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls};
+
+use super::values::{Column, Param};
+use super::Row;
+use crate::WriteResult;
+
+/// Savepoint guarding the lookup of the last insert id in a transaction,
+/// which fails when the session generated no id yet
+const LASTVAL_SAVEPOINT: &str = "sql_common_lastval";
+
+/// Connection to a Postgres database. A transaction holds the connection
+/// until it is committed or rolled back, so the other queries wait for it to
+/// complete, as for Sqlite.
+#[derive(Clone)]
+pub struct Connection {
+    session: Arc<Mutex<Session>>,
+}
+
+/// Client of a connection, and whether it is in a transaction that was
+/// dropped without being rolled back yet
+struct Session {
+    client: Client,
+    in_transaction: bool,
+}
+
+impl Session {
+    /// Roll back the transaction dropped while using the connection, if the
+    /// background rollback didn't already
+    async fn end_dropped_transaction(&mut self) -> Result<(), Error> {
+        if self.in_transaction {
+            self.client.batch_execute("ROLLBACK").await?;
+            self.in_transaction = false;
+        }
+        Ok(())
+    }
+}
+
+impl Connection {
+    /// Wrap a connected client. Its connection must be polled separately, see
+    /// [tokio_postgres::connect].
+    pub fn new(client: Client) -> Self {
+        Self {
+            session: Arc::new(Mutex::new(Session {
+                client,
+                in_transaction: false,
+            })),
+        }
+    }
+
+    /// Connect without TLS to the database described by `config`, e.g.
+    /// `host=localhost user=postgres dbname=test`. The connection is driven
+    /// by a task spawned on the current tokio runtime.
+    pub async fn connect(config: &str) -> Result<Self, Error> {
+        let (client, connection) = tokio_postgres::connect(config, NoTls)
+            .await
+            .context("failed to connect to postgres")?;
+        tokio::spawn(async move {
+            // The error is returned by the queries of the client as well
+            let _ = connection.await;
+        });
+        Ok(Self::new(client))
+    }
+
+    async fn session(&self) -> Result<MutexGuard<'_, Session>, Error> {
+        let mut session = self.session.lock().await;
+        session.end_dropped_transaction().await?;
+        Ok(session)
+    }
+
+    /// Performs a given query, binding `params` to its `$n` placeholders in
+    /// order, and returns the rows.
+    pub async fn read_query(&self, query: String, params: Vec<Value>) -> Result<Vec<Row>, Error> {
+        read_query(&self.session().await?.client, &query, params).await
+    }
+
+    /// Performs a given query, binding `params` to its `$n` placeholders in
+    /// order, and returns the write result.
+    pub async fn write_query(
+        &self,
+        query: String,
+        params: Vec<Value>,
+    ) -> Result<WriteResult, Error> {
+        write_query(&self.session().await?.client, &query, params, false).await
+    }
+
+    /// Begins trasaction and returns Transaction object.
+    pub async fn begin_transaction(&self) -> Result<Transaction, Error> {
+        let mut session = self.session.clone().lock_owned().await;
+        session.end_dropped_transaction().await?;
+        session.client.batch_execute("BEGIN").await?;
+        session.in_transaction = true;
+        Ok(Transaction {
+            session: Some(session),
+        })
+    }
+}
+
+/// Transaction object. If it is dropped without being committed it is rolled
+/// back in the background when dropped within a tokio runtime, else before
+/// the next query of the connection.
+pub struct Transaction {
+    session: Option<OwnedMutexGuard<Session>>,
+}
+
+impl Transaction {
+    fn client(&self) -> &Client {
+        &self
+            .session
+            .as_ref()
+            .expect("client should be Some before transaction ended")
+            .client
+    }
+
+    /// Performs a given query, binding `params` to its `$n` placeholders in
+    /// order, and returns the rows.
+    pub async fn read_query(
+        &mut self,
+        query: String,
+        params: Vec<Value>,
+    ) -> Result<Vec<Row>, Error> {
+        read_query(self.client(), &query, params).await
+    }
+
+    /// Performs a given query, binding `params` to its `$n` placeholders in
+    /// order, and returns the write result.
+    pub async fn write_query(
+        &mut self,
+        query: String,
+        params: Vec<Value>,
+    ) -> Result<WriteResult, Error> {
+        write_query(self.client(), &query, params, true).await
+    }
+
+    /// Commit transaction.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.end("COMMIT").await
+    }
+
+    /// Rollback transaction.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        self.end("ROLLBACK").await
+    }
+
+    async fn end(&mut self, statement: &str) -> Result<(), Error> {
+        let mut session = self.session.take().expect("transaction already ended");
+        // If this fails the transaction is rolled back before the next query
+        session.client.batch_execute(statement).await?;
+        session.in_transaction = false;
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            // Without a runtime the session is left in the transaction, for
+            // its next user to roll back
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move {
+                    let _ = session.end_dropped_transaction().await;
+                });
+            }
+        }
+    }
+}
+
+fn params(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param as &(dyn ToSql + Sync))
+        .collect()
+}
+
+async fn read_query(client: &Client, query: &str, values: Vec<Value>) -> Result<Vec<Row>, Error> {
+    let values: Vec<_> = values.into_iter().map(Param).collect();
+    let rows = client.query(query, &params(&values)).await?;
+    rows.iter()
+        .map(|row| {
+            (0..row.len())
+                .map(|idx| Ok(row.try_get::<_, Column>(idx)?.0))
+                .collect()
+        })
+        .collect()
+}
+
+async fn write_query(
+    client: &Client,
+    query: &str,
+    values: Vec<Value>,
+    in_transaction: bool,
+) -> Result<WriteResult, Error> {
+    let values: Vec<_> = values.into_iter().map(Param).collect();
+    let affected_rows = client.execute(query, &params(&values)).await?;
+    let last_insert_id = if affected_rows > 0 && is_insert(query) {
+        last_insert_id(client, in_transaction).await?
+    } else {
+        None
+    };
+    Ok(WriteResult::new(last_insert_id, affected_rows).with_rows_matched())
+}
+
+/// The last value generated by a sequence in the session, which is the id
+/// of the last inserted row when its table has a serial or identity key.
+/// None if the session generated no value yet.
+async fn last_insert_id(client: &Client, in_transaction: bool) -> Result<Option<u64>, Error> {
+    // A failed query aborts the transaction, unless rolled back to a savepoint
+    if in_transaction {
+        client
+            .batch_execute(&format!("SAVEPOINT {}", LASTVAL_SAVEPOINT))
+            .await?;
+    }
+    let id = match client.query_one("SELECT lastval()", &[]).await {
+        Ok(row) => {
+            let id: i64 = row.try_get(0)?;
+            Some(id as u64)
+        }
+        Err(err) if err.as_db_error().is_some() => None,
+        Err(err) => return Err(err.into()),
+    };
+    if in_transaction {
+        if id.is_none() {
+            client
+                .batch_execute(&format!("ROLLBACK TO SAVEPOINT {}", LASTVAL_SAVEPOINT))
+                .await?;
+        }
+        client
+            .batch_execute(&format!("RELEASE SAVEPOINT {}", LASTVAL_SAVEPOINT))
+            .await?;
+    }
+    Ok(id)
+}
+
+/// Whether the query is an INSERT, after the comments tagging it
+fn is_insert(query: &str) -> bool {
+    let mut query = query.trim_start();
+    loop {
+        if let Some(rest) = query.strip_prefix("/*") {
+            match rest.find("*/") {
+                Some(end) => query = rest[end + 2..].trim_start(),
+                None => return false,
+            }
+        } else if let Some(rest) = query.strip_prefix("--") {
+            query = rest
+                .split_once('\n')
+                .map_or("", |(_, rest)| rest)
+                .trim_start();
+        } else {
+            return query
+                .get(..6)
+                .is_some_and(|start| start.eq_ignore_ascii_case("INSERT"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use sql::queries;
+    use sql_tests_lib::{
+        test_bulk_insert, test_cas_write, test_cte_and_union, test_empty_list, test_json,
+        test_named_params, test_nullable_columns, test_query_observer, test_read_query,
+        test_read_stream_query, test_readonly, test_rows_matched, test_sql_values,
+        test_transaction_savepoint, TestSemantics,
+    };
+
+    use crate::sharded_transaction::{ShardedTransaction, ShardedTransactionError};
+
+    queries! {
+        write InsertValues(name: String, data: Vec<u8>, flag: bool) {
+            none,
+            "INSERT INTO bar (name, data, flag) VALUES ({name}, {data}, {flag})"
+        }
+        read SelectValues(name: String) -> (u64, String, Vec<u8>, bool) {
+            "SELECT id, name, data, flag FROM bar WHERE name = {name}"
+        }
+        write InsertFoo(values: (x: i64)) {
+            none,
+            "INSERT INTO foo (x) VALUES {values}"
+        }
+        read SelectFoo() -> (i64) {
+            "SELECT x FROM foo ORDER BY id"
+        }
+    }
+
+    /// Connection to a new schema of the database at `SQL_TEST_POSTGRES_URL`
+    /// with the tables of the tests, or None to skip the test without it
+    async fn connection(schema: &str) -> Option<sql::Connection> {
+        let url = std::env::var("SQL_TEST_POSTGRES_URL").ok()?;
+        let conn = sql::postgres::Connection::connect(&url).await.unwrap();
+        let schema = format!("sql_test_{}", schema);
+        for statement in [
+            format!("DROP SCHEMA IF EXISTS {} CASCADE", schema),
+            format!("CREATE SCHEMA {}", schema),
+            format!("SET search_path TO {}", schema),
+            "CREATE TABLE foo (
+                x BIGINT,
+                id BIGSERIAL PRIMARY KEY,
+                y TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                test TEXT
+            )"
+            .to_owned(),
+            "CREATE TABLE bar (id BIGSERIAL PRIMARY KEY, name TEXT, data BYTEA, flag BOOLEAN)"
+                .to_owned(),
+        ] {
+            conn.write_query(statement, Vec::new()).await.unwrap();
+        }
+        Some(conn.into())
+    }
+
+    /// Another session of the database at `SQL_TEST_POSTGRES_URL`, on the
+    /// schema of `connection(schema)`
+    async fn session(schema: &str) -> Connection {
+        let url = std::env::var("SQL_TEST_POSTGRES_URL").unwrap();
+        let conn = Connection::connect(&url).await.unwrap();
+        conn.write_query(
+            format!("SET search_path TO sql_test_{}", schema),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        conn
+    }
+
+    /// Sharded connections whose shards are each a session of their own on
+    /// the schema of `connection(schema)`
+    async fn sharded(schema: &str, shards: usize) -> crate::SqlShardedConnections {
+        let mut connections = Vec::new();
+        for _ in 0..shards {
+            connections.push(crate::SqlConnections::new_single(
+                session(schema).await.into(),
+            ));
+        }
+        connections.into()
+    }
+
+    async fn execute_on_shard(
+        transaction: &mut ShardedTransaction<'_>,
+        shard: usize,
+        statement: &'static str,
+    ) -> Result<(), Error> {
+        transaction
+            .run(shard, |mut tr| async move {
+                tr.execute(statement.to_owned()).await?;
+                Ok((tr, ()))
+            })
+            .await
+    }
+
+    macro_rules! postgres_tests {
+        ($( $name:ident => $test:expr, )*) => {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    if let Some(conn) = connection(stringify!($name)).await {
+                        #[allow(clippy::redundant_closure_call)]
+                        ($test)(conn).await;
+                    }
+                }
+            )*
+        };
+    }
+
+    // The queries shared with the other databases run unchanged
+    postgres_tests! {
+        test_read_query_postgres => |conn| test_read_query(conn, TestSemantics::Postgres),
+        test_read_stream_query_postgres => test_read_stream_query,
+        test_empty_list_postgres => test_empty_list,
+        test_named_params_postgres => test_named_params,
+        test_nullable_columns_postgres => |conn| test_nullable_columns(conn, TestSemantics::Postgres),
+        test_sql_values_postgres => test_sql_values,
+        test_cas_write_postgres => test_cas_write,
+        test_cte_and_union_postgres => test_cte_and_union,
+        test_bulk_insert_postgres => test_bulk_insert,
+        test_transaction_savepoint_postgres => test_transaction_savepoint,
+        test_query_observer_postgres => test_query_observer,
+        test_readonly_postgres => test_readonly,
+        test_json_postgres => test_json,
+        test_rows_matched_postgres => |conn| test_rows_matched(conn, TestSemantics::Postgres),
+    }
+
+    #[test]
+    fn test_is_insert() {
+        assert!(is_insert("INSERT INTO foo (x) VALUES ($1)"));
+        assert!(is_insert(
+            "/* client:foo */ -- bar\n insert INTO foo (x) VALUES ($1)"
+        ));
+        assert!(!is_insert("UPDATE foo SET x = 1"));
+        assert!(!is_insert("/* INSERT */ DELETE FROM foo"));
+        assert!(!is_insert("/* INSERT"));
+    }
+
+    #[tokio::test]
+    async fn test_typed_values() {
+        let conn = match connection("typed_values").await {
+            Some(conn) => conn,
+            None => return,
+        };
+        // Strings and bytes are both bytes, stored as text or bytea as the
+        // columns expect, without escaping their backslashes
+        let name = "it's a \\x41".to_owned();
+        let data = b"\\x41\0\xff".to_vec();
+        let res = InsertValues::query(&conn, &name, &data, &true)
+            .await
+            .unwrap();
+        assert_eq!(res.affected_rows(), 1);
+        assert_eq!(res.last_insert_id(), Some(1));
+        let res = InsertValues::query(&conn, &"other".to_owned(), &Vec::new(), &false)
+            .await
+            .unwrap();
+        assert_eq!(res.last_insert_id(), Some(2));
+
+        let rows = SelectValues::query(&conn, &name).await.unwrap();
+        assert_eq!(rows, vec![(1, name, data, true)]);
+        let rows = SelectValues::query(&conn, &"other".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(2, "other".to_owned(), Vec::new(), false)]);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_rollback_on_drop() {
+        let conn = match connection("rollback_on_drop").await {
+            Some(conn) => conn,
+            None => return,
+        };
+        let transaction = conn.start_transaction().await.unwrap();
+        let (transaction, res) = InsertFoo::query_with_transaction(transaction, &[(&1,), (&2,)])
+            .await
+            .unwrap();
+        assert_eq!(res.last_insert_id(), Some(2));
+        // Dropped without a runtime, the transaction is rolled back before
+        // the next query
+        std::thread::spawn(move || drop(transaction))
+            .join()
+            .unwrap();
+        assert_eq!(SelectFoo::query(&conn).await.unwrap(), vec![]);
+
+        // Dropped within the runtime, it is rolled back in the background
+        let transaction = conn.start_transaction().await.unwrap();
+        let (transaction, _) = InsertFoo::query_with_transaction(transaction, &[(&3,)])
+            .await
+            .unwrap();
+        drop(transaction);
+        assert_eq!(SelectFoo::query(&conn).await.unwrap(), vec![]);
+
+        let transaction = conn.start_transaction().await.unwrap();
+        let (transaction, _) = InsertFoo::query_with_transaction(transaction, &[(&4,)])
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(SelectFoo::query(&conn).await.unwrap(), vec![(4,)]);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_commit_failing_on_second_shard() {
+        let schema = "sharded_commit_failing";
+        let conn = match connection(schema).await {
+            Some(_) => session(schema).await,
+            None => return,
+        };
+        conn.write_query(
+            "CREATE TABLE baz (x BIGINT UNIQUE DEFERRABLE INITIALLY DEFERRED)".to_owned(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        let sharded = sharded(schema, 3).await;
+
+        // The duplicate row of shard 1 only fails once it commits, after
+        // shard 0 committed
+        let mut transaction = sharded.start_transaction();
+        for (shard, statement) in [
+            (0, "INSERT INTO foo (x) VALUES (1)"),
+            (1, "INSERT INTO baz (x) VALUES (1), (1)"),
+            (2, "INSERT INTO foo (x) VALUES (2)"),
+        ] {
+            execute_on_shard(&mut transaction, shard, statement)
+                .await
+                .unwrap();
+        }
+        match transaction.commit().await {
+            Err(ShardedTransactionError::PartialCommit {
+                committed,
+                failed,
+                rolled_back,
+                ..
+            }) => assert_eq!((committed, failed, rolled_back), (vec![0], 1, vec![2])),
+            res => panic!("unexpected result {:?}", res),
+        }
+        let rows = conn
+            .read_query("SELECT x FROM foo".to_owned(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_commit_losing_second_shard() {
+        let schema = "sharded_commit_losing";
+        let conn = match connection(schema).await {
+            Some(_) => session(schema).await,
+            None => return,
+        };
+        let sharded = sharded(schema, 2).await;
+
+        let mut transaction = sharded.start_transaction();
+        execute_on_shard(&mut transaction, 0, "INSERT INTO foo (x) VALUES (1)")
+            .await
+            .unwrap();
+        let pid = transaction
+            .run(1, |mut tr| async move {
+                let pid = tr.read_u64("SELECT pg_backend_pid()").await?;
+                Ok((tr, pid))
+            })
+            .await
+            .unwrap();
+        // Shard 1 loses its connection before the commit, so its ping fails
+        // and no shard is committed
+        conn.read_query(format!("SELECT pg_terminate_backend({})", pid), Vec::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            transaction.commit().await,
+            Err(ShardedTransactionError::RolledBack { shard: 1, .. })
+        ));
+        let rows = conn
+            .read_query("SELECT x FROM foo".to_owned(), Vec::new())
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+}
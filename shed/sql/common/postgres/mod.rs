@@ -0,0 +1,40 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module provides Postgres connections backed by `tokio-postgres`, available
+//! with the `postgres` feature.
+//!
+//! Queries are sent with `$n` placeholders for their values, which are
+//! converted to the types the server expects for them, so that e.g. the bytes
+//! of a string are stored as `text` or `bytea` depending on the column. The
+//! values of the rows are converted back to [mysql_async::Value], see [Row],
+//! and parsed with [mysql_async::prelude::FromValue] like Mysql results.
+//!
+//! The writes report the last value generated by a sequence in the session
+//! as their last insert id, that of the last inserted row when its table has
+//! a serial or identity key.
+
+#[cfg(feature = "postgres")]
+mod client;
+#[cfg(not(feature = "postgres"))]
+mod postgres_stub;
+#[cfg(feature = "postgres")]
+mod values;
+
+#[cfg(feature = "postgres")]
+pub use client::{Connection, Transaction};
+#[cfg(not(feature = "postgres"))]
+pub use postgres_stub::{Connection, Transaction};
+
+/// A row returned by a Postgres query, with NULL values as
+/// [mysql_async::Value::NULL], `bool` as 0 or 1, the integers and floats as
+/// such, the timestamps and dates as [mysql_async::Value::Date], and the text,
+/// `bytea` and `json` values as [mysql_async::Value::Bytes]. Columns of other
+/// types fail the query, unless cast to one of those in the query.
+pub type Row = Vec<mysql_async::Value>;
@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Postgres client stub, used without the `postgres` feature.
+
+use anyhow::Error;
+use mysql_async::Value;
+
+use super::Row;
+use crate::WriteResult;
+
+/// Connection object.
+#[derive(Clone)]
+pub struct Connection;
+
+impl Connection {
+    /// Performs a given query and returns the rows.
+    pub async fn read_query(&self, _query: String, _params: Vec<Value>) -> Result<Vec<Row>, Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+
+    /// Performs a given query and returns the write result.
+    pub async fn write_query(
+        &self,
+        _query: String,
+        _params: Vec<Value>,
+    ) -> Result<WriteResult, Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+
+    /// Begins trasaction and returns Transaction object.
+    pub async fn begin_transaction(&self) -> Result<Transaction, Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+}
+
+/// Transaction object.
+pub struct Transaction;
+
+impl Transaction {
+    /// Performs a given query and returns the rows.
+    pub async fn read_query(
+        &mut self,
+        _query: String,
+        _params: Vec<Value>,
+    ) -> Result<Vec<Row>, Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+
+    /// Performs a given query and returns the write result.
+    pub async fn write_query(
+        &mut self,
+        _query: String,
+        _params: Vec<Value>,
+    ) -> Result<WriteResult, Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+
+    /// Commit transaction.
+    pub async fn commit(self) -> Result<(), Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+
+    /// Rollback transaction.
+    pub async fn rollback(self) -> Result<(), Error> {
+        unimplemented!("This is a stub, enable the postgres feature");
+    }
+}
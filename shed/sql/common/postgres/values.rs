@@ -0,0 +1,259 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Conversions between [mysql_async::Value] and the binary format of the
+//! Postgres types.
+
+use std::error::Error as StdError;
+
+use bytes::{BufMut, BytesMut};
+use mysql_async::prelude::FromValue;
+use mysql_async::Value;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+type BoxError = Box<dyn StdError + Sync + Send>;
+
+/// Days from 1970-01-01 to 2000-01-01, the epoch of the Postgres dates
+const POSTGRES_EPOCH_DAYS: i64 = 10_957;
+const MICROS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000;
+
+/// Version of the binary format of `jsonb`
+const JSONB_VERSION: u8 = 1;
+
+/// Value bound to a query parameter, converted to the type the server
+/// inferred for the parameter from where it is used
+#[derive(Debug)]
+pub(super) struct Param(pub(super) Value);
+
+impl ToSql for Param {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let value = &self.0;
+        if let Value::NULL = value {
+            return Ok(IsNull::Yes);
+        }
+        match *ty {
+            Type::BOOL => convert::<bool>(value, ty)?.to_sql(ty, out),
+            Type::CHAR => convert::<i8>(value, ty)?.to_sql(ty, out),
+            Type::INT2 => convert::<i16>(value, ty)?.to_sql(ty, out),
+            Type::INT4 => convert::<i32>(value, ty)?.to_sql(ty, out),
+            Type::INT8 => convert::<i64>(value, ty)?.to_sql(ty, out),
+            Type::OID => convert::<u32>(value, ty)?.to_sql(ty, out),
+            Type::FLOAT4 => convert::<f32>(value, ty)?.to_sql(ty, out),
+            Type::FLOAT8 => convert::<f64>(value, ty)?.to_sql(ty, out),
+            Type::BYTEA => convert::<Vec<u8>>(value, ty)?.to_sql(ty, out),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                text(value, ty)?.to_sql(ty, out)
+            }
+            Type::JSON => {
+                out.put_slice(text(value, ty)?.as_bytes());
+                Ok(IsNull::No)
+            }
+            Type::JSONB => {
+                out.put_u8(JSONB_VERSION);
+                out.put_slice(text(value, ty)?.as_bytes());
+                Ok(IsNull::No)
+            }
+            Type::TIMESTAMP | Type::TIMESTAMPTZ => {
+                out.put_i64(timestamp_micros(value, ty)?);
+                Ok(IsNull::No)
+            }
+            Type::DATE => {
+                let days = timestamp_micros(value, ty)?.div_euclid(MICROS_PER_DAY);
+                out.put_i32(i32::try_from(days)?);
+                Ok(IsNull::No)
+            }
+            _ => Err(format!(
+                "unsupported Postgres parameter type {}, cast the parameter in the query",
+                ty
+            )
+            .into()),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+/// Value of a column of a result row: `bool` as 0 or 1, the integers and
+/// floats as such, the timestamps and dates as [Value::Date], and the types
+/// in text form, `bytea` and `json` as [Value::Bytes]
+pub(super) struct Column(pub(super) Value);
+
+impl<'a> FromSql<'a> for Column {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let value = match *ty {
+            Type::BOOL => Value::Int(bool::from_sql(ty, raw)?.into()),
+            Type::CHAR => Value::Int(i8::from_sql(ty, raw)?.into()),
+            Type::INT2 => Value::Int(i16::from_sql(ty, raw)?.into()),
+            Type::INT4 => Value::Int(i32::from_sql(ty, raw)?.into()),
+            Type::INT8 => Value::Int(i64::from_sql(ty, raw)?),
+            Type::OID => Value::UInt(u32::from_sql(ty, raw)?.into()),
+            Type::FLOAT4 => Value::Float(f32::from_sql(ty, raw)?),
+            Type::FLOAT8 => Value::Double(f64::from_sql(ty, raw)?),
+            Type::BYTEA
+            | Type::TEXT
+            | Type::VARCHAR
+            | Type::BPCHAR
+            | Type::NAME
+            | Type::UNKNOWN
+            | Type::JSON => Value::Bytes(raw.to_vec()),
+            Type::JSONB => match raw.split_first() {
+                Some((&JSONB_VERSION, json)) => Value::Bytes(json.to_vec()),
+                _ => return Err("unsupported jsonb version".into()),
+            },
+            Type::TIMESTAMP | Type::TIMESTAMPTZ => date(i64::from_sql(ty, raw)?)?,
+            Type::DATE => date(i64::from(i32::from_sql(ty, raw)?) * MICROS_PER_DAY)?,
+            _ => {
+                return Err(format!(
+                    "unsupported Postgres column type {}, cast the column in the query",
+                    ty
+                )
+                .into());
+            }
+        };
+        Ok(Column(value))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, BoxError> {
+        Ok(Column(Value::NULL))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+fn convert<T: FromValue>(value: &Value, ty: &Type) -> Result<T, BoxError> {
+    T::from_value_opt(value.clone()).map_err(|_| mismatch(value, ty))
+}
+
+fn mismatch(value: &Value, ty: &Type) -> BoxError {
+    format!(
+        "cannot bind {:?} to a Postgres parameter of type {}",
+        value, ty
+    )
+    .into()
+}
+
+/// Text of a value bound to a text parameter, e.g. a number compared with a
+/// text column
+fn text(value: &Value, ty: &Type) -> Result<String, BoxError> {
+    match value {
+        Value::Bytes(bytes) => String::from_utf8(bytes.clone()).map_err(|_| mismatch(value, ty)),
+        Value::Int(int) => Ok(int.to_string()),
+        Value::UInt(uint) => Ok(uint.to_string()),
+        Value::Float(float) => Ok(float.to_string()),
+        Value::Double(double) => Ok(double.to_string()),
+        Value::NULL | Value::Date(..) | Value::Time(..) => Err(mismatch(value, ty)),
+    }
+}
+
+/// Microseconds from the Postgres epoch to a [Value::Date]
+fn timestamp_micros(value: &Value, ty: &Type) -> Result<i64, BoxError> {
+    match *value {
+        Value::Date(year, month, day, hour, minute, second, micros) => {
+            let days = days_from_civil(year.into(), month.into(), day.into());
+            let seconds = i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+            Ok((days - POSTGRES_EPOCH_DAYS) * MICROS_PER_DAY
+                + seconds * 1_000_000
+                + i64::from(micros))
+        }
+        _ => Err(mismatch(value, ty)),
+    }
+}
+
+/// [Value::Date] of the microseconds from the Postgres epoch
+fn date(micros: i64) -> Result<Value, BoxError> {
+    let days = micros.div_euclid(MICROS_PER_DAY) + POSTGRES_EPOCH_DAYS;
+    let micros = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = micros / 1_000_000;
+    Ok(Value::Date(
+        u16::try_from(year).map_err(|_| "date out of range")?,
+        month as u8,
+        day as u8,
+        (seconds / 3600) as u8,
+        (seconds / 60 % 60) as u8,
+        (seconds % 60) as u8,
+        (micros % 1_000_000) as u32,
+    ))
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar, see
+/// <http://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date of the proleptic Gregorian calendar the days from 1970-01-01 fall on
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        let value = Value::Date(2021, 1, 21, 21, 21, 21, 5);
+        let micros = timestamp_micros(&value, &Type::TIMESTAMP).unwrap();
+        assert_eq!(micros, 664_579_281_000_005);
+        assert_eq!(date(micros).unwrap(), value);
+
+        let value = Value::Date(1999, 12, 31, 23, 59, 59, 0);
+        let micros = timestamp_micros(&value, &Type::TIMESTAMP).unwrap();
+        assert_eq!(micros, -1_000_000);
+        assert_eq!(date(micros).unwrap(), value);
+        assert_eq!(date(0).unwrap(), Value::Date(2000, 1, 1, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_params() {
+        let to_sql = |value: Value, ty: Type| {
+            let mut out = BytesMut::new();
+            Param(value).to_sql(&ty, &mut out).map(|_| out.to_vec())
+        };
+        // The same bytes are text or bytea depending on the parameter
+        assert_eq!(to_sql(Value::from("hi"), Type::TEXT).unwrap(), b"hi");
+        assert_eq!(to_sql(Value::from("hi"), Type::BYTEA).unwrap(), b"hi");
+        assert!(to_sql(Value::from(vec![0xff]), Type::TEXT).is_err());
+        assert_eq!(to_sql(Value::from(42u64), Type::TEXT).unwrap(), b"42");
+        assert_eq!(to_sql(Value::from(1), Type::BOOL).unwrap(), [1]);
+        assert_eq!(
+            to_sql(Value::from(42u64), Type::INT4).unwrap(),
+            42i32.to_be_bytes()
+        );
+        assert!(to_sql(Value::from(u64::MAX), Type::INT8).is_err());
+        assert_eq!(to_sql(Value::from("{}"), Type::JSONB).unwrap(), b"\x01{}");
+        assert!(to_sql(Value::from(1), Type::NUMERIC).is_err());
+    }
+}
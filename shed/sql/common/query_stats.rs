@@ -0,0 +1,41 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module recording the stats of the queries of `queries!`. Each query
+//! exports `sql.query.<path>.{calls,errors,rows,latency_us}`, where the path
+//! is the module path of the query, e.g. `my_crate::queries::SelectFoo`, and
+//! the rows are the rows read by read queries and affected by write queries,
+//! whether they ran in a transaction or not.
+
+use std::time::Duration;
+
+use anyhow::Error;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "sql.query";
+    calls: dynamic_timeseries("{}.calls", (query: String); Rate, Sum),
+    errors: dynamic_timeseries("{}.errors", (query: String); Rate, Sum),
+    rows: dynamic_timeseries("{}.rows", (query: String); Rate, Sum),
+    latency_us: dynamic_histogram("{}.latency_us", (query: String); 1000, 0, 10_000_000, Average, Count; P 50; P 95; P 99),
+}
+
+/// Record that the query at `path` completed with `result` after `duration`.
+/// This should never be used directly, it is made public so that the
+/// queries! macro can make use of it
+#[doc(hidden)]
+pub fn record(path: &str, duration: Duration, result: Result<u64, &Error>) {
+    let query = (path.to_owned(),);
+    STATS::calls.add_value(1, query.clone());
+    STATS::latency_us.add_value(duration.as_micros() as i64, query.clone());
+    match result {
+        Ok(rows) => STATS::rows.add_value(rows as i64, query),
+        Err(_) => STATS::errors.add_value(1, query),
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing read-only connections, see [Connection::readonly]. They
+//! fail the write queries, the transactions, the migrations and the read
+//! queries whose SQL is not a read, or that lock rows or write a file, before
+//! sending anything to the database.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use crate::{Connection, ConnectionLayer, Unwrap};
+
+/// First keywords of the statements read queries may run on a read-only
+/// connection
+const READ_KEYWORDS: &[&str] = &["SELECT", "SHOW", "EXPLAIN", "DESCRIBE", "DESC"];
+
+/// Keywords starting the statement following the common table expressions
+/// of a `WITH`
+const STATEMENT_KEYWORDS: &[&str] = &["SELECT", "INSERT", "UPDATE", "DELETE", "REPLACE"];
+
+/// Keywords following `FOR` in the locking clauses of a SELECT, e.g.
+/// `FOR UPDATE` or Postgres' `FOR NO KEY UPDATE`
+const LOCKING_KEYWORDS: &[&str] = &["UPDATE", "SHARE", "NO", "KEY"];
+
+/// Connection of [Connection::readonly]
+pub struct ReadOnlyConnection {
+    connection: Connection,
+}
+
+impl ConnectionLayer for ReadOnlyConnection {
+    fn kind(&self) -> &'static str {
+        "Read-only"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection running the read queries of this connection and rejecting
+    /// anything that may write: write queries, transactions, migrations and
+    /// read queries whose SQL doesn't start with `SELECT`, `SHOW`, `EXPLAIN`
+    /// or `DESCRIBE`, possibly after the common table expressions of a
+    /// `WITH`. The reads locking rows, `... FOR UPDATE` or `... LOCK IN SHARE
+    /// MODE`, those storing their result, `SELECT ... INTO`, and those
+    /// followed by another statement after a `;` are rejected too, the
+    /// comments being ignored. Writes that are rejected fail with an error
+    /// saying so, and are never sent to the database.
+    pub fn readonly(self) -> Connection {
+        if self.is_readonly() {
+            return self;
+        }
+        Connection::Layered(Arc::new(ReadOnlyConnection { connection: self }))
+    }
+
+    /// Whether this connection rejects the writes, see [Connection::readonly]
+    pub fn is_readonly(&self) -> bool {
+        self.layer::<ReadOnlyConnection>().is_some()
+    }
+
+    /// Fail if this connection is read-only, before running `what` on it.
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn check_write(&self, what: &str) -> Result<(), Error> {
+        if self.is_readonly() {
+            bail!("{} rejected by a read-only connection", what);
+        }
+        Ok(())
+    }
+
+    /// Fail if this connection is read-only and `sql` is not a read. This
+    /// should never be used directly, it is made public so that the queries!
+    /// macro can make use of it
+    #[doc(hidden)]
+    pub fn check_read(&self, sql: &str) -> Result<(), Error> {
+        if self.is_readonly() && !is_read(sql) {
+            bail!(
+                "query rejected by a read-only connection, it is not a read: {}",
+                sql
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_read(sql: &str) -> bool {
+    let words = match words(sql) {
+        Some(words) => words,
+        None => return false,
+    };
+    let mut keywords = words
+        .iter()
+        .filter(|(depth, _)| *depth == 0)
+        .map(|(_, word)| *word);
+    let mut keyword = keywords.next().unwrap_or_default();
+    if keyword.eq_ignore_ascii_case("WITH") {
+        // The words before the statement are names of expressions and
+        // keywords such as RECURSIVE or AS, the expressions being nested
+        keyword = keywords
+            .find(|word| is_keyword(STATEMENT_KEYWORDS, word))
+            .unwrap_or_default();
+    }
+    is_keyword(READ_KEYWORDS, keyword) && !locks_or_stores(&words)
+}
+
+/// Whether a SELECT of the query, possibly a subquery, locks the rows it
+/// reads or stores its result in a file, variables or a new table
+fn locks_or_stores(words: &[(usize, &str)]) -> bool {
+    let is = |idx: usize, keywords: &[&str]| {
+        words
+            .get(idx)
+            .is_some_and(|(_, word)| is_keyword(keywords, word))
+    };
+    (0..words.len()).any(|idx| {
+        is(idx, &["INTO"])
+            || (is(idx, &["FOR"]) && is(idx + 1, LOCKING_KEYWORDS))
+            || (is(idx, &["LOCK"]) && is(idx + 1, &["IN"]) && is(idx + 2, &["SHARE"]))
+    })
+}
+
+fn is_keyword(keywords: &[&str], word: &str) -> bool {
+    keywords
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+/// The words of `sql` outside of quotes and comments with their depth of
+/// parentheses, the parentheses leading the statement not counting, or None
+/// if a `;` is followed by another statement
+pub(crate) fn words(sql: &str) -> Option<Vec<(usize, &str)>> {
+    let sql = sql.trim_start_matches(|c: char| c.is_whitespace() || c == '(');
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = None;
+    let mut ended = false;
+    let mut words = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let is_word = c.is_ascii_alphanumeric() || c == '_';
+        if let Some(word_start) = start {
+            if !is_word {
+                words.push((depth, &sql[word_start..idx]));
+                start = None;
+            }
+        }
+        let next = chars.peek().map(|(_, next)| *next);
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '-') if next == Some('-') => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            (None, '/') if next == Some('*') => {
+                chars.next();
+                // Mysql runs the content of the `/*! ... */` comments
+                if chars.peek().map(|(_, c)| *c) != Some('!') {
+                    let mut previous = None;
+                    chars.find(|(_, c)| {
+                        let closes = previous == Some('*') && *c == '/';
+                        previous = Some(*c);
+                        closes
+                    });
+                }
+            }
+            (None, c) if ended && !c.is_whitespace() => return None,
+            (None, ';') => ended = true,
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, _) if is_word && start.is_none() => start = Some(idx),
+            (None, _) => {}
+        }
+    }
+    if let Some(word_start) = start {
+        words.push((depth, &sql[word_start..]));
+    }
+    Some(words)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_is_read() {
+        assert!(is_read("SELECT 1"));
+        assert!(is_read("  (select x FROM foo) UNION (SELECT y FROM bar)"));
+        assert!(is_read("WITH t AS (SELECT 1) SELECT * FROM t"));
+        assert!(is_read(
+            "WITH RECURSIVE n(v) AS (SELECT 1 UNION ALL SELECT v + 1 FROM n), \
+             `delete`(id) AS (SELECT id FROM foo WHERE y = 'UPDATE') SELECT v FROM n"
+        ));
+        assert!(!is_read(
+            "WITH t AS (SELECT id FROM foo) DELETE FROM foo WHERE id IN (SELECT id FROM t)"
+        ));
+        assert!(!is_read("WITH t AS (SELECT 1)"));
+        assert!(!is_read("INSERT INTO foo (x) VALUES (1)"));
+        assert!(!is_read("SELECTED"));
+        assert!(!is_read("SELECT x FROM foo WHERE id = 1 FOR UPDATE"));
+        assert!(!is_read("SELECT x FROM foo FOR NO KEY UPDATE SKIP LOCKED"));
+        assert!(!is_read("SELECT x FROM foo LOCK IN SHARE MODE"));
+        assert!(!is_read("SELECT * FROM (SELECT x FROM foo FOR SHARE) t"));
+        assert!(!is_read("SELECT x INTO OUTFILE '/tmp/x' FROM foo"));
+        assert!(!is_read("SELECT x FROM foo INTO @x"));
+        assert!(is_read(
+            "SELECT for_update, 'FOR UPDATE', `into` FROM foo WHERE y = 'INTO'"
+        ));
+        assert!(!is_read(""));
+    }
+
+    #[test]
+    fn test_is_read_several_statements() {
+        assert!(is_read("SELECT 1;"));
+        assert!(is_read("SELECT 1 ;  \n"));
+        assert!(is_read("SELECT 1; -- done"));
+        assert!(is_read("SELECT x FROM foo WHERE y = 'a;b'"));
+        assert!(!is_read("SELECT 1; DELETE FROM foo"));
+        assert!(!is_read("SELECT 1;DELETE FROM foo"));
+        assert!(!is_read("SELECT 1; (DELETE FROM foo)"));
+    }
+
+    #[test]
+    fn test_is_read_comments() {
+        assert!(is_read("/* leading */ SELECT 1"));
+        assert!(is_read("-- leading\nSELECT 1"));
+        assert!(is_read("SELECT x -- FOR UPDATE\nFROM foo"));
+        assert!(is_read(
+            "SELECT x /* INTO @x */ FROM foo /* ; DELETE FROM foo */"
+        ));
+        assert!(!is_read("/* SELECT */ DELETE FROM foo"));
+        assert!(!is_read("-- SELECT\nDELETE FROM foo"));
+        assert!(!is_read("SELECT x FROM foo /*! FOR UPDATE */"));
+        assert!(!is_read("SELECT 1 /* ; */; DELETE FROM foo"));
+    }
+
+    #[tokio::test]
+    async fn test_readonly() {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(!conn.is_readonly());
+        assert!(conn.check_write("write").is_ok());
+
+        let readonly = conn.readonly();
+        assert!(readonly.is_readonly());
+        assert!(
+            matches!(readonly.clone().readonly().as_layer::<ReadOnlyConnection>(), Some(ro) if matches!(ro.inner(Unwrap), Connection::Sqlite(_)))
+        );
+        assert!(matches!(readonly.backend(), Connection::Sqlite(_)));
+        assert!(readonly.check_read("SELECT 1").is_ok());
+        assert!(readonly.check_read("DELETE FROM foo").is_err());
+        assert!(readonly.check_write("write").is_err());
+        assert!(Transaction::new(&readonly).await.is_err());
+    }
+}
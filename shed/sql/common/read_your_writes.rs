@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing read-after-write consistency for [SqlConnections]: for a
+//! while after a write, reads go to the master instead of a replica that
+//! might not have caught up with the write yet.
+
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{Connection, ConnectionLayer, SqlConnections, Unwrap};
+
+/// Time of the last write on a set of connections
+struct WriteTracker {
+    window: Duration,
+    last_write: Mutex<Option<Instant>>,
+}
+
+impl WriteTracker {
+    fn record_write(&self) {
+        *self.last_write.lock().expect("lock poisoned") = Some(Instant::now());
+    }
+
+    fn in_window(&self) -> bool {
+        match *self.last_write.lock().expect("lock poisoned") {
+            Some(last_write) => last_write.elapsed() < self.window,
+            None => false,
+        }
+    }
+}
+
+/// Connection of [SqlConnections::read_your_writes]. The write connection
+/// records its writes, and the read connection reads from the master within
+/// the window following them.
+pub struct ReadYourWritesConnection {
+    connection: Connection,
+    /// Connection reading after a write, None for the write connection
+    master: Option<Connection>,
+    tracker: Arc<WriteTracker>,
+}
+
+impl ReadYourWritesConnection {
+    /// The connection running writes, and reads outside of the window
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The connection to read from now
+    pub fn read_connection(&self) -> &Connection {
+        match &self.master {
+            Some(master) if self.tracker.in_window() => master,
+            _ => &self.connection,
+        }
+    }
+
+    /// Start a new window of reads from the master
+    pub fn record_write(&self) {
+        self.tracker.record_write();
+    }
+}
+
+impl ConnectionLayer for ReadYourWritesConnection {
+    fn kind(&self) -> &'static str {
+        "Read your writes"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn read_inner(&self, _: Unwrap) -> &Connection {
+        self.read_connection()
+    }
+
+    fn record_write(&self) {
+        self.tracker.record_write();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<ReadYourWritesConnection> for Connection {
+    fn from(conn: ReadYourWritesConnection) -> Self {
+        Connection::Layered(Arc::new(conn))
+    }
+}
+
+impl SqlConnections {
+    /// Connections on which the reads that follow a write see it: for
+    /// `window` after a write query or the start of a transaction on
+    /// `write_connection`, the queries on `read_connection` go to
+    /// `read_master_connection`. The window should cover the usual
+    /// replication lag.
+    pub fn read_your_writes(&self, window: Duration) -> SqlConnections {
+        let tracker = Arc::new(WriteTracker {
+            window,
+            last_write: Mutex::new(None),
+        });
+        Self {
+            write_connection: ReadYourWritesConnection {
+                connection: self.write_connection.clone(),
+                master: None,
+                tracker: tracker.clone(),
+            }
+            .into(),
+            read_connection: ReadYourWritesConnection {
+                connection: self.read_connection.clone(),
+                master: Some(self.read_master_connection.clone()),
+                tracker,
+            }
+            .into(),
+            read_master_connection: self.read_master_connection.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sqlite() -> Connection {
+        Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap())
+    }
+
+    fn same(a: &Connection, b: &Connection) -> bool {
+        match (a, b) {
+            (Connection::Sqlite(a), Connection::Sqlite(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_read_your_writes() {
+        let connections = SqlConnections {
+            write_connection: sqlite(),
+            read_connection: sqlite(),
+            read_master_connection: sqlite(),
+        };
+        let master = &connections.read_master_connection;
+        let replica = &connections.read_connection;
+
+        let ryw = connections.read_your_writes(Duration::from_secs(3600));
+        assert!(same(ryw.read_connection.read_backend(), replica));
+        ryw.write_connection.record_write();
+        assert!(same(ryw.read_connection.read_backend(), master));
+        assert!(same(
+            ryw.write_connection.backend(),
+            &connections.write_connection
+        ));
+
+        // Each call starts tracking the writes separately
+        let ryw = connections.read_your_writes(Duration::ZERO);
+        ryw.write_connection.record_write();
+        assert!(same(ryw.read_connection.read_backend(), replica));
+    }
+}
@@ -0,0 +1,209 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing the replication positions of Mysql, so that a read on a
+//! replica, possibly by another service, sees an earlier write. A connection
+//! of [Connection::with_replication_tracking] returns the position of the
+//! master after each write in [WriteResult::replication_position], which a
+//! reader waits for with [Connection::wait_for_replication]:
+//!
+//! ```ignore
+//! let write = conn.write_connection.clone().with_replication_tracking();
+//! let res = InsertFoo::query(&write, &[(&x,)]).await?;
+//! // The position is e.g. sent to another service along with the request
+//! if let Some(position) = res.replication_position() {
+//!     conn.read_connection.wait_for_replication(position, Duration::from_secs(1)).await?;
+//! }
+//! let rows = SelectFoo::query(&conn.read_connection, &x).await?;
+//! ```
+//!
+//! The positions are the GTID sets executed by the master, which requires
+//! GTIDs to be enabled. Sqlite has no replicas, and neither it nor Postgres
+//! has positions. The writes of a transaction have no position, the one of
+//! the master after the commit is given by [Connection::replication_position].
+
+use std::any::Any;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Error};
+use mysql_async::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ReplicationTimeoutError;
+use crate::{Connection, ConnectionLayer, Unwrap, WriteResult};
+
+/// Position of the master in its replication stream: the GTID set it
+/// executed
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct ReplicationPosition {
+    gtid_set: String,
+}
+
+impl ReplicationPosition {
+    /// Position of the GTID set `gtid_set`, e.g.
+    /// `3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5`
+    pub fn new(gtid_set: impl Into<String>) -> Self {
+        Self {
+            gtid_set: gtid_set.into(),
+        }
+    }
+
+    /// The GTID set of the position
+    pub fn gtid_set(&self) -> &str {
+        &self.gtid_set
+    }
+}
+
+impl Display for ReplicationPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.gtid_set)
+    }
+}
+
+/// Connection of [Connection::with_replication_tracking]
+pub struct ReplicationTrackingConnection {
+    connection: Connection,
+}
+
+impl ReplicationTrackingConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl ConnectionLayer for ReplicationTrackingConnection {
+    fn kind(&self) -> &'static str {
+        "Replication tracking"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection returning the [Connection::replication_position] of the
+    /// master after each of its writes outside of a transaction, which takes
+    /// a query after each Mysql write
+    pub fn with_replication_tracking(self) -> Connection {
+        Connection::Layered(Arc::new(ReplicationTrackingConnection { connection: self }))
+    }
+
+    /// Whether there is a replication tracking connection on the way to the
+    /// [Connection::backend]
+    pub fn tracks_replication(&self) -> bool {
+        self.layer::<ReplicationTrackingConnection>().is_some()
+    }
+
+    /// The current position of the [Connection::backend] if it is Mysql,
+    /// which includes the writes completed on it when it is the master
+    pub async fn replication_position(&self) -> Result<Option<ReplicationPosition>, Error> {
+        const QUERY: &str = "SELECT @@GLOBAL.gtid_executed";
+        let rows: Vec<(String,)> = match self.backend() {
+            Connection::Mysql(conn) => conn.read_query(QUERY.to_owned()).await?,
+            Connection::MysqlPool(pool) => {
+                let conn = pool.acquire().await?;
+                conn.run(conn.read_query(QUERY.to_owned())).await?
+            }
+            Connection::Sqlite(_) | Connection::Postgres(_) => return Ok(None),
+            Connection::Layered(_) => {
+                unreachable!("backend is never a wrapping connection")
+            }
+        };
+        Ok(rows
+            .into_iter()
+            .next()
+            .map(|(gtid_set,)| ReplicationPosition::new(gtid_set)))
+    }
+
+    /// Wait for the [Connection::read_backend] to replicate the master up to
+    /// `position`, failing with [ReplicationTimeoutError] after `timeout`.
+    /// Sqlite has no replicas to wait for, and Postgres fails as it has no
+    /// positions.
+    pub async fn wait_for_replication(
+        &self,
+        position: &ReplicationPosition,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let query = format!(
+            "SELECT WAIT_FOR_EXECUTED_GTID_SET({}, {:.3})",
+            Value::from(position.gtid_set()).as_sql(false),
+            timeout.as_secs_f64(),
+        );
+        let rows: Vec<(Option<i64>,)> = match self.read_backend() {
+            Connection::Mysql(conn) => conn.read_query(query).await?,
+            Connection::MysqlPool(pool) => {
+                let conn = pool.acquire().await?;
+                conn.run(conn.read_query(query)).await?
+            }
+            Connection::Sqlite(_) => return Ok(()),
+            Connection::Postgres(_) => bail!("Postgres has no replication positions"),
+            Connection::Layered(_) => {
+                unreachable!("backend is never a wrapping connection")
+            }
+        };
+        // 0 once the position is reached, 1 on timeout
+        match rows.as_slice() {
+            [(Some(0),)] => Ok(()),
+            [(Some(1),)] => Err(ReplicationTimeoutError { timeout }.into()),
+            _ => bail!(
+                "unexpected result of WAIT_FOR_EXECUTED_GTID_SET: {:?}",
+                rows
+            ),
+        }
+    }
+}
+
+/// `result` of a write on `connection`, with the position of the master
+/// after it if the connection tracks it. The write succeeded, so failing to
+/// get the position leaves it unknown rather than failing the write. This
+/// should never be used directly, it is made public so that the queries!
+/// macro can make use of it
+#[doc(hidden)]
+pub async fn track_write(connection: &Connection, result: WriteResult) -> WriteResult {
+    if !connection.tracks_replication() {
+        return result;
+    }
+    match connection.replication_position().await {
+        Ok(Some(position)) => result.with_replication_position(position),
+        Ok(None) | Err(_) => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replication_tracking() -> Result<(), Error> {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(!conn.tracks_replication());
+
+        let conn = conn.with_replication_tracking().readonly();
+        assert!(conn.tracks_replication());
+        assert!(matches!(conn.backend(), Connection::Sqlite(_)));
+        assert_eq!(conn.replication_position().await?, None);
+        let result = track_write(&conn, WriteResult::new(None, 1)).await;
+        assert_eq!(result.replication_position(), None);
+
+        let position = ReplicationPosition::new("3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5");
+        conn.wait_for_replication(&position, Duration::from_secs(1))
+            .await?;
+        let result = WriteResult::new(None, 1).with_replication_position(position.clone());
+        assert_eq!(result.replication_position(), Some(&position));
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [RetryingConnection], which retries the read queries
+//! failing with transient errors, such as deadlocks, lost connections or too
+//! many connections, with an exponential backoff and jitter. Write queries and
+//! transactions are run once. Each retrying connection exports
+//! `sql.retry.<name>.{attempts,retries,exhausted}`. The retries are made with
+//! [futures_03_ext::retry].
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use futures_03_ext::retry::{self, Backoff};
+use stats::prelude::*;
+
+use crate::error::{error_kind, ErrorKind};
+use crate::{Connection, ConnectionLayer, SqlConnections, Unwrap};
+
+define_stats! {
+    prefix = "sql.retry";
+    attempts: dynamic_timeseries("{}.attempts", (name: String); Rate, Sum),
+    retries: dynamic_timeseries("{}.retries", (name: String); Rate, Sum),
+    exhausted: dynamic_timeseries("{}.exhausted", (name: String); Rate, Sum),
+}
+
+/// Whether a query failing with `err` might succeed if retried, i.e. if it
+/// failed on a connection error or a conflict, see [error_kind]. Errors
+/// tagged as retriable or not with [failure_ext::ErrorTagsExt], and io
+/// errors, are classified by [failure_ext::retriable].
+pub fn is_transient(err: &Error) -> bool {
+    failure_ext::retriable(err).unwrap_or_else(|| error_kind(err) != ErrorKind::Query)
+}
+
+/// How a [RetryingConnection] retries queries
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Most attempts of a query, including the first one. Defaults to 3.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each following one. Each
+    /// delay is randomly shortened by up to half to spread the retries.
+    /// Defaults to 50 milliseconds.
+    pub base_delay: Duration,
+    /// Longest delay between two attempts. Defaults to 2 seconds.
+    pub max_delay: Duration,
+    /// Time since the start of the first attempt after which no attempt
+    /// starts, if any. Defaults to none.
+    pub max_elapsed: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+            max_elapsed: None,
+        }
+    }
+}
+
+impl From<&RetryPolicy> for retry::RetryPolicy {
+    fn from(policy: &RetryPolicy) -> Self {
+        Self {
+            backoff: Backoff::Exponential {
+                base: policy.base_delay,
+                max: policy.max_delay,
+            },
+            max_attempts: policy.max_attempts,
+            max_elapsed: policy.max_elapsed,
+        }
+    }
+}
+
+/// Connection retrying its read queries according to a [RetryPolicy]. Use
+/// it as a [Connection] by converting it with [From].
+pub struct RetryingConnection {
+    name: String,
+    connection: Connection,
+    policy: RetryPolicy,
+}
+
+impl RetryingConnection {
+    /// Retry the read queries of `connection`, exporting stats under `name`.
+    /// If `connection` already retries its queries, its policy is replaced.
+    pub fn new(name: impl Into<String>, connection: Connection, policy: RetryPolicy) -> Self {
+        Self {
+            name: name.into(),
+            connection: match connection.as_layer::<RetryingConnection>() {
+                Some(conn) => conn.connection().clone(),
+                None => connection,
+            },
+            policy,
+        }
+    }
+
+    /// Name of the connection, used in its stats
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The connection running the queries, which isn't a retrying one
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Policy of the retries
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Run `query` until it succeeds, fails with an error that isn't
+    /// transient, or the attempts or time of the policy run out.
+    pub async fn retry<T, F, Fut>(&self, mut query: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let result = retry::retry(&(&self.policy).into(), is_transient, |attempt| {
+            STATS::attempts.add_value(1, (self.name.clone(),));
+            if attempt > 1 {
+                STATS::retries.add_value(1, (self.name.clone(),));
+            }
+            query()
+        })
+        .await;
+        // A transient error is only returned once the retries run out
+        if matches!(&result, Err(err) if is_transient(err)) {
+            STATS::exhausted.add_value(1, (self.name.clone(),));
+        }
+        result
+    }
+}
+
+impl ConnectionLayer for RetryingConnection {
+    fn kind(&self) -> &'static str {
+        "Retrying"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<RetryingConnection> for Connection {
+    fn from(conn: RetryingConnection) -> Self {
+        Connection::Layered(Arc::new(conn))
+    }
+}
+
+impl SqlConnections {
+    /// Retry the read queries on `read_connection` and
+    /// `read_master_connection` according to `policy`, exporting stats under
+    /// `<name>.read` and `<name>.read_master`.
+    pub fn with_read_retries(self, name: &str, policy: RetryPolicy) -> Self {
+        Self {
+            read_connection: RetryingConnection::new(
+                format!("{}.read", name),
+                self.read_connection,
+                policy.clone(),
+            )
+            .into(),
+            read_master_connection: RetryingConnection::new(
+                format!("{}.read_master", name),
+                self.read_master_connection,
+                policy,
+            )
+            .into(),
+            write_connection: self.write_connection,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+    use failure_ext::ErrorTagsExt;
+
+    fn retrying(name: &str) -> RetryingConnection {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        RetryingConnection::new(name, conn, RetryPolicy::default())
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&anyhow!(
+            "Deadlock found when trying to get lock; try restarting transaction"
+        )));
+        assert!(is_transient(
+            &anyhow!("Lost connection to MySQL server").context("While executing MySelect query")
+        ));
+        assert!(!is_transient(&anyhow!("Unknown column 'x'")));
+        assert!(is_transient(&anyhow!("flaky").with_retriable(true)));
+        assert!(!is_transient(
+            &anyhow!("Too many connections").with_retriable(false)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry() {
+        let conn = retrying("test_retry");
+        let mut attempts = 0;
+        let result = conn
+            .retry(|| {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        Err(anyhow!("Deadlock found"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up after max_attempts
+        attempts = 0;
+        let result: Result<(), _> = conn
+            .retry(|| {
+                attempts += 1;
+                async { Err(anyhow!("Deadlock found")) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // Doesn't retry the other errors
+        attempts = 0;
+        let result: Result<(), _> = conn
+            .retry(|| {
+                attempts += 1;
+                async { Err(anyhow!("Syntax error")) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::default();
+        let backoff = retry::RetryPolicy::from(&policy).backoff;
+        for attempt in 1..40 {
+            let delay = backoff.delay(attempt, Duration::from_secs(0));
+            assert!(delay <= policy.max_delay);
+            assert!(delay >= policy.base_delay / 2);
+        }
+        assert!(backoff.delay(1, Duration::from_secs(0)) <= policy.base_delay);
+    }
+}
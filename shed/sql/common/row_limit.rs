@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing connections failing the read queries that return more
+//! rows than a limit, see [Connection::with_max_rows], as a safeguard against
+//! unbounded scans. The limit applies to each query, and a single query can
+//! get its own by running on a limited clone of the connection:
+//!
+//! ```ignore
+//! let rows = SelectFoo::query(&conn.clone().with_max_rows(100), &x).await?;
+//! ```
+//!
+//! The queries fail with [TooManyRowsError] as soon as they exceed the limit:
+//! they stop fetching their rows once they have one more, or for the streams
+//! once they stream it. The Postgres client has no streaming API, so its
+//! queries fail once all their rows are fetched.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Error;
+use futures::future;
+use futures::stream::{BoxStream, StreamExt};
+
+use crate::error::TooManyRowsError;
+use crate::{Connection, ConnectionLayer, SqlConnections, Unwrap};
+
+/// Connection of [Connection::with_max_rows]
+pub struct RowLimitedConnection {
+    connection: Connection,
+    max_rows: usize,
+}
+
+impl RowLimitedConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Most rows a read query may return
+    pub fn max_rows(&self) -> usize {
+        self.max_rows
+    }
+}
+
+impl ConnectionLayer for RowLimitedConnection {
+    fn kind(&self) -> &'static str {
+        "Row-limited"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection failing the read queries of this connection that return
+    /// more than `max_rows` rows with [TooManyRowsError]
+    pub fn with_max_rows(self, max_rows: usize) -> Connection {
+        Connection::Layered(Arc::new(RowLimitedConnection {
+            connection: self,
+            max_rows,
+        }))
+    }
+
+    /// The limit of the first row-limited connection on the way to the
+    /// [Connection::read_backend], if any
+    pub fn max_rows(&self) -> Option<usize> {
+        self.read_layer::<RowLimitedConnection>()
+            .map(RowLimitedConnection::max_rows)
+    }
+}
+
+/// Fail if `rows` is more than `max_rows`, the [Connection::max_rows] of a
+/// read query. This should never be used directly, it is made public so that
+/// the queries! macro can make use of it
+#[doc(hidden)]
+pub fn check_max_rows(max_rows: Option<usize>, rows: usize) -> Result<(), Error> {
+    match max_rows {
+        Some(max_rows) if rows > max_rows => Err(TooManyRowsError { max_rows }.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Most rows to fetch for a read query whose limit is `max_rows`: one more,
+/// telling that the query exceeds it. This should never be used directly, it
+/// is made public so that the queries! macro can make use of it
+#[doc(hidden)]
+pub fn fetch_limit(max_rows: Option<usize>) -> usize {
+    max_rows.map_or(usize::MAX, |max_rows| max_rows.saturating_add(1))
+}
+
+/// `rows`, the stream of a read query, ended by a [TooManyRowsError] once it
+/// streamed more than `max_rows` rows. This should never be used directly,
+/// it is made public so that the queries! macro can make use of it
+#[doc(hidden)]
+pub fn limit_stream<'a, T: Send + 'a>(
+    max_rows: Option<usize>,
+    rows: BoxStream<'a, Result<T, Error>>,
+) -> BoxStream<'a, Result<T, Error>> {
+    let mut count = 0;
+    rows.scan(false, move |failed, row| {
+        if *failed {
+            return future::ready(None);
+        }
+        if row.is_ok() {
+            count += 1;
+            if let Err(err) = check_max_rows(max_rows, count) {
+                *failed = true;
+                return future::ready(Some(Err(err)));
+            }
+        }
+        future::ready(Some(row))
+    })
+    .boxed()
+}
+
+impl SqlConnections {
+    /// Fail the read queries returning more than `max_rows` rows, on each of
+    /// the connections
+    pub fn with_max_rows(self, max_rows: usize) -> Self {
+        Self {
+            write_connection: self.write_connection.with_max_rows(max_rows),
+            read_connection: self.read_connection.with_max_rows(max_rows),
+            read_master_connection: self.read_master_connection.with_max_rows(max_rows),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_max_rows() {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert_eq!(conn.max_rows(), None);
+        assert!(check_max_rows(conn.max_rows(), usize::MAX).is_ok());
+        assert_eq!(fetch_limit(conn.max_rows()), usize::MAX);
+
+        let conn = conn.with_max_rows(10).readonly();
+        assert_eq!(conn.max_rows(), Some(10));
+        assert_eq!(fetch_limit(conn.max_rows()), 11);
+        assert!(matches!(conn.backend(), Connection::Sqlite(_)));
+        assert!(check_max_rows(conn.max_rows(), 10).is_ok());
+        let err = check_max_rows(conn.max_rows(), 11).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TooManyRowsError>()
+                .map(|err| err.max_rows),
+            Some(10)
+        );
+    }
+}
@@ -0,0 +1,418 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [ShardedTransaction], which writes to several shards of
+//! [SqlShardedConnections] and then commits all of them or none.
+//!
+//! The Mysql shards run XA transactions, committed in two phases: they are
+//! all prepared, with `XA END` and `XA PREPARE`, before any is committed. A
+//! prepared transaction is guaranteed to commit, it survives the loss of its
+//! connection or a restart of the server, where it is listed by `XA RECOVER`
+//! until it is committed or rolled back. The XA transactions of a sharded
+//! transaction share the identifier [ShardedTransaction::xid], followed by
+//! the index of their shard.
+//!
+//! Sqlite and Postgres shards can't be prepared: Sqlite doesn't support it,
+//! and Postgres only does if `max_prepared_transactions` is set, which it
+//! isn't by default. Their commit is best effort instead: each of them is
+//! pinged with the Mysql shards being prepared, checking that it is still
+//! reachable with its transaction open, and they are then committed before
+//! the prepared shards, so that one of them failing can still roll back the
+//! others. A shard failing before the first commit rolls back all of them.
+//! The ping doesn't guarantee that the commits succeed, a shard can still
+//! fail to commit, e.g. on a deferred constraint or a lost connection. A
+//! shard failing after the first commit, which a single shard that can't be
+//! prepared never does, leaves the shards committed before it committed, and
+//! fails with [ShardedTransactionError::PartialCommit] telling which they
+//! are, so that the caller can repair them, or commit the shards that were
+//! prepared with `XA COMMIT` if their connection was lost.
+//!
+//! The Sqlite connections of a process share a lock held by their
+//! transactions, so a sharded transaction can't write to two Sqlite shards.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, format_err, Error};
+use thiserror::Error;
+
+use crate::transaction::Transaction;
+use crate::{Connection, SqlShardedConnections};
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error committing a [ShardedTransaction]
+#[derive(Error, Debug)]
+pub enum ShardedTransactionError {
+    /// A write failed, so every shard was rolled back
+    #[error("a write on shard {shard} failed, every shard was rolled back")]
+    Aborted {
+        /// Index of the shard whose write failed
+        shard: usize,
+    },
+    /// A shard failed to be prepared, or failed before any was committed, so
+    /// every shard was rolled back
+    #[error("shard {shard} failed to commit, every shard was rolled back")]
+    RolledBack {
+        /// Index of the shard that failed
+        shard: usize,
+        /// Its error
+        #[source]
+        source: Source,
+    },
+    /// A shard failed to commit once others were committed. The shards after
+    /// it were rolled back.
+    #[error(
+        "shard {failed} failed to commit after shards {committed:?} were committed, \
+        shards {rolled_back:?} were rolled back"
+    )]
+    PartialCommit {
+        /// Indices of the shards that were committed
+        committed: Vec<usize>,
+        /// Index of the shard that failed to commit
+        failed: usize,
+        /// Indices of the shards that were rolled back
+        rolled_back: Vec<usize>,
+        /// Error of the failed shard
+        #[source]
+        source: Source,
+    },
+}
+
+/// Transaction writing to several shards, see [SqlShardedConnections::start_transaction]
+///
+/// # Example
+/// ```
+/// use anyhow::Error;
+///
+/// use sql::{queries, SqlShardedConnections};
+///
+/// queries! {
+///     write InsertFoo(values: (x: i64)) {
+///         none,
+///         "INSERT INTO foo (x) VALUES {values}"
+///     }
+/// }
+///
+/// async fn move_foo(sharded: &SqlShardedConnections, from: &[u8], to: &[u8]) -> Result<(), Error> {
+///     let mut transaction = sharded.start_transaction();
+///     transaction
+///         .run_for_key(from, |tr| InsertFoo::query_with_transaction(tr, &[(&-1,)]))
+///         .await?;
+///     transaction
+///         .run_for_key(to, |tr| InsertFoo::query_with_transaction(tr, &[(&1,)]))
+///         .await?;
+///     Ok(transaction.commit().await?)
+/// }
+/// #
+/// # fn main() {}
+/// ```
+pub struct ShardedTransaction<'a> {
+    connections: &'a SqlShardedConnections,
+    transactions: BTreeMap<usize, Transaction>,
+    /// Shards running an XA transaction
+    xa_shards: BTreeSet<usize>,
+    xid: String,
+    aborted: Option<usize>,
+}
+
+/// Number of sharded transactions started by the process, making their XA
+/// identifiers unique
+static STARTED: AtomicU64 = AtomicU64::new(0);
+
+impl SqlShardedConnections {
+    /// Start a transaction writing to several shards, which starts the
+    /// transaction of a shard on its write connection when it first writes
+    /// to it, an XA transaction on Mysql
+    pub fn start_transaction(&self) -> ShardedTransaction<'_> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        ShardedTransaction {
+            connections: self,
+            transactions: BTreeMap::new(),
+            xa_shards: BTreeSet::new(),
+            xid: format!(
+                "sql.{}.{}.{}",
+                std::process::id(),
+                now.as_micros(),
+                STARTED.fetch_add(1, Ordering::Relaxed)
+            ),
+            aborted: None,
+        }
+    }
+}
+
+impl<'a> ShardedTransaction<'a> {
+    /// Run `body` with the transaction of the shard `index`, starting it if
+    /// needed. If `body` or the start of the transaction fails, the sharded
+    /// transaction is aborted, and can only be rolled back.
+    pub async fn run<T, F, Fut>(&mut self, index: usize, body: F) -> Result<T, Error>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<(Transaction, T), Error>>,
+    {
+        if let Some(shard) = self.aborted {
+            bail!(
+                "the transaction was aborted by a failed write on shard {}",
+                shard
+            );
+        }
+        let transaction = match self.transactions.remove(&index) {
+            Some(transaction) => transaction,
+            None => {
+                let shard = self
+                    .connections
+                    .shard(index)
+                    .ok_or_else(|| format_err!("there is no shard {}", index))?;
+                let connection = &shard.write_connection;
+                let xa = matches!(
+                    connection.backend(),
+                    Connection::Mysql(_) | Connection::MysqlPool(_)
+                );
+                let transaction = if xa {
+                    let xid = format!("{}.{}", self.xid, index);
+                    Transaction::new_xa(connection, xid).await
+                } else {
+                    connection.start_transaction().await
+                };
+                match transaction {
+                    Ok(transaction) => {
+                        if xa {
+                            self.xa_shards.insert(index);
+                        }
+                        transaction
+                    }
+                    Err(err) => {
+                        self.aborted = Some(index);
+                        return Err(err);
+                    }
+                }
+            }
+        };
+        match body(transaction).await {
+            Ok((transaction, value)) => {
+                self.transactions.insert(index, transaction);
+                Ok(value)
+            }
+            Err(err) => {
+                // The transaction was dropped by the body, which rolled it
+                // back
+                self.aborted = Some(index);
+                Err(err)
+            }
+        }
+    }
+
+    /// Like [ShardedTransaction::run] on the shard of `key`. Panics if there
+    /// are no shards.
+    pub async fn run_for_key<T, F, Fut>(&mut self, key: &[u8], body: F) -> Result<T, Error>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<(Transaction, T), Error>>,
+    {
+        let index = self.connections.shard_index_for_key(key);
+        self.run(index, body).await
+    }
+
+    /// Identifier of the XA transactions of the Mysql shards, each followed
+    /// by `.<index of the shard>`
+    pub fn xid(&self) -> &str {
+        &self.xid
+    }
+
+    /// Indices of the shards with a transaction, in order
+    pub fn shards(&self) -> impl Iterator<Item = usize> + '_ {
+        self.transactions.keys().copied()
+    }
+
+    /// Commit the transactions of all the shards, or of none of them if the
+    /// transaction was aborted or a shard fails before the first commit,
+    /// including when it fails to be prepared or pinged, see the [module
+    /// documentation](self). The rollbacks are best effort, a shard failing
+    /// to roll back is rolled back by its database once its connection
+    /// closes, or if it was prepared, stays prepared until it is rolled back
+    /// with `XA ROLLBACK`.
+    pub async fn commit(mut self) -> Result<(), ShardedTransactionError> {
+        if let Some(shard) = self.aborted {
+            rollback_all(self.transactions).await;
+            return Err(ShardedTransactionError::Aborted { shard });
+        }
+
+        // Prepare the Mysql shards, and ping the others, which makes them
+        // failing to commit less likely, but not impossible
+        for (index, transaction) in self.transactions.iter_mut() {
+            let result = if self.xa_shards.contains(index) {
+                transaction.xa_prepare().await
+            } else {
+                ping(transaction).await
+            };
+            if let Err(err) = result {
+                let shard = *index;
+                rollback_all(self.transactions).await;
+                return Err(ShardedTransactionError::RolledBack {
+                    shard,
+                    source: err.into(),
+                });
+            }
+        }
+
+        // The shards that are not prepared are committed first, while the
+        // prepared ones can still be rolled back if they fail
+        let (prepared, unprepared): (Vec<_>, Vec<_>) = self
+            .transactions
+            .into_iter()
+            .partition(|(index, _)| self.xa_shards.contains(index));
+        let mut committed = Vec::new();
+        let mut transactions = unprepared.into_iter().chain(prepared);
+        while let Some((index, transaction)) = transactions.next() {
+            if let Err(err) = transaction.commit().await {
+                let remaining: Vec<_> = transactions.collect();
+                let rolled_back = remaining.iter().map(|(index, _)| *index).collect();
+                rollback_all(remaining).await;
+                return Err(if committed.is_empty() {
+                    ShardedTransactionError::RolledBack {
+                        shard: index,
+                        source: err.into(),
+                    }
+                } else {
+                    ShardedTransactionError::PartialCommit {
+                        committed,
+                        failed: index,
+                        rolled_back,
+                        source: err.into(),
+                    }
+                });
+            }
+            committed.push(index);
+        }
+        Ok(())
+    }
+
+    /// Roll back the transactions of all the shards, failing with the error
+    /// of the first shard that failed to roll back
+    pub async fn rollback(self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (_, transaction) in self.transactions {
+            let rollback = transaction.rollback().await;
+            if result.is_ok() {
+                result = rollback;
+            }
+        }
+        result
+    }
+}
+
+/// Check that the shard of `transaction` is still reachable with the
+/// transaction open
+async fn ping(transaction: &mut Transaction) -> Result<(), Error> {
+    transaction.read_u64("SELECT 1").await?;
+    Ok(())
+}
+
+async fn rollback_all(transactions: impl IntoIterator<Item = (usize, Transaction)>) {
+    for (_, transaction) in transactions {
+        let _ = transaction.rollback().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fbinit::FacebookInit;
+
+    use crate::mysql::Pool;
+    use crate::pool::PoolOptions;
+    use crate::SqlConnections;
+
+    fn sharded(fb: FacebookInit) -> SqlShardedConnections {
+        let sqlite = rusqlite::Connection::open_in_memory().unwrap();
+        sqlite.execute_batch("CREATE TABLE foo(x INTEGER)").unwrap();
+        let unreachable = Pool::new(
+            fb,
+            "test_sharded_transaction",
+            PoolOptions::default(),
+            || async { Err(format_err!("server is down")) },
+        );
+        vec![
+            SqlConnections::new_single(Connection::with_sqlite(sqlite)),
+            SqlConnections::new_single(unreachable.into()),
+        ]
+        .into()
+    }
+
+    async fn insert(transaction: Transaction, x: i64) -> Result<(Transaction, ()), Error> {
+        let mut transaction = transaction;
+        transaction
+            .execute(format!("INSERT INTO foo (x) VALUES ({})", x))
+            .await?;
+        Ok((transaction, ()))
+    }
+
+    async fn count(sharded: &SqlShardedConnections) -> u64 {
+        let mut transaction = sharded
+            .shard(0)
+            .unwrap()
+            .write_connection
+            .start_transaction()
+            .await
+            .unwrap();
+        let count = transaction
+            .read_u64("SELECT COUNT(*) FROM foo")
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        count
+    }
+
+    #[fbinit::test]
+    async fn test_commit(fb: FacebookInit) {
+        let sharded = sharded(fb);
+        let mut transaction = sharded.start_transaction();
+        transaction.run(0, |tr| insert(tr, 1)).await.unwrap();
+        transaction.run(0, |tr| insert(tr, 2)).await.unwrap();
+        assert_eq!(transaction.shards().collect::<Vec<_>>(), vec![0]);
+        assert!(transaction.xa_shards.is_empty());
+        // Sqlite can't prepare a transaction
+        let sqlite = &sharded.shard(0).unwrap().write_connection;
+        assert!(Transaction::new_xa(sqlite, "xid".to_owned()).await.is_err());
+        transaction.commit().await.unwrap();
+        assert_eq!(count(&sharded).await, 2);
+
+        let mut transaction = sharded.start_transaction();
+        transaction.run(0, |tr| insert(tr, 3)).await.unwrap();
+        transaction.rollback().await.unwrap();
+        assert_eq!(count(&sharded).await, 2);
+    }
+
+    #[fbinit::test]
+    async fn test_aborted(fb: FacebookInit) {
+        let sharded = sharded(fb);
+        let mut transaction = sharded.start_transaction();
+        transaction.run(0, |tr| insert(tr, 1)).await.unwrap();
+        assert!(transaction.run(1, |tr| insert(tr, 2)).await.is_err());
+        assert!(transaction.run(0, |tr| insert(tr, 3)).await.is_err());
+        assert!(matches!(
+            transaction.commit().await,
+            Err(ShardedTransactionError::Aborted { shard: 1 })
+        ));
+        assert_eq!(count(&sharded).await, 0);
+
+        let mut transaction = sharded.start_transaction();
+        assert_ne!(transaction.xid(), sharded.start_transaction().xid());
+        // A missing shard doesn't abort the transaction
+        assert!(transaction.run(2, |tr| insert(tr, 1)).await.is_err());
+        transaction.run(0, |tr| insert(tr, 1)).await.unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(count(&sharded).await, 1);
+    }
+}
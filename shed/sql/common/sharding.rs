@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module routing keys to the shards of [SqlShardedConnections]. The shard of
+//! a key is picked by a [ShardingStrategy], [Fnv1aModulo] by default. The
+//! strategies here hash the keys the same way in every process and release,
+//! so that a key stays on the same shard.
+
+use std::sync::Arc;
+
+use hash_memo::fnv1a;
+
+use crate::{SqlConnections, SqlShardedConnections};
+
+/// Picks the shard of a key
+pub trait ShardingStrategy: Send + Sync + 'static {
+    /// Index of the shard of `key` among `shards`, which is never 0. It must
+    /// be below `shards`.
+    fn shard(&self, key: &[u8], shards: usize) -> usize;
+}
+
+impl<F> ShardingStrategy for F
+where
+    F: Fn(&[u8], usize) -> usize + Send + Sync + 'static,
+{
+    fn shard(&self, key: &[u8], shards: usize) -> usize {
+        self(key, shards)
+    }
+}
+
+/// Shard of a key given by its 64 bits FNV-1a hash modulo the number of
+/// shards
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Fnv1aModulo;
+
+impl ShardingStrategy for Fnv1aModulo {
+    fn shard(&self, key: &[u8], shards: usize) -> usize {
+        (fnv1a(key) % shards as u64) as usize
+    }
+}
+
+/// Shard of a key given by the jump consistent hash of its FNV-1a hash. When
+/// the number of shards grows from n to n + 1, only 1 / (n + 1) of the keys
+/// move, all to the new shard.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JumpConsistentHash;
+
+impl ShardingStrategy for JumpConsistentHash {
+    fn shard(&self, key: &[u8], shards: usize) -> usize {
+        // From "A Fast, Minimal Memory, Consistent Hash Algorithm" by Lamping
+        // and Veach
+        let mut hash = fnv1a(key);
+        let mut shard = 0;
+        let mut next = 0;
+        while next < shards as u64 {
+            shard = next;
+            hash = hash.wrapping_mul(2862933555777941757).wrapping_add(1);
+            next = ((shard + 1) as f64 * ((1u64 << 31) as f64 / ((hash >> 33) + 1) as f64)) as u64;
+        }
+        shard as usize
+    }
+}
+
+impl SqlShardedConnections {
+    /// Route the keys with `strategy` instead of [Fnv1aModulo]
+    pub fn with_sharding_strategy(self, strategy: impl ShardingStrategy) -> Self {
+        Self {
+            strategy: Arc::new(strategy),
+            ..self
+        }
+    }
+
+    /// Number of shards
+    pub fn len(&self) -> usize {
+        self.write_connections.len()
+    }
+
+    /// Connections of the shard `index`, if there are that many shards
+    pub fn shard(&self, index: usize) -> Option<SqlConnections> {
+        (index < self.len()).then(|| self.shard_at(index))
+    }
+
+    /// Connections of the shard of `key`. Panics if there are no shards.
+    pub fn shard_for_key(&self, key: &[u8]) -> SqlConnections {
+        self.shard_at(self.shard_index_for_key(key))
+    }
+
+    /// Index of the shard of `key`. Panics if there are no shards.
+    pub(crate) fn shard_index_for_key(&self, key: &[u8]) -> usize {
+        assert!(!self.is_empty(), "no shard to route the key to");
+        let index = self.strategy.shard(key, self.len());
+        assert!(
+            index < self.len(),
+            "sharding strategy picked shard {} of {}",
+            index,
+            self.len()
+        );
+        index
+    }
+
+    /// Connections of the shard `index`. Panics if there aren't that many
+    /// shards.
+    pub(crate) fn shard_at(&self, index: usize) -> SqlConnections {
+        SqlConnections {
+            write_connection: self.write_connections[index].clone(),
+            read_connection: self.read_connections[index].clone(),
+            read_master_connection: self.read_master_connections[index].clone(),
+        }
+    }
+
+    /// Connections of each shard, by index
+    pub fn iter_shards(&self) -> impl Iterator<Item = SqlConnections> + '_ {
+        (0..self.len()).map(move |index| self.shard_at(index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Connection;
+
+    fn sharded(shards: usize) -> SqlShardedConnections {
+        (0..shards)
+            .map(|_| {
+                SqlConnections::new_single(Connection::with_sqlite(
+                    rusqlite::Connection::open_in_memory().unwrap(),
+                ))
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn index_of(sharded: &SqlShardedConnections, key: &[u8]) -> usize {
+        let label = sharded.shard_for_key(key).write_connection.label();
+        sharded
+            .iter_shards()
+            .position(|other| other.write_connection.label() == label)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_shard_for_key() {
+        let sharded = sharded(4);
+        assert_eq!(sharded.len(), 4);
+        assert_eq!(sharded.read_master_connections.len(), 4);
+        assert!(sharded.shard(4).is_none());
+        // The hash is stable, these must never change
+        assert_eq!(index_of(&sharded, b"a"), 0);
+        assert_eq!(index_of(&sharded, b"b"), 1);
+
+        let sharded = sharded.with_sharding_strategy(|key: &[u8], _shards| key.len());
+        assert_eq!(index_of(&sharded, b"abc"), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_shard_for_key_empty() {
+        sharded(0).shard_for_key(b"a");
+    }
+
+    #[test]
+    fn test_jump_consistent_hash() {
+        let keys: Vec<_> = (0..1000u32).map(|key| key.to_le_bytes()).collect();
+        let mut moved = 0;
+        for key in &keys {
+            let before = JumpConsistentHash.shard(key, 10);
+            let after = JumpConsistentHash.shard(key, 11);
+            assert!(before < 10 && after < 11);
+            if before != after {
+                assert_eq!(after, 10);
+                moved += 1;
+            }
+        }
+        // About 1 / 11 of the keys move to the new shard
+        assert!(moved > 40 && moved < 150, "{} keys moved", moved);
+    }
+}
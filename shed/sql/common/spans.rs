@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [QuerySpan]. With the `tracing` feature, every query of
+//! `queries!` runs in a `sql_query` span with the fields `name`, `shard`,
+//! `pool`, `connection`, `in_transaction`, `rows` and `latency_us`, so that
+//! traces show database time. The shard is the one of the
+//! [crate::label::ConnectionLabel] of the connection, and the pool is the
+//! name of the pool or of the retrying connection running the query, if any.
+//! Without the feature, queries run without span.
+
+use std::future::Future;
+
+use anyhow::Error;
+
+use crate::transaction::Transaction;
+use crate::Connection;
+
+/// Span of a query. This should never be used directly, it is made public so
+/// that the queries! macro can make use of it
+#[doc(hidden)]
+pub struct QuerySpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl QuerySpan {
+    /// Span of the read query `name` on `connection`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn for_read(name: &str, connection: &Connection) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: connection_span(name, connection, connection.read_backend()),
+        }
+    }
+
+    /// Span of the write query `name` on `connection`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn for_write(name: &str, connection: &Connection) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: connection_span(name, connection, connection.backend()),
+        }
+    }
+
+    /// Span of the query `name` in `transaction`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn for_transaction(name: &str, transaction: &Transaction) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: span(name, None, None, transaction_kind(transaction), true),
+        }
+    }
+
+    /// Run `query` in the span, recording the number of rows given by `rows`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub async fn run<T, Fut>(self, rows: impl FnOnce(&T) -> u64, query: Fut) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            let start = std::time::Instant::now();
+            let result = query.instrument(self.span.clone()).await;
+            self.span
+                .record("latency_us", start.elapsed().as_micros() as u64);
+            match &result {
+                Ok(value) => {
+                    self.span.record("rows", rows(value));
+                }
+                Err(err) => {
+                    self.span.record("error", tracing::field::display(err));
+                }
+            }
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            query.await
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn span(
+    name: &str,
+    shard: Option<&str>,
+    pool: Option<&str>,
+    connection: &str,
+    in_transaction: bool,
+) -> tracing::Span {
+    tracing::info_span!(
+        "sql_query",
+        name,
+        shard,
+        pool,
+        connection,
+        in_transaction,
+        rows = tracing::field::Empty,
+        latency_us = tracing::field::Empty,
+        error = tracing::field::Empty,
+    )
+}
+
+/// Span of a query on `connection` running on `backend`, the backend it
+/// reads from or writes to
+#[cfg(feature = "tracing")]
+fn connection_span(name: &str, connection: &Connection, backend: &Connection) -> tracing::Span {
+    let pool = match connection.layer::<crate::retry::RetryingConnection>() {
+        Some(retrying) => Some(retrying.name()),
+        None => match backend {
+            Connection::MysqlPool(pool) => Some(pool.name()),
+            _ => None,
+        },
+    };
+    span(
+        name,
+        connection.label().shard(),
+        pool,
+        connection_kind(backend),
+        false,
+    )
+}
+
+#[cfg(feature = "tracing")]
+fn connection_kind(backend: &Connection) -> &'static str {
+    match backend {
+        Connection::Sqlite(_) => "sqlite",
+        Connection::Mysql(_) => "mysql",
+        Connection::MysqlPool(_) => "mysql_pool",
+        Connection::Postgres(_) => "postgres",
+        Connection::Layered(_) => {
+            unreachable!("backend is never a wrapping connection")
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn transaction_kind(transaction: &Transaction) -> &'static str {
+    match transaction.backend() {
+        Transaction::Sqlite(_) => "sqlite",
+        Transaction::Mysql(_) => "mysql",
+        Transaction::MysqlPool(_) => "mysql_pool",
+        Transaction::Postgres(_) => "postgres",
+        Transaction::Observed(_) => unreachable!("observed transactions are unwrapped"),
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use fbinit::FacebookInit;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::label::ConnectionLabel;
+    use crate::mysql::Pool;
+    use crate::pool::PoolOptions;
+    use crate::SqlConnections;
+
+    type Fields = HashMap<String, String>;
+
+    /// Subscriber keeping the fields of the spans
+    #[derive(Clone, Default)]
+    struct Spans {
+        next_id: Arc<AtomicU64>,
+        fields: Arc<Mutex<Vec<Fields>>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::new();
+            span.record(&mut FieldVisitor(&mut fields));
+            self.fields.lock().unwrap().push(fields);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.fields.lock().unwrap();
+            values.record(&mut FieldVisitor(&mut spans[span.into_u64() as usize - 1]));
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    impl Spans {
+        fn last(&self) -> Fields {
+            self.fields.lock().unwrap().last().cloned().unwrap()
+        }
+    }
+
+    #[fbinit::test]
+    fn test_query_span(fb: FacebookInit) {
+        let spans = Spans::default();
+        let _guard = tracing::subscriber::set_default(spans.clone());
+
+        let pool = Pool::new(fb, "master_pool", PoolOptions::default(), || async {
+            Err(anyhow!("no server"))
+        });
+        let connections = SqlConnections {
+            write_connection: pool.clone().into(),
+            read_connection: Connection::with_sqlite(
+                rusqlite::Connection::open_in_memory().unwrap(),
+            ),
+            read_master_connection: pool.into(),
+        }
+        .with_label(ConnectionLabel::new().with_shard(7))
+        .read_your_writes(Duration::from_secs(60));
+        let run = |span: QuerySpan| {
+            futures::executor::block_on(span.run(|rows| *rows, async { Ok(3) })).unwrap()
+        };
+
+        run(QuerySpan::for_read(
+            "SelectFoo",
+            &connections.read_connection,
+        ));
+        let fields = spans.last();
+        assert_eq!(fields["name"], "SelectFoo");
+        assert_eq!(fields["shard"], "7");
+        assert!(!fields.contains_key("pool"));
+        assert_eq!(fields["connection"], "sqlite");
+        assert_eq!(fields["in_transaction"], "false");
+        assert_eq!(fields["rows"], "3");
+        assert!(fields.contains_key("latency_us"));
+
+        run(QuerySpan::for_write(
+            "InsertFoo",
+            &connections.write_connection,
+        ));
+        let fields = spans.last();
+        assert_eq!(fields["pool"], "master_pool");
+        assert_eq!(fields["connection"], "mysql_pool");
+
+        // After a write the reads go to the master
+        connections.write_connection.record_write();
+        run(QuerySpan::for_read(
+            "SelectFoo",
+            &connections.read_connection,
+        ));
+        let fields = spans.last();
+        assert_eq!(fields["shard"], "7");
+        assert_eq!(fields["pool"], "master_pool");
+        assert_eq!(fields["connection"], "mysql_pool");
+    }
+}
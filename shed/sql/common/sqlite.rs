@@ -8,13 +8,30 @@
  */
 
 //! Module containing sqlite related structures and traits
+//!
+//! The queries run on a worker thread of their [SqliteMultithreaded], see
+//! [SqliteMultithreaded::run], so that waiting for the connection and running
+//! the statements don't block the async runtime. The queries of a
+//! transaction run on a worker thread of the [SqliteConnectionGuard] holding
+//! its connection, see [SqliteConnectionGuard::run].
 
 #![allow(clippy::mutex_atomic)]
 
+use anyhow::{anyhow, bail, Error};
+use futures::channel::{mpsc, oneshot};
+use futures::executor::block_on;
+use futures::sink::SinkExt;
+use futures::stream::{BoxStream, StreamExt};
 use lazy_static::lazy_static;
-use rusqlite::Connection as SqliteConnection;
+use rusqlite::backup::Backup;
+use rusqlite::{Connection as SqliteConnection, OpenFlags};
+use std::fmt::{self, Display};
 use std::ops::Deref;
-use std::sync::{Arc, Condvar, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{mpsc as std_mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 
 lazy_static! {
     /// Lock to ensure that only one connection is in use for writes at a time inside the process
@@ -30,12 +47,204 @@ impl crate::Connection {
     pub fn with_sqlite(con: SqliteConnection) -> Self {
         SqliteMultithreaded::new(con).into()
     }
+
+    /// Like [crate::Connection::with_sqlite], configuring the connection with
+    /// `options` first.
+    pub fn with_sqlite_options(
+        con: SqliteConnection,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self, Error> {
+        Ok(SqliteMultithreaded::new_with_options(con, options)?.into())
+    }
+
+    /// Like [crate::Connection::with_sqlite], on a connection to the shared
+    /// in-memory database `name`, see [open_shared_memory].
+    pub fn with_sqlite_shared_memory(name: &str) -> Result<Self, Error> {
+        Ok(Self::with_sqlite(open_shared_memory(name)?))
+    }
+}
+
+/// Open a connection to the in-memory database `name`, which the connections
+/// of the process opening the same name share, e.g. so that the read and write
+/// connections of a test see the same rows. The database is deleted once its
+/// last connection is closed. `name` may only contain ASCII letters, digits,
+/// `_`, `-` and `.`.
+pub fn open_shared_memory(name: &str) -> Result<SqliteConnection, Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        bail!("invalid shared Sqlite database name {:?}", name);
+    }
+    Ok(SqliteConnection::open_with_flags(
+        format!("file:{}?mode=memory&cache=shared", name),
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_URI,
+    )?)
+}
+
+/// Journal mode of a sqlite database, see
+/// <https://www.sqlite.org/pragma.html#pragma_journal_mode>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SqliteJournalMode {
+    /// The rollback journal is deleted at the end of each transaction
+    Delete,
+    /// The rollback journal is truncated at the end of each transaction
+    Truncate,
+    /// The rollback journal header is zeroed at the end of each transaction
+    Persist,
+    /// The rollback journal is kept in memory
+    Memory,
+    /// Write-ahead log, letting readers run concurrently with a writer
+    Wal,
+    /// No rollback journal
+    Off,
+}
+
+impl Display for SqliteJournalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SqliteJournalMode::Delete => "DELETE",
+            SqliteJournalMode::Truncate => "TRUNCATE",
+            SqliteJournalMode::Persist => "PERSIST",
+            SqliteJournalMode::Memory => "MEMORY",
+            SqliteJournalMode::Wal => "WAL",
+            SqliteJournalMode::Off => "OFF",
+        })
+    }
 }
 
+/// How often sqlite waits for its writes to reach the disk, see
+/// <https://www.sqlite.org/pragma.html#pragma_synchronous>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SqliteSynchronous {
+    /// Never wait
+    Off,
+    /// Wait at the most critical moments, which is safe with the WAL journal
+    Normal,
+    /// Wait at every critical moment
+    Full,
+    /// Like Full, also waiting for the rollback journal to be deleted
+    Extra,
+}
+
+impl Display for SqliteSynchronous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SqliteSynchronous::Off => "OFF",
+            SqliteSynchronous::Normal => "NORMAL",
+            SqliteSynchronous::Full => "FULL",
+            SqliteSynchronous::Extra => "EXTRA",
+        })
+    }
+}
+
+/// Pragmas set on a sqlite connection when creating a [SqliteMultithreaded]
+/// with [SqliteMultithreaded::new_with_options]. The options left unset keep
+/// the sqlite defaults.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use sql_common::sqlite::{SqliteConnectionOptions, SqliteJournalMode, SqliteSynchronous};
+///
+/// let options = SqliteConnectionOptions::new()
+///     .journal_mode(SqliteJournalMode::Wal)
+///     .synchronous(SqliteSynchronous::Normal)
+///     .busy_timeout(Duration::from_secs(5));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SqliteConnectionOptions {
+    journal_mode: Option<SqliteJournalMode>,
+    busy_timeout: Option<Duration>,
+    synchronous: Option<SqliteSynchronous>,
+    cache_size: Option<i64>,
+}
+
+impl SqliteConnectionOptions {
+    /// Options keeping the sqlite defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the journal mode. In-memory databases ignore the WAL mode.
+    pub fn journal_mode(self, journal_mode: SqliteJournalMode) -> Self {
+        Self {
+            journal_mode: Some(journal_mode),
+            ..self
+        }
+    }
+
+    /// Wait for up to `busy_timeout` for the locks held by other connections
+    /// to the database instead of failing with `database is locked`
+    pub fn busy_timeout(self, busy_timeout: Duration) -> Self {
+        Self {
+            busy_timeout: Some(busy_timeout),
+            ..self
+        }
+    }
+
+    /// Set how often sqlite waits for its writes to reach the disk
+    pub fn synchronous(self, synchronous: SqliteSynchronous) -> Self {
+        Self {
+            synchronous: Some(synchronous),
+            ..self
+        }
+    }
+
+    /// Set the size of the page cache, in pages if positive or in KiB if
+    /// negative, like the `cache_size` pragma
+    pub fn cache_size(self, cache_size: i64) -> Self {
+        Self {
+            cache_size: Some(cache_size),
+            ..self
+        }
+    }
+
+    /// Set the pragmas on `con`
+    pub fn apply(&self, con: &SqliteConnection) -> Result<(), Error> {
+        if let Some(journal_mode) = self.journal_mode {
+            // Setting the journal mode returns the new mode, so it can't be
+            // run with execute
+            con.query_row(
+                &format!("PRAGMA journal_mode = {}", journal_mode),
+                rusqlite::NO_PARAMS,
+                |_| Ok(()),
+            )?;
+        }
+        if let Some(busy_timeout) = self.busy_timeout {
+            con.busy_timeout(busy_timeout)?;
+        }
+        if let Some(synchronous) = self.synchronous {
+            con.execute_batch(&format!("PRAGMA synchronous = {}", synchronous))?;
+        }
+        if let Some(cache_size) = self.cache_size {
+            con.execute_batch(&format!("PRAGMA cache_size = {}", cache_size))?;
+        }
+        Ok(())
+    }
+}
+
+/// Pages copied by each step of [SqliteMultithreaded::snapshot_to] and
+/// [SqliteMultithreaded::restore_from]
+const BACKUP_STEP_PAGES: i32 = 100;
+
+/// Pause between the steps of a snapshot or restore, in which the other
+/// connections to the databases can use them
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(5);
+
+/// Work sent to the worker thread of a [SqliteMultithreaded]
+type Job = Box<dyn FnOnce() + Send>;
+
 /// Wrapper around rusqlite connection that makes it fully thread safe (but not deadlock safe)
 pub struct SqliteMultithreaded {
     con: Arc<Mutex<Option<SqliteConnection>>>,
     condvar: Arc<Condvar>,
+    // Started on first use, and stopped once the connection is dropped
+    worker: Mutex<Option<std_mpsc::Sender<Job>>>,
 }
 
 /// Returns a guard that grabs a lock and connection. Can be used instead of SqliteConnection
@@ -46,6 +255,10 @@ pub struct SqliteConnectionGuard {
     condvar: Arc<Condvar>,
     // drop() need to remove the connection, so use Option<...> here
     con: Option<SqliteConnection>,
+    // Started by the first run(), and stopped once the guard is dropped. It
+    // can't be the worker of the SqliteMultithreaded, whose jobs wait for the
+    // connection this guard holds
+    worker: Option<std_mpsc::Sender<Job>>,
 }
 
 impl SqliteConnectionGuard {
@@ -74,6 +287,30 @@ impl SqliteConnectionGuard {
             m,
             condvar,
             con: Some(con),
+            worker: None,
+        }
+    }
+
+    /// Run `f` with the connection of this guard on a worker thread of the
+    /// guard, e.g. for the queries of a transaction holding the connection,
+    /// giving the guard back with the result of `f`. As for
+    /// [SqliteMultithreaded::run], running `f` doesn't block the caller, and
+    /// a panic of `f` is resumed in the caller.
+    pub async fn run<T, F>(mut self, f: F) -> (Self, Result<T, Error>)
+    where
+        T: Send + 'static,
+        F: FnOnce(&SqliteConnection) -> Result<T, Error> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let worker = self.worker.get_or_insert_with(spawn_worker).clone();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&self)));
+            let _ = sender.send((self, result));
+        });
+        worker.send(job).expect("sqlite worker thread stopped");
+        match receiver.await.expect("sqlite worker thread stopped") {
+            (guard, Ok(result)) => (guard, result),
+            (_, Err(payload)) => panic::resume_unwind(payload),
         }
     }
 }
@@ -105,9 +342,20 @@ impl SqliteMultithreaded {
         Self {
             con: Arc::new(Mutex::new(Some(con))),
             condvar: Arc::new(Condvar::new()),
+            worker: Mutex::new(None),
         }
     }
 
+    /// Create a new instance wrapping the provided sqlite connection, once
+    /// `options` are set on it.
+    pub fn new_with_options(
+        con: SqliteConnection,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self, Error> {
+        options.apply(&con)?;
+        Ok(Self::new(con))
+    }
+
     /// Returns a guard that grabs a lock and connection.
     /// When guard is destroyed then connection is put back and threads that are waiting for it
     /// are notified
@@ -117,4 +365,308 @@ impl SqliteMultithreaded {
     pub fn get_sqlite_guard(&self) -> SqliteConnectionGuard {
         SqliteConnectionGuard::new(self.con.clone(), self.condvar.clone())
     }
+
+    /// Like [SqliteMultithreaded::get_sqlite_guard], waiting for the
+    /// connection on the worker thread instead of blocking the caller
+    pub async fn get_sqlite_guard_async(&self) -> SqliteConnectionGuard {
+        self.dispatch(|guard| guard).await
+    }
+
+    /// Run `f` with the connection on the worker thread of this connection,
+    /// which runs the calls one at a time in order. Waiting for the
+    /// connection and running `f` don't block the caller, and a panic of `f`
+    /// is resumed in the caller.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SqliteConnection) -> Result<T, Error> + Send + 'static,
+    {
+        self.dispatch(move |guard| f(&guard)).await
+    }
+
+    /// Copy the database of this connection to the file at `path`, replacing
+    /// its database if it has one, with the online backup of sqlite. The copy
+    /// is made in steps, in between which the other connections to the
+    /// database may read and write it, and it restarts when one of them
+    /// writes, so that the snapshot is the database at the end of the copy.
+    /// The connections of this crate wait for the copy, as for any use of a
+    /// sqlite connection.
+    pub async fn snapshot_to(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref().to_owned();
+        self.run(move |con| {
+            let mut snapshot = SqliteConnection::open(&path)?;
+            Backup::new(con, &mut snapshot)?.run_to_completion(
+                BACKUP_STEP_PAGES,
+                BACKUP_STEP_PAUSE,
+                None,
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replace the database of this connection by the one in the file at
+    /// `path`, e.g. a snapshot of [SqliteMultithreaded::snapshot_to], with
+    /// the online backup of sqlite. The other connections to the database
+    /// see the restored database once it completes.
+    pub async fn restore_from(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref().to_owned();
+        self.dispatch(move |mut guard| {
+            let snapshot =
+                SqliteConnection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let con = guard
+                .con
+                .as_mut()
+                .expect("invariant violation - connection taken before drop()");
+            Backup::new(&snapshot, con)?.run_to_completion(
+                BACKUP_STEP_PAGES,
+                BACKUP_STEP_PAUSE,
+                None,
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn dispatch<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(SqliteConnectionGuard) -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let (con, condvar) = (self.con.clone(), self.condvar.clone());
+        self.send_job(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                f(SqliteConnectionGuard::new(con, condvar))
+            }));
+            let _ = sender.send(result);
+        }));
+        // The worker thread catches the panics of the jobs, so it runs each
+        // of them
+        match receiver.await.expect("sqlite worker thread stopped") {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    fn send_job(&self, job: Job) {
+        let mut worker = self.worker.lock().expect("lock poisoned");
+        let sender = worker.get_or_insert_with(spawn_worker);
+        sender.send(job).expect("sqlite worker thread stopped");
+    }
+}
+
+/// Start a thread running the jobs sent to it in order, until the sender is
+/// dropped
+fn spawn_worker() -> std_mpsc::Sender<Job> {
+    let (sender, receiver) = std_mpsc::channel::<Job>();
+    thread::Builder::new()
+        .name("sqlite".to_owned())
+        .spawn(move || {
+            for job in receiver {
+                job();
+            }
+        })
+        .expect("failed to spawn the sqlite worker thread");
+    sender
+}
+
+/// Number of items buffered by a stream of [SqliteMultithreaded::stream]
+/// before its producer waits for them to be consumed
+const STREAM_BUFFER: usize = 1024;
+
+impl SqliteMultithreaded {
+    /// Run `produce` with the connection on the worker thread of this
+    /// connection and return a stream of the items it emits. `produce` is
+    /// given a function sending an item, which returns false once the stream
+    /// was dropped, and an error it returns, or its panic, ends the stream.
+    /// The worker runs `produce` until it returns, so the other uses of the
+    /// connection wait for the stream to be consumed or dropped.
+    pub fn stream<T, F>(&self, produce: F) -> BoxStream<'static, Result<T, Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SqliteConnection, &mut dyn FnMut(T) -> bool) -> Result<(), Error>
+            + Send
+            + 'static,
+    {
+        let (mut sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let (con, condvar) = (self.con.clone(), self.condvar.clone());
+        self.send_job(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let guard = SqliteConnectionGuard::new(con, condvar);
+                let mut send = |item| block_on(sender.send(Ok(item))).is_ok();
+                produce(&guard, &mut send)
+            }));
+            let err = match result {
+                Ok(Ok(())) => return,
+                Ok(Err(err)) => err,
+                Err(_) => anyhow!("sqlite stream panicked"),
+            };
+            let _ = block_on(sender.send(Err(err)));
+        }));
+        receiver.boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pragma(con: &SqliteMultithreaded, name: &str) -> String {
+        con.get_sqlite_guard()
+            .query_row(&format!("PRAGMA {}", name), rusqlite::NO_PARAMS, |row| {
+                row.get::<_, rusqlite::types::Value>(0)
+            })
+            .map(|value| format!("{:?}", value))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_connection_options() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("sqlite_options_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let options = SqliteConnectionOptions::new()
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_millis(1500))
+            .synchronous(SqliteSynchronous::Normal)
+            .cache_size(-4096);
+        let con = SqliteMultithreaded::new_with_options(
+            SqliteConnection::open(dir.join("test.db"))?,
+            &options,
+        )?;
+        assert_eq!(pragma(&con, "journal_mode"), "Text(\"wal\")");
+        assert_eq!(pragma(&con, "busy_timeout"), "Integer(1500)");
+        assert_eq!(pragma(&con, "synchronous"), "Integer(1)");
+        assert_eq!(pragma(&con, "cache_size"), "Integer(-4096)");
+        drop(con);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run() -> Result<(), Error> {
+        let con = Arc::new(SqliteMultithreaded::new(SqliteConnection::open_in_memory()?));
+        let answer: i64 = con
+            .run(|con| Ok(con.query_row("SELECT 42", rusqlite::NO_PARAMS, |row| row.get(0))?))
+            .await?;
+        assert_eq!(answer, 42);
+        assert!(con
+            .run(|con| Ok(con.execute_batch("SELECT x")?))
+            .await
+            .is_err());
+
+        // Waiting for the connection doesn't block this single threaded runtime
+        let guard = con.get_sqlite_guard_async().await;
+        let mut run = tokio::spawn({
+            let con = con.clone();
+            async move { con.run(|con| Ok(con.is_autocommit())).await }
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut run)
+            .await
+            .is_err());
+        drop(guard);
+        assert!(run.await.unwrap()?);
+
+        let panicked = tokio::spawn({
+            let con = con.clone();
+            async move { con.run(|_| -> Result<(), Error> { panic!("boom") }).await }
+        });
+        assert!(panicked.await.unwrap_err().is_panic());
+        assert!(con.run(|_| Ok(())).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guard_run() -> Result<(), Error> {
+        let con = Arc::new(SqliteMultithreaded::new(SqliteConnection::open_in_memory()?));
+        let guard = con.get_sqlite_guard_async().await;
+        let (guard, result) = guard
+            .run(|con| Ok(con.execute_batch("BEGIN; CREATE TABLE foo (x INTEGER)")?))
+            .await;
+        result?;
+        let thread = std::thread::current().id();
+        let (guard, result) = guard
+            .run(move |con| {
+                assert_ne!(std::thread::current().id(), thread);
+                Ok(con.is_autocommit())
+            })
+            .await;
+        assert!(!result?);
+        let (guard, result) = guard.run(|con| Ok(con.execute_batch("SELECT y")?)).await;
+        assert!(result.is_err());
+        let (guard, result) = guard.run(|con| Ok(con.execute_batch("COMMIT")?)).await;
+        result?;
+        drop(guard);
+
+        let tables: i64 = con
+            .run(|con| {
+                Ok(con.query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE name = 'foo'",
+                    rusqlite::NO_PARAMS,
+                    |row| row.get(0),
+                )?)
+            })
+            .await?;
+        assert_eq!(tables, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("sqlite_snapshot_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("snapshot.db");
+        let count = |con: &SqliteConnection| -> Result<i64, Error> {
+            Ok(
+                con.query_row("SELECT COUNT(*) FROM foo", rusqlite::NO_PARAMS, |row| {
+                    row.get(0)
+                })?,
+            )
+        };
+
+        let con = SqliteMultithreaded::new(SqliteConnection::open_in_memory()?);
+        con.run(|con| {
+            Ok(con.execute_batch("CREATE TABLE foo(x INTEGER); INSERT INTO foo VALUES (1), (2)")?)
+        })
+        .await?;
+        con.snapshot_to(&path).await?;
+        // A connection to the snapshot sees the next one
+        let reader = SqliteConnection::open(&path)?;
+        assert_eq!(count(&reader)?, 2);
+        con.run(|con| Ok(con.execute_batch("INSERT INTO foo VALUES (3)")?))
+            .await?;
+        con.snapshot_to(&path).await?;
+        assert_eq!(count(&reader)?, 3);
+
+        let restored = SqliteMultithreaded::new(SqliteConnection::open_in_memory()?);
+        restored
+            .run(|con| Ok(con.execute_batch("CREATE TABLE bar(y INTEGER)")?))
+            .await?;
+        restored.restore_from(&path).await?;
+        assert_eq!(restored.run(count).await?, 3);
+        assert!(restored
+            .run(|con| Ok(con.execute_batch("SELECT y FROM bar")?))
+            .await
+            .is_err());
+        assert!(restored.restore_from(dir.join("missing.db")).await.is_err());
+
+        drop(reader);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_shared_memory() -> Result<(), Error> {
+        let first = open_shared_memory("test_open_shared_memory")?;
+        first.execute_batch("CREATE TABLE foo(x INTEGER); INSERT INTO foo VALUES (42)")?;
+        let second = open_shared_memory("test_open_shared_memory")?;
+        let x: i64 =
+            second.query_row("SELECT x FROM foo", rusqlite::NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(x, 42);
+
+        let other = open_shared_memory("test_open_shared_memory_other")?;
+        assert!(other.execute_batch("SELECT x FROM foo").is_err());
+        assert!(open_shared_memory("foo?mode=rw").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [QueryTag], the identity of the caller sent as a leading
+//! comment of the queries, e.g. `/* client:foo req:123 */ SELECT ...`, so
+//! that the processlist and the slow query logs tell which service and
+//! request the load comes from. A tag is attached to every query of a
+//! connection with [Connection::with_query_tag], or to the queries run by a
+//! future with [scope]:
+//!
+//! ```ignore
+//! let conn = conn.with_query_tag(QueryTag::new().with("client", "foo"));
+//! let tag = QueryTag::new().with("req", request_id);
+//! let rows = tag::scope(tag, SelectFoo::query(&conn, &x)).await?;
+//! ```
+//!
+//! The queries sent to Mysql and Postgres are tagged, including those of the
+//! transactions run in a [scope], while the Sqlite queries are sent as they
+//! are.
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::{Connection, ConnectionLayer, Unwrap};
+
+tokio::task_local! {
+    static SCOPE_TAG: QueryTag;
+}
+
+/// Pairs of names and values of a query comment, e.g. `client:foo`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueryTag {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryTag {
+    /// Tag without any pair
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the pair `name:value`, replacing the value of `name` if the tag
+    /// already has it. The characters other than ASCII letters, digits, `_`,
+    /// `-` and `.` are replaced by `_`, so that tags can't end the comment or
+    /// split a pair.
+    pub fn with(mut self, name: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        let name = sanitize(name.as_ref());
+        let value = sanitize(value.as_ref());
+        match self.pairs.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.pairs.push((name, value)),
+        }
+        self
+    }
+
+    /// The pairs of the tag, in the order they were added
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Whether the tag has no pair
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// This tag with the pairs of `other` added, see [QueryTag::with]
+    pub fn merge(&self, other: &QueryTag) -> QueryTag {
+        other
+            .pairs()
+            .fold(self.clone(), |tag, (name, value)| tag.with(name, value))
+    }
+
+    /// The comment of the tag, e.g. `/* client:foo req:123 */`, or an empty
+    /// string for an empty tag
+    pub fn comment(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let pairs: Vec<_> = self
+            .pairs()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect();
+        format!("/* {} */", pairs.join(" "))
+    }
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Run `future` with the queries it sends tagged with `tag`, merged into the
+/// tag of the enclosing scope if any
+pub async fn scope<F: Future>(tag: QueryTag, future: F) -> F::Output {
+    let tag = match current() {
+        Some(current) => current.merge(&tag),
+        None => tag,
+    };
+    SCOPE_TAG.scope(tag, future).await
+}
+
+/// The tag of the enclosing [scope], if any
+pub fn current() -> Option<QueryTag> {
+    SCOPE_TAG.try_with(QueryTag::clone).ok()
+}
+
+/// Connection of [Connection::with_query_tag]
+pub struct TaggedConnection {
+    connection: Connection,
+    tag: QueryTag,
+}
+
+impl TaggedConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The tag of the queries
+    pub fn tag(&self) -> &QueryTag {
+        &self.tag
+    }
+}
+
+impl ConnectionLayer for TaggedConnection {
+    fn kind(&self) -> &'static str {
+        "Tagged"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection sending the queries of this connection with the comment of
+    /// `tag`, see [crate::tag]. The pairs of a [scope] the queries run in are
+    /// added to it.
+    pub fn with_query_tag(self, tag: QueryTag) -> Connection {
+        Connection::Layered(Arc::new(TaggedConnection {
+            connection: self,
+            tag,
+        }))
+    }
+
+    /// The tag of the first tagged connection on the way to the backend, if
+    /// any
+    pub fn query_tag(&self) -> Option<&QueryTag> {
+        self.layer::<TaggedConnection>().map(TaggedConnection::tag)
+    }
+}
+
+/// Run `future`, a query on `connection`, in the scope of the tag of the
+/// connection if it has one. This should never be used directly, it is made
+/// public so that the queries! macro can make use of it
+#[doc(hidden)]
+pub async fn with_connection_tag<F: Future>(connection: &Connection, future: F) -> F::Output {
+    match connection.query_tag() {
+        Some(tag) => {
+            let tag = match current() {
+                Some(current) => tag.merge(&current),
+                None => tag.clone(),
+            };
+            SCOPE_TAG.scope(tag, future).await
+        }
+        None => future.await,
+    }
+}
+
+/// `query` with the comment of the tag of the enclosing [scope] in front of
+/// it. This should never be used directly, it is made public so that the
+/// queries! macro can make use of it
+#[doc(hidden)]
+pub fn tag_query(query: String) -> String {
+    match SCOPE_TAG.try_with(QueryTag::comment) {
+        Ok(comment) if !comment.is_empty() => format!("{} {}", comment, query),
+        _ => query,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comment() {
+        let tag = QueryTag::new()
+            .with("client", "foo")
+            .with("req", "123")
+            .with("client", "bar */ DROP TABLE foo");
+        assert_eq!(tag.comment(), "/* client:bar____DROP_TABLE_foo req:123 */");
+        assert_eq!(QueryTag::new().comment(), "");
+        assert_eq!(
+            QueryTag::new()
+                .with("a:b", "c d")
+                .pairs()
+                .collect::<Vec<_>>(),
+            vec![("a_b", "c_d")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(tag_query("SELECT 1".to_owned()), "SELECT 1");
+        assert_eq!(current(), None);
+
+        let outer = QueryTag::new().with("client", "foo").with("req", "1");
+        let inner = QueryTag::new().with("req", "2");
+        let query = scope(outer, async {
+            scope(inner, async { tag_query("SELECT 1".to_owned()) }).await
+        })
+        .await;
+        assert_eq!(query, "/* client:foo req:2 */ SELECT 1");
+    }
+
+    #[tokio::test]
+    async fn test_tagged_connection() {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert_eq!(conn.query_tag(), None);
+
+        let tag = QueryTag::new().with("client", "foo");
+        let conn = conn.with_query_tag(tag.clone()).readonly();
+        assert_eq!(conn.query_tag(), Some(&tag));
+        assert!(matches!(conn.backend(), Connection::Sqlite(_)));
+        let query = with_connection_tag(&conn, async { tag_query("SELECT 1".to_owned()) }).await;
+        assert_eq!(query, "/* client:foo */ SELECT 1");
+    }
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing [TestDatabase], which creates the schema of the tests
+//! once and gives each test case its own shared in-memory Sqlite database
+//! with it, see [crate::sqlite::open_shared_memory]. The test cases can run
+//! in parallel without seeing each other's rows, and without the cost of
+//! creating a database file per test case.
+//!
+//! # Example
+//! ```
+//! use anyhow::Error;
+//! use lazy_static::lazy_static;
+//! use sql_common::test_database::TestDatabase;
+//!
+//! lazy_static! {
+//!     static ref DATABASE: TestDatabase =
+//!         TestDatabase::new("example", "CREATE TABLE foo(x INTEGER)").unwrap();
+//! }
+//!
+//! fn test_foo() -> Result<(), Error> {
+//!     let connections = DATABASE.connections()?;
+//!     // Use connections.write_connection and connections.read_connection
+//!     Ok(())
+//! }
+//! #
+//! # fn main() {
+//! #     test_foo().unwrap();
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Error;
+use rusqlite::backup::Backup;
+use rusqlite::Connection as SqliteConnection;
+
+use crate::sqlite::open_shared_memory;
+use crate::{Connection, SqlConnections};
+
+/// Databases created in the process, numbering them so that their names are
+/// unique
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+/// Schema copied to a new database for each test case
+pub struct TestDatabase {
+    name: String,
+    template: Mutex<SqliteConnection>,
+}
+
+impl TestDatabase {
+    /// Create the schema with `schema_sql`. The databases are named after
+    /// `name`.
+    pub fn new(name: impl Into<String>, schema_sql: &str) -> Result<Self, Error> {
+        let template = SqliteConnection::open_in_memory()?;
+        template.execute_batch(schema_sql)?;
+        Ok(Self {
+            name: name.into(),
+            template: Mutex::new(template),
+        })
+    }
+
+    /// Connections to a new database with the schema, independent of those
+    /// of the other calls. Each of them is a separate connection to the
+    /// database.
+    pub fn connections(&self) -> Result<SqlConnections, Error> {
+        let name = format!(
+            "{}_{}",
+            self.name,
+            DATABASES.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut write = open_shared_memory(&name)?;
+        {
+            let template = self.template.lock().expect("lock poisoned");
+            Backup::new(&template, &mut write)?.run_to_completion(100, Duration::ZERO, None)?;
+        }
+        Ok(SqlConnections {
+            write_connection: Connection::with_sqlite(write),
+            read_connection: Connection::with_sqlite_shared_memory(&name)?,
+            read_master_connection: Connection::with_sqlite_shared_memory(&name)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn execute(connection: &Connection, sql: &str) -> Result<(), Error> {
+        match connection {
+            Connection::Sqlite(con) => Ok(con.get_sqlite_guard().execute_batch(sql)?),
+            _ => panic!("not a Sqlite connection"),
+        }
+    }
+
+    fn count(connection: &Connection) -> i64 {
+        match connection {
+            Connection::Sqlite(con) => con
+                .get_sqlite_guard()
+                .query_row("SELECT COUNT(*) FROM foo", rusqlite::NO_PARAMS, |row| {
+                    row.get(0)
+                })
+                .unwrap(),
+            _ => panic!("not a Sqlite connection"),
+        }
+    }
+
+    #[test]
+    fn test_connections() -> Result<(), Error> {
+        let database = TestDatabase::new("test_connections", "CREATE TABLE foo(x INTEGER)")?;
+        let first = database.connections()?;
+        let second = database.connections()?;
+        execute(&first.write_connection, "INSERT INTO foo VALUES (1), (2)")?;
+        assert_eq!(count(&first.read_connection), 2);
+        assert_eq!(count(&first.read_master_connection), 2);
+        assert_eq!(count(&second.read_connection), 0);
+
+        assert!(TestDatabase::new("test_connections", "CREATE TABLE").is_err());
+        Ok(())
+    }
+}
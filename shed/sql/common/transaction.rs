@@ -9,10 +9,18 @@
 
 //! Module that provides support for SQL transactions to this library.
 
-use anyhow::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{bail, format_err, Context, Error};
 use futures::future::TryFutureExt;
 
+use crate::error::{error_kind, ErrorKind};
 use crate::mysql;
+use crate::observer::{QueryInfo, QueryObserver};
+use crate::postgres;
+use crate::retry::RetryPolicy;
 use crate::sqlite::SqliteConnectionGuard;
 
 impl crate::Connection {
@@ -23,7 +31,7 @@ impl crate::Connection {
     }
 }
 
-/// Enum for generalizing transactions over Sqlite and MyRouter.
+/// Enum for generalizing transactions over Sqlite, MyRouter and Postgres.
 ///
 /// # Example
 /// ```
@@ -64,6 +72,20 @@ pub enum Transaction {
     Sqlite(Option<SqliteConnectionGuard>),
     /// A variant used for the new Mysql client connection.
     Mysql(Option<mysql::Transaction>),
+    /// A Mysql transaction holding a connection of a pool until it completes.
+    MysqlPool(Option<mysql::PooledTransaction>),
+    /// A Postgres transaction. Like for Sqlite, it holds the connection until
+    /// it is completed, and it is rolled back when dropped.
+    Postgres(Option<postgres::Transaction>),
+    /// A transaction started on a connection with an observer, which reports
+    /// its queries to it.
+    Observed(Option<Box<ObservedTransaction>>),
+}
+
+/// Transaction of a connection with a [QueryObserver]
+pub struct ObservedTransaction {
+    transaction: Transaction,
+    observer: Arc<dyn QueryObserver>,
 }
 
 impl Transaction {
@@ -76,27 +98,288 @@ impl Transaction {
     /// Create a new transaction for the provided connection using provided
     /// transaction options.
     pub async fn new_with_options(connection: &super::Connection) -> Result<Transaction, Error> {
-        match connection {
-            super::Connection::Sqlite(con) => {
-                let con = con.get_sqlite_guard();
+        Transaction::begin(connection, None)
+            .await
+            .with_context(|| crate::label::context(connection, "While starting transaction"))
+    }
+
+    /// Create an XA transaction identified by `xid` for the provided Mysql
+    /// connection, which is prepared by [Transaction::xa_prepare] before it
+    /// is committed. Fails for the other databases.
+    pub(crate) async fn new_xa(
+        connection: &super::Connection,
+        xid: String,
+    ) -> Result<Transaction, Error> {
+        Transaction::begin(connection, Some(xid))
+            .await
+            .with_context(|| crate::label::context(connection, "While starting XA transaction"))
+    }
+
+    async fn begin(
+        connection: &super::Connection,
+        xid: Option<String>,
+    ) -> Result<Transaction, Error> {
+        // Any transaction may write
+        connection.check_write("transaction")?;
+        connection.acquire_write().await?;
+        // Counted as a write from its start, so that the reads that follow
+        // it see its writes
+        connection.record_write();
+        let start = Instant::now();
+        let statement = if xid.is_some() { "XA START" } else { "BEGIN" };
+        let transaction = match (connection.backend(), xid) {
+            (super::Connection::Mysql(conn), Some(xid)) => {
+                let transaction = conn.begin_xa_transaction(xid).map_err(Error::from).await?;
+                Ok(Transaction::Mysql(Some(transaction)))
+            }
+            (super::Connection::MysqlPool(pool), Some(xid)) => {
+                let transaction = mysql::PooledTransaction::new_xa(pool, xid).await?;
+                Ok(Transaction::MysqlPool(Some(killing(
+                    connection,
+                    transaction,
+                ))))
+            }
+            (_, Some(_)) => Err(format_err!("XA transactions are only supported by Mysql")),
+            (super::Connection::Sqlite(con), None) => {
+                let con = con.get_sqlite_guard_async().await;
                 // Transactions in SQLite are always SERIALIZABLE; no transaction options.
-                con.execute_batch("BEGIN DEFERRED")
-                    .map(move |_| Transaction::Sqlite(Some(con)))
-                    .map_err(failure_ext::convert)
+                let (con, res) = con
+                    .run(|con| Ok(con.execute_batch("BEGIN DEFERRED")?))
+                    .await;
+                res.map(move |_| Transaction::Sqlite(Some(con)))
             }
-            super::Connection::Mysql(conn) => {
+            (super::Connection::Mysql(conn), None) => {
                 let transaction = conn.begin_transaction().map_err(Error::from).await?;
                 Ok(Transaction::Mysql(Some(transaction)))
             }
+            (super::Connection::MysqlPool(pool), None) => {
+                let transaction = mysql::PooledTransaction::new(pool).await?;
+                Ok(Transaction::MysqlPool(Some(killing(
+                    connection,
+                    transaction,
+                ))))
+            }
+            (super::Connection::Postgres(conn), None) => {
+                let transaction = conn.begin_transaction().await?;
+                Ok(Transaction::Postgres(Some(transaction)))
+            }
+            (super::Connection::Layered(_), None) => {
+                unreachable!("backend is never a wrapping connection")
+            }
+        };
+        let observer = connection.observer().cloned();
+        observe_statement(observer.as_ref(), statement, start, &transaction);
+        transaction.map(|transaction| transaction.with_observer(observer))
+    }
+
+    /// Split the transaction from its observer, which is None if it has
+    /// none. This should never be used directly, it is made public so that
+    /// the queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn take_observer(mut self) -> (Transaction, Option<Arc<dyn QueryObserver>>) {
+        if let Transaction::Observed(ref mut observed) = self {
+            let ObservedTransaction {
+                transaction,
+                observer,
+            } = *observed
+                .take()
+                .expect("should be Some before transaction ended");
+            return (transaction, Some(observer));
+        }
+        (self, None)
+    }
+
+    /// Give back its observer to a transaction split by [Transaction::take_observer].
+    /// This should never be used directly, it is made public so that the
+    /// queries! macro can make use of it
+    #[doc(hidden)]
+    pub fn with_observer(self, observer: Option<Arc<dyn QueryObserver>>) -> Transaction {
+        match observer {
+            Some(observer) => Transaction::Observed(Some(Box::new(ObservedTransaction {
+                transaction: self,
+                observer,
+            }))),
+            None => self,
+        }
+    }
+
+    /// Create a savepoint named `name`, which is an SQL identifier, to which
+    /// the transaction can be rolled back without aborting it
+    pub async fn savepoint(&mut self, name: &str) -> Result<(), Error> {
+        self.execute(format!("SAVEPOINT {}", savepoint_name(name)?))
+            .await
+    }
+
+    /// Roll back the changes made since the savepoint `name` was created,
+    /// which remains and can be rolled back to again
+    pub async fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), Error> {
+        self.execute(format!("ROLLBACK TO SAVEPOINT {}", savepoint_name(name)?))
+            .await
+    }
+
+    /// Remove the savepoint `name` and the ones created after it, keeping
+    /// the changes made since
+    pub async fn release_savepoint(&mut self, name: &str) -> Result<(), Error> {
+        self.execute(format!("RELEASE SAVEPOINT {}", savepoint_name(name)?))
+            .await
+    }
+
+    /// The transaction running the queries and its observer, if any
+    fn unwrap_observed(&mut self) -> (&mut Transaction, Option<&Arc<dyn QueryObserver>>) {
+        match self {
+            Transaction::Observed(observed) => {
+                let observed = observed
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                (&mut observed.transaction, Some(&observed.observer))
+            }
+            transaction => (transaction, None),
+        }
+    }
+
+    /// The transaction running the queries, unwrapping an observed one
+    #[cfg(feature = "tracing")]
+    pub(crate) fn backend(&self) -> &Transaction {
+        match self {
+            Transaction::Observed(observed) => {
+                &observed
+                    .as_ref()
+                    .expect("should be Some before transaction ended")
+                    .transaction
+            }
+            transaction => transaction,
+        }
+    }
+
+    /// Run an SQL statement returning no rows
+    pub(crate) async fn execute(&mut self, statement: String) -> Result<(), Error> {
+        let (transaction, observer) = self.unwrap_observed();
+        let start = Instant::now();
+        let result = transaction.execute_backend(&statement).await;
+        observe_statement(observer, &statement, start, &result);
+        result
+    }
+
+    async fn execute_backend(&mut self, statement: &str) -> Result<(), Error> {
+        match self {
+            Transaction::Sqlite(con) => {
+                let statement = statement.to_owned();
+                let (guard, res) = con
+                    .take()
+                    .expect("should be Some before transaction ended")
+                    .run(move |con| Ok(con.execute_batch(&statement)?))
+                    .await;
+                *con = Some(guard);
+                res
+            }
+            Transaction::Mysql(tr) => {
+                tr.as_mut()
+                    .expect("should be Some before transaction ended")
+                    .write_query(statement.to_owned())
+                    .map_err(Error::from)
+                    .await?;
+                Ok(())
+            }
+            Transaction::MysqlPool(tr) => {
+                let tr = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                mysql::QueryKiller::run(tr.killer(), statement.to_owned(), |statement| {
+                    tr.write_query(statement)
+                })
+                .await?;
+                Ok(())
+            }
+            Transaction::Postgres(tr) => {
+                tr.as_mut()
+                    .expect("should be Some before transaction ended")
+                    .write_query(statement.to_owned(), Vec::new())
+                    .await?;
+                Ok(())
+            }
+            Transaction::Observed(_) => unreachable!("observed transactions are unwrapped"),
+        }
+    }
+
+    /// Run an SQL query returning a single non-negative integer
+    pub(crate) async fn read_u64(&mut self, query: &str) -> Result<u64, Error> {
+        let missing = || format_err!("no value returned by {}", query);
+        match self.unwrap_observed().0 {
+            Transaction::Sqlite(con) => {
+                let query = query.to_owned();
+                let (guard, res) = con
+                    .take()
+                    .expect("should be Some before transaction ended")
+                    .run(move |con| {
+                        let value: i64 = con
+                            .query_row(&query, rusqlite::NO_PARAMS, |row| row.get(0))
+                            .map_err(failure_ext::convert)?;
+                        Ok(value as u64)
+                    })
+                    .await;
+                *con = Some(guard);
+                res
+            }
+            Transaction::Mysql(tr) => {
+                let rows: Vec<(u64,)> = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended")
+                    .read_query(query.to_owned())
+                    .map_err(Error::from)
+                    .await?;
+                rows.into_iter()
+                    .next()
+                    .map(|(value,)| value)
+                    .ok_or_else(missing)
+            }
+            Transaction::MysqlPool(tr) => {
+                let rows: Vec<(u64,)> = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended")
+                    .read_query(query.to_owned())
+                    .map_err(Error::from)
+                    .await?;
+                rows.into_iter()
+                    .next()
+                    .map(|(value,)| value)
+                    .ok_or_else(missing)
+            }
+            Transaction::Postgres(tr) => {
+                let rows = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended")
+                    .read_query(query.to_owned(), Vec::new())
+                    .await?;
+                let value = rows
+                    .into_iter()
+                    .next()
+                    .and_then(|row| row.into_iter().next())
+                    .ok_or_else(missing)?;
+                mysql_async::from_value_opt(value)
+                    .map_err(|err| format_err!("invalid value returned by {}: {}", query, err))
+            }
+            Transaction::Observed(_) => unreachable!("observed transactions are unwrapped"),
         }
     }
 
     /// Perform a commit on this transaction
-    pub async fn commit(mut self) -> Result<(), Error> {
+    pub async fn commit(self) -> Result<(), Error> {
+        let (transaction, observer) = self.take_observer();
+        let start = Instant::now();
+        let result = transaction.commit_backend().await;
+        observe_statement(observer.as_ref(), "COMMIT", start, &result);
+        result
+    }
+
+    async fn commit_backend(mut self) -> Result<(), Error> {
         match self {
             Transaction::Sqlite(ref mut con) => {
-                let actual_con = con.take().unwrap();
-                let res = match actual_con.execute_batch("COMMIT") {
+                let (actual_con, res) = con
+                    .take()
+                    .unwrap()
+                    .run(|con| Ok(con.execute_batch("COMMIT")?))
+                    .await;
+                let res = match res {
                     // Successfully committed, need to give the connection back
                     Ok(()) => Ok(()),
                     // Put it back so rollback will be performed on drop
@@ -112,11 +395,54 @@ impl Transaction {
                 let tr = tr.take().expect("Called commit after drop");
                 Ok(tr.commit().await?)
             }
+            Transaction::MysqlPool(ref mut tr) => {
+                let tr = tr.take().expect("Called commit after drop");
+                tr.commit().await
+            }
+            Transaction::Postgres(ref mut tr) => {
+                let tr = tr.take().expect("Called commit after drop");
+                tr.commit().await
+            }
+            Transaction::Observed(_) => unreachable!("observed transactions are unwrapped"),
         }
     }
 
+    /// Prepare an XA transaction created by [Transaction::new_xa], which
+    /// can then only be committed or rolled back
+    pub(crate) async fn xa_prepare(&mut self) -> Result<(), Error> {
+        let (transaction, observer) = self.unwrap_observed();
+        let start = Instant::now();
+        let result = match transaction {
+            Transaction::Mysql(tr) => {
+                tr.as_mut()
+                    .expect("should be Some before transaction ended")
+                    .xa_prepare()
+                    .map_err(Error::from)
+                    .await
+            }
+            Transaction::MysqlPool(tr) => {
+                tr.as_mut()
+                    .expect("should be Some before transaction ended")
+                    .xa_prepare()
+                    .map_err(Error::from)
+                    .await
+            }
+            _ => Err(format_err!("XA transactions are only supported by Mysql")),
+        };
+        observe_statement(observer, "XA PREPARE", start, &result);
+        result
+    }
+
     /// Perform a rollback on this transaction
-    pub async fn rollback(mut self) -> Result<(), Error> {
+    pub async fn rollback(self) -> Result<(), Error> {
+        let (transaction, observer) = self.take_observer();
+        let start = Instant::now();
+        let result = transaction.rollback_backend().await;
+        observe_statement(observer.as_ref(), "ROLLBACK", start, &result);
+        result
+    }
+
+    async fn rollback_backend(mut self) -> Result<(), Error> {
         match self {
             // Sqlite will rollback on drop
             Transaction::Sqlite(..) => Ok(()),
@@ -124,10 +450,126 @@ impl Transaction {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.rollback().await?)
             }
+            Transaction::MysqlPool(ref mut tr) => {
+                let tr = tr.take().expect("Called rollback after drop");
+                tr.rollback().await
+            }
+            Transaction::Postgres(ref mut tr) => {
+                let tr = tr.take().expect("Called rollback after drop");
+                tr.rollback().await
+            }
+            Transaction::Observed(_) => unreachable!("observed transactions are unwrapped"),
         }
     }
 }
 
+/// `transaction`, killing its dropped queries if `connection` does
+fn killing(
+    connection: &super::Connection,
+    transaction: mysql::PooledTransaction,
+) -> mysql::PooledTransaction {
+    if connection.kills_on_drop() {
+        transaction.with_kill_on_drop()
+    } else {
+        transaction
+    }
+}
+
+/// Report a statement starting, ending or run by a transaction
+fn observe_statement<T>(
+    observer: Option<&Arc<dyn QueryObserver>>,
+    statement: &str,
+    start: Instant,
+    result: &Result<T, Error>,
+) {
+    if let Some(observer) = observer {
+        observer.observe(&QueryInfo {
+            name: statement,
+            sql: statement,
+            params: "",
+            duration: start.elapsed(),
+            result: result.as_ref().map(|_| 0),
+            in_transaction: true,
+        });
+    }
+}
+
+/// Whether a transaction failing with `err` might succeed if run again from
+/// its start, i.e. if it failed on a conflict, see [ErrorKind::Conflict].
+/// Errors tagged as retriable or not with [failure_ext::ErrorTagsExt] are
+/// classified by [failure_ext::retriable].
+pub fn is_retriable(err: &Error) -> bool {
+    failure_ext::retriable(err).unwrap_or_else(|| error_kind(err) == ErrorKind::Conflict)
+}
+
+/// Run `body` in a transaction on `connection` and commit it. When `body` or
+/// the commit fail with an error that [is_retriable], the transaction is
+/// rolled back and `body` runs again in a new one, for at most
+/// `policy.max_attempts` attempts with the backoff of `policy`, see
+/// [futures_03_ext::retry::retry]. As it may
+/// run several times, `body` should have no effect outside the transaction.
+///
+/// # Example
+/// ```
+/// use anyhow::Error;
+/// use sql::{queries, Connection};
+/// use sql_common::retry::RetryPolicy;
+/// use sql_common::transaction;
+///
+/// queries! {
+///     write IncrementAll() {
+///         none,
+///         "UPDATE foo SET x = x + 1"
+///     }
+/// }
+///
+/// async fn increment_all(conn: &Connection) -> Result<u64, Error> {
+///     transaction::retry(conn, &RetryPolicy::default(), |transaction| async move {
+///         let (transaction, res) = IncrementAll::query_with_transaction(transaction).await?;
+///         Ok((transaction, res.affected_rows()))
+///     })
+///     .await
+/// }
+/// #
+/// # fn main() {}
+/// ```
+pub async fn retry<T, F, Fut>(
+    connection: &crate::Connection,
+    policy: &RetryPolicy,
+    body: F,
+) -> Result<T, Error>
+where
+    F: FnMut(Transaction) -> Fut,
+    Fut: Future<Output = Result<(Transaction, T), Error>>,
+{
+    // Each attempt calls body once it started its transaction, after the
+    // previous attempt is done
+    let body = Mutex::new(body);
+    futures_03_ext::retry::retry(&policy.into(), is_retriable, |_| {
+        let body = &body;
+        async move {
+            // The transaction is rolled back when dropped on error
+            let transaction = connection.start_transaction().await?;
+            let attempt = (body.lock().expect("lock poisoned"))(transaction);
+            let (transaction, value) = attempt.await?;
+            transaction.commit().await?;
+            Ok(value)
+        }
+    })
+    .await
+}
+
+/// Check that a savepoint name can be inlined in a statement
+fn savepoint_name(name: &str) -> Result<&str, Error> {
+    let mut chars = name.chars();
+    let valid = matches!(chars.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("invalid savepoint name {:?}", name);
+    }
+    Ok(name)
+}
+
 impl Drop for Transaction {
     fn drop(&mut self) {
         match self {
@@ -146,7 +588,92 @@ impl Drop for Transaction {
                     );
                 }
             }
-            Transaction::Mysql(_) => {}
+            Transaction::Mysql(_)
+            | Transaction::MysqlPool(_)
+            | Transaction::Postgres(_)
+            | Transaction::Observed(_) => {}
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::anyhow;
+    use failure_ext::ErrorTagsExt;
+
+    use crate::Connection;
+
+    #[test]
+    fn test_is_retriable() {
+        assert!(is_retriable(
+            &anyhow!("ERROR 40001: could not serialize access due to concurrent update")
+                .context("While executing MyUpdate query in transaction")
+        ));
+        assert!(is_retriable(&anyhow!(
+            "Deadlock found when trying to get lock"
+        )));
+        assert!(!is_retriable(&anyhow!("Lost connection to MySQL server")));
+        assert!(!is_retriable(
+            &anyhow!("Deadlock found").with_retriable(false)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry() -> Result<(), Error> {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory()?);
+        let mut transaction = conn.start_transaction().await?;
+        transaction
+            .execute("CREATE TABLE foo (x INTEGER)".to_owned())
+            .await?;
+        transaction.commit().await?;
+
+        let mut attempts = 0;
+        let policy = RetryPolicy::default();
+        let value = retry(&conn, &policy, |mut transaction| {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                transaction
+                    .execute(format!("INSERT INTO foo (x) VALUES ({})", attempt))
+                    .await?;
+                if attempt < 2 {
+                    bail!("Deadlock found");
+                }
+                Ok((transaction, attempt))
+            }
+        })
+        .await?;
+        assert_eq!(value, 2);
+
+        // Only the insert of the committed attempt remains
+        let mut transaction = conn.start_transaction().await?;
+        let count = transaction
+            .read_u64("SELECT COUNT(*) FROM foo WHERE x = 2")
+            .await?;
+        assert_eq!(count, 1);
+        assert_eq!(transaction.read_u64("SELECT COUNT(*) FROM foo").await?, 1);
+        transaction.rollback().await?;
+
+        // Gives up after max_attempts, and doesn't retry the other errors
+        attempts = 0;
+        let result: Result<(), _> = retry(&conn, &policy, |_| {
+            attempts += 1;
+            async { Err(anyhow!("Deadlock found")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let result: Result<(), _> = retry(&conn, &policy, |_| {
+            attempts += 1;
+            async { Err(anyhow!("Syntax error")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+        Ok(())
+    }
+}
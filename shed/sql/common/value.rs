@@ -0,0 +1,76 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing the traits converting user defined types, e.g. newtypes
+//! of ids or timestamps, to and from the values of the queries:
+//!
+//! * [ToSqlValue] for the parameters, implemented for every type
+//!   implementing `mysql_async::prelude::ToValue`
+//! * [FromSqlValue] for the result columns, hooked into the queries with
+//!   `sql::from_sql_value_conv!`
+//!
+//! The wrappers of a single field can derive both:
+//!
+//! ```ignore
+//! #[derive(Clone, Copy, Debug, ToSqlValue, FromSqlValue)]
+//! pub struct ChangesetId(u64);
+//!
+//! queries! {
+//!     read SelectParent(id: ChangesetId) -> (ChangesetId) {
+//!         "SELECT parent FROM changesets WHERE id = {id}"
+//!     }
+//! }
+//! ```
+
+use mysql_async::prelude::{ConvIr, ToValue};
+use mysql_async::{FromValueError, Value};
+
+pub use mysql_derive::{FromSqlValue, ToSqlValue};
+
+/// Conversion of a query parameter to the value sent to the database
+pub trait ToSqlValue {
+    /// The value of the parameter
+    fn to_sql_value(&self) -> Value;
+}
+
+impl<T: ToValue + ?Sized> ToSqlValue for T {
+    fn to_sql_value(&self) -> Value {
+        self.to_value()
+    }
+}
+
+/// Conversion of the value of a result column. Use `sql::from_sql_value_conv!`
+/// to read the type in queries, which also reads `Option` of it for the
+/// nullable columns.
+pub trait FromSqlValue: Sized {
+    /// Convert `value`, failing with it if it is not a valid `Self`
+    fn from_sql_value(value: Value) -> Result<Self, FromValueError>;
+}
+
+/// Intermediate result of the conversion of a [Value] to a type implementing
+/// [FromSqlValue]
+pub struct SqlValueIr<T> {
+    value: Value,
+    output: T,
+}
+
+impl<T: FromSqlValue> ConvIr<T> for SqlValueIr<T> {
+    fn new(v: Value) -> Result<Self, FromValueError> {
+        let output = T::from_sql_value(v.clone())?;
+        Ok(Self { value: v, output })
+    }
+
+    fn commit(self) -> T {
+        self.output
+    }
+
+    fn rollback(self) -> Value {
+        self.value
+    }
+}
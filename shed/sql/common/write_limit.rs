@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module providing connections capping the rate of their writes with a
+//! [TokenBucket], see [Connection::with_write_limiter], e.g. so that a
+//! background job doesn't overload the master:
+//!
+//! ```ignore
+//! let conn = conn.with_write_limit("backfill", 100, 50.0);
+//! for chunk in rows.chunks(100) {
+//!     InsertFoo::query(&conn.write_connection, chunk).await?;
+//! }
+//! ```
+//!
+//! Each write query and transaction takes a token, waiting for it when the
+//! bucket is empty rather than failing, so the limit is on queries and not
+//! on rows. A write cancelled while waiting hands its token back. The
+//! connections sharing a bucket are limited together.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use anyhow::Error;
+use rate_limiter::TokenBucket;
+
+use crate::{Connection, ConnectionLayer, SqlConnections, Unwrap};
+
+/// Connection of [Connection::with_write_limiter]
+pub struct WriteLimitedConnection {
+    connection: Connection,
+    limiter: Arc<TokenBucket>,
+}
+
+impl WriteLimitedConnection {
+    /// The connection running the queries
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The bucket the writes take their tokens from
+    pub fn limiter(&self) -> &TokenBucket {
+        &self.limiter
+    }
+}
+
+impl ConnectionLayer for WriteLimitedConnection {
+    fn kind(&self) -> &'static str {
+        "Write-limited"
+    }
+
+    fn inner(&self, _: Unwrap) -> &Connection {
+        &self.connection
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Connection {
+    /// Connection whose write queries and transactions each wait for a token
+    /// of `limiter` before running. The reads aren't limited.
+    pub fn with_write_limiter(self, limiter: Arc<TokenBucket>) -> Connection {
+        Connection::Layered(Arc::new(WriteLimitedConnection {
+            connection: self,
+            limiter,
+        }))
+    }
+
+    /// The buckets of the write-limited connections on the way to the
+    /// [Connection::backend], the outer ones first
+    pub fn write_limiters(&self) -> Vec<&TokenBucket> {
+        self.layers()
+            .filter_map(|layer| layer.as_any().downcast_ref::<WriteLimitedConnection>())
+            .map(WriteLimitedConnection::limiter)
+            .collect()
+    }
+
+    /// Wait for a token of each of the [Connection::write_limiters] before a
+    /// write. This should never be used directly, it is made public so that
+    /// the queries! macro can make use of it
+    #[doc(hidden)]
+    pub async fn acquire_write(&self) -> Result<(), Error> {
+        for limiter in self.write_limiters() {
+            limiter.acquire(1).await?;
+        }
+        Ok(())
+    }
+}
+
+impl SqlConnections {
+    /// Cap the writes of the write connection at `per_second` queries and
+    /// transactions per second, allowing bursts of `burst` of them, with the
+    /// stats of the bucket exported under `rate_limiter.sql.<name>`. Panics
+    /// if `burst` is zero or `per_second` is not positive.
+    pub fn with_write_limit(self, name: &str, burst: u32, per_second: f64) -> Self {
+        let limiter = TokenBucket::new(format!("sql.{}", name), burst, per_second);
+        Self {
+            write_connection: self.write_connection.with_write_limiter(Arc::new(limiter)),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_write() -> Result<(), Error> {
+        let conn = Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap());
+        assert!(conn.write_limiters().is_empty());
+
+        let conns = SqlConnections::new_single(conn).with_write_limit("test", 2, 10.0);
+        assert!(conns.read_connection.write_limiters().is_empty());
+        let conn = conns.write_connection.readonly();
+        assert_eq!(conn.write_limiters().len(), 1);
+        assert!(matches!(conn.backend(), Connection::Sqlite(_)));
+
+        // The burst is taken at once, then the writes wait for the refill
+        let start = tokio::time::Instant::now();
+        conn.acquire_write().await?;
+        conn.acquire_write().await?;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        conn.acquire_write().await?;
+        conn.acquire_write().await?;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+}
@@ -7,13 +7,14 @@
  * of this source tree.
  */
 
-//! Module introduces a proc macro for sql_common::mysql.
+//! Module introduces a proc macro for sql_common::mysql and the value
+//! conversions of sql_common::value.
 
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Type};
 
 /// The proc macro allows to derive an implementation of mysql_client::OptionalTryFromRowField
 /// trait for the type if that type implements mysql_async::FromValueOpt.
@@ -50,3 +51,89 @@ pub fn derive_tryfrom_rowfield(input: TokenStream) -> TokenStream {
     };
     expanded.into()
 }
+
+/// The single field of a wrapper struct: how to access it, how to build the
+/// struct from it, and its type
+fn wrapped_field(
+    input: &DeriveInput,
+) -> Result<(proc_macro2::TokenStream, proc_macro2::TokenStream, &Type), syn::Error> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "sql values can't be derived for generic types",
+        ));
+    }
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "sql values can only be derived for structs",
+            ));
+        }
+    };
+    match fields {
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            let field = &fields.unnamed[0];
+            Ok((quote!(0), quote!(#name(value)), &field.ty))
+        }
+        Fields::Named(fields) if fields.named.len() == 1 => {
+            let field = &fields.named[0];
+            let ident = &field.ident;
+            Ok((quote!(#ident), quote!(#name { #ident: value }), &field.ty))
+        }
+        _ => Err(syn::Error::new_spanned(
+            fields,
+            "sql values can only be derived for structs with a single field",
+        )),
+    }
+}
+
+/// The proc macro allows to derive an implementation of sql::value::ToSqlValue
+/// for a struct wrapping a single field, converted like the field.
+#[proc_macro_derive(ToSqlValue)]
+pub fn derive_to_sql_value(input: TokenStream) -> TokenStream {
+    let parsed_input = parse_macro_input!(input as DeriveInput);
+    let (field, _, _) = match wrapped_field(&parsed_input) {
+        Ok(field) => field,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let name = &parsed_input.ident;
+
+    let expanded = quote! {
+        impl sql::value::ToSqlValue for #name {
+            fn to_sql_value(&self) -> sql::mysql_async::Value {
+                sql::value::ToSqlValue::to_sql_value(&self.#field)
+            }
+        }
+    };
+    expanded.into()
+}
+
+/// The proc macro allows to derive an implementation of sql::value::FromSqlValue
+/// for a struct wrapping a single field, converted like the field, and to read
+/// it as a column with sql::from_sql_value_conv.
+#[proc_macro_derive(FromSqlValue)]
+pub fn derive_from_sql_value(input: TokenStream) -> TokenStream {
+    let parsed_input = parse_macro_input!(input as DeriveInput);
+    let (_, constructor, ty) = match wrapped_field(&parsed_input) {
+        Ok(field) => field,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let name = &parsed_input.ident;
+
+    let expanded = quote! {
+        impl sql::value::FromSqlValue for #name {
+            fn from_sql_value(
+                value: sql::mysql_async::Value,
+            ) -> Result<Self, sql::mysql_async::FromValueError> {
+                let value = <#ty as sql::mysql_async::prelude::FromValue>::from_value_opt(value)?;
+                Ok(#constructor)
+            }
+        }
+
+        sql::from_sql_value_conv!(#name);
+    };
+    expanded.into()
+}
@@ -11,12 +11,72 @@
 //! types of databases.
 //!
 //! This crate API is heavily based on mysql_async. If you wish to use your custom structure in SQL
-//! queries or to parse your structure from a result of SQL query then implement
-//! `mysql_async::prelude::ToValue` and/or `mysql_async::prelude::::FromValue` for it.
+//! queries or to parse your structure from a result of SQL query then implement [ToSqlValue]
+//! and/or [FromSqlValue] for it, see [sql_common::value], or `mysql_async::prelude::ToValue`
+//! and/or `mysql_async::prelude::::FromValue`. The structures wrapping a single value can derive
+//! [ToSqlValue] and [FromSqlValue].
 //!
 //! Queries are created using the `queries!` macro, you need to specify your query type to be either
 //! `read` if you perform a SELECT and expect the result to be parsed into a tuple or `write` if
 //! you execute an INSERT/UPDATE/DELETE query which will give you `WriteResult` upon completion.
+//! A `read_stream` query is a `read` query whose rows can also be iterated over with
+//! `query_stream`, which streams them from Sqlite and Mysql instead of collecting them into a
+//! `Vec`. Postgres rows are still fetched at once. As the stream holds its connection, other
+//! Sqlite queries wait for it to be consumed or dropped, and a pooled Mysql connection is closed
+//! if the stream is dropped before its end.
+//!
+//! A `cas_write` query is a compare-and-swap `write`, e.g. `UPDATE ... WHERE version = {version}`,
+//! returning [sql_common::cas::CasOutcome], `Updated` or `Conflict` when no row had the version,
+//! see [sql_common::cas] for how to retry it.
+//!
+//! The parameters of a query are referred to by name in its SQL, as `{name}` or `:name`, and a
+//! query using a parameter it doesn't declare fails to compile. The `:name` in quotes and the
+//! casts `::type` are not parameters. Besides `query` taking the parameters in order, a query
+//! with parameters has `query_named` taking them by name, e.g.
+//! `MySelect::query_named(&conn, MySelect::Params { param_a: &A, param_uint: &72 })`.
+//!
+//! The last parameters of a query can be lists, declared as `>list ids: u64` and taken as
+//! `&[u64]`, to use with `IN`: `"SELECT x FROM foo WHERE id IN {ids}"`. Their values are escaped
+//! like the other parameters. An empty list is an empty subquery, so that `IN` it matches no rows
+//! and `NOT IN` it every row.
+//!
+//! Queries can have common table expressions, `WITH t AS (...) SELECT ...`, and combine SELECTs
+//! with `UNION`, `INTERSECT` or `EXCEPT`, a parameter being usable in several of them. Sqlite
+//! rejects parentheses around the combined SELECTs, so a query with them gives Sqlite its own
+//! SQL with `mysql(...) sqlite(...)`.
+//!
+//! With the `validate_queries` feature, the SQL of the queries is parsed at compile time, and a
+//! query that is not valid SQL for Mysql or Sqlite, or that doesn't use each of its parameters,
+//! fails to compile. `validate_postgres_queries` also parses the Mysql queries as Postgres, which
+//! is sent the same SQL, so that e.g. `LIMIT 1, 2` fails to compile. The Mysql-only statements,
+//! `REPLACE INTO` and `ON DUPLICATE KEY UPDATE`, are not parsed as Postgres.
+//!
+//! Parameters and results stored as JSON can be `serde_json::Value`, or any type implementing
+//! `Serialize` and `Deserialize` wrapped in [Json], see [sql_common::json].
+//!
+//! Each query exports the number of its calls, errors and rows, and its latency, under
+//! `sql.query.<path>`, the path of the query being e.g. `my_crate::queries::SelectFoo`, see
+//! [sql_common::query_stats].
+//!
+//! The `read` queries also have `explain`, returning the plan the database chose for the query,
+//! e.g. to check in tests that it uses an index, see [sql_common::explain].
+//!
+//! The read queries returning more rows than a limit can be failed, see
+//! [sql_common::row_limit].
+//!
+//! The Mysql and Postgres queries can be sent with a leading comment naming the caller, e.g.
+//! `/* client:foo req:123 */`, see [sql_common::tag].
+//!
+//! The errors of the queries name the tier, shard and role of their connection, see
+//! [sql_common::label].
+//!
+//! The Mysql writes can return the replication position of the master, which the reads on
+//! replicas can wait for, see [sql_common::replication].
+//!
+//! The rate of the writes of a connection can be capped, see [sql_common::write_limit].
+//!
+//! The results of `read` queries can be cached in memcache under their `cache_key(params...)`,
+//! with the `memcache` feature, see [sql_common::cache].
 //!
 //! This crate also supports SQL transactions, see [Transaction] for more details.
 //!
@@ -68,11 +128,23 @@ pub use futures_util;
 pub use mysql_async;
 pub use rusqlite;
 pub use sql_common::mysql;
+pub use sql_common::postgres;
 pub use sql_common::{
-    self, error, sqlite, transaction::Transaction, Connection, SqlConnections,
-    SqlConnectionsWithSchema, SqlShardedConnections, WriteResult,
+    self, cache, cas, error, explain,
+    json::Json,
+    label, replication, row_limit, spans, sqlite, tag,
+    transaction::Transaction,
+    value::{self, FromSqlValue, ToSqlValue},
+    write_limit, Connection, SqlConnections, SqlConnectionsWithSchema, SqlShardedConnections,
+    WriteResult,
 };
 
+#[doc(hidden)]
+pub use sql_check::check_query as _check_query;
+#[doc(hidden)]
+pub use sql_check::named_query as _named_query;
+
+use mysql_async::prelude::FromValue;
 use mysql_async::Value;
 use rusqlite::types::{
     FromSql as FromSqliteValue, FromSqlResult as FromSqliteValueResult, ToSql as ToSqliteValue,
@@ -119,6 +191,211 @@ impl FromSqliteValue for ValueWrapper {
     }
 }
 
+/// Convert the value of the column `index` of a result row, named `name` if
+/// known, to its type `rtype`, failing with a [error::ColumnError]. This should
+/// never be used directly, it is made public so that the queries! macro can
+/// make use of it
+#[doc(hidden)]
+pub fn from_column<T: FromValue>(
+    value: Value,
+    index: usize,
+    name: Option<&str>,
+    rtype: &'static str,
+) -> Result<T, error::ColumnError> {
+    T::from_value_opt(value).map_err(|err| {
+        let column = match name {
+            Some(name) => format!("column `{}` (index {})", name, index),
+            None => format!("column {}", index),
+        };
+        match err.0 {
+            Value::NULL => error::ColumnError::Null { column, rtype },
+            value => error::ColumnError::InvalidValue {
+                column,
+                rtype,
+                value,
+            },
+        }
+    })
+}
+
+/// Dialect of the SQL text sent to the Mysql and Postgres connections, which
+/// run the same queries.
+/// This should never be used directly, it is made public so that internal
+/// macros can make use of it
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SqlDialect {
+    /// Mysql, escaping strings with backslashes
+    Mysql,
+    /// Postgres, with `$n` placeholders for the values
+    Postgres,
+}
+
+impl SqlDialect {
+    /// Replacement of `{insert_or_ignore}` in queries
+    pub fn insert_or_ignore(self) -> &'static str {
+        match self {
+            SqlDialect::Mysql => "INSERT IGNORE",
+            SqlDialect::Postgres => "INSERT",
+        }
+    }
+
+    /// Complete an `{insert_or_ignore}` query, Postgres ignoring conflicting
+    /// rows with a clause at the end of the query
+    pub fn finish_insert_or_ignore(self, query: String) -> String {
+        match self {
+            SqlDialect::Mysql => query,
+            SqlDialect::Postgres => format!(
+                "{} ON CONFLICT DO NOTHING",
+                query.trim_end().trim_end_matches(';')
+            ),
+        }
+    }
+}
+
+/// Values of a query being formatted for a [SqlDialect]. Mysql gets them
+/// inlined in the text of the query, while Postgres gets `$n` placeholders
+/// bound to them, so that the server converts each one to the type expected
+/// where it is used, e.g. the bytes of a string to `text` or `bytea`.
+/// This should never be used directly, it is made public so that internal
+/// macros can make use of it
+#[doc(hidden)]
+pub struct QueryValues {
+    dialect: SqlDialect,
+    params: Vec<Value>,
+}
+
+impl QueryValues {
+    /// Start formatting a query for `dialect`
+    pub fn new(dialect: SqlDialect) -> Self {
+        Self {
+            dialect,
+            params: Vec::new(),
+        }
+    }
+
+    /// Text standing for `value` in the query
+    pub fn quote(&mut self, value: &Value) -> String {
+        match self.dialect {
+            SqlDialect::Mysql => value.as_sql(false),
+            SqlDialect::Postgres => {
+                self.params.push(value.clone());
+                format!("${}", self.params.len())
+            }
+        }
+    }
+
+    /// Text standing for `values` as a list after `IN`. As an empty list is
+    /// invalid in a query, an empty list is an empty subquery instead, so
+    /// that `IN` is false and `NOT IN` true. Postgres compares the values
+    /// of the subquery by their type, so its `NULL` is cast to
+    /// `postgres_type`, the type of the values, see [PostgresListType].
+    pub fn list(&mut self, values: impl IntoIterator<Item = Value>, postgres_type: &str) -> String {
+        let values: Vec<_> = values.into_iter().map(|value| self.quote(&value)).collect();
+        if !values.is_empty() {
+            return format!("({})", values.join(", "));
+        }
+        match self.dialect {
+            SqlDialect::Mysql => "(SELECT NULL FROM DUAL WHERE FALSE)".to_owned(),
+            SqlDialect::Postgres => format!("(SELECT NULL::{} WHERE FALSE)", postgres_type),
+        }
+    }
+
+    /// Replacement of `{insert_or_ignore}` in queries
+    pub fn insert_or_ignore(&self) -> &'static str {
+        self.dialect.insert_or_ignore()
+    }
+
+    /// Complete an `{insert_or_ignore}` query
+    pub fn finish_insert_or_ignore(&self, query: String) -> String {
+        self.dialect.finish_insert_or_ignore(query)
+    }
+
+    /// Values bound to the placeholders of the query, in their order
+    pub fn into_params(self) -> Vec<Value> {
+        self.params
+    }
+}
+
+/// Postgres type of the values of the `>list` parameters of type `Self`,
+/// which an empty list is cast to. The lists of other types are cast to
+/// `text`. This should never be used directly, it is made public so that
+/// internal macros can make use of it
+#[doc(hidden)]
+pub trait PostgresListType {
+    /// Name of the type in Postgres
+    const POSTGRES_TYPE: &'static str;
+}
+
+macro_rules! postgres_list_type {
+    ($postgres_type:expr => $( $type:ty ),*) => {
+        $(
+            impl PostgresListType for $type {
+                const POSTGRES_TYPE: &'static str = $postgres_type;
+            }
+        )*
+    };
+}
+
+postgres_list_type!("boolean" => bool);
+postgres_list_type!("smallint" => i8, i16, u8);
+postgres_list_type!("integer" => i32, u16);
+postgres_list_type!("bigint" => i64, u32, u64, isize, usize);
+postgres_list_type!("real" => f32);
+postgres_list_type!("double precision" => f64);
+postgres_list_type!("text" => String, str);
+postgres_list_type!("bytea" => Vec<u8>);
+
+/// Finds the [PostgresListType] of `T` if it has one, by the method
+/// resolution of [KnownListType] and [UnknownListType]. This should never be
+/// used directly, it is made public so that internal macros can make use of
+/// it
+#[doc(hidden)]
+pub struct ListType<T: ?Sized>(std::marker::PhantomData<T>);
+
+impl<T: ?Sized> ListType<T> {
+    /// Probe of the type `T`
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(std::marker::PhantomData)
+    }
+}
+
+/// Postgres type of the lists whose type has a [PostgresListType]
+#[doc(hidden)]
+pub trait KnownListType {
+    /// Name of the type in Postgres
+    fn postgres_type(&self) -> &'static str;
+}
+
+impl<T: PostgresListType + ?Sized> KnownListType for ListType<T> {
+    fn postgres_type(&self) -> &'static str {
+        T::POSTGRES_TYPE
+    }
+}
+
+/// Postgres type of the other lists, only picked when [KnownListType] isn't
+/// implemented as it takes one more reference
+#[doc(hidden)]
+pub trait UnknownListType {
+    /// Name of the type in Postgres
+    fn postgres_type(&self) -> &'static str {
+        "text"
+    }
+}
+
+impl<T: ?Sized> UnknownListType for &ListType<T> {}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _postgres_list_type {
+    ($ltype:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::{KnownListType as _, UnknownListType as _};
+        (&$crate::ListType::<$ltype>::new()).postgres_type()
+    }};
+}
+
 #[macro_export]
 /// TODO: write doc for this macro and consider rewriting this as a proc macro
 macro_rules! queries {
@@ -162,7 +439,9 @@ macro_rules! queries {
             ) -> Result<Vec<($( $rtype, )*)>, Error> {
                 query_internal(connection $( , $pname )* $( , $lname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
@@ -175,6 +454,39 @@ macro_rules! queries {
                     .await
                     .context(stringify!(While executing $name query in transaction))
             }
+
+            $crate::_query_named_impl!(pub(super) (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> Vec<($( $rtype, )*)>);
+
+            #[allow(dead_code)]
+            pub(super) async fn explain(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::explain::QueryPlan, Error> {
+                explain_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While explaining $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub(super) fn cache_key(
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> $crate::cache::CacheKey {
+                let mut values = $crate::QueryValues::new(SqlDialect::Mysql);
+                $crate::cache::CacheKey::new($crate::_query_name!(), &[
+                    $( values.quote(&ToSqlValue::to_sql_value($pname)), )*
+                    $( values.list(
+                        $lname.iter().map(ToSqlValue::to_sql_value),
+                        $crate::_postgres_list_type!($ltype),
+                    ), )*
+                ])
+            }
         }
         $crate::queries!($( $tt )*);
     );
@@ -217,7 +529,9 @@ macro_rules! queries {
             ) -> Result<Vec<($( $rtype, )*)>, Error> {
                 query_internal(connection $( , $pname )* $( , $lname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
@@ -230,6 +544,225 @@ macro_rules! queries {
                     .await
                     .context(stringify!(While executing $name query in transaction))
             }
+
+            $crate::_query_named_impl!(pub $( ( $( $mods )* ) )? (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> Vec<($( $rtype, )*)>);
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn explain(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::explain::QueryPlan, Error> {
+                explain_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While explaining $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? fn cache_key(
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> $crate::cache::CacheKey {
+                let mut values = $crate::QueryValues::new(SqlDialect::Mysql);
+                $crate::cache::CacheKey::new($crate::_query_name!(), &[
+                    $( values.quote(&ToSqlValue::to_sql_value($pname)), )*
+                    $( values.list(
+                        $lname.iter().map(ToSqlValue::to_sql_value),
+                        $crate::_postgres_list_type!($ltype),
+                    ), )*
+                ])
+            }
+        }
+        $crate::queries!($( $tt )*);
+    );
+
+    (
+        read_stream $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) -> ($( $rtype:ty ),* $(,)*) { $q:expr }
+        $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            read_stream $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*) { mysql($q) sqlite($q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        read_stream $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) -> ($( $rtype:ty ),* $(,)*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
+        $( $tt:tt )*
+    ) => (
+        #[allow(non_snake_case)]
+        mod $name {
+            $crate::_read_query_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*) { mysql($mysql_q) sqlite($sqlite_q) });
+            $crate::_read_stream_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*));
+
+            #[allow(dead_code)]
+            pub(super) async fn query(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<Vec<($( $rtype, )*)>, Error> {
+                query_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub(super) async fn query_with_transaction(
+                transaction: Transaction,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
+                query_internal_with_transaction(transaction $( , $pname )* $( , $lname )*)
+                    .await
+                    .context(stringify!(While executing $name query in transaction))
+            }
+
+            $crate::_query_named_impl!(pub(super) (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> Vec<($( $rtype, )*)>);
+
+            #[allow(dead_code)]
+            pub(super) async fn explain(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::explain::QueryPlan, Error> {
+                explain_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While explaining $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub(super) fn query_stream<'a>(
+                connection: &'a Connection,
+                $( $pname: &'a $ptype, )*
+                $( $lname: &'a [ $ltype ], )*
+            ) -> BoxStream<'a, Result<($( $rtype, )*), Error>> {
+                query_stream_internal(connection $( , $pname )* $( , $lname )*)
+                    .map_err(move |err| {
+                        let context = stringify!(While streaming $name query);
+                        err.context($crate::label::context(connection, context))
+                    })
+                    .boxed()
+            }
+        }
+        $crate::queries!($( $tt )*);
+    );
+
+    (
+        pub $( ( $( $mods:tt )* ) )? read_stream $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) -> ($( $rtype:ty ),* $(,)*) { $q:expr }
+        $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            pub $( ( $( $mods )* ) )? read_stream $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*) { mysql($q) sqlite($q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        pub $( ( $( $mods:tt )* ) )? read_stream $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) -> ($( $rtype:ty ),* $(,)*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
+        $( $tt:tt )*
+    ) => (
+        #[allow(non_snake_case)]
+        pub $( ( $( $mods )* ) )? mod $name {
+            $crate::_read_query_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*) { mysql($mysql_q) sqlite($sqlite_q) });
+            $crate::_read_stream_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*));
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn query(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<Vec<($( $rtype, )*)>, Error> {
+                query_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn query_with_transaction(
+                transaction: Transaction,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
+                query_internal_with_transaction(transaction $( , $pname )* $( , $lname )*)
+                    .await
+                    .context(stringify!(While executing $name query in transaction))
+            }
+
+            $crate::_query_named_impl!(pub $( ( $( $mods )* ) )? (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> Vec<($( $rtype, )*)>);
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn explain(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::explain::QueryPlan, Error> {
+                explain_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While explaining $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? fn query_stream<'a>(
+                connection: &'a Connection,
+                $( $pname: &'a $ptype, )*
+                $( $lname: &'a [ $ltype ], )*
+            ) -> BoxStream<'a, Result<($( $rtype, )*), Error>> {
+                query_stream_internal(connection $( , $pname )* $( , $lname )*)
+                    .map_err(move |err| {
+                        let context = stringify!(While streaming $name query);
+                        err.context($crate::label::context(connection, context))
+                    })
+                    .boxed()
+            }
         }
         $crate::queries!($( $tt )*);
     );
@@ -273,7 +806,9 @@ macro_rules! queries {
             ) -> Result<WriteResult, Error> {
                 query_internal(connection, values $( , $pname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
@@ -329,7 +864,9 @@ macro_rules! queries {
             ) -> Result<WriteResult, Error> {
                 query_internal(connection, values $( , $pname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
@@ -388,7 +925,9 @@ macro_rules! queries {
             ) -> Result<WriteResult, Error> {
                 query_internal(connection $( , $pname )* $( , $lname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
@@ -401,6 +940,11 @@ macro_rules! queries {
                     .await
                     .context(stringify!(While executing $name query))
             }
+
+            $crate::_query_named_impl!(pub(super) (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> WriteResult);
         }
         $crate::queries!($( $tt )*);
     );
@@ -430,7 +974,10 @@ macro_rules! queries {
     ) => (
         #[allow(non_snake_case)]
         pub $( ( $( $mods )* ) )? mod $name {
-            $crate::_write_query_impl!(($( $pname: $ptype ),* ) {
+            $crate::_write_query_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) {
                 $qtype,
                 mysql($mysql_q)
                 sqlite($sqlite_q)
@@ -439,25 +986,212 @@ macro_rules! queries {
             #[allow(dead_code)]
             pub $( ( $( $mods )* ) )? async fn query(
                 connection: &Connection,
-                $( $pname: & $ptype ),*
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
             ) -> Result<WriteResult, Error> {
-                query_internal(connection, $( , $pname )*)
+                query_internal(connection $( , $pname )* $( , $lname )*)
                     .await
-                    .context(stringify!(While executing $name query))
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
             }
 
             #[allow(dead_code)]
             pub $( ( $( $mods )* ) )? async fn query_with_transaction(
                 transaction: Transaction,
-                $( $pname: & $ptype ),*
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
             ) -> Result<(Transaction, WriteResult), Error> {
-                query_internal_with_transaction(transaction $( , $pname )*)
+                query_internal_with_transaction(transaction $( , $pname )* $( , $lname )*)
                     .await
                     .context(stringify!(While executing $name query))
             }
+
+            $crate::_query_named_impl!(pub $( ( $( $mods )* ) )? (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> WriteResult);
         }
         $crate::queries!($( $tt )*);
     );
+
+    (
+        cas_write $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) { $q:expr }
+        $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            cas_write $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) { mysql($q) sqlite($q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        cas_write $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
+        $( $tt:tt )*
+    ) => (
+        #[allow(non_snake_case)]
+        mod $name {
+            $crate::_write_query_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) {
+                none,
+                mysql($mysql_q)
+                sqlite($sqlite_q)
+            });
+
+            #[allow(dead_code)]
+            pub(super) async fn query(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::cas::CasOutcome, Error> {
+                query_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .map($crate::cas::CasOutcome::from)
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub(super) async fn query_with_transaction(
+                transaction: Transaction,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<(Transaction, $crate::cas::CasOutcome), Error> {
+                query_internal_with_transaction(transaction $( , $pname )* $( , $lname )*)
+                    .await
+                    .map(|(transaction, res)| (transaction, res.into()))
+                    .context(stringify!(While executing $name query))
+            }
+
+            $crate::_query_named_impl!(pub(super) (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> $crate::cas::CasOutcome);
+        }
+        $crate::queries!($( $tt )*);
+    );
+
+    (
+        pub $( ( $( $mods:tt )* ) )? cas_write $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) { $q:expr }
+        $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            pub $( ( $( $mods )* ) )? cas_write $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) { mysql($q) sqlite($q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        pub $( ( $( $mods:tt )* ) )? cas_write $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
+        $( $tt:tt )*
+    ) => (
+        #[allow(non_snake_case)]
+        pub $( ( $( $mods )* ) )? mod $name {
+            $crate::_write_query_impl!((
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) {
+                none,
+                mysql($mysql_q)
+                sqlite($sqlite_q)
+            });
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn query(
+                connection: &Connection,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<$crate::cas::CasOutcome, Error> {
+                query_internal(connection $( , $pname )* $( , $lname )*)
+                    .await
+                    .map($crate::cas::CasOutcome::from)
+                    .with_context(|| {
+                        $crate::label::context(connection, stringify!(While executing $name query))
+                    })
+            }
+
+            #[allow(dead_code)]
+            pub $( ( $( $mods )* ) )? async fn query_with_transaction(
+                transaction: Transaction,
+                $( $pname: & $ptype, )*
+                $( $lname: & [ $ltype ], )*
+            ) -> Result<(Transaction, $crate::cas::CasOutcome), Error> {
+                query_internal_with_transaction(transaction $( , $pname )* $( , $lname )*)
+                    .await
+                    .map(|(transaction, res)| (transaction, res.into()))
+                    .context(stringify!(While executing $name query))
+            }
+
+            $crate::_query_named_impl!(pub $( ( $( $mods )* ) )? (
+                $( $pname: $ptype, )*
+                $( >list $lname: $ltype )*
+            ) -> $crate::cas::CasOutcome);
+        }
+        $crate::queries!($( $tt )*);
+    );
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _query_named_impl {
+    ( $vis:vis () -> $result:ty ) => ();
+
+    ( $vis:vis (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+    ) -> $result:ty ) => (
+        /// Parameters of the query, for [query_named]
+        #[allow(dead_code)]
+        $vis struct Params<'a> {
+            $(
+                #[allow(missing_docs)]
+                pub $pname: &'a $ptype,
+            )*
+            $(
+                #[allow(missing_docs)]
+                pub $lname: &'a [ $ltype ],
+            )*
+        }
+
+        /// Run the query with its parameters given by name
+        #[allow(dead_code)]
+        $vis async fn query_named(
+            connection: &Connection,
+            params: Params<'_>,
+        ) -> Result<$result, Error> {
+            query(connection $( , params.$pname )* $( , params.$lname )*).await
+        }
+
+        /// Run the query in `transaction` with its parameters given by name
+        #[allow(dead_code)]
+        $vis async fn query_named_with_transaction(
+            transaction: Transaction,
+            params: Params<'_>,
+        ) -> Result<(Transaction, $result), Error> {
+            query_with_transaction(transaction $( , params.$pname )* $( , params.$lname )*).await
+        }
+    );
 }
 
 #[macro_export]
@@ -481,8 +1215,11 @@ macro_rules! _query_common {
             Statement as SqliteStatement,
         };
         use $crate::{
+            mysql::QueryKiller,
+            spans::QuerySpan,
             sqlite::{SqliteConnectionGuard, SqliteMultithreaded},
-            Connection, Transaction, ValueWrapper,
+            value::ToSqlValue,
+            Connection, QueryValues, SqlDialect, Transaction, ValueWrapper,
         };
 
         #[allow(unused_imports)]
@@ -490,37 +1227,159 @@ macro_rules! _query_common {
     };
 }
 
-#[macro_export]
-#[doc(hidden)]
-macro_rules! _read_query_impl {
-    ( (
-        $( $pname:ident: $ptype:ty, )*
-        $( >list $lname:ident: $ltype:ty )*
-    ) -> ($( $rtype:ty ),*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
-        $crate::_query_common!();
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _read_query_impl {
+    ( (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+    ) -> ($( $rtype:ty ),*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
+        $crate::_query_common!();
+
+        $crate::_check_query!(
+            params($( $pname ),*) lists($( $lname ),*) read mysql($mysql_q) sqlite($sqlite_q)
+        );
+
+        async fn query_internal(
+            connection: &Connection,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<Vec<($( $rtype, )*)>, Error> {
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_read($crate::_query_name!(), connection);
+            let query = async {
+                check_read(connection)?;
+                let row_limit = connection.max_rows();
+                let result = query_retrying(connection, row_limit, $( $pname, )* $( $lname, )*).await;
+                let rows = match (result, connection.read_failover()) {
+                    (Err(err), Some(failover)) if failover.should_fall_back(&err) => {
+                        query_retrying(failover.fallback(), row_limit, $( $pname, )* $( $lname, )*).await
+                    }
+                    (result, _) => result,
+                }?;
+                $crate::row_limit::check_max_rows(connection.max_rows(), rows.len())?;
+                Ok(rows)
+            };
+            let result = span
+                .run(|rows| rows.len() as u64, $crate::tag::with_connection_tag(connection, query))
+                .await;
+            $crate::_observe_query!(
+                connection.observer(),
+                start,
+                false,
+                $mysql_q,
+                result.as_ref().map(|rows| rows.len() as u64),
+                ($( $pname ),*),
+                ($( $lname ),*)
+            );
+            result
+        }
+
+        async fn query_internal_with_transaction(
+            transaction: Transaction,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
+            let (transaction, observer) = transaction.take_observer();
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_transaction($crate::_query_name!(), &transaction);
+            let result = span.run(
+                |(_, rows)| rows.len() as u64,
+                query_once_with_transaction(transaction, $( $pname, )* $( $lname, )*),
+            ).await;
+            $crate::_observe_query!(
+                observer.as_ref(),
+                start,
+                true,
+                $mysql_q,
+                result.as_ref().map(|(_, rows)| rows.len() as u64),
+                ($( $pname ),*),
+                ($( $lname ),*)
+            );
+            result.map(|(transaction, rows)| (transaction.with_observer(observer), rows))
+        }
+
+        async fn query_retrying(
+            connection: &Connection,
+            row_limit: Option<usize>,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<Vec<($( $rtype, )*)>, Error> {
+            match connection.read_retrying() {
+                Some(conn) => {
+                    conn.retry(|| query_once(conn.connection(), row_limit, $( $pname, )* $( $lname, )*))
+                        .await
+                }
+                None => query_once(connection, row_limit, $( $pname, )* $( $lname, )*).await,
+            }
+        }
 
-        async fn query_internal(
+        fn check_read(connection: &Connection) -> Result<(), Error> {
+            connection.check_read($mysql_q)?;
+            connection.check_read($sqlite_q)
+        }
+
+        /// The rows of the query, fetching at most one more than `row_limit`,
+        /// except on Postgres
+        async fn query_once(
             connection: &Connection,
+            row_limit: Option<usize>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
         ) -> Result<Vec<($( $rtype, )*)>, Error> {
-            match connection {
+            use $crate::futures::stream::{StreamExt, TryStreamExt};
+
+            let fetch_limit = $crate::row_limit::fetch_limit(row_limit);
+            match connection.read_backend() {
                 Connection::Sqlite(multithread_con) => {
-                    sqlite_query(multithread_con.clone() $( , $pname )* $( , $lname )*).await
+                    sqlite_query(multithread_con.clone(), fetch_limit $( , $pname )* $( , $lname )*).await
+                }
+                // With a limit the rows are streamed, so that the query is
+                // dropped once it exceeds it
+                Connection::Mysql(conn) if row_limit.is_some() => {
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    QueryKiller::run_stream(connection.read_query_killer(), query, |query| conn.read_query_stream::<($( $rtype, )*)>(query))
+                        .take(fetch_limit)
+                        .try_collect()
+                        .await
                 }
                 Connection::Mysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
-                    conn.read_query(query).map_err(Error::from).await
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    QueryKiller::run(connection.read_query_killer(), query, |query| conn.read_query(query))
+                        .await
+                }
+                Connection::MysqlPool(pool) if row_limit.is_some() => {
+                    let conn = pool.acquire().await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let rows = QueryKiller::run_stream(connection.read_query_killer(), query, |query| conn.read_query_stream::<($( $rtype, )*)>(query));
+                    conn.run_stream(rows).take(fetch_limit).try_collect().await
+                }
+                Connection::MysqlPool(pool) => {
+                    let conn = pool.acquire().await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    QueryKiller::run(connection.read_query_killer(), query, |query| conn.run(conn.read_query(query)))
+                        .await
+                }
+                Connection::Postgres(conn) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, $( $pname, )* $( $lname, )*);
+                    let rows = conn.read_query(query, params).await?;
+                    rows.into_iter().map(postgres_row).collect()
+                }
+                Connection::Layered(_) => {
+                    unreachable!("backend is never a wrapping connection")
                 }
             }
         }
 
-        async fn query_internal_with_transaction(
+        async fn query_once_with_transaction(
             mut transaction: Transaction,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
         ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
             match transaction {
+                Transaction::Observed(_) => {
+                    unreachable!("observed transactions are unwrapped")
+                }
                 Transaction::Sqlite(ref mut con) => {
                     let con = con
                         .take()
@@ -533,17 +1392,33 @@ macro_rules! _read_query_impl {
                         })
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
                     let mut tr = transaction.take()
                         .expect("should be Some before transaction ended");
                     let result = tr.read_query(query).map_err(Error::from).await?;
                     Ok((Transaction::Mysql(Some(tr)), result))
                 }
+                Transaction::MysqlPool(ref mut transaction) => {
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let result = QueryKiller::run(tr.killer(), query, |query| tr.read_query(query)).await?;
+                    Ok((Transaction::MysqlPool(Some(tr)), result))
+                }
+                Transaction::Postgres(ref mut transaction) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, $( $pname, )* $( $lname, )*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let rows = tr.read_query(query, params).await?;
+                    let result = rows.into_iter().map(postgres_row).collect::<Result<_, _>>()?;
+                    Ok((Transaction::Postgres(Some(tr)), result))
+                }
             }
         }
 
         async fn sqlite_query(
             multithread_con: Arc<SqliteMultithreaded>,
+            fetch_limit: usize,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
         ) -> Result<Vec<($( $rtype, )*)>, Error> {
@@ -553,37 +1428,16 @@ macro_rules! _read_query_impl {
                 $( >list $lname )*
             );
 
-            let con = multithread_con.get_sqlite_guard();
-
-            let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-            for idx in 0..params.len() {
-                ref_params.push((&params[idx].0, &params[idx].1))
-            }
+            multithread_con.run(move |con| {
+                let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                for idx in 0..params.len() {
+                    ref_params.push((&params[idx].0, &params[idx].1))
+                }
 
-            sqlite_statement(&con  $( , $lname )*)
-                .and_then(|mut stmt| {
-                    stmt.query_map_named(
-                        &ref_params[..],
-                        |row| {
-                            #[allow(clippy::eval_order_dependence)]
-                            {
-                                let mut idx = 0;
-                                let res = (
-                                    $({
-                                        let res: ValueWrapper = row.get(idx)?;
-                                        idx += 1;
-                                        <$rtype as FromValue>::from_value_opt(res.0)
-                                            .unwrap_or_else(|err| {
-                                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                                            })
-                                    },)*
-                                );
-                                // suppress unused_assignments warning
-                                let _ = idx;
-                                Ok(res)
-                            }
-                    })?.collect()
-                }).map_err(Error::from)
+                let mut stmt = sqlite_statement(con  $( , $lname )*)?;
+                let rows = stmt.query_map_named(&ref_params[..], |row| Ok(sqlite_row(row)))?;
+                rows.take(fetch_limit).map(|row| row.map_err(Error::from).and_then(|row| row)).collect()
+            }).await
         }
 
         async fn sqlite_query_with_transaction(
@@ -597,59 +1451,251 @@ macro_rules! _read_query_impl {
                 $( >list $lname )*
             );
 
-            let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-            for idx in 0..params.len() {
-                ref_params.push((&params[idx].0, &params[idx].1))
-            }
+            let (transaction, res) = transaction.run(move |con| {
+                let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                for idx in 0..params.len() {
+                    ref_params.push((&params[idx].0, &params[idx].1))
+                }
 
-            let res: SqliteResult<Vec<($( $rtype, )*)>> = {
-                let mut stmt = sqlite_statement(&transaction  $( , $lname )*)?;
-                let res = stmt.query_map_named(
-                    &ref_params[..],
-                    |row| {
-                        #[allow(clippy::eval_order_dependence)]
-                        {
-                            let mut idx = 0;
-                            let res = (
-                                $({
-                                    let res: ValueWrapper = row.get(idx)?;
-                                    idx += 1;
-                                    <$rtype as FromValue>::from_value_opt(res.0)
-                                        .unwrap_or_else(|err| {
-                                            panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                                        })
-                                },)*
-                            );
-                            // suppress unused_assignments warning
-                            let _ = idx;
-                            Ok(res)
-                        }
-                })?.collect();
-                res
-            };
+                let mut stmt = sqlite_statement(con  $( , $lname )*)?;
+                let rows = stmt.query_map_named(&ref_params[..], |row| Ok(sqlite_row(row)))?;
+                rows.map(|row| row.map_err(Error::from).and_then(|row| row)).collect()
+            }).await;
 
             Ok((transaction, res?))
         }
 
-        fn mysql_query($( $pname: & $ptype, )* $( $lname: & [ $ltype ], )*) -> String {
-            $crate::_emit_mysql_lnames!($( $lname ),*);
-            format!(
-                $mysql_q,
-                $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+        fn sql_query(
+            dialect: SqlDialect,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> (String, Vec<$crate::mysql_async::Value>) {
+            let mut dialect = QueryValues::new(dialect);
+            $crate::_emit_mysql_lnames!(dialect; $( $lname: $ltype ),*);
+            let query = $crate::tag::tag_query(format!(
+                $crate::_named_query!($mysql_q),
+                $( $pname = dialect.quote(&ToSqlValue::to_sql_value($pname)), )*
                 $( $lname = $lname, )*
-            )
+            ));
+            (query, dialect.into_params())
+        }
+
+        #[allow(unused_mut, unused_variables)]
+        fn postgres_row(row: $crate::postgres::Row) -> Result<($( $rtype, )*), Error> {
+            let mut row = row.into_iter().enumerate();
+            Ok(($({
+                let (idx, value) = row.next().ok_or_else(|| {
+                    $crate::anyhow::format_err!("Missing column for `{}`", stringify!($rtype))
+                })?;
+                $crate::from_column::<$rtype>(value, idx, None, stringify!($rtype))?
+            },)*))
+        }
+
+        #[allow(unused_mut, unused_variables)]
+        fn sqlite_row(row: &$crate::rusqlite::Row<'_>) -> Result<($( $rtype, )*), Error> {
+            let mut idx = 0;
+            let res = ($({
+                let value: ValueWrapper = row.get(idx)?;
+                let column = row.column_name(idx).ok();
+                let res = $crate::from_column::<$rtype>(value.0, idx, column, stringify!($rtype))?;
+                idx += 1;
+                res
+            },)*);
+            // suppress unused_assignments warning
+            let _ = idx;
+            Ok(res)
         }
 
         fn sqlite_statement<'a>(
             connection: &'a SqliteConnection,
             $( $lname: usize, )*
         ) -> SqliteResult<SqliteStatement<'a>> {
+            connection.prepare(&sqlite_sql($( $lname, )*))
+        }
+
+        fn sqlite_sql($( $lname: usize, )*) -> String {
             $crate::_emit_sqlite_lnames!($( $lname ),*);
-            connection.prepare(&format!(
-                $sqlite_q,
+            format!(
+                $crate::_named_query!($sqlite_q),
                 $( $pname = concat!(":", stringify!($pname)), )*
                 $( $lname = $lname, )*
-            ))
+            )
+        }
+
+        async fn explain_internal(
+            connection: &Connection,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<$crate::explain::QueryPlan, Error> {
+            use $crate::explain::QueryPlan;
+
+            match connection.read_backend() {
+                Connection::Sqlite(multithread_con) => {
+                    $crate::_prepare_sqlite_params!(
+                        params,
+                        $( $pname ),*
+                        $( >list $lname )*
+                    );
+
+                    multithread_con.run(move |con| {
+                        let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                        for idx in 0..params.len() {
+                            ref_params.push((&params[idx].0, &params[idx].1))
+                        }
+
+                        let query = format!("EXPLAIN QUERY PLAN {}", sqlite_sql($( $lname, )*));
+                        let mut stmt = con.prepare(&query)?;
+                        let details = stmt.query_map_named(&ref_params[..], |row| row.get(3))?;
+                        Ok(QueryPlan::from_sqlite(details.collect::<SqliteResult<_>>()?))
+                    }).await
+                }
+                Connection::Mysql(conn) => {
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let query = format!("EXPLAIN FORMAT=JSON {}", query);
+                    let rows = conn.read_query(query).map_err(Error::from).await?;
+                    QueryPlan::from_mysql(rows)
+                }
+                Connection::MysqlPool(pool) => {
+                    let conn = pool.acquire().await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let query = format!("EXPLAIN FORMAT=JSON {}", query);
+                    let rows = conn.run(conn.read_query(query)).await?;
+                    QueryPlan::from_mysql(rows)
+                }
+                Connection::Postgres(conn) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, $( $pname, )* $( $lname, )*);
+                    let query = format!("EXPLAIN (FORMAT JSON) {}", query);
+                    QueryPlan::from_postgres(conn.read_query(query, params).await?)
+                }
+                Connection::Layered(_) => {
+                    unreachable!("backend is never a wrapping connection")
+                }
+            }
+        }
+    );
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _query_name {
+    // The queries are each defined in a module of their name
+    () => {
+        module_path!().rsplit("::").next().unwrap_or_default()
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _observe_query {
+    (
+        $observer:expr,
+        $start:expr,
+        $in_transaction:expr,
+        $sql:expr,
+        $result:expr,
+        ($( $pname:ident ),*),
+        ($( $lname:ident ),*)
+    ) => {
+        let duration = $start.elapsed();
+        let result: Result<u64, &Error> = $result;
+        // Keyed by the full path of the query, as queries of different modules
+        // can have the same name
+        $crate::sql_common::query_stats::record(module_path!(), duration, result);
+        if let Some(observer) = $observer {
+            observer.observe(&$crate::sql_common::observer::QueryInfo {
+                name: $crate::_query_name!(),
+                sql: $sql,
+                params: &$crate::sql_common::observer::params_summary(
+                    &[$( stringify!($pname) ),*],
+                    &[$( (stringify!($lname), $lname.len()) ),*],
+                ),
+                duration,
+                result,
+                in_transaction: $in_transaction,
+            });
+        }
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _read_stream_impl {
+    ( (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+    ) -> ($( $rtype:ty ),*) ) => (
+        use $crate::futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+
+        fn query_stream_internal<'a>(
+            connection: &'a Connection,
+            $( $pname: &'a $ptype, )*
+            $( $lname: &'a [ $ltype ], )*
+        ) -> BoxStream<'a, Result<($( $rtype, )*), Error>> {
+            if let Err(err) = check_read(connection) {
+                return stream::once(async { Err(err) }).boxed();
+            }
+            let rows = match connection.read_backend() {
+                Connection::Sqlite(multithread_con) => {
+                    $crate::_prepare_sqlite_params!(
+                        params,
+                        $( $pname ),*
+                        $( >list $lname )*
+                    );
+
+                    multithread_con.stream(move |con, send| {
+                        let ref_params: Vec<(&str, &dyn ToSqliteValue)> = params
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value as &dyn ToSqliteValue))
+                            .collect();
+                        let mut stmt = sqlite_statement(con $( , $lname )*)?;
+                        let mut rows = stmt.query_named(&ref_params[..])?;
+                        while let Some(row) = rows.next()? {
+                            if !send(sqlite_row(row)?) {
+                                break;
+                            }
+                        }
+                        Ok(())
+                    })
+                }
+                Connection::Mysql(conn) => async move {
+                    let query = stream_query(connection $( , $pname )* $( , $lname )*).await;
+                    Ok::<_, Error>(QueryKiller::run_stream(connection.read_query_killer(), query, |query| conn.read_query_stream(query)))
+                }
+                .try_flatten_stream()
+                .boxed(),
+                Connection::MysqlPool(pool) => async move {
+                    let query = stream_query(connection $( , $pname )* $( , $lname )*).await;
+                    let conn = pool.acquire().await?;
+                    let rows = QueryKiller::run_stream(connection.read_query_killer(), query, |query| conn.read_query_stream(query));
+                    Ok::<_, Error>(conn.run_stream(rows))
+                }
+                .try_flatten_stream()
+                .boxed(),
+                // The Postgres client has no streaming API, so its rows are
+                // fetched at once
+                Connection::Postgres(_) => {
+                    query_internal(connection $( , $pname )* $( , $lname )*)
+                        .map_ok(|rows| stream::iter(rows.into_iter().map(Ok)))
+                        .try_flatten_stream()
+                        .boxed()
+                }
+                Connection::Layered(_) => {
+                    unreachable!("backend is never a wrapping connection")
+                }
+            };
+            $crate::row_limit::limit_stream(connection.max_rows(), rows)
+        }
+
+        /// The Mysql query, tagged like the queries run on `connection`
+        async fn stream_query(
+            connection: &Connection,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> String {
+            $crate::tag::with_connection_tag(connection, async {
+                sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*).0
+            })
+            .await
         }
     );
 }
@@ -666,28 +1712,103 @@ macro_rules! _write_query_impl {
 
         $crate::_query_common!();
 
+        $crate::_check_query!(
+            params($( $pname ),*) lists(values) $qtype mysql($mysql_q) sqlite($sqlite_q)
+        );
+
         async fn query_internal(
             connection: &Connection,
             values: &[($( & $vtype, )*)],
             $( $pname: & $ptype ),*
         ) -> Result<WriteResult, Error> {
+            connection.acquire_write().await?;
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_write($crate::_query_name!(), connection);
+            let result = span.run(
+                |res| res.affected_rows(),
+                $crate::tag::with_connection_tag(
+                    connection,
+                    query_once(connection, values, $( $pname ),*),
+                ),
+            ).await;
+            $crate::_observe_query!(
+                connection.observer(),
+                start,
+                false,
+                $mysql_q,
+                result.as_ref().map(|res| res.affected_rows()),
+                ($( $pname ),*),
+                (values)
+            );
+            let result = result?;
+            connection.record_write();
+            Ok($crate::replication::track_write(connection, result).await)
+        }
+
+        async fn query_internal_with_transaction(
+            transaction: Transaction,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype ),*
+        ) -> Result<(Transaction, WriteResult), Error> {
+            let (transaction, observer) = transaction.take_observer();
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_transaction($crate::_query_name!(), &transaction);
+            let result = span.run(
+                |(_, res)| res.affected_rows(),
+                query_once_with_transaction(transaction, values, $( $pname ),*),
+            ).await;
+            $crate::_observe_query!(
+                observer.as_ref(),
+                start,
+                true,
+                $mysql_q,
+                result.as_ref().map(|(_, res)| res.affected_rows()),
+                ($( $pname ),*),
+                (values)
+            );
+            result.map(|(transaction, res)| (transaction.with_observer(observer), res))
+        }
+
+        async fn query_once(
+            connection: &Connection,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype ),*
+        ) -> Result<WriteResult, Error> {
+            connection.check_write("write query")?;
             if values.is_empty() {
                 return Ok(WriteResult::new(None, 0));
             }
 
-            match connection {
+            match connection.backend() {
                 Connection::Sqlite(multithread_con) => {
                     sqlite_exec_query(multithread_con.clone(), values, $( $pname ),*).await
                 }
                 Connection::Mysql(conn) => {
-                    let query = mysql_query(values, $( $pname ),*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
-                    Ok(res.into())
+                    let (query, _) = sql_query(SqlDialect::Mysql, values, $( $pname ),*);
+                    let res = QueryKiller::run(connection.query_killer(), query, |query| conn.write_query(query))
+                        .await?;
+                    let res: WriteResult = res.into();
+                    Ok(res.with_consecutive_insert_ids($mysql_q))
+                }
+                Connection::MysqlPool(pool) => {
+                    let conn = pool.acquire().await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, values, $( $pname ),*);
+                    let res = QueryKiller::run(connection.query_killer(), query, |query| conn.run(conn.write_query(query)))
+                        .await?;
+                    let res: WriteResult = res.into();
+                    Ok(res.with_consecutive_insert_ids($mysql_q))
+                }
+                Connection::Postgres(conn) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, values, $( $pname ),*);
+                    conn.write_query(query, params).await
+                }
+                Connection::Layered(_) => {
+                    unreachable!("backend is never a wrapping connection")
                 }
             }
         }
 
-        async fn query_internal_with_transaction(
+        async fn query_once_with_transaction(
             mut transaction: Transaction,
             values: &[($( & $vtype, )*)],
             $( $pname: & $ptype ),*
@@ -697,6 +1818,9 @@ macro_rules! _write_query_impl {
             }
 
             match transaction {
+                Transaction::Observed(_) => {
+                    unreachable!("observed transactions are unwrapped")
+                }
                 Transaction::Sqlite(ref mut transaction) => {
                     let con = transaction
                         .take()
@@ -709,17 +1833,42 @@ macro_rules! _write_query_impl {
                         })
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let query = mysql_query(values, $( $pname ),*);
+                    let (query, _) = sql_query(SqlDialect::Mysql, values, $( $pname ),*);
                     let mut tr = transaction.take()
                         .expect("should be Some before transaction ended");
 
                     let result = tr.write_query(query).map_err(Error::from).await?;
-                    Ok((Transaction::Mysql(Some(tr)), result.into()))
+                    let result: WriteResult = result.into();
+                    let result = result.with_consecutive_insert_ids($mysql_q);
+                    Ok((Transaction::Mysql(Some(tr)), result))
+                },
+                Transaction::MysqlPool(ref mut transaction) => {
+                    let (query, _) = sql_query(SqlDialect::Mysql, values, $( $pname ),*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+
+                    let result = QueryKiller::run(tr.killer(), query, |query| tr.write_query(query)).await?;
+                    let result: WriteResult = result.into();
+                    let result = result.with_consecutive_insert_ids($mysql_q);
+                    Ok((Transaction::MysqlPool(Some(tr)), result))
+                },
+                Transaction::Postgres(ref mut transaction) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, values, $( $pname ),*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+
+                    let result = tr.write_query(query, params).await?;
+                    Ok((Transaction::Postgres(Some(tr)), result))
                 },
             }
         }
 
-        fn mysql_query(values: &[($( & $vtype, )*)], $( $pname: & $ptype ),*) -> String {
+        fn sql_query(
+            dialect: SqlDialect,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype ),*
+        ) -> (String, Vec<$crate::mysql_async::Value>) {
+            let mut dialect = QueryValues::new(dialect);
             let mut val = String::new();
             let mut first = true;
             for value in values {
@@ -729,11 +1878,14 @@ macro_rules! _write_query_impl {
                     write!(&mut val, ", ").unwrap();
                 }
                 write!(&mut val, "(").unwrap();
-                $crate::_append_to_mysql_values!(val, value, $( $vtype, )*);
+                $crate::_append_to_mysql_values!(dialect; val, value, $( $vtype, )*);
                 write!(&mut val, ")").unwrap();
             }
 
-            $crate::_write_mysql_query!($qtype, $mysql_q, values: val, $( $pname ),*)
+            let query = $crate::tag::tag_query(
+                $crate::_write_mysql_query!($qtype, dialect, $mysql_q, values: val, $( $pname ),*)
+            );
+            (query, dialect.into_params())
         }
 
         async fn sqlite_exec_query(
@@ -749,31 +1901,36 @@ macro_rules! _write_query_impl {
                 $(
                     params.push((
                         concat!(":", stringify!($pname)),
-                        ValueWrapper(ToValue::to_value($pname)),
+                        ValueWrapper(ToSqlValue::to_sql_value($pname)),
                     ));
                 )*
 
                 multi_params.push(params);
             }
 
-            let con = multithread_con.get_sqlite_guard();
+            multithread_con.run(move |con| {
+                let mut stmt = sqlite_statement(con)?;
 
-            let mut stmt = sqlite_statement(&con)?;
+                let mut res = Vec::new();
+                let mut insert_ids = Vec::new();
+                for params in multi_params {
+                    let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                    for param in &params {
+                        param_refs.push((param.0, &param.1));
+                    }
 
-            let mut res = Vec::new();
-            for params in multi_params {
-                let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-                for param in &params {
-                    param_refs.push((param.0, &param.1));
+                    let affected_rows = stmt.execute_named(param_refs.as_ref())?;
+                    if affected_rows > 0 {
+                        insert_ids.push(con.last_insert_rowid() as u64);
+                    }
+                    res.push(affected_rows);
                 }
 
-                res.push(stmt.execute_named(param_refs.as_ref())?);
-            }
-
-            Ok(WriteResult::new(
-                Some(con.last_insert_rowid() as u64),
-                res.into_iter().sum::<usize>() as u64,
-            ))
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res.into_iter().sum::<usize>() as u64,
+                ).with_insert_ids(insert_ids).with_rows_matched())
+            }).await
         }
 
         async fn sqlite_exec_query_with_transaction(
@@ -789,35 +1946,38 @@ macro_rules! _write_query_impl {
                 $(
                     params.push((
                         concat!(":", stringify!($pname)),
-                        ValueWrapper(ToValue::to_value($pname)),
+                        ValueWrapper(ToSqlValue::to_sql_value($pname)),
                     ));
                 )*
 
                 multi_params.push(params);
             }
 
-            let res = {
-                let mut stmt = sqlite_statement(&transaction)?;
+            let (transaction, res) = transaction.run(move |con| {
+                let mut stmt = sqlite_statement(con)?;
 
                 let mut res = Vec::new();
+                let mut insert_ids = Vec::new();
                 for params in multi_params {
                     let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
                     for param in &params {
                         param_refs.push((param.0, &param.1));
                     }
 
-                    res.push(stmt.execute_named(param_refs.as_ref())?);
+                    let affected_rows = stmt.execute_named(param_refs.as_ref())?;
+                    if affected_rows > 0 {
+                        insert_ids.push(con.last_insert_rowid() as u64);
+                    }
+                    res.push(affected_rows);
                 }
 
-                res.into_iter().sum::<usize>()
-            };
-
-            let res = WriteResult::new(
-                Some(transaction.last_insert_rowid() as u64),
-                res as u64,
-            );
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res.into_iter().sum::<usize>() as u64,
+                ).with_insert_ids(insert_ids).with_rows_matched())
+            }).await;
 
-            Ok((transaction, res))
+            Ok((transaction, res?))
         }
 
         fn sqlite_statement<'a>(
@@ -844,29 +2004,105 @@ macro_rules! _write_query_impl {
 
         $crate::_query_common!();
 
+        $crate::_check_query!(
+            params($( $pname ),*) lists($( $lname ),*) $qtype mysql($mysql_q) sqlite($sqlite_q)
+        );
+
         async fn query_internal(
             connection: &Connection,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
         ) -> Result<WriteResult, Error> {
-            match connection {
+            connection.acquire_write().await?;
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_write($crate::_query_name!(), connection);
+            let result = span.run(
+                |res| res.affected_rows(),
+                $crate::tag::with_connection_tag(
+                    connection,
+                    query_once(connection, $( $pname, )* $( $lname, )*),
+                ),
+            ).await;
+            $crate::_observe_query!(
+                connection.observer(),
+                start,
+                false,
+                $mysql_q,
+                result.as_ref().map(|res| res.affected_rows()),
+                ($( $pname ),*),
+                ($( $lname ),*)
+            );
+            let result = result?;
+            connection.record_write();
+            Ok($crate::replication::track_write(connection, result).await)
+        }
+
+        async fn query_internal_with_transaction(
+            transaction: Transaction,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<(Transaction, WriteResult), Error> {
+            let (transaction, observer) = transaction.take_observer();
+            let start = std::time::Instant::now();
+            let span = QuerySpan::for_transaction($crate::_query_name!(), &transaction);
+            let result = span.run(
+                |(_, res)| res.affected_rows(),
+                query_once_with_transaction(transaction, $( $pname, )* $( $lname, )*),
+            ).await;
+            $crate::_observe_query!(
+                observer.as_ref(),
+                start,
+                true,
+                $mysql_q,
+                result.as_ref().map(|(_, res)| res.affected_rows()),
+                ($( $pname ),*),
+                ($( $lname ),*)
+            );
+            result.map(|(transaction, res)| (transaction.with_observer(observer), res))
+        }
+
+        async fn query_once(
+            connection: &Connection,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<WriteResult, Error> {
+            connection.check_write("write query")?;
+            match connection.backend() {
                 Connection::Sqlite(multithread_con) => {
                     sqlite_exec_query(multithread_con.clone() $( , $pname )* $( , $lname )*).await
                 }
                 Connection::Mysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let res = QueryKiller::run(connection.query_killer(), query, |query| conn.write_query(query))
+                        .await?;
+                    Ok(res.into())
+                }
+                Connection::MysqlPool(pool) => {
+                    let conn = pool.acquire().await?;
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let res = QueryKiller::run(connection.query_killer(), query, |query| conn.run(conn.write_query(query)))
+                        .await?;
                     Ok(res.into())
                 }
+                Connection::Postgres(conn) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, $( $pname, )* $( $lname, )*);
+                    conn.write_query(query, params).await
+                }
+                Connection::Layered(_) => {
+                    unreachable!("backend is never a wrapping connection")
+                }
             }
         }
 
-        async fn query_internal_with_transaction(
+        async fn query_once_with_transaction(
             mut transaction: Transaction,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
         ) -> Result<(Transaction, WriteResult), Error> {
             match transaction {
+                Transaction::Observed(_) => {
+                    unreachable!("observed transactions are unwrapped")
+                }
                 Transaction::Sqlite(ref mut transaction) => {
                     let con = transaction
                         .take()
@@ -879,18 +2115,40 @@ macro_rules! _write_query_impl {
                         })
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
                     let mut tr = transaction.take()
                         .expect("should be Some before transaction ended");
                     let result = tr.write_query(query).map_err(Error::from).await?;
                     Ok((Transaction::Mysql(Some(tr)), result.into()))
                 },
+                Transaction::MysqlPool(ref mut transaction) => {
+                    let (query, _) = sql_query(SqlDialect::Mysql, $( $pname, )* $( $lname, )*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let result = QueryKiller::run(tr.killer(), query, |query| tr.write_query(query)).await?;
+                    Ok((Transaction::MysqlPool(Some(tr)), result.into()))
+                },
+                Transaction::Postgres(ref mut transaction) => {
+                    let (query, params) = sql_query(SqlDialect::Postgres, $( $pname, )* $( $lname, )*);
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let result = tr.write_query(query, params).await?;
+                    Ok((Transaction::Postgres(Some(tr)), result))
+                },
             }
         }
 
-        fn mysql_query($( $pname: & $ptype, )* $( $lname: & [ $ltype ], )*) -> String {
-            $crate::_emit_mysql_lnames!($( $lname ),*);
-            $crate::_write_mysql_query!($qtype, $mysql_q, $( $pname ),* $( >list $lname )*)
+        fn sql_query(
+            dialect: SqlDialect,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> (String, Vec<$crate::mysql_async::Value>) {
+            let mut dialect = QueryValues::new(dialect);
+            $crate::_emit_mysql_lnames!(dialect; $( $lname: $ltype ),*);
+            let query = $crate::tag::tag_query(
+                $crate::_write_mysql_query!($qtype, dialect, $mysql_q, $( $pname ),* $( >list $lname )*)
+            );
+            (query, dialect.into_params())
         }
 
         async fn sqlite_exec_query(
@@ -904,21 +2162,21 @@ macro_rules! _write_query_impl {
                 $( >list $lname )*
             );
 
-            let con = multithread_con.get_sqlite_guard();
-
-            let mut stmt = sqlite_statement(&con  $( , $lname )*)?;
+            multithread_con.run(move |con| {
+                let mut stmt = sqlite_statement(con  $( , $lname )*)?;
 
-            let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-            for param in &params {
-                param_refs.push((&param.0, &param.1));
-            }
+                let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                for param in &params {
+                    param_refs.push((&param.0, &param.1));
+                }
 
-            let res = stmt.execute_named(param_refs.as_ref())?;
+                let res = stmt.execute_named(param_refs.as_ref())?;
 
-            Ok(WriteResult::new(
-                Some(con.last_insert_rowid() as u64),
-                res as u64,
-            ))
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res as u64,
+                ).with_rows_matched())
+            }).await
         }
 
         async fn sqlite_exec_query_with_transaction(
@@ -932,23 +2190,23 @@ macro_rules! _write_query_impl {
                 $( >list $lname )*
             );
 
-            let res = {
-                let mut stmt = sqlite_statement(&transaction  $( , $lname )*)?;
+            let (transaction, res) = transaction.run(move |con| {
+                let mut stmt = sqlite_statement(con  $( , $lname )*)?;
 
                 let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
                 for param in &params {
                     param_refs.push((&param.0, &param.1));
                 }
 
-                stmt.execute_named(param_refs.as_ref())?
-            };
+                let res = stmt.execute_named(param_refs.as_ref())?;
 
-            let res = WriteResult::new(
-                Some(transaction.last_insert_rowid() as u64),
-                res as u64,
-            );
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res as u64,
+                ).with_rows_matched())
+            }).await;
 
-            Ok((transaction, res))
+            Ok((transaction, res?))
         }
 
         fn sqlite_statement<'a>(
@@ -969,36 +2227,36 @@ macro_rules! _write_query_impl {
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _write_mysql_query {
-    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
-        format!(
-            $q,
-            insert_or_ignore = "INSERT IGNORE",
+    (insert_or_ignore, $dialect:ident, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+        $dialect.finish_insert_or_ignore(format!(
+            $crate::_named_query!($q),
+            insert_or_ignore = $dialect.insert_or_ignore(),
             values = $values,
-            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
-        )
+            $( $pname = $dialect.quote(&ToSqlValue::to_sql_value($pname)), )*
+        ))
     };
 
-    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
-        format!(
-            $q,
-            insert_or_ignore = "INSERT IGNORE",
-            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+    (insert_or_ignore, $dialect:ident, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        $dialect.finish_insert_or_ignore(format!(
+            $crate::_named_query!($q),
+            insert_or_ignore = $dialect.insert_or_ignore(),
+            $( $pname = $dialect.quote(&ToSqlValue::to_sql_value($pname)), )*
             $( $lname = $lname, )*
-        )
+        ))
     };
 
-    (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+    (none, $dialect:ident, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
         format!(
-            $q,
+            $crate::_named_query!($q),
             values = $values,
-            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $( $pname = $dialect.quote(&ToSqlValue::to_sql_value($pname)), )*
         )
     };
 
-    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+    (none, $dialect:ident, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
-            $q,
-            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $crate::_named_query!($q),
+            $( $pname = $dialect.quote(&ToSqlValue::to_sql_value($pname)), )*
             $( $lname = $lname, )*
         )
     };
@@ -1009,7 +2267,7 @@ macro_rules! _write_mysql_query {
 macro_rules! _write_sqlite_query {
     (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
         format!(
-            $q,
+            $crate::_named_query!($q),
             insert_or_ignore = "INSERT OR IGNORE",
             values = $values,
             $( $pname = concat!(":", stringify!($pname)), )*
@@ -1018,7 +2276,7 @@ macro_rules! _write_sqlite_query {
 
     (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
-            $q,
+            $crate::_named_query!($q),
             insert_or_ignore = "INSERT OR IGNORE",
             $( $pname = concat!(":", stringify!($pname)), )*
             $( $lname = $lname, )*
@@ -1027,7 +2285,7 @@ macro_rules! _write_sqlite_query {
 
     (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
         format!(
-            $q,
+            $crate::_named_query!($q),
             values = $values,
             $( $pname = concat!(":", stringify!($pname)), )*
         )
@@ -1035,7 +2293,7 @@ macro_rules! _write_sqlite_query {
 
     (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
-            $q,
+            $crate::_named_query!($q),
             $( $pname = concat!(":", stringify!($pname)), )*
             $( $lname = $lname, )*
         )
@@ -1074,7 +2332,7 @@ macro_rules! _sqlite_named_params {
                 $(
                     $params.push((
                         concat!(":", stringify!($unames)),
-                        ValueWrapper(ToValue::to_value($uses)),
+                        ValueWrapper($uses.to_sql_value()),
                     ));
                 )*
             }
@@ -1085,18 +2343,18 @@ macro_rules! _sqlite_named_params {
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _append_to_mysql_values {
-    ($values:ident, $tup:ident, $( $vtype:ty, )*) => (
-        $crate::_append_to_mysql_values!(@expand () {} $values, $tup, $( $vtype, )* )
+    ($dialect:ident; $values:ident, $tup:ident, $( $vtype:ty, )*) => (
+        $crate::_append_to_mysql_values!(@expand $dialect; () {} $values, $tup, $( $vtype, )* )
     );
 
     (
-        @expand
+        @expand $dialect:ident;
         ( $( $binds:pat , )* )
         { $( $uses:expr , )* }
         $values:ident, $tup:ident, $vtype:ty, $( $vtypes:ty, )+
     ) => (
         $crate::_append_to_mysql_values!(
-            @expand
+            @expand $dialect;
             ( $( $binds , )* value , )
             { $( $uses , )* value , }
             $values, $tup, $( $vtypes, )+
@@ -1104,7 +2362,7 @@ macro_rules! _append_to_mysql_values {
     );
 
     (
-        @expand
+        @expand $dialect:ident;
         ( $( $binds:pat , )* )
         { $( $uses:expr , )* }
         $values:ident, $tup:ident, $vtype:ty,
@@ -1112,9 +2370,9 @@ macro_rules! _append_to_mysql_values {
         match $tup {
             ( $( $binds , )* value , ) => {
                 $(
-                    write!(&mut $values, "{}, ", $uses.to_value().as_sql(false)).unwrap();
+                    write!(&mut $values, "{}, ", $dialect.quote(&$uses.to_sql_value())).unwrap();
                 )*
-                write!(&mut $values, "{}", value.to_value().as_sql(false)).unwrap();
+                write!(&mut $values, "{}", $dialect.quote(&value.to_sql_value())).unwrap();
             }
         }
     );
@@ -1124,23 +2382,12 @@ macro_rules! _append_to_mysql_values {
 #[doc(hidden)]
 /// Serialize all >list $lname elements into strings suitable for interpolation into a SQL string.
 macro_rules! _emit_mysql_lnames {
-    ($( $lname:ident ),*) => {
+    ($dialect:ident; $( $lname:ident: $ltype:ty ),*) => {
         $(
-            let $lname = {
-                let mut val = String::new();
-                write!(&mut val, "(").unwrap();
-                let mut first = true;
-                for lval in $lname {
-                    if first {
-                        first = false;
-                    } else {
-                        write!(&mut val, ", ").unwrap();
-                    }
-                    write!(&mut val, "{}", ToValue::to_value(&lval).as_sql(false)).unwrap();
-                }
-                write!(&mut val, ")").unwrap();
-                val
-            };
+            let $lname = $dialect.list(
+                $lname.iter().map(|lval| ToSqlValue::to_sql_value(lval)),
+                $crate::_postgres_list_type!($ltype),
+            );
         )*
     }
 }
@@ -1148,7 +2395,7 @@ macro_rules! _emit_mysql_lnames {
 #[macro_export]
 #[doc(hidden)]
 /// Serialize all >list $lname elements into strings suitable for interpolation into a SQLite
-/// prepared statement.
+/// prepared statement. Unlike Mysql, SQLite accepts empty lists.
 macro_rules! _emit_sqlite_lnames {
     ($( $lname:ident ),*) => {
         $(
@@ -1177,7 +2424,7 @@ macro_rules! _emit_sqlite_lnames {
 macro_rules! _prepare_sqlite_params {
     ($params:ident, $( $pname:ident ),* $( >list $lname:ident )*) => (
         let $params = vec![ $(
-            (format!(":{}", stringify!($pname)), ValueWrapper(ToValue::to_value($pname)))
+            (format!(":{}", stringify!($pname)), ValueWrapper(ToSqlValue::to_sql_value($pname)))
         ),* ].into_iter();
 
         $(
@@ -1186,7 +2433,7 @@ macro_rules! _prepare_sqlite_params {
                     .enumerate()
                     .map(|(idx, val)| (
                         format!(":{}{}", stringify!($lname), idx),
-                        ValueWrapper(ToValue::to_value(val)),
+                        ValueWrapper(ToSqlValue::to_sql_value(val)),
                     ))
             );
         )*
@@ -1199,6 +2446,44 @@ macro_rules! _prepare_sqlite_params {
     )
 }
 
+#[macro_export]
+/// Implement the conversions reading `T`, which implements [FromSqlValue], as the column of a
+/// query, and `Option<T>` for the nullable columns. [FromSqlValue] can be derived instead, which
+/// calls it.
+///
+/// # Example:
+/// ```ignore
+/// pub struct Timestamp(i64);
+///
+/// impl sql::FromSqlValue for Timestamp {
+///     fn from_sql_value(value: Value) -> Result<Self, FromValueError> {
+///         Ok(Timestamp(i64::from_value_opt(value)? * 1000))
+///     }
+/// }
+/// sql::from_sql_value_conv!(Timestamp);
+/// ```
+macro_rules! from_sql_value_conv {
+    ($t:ty) => {
+        impl $crate::mysql_async::prelude::FromValue for $t {
+            type Intermediate = $crate::value::SqlValueIr<$t>;
+        }
+
+        impl $crate::mysql::TryFromRowField for $t {
+            fn try_from(field: $crate::mysql::RowField) -> Result<Self, $crate::mysql::MysqlError> {
+                $crate::mysql::opt_try_from_rowfield(field)
+            }
+        }
+
+        impl $crate::mysql::OptionalTryFromRowField for $t {
+            fn try_from_opt(
+                field: $crate::mysql::RowField,
+            ) -> Result<Option<Self>, $crate::mysql::MysqlError> {
+                $crate::mysql::opt_try_from_rowfield(field)
+            }
+        }
+    };
+}
+
 #[macro_export]
 /// Given types `T`, `Intermediate`, and `Raw` as macro arguments:
 ///   * Define `FromValue` for `T` with `Intermedate` set as the associated type.
@@ -10,12 +10,18 @@
 #![deny(warnings)]
 
 use sql_tests_lib::{
-    test_datetime_query, test_read_query, test_transaction_commit, test_transaction_rollback,
-    test_transaction_rollback_on_drop, test_write_query, TestSemantics,
+    test_bulk_insert, test_cache_key, test_cas_write, test_connection_label, test_cte_and_union,
+    test_datetime_query, test_empty_list, test_explain, test_json, test_max_rows,
+    test_named_params, test_nullable_columns, test_query_observer, test_query_stats,
+    test_read_query, test_read_stream_query, test_readonly, test_replication_position,
+    test_rows_matched, test_sql_values, test_transaction_commit, test_transaction_rollback,
+    test_transaction_rollback_on_drop, test_transaction_savepoint, test_write_query, TestSemantics,
 };
 
+use crate::mysql_async::Value;
 use crate::rusqlite::Connection as SqliteConnection;
-use crate::Connection;
+use crate::sql_common::retry::{RetryPolicy, RetryingConnection};
+use crate::{Connection, QueryValues, SqlDialect};
 
 #[tokio::test]
 async fn test_read_query_sqlite() {
@@ -26,6 +32,16 @@ async fn test_read_query_sqlite() {
     .await
 }
 
+#[tokio::test]
+async fn test_read_query_retrying_sqlite() {
+    let conn = Connection::with_sqlite(SqliteConnection::open_in_memory().unwrap());
+    test_read_query(
+        RetryingConnection::new("test", conn, RetryPolicy::default()).into(),
+        TestSemantics::Sqlite,
+    )
+    .await
+}
+
 fn prepare_sqlite_con() -> Connection {
     let conn = SqliteConnection::open_in_memory().unwrap();
     conn.execute_batch(
@@ -41,6 +57,16 @@ fn prepare_sqlite_con() -> Connection {
     Connection::with_sqlite(conn)
 }
 
+#[tokio::test]
+async fn test_query_stats_with_sqlite() {
+    test_query_stats(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_read_stream_query_with_sqlite() {
+    test_read_stream_query(prepare_sqlite_con()).await;
+}
+
 #[tokio::test]
 async fn test_datetime_query_with_sqlite() {
     test_datetime_query(prepare_sqlite_con()).await;
@@ -66,6 +92,132 @@ async fn test_transaction_commit_with_sqlite() {
     test_transaction_commit(prepare_sqlite_con(), TestSemantics::Sqlite).await;
 }
 
+#[test]
+fn test_query_values() {
+    let mut mysql = QueryValues::new(SqlDialect::Mysql);
+    assert_eq!(mysql.quote(&Value::from("a\\b")), "'a\\\\b'");
+    assert_eq!(
+        mysql.list(vec![Value::from(1u64), Value::from("a'b")], "text"),
+        "(1, 'a\\'b')"
+    );
+    assert_eq!(
+        mysql.list(vec![], "bigint"),
+        "(SELECT NULL FROM DUAL WHERE FALSE)"
+    );
+    assert!(mysql.into_params().is_empty());
+
+    let mut postgres = QueryValues::new(SqlDialect::Postgres);
+    assert_eq!(postgres.quote(&Value::from("it's")), "$1");
+    assert_eq!(
+        postgres.list(vec![Value::from(vec![0u8, 255]), Value::NULL], "bytea"),
+        "($2, $3)"
+    );
+    assert_eq!(
+        postgres.list(vec![], "bigint"),
+        "(SELECT NULL::bigint WHERE FALSE)"
+    );
+    assert_eq!(crate::_postgres_list_type!(u64), "bigint");
+    assert_eq!(crate::_postgres_list_type!(Vec<u8>), "bytea");
+    assert_eq!(crate::_postgres_list_type!(sql_tests_lib::RowId), "text");
+    assert_eq!(
+        postgres.finish_insert_or_ignore("INSERT INTO foo (x) VALUES (1);".to_owned()),
+        "INSERT INTO foo (x) VALUES (1) ON CONFLICT DO NOTHING"
+    );
+    assert_eq!(
+        postgres.into_params(),
+        vec![
+            Value::from("it's"),
+            Value::from(vec![0u8, 255]),
+            Value::NULL
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_transaction_savepoint_with_sqlite() {
+    test_transaction_savepoint(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_bulk_insert_with_sqlite() {
+    test_bulk_insert(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_cas_write_with_sqlite() {
+    test_cas_write(prepare_sqlite_con()).await;
+}
+
+#[test]
+fn test_cache_key_query() {
+    test_cache_key();
+}
+
+#[tokio::test]
+async fn test_connection_label_with_sqlite() {
+    test_connection_label(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_replication_position_with_sqlite() {
+    test_replication_position(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_max_rows_with_sqlite() {
+    test_max_rows(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_cte_and_union_with_sqlite() {
+    test_cte_and_union(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_empty_list_with_sqlite() {
+    test_empty_list(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_named_params_with_sqlite() {
+    test_named_params(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_json_with_sqlite() {
+    test_json(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_nullable_columns_with_sqlite() {
+    test_nullable_columns(prepare_sqlite_con(), TestSemantics::Sqlite).await;
+}
+
+#[tokio::test]
+async fn test_explain_with_sqlite() {
+    test_explain(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_rows_matched_with_sqlite() {
+    test_rows_matched(prepare_sqlite_con(), TestSemantics::Sqlite).await;
+}
+
+#[tokio::test]
+async fn test_sql_values_with_sqlite() {
+    test_sql_values(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_readonly_with_sqlite() {
+    test_readonly(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_query_observer_with_sqlite() {
+    test_query_observer(prepare_sqlite_con()).await;
+}
+
 #[cfg(fbcode_build)]
 #[cfg(test)]
 mod mysql {
@@ -9,14 +9,28 @@
 
 #![deny(warnings, clippy::all)]
 
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use chrono::{NaiveDate, NaiveDateTime};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 use sql::anyhow::Error;
+use sql::futures::{StreamExt, TryStreamExt};
 use sql::mysql_async::prelude::*;
 use sql::mysql_async::{FromValueError, Value};
+use sql::sql_common::bulk_insert::BulkInsert;
+use sql::sql_common::cas::{CasOutcome, CasRetry};
+use sql::sql_common::error::TooManyRowsError;
+use sql::sql_common::label::ConnectionLabel;
 use sql::sql_common::mysql;
-use sql::{queries, Connection, Transaction};
+use sql::sql_common::observer::{QueryInfo, QueryObserver};
+use sql::sql_common::replication::ReplicationPosition;
+use sql::{
+    queries, Connection, FromSqlValue, Json, SqlConnections, SqlShardedConnections, ToSqlValue,
+    Transaction,
+};
 
 pub struct A;
 
@@ -51,6 +65,32 @@ impl FromValue for B {
     type Intermediate = IntB;
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToSqlValue, FromSqlValue)]
+pub struct RowId(pub u64);
+
+/// Value stored in thousandths
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Millis(pub i64);
+
+impl ToSqlValue for Millis {
+    fn to_sql_value(&self) -> Value {
+        Value::Int(self.0 / 1000)
+    }
+}
+
+impl FromSqlValue for Millis {
+    fn from_sql_value(value: Value) -> Result<Self, FromValueError> {
+        Ok(Millis(i64::from_value_opt(value)? * 1000))
+    }
+}
+sql::from_sql_value_conv!(Millis);
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Settings {
+    pub colors: Vec<String>,
+    pub size: Option<u64>,
+}
+
 queries! {
     read TestQuery(param_a: A, param_uint: u64) -> (u64, B, B, i64) {
         "SELECT 44, NULL, {param_a}, {param_uint}"
@@ -104,6 +144,73 @@ queries! {
     read TestQuery14(date: NaiveDateTime) -> (String) {
         "SELECT datetime(y) FROM foo WHERE y = {date}"
     }
+
+    read_stream TestQuery15(>list id: u64) -> (i64) {
+        "SELECT x FROM foo WHERE ID IN {id} ORDER BY ID"
+    }
+
+    write TestQuery16(x: i64, >list ids: u64) {
+        none,
+        "UPDATE foo SET x = :x WHERE id IN :ids"
+    }
+
+    read TestQuery17(value: serde_json::Value, settings: Json<Settings>) -> (serde_json::Value, Json<Settings>) {
+        "SELECT {value}, {settings}"
+    }
+
+    write TestQuery18(x: Option<i64>) {
+        none,
+        "INSERT INTO foo (x) VALUES ({x})"
+    }
+
+    read TestQuery19(>list id: u64) -> (Option<i64>, u64) {
+        "SELECT x, id FROM foo WHERE id IN {id} ORDER BY id"
+    }
+    write TestQuery20(values: (x: Millis)) {
+        none,
+        "INSERT INTO foo (x) VALUES {values}"
+    }
+    read TestQuery21(x: Millis, >list id: RowId) -> (RowId, Millis, Option<RowId>) {
+        "SELECT id, x, NULL FROM foo WHERE x = {x} AND id IN {id} ORDER BY id"
+    }
+    read TestQuery22(x: i64) -> (u64) {
+        "SELECT id FROM foo WHERE x = {x}"
+    }
+    cas_write TestQuery23(id: u64, x: i64) {
+        "UPDATE foo SET x = x + 1 WHERE id = {id} AND x = {x}"
+    }
+    read TestQuery24(x: i64, id: u64) -> (u64, i64) {
+        "WITH matching AS (SELECT id, x FROM foo WHERE x = {x}),
+         counts AS (SELECT COUNT(*) AS n FROM matching WHERE id > {id})
+         SELECT id, n FROM matching, counts ORDER BY id"
+    }
+    read TestQuery25(x: i64, y: i64, >list ids: u64) -> (u64) {
+        "SELECT id FROM foo WHERE x = {x}
+         UNION SELECT id FROM foo WHERE x > {y} AND id IN {ids}
+         UNION ALL SELECT id FROM foo WHERE x = {x} AND id IN {ids}
+         ORDER BY id"
+    }
+    read TestQuery26(x: i64, y: i64) -> (u64) {
+        mysql("(SELECT id FROM foo WHERE x = {x}) UNION (SELECT id FROM foo WHERE x = {y}) ORDER BY id")
+        sqlite("SELECT id FROM foo WHERE x = {x} UNION SELECT id FROM foo WHERE x = {y} ORDER BY id")
+    }
+    read TestQuery27(id1: u64, id2: u64, x: i64) -> (u64, String) {
+        "SELECT id, ':x' FROM foo WHERE :id1 <= id AND id <= :id2 AND x = {x} ORDER BY id"
+    }
+    read TestQuery28(>list id: u64) -> (i64) {
+        "SELECT x FROM foo WHERE id NOT IN {id} ORDER BY id"
+    }
+    pub write TestQuery29(x: i64, >list ids: u64) {
+        none,
+        "UPDATE foo SET x = {x} WHERE id IN {ids}"
+    }
+    write TestQuery30(x: i64) {
+        none,
+        "UPDATE foo SET x = {x}"
+    }
+    read TestQuery31(id: u64) -> (i64) {
+        "SELECT x FROM missing_table WHERE id = {id}"
+    }
 }
 
 pub async fn test_basic_query(conn: Connection) -> Result<(), Error> {
@@ -150,7 +257,7 @@ pub async fn test_read_query(conn: Connection, semantics: TestSemantics) {
     assert_eq!(
         TestQuery6::query(&conn).await.unwrap(),
         vec![(match semantics {
-            TestSemantics::Mysql => 6i64,
+            TestSemantics::Mysql | TestSemantics::Postgres => 6i64,
             TestSemantics::Sqlite => 7i64,
         },)]
     );
@@ -165,14 +272,38 @@ pub async fn test_datetime_query(conn: Connection) {
     assert_eq!(res, vec![("2021-01-21 21:21:21".to_owned(),)]);
 }
 
+pub async fn test_read_stream_query(conn: Connection) {
+    let res = TestQuery3::query(&conn, &[(&44,), (&72,), (&53,)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 3);
+
+    let rows: Vec<_> = TestQuery15::query_stream(&conn, &[1, 3])
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(44,), (53,)]);
+
+    // Dropping the stream early releases the connection
+    let first = TestQuery15::query_stream(&conn, &[1, 2, 3])
+        .next()
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, (44,));
+    assert_eq!(TestQuery15::query(&conn, &[2]).await.unwrap(), vec![(72,)]);
+}
+
 pub async fn test_write_query(conn: Connection) {
     let res = TestQuery3::query(&conn, &[(&44,)]).await.unwrap();
     assert_eq!(res.affected_rows(), 1);
     assert_eq!(res.last_insert_id(), Some(1));
+    assert_eq!(res.insert_ids(), &[1]);
 
     let res = TestQuery3::query(&conn, &[(&72,), (&53,)]).await.unwrap();
     assert_eq!(res.affected_rows(), 2);
     assert_eq!(res.last_insert_id(), Some(3));
+    assert_eq!(res.insert_ids(), &[2, 3]);
 
     assert_eq!(
         TestQuery4::query(&conn, &1, &3).await.unwrap(),
@@ -182,6 +313,7 @@ pub async fn test_write_query(conn: Connection) {
     let res = TestQuery7::query(&conn, &123).await.unwrap();
     assert_eq!(res.affected_rows(), 1);
     assert_eq!(res.last_insert_id(), Some(1));
+    assert!(res.insert_ids().is_empty());
 
     assert_eq!(
         TestQuery5::query(&conn, &[1, 2, 3]).await.unwrap(),
@@ -216,6 +348,7 @@ pub async fn test_write_query(conn: Connection) {
 pub enum TestSemantics {
     Sqlite,
     Mysql,
+    Postgres,
 }
 
 pub async fn in_transaction(transaction: Transaction, semantics: TestSemantics) -> Transaction {
@@ -232,8 +365,11 @@ pub async fn in_transaction(transaction: Transaction, semantics: TestSemantics)
     match semantics {
         // MySQL returns first ID for multi-row inserts
         TestSemantics::Mysql => assert_eq!(res.last_insert_id(), Some(2)),
-        TestSemantics::Sqlite => assert_eq!(res.last_insert_id(), Some(3)),
+        TestSemantics::Sqlite | TestSemantics::Postgres => {
+            assert_eq!(res.last_insert_id(), Some(3))
+        }
     }
+    assert_eq!(res.insert_ids(), &[2, 3]);
 
     let (transaction, res) = TestQuery4::query_with_transaction(transaction, &1, &3)
         .await
@@ -247,6 +383,7 @@ pub async fn in_transaction(transaction: Transaction, semantics: TestSemantics)
         // MySQL counts a replace of an existing row as affecting two rows.
         TestSemantics::Mysql => assert_eq!(res.affected_rows(), 2),
         TestSemantics::Sqlite => assert_eq!(res.affected_rows(), 1),
+        TestSemantics::Postgres => unreachable!("Postgres has no REPLACE"),
     }
     assert_eq!(res.last_insert_id(), Some(1));
 
@@ -284,3 +421,449 @@ pub async fn test_transaction_commit(conn: Connection, semantics: TestSemantics)
         vec![(123,), (72,), (53,)]
     );
 }
+
+pub async fn test_transaction_savepoint(conn: Connection) {
+    let transaction = conn.start_transaction().await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&44,)])
+        .await
+        .unwrap();
+
+    transaction.savepoint("chunk").await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&72,)])
+        .await
+        .unwrap();
+    transaction.rollback_to_savepoint("chunk").await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&53,)])
+        .await
+        .unwrap();
+    transaction.release_savepoint("chunk").await.unwrap();
+    assert!(transaction.savepoint("not a name").await.is_err());
+    transaction.commit().await.unwrap();
+
+    let rows = TestQuery4::query(&conn, &1, &10).await.unwrap();
+    assert_eq!(rows, vec![(44,), (53,)]);
+}
+
+/// Name, params, result and whether in a transaction of an observed query
+type Observed = (String, String, Result<u64, String>, bool);
+
+#[derive(Default)]
+struct RecordingObserver(Mutex<Vec<Observed>>);
+
+impl QueryObserver for RecordingObserver {
+    fn observe(&self, info: &QueryInfo<'_>) {
+        self.0.lock().unwrap().push((
+            info.name.to_owned(),
+            info.params.to_owned(),
+            info.result.map_err(|err| err.to_string()),
+            info.in_transaction,
+        ));
+    }
+}
+
+pub async fn test_query_observer(conn: Connection) {
+    let observer = Arc::new(RecordingObserver::default());
+    let conn = conn.with_observer(observer.clone());
+
+    TestQuery3::query(&conn, &[(&44,), (&72,)]).await.unwrap();
+    TestQuery5::query(&conn, &[1, 2, 3]).await.unwrap();
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery4::query_with_transaction(transaction, &1, &2)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+
+    let ok = |name: &str, params: &str, rows, in_transaction| {
+        (name.to_owned(), params.to_owned(), Ok(rows), in_transaction)
+    };
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        vec![
+            ok("TestQuery3", "values: [2 items]", 2, false),
+            ok("TestQuery5", "id: [3 items]", 2, false),
+            ok("BEGIN", "", 0, true),
+            ok("TestQuery4", "id1, id2", 2, true),
+            ok("COMMIT", "", 0, true),
+        ]
+    );
+}
+
+pub async fn test_bulk_insert(conn: Connection) {
+    let rows: Vec<i64> = (0..5).collect();
+    let result = BulkInsert::new(2)
+        .run(rows, |chunk| {
+            let conn = &conn;
+            async move {
+                let values: Vec<_> = chunk.iter().map(|x| (x,)).collect();
+                TestQuery3::query(conn, &values).await
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(result.affected_rows(), 5);
+    assert_eq!(result.chunks(), 3);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, result) = BulkInsert::new(4)
+        .run_with_transaction(
+            transaction,
+            vec![10, 11, 12, 13, 14],
+            |transaction, chunk| async move {
+                let values: Vec<_> = chunk.iter().map(|x| (x,)).collect();
+                TestQuery3::query_with_transaction(transaction, &values).await
+            },
+        )
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(result.affected_rows(), 5);
+    assert_eq!(result.chunks(), 2);
+
+    let rows = TestQuery4::query(&conn, &1, &10).await.unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            (0,),
+            (1,),
+            (2,),
+            (3,),
+            (4,),
+            (10,),
+            (11,),
+            (12,),
+            (13,),
+            (14,)
+        ]
+    );
+}
+
+pub async fn test_named_params(conn: Connection) {
+    TestQuery3::query(&conn, &[(&44,), (&72,), (&53,)])
+        .await
+        .unwrap();
+
+    let res = TestQuery16::query_named(
+        &conn,
+        TestQuery16::Params {
+            ids: &[1, 3],
+            x: &60,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.affected_rows(), 2);
+
+    let rows = TestQuery4::query_named(&conn, TestQuery4::Params { id2: &3, id1: &1 })
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(60,), (72,), (60,)]);
+
+    let rows = TestQuery27::query_named(
+        &conn,
+        TestQuery27::Params {
+            x: &60,
+            id2: &3,
+            id1: &2,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(rows, vec![(3, ":x".to_owned())]);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, rows) =
+        TestQuery5::query_named_with_transaction(transaction, TestQuery5::Params { id: &[2] })
+            .await
+            .unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(rows, vec![(72,)]);
+}
+
+pub async fn test_empty_list(conn: Connection) {
+    TestQuery3::query(&conn, &[(&44,), (&72,)]).await.unwrap();
+
+    let rows = TestQuery5::query(&conn, &[]).await.unwrap();
+    assert!(rows.is_empty());
+    let res = TestQuery8::query(&conn, &[]).await.unwrap();
+    assert_eq!(res.affected_rows(), 0);
+    let rows = TestQuery5::query(&conn, &[2]).await.unwrap();
+    assert_eq!(rows, vec![(72,)]);
+
+    // NOT IN an empty list matches every row
+    let rows = TestQuery28::query(&conn, &[]).await.unwrap();
+    assert_eq!(rows, vec![(44,), (72,)]);
+    let rows = TestQuery28::query(&conn, &[1]).await.unwrap();
+    assert_eq!(rows, vec![(72,)]);
+
+    let res = TestQuery29::query(&conn, &45, &[]).await.unwrap();
+    assert_eq!(res.affected_rows(), 0);
+    let res = TestQuery29::query_named(
+        &conn,
+        TestQuery29::Params {
+            x: &45,
+            ids: &[1, 2],
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.affected_rows(), 2);
+}
+
+pub async fn test_query_stats(conn: Connection) {
+    let stat = |stat: &str| {
+        stats::snapshot()
+            .get(&format!("sql.query.sql_tests_lib::{}", stat))
+            .copied()
+            .unwrap_or_default()
+    };
+    TestQuery3::query(&conn, &[(&44,), (&72,)]).await.unwrap();
+    TestQuery30::query(&conn, &45).await.unwrap();
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery30::query_with_transaction(transaction, &46)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(stat("TestQuery30.calls.sum"), 2);
+    assert_eq!(stat("TestQuery30.rows.sum"), 4);
+    assert_eq!(stat("TestQuery30.errors.sum"), 0);
+    assert_eq!(stat("TestQuery30.latency_us.count"), 2);
+
+    assert!(TestQuery31::query(&conn, &1).await.is_err());
+    assert_eq!(stat("TestQuery31.calls.sum"), 1);
+    assert_eq!(stat("TestQuery31.errors.sum"), 1);
+}
+
+pub async fn test_readonly(conn: Connection) {
+    TestQuery3::query(&conn, &[(&44,)]).await.unwrap();
+
+    let readonly = conn.clone().readonly();
+    let rows = TestQuery5::query(&readonly, &[1]).await.unwrap();
+    assert_eq!(rows, vec![(44,)]);
+    let rows: Vec<_> = TestQuery15::query_stream(&readonly, &[1])
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(44,)]);
+
+    assert!(TestQuery3::query(&readonly, &[(&72,)]).await.is_err());
+    assert!(TestQuery8::query(&readonly, &[1]).await.is_err());
+    assert!(readonly.start_transaction().await.is_err());
+
+    let rows = TestQuery5::query(&conn, &[1, 2]).await.unwrap();
+    assert_eq!(rows, vec![(44,)]);
+}
+
+pub async fn test_json(conn: Connection) {
+    let value = serde_json::json!({"a": [1, 2], "b": null});
+    let settings = Json(Settings {
+        colors: vec!["red".to_owned(), "blue".to_owned()],
+        size: Some(3),
+    });
+    let rows = TestQuery17::query(&conn, &value, &settings).await.unwrap();
+    assert_eq!(rows, vec![(value, settings)]);
+}
+
+pub async fn test_nullable_columns(conn: Connection, semantics: TestSemantics) {
+    TestQuery18::query(&conn, &Some(44)).await.unwrap();
+    TestQuery18::query(&conn, &None).await.unwrap();
+
+    let rows = TestQuery19::query(&conn, &[1, 2]).await.unwrap();
+    assert_eq!(rows, vec![(Some(44), 1), (None, 2)]);
+
+    let err = TestQuery5::query(&conn, &[2]).await.unwrap_err();
+    if let TestSemantics::Sqlite = semantics {
+        assert!(
+            format!("{:#}", err)
+                .contains("column `x` (index 0) is NULL, but `i64` is not optional"),
+            "{:#}",
+            err
+        );
+    }
+}
+
+pub async fn test_sql_values(conn: Connection) {
+    let values = [(&Millis(44_000),), (&Millis(44_000),), (&Millis(72_000),)];
+    TestQuery20::query(&conn, &values).await.unwrap();
+
+    let rows = TestQuery21::query(&conn, &Millis(44_000), &[RowId(1), RowId(3)])
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(RowId(1), Millis(44_000), None)]);
+}
+
+pub async fn test_explain(conn: Connection) {
+    let plan = TestQuery4::explain(&conn, &1, &3).await.unwrap();
+    assert!(plan.uses_index("PRIMARY"), "{:?}", plan);
+    assert_eq!(plan.full_scans().count(), 0);
+
+    let plan = TestQuery22::explain(&conn, &44).await.unwrap();
+    assert_eq!(plan.full_scans().collect::<Vec<_>>(), vec!["foo"]);
+}
+
+pub async fn test_rows_matched(conn: Connection, semantics: TestSemantics) {
+    TestQuery3::query(&conn, &[(&44,), (&72,)]).await.unwrap();
+
+    // Row 1 already has the value, so it is matched but not changed
+    let res = TestQuery16::query(&conn, &44, &[1, 2]).await.unwrap();
+    match semantics {
+        TestSemantics::Sqlite | TestSemantics::Postgres => {
+            assert_eq!(res.affected_rows(), 2);
+            assert_eq!(res.rows_matched(), Some(2));
+            assert_eq!(res.rows_changed(), None);
+        }
+        TestSemantics::Mysql => {
+            assert_eq!(res.affected_rows(), 1);
+            assert_eq!(res.rows_matched(), Some(2));
+            assert_eq!(res.rows_changed(), Some(1));
+        }
+    }
+}
+
+pub async fn test_cas_write(conn: Connection) {
+    TestQuery3::query(&conn, &[(&0,)]).await.unwrap();
+
+    let outcome = TestQuery23::query(&conn, &1, &0).await.unwrap();
+    assert_eq!(outcome, CasOutcome::Updated);
+    let outcome = TestQuery23::query(&conn, &1, &0).await.unwrap();
+    assert_eq!(outcome, CasOutcome::Conflict);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, outcome) = TestQuery23::query_with_transaction(transaction, &1, &1)
+        .await
+        .unwrap();
+    assert_eq!(outcome, CasOutcome::Updated);
+    transaction.commit().await.unwrap();
+
+    let mut attempts = 0;
+    let outcome = CasRetry::new(3)
+        .run(|| {
+            attempts += 1;
+            let conn = &conn;
+            async move {
+                let rows = TestQuery19::query(conn, &[1]).await?;
+                let x = rows[0].0.unwrap();
+                // Another writer updates the row before the first attempt
+                if x == 2 {
+                    TestQuery23::query(conn, &1, &x).await?;
+                }
+                TestQuery23::query(conn, &1, &2).await
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(outcome, CasOutcome::Conflict);
+    assert_eq!(attempts, 3);
+
+    let outcome = CasRetry::new(3)
+        .run(|| async {
+            let rows = TestQuery19::query(&conn, &[1]).await?;
+            TestQuery23::query(&conn, &1, &rows[0].0.unwrap()).await
+        })
+        .await
+        .unwrap();
+    assert_eq!(outcome, CasOutcome::Updated);
+    assert_eq!(
+        TestQuery19::query(&conn, &[1]).await.unwrap(),
+        vec![(Some(4), 1)]
+    );
+}
+
+pub async fn test_cte_and_union(conn: Connection) {
+    TestQuery3::query(&conn, &[(&1,), (&1,), (&2,), (&3,)])
+        .await
+        .unwrap();
+
+    let res = TestQuery24::query(&conn, &1, &1).await.unwrap();
+    assert_eq!(res, vec![(1, 1), (2, 1)]);
+
+    let res = TestQuery25::query(&conn, &1, &1, &[2, 3]).await.unwrap();
+    assert_eq!(res, vec![(1,), (2,), (2,), (3,)]);
+    let res = TestQuery25::query(&conn, &3, &1, &[]).await.unwrap();
+    assert_eq!(res, vec![(4,)]);
+
+    let res = TestQuery26::query(&conn, &2, &3).await.unwrap();
+    assert_eq!(res, vec![(3,), (4,)]);
+
+    let readonly = conn.readonly();
+    let res = TestQuery24::query(&readonly, &2, &0).await.unwrap();
+    assert_eq!(res, vec![(3, 1)]);
+}
+
+pub fn test_cache_key() {
+    let key = TestQuery25::cache_key(&1, &2, &[3, 4]);
+    assert_eq!(key.query(), "TestQuery25");
+    assert_eq!(key, TestQuery25::cache_key(&1, &2, &[3, 4]));
+    assert_ne!(key, TestQuery25::cache_key(&1, &2, &[3]));
+    assert_ne!(key, TestQuery25::cache_key(&2, &1, &[3, 4]));
+    assert_ne!(key.as_str(), TestQuery24::cache_key(&1, &2).as_str());
+    assert_eq!(
+        TestQuery12::cache_key(&"a'b".to_owned()),
+        TestQuery12::cache_key(&"a'b".to_owned())
+    );
+}
+
+pub async fn test_connection_label(conn: Connection) {
+    let sharded = SqlShardedConnections::from(vec![SqlConnections::new_single(conn)])
+        .with_label(ConnectionLabel::new().with_tier("foo"));
+    let shard = sharded.shard(0).unwrap();
+    TestQuery7::query(&shard.write_connection, &1)
+        .await
+        .unwrap();
+
+    let readonly = shard.read_connection.clone().readonly();
+    let err = TestQuery7::query(&readonly, &2)
+        .await
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "While executing TestQuery7 query (tier:foo shard:0 role:read)"
+    );
+    let err = readonly.start_transaction().await.map(|_| ()).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("tier:foo shard:0 role:read"),
+        "{:#}",
+        err
+    );
+}
+
+pub async fn test_replication_position(conn: Connection) {
+    let write = conn.clone().with_replication_tracking();
+    let res = TestQuery7::query(&write, &1).await.unwrap();
+    assert_eq!(res.affected_rows(), 1);
+    // Sqlite has no replication positions, nor replicas to wait for
+    assert_eq!(res.replication_position(), None);
+    let position = ReplicationPosition::new("3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5");
+    conn.wait_for_replication(&position, Duration::from_millis(1))
+        .await
+        .unwrap();
+}
+
+pub async fn test_max_rows(conn: Connection) {
+    TestQuery3::query(&conn, &[(&44,), (&72,), (&53,)])
+        .await
+        .unwrap();
+
+    let limited = conn.clone().with_max_rows(2);
+    let res = TestQuery15::query(&limited, &[1, 2]).await.unwrap();
+    assert_eq!(res, vec![(44,), (72,)]);
+    let err = TestQuery15::query(&limited, &[1, 2, 3]).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TooManyRowsError>()
+            .map(|err| err.max_rows),
+        Some(2)
+    );
+
+    let rows: Vec<_> = TestQuery15::query_stream(&limited, &[1, 2, 3])
+        .collect()
+        .await;
+    assert_eq!(rows.len(), 3);
+    assert!(rows[2].as_ref().unwrap_err().is::<TooManyRowsError>());
+
+    // Writes return no rows, and a query can have its own limit
+    TestQuery7::query(&limited, &1).await.unwrap();
+    let res = TestQuery15::query(&limited.with_max_rows(3), &[1, 2, 3])
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 3);
+}
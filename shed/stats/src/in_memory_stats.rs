@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use fbinit::FacebookInit;
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use stats_traits::{
+    stat_types::{
+        BoxCounter, BoxHistogram, BoxSingletonCounter, BoxTimeseries, Counter, Histogram,
+        SingletonCounter, Timeseries,
+    },
+    stats_manager::{
+        AggregationType, BoxStatsManager, BucketConfig, StatsManager, StatsManagerFactory,
+    },
+};
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<String, Arc<Value>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Whether a value is exported as a single number, or as aggregates of samples
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Samples,
+}
+
+struct Value {
+    kind: Kind,
+    sum: AtomicI64,
+    count: AtomicI64,
+}
+
+impl Value {
+    fn add(&self, value: i64, nsamples: u32) {
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(nsamples.into(), Ordering::Relaxed);
+    }
+}
+
+/// The value registered under `name`, shared by the stats of all the threads,
+/// created on first use.
+fn register(name: &str, kind: Kind) -> Arc<Value> {
+    let mut registry = REGISTRY.lock().expect("poisoned lock");
+    match registry.entry(name.to_owned()) {
+        Entry::Occupied(entry) => entry.get().clone(),
+        Entry::Vacant(entry) => entry
+            .insert(Arc::new(Value {
+                kind,
+                sum: AtomicI64::new(0),
+                count: AtomicI64::new(0),
+            }))
+            .clone(),
+    }
+}
+
+/// Current values of the stats recorded by the [InMemoryStatsFactory]. A
+/// counter is exported under its name, while a timeseries or histogram is
+/// exported as the `.sum`, `.count` and `.avg` of all its samples.
+pub fn snapshot() -> BTreeMap<String, i64> {
+    let registry = REGISTRY.lock().expect("poisoned lock");
+    let mut snapshot = BTreeMap::new();
+    for (name, value) in &*registry {
+        let sum = value.sum.load(Ordering::Relaxed);
+        match value.kind {
+            Kind::Counter => {
+                snapshot.insert(name.clone(), sum);
+            }
+            Kind::Samples => {
+                let count = value.count.load(Ordering::Relaxed);
+                snapshot.insert(format!("{}.sum", name), sum);
+                snapshot.insert(format!("{}.count", name), count);
+                snapshot.insert(format!("{}.avg", name), sum.checked_div(count).unwrap_or(0));
+            }
+        }
+    }
+    snapshot
+}
+
+/// Stats manager factory recording the stats in memory for the whole process,
+/// to be read with [snapshot]. This is the default outside of fbcode, where
+/// there is no fb303 to export the stats to.
+pub struct InMemoryStatsFactory;
+
+impl StatsManagerFactory for InMemoryStatsFactory {
+    fn create(&self) -> BoxStatsManager {
+        Box::new(InMemoryStats)
+    }
+}
+
+struct InMemoryStats;
+
+impl StatsManager for InMemoryStats {
+    // Values are shared by all the threads already
+    fn aggregate(&self) {}
+
+    fn create_counter(&self, name: &str) -> BoxCounter {
+        Box::new(InMemoryStat(register(name, Kind::Counter)))
+    }
+
+    fn create_timeseries(
+        &self,
+        name: &str,
+        _aggregation_types: &[AggregationType],
+        _intervals: &[Duration],
+    ) -> BoxTimeseries {
+        Box::new(InMemoryStat(register(name, Kind::Samples)))
+    }
+
+    fn create_histogram(
+        &self,
+        name: &str,
+        _aggregation_types: &[AggregationType],
+        _conf: BucketConfig,
+        _percentiles: &[u8],
+    ) -> BoxHistogram {
+        Box::new(InMemoryStat(register(name, Kind::Samples)))
+    }
+}
+
+#[cfg(not(fbcode_build))]
+pub(crate) fn create_singleton_counter(name: &str) -> BoxSingletonCounter {
+    Box::new(InMemoryStat(register(name, Kind::Counter)))
+}
+
+struct InMemoryStat(Arc<Value>);
+
+impl Counter for InMemoryStat {
+    fn increment_value(&self, value: i64) {
+        self.0.add(value, 1);
+    }
+}
+
+impl Timeseries for InMemoryStat {
+    fn add_value(&self, value: i64) {
+        self.0.add(value, 1);
+    }
+
+    fn add_value_aggregated(&self, value: i64, nsamples: u32) {
+        self.0.add(value, nsamples);
+    }
+}
+
+impl Histogram for InMemoryStat {
+    fn add_value(&self, value: i64) {
+        self.0.add(value, 1);
+    }
+
+    fn add_repeated_value(&self, value: i64, nsamples: u32) {
+        self.0.add(value.saturating_mul(nsamples.into()), nsamples);
+    }
+}
+
+impl SingletonCounter for InMemoryStat {
+    fn set_value(&self, _fb: FacebookInit, value: i64) {
+        self.0.sum.store(value, Ordering::Relaxed);
+    }
+
+    fn increment_value(&self, _fb: FacebookInit, value: i64) {
+        self.0.add(value, 1);
+    }
+
+    fn get_value(&self, _fb: FacebookInit) -> Option<i64> {
+        Some(self.0.sum.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let stats = InMemoryStatsFactory.create();
+        let counter = stats.create_counter("test_snapshot.counter");
+        let timeseries = stats.create_timeseries("test_snapshot.timeseries", &[], &[]);
+        let histogram = stats.create_histogram(
+            "test_snapshot.histogram",
+            &[],
+            BucketConfig {
+                width: 1,
+                min: 0,
+                max: 100,
+            },
+            &[],
+        );
+
+        // Stats created by another thread share the values
+        counter.increment_value(2);
+        InMemoryStatsFactory
+            .create()
+            .create_counter("test_snapshot.counter")
+            .increment_value(3);
+        timeseries.add_value(4);
+        timeseries.add_value_aggregated(8, 2);
+        histogram.add_repeated_value(5, 2);
+
+        let snapshot = snapshot();
+        assert_eq!(snapshot["test_snapshot.counter"], 5);
+        assert_eq!(snapshot["test_snapshot.timeseries.sum"], 12);
+        assert_eq!(snapshot["test_snapshot.timeseries.count"], 3);
+        assert_eq!(snapshot["test_snapshot.timeseries.avg"], 4);
+        assert_eq!(snapshot["test_snapshot.histogram.sum"], 10);
+        assert_eq!(snapshot["test_snapshot.histogram.count"], 2);
+    }
+}
@@ -13,8 +13,8 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+mod in_memory_stats;
 pub mod macros;
-mod noop_stats;
 pub mod thread_local_aggregator;
 
 pub mod prelude {
@@ -47,6 +47,7 @@ use stats_traits::{
     stats_manager::{BoxStatsManager, StatsManagerFactory},
 };
 
+pub use self::in_memory_stats::{snapshot, InMemoryStatsFactory};
 pub use self::thread_local_aggregator::schedule_stats_aggregation_preview;
 
 lazy_static! {
@@ -57,7 +58,8 @@ lazy_static! {
 /// This function must be called exactly once before accessing any of the stats,
 /// otherwise it will panic.
 /// If it won't be called a default stats manager factory will be assumed that
-/// does nothing. (Facebook only: the default will use fb303 counters)
+/// records the stats in memory, see [snapshot]. (Facebook only: the default
+/// will use fb303 counters)
 pub fn register_stats_manager_factory(factory: impl StatsManagerFactory + Send + Sync + 'static) {
     let mut global_factory = STATS_MANAGER_FACTORY.write().expect("poisoned lock");
     assert!(
@@ -94,7 +96,7 @@ fn get_default_stats_manager_factory() -> Box<dyn StatsManagerFactory + Send + S
     }
     #[cfg(not(fbcode_build))]
     {
-        Box::new(crate::noop_stats::NoopStatsFactory)
+        Box::new(crate::in_memory_stats::InMemoryStatsFactory)
     }
 }
 
@@ -109,7 +111,6 @@ pub fn create_singleton_counter(name: String) -> BoxSingletonCounter {
 
     #[cfg(not(fbcode_build))]
     {
-        let _ = name;
-        Box::new(crate::noop_stats::Noop)
+        crate::in_memory_stats::create_singleton_counter(&name)
     }
 }
@@ -0,0 +1,228 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Future returned by [Clock::sleep]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Source of the current time. Code that measures timeouts, TTLs or rates
+/// should read the time through a [Clock] instead of calling [Instant::now] or
+/// [SystemTime::now] directly, and wait with [Clock::sleep] instead of
+/// [tokio::time::sleep], so that tests can control it with [TestClock].
+pub trait Clock: Send + Sync {
+    /// The current monotonic time
+    fn now(&self) -> Instant;
+
+    /// The current wall clock time
+    fn system_time(&self) -> SystemTime;
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is in the future
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+
+    /// Wait until `duration` has elapsed on this clock. By default this is a
+    /// [tokio::time::sleep], so it must be polled within a tokio runtime.
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (**self).sleep(duration)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (**self).sleep(duration)
+    }
+}
+
+/// The real clock of the system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[derive(Debug)]
+struct TestTime {
+    instant: Instant,
+    system_time: SystemTime,
+    /// Tasks waiting in [Clock::sleep] by id of their sleep, woken up to
+    /// check their deadline whenever the clock moves
+    sleepers: HashMap<u64, Waker>,
+    next_sleeper: u64,
+}
+
+/// A clock that only moves when told to, for deterministic tests. It starts
+/// at the time it was created, and its [Clock::sleep] completes once the clock
+/// was advanced past the deadline, however long that takes in real time.
+/// Cloning gives another handle to the same clock.
+#[derive(Clone, Debug)]
+pub struct TestClock {
+    time: Arc<Mutex<TestTime>>,
+}
+
+impl TestClock {
+    /// Create a clock stopped at the current time
+    pub fn new() -> Self {
+        Self {
+            time: Arc::new(Mutex::new(TestTime {
+                instant: Instant::now(),
+                system_time: SystemTime::now(),
+                sleepers: HashMap::new(),
+                next_sleeper: 0,
+            })),
+        }
+    }
+
+    /// Move both the monotonic and the wall clock time forward
+    pub fn advance(&self, duration: Duration) {
+        let sleepers = {
+            let mut time = self.time.lock().expect("poisoned lock");
+            time.instant += duration;
+            time.system_time += duration;
+            std::mem::take(&mut time.sleepers)
+        };
+        for sleeper in sleepers.into_values() {
+            sleeper.wake();
+        }
+    }
+
+    /// Set the wall clock time, e.g. to simulate the system clock being
+    /// adjusted. The monotonic time is not affected.
+    pub fn set_system_time(&self, system_time: SystemTime) {
+        self.time.lock().expect("poisoned lock").system_time = system_time;
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.time.lock().expect("poisoned lock").instant
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().expect("poisoned lock").system_time
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let (deadline, id) = {
+            let mut time = self.time.lock().expect("poisoned lock");
+            time.next_sleeper += 1;
+            (time.instant + duration, time.next_sleeper)
+        };
+        let time = self.time.clone();
+        Box::pin(future::poll_fn(move |cx| {
+            let mut time = time.lock().expect("poisoned lock");
+            if time.instant >= deadline {
+                time.sleepers.remove(&id);
+                Poll::Ready(())
+            } else {
+                // Re-polling replaces the waker rather than queueing another
+                time.sleepers.insert(id, cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_clock() {
+        let clock = TestClock::new();
+        let start = clock.now();
+        let start_system_time = clock.system_time();
+        assert_eq!(clock.elapsed(start), Duration::from_secs(0));
+
+        let handle: Arc<dyn Clock> = Arc::new(clock.clone());
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(handle.elapsed(start), Duration::from_secs(5));
+        assert_eq!(
+            handle.system_time(),
+            start_system_time + Duration::from_secs(5)
+        );
+
+        clock.set_system_time(UNIX_EPOCH);
+        assert_eq!(clock.system_time(), UNIX_EPOCH);
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_sleep() {
+        let clock = TestClock::new();
+        let handle: Arc<dyn Clock> = Arc::new(clock.clone());
+        clock.sleep(Duration::ZERO).await;
+
+        let sleep = tokio::spawn(handle.sleep(Duration::from_secs(2)));
+        tokio::task::yield_now().await;
+        clock.advance(Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert!(!sleep.is_finished());
+
+        clock.advance(Duration::from_secs(1));
+        sleep.await.unwrap();
+    }
+
+    #[test]
+    fn test_sleep_repoll() {
+        let clock = TestClock::new();
+        let mut sleep = clock.sleep(Duration::from_secs(1));
+        let mut cx = std::task::Context::from_waker(Waker::noop());
+        for _ in 0..3 {
+            assert!(sleep.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(clock.time.lock().unwrap().sleepers.len(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(sleep.as_mut().poll(&mut cx).is_ready());
+        assert!(clock.time.lock().unwrap().sleepers.is_empty());
+    }
+}
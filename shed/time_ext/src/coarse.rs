@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::Clock;
+
+struct Inner {
+    base: Instant,
+    /// Nanoseconds since `base` as of the last tick
+    elapsed_nanos: AtomicU64,
+    /// Nanoseconds since the unix epoch as of the last tick
+    unix_nanos: AtomicU64,
+    /// Dropped with the last clone of the clock, which wakes up the thread
+    /// so that it stops right away instead of after its next tick
+    _stop: Sender<()>,
+}
+
+impl Inner {
+    fn tick(&self) {
+        let elapsed = self.base.elapsed().as_nanos() as u64;
+        // Keep the clock monotonic even if the ticks race
+        self.elapsed_nanos.fetch_max(elapsed, Ordering::Relaxed);
+        self.unix_nanos.store(unix_nanos(), Ordering::Relaxed);
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+}
+
+/// A [Clock] returning a cached time that is refreshed by a background thread
+/// every `resolution`, for hot paths where the cost of reading the system
+/// clock matters, e.g. timestamping every request. Reading it is a single
+/// atomic load, but the time it returns lags behind the real one by up to
+/// `resolution`, plus any scheduling delay of the thread.
+///
+/// Cloning gives another handle to the same clock, the thread stops once all
+/// the clones are dropped.
+#[derive(Clone)]
+pub struct CoarseClock {
+    inner: Arc<Inner>,
+}
+
+impl CoarseClock {
+    /// Start a clock refreshed every `resolution`, which must not be zero
+    pub fn new(resolution: Duration) -> io::Result<Self> {
+        if resolution.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the resolution of a CoarseClock must not be zero",
+            ));
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let inner = Arc::new(Inner {
+            base: Instant::now(),
+            elapsed_nanos: AtomicU64::new(0),
+            unix_nanos: AtomicU64::new(unix_nanos()),
+            _stop: stop,
+        });
+
+        let weak = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("coarse-clock".into())
+            .spawn(move || {
+                // Nothing is ever sent, this only returns once disconnected
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(resolution) {
+                    match weak.upgrade() {
+                        Some(inner) => inner.tick(),
+                        None => return,
+                    }
+                }
+            })?;
+
+        Ok(Self { inner })
+    }
+
+    /// Refresh the cached time now, e.g. after a long computation on a hot
+    /// path that needs a fresh timestamp
+    pub fn refresh(&self) {
+        self.inner.tick();
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Instant {
+        self.inner.base + Duration::from_nanos(self.inner.elapsed_nanos.load(Ordering::Relaxed))
+    }
+
+    fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.inner.unix_nanos.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coarse_clock() {
+        let clock = CoarseClock::new(Duration::from_millis(1)).unwrap();
+        let start = clock.now();
+        assert!(start <= Instant::now());
+
+        thread::sleep(Duration::from_millis(50));
+        let later = clock.now();
+        assert!(later > start, "clock did not tick");
+        assert!(later <= Instant::now());
+
+        let system_time = clock.system_time();
+        assert!(system_time <= SystemTime::now());
+        assert!(system_time > SystemTime::now() - Duration::from_secs(10));
+
+        clock.refresh();
+        assert!(clock.now() >= later);
+    }
+
+    #[test]
+    fn coarse_clock_zero_resolution() {
+        let err = CoarseClock::new(Duration::ZERO).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,304 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::TimeUnit;
+
+/// Error returned by [parse_duration]
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ParseDurationError {
+    /// The input is empty
+    #[error("empty duration")]
+    Empty,
+    /// Expected a number at the given byte offset
+    #[error("expected a number at position {0}")]
+    InvalidNumber(usize),
+    /// A number at the given byte offset is not followed by a unit
+    #[error("missing unit after the number at position {0}")]
+    MissingUnit(usize),
+    /// The unit is not one of `d`, `h`, `m`, `s`, `ms`, `us`, `µs` or `ns`
+    #[error("unknown unit {0:?}")]
+    UnknownUnit(String),
+    /// The duration is too long to be represented as [Duration]
+    #[error("duration is too long")]
+    Overflow,
+}
+
+fn unit_suffix(unit: TimeUnit) -> &'static str {
+    match unit {
+        TimeUnit::Days => "d",
+        TimeUnit::Hours => "h",
+        TimeUnit::Minutes => "m",
+        TimeUnit::Seconds => "s",
+        TimeUnit::Millis => "ms",
+        TimeUnit::Micros => "us",
+        TimeUnit::Nanos => "ns",
+    }
+}
+
+fn parse_unit(suffix: &str) -> Option<TimeUnit> {
+    Some(match suffix {
+        "d" => TimeUnit::Days,
+        "h" => TimeUnit::Hours,
+        "m" => TimeUnit::Minutes,
+        "s" => TimeUnit::Seconds,
+        "ms" => TimeUnit::Millis,
+        "us" | "µs" => TimeUnit::Micros,
+        "ns" => TimeUnit::Nanos,
+        _ => return None,
+    })
+}
+
+/// Parse a human readable duration made of one or more numbers with units,
+/// e.g. `1h30m`, `250ms` or `1.5s`. The supported units are `d`, `h`, `m`,
+/// `s`, `ms`, `us` (or `µs`) and `ns`, components and units may be separated
+/// by whitespace. A bare `0` is accepted as the zero duration. Fractions are
+/// exact down to the nanosecond, any further digits are truncated.
+pub fn parse_duration(input: &str) -> Result<Duration, ParseDurationError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(ParseDurationError::Empty);
+    }
+    if input == "0" {
+        return Ok(Duration::from_secs(0));
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let position = input.len() - rest.len();
+
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(number_len);
+        let after = after.trim_start();
+        let unit_len = after
+            .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+            .unwrap_or(after.len());
+        let (suffix, after) = after.split_at(unit_len);
+
+        if suffix.is_empty() {
+            return Err(ParseDurationError::MissingUnit(position));
+        }
+        let unit =
+            parse_unit(suffix).ok_or_else(|| ParseDurationError::UnknownUnit(suffix.to_owned()))?;
+        let nanos = parse_number(number, unit.as_nanos(), position)?;
+        total = total
+            .checked_add(nanos)
+            .ok_or(ParseDurationError::Overflow)?;
+
+        rest = after.trim_start();
+    }
+
+    crate::from_nanos_u128(total).ok_or(ParseDurationError::Overflow)
+}
+
+/// Parse a decimal number of units of `unit_nanos`, found at `position` of
+/// the input, into nanoseconds
+fn parse_number(
+    number: &str,
+    unit_nanos: u128,
+    position: usize,
+) -> Result<u128, ParseDurationError> {
+    let (whole, fraction) = match number.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (number, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+        return Err(ParseDurationError::InvalidNumber(position));
+    }
+
+    // The number is made of ASCII digits only, so it can only be too large
+    let mut nanos: u128 = 0;
+    for digit in whole.bytes() {
+        nanos = nanos
+            .checked_mul(10)
+            .and_then(|nanos| nanos.checked_add((digit - b'0') as u128))
+            .ok_or(ParseDurationError::Overflow)?;
+    }
+    nanos = nanos
+        .checked_mul(unit_nanos)
+        .ok_or(ParseDurationError::Overflow)?;
+    let mut scale = unit_nanos;
+    for digit in fraction.bytes() {
+        scale /= 10;
+        nanos = nanos
+            .checked_add((digit - b'0') as u128 * scale)
+            .ok_or(ParseDurationError::Overflow)?;
+    }
+    Ok(nanos)
+}
+
+/// Format a duration as a compact human readable string that can be parsed
+/// back by [parse_duration], e.g. `1h30m`, `2d0h0m5s`, `1.5s` or `250ms`.
+/// Durations of at least a second are formatted using days, hours, minutes
+/// and seconds, with the subsecond part as a fraction of the seconds, shorter
+/// ones using the largest fitting unit out of `ms`, `us` and `ns`.
+pub fn format_duration(duration: Duration) -> String {
+    HumanDuration(duration).to_string()
+}
+
+/// A [Duration] that is displayed with [format_duration] and parsed with
+/// [parse_duration], e.g. for config values and command line flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos = self.0.as_nanos();
+        if nanos == 0 {
+            return write!(f, "0s");
+        }
+
+        if nanos < TimeUnit::Seconds.as_nanos() {
+            let unit = [TimeUnit::Millis, TimeUnit::Micros, TimeUnit::Nanos]
+                .iter()
+                .copied()
+                .find(|unit| nanos >= unit.as_nanos())
+                .unwrap_or(TimeUnit::Nanos);
+            return write_fraction(f, nanos, unit);
+        }
+
+        // Skip the leading zero units, but keep the ones in the middle so
+        // that e.g. `1d0h5m` is not ambiguous to a reader.
+        let mut started = false;
+        let mut rest = nanos;
+        for unit in [TimeUnit::Days, TimeUnit::Hours, TimeUnit::Minutes] {
+            let count = rest / unit.as_nanos();
+            rest %= unit.as_nanos();
+            if count > 0 || started {
+                started = true;
+                write!(f, "{}{}", count, unit_suffix(unit))?;
+            }
+        }
+        if rest > 0 {
+            write_fraction(f, rest, TimeUnit::Seconds)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `nanos` as a number of `unit`s with an exact decimal fraction
+fn write_fraction(f: &mut fmt::Formatter<'_>, nanos: u128, unit: TimeUnit) -> fmt::Result {
+    let unit_nanos = unit.as_nanos();
+    write!(f, "{}", nanos / unit_nanos)?;
+    let mut fraction = nanos % unit_nanos;
+    if fraction > 0 {
+        write!(f, ".")?;
+        let mut scale = unit_nanos;
+        while fraction > 0 {
+            scale /= 10;
+            write!(f, "{}", fraction / scale)?;
+            fraction %= scale;
+        }
+    }
+    write!(f, "{}", unit_suffix(unit))
+}
+
+impl FromStr for HumanDuration {
+    type Err = ParseDurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(HumanDuration)
+    }
+}
+
+impl From<Duration> for HumanDuration {
+    fn from(duration: Duration) -> Self {
+        HumanDuration(duration)
+    }
+}
+
+impl From<HumanDuration> for Duration {
+    fn from(duration: HumanDuration) -> Self {
+        duration.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration(".5us"), Ok(Duration::from_nanos(500)));
+        assert_eq!(parse_duration("2d 3h"), Ok(Duration::from_secs(183_600)));
+        assert_eq!(parse_duration("10µs"), Ok(Duration::from_micros(10)));
+        assert_eq!(parse_duration("0"), Ok(Duration::from_secs(0)));
+
+        assert_eq!(parse_duration(" "), Err(ParseDurationError::Empty));
+        assert_eq!(
+            parse_duration("10"),
+            Err(ParseDurationError::MissingUnit(0))
+        );
+        assert_eq!(
+            parse_duration("1h30"),
+            Err(ParseDurationError::MissingUnit(2))
+        );
+        assert_eq!(
+            parse_duration("5 weeks"),
+            Err(ParseDurationError::UnknownUnit("weeks".to_owned()))
+        );
+        assert_eq!(
+            parse_duration("1h1.2.3s"),
+            Err(ParseDurationError::InvalidNumber(2))
+        );
+        assert_eq!(
+            parse_duration("h"),
+            Err(ParseDurationError::InvalidNumber(0))
+        );
+        assert_eq!(
+            parse_duration("99999999999999999999999d"),
+            Err(ParseDurationError::Overflow)
+        );
+        // Overflowing the intermediate nanoseconds, by the whole number, its
+        // multiplication by the unit or the addition of the fraction
+        assert_eq!(
+            parse_duration(&format!("{}0ns", u128::MAX)),
+            Err(ParseDurationError::Overflow)
+        );
+        assert_eq!(
+            parse_duration(&format!("{}d", u128::MAX / 1_000)),
+            Err(ParseDurationError::Overflow)
+        );
+        assert_eq!(
+            parse_duration(&format!("{}.999999999s", u128::MAX / 1_000_000_000)),
+            Err(ParseDurationError::Overflow)
+        );
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_secs(86_405)), "1d0h0m5s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_nanos(1_500)), "1.5us");
+        assert_eq!(format_duration(Duration::from_nanos(7)), "7ns");
+        assert_eq!("90s".parse::<HumanDuration>().unwrap().to_string(), "1m30s");
+    }
+
+    quickcheck! {
+        fn roundtrip(secs: u64, nanos: u32) -> bool {
+            let duration = Duration::new(secs, nanos % 1_000_000_000);
+            parse_duration(&format_duration(duration)) == Ok(duration)
+        }
+    }
+}
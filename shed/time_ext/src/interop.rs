@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Conversions between [std::time] types and the types of the `chrono` and
+//! `time` crates, enabled by the features of the same name. All conversions
+//! are exact to the nanosecond and fail with [OverflowError] instead of
+//! truncating or panicking when the value can't be represented.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::OverflowError;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Conversions of [Duration] to and from the durations of other crates.
+/// These are signed, so converting a negative duration to [Duration] fails.
+pub trait DurationInterop: Sized {
+    /// Convert to a `chrono::Duration`
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> Result<chrono::Duration>;
+
+    /// Convert from a `chrono::Duration`
+    #[cfg(feature = "chrono")]
+    fn try_from_chrono(duration: chrono::Duration) -> Result<Self>;
+
+    /// Convert to a `time::Duration`
+    #[cfg(feature = "time")]
+    fn to_time(&self) -> Result<time::Duration>;
+
+    /// Convert from a `time::Duration`
+    #[cfg(feature = "time")]
+    fn try_from_time(duration: time::Duration) -> Result<Self>;
+}
+
+impl DurationInterop for Duration {
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> Result<chrono::Duration> {
+        chrono::Duration::from_std(*self).map_err(|_| OverflowError.into())
+    }
+
+    #[cfg(feature = "chrono")]
+    fn try_from_chrono(duration: chrono::Duration) -> Result<Self> {
+        duration.to_std().map_err(|_| OverflowError.into())
+    }
+
+    #[cfg(feature = "time")]
+    fn to_time(&self) -> Result<time::Duration> {
+        (*self).try_into().map_err(|_| OverflowError.into())
+    }
+
+    #[cfg(feature = "time")]
+    fn try_from_time(duration: time::Duration) -> Result<Self> {
+        duration.try_into().map_err(|_| OverflowError.into())
+    }
+}
+
+/// Conversions of [SystemTime] to and from the timestamps of other crates.
+pub trait SystemTimeInterop: Sized {
+    /// Convert to a UTC `chrono::DateTime`
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> Result<chrono::DateTime<chrono::Utc>>;
+
+    /// Convert from a `chrono::DateTime` in any timezone
+    #[cfg(feature = "chrono")]
+    fn try_from_chrono<Tz: chrono::TimeZone>(datetime: &chrono::DateTime<Tz>) -> Result<Self>;
+
+    /// Convert to a UTC `time::OffsetDateTime`
+    #[cfg(feature = "time")]
+    fn to_time(&self) -> Result<time::OffsetDateTime>;
+
+    /// Convert from a `time::OffsetDateTime` in any offset
+    #[cfg(feature = "time")]
+    fn try_from_time(datetime: time::OffsetDateTime) -> Result<Self>;
+}
+
+impl SystemTimeInterop for SystemTime {
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> Result<chrono::DateTime<chrono::Utc>> {
+        use chrono::TimeZone;
+
+        let nanos = unix_nanos(self);
+        let secs = nanos
+            .div_euclid(NANOS_PER_SEC)
+            .try_into()
+            .map_err(|_| OverflowError)?;
+        let subsec_nanos = nanos.rem_euclid(NANOS_PER_SEC) as u32;
+        chrono::Utc
+            .timestamp_opt(secs, subsec_nanos)
+            .single()
+            .ok_or_else(|| OverflowError.into())
+    }
+
+    #[cfg(feature = "chrono")]
+    fn try_from_chrono<Tz: chrono::TimeZone>(datetime: &chrono::DateTime<Tz>) -> Result<Self> {
+        from_unix_nanos(
+            datetime.timestamp() as i128 * NANOS_PER_SEC
+                + datetime.timestamp_subsec_nanos() as i128,
+        )
+    }
+
+    #[cfg(feature = "time")]
+    fn to_time(&self) -> Result<time::OffsetDateTime> {
+        time::OffsetDateTime::from_unix_timestamp_nanos(unix_nanos(self))
+            .map_err(|_| OverflowError.into())
+    }
+
+    #[cfg(feature = "time")]
+    fn try_from_time(datetime: time::OffsetDateTime) -> Result<Self> {
+        from_unix_nanos(datetime.unix_timestamp_nanos())
+    }
+}
+
+/// Signed number of nanoseconds since the unix epoch, it can't overflow as
+/// [SystemTime] is at most `u64::MAX` seconds away from the epoch
+fn unix_nanos(time: &SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+fn from_unix_nanos(nanos: i128) -> Result<SystemTime> {
+    let abs = nanos.unsigned_abs();
+    let secs = (abs / NANOS_PER_SEC as u128)
+        .try_into()
+        .map_err(|_| OverflowError)?;
+    let offset = Duration::new(secs, (abs % NANOS_PER_SEC as u128) as u32);
+    let time = if nanos >= 0 {
+        UNIX_EPOCH.checked_add(offset)
+    } else {
+        UNIX_EPOCH.checked_sub(offset)
+    };
+    time.ok_or_else(|| OverflowError.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_interop() {
+        let duration = Duration::new(5, 123_456_789);
+        let chrono = duration.to_chrono().unwrap();
+        assert_eq!(chrono.num_nanoseconds(), Some(5_123_456_789));
+        assert_eq!(Duration::try_from_chrono(chrono).unwrap(), duration);
+        assert!(Duration::try_from_chrono(-chrono).is_err());
+        assert!(Duration::from_secs(u64::MAX).to_chrono().is_err());
+
+        // Before the epoch the subsecond part must not be truncated
+        let time = UNIX_EPOCH - Duration::new(1, 250_000_000);
+        let datetime = time.to_chrono().unwrap();
+        assert_eq!(datetime.timestamp(), -2);
+        assert_eq!(datetime.timestamp_subsec_nanos(), 750_000_000);
+        assert_eq!(SystemTime::try_from_chrono(&datetime).unwrap(), time);
+
+        let now = SystemTime::now();
+        assert_eq!(
+            SystemTime::try_from_chrono(&now.to_chrono().unwrap()).unwrap(),
+            now
+        );
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_interop() {
+        let duration = Duration::new(5, 123_456_789);
+        let time_duration = duration.to_time().unwrap();
+        assert_eq!(time_duration.whole_nanoseconds(), 5_123_456_789);
+        assert_eq!(Duration::try_from_time(time_duration).unwrap(), duration);
+        assert!(Duration::try_from_time(-time_duration).is_err());
+
+        let time = UNIX_EPOCH - Duration::new(1, 250_000_000);
+        let datetime = time.to_time().unwrap();
+        assert_eq!(datetime.unix_timestamp_nanos(), -1_250_000_000);
+        assert_eq!(SystemTime::try_from_time(datetime).unwrap(), time);
+    }
+}
@@ -12,18 +12,83 @@
 
 //! Crate extending functionality of [std::time]
 
+mod clock;
+mod coarse;
+mod human;
+#[cfg(any(feature = "chrono", feature = "time"))]
+mod interop;
+
+pub use crate::clock::{Clock, Sleep, SystemClock, TestClock};
+pub use crate::coarse::CoarseClock;
+pub use crate::human::{format_duration, parse_duration, HumanDuration, ParseDurationError};
+#[cfg(any(feature = "chrono", feature = "time"))]
+pub use crate::interop::{DurationInterop, SystemTimeInterop};
+
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use thiserror::Error;
 
 /// Error that might be returned when requesting time e.g. in micro seconds,
-/// but the result wouldn't fit into u64.
+/// but the result wouldn't fit into u64, or more generally when converting a
+/// time value into a type that can't represent it, e.g. a negative number of
+/// seconds into [Duration].
 #[derive(Debug, Error)]
-#[error("value too large for u64")]
+#[error("value out of range for the target type")]
 pub struct OverflowError;
 
+/// Unit of time for the conversions of [DurationExt].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeUnit {
+    /// 24 hours
+    Days,
+    /// 60 minutes
+    Hours,
+    /// 60 seconds
+    Minutes,
+    /// Seconds
+    Seconds,
+    /// Milliseconds
+    Millis,
+    /// Microseconds
+    Micros,
+    /// Nanoseconds
+    Nanos,
+}
+
+impl TimeUnit {
+    /// All the units, from the largest to the smallest
+    pub const ALL: [TimeUnit; 7] = [
+        TimeUnit::Days,
+        TimeUnit::Hours,
+        TimeUnit::Minutes,
+        TimeUnit::Seconds,
+        TimeUnit::Millis,
+        TimeUnit::Micros,
+        TimeUnit::Nanos,
+    ];
+
+    /// Length of the unit in nanoseconds
+    pub fn as_nanos(self) -> u128 {
+        match self {
+            TimeUnit::Days => 24 * 60 * 60 * NANOS_PER_SEC,
+            TimeUnit::Hours => 60 * 60 * NANOS_PER_SEC,
+            TimeUnit::Minutes => 60 * NANOS_PER_SEC,
+            TimeUnit::Seconds => NANOS_PER_SEC,
+            TimeUnit::Millis => 1_000_000,
+            TimeUnit::Micros => 1_000,
+            TimeUnit::Nanos => 1,
+        }
+    }
+}
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
 /// A trait implemented for [Duration] that extends the standard functionality.
+///
+/// Besides the per unit helpers, there are conversions to and from `u64` and
+/// `i64` counts of any [TimeUnit] that either fail or saturate when the value
+/// can't be represented, instead of panicking or silently truncating.
 pub trait DurationExt {
     /// Returns the number of whole milliseconds contained in this `Duration`.
     /// Results in an error if the resulting value would overflow a `u64`.
@@ -64,6 +129,44 @@ pub trait DurationExt {
 
     /// Returns `true` if the duration equals `Duration::zero()`.
     fn is_zero(&self) -> bool;
+
+    /// Returns the number of whole `unit`s contained in this `Duration`.
+    /// Results in an error if the resulting value would overflow a `u64`.
+    fn as_unit_u64(&self, unit: TimeUnit) -> Result<u64>;
+
+    /// Returns the number of whole `unit`s contained in this `Duration`, or
+    /// `u64::MAX` if that would overflow.
+    fn as_unit_u64_saturating(&self, unit: TimeUnit) -> u64;
+
+    /// Returns the number of whole `unit`s contained in this `Duration`.
+    /// Results in an error if the resulting value would overflow an `i64`.
+    fn as_unit_i64(&self, unit: TimeUnit) -> Result<i64>;
+
+    /// Returns the number of whole `unit`s contained in this `Duration`, or
+    /// `i64::MAX` if that would overflow.
+    fn as_unit_i64_saturating(&self, unit: TimeUnit) -> i64;
+
+    /// Create a new `Duration` from a number of `unit`s. Results in an error
+    /// if the duration is too long to be represented.
+    fn try_from_unit_u64(value: u64, unit: TimeUnit) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Create a new `Duration` from a number of `unit`s, saturating at
+    /// the longest representable duration.
+    fn from_unit_u64_saturating(value: u64, unit: TimeUnit) -> Self;
+
+    /// Create a new `Duration` from a signed number of `unit`s. Results in
+    /// an error if the value is negative or the duration is too long to be
+    /// represented.
+    fn try_from_unit_i64(value: i64, unit: TimeUnit) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Create a new `Duration` from a signed number of `unit`s. Negative
+    /// values saturate at zero and too long durations at the longest
+    /// representable one.
+    fn from_unit_i64_saturating(value: i64, unit: TimeUnit) -> Self;
 }
 
 impl DurationExt for Duration {
@@ -112,6 +215,58 @@ impl DurationExt for Duration {
     fn is_zero(&self) -> bool {
         self == &Self::zero()
     }
+
+    fn as_unit_u64(&self, unit: TimeUnit) -> Result<u64> {
+        (self.as_nanos() / unit.as_nanos())
+            .try_into()
+            .map_err(|_| OverflowError.into())
+    }
+
+    fn as_unit_u64_saturating(&self, unit: TimeUnit) -> u64 {
+        (self.as_nanos() / unit.as_nanos())
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    fn as_unit_i64(&self, unit: TimeUnit) -> Result<i64> {
+        (self.as_nanos() / unit.as_nanos())
+            .try_into()
+            .map_err(|_| OverflowError.into())
+    }
+
+    fn as_unit_i64_saturating(&self, unit: TimeUnit) -> i64 {
+        (self.as_nanos() / unit.as_nanos())
+            .try_into()
+            .unwrap_or(i64::MAX)
+    }
+
+    fn try_from_unit_u64(value: u64, unit: TimeUnit) -> Result<Self> {
+        from_nanos_u128(value as u128 * unit.as_nanos()).ok_or_else(|| OverflowError.into())
+    }
+
+    fn from_unit_u64_saturating(value: u64, unit: TimeUnit) -> Self {
+        from_nanos_u128(value as u128 * unit.as_nanos()).unwrap_or(MAX_DURATION)
+    }
+
+    fn try_from_unit_i64(value: i64, unit: TimeUnit) -> Result<Self> {
+        let value: u64 = value.try_into().map_err(|_| OverflowError)?;
+        Self::try_from_unit_u64(value, unit)
+    }
+
+    fn from_unit_i64_saturating(value: i64, unit: TimeUnit) -> Self {
+        match value.try_into() {
+            Ok(value) => Self::from_unit_u64_saturating(value, unit),
+            Err(_) => Self::zero(),
+        }
+    }
+}
+
+/// The longest representable [Duration]
+const MAX_DURATION: Duration = Duration::new(u64::MAX, 999_999_999);
+
+fn from_nanos_u128(nanos: u128) -> Option<Duration> {
+    let secs = (nanos / NANOS_PER_SEC).try_into().ok()?;
+    Some(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
 }
 
 /// A trait implemented for [Instant] that extends the standard functionality.
@@ -180,6 +335,58 @@ mod tests {
         assert!(nanos.is_err());
     }
 
+    quickcheck! {
+        fn unit_roundtrip(x: u32) -> bool {
+            TimeUnit::ALL.iter().all(|unit| {
+                let dur = Duration::try_from_unit_u64(x as u64, *unit).unwrap();
+                dur.as_unit_u64(*unit).unwrap() == x as u64
+                    && dur.as_unit_i64(*unit).unwrap() == x as i64
+                    && Duration::from_unit_i64_saturating(x as i64, *unit) == dur
+            })
+        }
+    }
+
+    #[test]
+    fn unit_extremes() {
+        let big = Duration::new(u64::MAX, 999_999_999);
+        assert!(big.as_unit_u64(TimeUnit::Millis).is_err());
+        assert_eq!(big.as_unit_u64_saturating(TimeUnit::Nanos), u64::MAX);
+        assert_eq!(big.as_unit_u64(TimeUnit::Seconds).unwrap(), u64::MAX);
+        assert!(big.as_unit_i64(TimeUnit::Seconds).is_err());
+        assert_eq!(big.as_unit_i64_saturating(TimeUnit::Seconds), i64::MAX);
+        assert_eq!(
+            big.as_unit_u64(TimeUnit::Days).unwrap(),
+            u64::MAX / (24 * 60 * 60)
+        );
+
+        assert!(Duration::try_from_unit_u64(u64::MAX, TimeUnit::Minutes).is_err());
+        assert_eq!(
+            Duration::from_unit_u64_saturating(u64::MAX, TimeUnit::Days),
+            MAX_DURATION
+        );
+        assert_eq!(
+            Duration::try_from_unit_u64(u64::MAX, TimeUnit::Nanos).unwrap(),
+            Duration::from_nanos(u64::MAX)
+        );
+        // All the conversions fail with the same error
+        assert!(Duration::try_from_unit_i64(-1, TimeUnit::Seconds)
+            .unwrap_err()
+            .is::<OverflowError>());
+        assert!(big
+            .as_unit_i64(TimeUnit::Seconds)
+            .unwrap_err()
+            .is::<OverflowError>());
+        assert!(big.as_nanos_u64().unwrap_err().is::<OverflowError>());
+        assert_eq!(
+            Duration::from_unit_i64_saturating(i64::MIN, TimeUnit::Days),
+            Duration::zero()
+        );
+        assert_eq!(
+            Duration::from_unit_i64_saturating(90, TimeUnit::Minutes),
+            Duration::from_secs(90 * 60)
+        );
+    }
+
     #[test]
     fn zero() {
         let zero = Duration::zero();